@@ -0,0 +1,38 @@
+#![warn(missing_docs)]
+
+//! Back buffer that isn't attached to any window.
+
+use system::BackBuffer;
+
+/// Back buffer that only lives in memory. Used when running without
+/// a window, e.g. to capture frames on a headless machine.
+pub struct MemoryBackBuffer {
+    width : u32,
+    height : u32,
+    buffer : Vec<u32>,
+}
+
+impl MemoryBackBuffer {
+    /// Creates a new back buffer of the given size, cleared to black.
+    pub fn new(width : u32, height : u32) -> MemoryBackBuffer {
+        MemoryBackBuffer {
+            width : width,
+            height : height,
+            buffer : vec![0; (width * height) as usize],
+        }
+    }
+}
+
+impl BackBuffer for MemoryBackBuffer {
+    fn get_buffer(&mut self) -> &mut [u32] {
+        &mut self.buffer
+    }
+
+    fn get_width(&self) -> u32 {
+        self.width
+    }
+
+    fn get_height(&self) -> u32 {
+        self.height
+    }
+}
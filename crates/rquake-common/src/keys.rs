@@ -0,0 +1,33 @@
+#![allow(missing_docs)]
+
+//! Key numbers used by the input events. Printable keys use their lower case
+//! ASCII value.
+//!
+//! Original source can be found in keys.h
+
+pub const K_TAB : i32 = 9;
+pub const K_ENTER : i32 = 13;
+pub const K_ESCAPE : i32 = 27;
+pub const K_SPACE : i32 = 32;
+pub const K_BACKSPACE : i32 = 127;
+pub const K_UPARROW : i32 = 128;
+pub const K_DOWNARROW : i32 = 129;
+pub const K_LEFTARROW : i32 = 130;
+pub const K_RIGHTARROW : i32 = 131;
+pub const K_ALT : i32 = 132;
+pub const K_CTRL : i32 = 133;
+pub const K_SHIFT : i32 = 134;
+/// F2 to F12 follow F1.
+pub const K_F1 : i32 = 135;
+pub const K_INS : i32 = 147;
+pub const K_DEL : i32 = 148;
+pub const K_PGDN : i32 = 149;
+pub const K_PGUP : i32 = 150;
+pub const K_HOME : i32 = 151;
+pub const K_END : i32 = 152;
+pub const K_MOUSE1 : i32 = 200;
+pub const K_MOUSE2 : i32 = 201;
+pub const K_MOUSE3 : i32 = 202;
+pub const K_MWHEELUP : i32 = 239;
+pub const K_MWHEELDOWN : i32 = 240;
+pub const K_PAUSE : i32 = 255;
@@ -4,11 +4,18 @@ pub use system::Window;
 pub use system::BackBuffer;
 pub use system::ToggleFullscreen;
 pub use system::NativeSoundEngine;
+pub use system::SoundFormat;
 pub use utils::Timer;
 pub use types::EventAction;
 pub use resources::GameResources;
+pub use backbuffer::MemoryBackBuffer;
+pub use mathlib::Vec3;
+
+pub mod mathlib;
+pub mod keys;
 
 mod system;
 mod utils;
 mod types;
-mod resources;
\ No newline at end of file
+mod resources;
+mod backbuffer;
\ No newline at end of file
@@ -0,0 +1,118 @@
+#![warn(missing_docs)]
+
+//! Vector math used by all crates.
+//!
+//! Original source can be found in mathlib.c
+
+use std::f32::consts::PI;
+
+/// 3D vector.
+pub type Vec3 = [f32; 3];
+
+/// Index of pitch in angle vectors (up / down).
+pub const PITCH : usize = 0;
+/// Index of yaw in angle vectors (left / right).
+pub const YAW : usize = 1;
+/// Index of roll in angle vectors (fall over).
+pub const ROLL : usize = 2;
+
+/// Returns the dot product of two vectors.
+pub fn dot_product(a : &Vec3, b : &Vec3) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Returns a + b.
+pub fn vector_add(a : &Vec3, b : &Vec3) -> Vec3 {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+/// Returns a - b.
+pub fn vector_subtract(a : &Vec3, b : &Vec3) -> Vec3 {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+/// Returns v * scale.
+pub fn vector_scale(v : &Vec3, scale : f32) -> Vec3 {
+    [v[0] * scale, v[1] * scale, v[2] * scale]
+}
+
+/// Returns a + b * scale.
+pub fn vector_ma(a : &Vec3, scale : f32, b : &Vec3) -> Vec3 {
+    [a[0] + scale * b[0], a[1] + scale * b[1], a[2] + scale * b[2]]
+}
+
+/// Returns the cross product of two vectors.
+pub fn cross_product(a : &Vec3, b : &Vec3) -> Vec3 {
+    [a[1] * b[2] - a[2] * b[1],
+     a[2] * b[0] - a[0] * b[2],
+     a[0] * b[1] - a[1] * b[0]]
+}
+
+/// Returns the length of a vector.
+pub fn vector_length(v : &Vec3) -> f32 {
+    dot_product(v, v).sqrt()
+}
+
+/// Normalizes the vector in place and returns its previous length.
+pub fn vector_normalize(v : &mut Vec3) -> f32 {
+    let length = vector_length(v);
+    if length != 0.0 {
+        let ilength = 1.0 / length;
+        v[0] *= ilength;
+        v[1] *= ilength;
+        v[2] *= ilength;
+    }
+    length
+}
+
+/// Wraps an angle in degrees into the range 0 to 360 with 16 bit precision.
+pub fn anglemod(a : f32) -> f32 {
+    (360.0 / 65536.0) * (((a * (65536.0 / 360.0)) as i32) & 65535) as f32
+}
+
+/// Calculates the forward, right and up vectors for angles in degrees.
+pub fn angle_vectors(angles : &Vec3) -> (Vec3, Vec3, Vec3) {
+    let (sy, cy) = (angles[YAW] * (PI * 2.0 / 360.0)).sin_cos();
+    let (sp, cp) = (angles[PITCH] * (PI * 2.0 / 360.0)).sin_cos();
+    let (sr, cr) = (angles[ROLL] * (PI * 2.0 / 360.0)).sin_cos();
+
+    let forward = [cp * cy, cp * sy, -sp];
+    let right = [-1.0 * sr * sp * cy + -1.0 * cr * -sy,
+                 -1.0 * sr * sp * sy + -1.0 * cr * cy,
+                 -1.0 * sr * cp];
+    let up = [cr * sp * cy + -sr * -sy,
+              cr * sp * sy + -sr * cy,
+              cr * cp];
+    (forward, right, up)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a : &Vec3, b : &Vec3) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < 0.0001, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn angle_vectors_test() {
+        let (forward, right, up) = angle_vectors(&[0.0, 0.0, 0.0]);
+        assert_near(&forward, &[1.0, 0.0, 0.0]);
+        assert_near(&right, &[0.0, -1.0, 0.0]);
+        assert_near(&up, &[0.0, 0.0, 1.0]);
+
+        let (forward, right, _) = angle_vectors(&[0.0, 90.0, 0.0]);
+        assert_near(&forward, &[0.0, 1.0, 0.0]);
+        assert_near(&right, &[1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn normalize() {
+        let mut v = [3.0, 0.0, 4.0];
+        assert_eq!(vector_normalize(&mut v), 5.0);
+        assert_near(&v, &[0.6, 0.0, 0.8]);
+        assert_near(&cross_product(&[1.0, 0.0, 0.0], &[0.0, 1.0, 0.0]), &[0.0, 0.0, 1.0]);
+    }
+}
@@ -6,4 +6,8 @@
 pub trait GameResources {
     /// Reads all PAK?.pak files in the given path.
     fn add_game_directory(&mut self, path: &str);
+
+    /// Reads the complete content of a file. Files in pak files that were
+    /// added later override earlier files with the same name.
+    fn load_file(&mut self, name: &str) -> Option<Vec<u8>>;
 }
\ No newline at end of file
@@ -44,11 +44,42 @@ pub trait ToggleFullscreen {
     fn toggle_fullscreen(&mut self);
 }
 
+/// Sample format and buffer size of a sound device.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundFormat {
+    /// Samples per second.
+    pub rate : u32,
+    /// Number of channels, 1 or 2.
+    pub channels : u32,
+    /// Bits per sample. The mixer only writes 16 bit samples, devices in
+    /// other formats are rejected.
+    pub bits : u32,
+    /// Size of the device ring buffer in sample frames.
+    pub frames : usize,
+}
+
 /// Trait for the native part of the sound engine.
+///
+/// The device plays a ring buffer in a loop. The portable mixer asks for
+/// the current play position and writes new samples ahead of it.
 pub trait NativeSoundEngine {
     /// Initializes the native sound engine.
     fn init(&mut self);
 
     /// Terminates the native sound engine.
     fn shutdown(&mut self);
+
+    /// Returns the device format, or None if no device is available.
+    fn get_format(&self) -> Option<SoundFormat>;
+
+    /// Returns the sample frame in the ring buffer that is currently played.
+    fn get_dma_position(&mut self) -> usize;
+
+    /// Writes interleaved 16 bit samples to the ring buffer, starting at
+    /// sample frame offset. The samples never cross the end of the buffer.
+    fn write_samples(&mut self, offset : usize, samples : &[i16]);
+
+    /// Called once per frame. Devices without their own clock advance
+    /// their play position here.
+    fn update(&mut self, _timestep : f32) {}
 }
\ No newline at end of file
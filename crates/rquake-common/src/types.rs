@@ -7,4 +7,13 @@ pub enum EventAction {
     
     /// Switch between fullscreen and windowed mode.
     ToggleFullscreen,
+
+    /// A key has been pressed, see the keys module for the key numbers.
+    KeyDown(i32),
+
+    /// A key has been released.
+    KeyUp(i32),
+
+    /// The mouse has been moved by x and y pixels.
+    MouseMove(i32, i32),
 }
\ No newline at end of file
@@ -0,0 +1,117 @@
+#![warn(missing_docs)]
+
+//! Music playback. Replaces the CD audio tracks of the original with
+//! music/trackNN.ogg or music/trackNN.wav files.
+//!
+//! Original source can be found in cd_audio.c
+
+use std::io::Cursor;
+
+use rquake_common::GameResources;
+use rquake_fs::Sound;
+use snd::SoundEngine;
+
+/// State of the music player.
+pub struct CdAudio {
+    track : Option<u32>,
+    looping : bool,
+    paused : bool,
+}
+
+impl CdAudio {
+    /// Creates a stopped music player.
+    pub fn new() -> CdAudio {
+        CdAudio {
+            track : None,
+            looping : false,
+            paused : false,
+        }
+    }
+
+    /// Plays a music track. Does nothing if the track is already playing.
+    pub fn play(&mut self, track : u32, looping : bool, snd : &mut SoundEngine, game_res : &mut GameResources) {
+        if self.track == Some(track) && snd.is_music_active() {
+            return;
+        }
+        self.stop(snd);
+
+        let sound = match load_track(track, game_res) {
+            Some(sound) => sound,
+            None => {
+                println!("CDAudio: Can't find track {}", track);
+                return;
+            },
+        };
+        snd.start_music(&sound, looping);
+        self.track = Some(track);
+        self.looping = looping;
+        self.paused = false;
+    }
+
+    /// Stops the current track.
+    pub fn stop(&mut self, snd : &mut SoundEngine) {
+        snd.stop_music();
+        self.track = None;
+        self.paused = false;
+    }
+
+    /// Pauses the current track.
+    pub fn pause(&mut self, snd : &mut SoundEngine) {
+        if self.track.is_some() {
+            snd.pause_music(true);
+            self.paused = true;
+        }
+    }
+
+    /// Resumes a paused track.
+    pub fn resume(&mut self, snd : &mut SoundEngine) {
+        if self.paused {
+            snd.pause_music(false);
+            self.paused = false;
+        }
+    }
+
+    /// Handles the cd command.
+    pub fn cd_cmd(&mut self, args : &[String], snd : &mut SoundEngine, game_res : &mut GameResources) {
+        let command = match args.get(1) {
+            Some(command) => command.to_lowercase(),
+            None => return,
+        };
+        let track = args.get(2).and_then(|track| track.parse().ok());
+
+        match command.as_str() {
+            "play" | "loop" => match track {
+                Some(track) => self.play(track, command == "loop", snd, game_res),
+                None => println!("cd {} <track>", command),
+            },
+            "stop" => self.stop(snd),
+            "pause" => self.pause(snd),
+            "resume" => self.resume(snd),
+            "info" => match self.track {
+                Some(track) => println!("{} track {}{}",
+                                        if self.paused { "Paused" } else if self.looping { "Looping" } else { "Playing" },
+                                        track, if snd.is_music_active() { "" } else { " (finished)" }),
+                None => println!("No track playing"),
+            },
+            _ => println!("Unknown cd command {}", command),
+        }
+    }
+}
+
+/// Loads a music track, ogg files are preferred over wave files.
+fn load_track(track : u32, game_res : &mut GameResources) -> Option<Sound> {
+    let name = format!("music/track{:02}", track);
+    if let Some(data) = game_res.load_file(&format!("{}.ogg", name)) {
+        match Sound::read_ogg(&mut Cursor::new(data)) {
+            Ok(sound) => return Some(sound),
+            Err(err) => println!("Failed to read {}.ogg: {}", name, err),
+        }
+    }
+    if let Some(data) = game_res.load_file(&format!("{}.wav", name)) {
+        match Sound::read(&mut Cursor::new(data)) {
+            Ok(sound) => return Some(sound),
+            Err(err) => println!("Failed to read {}.wav: {}", name, err),
+        }
+    }
+    None
+}
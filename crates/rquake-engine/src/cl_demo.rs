@@ -0,0 +1,184 @@
+#![warn(missing_docs)]
+
+//! Demo files. A demo starts with the forced CD track as text line,
+//! followed by every message received from the server. Each message is
+//! stored with its length and the view angles of the client.
+//!
+//! Original source can be found in cl_demo.c
+
+use std::fs::File;
+use std::io::{self, Write};
+
+use rquake_common::Vec3;
+use msg::SizeBuf;
+use net::MAX_MSGLEN;
+use protocol::ServerMessage;
+
+/// Writes the received server messages to a demo file.
+pub struct DemoRecorder {
+    file : File,
+    viewangles : Vec3,
+}
+
+impl DemoRecorder {
+    /// Creates the demo file and writes the CD track, -1 if no track is forced.
+    pub fn create(path : &str, cdtrack : i32) -> io::Result<DemoRecorder> {
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", cdtrack)?;
+        Ok(DemoRecorder {
+            file : file,
+            viewangles : [0.0; 3],
+        })
+    }
+
+    /// Appends a message received from the server.
+    pub fn write_message(&mut self, viewangles : &Vec3, data : &[u8]) -> io::Result<()> {
+        let mut buf = SizeBuf::new(MAX_MSGLEN + 16);
+        buf.write_long(data.len() as i32);
+        for &angle in viewangles {
+            buf.write_float(angle);
+        }
+        buf.write(data);
+        self.viewangles = *viewangles;
+        self.file.write_all(&buf.data)
+    }
+
+    /// Writes a disconnect message so playback ends cleanly and closes the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        let mut buf = SizeBuf::new(1);
+        ServerMessage::Disconnect.write(&mut buf);
+        let viewangles = self.viewangles;
+        self.write_message(&viewangles, &buf.data)?;
+        self.file.flush()
+    }
+}
+
+/// Reads the messages of a demo.
+pub struct DemoPlayer {
+    data : Vec<u8>,
+    pos : usize,
+    /// CD track to play instead of the one sent by the server, -1 for none.
+    pub cdtrack : i32,
+}
+
+impl DemoPlayer {
+    /// Starts reading demo data. Returns None if the CD track line is missing.
+    pub fn new(data : Vec<u8>) -> Option<DemoPlayer> {
+        let end = match data.iter().position(|&c| c == b'\n') {
+            Some(end) => end,
+            None => return None,
+        };
+        let cdtrack = match String::from_utf8_lossy(&data[..end]).trim().parse() {
+            Ok(track) => track,
+            Err(_) => return None,
+        };
+        Some(DemoPlayer {
+            data : data,
+            pos : end + 1,
+            cdtrack : cdtrack,
+        })
+    }
+
+    /// Returns the view angles and data of the next message, None at the
+    /// end of the demo.
+    pub fn read_message(&mut self) -> Option<(Vec3, Vec<u8>)> {
+        if self.pos + 16 > self.data.len() {
+            return None;
+        }
+        let header = &self.data[self.pos..self.pos + 16];
+        let value = |i : usize| header[i] as u32 | (header[i + 1] as u32) << 8 | (header[i + 2] as u32) << 16 | (header[i + 3] as u32) << 24;
+        let len = value(0) as usize;
+        if len > MAX_MSGLEN {
+            println!("Demo message > MAX_MSGLEN");
+            return None;
+        }
+        let viewangles = [f32::from_bits(value(4)), f32::from_bits(value(8)), f32::from_bits(value(12))];
+        let start = self.pos + 16;
+        if start + len > self.data.len() {
+            return None;
+        }
+        self.pos = start + len;
+        Some((viewangles, self.data[start..start + len].to_vec()))
+    }
+}
+
+/// Counts the frames of a timedemo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeDemo {
+    /// Frame counter when the demo was started.
+    pub start_frame : u64,
+    /// Real time of the second frame, when timing starts.
+    pub start_time : f64,
+    /// Last frame a message was read in.
+    pub last_frame : Option<u64>,
+}
+
+impl TimeDemo {
+    /// Starts a timedemo in the given frame.
+    pub fn new(frame : u64) -> TimeDemo {
+        TimeDemo {
+            start_frame : frame,
+            start_time : 0.0,
+            last_frame : None,
+        }
+    }
+
+    /// Returns the number of timed frames and seconds when the demo ended.
+    pub fn result(&self, frame : u64, time : f64) -> (u64, f64) {
+        let frames = frame.saturating_sub(self.start_frame + 1);
+        let time = time - self.start_time;
+        (frames, if time > 0.0 { time } else { 1.0 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+
+    #[test]
+    fn record_and_play() {
+        let path = env::temp_dir().join("rquake_record_and_play.dem");
+        let path = path.to_str().unwrap();
+        let mut recorder = DemoRecorder::create(path, 4).unwrap();
+        recorder.write_message(&[10.0, 90.0, 0.0], &[7, 0, 0, 128, 63]).unwrap();
+        recorder.write_message(&[0.0, 45.0, 0.0], &[1]).unwrap();
+        recorder.finish().unwrap();
+
+        let mut data = Vec::new();
+        File::open(path).unwrap().read_to_end(&mut data).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(&data[..2], b"4\n");
+
+        let mut player = DemoPlayer::new(data).unwrap();
+        assert_eq!(player.cdtrack, 4);
+        assert_eq!(player.read_message(), Some(([10.0, 90.0, 0.0], vec![7, 0, 0, 128, 63])));
+        assert_eq!(player.read_message(), Some(([0.0, 45.0, 0.0], vec![1])));
+        assert_eq!(player.read_message(), Some(([0.0, 45.0, 0.0], vec![2])));
+        assert_eq!(player.read_message(), None);
+    }
+
+    #[test]
+    fn bad_demos() {
+        assert!(DemoPlayer::new(b"no track".to_vec()).is_none());
+        let mut player = DemoPlayer::new(b"-1\n\x05\x00\x00\x00".to_vec()).unwrap();
+        assert_eq!(player.cdtrack, -1);
+        assert_eq!(player.read_message(), None);
+
+        let mut data = b"-1\n".to_vec();
+        data.extend_from_slice(&[0xff, 0xff, 0, 0]);
+        data.extend_from_slice(&[0; 12]);
+        let mut player = DemoPlayer::new(data).unwrap();
+        assert_eq!(player.read_message(), None);
+    }
+
+    #[test]
+    fn timedemo_result() {
+        let mut td = TimeDemo::new(10);
+        td.start_time = 2.0;
+        assert_eq!(td.result(111, 4.0), (100, 2.0));
+        assert_eq!(td.result(11, 2.0), (0, 1.0));
+    }
+}
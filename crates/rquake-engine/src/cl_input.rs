@@ -0,0 +1,475 @@
+#![warn(missing_docs)]
+
+//! Button states of the +/- commands and creation of the move command
+//! that is sent to the server every frame.
+//!
+//! Original source can be found in cl_input.c and in_win.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{anglemod, PITCH, ROLL, YAW};
+use cvar::CvarList;
+
+/// Player input of one frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UserCmd {
+    /// View angles in degrees.
+    pub viewangles : Vec3,
+    /// Speed along the view direction.
+    pub forwardmove : f32,
+    /// Speed to the right.
+    pub sidemove : f32,
+    /// Speed upwards, only used when swimming or flying.
+    pub upmove : f32,
+    /// Bit 1 is attack, bit 2 is jump.
+    pub buttons : i32,
+    /// Impulse command or 0.
+    pub impulse : i32,
+}
+
+impl UserCmd {
+    /// Creates a command without any movement.
+    pub fn new() -> UserCmd {
+        UserCmd {
+            viewangles : [0.0; 3],
+            forwardmove : 0.0,
+            sidemove : 0.0,
+            upmove : 0.0,
+            buttons : 0,
+            impulse : 0,
+        }
+    }
+}
+
+/// A button that can be held down by up to two keys. Bit 1 of the state
+/// is set while the button is down, bit 2 and 4 record that the button was
+/// pressed or released since the last frame.
+#[derive(Clone, Copy, Debug)]
+pub struct KButton {
+    down : [i32; 2],
+    state : i32,
+}
+
+impl KButton {
+    fn new() -> KButton {
+        KButton {
+            down : [0; 2],
+            state : 0,
+        }
+    }
+
+    /// Returns true if the button is held down.
+    pub fn is_down(&self) -> bool {
+        self.state & 1 != 0
+    }
+
+    /// Presses the button. Key -1 is used for buttons typed at the console
+    /// which stay down until released by hand.
+    fn key_down(&mut self, key : i32) {
+        if key == self.down[0] || key == self.down[1] {
+            // repeating key
+            return;
+        }
+
+        if self.down[0] == 0 {
+            self.down[0] = key;
+        } else if self.down[1] == 0 {
+            self.down[1] = key;
+        } else {
+            println!("Three keys down for a button!");
+            return;
+        }
+
+        if self.state & 1 != 0 {
+            // still down
+            return;
+        }
+        self.state |= 1 + 2;
+    }
+
+    /// Releases the button. Without a key all keys are released.
+    fn key_up(&mut self, key : Option<i32>) {
+        let key = match key {
+            Some(key) => key,
+            None => {
+                // typed manually at the console, assume for unsticking, so clear all
+                self.down = [0; 2];
+                self.state = 4;
+                return;
+            }
+        };
+
+        if self.down[0] == key {
+            self.down[0] = 0;
+        } else if self.down[1] == key {
+            self.down[1] = 0;
+        } else {
+            // key up without corresponding down (menu pass through)
+            return;
+        }
+        if self.down[0] != 0 || self.down[1] != 0 {
+            // some other key is still holding it down
+            return;
+        }
+
+        if self.state & 1 == 0 {
+            // still up (this should not happen)
+            return;
+        }
+        self.state &= !1;
+        self.state |= 4;
+    }
+
+    /// Returns the fraction of the frame that the button was held down
+    /// and clears the impulse bits.
+    fn key_state(&mut self) -> f32 {
+        let impulsedown = self.state & 2 != 0;
+        let impulseup = self.state & 4 != 0;
+        let down = self.state & 1 != 0;
+
+        let val = match (impulsedown, impulseup) {
+            // pressed and held this frame
+            (true, false) => if down { 0.5 } else { 0.0 },
+            // released this frame
+            (false, true) => 0.0,
+            // held the entire frame
+            (false, false) => if down { 1.0 } else { 0.0 },
+            // pressed and released this frame
+            (true, true) => if down { 0.75 } else { 0.25 },
+        };
+
+        self.state &= 1;
+        val
+    }
+}
+
+/// Client side input state.
+pub struct Input {
+    forward : KButton,
+    back : KButton,
+    moveleft : KButton,
+    moveright : KButton,
+    up : KButton,
+    down : KButton,
+    left : KButton,
+    right : KButton,
+    lookup : KButton,
+    lookdown : KButton,
+    strafe : KButton,
+    speed : KButton,
+    attack : KButton,
+    jump : KButton,
+    klook : KButton,
+    mlook : KButton,
+    impulse : i32,
+    mouse_x : f32,
+    mouse_y : f32,
+    /// Current view angles of the player.
+    pub viewangles : Vec3,
+}
+
+impl Input {
+    /// Creates the input state with all buttons released.
+    pub fn new() -> Input {
+        Input {
+            forward : KButton::new(),
+            back : KButton::new(),
+            moveleft : KButton::new(),
+            moveright : KButton::new(),
+            up : KButton::new(),
+            down : KButton::new(),
+            left : KButton::new(),
+            right : KButton::new(),
+            lookup : KButton::new(),
+            lookdown : KButton::new(),
+            strafe : KButton::new(),
+            speed : KButton::new(),
+            attack : KButton::new(),
+            jump : KButton::new(),
+            klook : KButton::new(),
+            mlook : KButton::new(),
+            impulse : 0,
+            mouse_x : 0.0,
+            mouse_y : 0.0,
+            viewangles : [0.0; 3],
+        }
+    }
+
+    fn button(&mut self, name : &str) -> Option<&mut KButton> {
+        match name {
+            "forward" => Some(&mut self.forward),
+            "back" => Some(&mut self.back),
+            "moveleft" => Some(&mut self.moveleft),
+            "moveright" => Some(&mut self.moveright),
+            "moveup" => Some(&mut self.up),
+            "movedown" => Some(&mut self.down),
+            "left" => Some(&mut self.left),
+            "right" => Some(&mut self.right),
+            "lookup" => Some(&mut self.lookup),
+            "lookdown" => Some(&mut self.lookdown),
+            "strafe" => Some(&mut self.strafe),
+            "speed" => Some(&mut self.speed),
+            "attack" => Some(&mut self.attack),
+            "jump" => Some(&mut self.jump),
+            "klook" => Some(&mut self.klook),
+            "mlook" => Some(&mut self.mlook),
+            _ => None,
+        }
+    }
+
+    /// Executes the +/- button commands and impulse. Returns false if the
+    /// command is not an input command.
+    pub fn command(&mut self, args : &[String]) -> bool {
+        let name = args[0].as_str();
+        let key = args.get(1).and_then(|key| key.parse().ok());
+
+        if name == "impulse" {
+            self.impulse = key.unwrap_or(0);
+            return true;
+        }
+        if name == "centerview" {
+            self.viewangles[PITCH] = 0.0;
+            return true;
+        }
+
+        let (pressed, button) = if name.starts_with('+') {
+            (true, &name[1..])
+        } else if name.starts_with('-') {
+            (false, &name[1..])
+        } else {
+            return false;
+        };
+        match self.button(button) {
+            Some(button) => {
+                if pressed {
+                    button.key_down(key.unwrap_or(-1));
+                } else {
+                    button.key_up(key);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Accumulates mouse movement until the next move command is created.
+    pub fn mouse_move(&mut self, dx : i32, dy : i32) {
+        self.mouse_x += dx as f32;
+        self.mouse_y += dy as f32;
+    }
+
+    /// Creates the move command for this frame from the button states and
+    /// the mouse movement.
+    pub fn create_cmd(&mut self, frametime : f32, cvars : &CvarList) -> UserCmd {
+        self.adjust_angles(frametime, cvars);
+
+        let mut cmd = UserCmd::new();
+        if self.strafe.is_down() {
+            cmd.sidemove += cvars.value("cl_sidespeed") * self.right.key_state();
+            cmd.sidemove -= cvars.value("cl_sidespeed") * self.left.key_state();
+        }
+        cmd.sidemove += cvars.value("cl_sidespeed") * self.moveright.key_state();
+        cmd.sidemove -= cvars.value("cl_sidespeed") * self.moveleft.key_state();
+
+        cmd.upmove += cvars.value("cl_upspeed") * self.up.key_state();
+        cmd.upmove -= cvars.value("cl_upspeed") * self.down.key_state();
+
+        if !self.klook.is_down() {
+            cmd.forwardmove += cvars.value("cl_forwardspeed") * self.forward.key_state();
+            cmd.forwardmove -= cvars.value("cl_backspeed") * self.back.key_state();
+        }
+
+        // adjust for speed key
+        if self.speed.is_down() {
+            let movespeedkey = cvars.value("cl_movespeedkey");
+            cmd.forwardmove *= movespeedkey;
+            cmd.sidemove *= movespeedkey;
+            cmd.upmove *= movespeedkey;
+        }
+
+        self.apply_mouse(&mut cmd, cvars);
+        cmd.viewangles = self.viewangles;
+
+        if self.attack.state & 3 != 0 {
+            cmd.buttons |= 1;
+        }
+        self.attack.state &= !2;
+        if self.jump.state & 3 != 0 {
+            cmd.buttons |= 2;
+        }
+        self.jump.state &= !2;
+
+        cmd.impulse = self.impulse;
+        self.impulse = 0;
+        cmd
+    }
+
+    /// Turns the view with the keyboard.
+    fn adjust_angles(&mut self, frametime : f32, cvars : &CvarList) {
+        let speed = if self.speed.is_down() {
+            frametime * cvars.value("cl_anglespeedkey")
+        } else {
+            frametime
+        };
+        let yawspeed = speed * cvars.value("cl_yawspeed");
+        let pitchspeed = speed * cvars.value("cl_pitchspeed");
+
+        if !self.strafe.is_down() {
+            self.viewangles[YAW] -= yawspeed * self.right.key_state();
+            self.viewangles[YAW] += yawspeed * self.left.key_state();
+            self.viewangles[YAW] = anglemod(self.viewangles[YAW]);
+        }
+        if self.klook.is_down() {
+            self.viewangles[PITCH] -= pitchspeed * self.forward.key_state();
+            self.viewangles[PITCH] += pitchspeed * self.back.key_state();
+        }
+
+        let up = self.lookup.key_state();
+        let down = self.lookdown.key_state();
+        self.viewangles[PITCH] -= pitchspeed * up;
+        self.viewangles[PITCH] += pitchspeed * down;
+
+        self.clamp_angles();
+    }
+
+    /// Turns the view or moves the player with the accumulated mouse movement.
+    fn apply_mouse(&mut self, cmd : &mut UserCmd, cvars : &CvarList) {
+        let sensitivity = cvars.value("sensitivity");
+        let mouse_x = self.mouse_x * sensitivity;
+        let mouse_y = self.mouse_y * sensitivity;
+        self.mouse_x = 0.0;
+        self.mouse_y = 0.0;
+
+        // add mouse X/Y movement to cmd
+        let lookstrafe = cvars.value("lookstrafe") != 0.0;
+        if self.strafe.is_down() || (lookstrafe && self.mlook.is_down()) {
+            cmd.sidemove += cvars.value("m_side") * mouse_x;
+        } else {
+            self.viewangles[YAW] -= cvars.value("m_yaw") * mouse_x;
+        }
+
+        if self.mlook.is_down() && !self.strafe.is_down() {
+            self.viewangles[PITCH] += cvars.value("m_pitch") * mouse_y;
+            self.clamp_angles();
+        } else {
+            cmd.forwardmove -= cvars.value("m_forward") * mouse_y;
+        }
+    }
+
+    fn clamp_angles(&mut self) {
+        if self.viewangles[PITCH] > 80.0 {
+            self.viewangles[PITCH] = 80.0;
+        }
+        if self.viewangles[PITCH] < -70.0 {
+            self.viewangles[PITCH] = -70.0;
+        }
+        if self.viewangles[ROLL] > 50.0 {
+            self.viewangles[ROLL] = 50.0;
+        }
+        if self.viewangles[ROLL] < -50.0 {
+            self.viewangles[ROLL] = -50.0;
+        }
+    }
+}
+
+/// Registers the input cvars.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("cl_upspeed", "200", false, false);
+    cvars.register("cl_forwardspeed", "200", true, false);
+    cvars.register("cl_backspeed", "200", true, false);
+    cvars.register("cl_sidespeed", "350", false, false);
+    cvars.register("cl_movespeedkey", "2.0", false, false);
+    cvars.register("cl_yawspeed", "140", false, false);
+    cvars.register("cl_pitchspeed", "150", false, false);
+    cvars.register("cl_anglespeedkey", "1.5", false, false);
+    cvars.register("cl_rollspeed", "200", false, false);
+    cvars.register("cl_rollangle", "2.0", false, false);
+    cvars.register("sensitivity", "3", true, false);
+    cvars.register("lookspring", "0", true, false);
+    cvars.register("lookstrafe", "0", true, false);
+    cvars.register("m_pitch", "0.022", true, false);
+    cvars.register("m_yaw", "0.022", true, false);
+    cvars.register("m_forward", "1", true, false);
+    cvars.register("m_side", "0.8", true, false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line : &str) -> Vec<String> {
+        line.split(' ').map(|arg| arg.to_string()).collect()
+    }
+
+    fn cvars() -> CvarList {
+        let mut cvars = CvarList::new();
+        register_cvars(&mut cvars);
+        cvars
+    }
+
+    #[test]
+    fn button_states() {
+        let mut button = KButton::new();
+        button.key_down(10);
+        button.key_down(11);
+        button.key_up(Some(10));
+        assert!(button.is_down());
+        assert_eq!(button.key_state(), 0.5);
+        assert_eq!(button.key_state(), 1.0);
+        button.key_up(Some(11));
+        assert_eq!(button.key_state(), 0.0);
+
+        button.key_down(12);
+        button.key_up(Some(12));
+        assert_eq!(button.key_state(), 0.25);
+
+        // typed at the console, released without a key
+        button.key_down(-1);
+        button.key_down(13);
+        button.key_up(None);
+        assert!(!button.is_down());
+        assert_eq!(button.key_state(), 0.0);
+    }
+
+    #[test]
+    fn move_commands() {
+        let cvars = cvars();
+        let mut input = Input::new();
+        assert!(input.command(&args("+forward 119")));
+        assert!(input.command(&args("+moveleft 97")));
+        assert!(input.command(&args("+attack 200")));
+        assert!(input.command(&args("-attack 200")));
+        assert!(input.command(&args("impulse 3")));
+        assert!(!input.command(&args("+unknown")));
+
+        let cmd = input.create_cmd(0.1, &cvars);
+        assert_eq!(cmd.forwardmove, 100.0);
+        assert_eq!(cmd.sidemove, -175.0);
+        assert_eq!(cmd.buttons, 1);
+        assert_eq!(cmd.impulse, 3);
+
+        assert!(input.command(&args("+speed")));
+        let cmd = input.create_cmd(0.1, &cvars);
+        assert_eq!(cmd.forwardmove, 400.0);
+        assert_eq!(cmd.buttons, 0);
+        assert_eq!(cmd.impulse, 0);
+    }
+
+    #[test]
+    fn mouse_look() {
+        let cvars = cvars();
+        let mut input = Input::new();
+        input.mouse_move(100, 0);
+        let cmd = input.create_cmd(0.1, &cvars);
+        assert!((cmd.viewangles[YAW] + 6.6).abs() < 0.001);
+
+        input.command(&args("+mlook"));
+        input.mouse_move(0, 10000);
+        let cmd = input.create_cmd(0.1, &cvars);
+        assert_eq!(cmd.viewangles[PITCH], 80.0);
+        assert_eq!(cmd.forwardmove, 0.0);
+
+        input.command(&args("+right"));
+        input.create_cmd(0.5, &cvars);
+        assert!((input.viewangles[YAW] - anglemod(-6.6 - 35.0)).abs() < 0.01);
+    }
+}
@@ -0,0 +1,535 @@
+#![warn(missing_docs)]
+
+//! Connection of the local client to a server or a demo, including
+//! demo recording and timedemos.
+//!
+//! Original source can be found in cl_main.c and cl_demo.c
+
+use std::path::Path;
+
+use rquake_common::{GameResources, Vec3};
+use cl_demo::{DemoPlayer, DemoRecorder, TimeDemo};
+use cl_input::UserCmd;
+use cmd::CommandBuffer;
+use cvar::CvarList;
+use msg::{MsgReader, SizeBuf};
+use net::{NetMessage, NetSocket, MAX_MSGLEN};
+use protocol::{self, ClientMessage, ServerMessage};
+
+/// Connection state that persists across levels.
+pub struct ClientStatic {
+    /// Number of signon messages received, `SIGNONS` once the client is in the game.
+    pub signon : i32,
+    /// Client time in seconds.
+    pub time : f32,
+    /// Server times of the last two messages, newest first.
+    pub mtime : [f32; 2],
+    /// View angles of the last two demo messages, newest first.
+    pub mviewangles : [Vec3; 2],
+    /// Player name sent to the server when signing on.
+    pub name : String,
+    /// Shirt and pants colors sent to the server when signing on.
+    pub color : i32,
+    game_dir : String,
+    netcon : Option<Box<NetSocket>>,
+    message : SizeBuf,
+    recorder : Option<DemoRecorder>,
+    player : Option<DemoPlayer>,
+    timedemo : Option<TimeDemo>,
+    forcetrack : i32,
+    framecount : u64,
+}
+
+/// Adds the extension to a file name that has none.
+pub fn default_extension(name : &str, extension : &str) -> String {
+    if Path::new(name).extension().is_some() {
+        name.to_string()
+    } else {
+        format!("{}.{}", name, extension)
+    }
+}
+
+/// Registers the player name and color cvars.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("_cl_name", "player", true, false);
+    cvars.register("_cl_color", "0", true, false);
+}
+
+impl ClientStatic {
+    /// Creates a disconnected client. Demos are recorded to game_dir.
+    pub fn new(game_dir : &str) -> ClientStatic {
+        ClientStatic {
+            signon : 0,
+            time : 0.0,
+            mtime : [0.0; 2],
+            mviewangles : [[0.0; 3]; 2],
+            name : String::new(),
+            color : 0,
+            game_dir : game_dir.to_string(),
+            netcon : None,
+            message : SizeBuf::new(MAX_MSGLEN),
+            recorder : None,
+            player : None,
+            timedemo : None,
+            forcetrack : -1,
+            framecount : 0,
+        }
+    }
+
+    /// Returns true if connected to a server or playing a demo.
+    pub fn is_connected(&self) -> bool {
+        self.netcon.is_some() || self.player.is_some()
+    }
+
+    /// Returns true while a demo is played.
+    pub fn is_demo_playback(&self) -> bool {
+        self.player.is_some()
+    }
+
+    /// Returns the estimated round trip time to the server in seconds.
+    pub fn round_trip_time(&self) -> f64 {
+        self.netcon.as_ref().map_or(0.0, |socket| socket.round_trip_time())
+    }
+
+    /// Returns true while a timedemo is running.
+    pub fn is_timedemo(&self) -> bool {
+        self.timedemo.is_some()
+    }
+
+    /// Starts the signon with a server.
+    pub fn connect(&mut self, socket : Box<NetSocket>, time : f64) {
+        self.disconnect(time);
+        println!("Connected to {}", socket.address());
+        self.netcon = Some(socket);
+        self.signon = 0;
+    }
+
+    /// Stops demo playback or tells the server that the client leaves.
+    pub fn disconnect(&mut self, time : f64) {
+        if self.player.is_some() {
+            self.stop_playback(time);
+        } else if let Some(mut socket) = self.netcon.take() {
+            if self.recorder.is_some() {
+                self.stop_cmd();
+            }
+
+            let mut buf = SizeBuf::new(1);
+            ClientMessage::Disconnect.write(&mut buf);
+            for _ in 0..3 {
+                socket.send_unreliable_message(&buf.data, time);
+            }
+            socket.close();
+        }
+        self.message.clear();
+        self.signon = 0;
+    }
+
+    /// Handles the record command.
+    pub fn record_cmd(&mut self, args : &[String], commands : &mut CommandBuffer) {
+        if args.len() < 2 || args.len() > 4 {
+            println!("record <demoname> [<map> [cd track]]");
+            return;
+        }
+        if args[1].contains("..") {
+            println!("Relative pathnames are not allowed.");
+            return;
+        }
+        if args.len() == 2 && self.netcon.is_some() {
+            println!("Can not record - already connected to server");
+            println!("Client demo recording must be started before connecting");
+            return;
+        }
+        if self.player.is_some() {
+            println!("Can't record during demo playback");
+            return;
+        }
+
+        let track = match args.get(3) {
+            Some(track) => {
+                let track = track.parse().unwrap_or(0);
+                println!("Forcing CD track to {}", track);
+                track
+            }
+            None => -1,
+        };
+
+        // start the map up
+        if let Some(map) = args.get(2) {
+            commands.add_text(&format!("map {}", map));
+        }
+
+        let name = Path::new(&self.game_dir).join(default_extension(&args[1], "dem"));
+        let name = name.to_string_lossy().into_owned();
+        println!("recording to {}.", name);
+        match DemoRecorder::create(&name, track) {
+            Ok(recorder) => {
+                self.recorder = Some(recorder);
+                self.forcetrack = track;
+            }
+            Err(err) => println!("ERROR: couldn't open: {}", err),
+        }
+    }
+
+    /// Handles the stop command, finishes the demo that is being recorded.
+    pub fn stop_cmd(&mut self) {
+        let recorder = match self.recorder.take() {
+            Some(recorder) => recorder,
+            None => {
+                println!("Not recording a demo.");
+                return;
+            }
+        };
+        if let Err(err) = recorder.finish() {
+            println!("ERROR: couldn't write demo: {}", err);
+        }
+        println!("Completed demo");
+    }
+
+    /// Handles the playdemo command. Demos are read from the game
+    /// directory or the pak files.
+    pub fn playdemo_cmd(&mut self, args : &[String], game_res : &mut GameResources, time : f64) {
+        if args.len() != 2 {
+            println!("playdemo <demoname> : plays a demo");
+            return;
+        }
+        self.disconnect(time);
+
+        let name = default_extension(&args[1], "dem");
+        println!("Playing demo from {}.", name);
+        match game_res.load_file(&name).and_then(DemoPlayer::new) {
+            Some(player) => self.play_demo(player),
+            None => println!("ERROR: couldn't open."),
+        }
+    }
+
+    /// Handles the timedemo command.
+    pub fn timedemo_cmd(&mut self, args : &[String], game_res : &mut GameResources, time : f64) {
+        if args.len() != 2 {
+            println!("timedemo <demoname> : gets demo speeds");
+            return;
+        }
+        self.playdemo_cmd(args, game_res, time);
+        if self.player.is_some() {
+            // the start time is taken in the second frame so the loading
+            // time doesn't count
+            self.timedemo = Some(TimeDemo::new(self.framecount));
+        }
+    }
+
+    /// Starts playing a demo.
+    pub fn play_demo(&mut self, player : DemoPlayer) {
+        self.forcetrack = player.cdtrack;
+        self.player = Some(player);
+        self.signon = 0;
+    }
+
+    /// Ends demo playback and reports the timedemo result.
+    fn stop_playback(&mut self, time : f64) {
+        self.player = None;
+        self.signon = 0;
+        if let Some(td) = self.timedemo.take() {
+            let (frames, seconds) = td.result(self.framecount, time);
+            println!("{} frames {:5.1} seconds {:5.1} fps", frames, seconds, frames as f64 / seconds);
+        }
+    }
+
+    /// Returns the next message from the demo or the server. Received
+    /// messages are written to the demo that is being recorded.
+    fn get_message(&mut self, viewangles : &Vec3, time : f64) -> Option<Vec<u8>> {
+        if self.player.is_some() {
+            // always grab until fully connected
+            if self.signon == protocol::SIGNONS {
+                match self.timedemo {
+                    Some(ref mut td) => {
+                        // one message per frame
+                        if td.last_frame == Some(self.framecount) {
+                            return None;
+                        }
+                        td.last_frame = Some(self.framecount);
+                        if self.framecount == td.start_frame + 1 {
+                            td.start_time = time;
+                        }
+                    }
+                    None => if self.time <= self.mtime[0] {
+                        // don't need another message yet
+                        return None;
+                    },
+                }
+            }
+
+            let message = self.player.as_mut().and_then(|player| player.read_message());
+            return match message {
+                Some((angles, data)) => {
+                    self.mviewangles[1] = self.mviewangles[0];
+                    self.mviewangles[0] = angles;
+                    Some(data)
+                }
+                None => {
+                    self.stop_playback(time);
+                    None
+                }
+            };
+        }
+
+        let message = match self.netcon {
+            Some(ref mut socket) => socket.get_message(time),
+            None => return None,
+        };
+        let data = match message {
+            Some(NetMessage::Reliable(data)) | Some(NetMessage::Unreliable(data)) => data,
+            Some(NetMessage::Disconnected) => {
+                println!("CL_ReadFromServer: lost server connection");
+                self.disconnect(time);
+                return None;
+            }
+            None => return None,
+        };
+
+        if let Some(ref mut recorder) = self.recorder {
+            if let Err(err) = recorder.write_message(viewangles, &data) {
+                println!("ERROR: couldn't write demo: {}", err);
+            }
+        }
+        Some(data)
+    }
+
+    /// Reads all messages of this frame. The time, signon and disconnect
+    /// messages are handled here, all messages are returned for the rest
+    /// of the client.
+    pub fn read_messages(&mut self, frametime : f32, viewangles : &Vec3, time : f64) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        self.time += frametime;
+
+        while let Some(data) = self.get_message(viewangles, time) {
+            let mut msg = MsgReader::new(&data);
+            while let Some(mut message) = ServerMessage::read(&mut msg) {
+                match message {
+                    ServerMessage::Time(t) => {
+                        self.mtime[1] = self.mtime[0];
+                        self.mtime[0] = t;
+                    }
+                    ServerMessage::SignonNum(n) => {
+                        if n <= self.signon {
+                            println!("Received signon {} when at {}", n, self.signon);
+                        } else {
+                            self.signon = n;
+                            self.signon_reply();
+                        }
+                    }
+                    ServerMessage::ServerInfo { .. } => {
+                        // a new level starts the signon over
+                        self.signon = 0;
+                    }
+                    ServerMessage::Update(_) if self.signon == protocol::SIGNONS - 1 => {
+                        // first update is the final signon stage
+                        self.signon = protocol::SIGNONS;
+                    }
+                    ServerMessage::CdTrack { ref mut track, ref mut looptrack } => {
+                        if self.forcetrack != -1 && (self.player.is_some() || self.recorder.is_some()) {
+                            *track = self.forcetrack;
+                            *looptrack = self.forcetrack;
+                        }
+                    }
+                    _ => {}
+                }
+                let disconnect = message == ServerMessage::Disconnect;
+                messages.push(message);
+                if disconnect {
+                    println!("Server disconnected");
+                    self.disconnect(time);
+                    break;
+                }
+            }
+            if !self.is_connected() {
+                break;
+            }
+        }
+
+        self.framecount += 1;
+        messages
+    }
+
+    /// Queues the string commands the server expects after each signon
+    /// message. The name and colors are sent before spawning.
+    fn signon_reply(&mut self) {
+        let commands = match self.signon {
+            1 => vec!["prespawn".to_string()],
+            2 => vec![format!("name \"{}\"\n", self.name), format!("color {} {}\n", self.color >> 4, self.color & 15), "spawn".to_string()],
+            3 => vec!["begin".to_string()],
+            _ => return,
+        };
+        for command in commands {
+            ClientMessage::StringCmd(command).write(&mut self.message);
+        }
+    }
+
+    /// Sends a console command to the server, used by the commands that
+    /// are executed by the server like say or status.
+    pub fn forward_to_server(&mut self, args : &[String]) {
+        if self.netcon.is_none() {
+            println!("Can't \"{}\", not connected", args[0]);
+            return;
+        }
+        let mut text = args[0].clone();
+        for arg in &args[1..] {
+            text += &format!(" \"{}\"", arg);
+        }
+        ClientMessage::StringCmd(text).write(&mut self.message);
+    }
+
+    /// Sends the move command once the client is in the game and the
+    /// queued reliable commands.
+    pub fn send_cmd(&mut self, cmd : &UserCmd, time : f64) {
+        let socket = match self.netcon {
+            Some(ref mut socket) => socket,
+            None => {
+                self.message.clear();
+                return;
+            }
+        };
+
+        if self.signon == protocol::SIGNONS {
+            let mut buf = SizeBuf::new(128);
+            ClientMessage::Move { time : self.mtime[0], cmd : *cmd }.write(&mut buf);
+            socket.send_unreliable_message(&buf.data, time);
+        }
+
+        if !self.message.is_empty() && socket.can_send_message(time) {
+            socket.send_message(&self.message.data, time);
+            self.message.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use net::NetDriver;
+    use net_loop::LoopDriver;
+
+    fn write(messages : &[ServerMessage]) -> Vec<u8> {
+        let mut buf = SizeBuf::new(MAX_MSGLEN);
+        for message in messages {
+            message.write(&mut buf);
+        }
+        buf.data
+    }
+
+    fn write_client(messages : &[ClientMessage]) -> Vec<u8> {
+        let mut buf = SizeBuf::new(MAX_MSGLEN);
+        for message in messages {
+            message.write(&mut buf);
+        }
+        buf.data
+    }
+
+    fn demo(messages : &[Vec<ServerMessage>]) -> DemoPlayer {
+        let mut data = b"3\n".to_vec();
+        for message in messages {
+            let message = write(message);
+            let mut buf = SizeBuf::new(MAX_MSGLEN + 16);
+            buf.write_long(message.len() as i32);
+            for _ in 0..3 {
+                buf.write_float(0.0);
+            }
+            buf.write(&message);
+            data.extend_from_slice(&buf.data);
+        }
+        DemoPlayer::new(data).unwrap()
+    }
+
+    #[test]
+    fn signon_replies() {
+        let mut driver = LoopDriver::new();
+        driver.listen(true);
+        let mut cls = ClientStatic::new("Id1");
+        cls.connect(driver.connect("local", 0.0).unwrap(), 0.0);
+        cls.name = "ranger".to_string();
+        cls.color = 0x1a;
+        let mut server = driver.check_new_connections(0.0).unwrap();
+
+        server.send_message(&write(&[ServerMessage::SignonNum(1)]), 0.0);
+        cls.read_messages(0.1, &[0.0; 3], 0.0);
+        assert_eq!(cls.signon, 1);
+        cls.send_cmd(&UserCmd::new(), 0.0);
+        let prespawn = write_client(&[ClientMessage::StringCmd("prespawn".to_string())]);
+        assert_eq!(server.get_message(0.0), Some(NetMessage::Reliable(prespawn)));
+
+        server.send_message(&write(&[ServerMessage::SignonNum(2), ServerMessage::SignonNum(3), ServerMessage::SignonNum(4)]), 0.0);
+        cls.read_messages(0.1, &[0.0; 3], 0.0);
+        assert_eq!(cls.signon, protocol::SIGNONS);
+        let mut cmd = UserCmd::new();
+        cmd.forwardmove = 200.0;
+        cls.send_cmd(&cmd, 0.0);
+        assert_eq!(server.get_message(0.0), Some(NetMessage::Unreliable(write_client(&[ClientMessage::Move { time : 0.0, cmd : cmd }]))));
+        match server.get_message(0.0) {
+            Some(NetMessage::Reliable(data)) => {
+                assert!(data.windows(5).any(|w| w == b"spawn") && data.ends_with(b"begin\0"));
+                assert!(data.windows(15).any(|w| w == b"name \"ranger\"\n\0"));
+                assert!(data.windows(11).any(|w| w == b"color 1 10\n"));
+            }
+            message => panic!("unexpected {:?}", message),
+        }
+
+        // a level change starts the signon over
+        let serverinfo = ServerMessage::ServerInfo {
+            protocol : protocol::PROTOCOL_VERSION,
+            maxclients : 1,
+            gametype : protocol::GAME_COOP,
+            levelname : String::new(),
+            models : vec!["maps/e1m2.bsp".to_string()],
+            sounds : Vec::new(),
+        };
+        server.send_message(&write(&[serverinfo, ServerMessage::SignonNum(1)]), 0.0);
+        cls.read_messages(0.1, &[0.0; 3], 0.0);
+        assert_eq!(cls.signon, 1);
+
+        server.send_message(&write(&[ServerMessage::Disconnect]), 0.0);
+        cls.read_messages(0.1, &[0.0; 3], 0.0);
+        assert!(!cls.is_connected());
+    }
+
+    #[test]
+    fn demo_playback() {
+        let mut cls = ClientStatic::new("Id1");
+        cls.play_demo(demo(&[
+            vec![ServerMessage::CdTrack { track : 2, looptrack : 2 }, ServerMessage::SignonNum(1)],
+            vec![ServerMessage::SignonNum(2), ServerMessage::SignonNum(3), ServerMessage::SignonNum(4)],
+            vec![ServerMessage::Time(0.1)],
+            vec![ServerMessage::Time(0.2)],
+        ]));
+        assert!(cls.is_connected());
+
+        // the signon is read at once, the forced track replaces the one
+        // sent by the server
+        let messages = cls.read_messages(0.0, &[0.0; 3], 0.0);
+        assert_eq!(messages.len(), 5);
+        assert_eq!(messages[0], ServerMessage::CdTrack { track : 3, looptrack : 3 });
+
+        // afterwards messages are read when the client time passes the server time
+        assert_eq!(cls.read_messages(0.05, &[0.0; 3], 0.0), vec![ServerMessage::Time(0.1)]);
+        assert!(cls.read_messages(0.05, &[0.0; 3], 0.0).is_empty());
+        assert_eq!(cls.read_messages(0.1, &[0.0; 3], 0.0), vec![ServerMessage::Time(0.2)]);
+        assert!(cls.read_messages(0.1, &[0.0; 3], 0.0).is_empty());
+        assert!(!cls.is_connected());
+    }
+
+    #[test]
+    fn timedemo_frames() {
+        let mut cls = ClientStatic::new("Id1");
+        cls.play_demo(demo(&[
+            vec![ServerMessage::SignonNum(1), ServerMessage::SignonNum(2), ServerMessage::SignonNum(3), ServerMessage::SignonNum(4)],
+            vec![ServerMessage::Time(5.0)],
+            vec![ServerMessage::Time(6.0)],
+        ]));
+        cls.timedemo = Some(TimeDemo::new(cls.framecount));
+        assert!(cls.is_timedemo());
+
+        // one message per frame regardless of the time
+        assert_eq!(cls.read_messages(0.0, &[0.0; 3], 0.0).len(), 5);
+        assert_eq!(cls.read_messages(0.0, &[0.0; 3], 0.5), vec![ServerMessage::Time(6.0)]);
+        assert_eq!(cls.timedemo.unwrap().start_time, 0.5);
+        assert!(cls.read_messages(0.0, &[0.0; 3], 1.5).is_empty());
+        assert!(!cls.is_timedemo() && !cls.is_connected());
+    }
+}
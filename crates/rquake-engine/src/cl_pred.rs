@@ -0,0 +1,446 @@
+#![warn(missing_docs)]
+
+//! Prediction of the player movement. The move commands the server hasn't
+//! answered yet are run on the last player state of the server with the
+//! movement code of the server, so the view doesn't lag behind the input.
+//! Differences between the prediction and the server are smoothed out.
+//!
+//! Original source can be found in cl_pred.c of QuakeWorld
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{vector_add, vector_length, vector_scale, vector_subtract};
+use cl_input::UserCmd;
+use client::ClientState;
+use cvar::CvarList;
+use model::BrushModel;
+use progdefs;
+use progs::Progs;
+use protocol;
+use server::{self, Server};
+use sv_phys::PhysicsParams;
+
+/// Corrections longer than this are teleports and aren't smoothed.
+const MAX_SMOOTH_ERROR : f32 = 64.0;
+
+/// Part of the remaining correction that is removed per second.
+const SMOOTH_RATE : f32 = 10.0;
+
+/// Most move commands that are kept for replaying.
+const MAX_STORED_CMDS : usize = 128;
+
+/// Bounding box of the players.
+const PLAYER_MINS : Vec3 = [-16.0, -16.0, -24.0];
+const PLAYER_MAXS : Vec3 = [16.0, 16.0, 32.0];
+
+/// Registers the prediction cvar.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("cl_predict", "0", true, false);
+}
+
+/// The client doesn't run QuakeC, the think functions of the player are
+/// skipped.
+struct NoProgs;
+
+impl Progs for NoProgs {
+    fn execute(&mut self, _sv : &mut Server, _function : i32) {
+    }
+}
+
+/// The world of the level with the player entity, the entities it collides
+/// with and the move commands that have been sent to the server.
+pub struct Prediction {
+    world : Server,
+    cmds : VecDeque<(f64, f32, UserCmd)>,
+    update_time : f32,
+    origin : Option<Vec3>,
+    error : Vec3,
+}
+
+impl Prediction {
+    /// Creates the prediction without a level.
+    pub fn new() -> Prediction {
+        Prediction {
+            world : Server::new(1),
+            cmds : VecDeque::new(),
+            update_time : 0.0,
+            origin : None,
+            error : [0.0; 3],
+        }
+    }
+
+    /// Takes the collision hulls of the brush models of a new level by model
+    /// index, None for models that aren't brush models. Model 1 is the world,
+    /// there is no prediction without it.
+    pub fn new_map(&mut self, models : Vec<Option<Rc<BrushModel>>>) {
+        self.world = Server::new(1);
+        let world = models.get(1).and_then(|world| world.clone());
+        if let Some(world) = world {
+            self.world.clear_world(world.mins, world.maxs);
+            self.world.models = models;
+            let v = &mut self.world.edicts[0].v;
+            v.set_float(progdefs::MODELINDEX, 1.0);
+            v.set_float(progdefs::SOLID, server::SOLID_BSP);
+            v.set_float(progdefs::MOVETYPE, server::MOVETYPE_PUSH);
+        }
+        self.world.clients[0].active = true;
+        self.world.clients[0].spawned = true;
+        self.reset();
+    }
+
+    /// Forgets the move commands and the predicted position.
+    pub fn reset(&mut self) {
+        self.cmds.clear();
+        self.origin = None;
+        self.error = [0.0; 3];
+    }
+
+    /// Returns the predicted origin of the player with the rest of the last
+    /// correction, `None` if nothing is predicted.
+    pub fn origin(&self) -> Option<Vec3> {
+        self.origin.map(|origin| vector_add(&origin, &self.error))
+    }
+
+    /// Predicts the player position after the move command that has just
+    /// been sent. When the server sent a new player state the commands that
+    /// are older than the round trip time are dropped and the others are
+    /// run again on the new state.
+    pub fn predict(&mut self, cl : &ClientState, cmd : &UserCmd, round_trip_time : f64, time : f64, params : &PhysicsParams) {
+        if self.world.models.len() < 2 || cl.viewentity == 0 || cl.viewentity >= cl.entities.len() ||
+            cl.intermission != 0 || cl.paused || cl.stats[protocol::STAT_HEALTH as usize] <= 0 {
+            self.reset();
+            return;
+        }
+        self.cmds.push_back((time, params.frametime, *cmd));
+        if self.cmds.len() > MAX_STORED_CMDS {
+            self.cmds.pop_front();
+        }
+        self.error = vector_scale(&self.error, (1.0 - params.frametime * SMOOTH_RATE).max(0.0));
+
+        // where the last prediction goes with the new command
+        let expected = self.origin.map(|_| {
+            self.world.run_player_move(0, cmd, &mut NoProgs, params);
+            self.player_origin()
+        });
+        if expected.is_some() && cl.mtime[0] == self.update_time {
+            self.origin = expected;
+            return;
+        }
+
+        self.update_time = cl.mtime[0];
+        while self.cmds.front().map_or(false, |&(sent, _, _)| sent <= time - round_trip_time) {
+            self.cmds.pop_front();
+        }
+        self.set_player(cl);
+        self.set_entities(cl);
+        for &(_, frametime, ref cmd) in &self.cmds {
+            let params = PhysicsParams { frametime : frametime, ..*params };
+            self.world.run_player_move(0, cmd, &mut NoProgs, &params);
+        }
+        let origin = self.player_origin();
+
+        if let Some(expected) = expected {
+            let error = vector_add(&self.error, &vector_subtract(&expected, &origin));
+            self.error = if vector_length(&error) > MAX_SMOOTH_ERROR { [0.0; 3] } else { error };
+        }
+        self.origin = Some(origin);
+    }
+
+    fn player_origin(&self) -> Vec3 {
+        self.world.edicts[1].v.vector(progdefs::ORIGIN)
+    }
+
+    /// Copies the last player state sent by the server to the player entity.
+    fn set_player(&mut self, cl : &ClientState) {
+        let mut flags = server::FL_CLIENT;
+        if cl.onground {
+            flags |= server::FL_ONGROUND;
+        }
+        let v = &mut self.world.edicts[1].v;
+        v.set_vector(progdefs::ORIGIN, cl.entities[cl.viewentity].msg_origins[0]);
+        v.set_vector(progdefs::VELOCITY, cl.mvelocity[0]);
+        v.set_vector(progdefs::MINS, PLAYER_MINS);
+        v.set_vector(progdefs::MAXS, PLAYER_MAXS);
+        v.set_vector(progdefs::SIZE, vector_subtract(&PLAYER_MAXS, &PLAYER_MINS));
+        v.set_float(progdefs::FLAGS, flags as f32);
+        v.set_float(progdefs::WATERLEVEL, if cl.inwater { 2.0 } else { 0.0 });
+        v.set_float(progdefs::HEALTH, cl.stats[protocol::STAT_HEALTH as usize] as f32);
+        v.set_float(progdefs::MOVETYPE, server::MOVETYPE_WALK);
+        v.set_float(progdefs::SOLID, server::SOLID_SLIDEBOX);
+    }
+
+    /// Replaces the entities the player collides with by the brush entities
+    /// and the other players of the last server update.
+    fn set_entities(&mut self, cl : &ClientState) {
+        for ent in 2..self.world.edicts.len() {
+            self.world.unlink_edict(ent);
+        }
+        self.world.edicts.truncate(2);
+
+        for (num, ent) in cl.entities.iter().enumerate().skip(1) {
+            if num == cl.viewentity || ent.state.modelindex == 0 || ent.msgtime != cl.mtime[0] {
+                continue;
+            }
+            let model = self.world.models.get(ent.state.modelindex as usize).and_then(|model| model.clone());
+            let (mins, maxs, solid, movetype) = match model {
+                Some(ref model) => (model.mins, model.maxs, server::SOLID_BSP, server::MOVETYPE_PUSH),
+                None if num <= cl.maxclients as usize => (PLAYER_MINS, PLAYER_MAXS, server::SOLID_SLIDEBOX, server::MOVETYPE_WALK),
+                None => continue,
+            };
+            let e = match self.world.alloc_edict() {
+                Some(e) => e,
+                None => break,
+            };
+            {
+                let v = &mut self.world.edicts[e].v;
+                v.set_vector(progdefs::ORIGIN, ent.msg_origins[0]);
+                v.set_vector(progdefs::ANGLES, ent.msg_angles[0]);
+                v.set_vector(progdefs::MINS, mins);
+                v.set_vector(progdefs::MAXS, maxs);
+                v.set_vector(progdefs::SIZE, vector_subtract(&maxs, &mins));
+                v.set_float(progdefs::MODELINDEX, ent.state.modelindex as f32);
+                v.set_float(progdefs::SOLID, solid);
+                v.set_float(progdefs::MOVETYPE, movetype);
+            }
+            self.world.link_edict(e, false, &mut NoProgs);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cl_main::ClientStatic;
+    use client::Entity;
+    use host_cmd::CommandParams;
+    use net::NetDriver;
+    use net_loop::LoopDriver;
+    use protocol::{ClientData, ServerMessage};
+    use sv_phys;
+    use sv_user;
+    use trace::{ClipNode, Hull, Plane, CONTENTS_EMPTY, CONTENTS_SOLID};
+    use view;
+
+    /// Frames of 1/32 second and a latency of 4 frames each way.
+    const FRAMETIME : f32 = 1.0 / 32.0;
+    const LATENCY : f64 = 0.125;
+
+    /// Floor at z = 0 expanded by the given box.
+    fn floor_hull(clip_mins : Vec3, clip_maxs : Vec3) -> Hull {
+        Hull {
+            clipnodes : Rc::new(vec![ClipNode { plane : 0, children : [CONTENTS_EMPTY, CONTENTS_SOLID] }]),
+            planes : Rc::new(vec![Plane { normal : [0.0, 0.0, 1.0], dist : -clip_mins[2], kind : 2 }]),
+            first_clipnode : 0,
+            clip_mins : clip_mins,
+            clip_maxs : clip_maxs,
+        }
+    }
+
+    fn floor() -> Rc<BrushModel> {
+        Rc::new(BrushModel {
+            mins : [-4096.0; 3],
+            maxs : [4096.0; 3],
+            hulls : [floor_hull([0.0; 3], [0.0; 3]),
+                     floor_hull([-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]),
+                     floor_hull([-32.0, -32.0, -24.0], [32.0, 32.0, 64.0])],
+        })
+    }
+
+    /// Brush model filling x >= 0, used as a closed door.
+    fn door() -> Rc<BrushModel> {
+        let hull = |clip_mins : Vec3, clip_maxs : Vec3| Hull {
+            clipnodes : Rc::new(vec![ClipNode { plane : 0, children : [CONTENTS_EMPTY, CONTENTS_SOLID] }]),
+            planes : Rc::new(vec![Plane { normal : [-1.0, 0.0, 0.0], dist : clip_maxs[0], kind : 0 }]),
+            first_clipnode : 0,
+            clip_mins : clip_mins,
+            clip_maxs : clip_maxs,
+        };
+        Rc::new(BrushModel {
+            mins : [0.0, -4096.0, -4096.0],
+            maxs : [64.0, 4096.0, 4096.0],
+            hulls : [hull([0.0; 3], [0.0; 3]),
+                     hull([-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]),
+                     hull([-32.0, -32.0, -24.0], [32.0, 32.0, 64.0])],
+        })
+    }
+
+    fn params() -> PhysicsParams {
+        let mut cvars = CvarList::new();
+        sv_phys::register_cvars(&mut cvars);
+        sv_user::register_cvars(&mut cvars);
+        view::register_cvars(&mut cvars);
+        PhysicsParams::new(&cvars, FRAMETIME)
+    }
+
+    /// A client standing on the floor with a state received at the given
+    /// server time.
+    fn client_at(origin : Vec3, mtime : f32) -> ClientState {
+        let mut cl = ClientState::new();
+        let mut data = ClientData::new();
+        data.health = 100;
+        data.onground = true;
+        cl.parse_message(&ServerMessage::ClientData(data), 0.0);
+        cl.viewentity = 1;
+        cl.entities = vec![Entity::new(), Entity::new()];
+        cl.entities[1].msg_origins[0] = origin;
+        cl.mtime[0] = mtime;
+        cl
+    }
+
+    #[test]
+    fn movement_over_latency() {
+        let params = params();
+        let mut driver = LoopDriver::with_latency(LATENCY);
+        let mut cls = ClientStatic::new("Id1");
+        cls.connect(driver.connect("local", 0.0).unwrap(), 0.0);
+        cls.signon = protocol::SIGNONS;
+
+        // the server with a player standing on the floor
+        let mut sv = Server::new(1);
+        sv.models = vec![None, Some(floor())];
+        {
+            let world = &mut sv.edicts[0].v;
+            world.set_float(progdefs::MODELINDEX, 1.0);
+            world.set_float(progdefs::SOLID, server::SOLID_BSP);
+            world.set_float(progdefs::MOVETYPE, server::MOVETYPE_PUSH);
+            let v = &mut sv.edicts[1].v;
+            v.set_vector(progdefs::ORIGIN, [0.0, 0.0, 24.0]);
+            v.set_vector(progdefs::MINS, [-16.0, -16.0, -24.0]);
+            v.set_vector(progdefs::MAXS, [16.0, 16.0, 32.0]);
+            v.set_vector(progdefs::SIZE, [32.0, 32.0, 56.0]);
+            v.set_float(progdefs::MOVETYPE, server::MOVETYPE_WALK);
+            v.set_float(progdefs::SOLID, server::SOLID_SLIDEBOX);
+            v.set_float(progdefs::HEALTH, 100.0);
+            v.set_float(progdefs::FLAGS, (server::FL_CLIENT | server::FL_ONGROUND) as f32);
+        }
+        sv.clients[0].active = true;
+        sv.clients[0].spawned = true;
+        sv.clients[0].netconnection = driver.check_new_connections(0.0);
+        let commands = CommandParams { hostname : String::new(), teamplay : false, pausable : true };
+
+        let mut cl = ClientState::new();
+        cl.viewentity = 1;
+        let mut prediction = Prediction::new();
+        prediction.new_map(vec![None, Some(floor())]);
+
+        let mut cmd = UserCmd::new();
+        cmd.forwardmove = 200.0;
+        let mut server_origins = Vec::new();
+        let mut predicted = Vec::new();
+        let mut received = Vec::new();
+        for frame in 0..80 {
+            let time = frame as f64 * FRAMETIME as f64;
+            cls.send_cmd(&cmd, time);
+
+            sv.read_client_messages(&mut NoProgs, &commands, time);
+            sv.run_clients(&params);
+            sv.physics(&mut NoProgs, &params);
+            sv.send_client_messages(&mut NoProgs, time);
+            server_origins.push(sv.edicts[1].v.vector(progdefs::ORIGIN));
+
+            for message in cls.read_messages(FRAMETIME, &cmd.viewangles, time) {
+                cl.parse_message(&message, cls.time);
+            }
+            prediction.predict(&cl, &cmd, cls.round_trip_time(), time, &params);
+            predicted.push(prediction.origin());
+            received.push(cl.entities.get(1).map_or(0.0, |ent| ent.msg_origins[0][0]));
+        }
+
+        // nothing is predicted until the first player state arrives
+        assert_eq!(predicted[3], None);
+        assert!(predicted[8].is_some());
+
+        // the prediction is where the server will be once it has run the
+        // same commands, the server state lags 8 frames behind
+        for frame in 40..76 {
+            let origin = predicted[frame].unwrap();
+            let server = server_origins[frame + 4];
+            assert!((origin[0] - server[0]).abs() < 1.0, "frame {}: {} {}", frame, origin[0], server[0]);
+            assert!((origin[2] - 24.0).abs() < 0.1);
+            assert!(server[0] - received[frame] > 40.0);
+        }
+    }
+
+    /// Runs the player forward for two seconds and returns the x coordinate
+    /// it gets to.
+    fn run_forward(prediction : &mut Prediction, cl : &ClientState) -> f32 {
+        let params = params();
+        let mut cmd = UserCmd::new();
+        cmd.forwardmove = 400.0;
+        for frame in 0..64 {
+            prediction.predict(cl, &cmd, 0.0, 1.0 + frame as f64 * FRAMETIME as f64, &params);
+        }
+        prediction.origin().unwrap()[0]
+    }
+
+    #[test]
+    fn clip_against_entities() {
+        let mut prediction = Prediction::new();
+        prediction.new_map(vec![None, Some(floor()), None, Some(door())]);
+        let mut cl = client_at([0.0, 0.0, 24.0], 1.0);
+        cl.maxclients = 2;
+        assert!(run_forward(&mut prediction, &cl) > 200.0);
+
+        // the door of the last update blocks the player
+        let mut door = Entity::new();
+        door.state.modelindex = 3;
+        door.msg_origins[0] = [100.0, 0.0, 0.0];
+        door.msgtime = 1.0;
+        cl.entities.push(Entity::new());
+        cl.entities.push(door);
+        prediction.reset();
+        let x = run_forward(&mut prediction, &cl);
+        assert!(x > 80.0 && x <= 84.0, "{}", x);
+
+        // entities that weren't in the last update are gone
+        cl.entities[3].msgtime = 0.5;
+        prediction.reset();
+        assert!(run_forward(&mut prediction, &cl) > 200.0);
+
+        // the other players block the player as well
+        cl.entities[2].state.modelindex = 2;
+        cl.entities[2].msg_origins[0] = [50.0, 0.0, 24.0];
+        cl.entities[2].msgtime = 1.0;
+        prediction.reset();
+        let x = run_forward(&mut prediction, &cl);
+        assert!(x > 14.0 && x <= 18.0, "{}", x);
+    }
+
+    #[test]
+    fn corrections_are_smoothed() {
+        let params = params();
+        let mut prediction = Prediction::new();
+        prediction.new_map(vec![None, Some(floor())]);
+        let cmd = UserCmd::new();
+
+        let cl = client_at([0.0, 0.0, 24.0], 1.0);
+        prediction.predict(&cl, &cmd, 0.0, 1.0, &params);
+        assert_eq!(prediction.origin(), Some([0.0, 0.0, 24.0]));
+
+        // the server has the player a bit further, the view glides there
+        let cl = client_at([8.0, 0.0, 24.0], 1.1);
+        prediction.predict(&cl, &cmd, 0.0, 1.1, &params);
+        assert_eq!(prediction.origin(), Some([0.0, 0.0, 24.0]));
+        let mut last = 0.0;
+        for frame in 0..20 {
+            prediction.predict(&cl, &cmd, 0.0, 1.1 + frame as f64 * 0.03, &params);
+            let x = prediction.origin().unwrap()[0];
+            assert!(x >= last && x <= 8.0);
+            last = x;
+        }
+        assert!(last > 7.9);
+
+        // teleports aren't smoothed
+        let cl = client_at([500.0, 0.0, 24.0], 2.0);
+        prediction.predict(&cl, &cmd, 0.0, 2.0, &params);
+        assert_eq!(prediction.origin(), Some([500.0, 0.0, 24.0]));
+
+        // dead players aren't predicted
+        let mut cl = client_at([500.0, 0.0, 24.0], 2.1);
+        cl.stats[protocol::STAT_HEALTH as usize] = 0;
+        prediction.predict(&cl, &cmd, 0.0, 2.1, &params);
+        assert_eq!(prediction.origin(), None);
+    }
+}
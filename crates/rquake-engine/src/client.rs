@@ -0,0 +1,680 @@
+#![warn(missing_docs)]
+
+//! Client state of the current level: the entities received from the
+//! server, their interpolation and the player data used for the view.
+//!
+//! Original source can be found in client.h, cl_main.c and cl_parse.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{angle_vectors, vector_ma};
+use protocol::{self, ClientData, EntityState, EntityUpdate, ServerMessage, TempEntity};
+use r_light::DLights;
+use r_part::{self, Particles, EF_ROCKET};
+use server;
+
+/// Maximum number of static entities like torches.
+pub const MAX_STATIC_ENTITIES : usize = 128;
+
+/// Maximum number of entities drawn in a frame.
+pub const MAX_VISEDICTS : usize = 256;
+
+/// Updates that move an entity further than this along an axis are
+/// teleports and aren't interpolated.
+const TELEPORT_DISTANCE : f32 = 100.0;
+
+/// An entity as known by the client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entity {
+    /// Interpolated state used for drawing, no model if modelindex is 0.
+    pub state : EntityState,
+    /// State the updates are relative to.
+    pub baseline : EntityState,
+    /// Origins of the last two updates, newest first.
+    pub msg_origins : [Vec3; 2],
+    /// Angles of the last two updates, newest first.
+    pub msg_angles : [Vec3; 2],
+    /// Server time of the last update.
+    pub msgtime : f32,
+    /// Don't interpolate from the previous update.
+    pub forcelink : bool,
+}
+
+impl Entity {
+    /// Creates an entity that hasn't been received yet.
+    pub fn new() -> Entity {
+        Entity {
+            state : EntityState::new(),
+            baseline : EntityState::new(),
+            msg_origins : [[0.0; 3]; 2],
+            msg_angles : [[0.0; 3]; 2],
+            msgtime : 0.0,
+            forcelink : false,
+        }
+    }
+}
+
+/// First palette index of the shirt colors of player skins.
+const TOP_RANGE : usize = 16;
+
+/// First palette index of the pants colors of player skins.
+const BOTTOM_RANGE : usize = 96;
+
+/// Scoreboard entry of a client slot.
+#[derive(Clone)]
+pub struct Score {
+    /// Player name, empty for unused slots.
+    pub name : String,
+    /// Frag count.
+    pub frags : i32,
+    /// Shirt color in the high and pants color in the low 4 bits.
+    pub colors : i32,
+    /// Palette translation for the skin of the player.
+    pub translation : [u8; 256],
+}
+
+impl Score {
+    /// Creates an unused slot.
+    pub fn new() -> Score {
+        Score {
+            name : String::new(),
+            frags : 0,
+            colors : 0,
+            translation : translation_table(0),
+        }
+    }
+
+    /// Returns a player skin with the shirt and pants colors of the player.
+    pub fn translate_skin(&self, pixels : &[u8]) -> Vec<u8> {
+        pixels.iter().map(|&p| self.translation[p as usize]).collect()
+    }
+}
+
+/// Returns the palette translation that replaces the shirt and pants
+/// ranges of a player skin with the given colors. Colors from 8 on are
+/// stored from bright to dark in the palette and are reversed.
+pub fn translation_table(colors : i32) -> [u8; 256] {
+    let mut table = [0; 256];
+    for (i, entry) in table.iter_mut().enumerate() {
+        *entry = i as u8;
+    }
+    let top = (colors & 0xf0) as usize;
+    let bottom = ((colors & 15) << 4) as usize;
+    for j in 0..16 {
+        table[TOP_RANGE + j] = (if top < 128 { top + j } else { top + 15 - j }) as u8;
+        table[BOTTOM_RANGE + j] = (if bottom < 128 { bottom + j } else { bottom + 15 - j }) as u8;
+    }
+    table
+}
+
+/// Interpolates between two angles in degrees the short way around.
+fn lerp_angle(from : f32, to : f32, frac : f32) -> f32 {
+    let mut d = to - from;
+    if d > 180.0 {
+        d -= 360.0;
+    } else if d < -180.0 {
+        d += 360.0;
+    }
+    from + frac * d
+}
+
+/// State of the level the client is in, cleared when a new server info
+/// is received.
+pub struct ClientState {
+    /// Maximum number of players on the server.
+    pub maxclients : i32,
+    /// `GAME_COOP` or `GAME_DEATHMATCH`.
+    pub gametype : i32,
+    /// Message of the world entity.
+    pub levelname : String,
+    /// Model names by model index, index 0 is unused.
+    pub model_precache : Vec<String>,
+    /// Sound names by sound index, index 0 is unused.
+    pub sound_precache : Vec<String>,
+    /// Entities by entity number.
+    pub entities : Vec<Entity>,
+    /// Entities that are never updated like torches.
+    pub static_entities : Vec<Entity>,
+    /// Entity the view is attached to.
+    pub viewentity : usize,
+    /// Health, ammo and the other STAT_ values.
+    pub stats : [i32; protocol::MAX_CL_STATS],
+    /// Inventory bits.
+    pub items : i32,
+    /// Height of the eyes above the entity origin.
+    pub viewheight : f32,
+    /// Pitch the view drifts to when walking up or down stairs.
+    pub idealpitch : f32,
+    /// Temporary view offset for weapon recoil.
+    pub punchangle : Vec3,
+    /// Player velocities of the last two updates, newest first.
+    pub mvelocity : [Vec3; 2],
+    /// Interpolated player velocity.
+    pub velocity : Vec3,
+    /// The player is standing on the ground.
+    pub onground : bool,
+    /// The player is in water.
+    pub inwater : bool,
+    /// The server is paused.
+    pub paused : bool,
+    /// 0 while playing, 1 for the intermission, 2 for the finale and 3 for cutscenes.
+    pub intermission : i32,
+    /// Client time when the level was completed.
+    pub completed_time : f32,
+    /// Server times of the last two messages, newest first.
+    pub mtime : [f32; 2],
+    /// Entities drawn in this frame.
+    pub visedicts : Vec<EntityState>,
+    /// Light style strings set by the server.
+    pub lightstyles : Vec<String>,
+    /// Names, frags and colors of the client slots.
+    pub scores : Vec<Score>,
+    /// Dynamic lights of muzzle flashes, rockets and explosions.
+    pub dlights : DLights,
+    /// Flags like EF_ROCKET of the precached models by model index.
+    pub model_flags : Vec<i32>,
+    seed : u32,
+}
+
+impl ClientState {
+    /// Creates the state of a client that isn't in a level.
+    pub fn new() -> ClientState {
+        ClientState {
+            maxclients : 0,
+            gametype : protocol::GAME_COOP,
+            levelname : String::new(),
+            model_precache : vec![String::new()],
+            sound_precache : vec![String::new()],
+            entities : Vec::new(),
+            static_entities : Vec::new(),
+            viewentity : 0,
+            stats : [0; protocol::MAX_CL_STATS],
+            items : 0,
+            viewheight : protocol::DEFAULT_VIEWHEIGHT as f32,
+            idealpitch : 0.0,
+            punchangle : [0.0; 3],
+            mvelocity : [[0.0; 3]; 2],
+            velocity : [0.0; 3],
+            onground : false,
+            inwater : false,
+            paused : false,
+            intermission : 0,
+            completed_time : 0.0,
+            mtime : [0.0; 2],
+            visedicts : Vec::new(),
+            lightstyles : vec![String::new(); server::MAX_LIGHTSTYLES],
+            scores : Vec::new(),
+            dlights : DLights::new(),
+            model_flags : Vec::new(),
+            seed : 1,
+        }
+    }
+
+    /// Returns the entity with the given number, entities are allocated
+    /// as they are referenced.
+    fn entity_num(&mut self, num : i32) -> Option<&mut Entity> {
+        if num < 0 || num as usize >= server::MAX_EDICTS {
+            println!("CL_EntityNum: {} is an invalid number", num);
+            return None;
+        }
+        let num = num as usize;
+        while self.entities.len() <= num {
+            self.entities.push(Entity::new());
+        }
+        Some(&mut self.entities[num])
+    }
+
+    /// Updates the state with a message from the server. The current
+    /// client time is needed for the intermission.
+    pub fn parse_message(&mut self, message : &ServerMessage, time : f32) {
+        match *message {
+            ServerMessage::ServerInfo { maxclients, gametype, ref levelname, ref models, ref sounds, .. } => {
+                *self = ClientState::new();
+                self.maxclients = maxclients;
+                self.scores = vec![Score::new(); maxclients.max(0) as usize];
+                self.gametype = gametype;
+                self.levelname = levelname.clone();
+                self.model_precache.extend(models.iter().cloned());
+                self.sound_precache.extend(sounds.iter().cloned());
+            }
+            ServerMessage::Time(t) => {
+                self.mtime[1] = self.mtime[0];
+                self.mtime[0] = t;
+            }
+            ServerMessage::SetView(entity) => {
+                if entity > 0 && (entity as usize) < server::MAX_EDICTS {
+                    self.viewentity = entity as usize;
+                }
+            }
+            ServerMessage::UpdateStat { stat, value } => {
+                if stat < 0 || stat as usize >= protocol::MAX_CL_STATS {
+                    println!("svc_updatestat: {} is invalid", stat);
+                    return;
+                }
+                self.stats[stat as usize] = value;
+            }
+            ServerMessage::ClientData(ref data) => self.parse_clientdata(data),
+            ServerMessage::SpawnBaseline { entity, ref baseline } => {
+                if let Some(ent) = self.entity_num(entity) {
+                    ent.baseline = *baseline;
+                }
+            }
+            ServerMessage::SpawnStatic(ref baseline) => {
+                if self.static_entities.len() >= MAX_STATIC_ENTITIES {
+                    println!("Too many static entities");
+                    return;
+                }
+                let mut ent = Entity::new();
+                ent.baseline = *baseline;
+                ent.state = *baseline;
+                self.static_entities.push(ent);
+            }
+            ServerMessage::Update(ref update) => self.parse_update(update),
+            ServerMessage::LightStyle { style, ref map } => {
+                if style < 0 || style as usize >= server::MAX_LIGHTSTYLES {
+                    println!("svc_lightstyle > MAX_LIGHTSTYLES");
+                    return;
+                }
+                self.lightstyles[style as usize] = map.clone();
+            }
+            ServerMessage::TempEntity(TempEntity::Point { kind : protocol::TE_EXPLOSION, ref origin }) |
+            ServerMessage::TempEntity(TempEntity::Explosion2 { ref origin, .. }) => {
+                let dl = self.dlights.alloc(0, time);
+                dl.origin = *origin;
+                dl.radius = 350.0;
+                dl.die = time + 0.5;
+                dl.decay = 300.0;
+            }
+            ServerMessage::UpdateName { client, ref name } => match self.scores.get_mut(client as usize) {
+                Some(score) => score.name = name.clone(),
+                None => println!("CL_ParseServerMessage: svc_updatename > MAX_SCOREBOARD"),
+            },
+            ServerMessage::UpdateFrags { client, frags } => match self.scores.get_mut(client as usize) {
+                Some(score) => score.frags = frags,
+                None => println!("CL_ParseServerMessage: svc_updatefrags > MAX_SCOREBOARD"),
+            },
+            ServerMessage::UpdateColors { client, colors } => match self.scores.get_mut(client as usize) {
+                Some(score) => {
+                    score.colors = colors;
+                    score.translation = translation_table(colors);
+                }
+                None => println!("CL_ParseServerMessage: svc_updatecolors > MAX_SCOREBOARD"),
+            },
+            ServerMessage::KilledMonster => self.stats[protocol::STAT_MONSTERS as usize] += 1,
+            ServerMessage::FoundSecret => self.stats[protocol::STAT_SECRETS as usize] += 1,
+            ServerMessage::SetPause(paused) => self.paused = paused,
+            ServerMessage::Intermission => {
+                self.intermission = 1;
+                self.completed_time = time;
+            }
+            ServerMessage::Finale(_) => {
+                self.intermission = 2;
+                self.completed_time = time;
+            }
+            ServerMessage::Cutscene(_) => {
+                self.intermission = 3;
+                self.completed_time = time;
+            }
+            _ => {}
+        }
+    }
+
+    /// Copies the player data sent every frame.
+    fn parse_clientdata(&mut self, data : &ClientData) {
+        self.viewheight = data.viewheight as f32;
+        self.idealpitch = data.idealpitch as f32;
+        self.punchangle = data.punchangle;
+        self.mvelocity[1] = self.mvelocity[0];
+        self.mvelocity[0] = data.velocity;
+        self.items = data.items;
+        self.onground = data.onground;
+        self.inwater = data.inwater;
+
+        let stats = [(protocol::STAT_WEAPONFRAME, data.weaponframe), (protocol::STAT_ARMOR, data.armor),
+                     (protocol::STAT_WEAPON, data.weapon), (protocol::STAT_HEALTH, data.health),
+                     (protocol::STAT_AMMO, data.ammo), (protocol::STAT_SHELLS, data.shells),
+                     (protocol::STAT_NAILS, data.nails), (protocol::STAT_ROCKETS, data.rockets),
+                     (protocol::STAT_CELLS, data.cells), (protocol::STAT_ACTIVEWEAPON, data.activeweapon)];
+        for &(stat, value) in &stats {
+            self.stats[stat as usize] = value;
+        }
+    }
+
+    /// Applies an entity update and keeps the last two positions for
+    /// interpolation.
+    fn parse_update(&mut self, update : &EntityUpdate) {
+        let mtime = self.mtime;
+        let ent = match self.entity_num(update.entity) {
+            Some(ent) => ent,
+            None => return,
+        };
+
+        // no previous frame to lerp from
+        let mut forcelink = ent.msgtime != mtime[1];
+        ent.msgtime = mtime[0];
+
+        let state = update.apply(&ent.baseline);
+        if state.modelindex != ent.state.modelindex && state.modelindex == 0 {
+            forcelink = true;
+        }
+        ent.state.modelindex = state.modelindex;
+        ent.state.frame = state.frame;
+        ent.state.colormap = state.colormap;
+        ent.state.skin = state.skin;
+        ent.state.effects = state.effects;
+
+        // shift the known values for interpolation
+        ent.msg_origins[1] = ent.msg_origins[0];
+        ent.msg_angles[1] = ent.msg_angles[0];
+        ent.msg_origins[0] = state.origin;
+        ent.msg_angles[0] = state.angles;
+
+        if update.nolerp {
+            ent.forcelink = true;
+        }
+        if forcelink {
+            // didn't have an update last message
+            ent.msg_origins[1] = ent.msg_origins[0];
+            ent.msg_angles[1] = ent.msg_angles[0];
+            ent.state.origin = ent.msg_origins[0];
+            ent.state.angles = ent.msg_angles[0];
+            ent.forcelink = true;
+        }
+    }
+
+    /// Returns how far the client time is between the last two server
+    /// messages. Without interpolation the client time jumps to the
+    /// newest message. The client time is clamped to the message times.
+    pub fn lerp_point(&mut self, time : &mut f32, nolerp : bool) -> f32 {
+        let mut f = self.mtime[0] - self.mtime[1];
+        if f == 0.0 || nolerp {
+            *time = self.mtime[0];
+            return 1.0;
+        }
+        if f > 0.1 {
+            // dropped packet, or start of demo
+            self.mtime[1] = self.mtime[0] - 0.1;
+            f = 0.1;
+        }
+
+        let frac = (*time - self.mtime[1]) / f;
+        if frac < 0.0 {
+            if frac < -0.01 {
+                *time = self.mtime[1];
+            }
+            0.0
+        } else if frac > 1.0 {
+            if frac > 1.01 {
+                *time = self.mtime[0];
+            }
+            1.0
+        } else {
+            frac
+        }
+    }
+
+    /// Interpolates the entities and the player velocity and builds the
+    /// list of entities to draw. The view angles are interpolated between
+    /// the demo messages if given.
+    pub fn relink_entities(&mut self, time : &mut f32, nolerp : bool, demo_angles : Option<(&[Vec3; 2], &mut Vec3)>,
+                           particles : &mut Particles) {
+        let frac = self.lerp_point(time, nolerp);
+        self.visedicts.clear();
+
+        for i in 0..3 {
+            self.velocity[i] = self.mvelocity[1][i] + frac * (self.mvelocity[0][i] - self.mvelocity[1][i]);
+        }
+        if let Some((mviewangles, viewangles)) = demo_angles {
+            for i in 0..3 {
+                viewangles[i] = lerp_angle(mviewangles[1][i], mviewangles[0][i], frac);
+            }
+        }
+
+        for (num, ent) in self.entities.iter_mut().enumerate().skip(1) {
+            if ent.state.modelindex == 0 {
+                continue;
+            }
+            // if the object wasn't included in the last packet, remove it
+            if ent.msgtime != self.mtime[0] {
+                ent.state.modelindex = 0;
+                continue;
+            }
+
+            let oldorg = ent.state.origin;
+            if ent.forcelink {
+                // the entity was not updated in the last message so move to the final spot
+                ent.state.origin = ent.msg_origins[0];
+                ent.state.angles = ent.msg_angles[0];
+            } else {
+                // if the delta is large, assume a teleport and don't lerp
+                let mut f = frac;
+                let mut delta = [0.0; 3];
+                for j in 0..3 {
+                    delta[j] = ent.msg_origins[0][j] - ent.msg_origins[1][j];
+                    if delta[j] > TELEPORT_DISTANCE || delta[j] < -TELEPORT_DISTANCE {
+                        f = 1.0;
+                    }
+                }
+                for j in 0..3 {
+                    ent.state.origin[j] = ent.msg_origins[1][j] + f * delta[j];
+                    ent.state.angles[j] = lerp_angle(ent.msg_angles[1][j], ent.msg_angles[0][j], f);
+                }
+            }
+            ent.forcelink = false;
+
+            let key = num as i32;
+            let effects = ent.state.effects;
+            if effects & server::EF_BRIGHTFIELD != 0 {
+                particles.entity_particles(&ent.state.origin, *time);
+            }
+            if effects & server::EF_MUZZLEFLASH != 0 {
+                let (forward, _, _) = angle_vectors(&ent.state.angles);
+                let radius = (200 + (r_part::rand(&mut self.seed) & 31)) as f32;
+                let dl = self.dlights.alloc(key, *time);
+                dl.origin = vector_ma(&ent.state.origin, 18.0, &forward);
+                dl.origin[2] += 16.0;
+                dl.radius = radius;
+                dl.minlight = 32.0;
+                dl.die = *time + 0.1;
+            }
+            if effects & server::EF_BRIGHTLIGHT != 0 {
+                let radius = (400 + (r_part::rand(&mut self.seed) & 31)) as f32;
+                let dl = self.dlights.alloc(key, *time);
+                dl.origin = ent.state.origin;
+                dl.origin[2] += 16.0;
+                dl.radius = radius;
+                dl.die = *time + 0.001;
+            }
+            if effects & server::EF_DIMLIGHT != 0 {
+                let radius = (200 + (r_part::rand(&mut self.seed) & 31)) as f32;
+                let dl = self.dlights.alloc(key, *time);
+                dl.origin = ent.state.origin;
+                dl.radius = radius;
+                dl.die = *time + 0.001;
+            }
+            let flags = self.model_flags.get(ent.state.modelindex as usize).cloned().unwrap_or(0);
+            particles.model_trail(flags, &oldorg, &ent.state.origin, *time);
+            if flags & EF_ROCKET != 0 {
+                let dl = self.dlights.alloc(key, *time);
+                dl.origin = ent.state.origin;
+                dl.radius = 200.0;
+                dl.die = *time + 0.01;
+            }
+
+            if num == self.viewentity {
+                continue;
+            }
+            if self.visedicts.len() < MAX_VISEDICTS {
+                self.visedicts.push(ent.state);
+            }
+        }
+
+        for ent in &self.static_entities {
+            if self.visedicts.len() < MAX_VISEDICTS {
+                self.visedicts.push(ent.state);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(entity : i32, origin : Vec3) -> ServerMessage {
+        ServerMessage::Update(EntityUpdate::delta(entity, &EntityState::new(), &EntityState {
+            origin : origin,
+            modelindex : 2,
+            ..EntityState::new()
+        }, false))
+    }
+
+    fn serverinfo() -> ServerMessage {
+        ServerMessage::ServerInfo {
+            protocol : protocol::PROTOCOL_VERSION,
+            maxclients : 1,
+            gametype : protocol::GAME_COOP,
+            levelname : "the Slipgate Complex".to_string(),
+            models : vec!["maps/e1m1.bsp".to_string(), "progs/player.mdl".to_string()],
+            sounds : vec![],
+        }
+    }
+
+    #[test]
+    fn scoreboard() {
+        let mut cl = ClientState::new();
+        let mut info = serverinfo();
+        if let ServerMessage::ServerInfo { ref mut maxclients, .. } = info {
+            *maxclients = 2;
+        }
+        cl.parse_message(&info, 0.0);
+        cl.parse_message(&ServerMessage::UpdateName { client : 1, name : "ranger".to_string() }, 0.0);
+        cl.parse_message(&ServerMessage::UpdateFrags { client : 1, frags : 7 }, 0.0);
+        cl.parse_message(&ServerMessage::UpdateColors { client : 1, colors : 0x4d }, 0.0);
+        cl.parse_message(&ServerMessage::UpdateFrags { client : 2, frags : 1 }, 0.0);
+        let score = &cl.scores[1];
+        assert_eq!((score.name.as_str(), score.frags, score.colors), ("ranger", 7, 0x4d));
+
+        // the shirt range is replaced by color 4, the pants range by the
+        // reversed color 13
+        assert_eq!(score.translate_skin(&[0, 16, 31, 96, 111, 128]), vec![0, 64, 79, 223, 208, 128]);
+        assert_eq!(cl.scores[0].translate_skin(&[16, 100]), vec![0, 4]);
+    }
+
+    #[test]
+    fn interpolation() {
+        let mut cl = ClientState::new();
+        let mut particles = Particles::new();
+        for message in &[serverinfo(), ServerMessage::SetView(1), ServerMessage::Time(1.0), update(2, [0.0; 3])] {
+            cl.parse_message(message, 0.0);
+        }
+        assert_eq!(cl.model_precache, vec!["", "maps/e1m1.bsp", "progs/player.mdl"]);
+
+        let mut time = 1.0;
+        cl.relink_entities(&mut time, false, None, &mut particles);
+        assert_eq!(cl.visedicts.len(), 1);
+        assert_eq!(cl.visedicts[0].origin, [0.0; 3]);
+
+        cl.parse_message(&ServerMessage::Time(1.1), 0.0);
+        cl.parse_message(&update(2, [64.0, 0.0, 0.0]), 0.0);
+        let mut time = 1.05;
+        cl.relink_entities(&mut time, false, None, &mut particles);
+        assert!((cl.visedicts[0].origin[0] - 32.0).abs() < 0.01);
+
+        // teleports aren't interpolated
+        cl.parse_message(&ServerMessage::Time(1.2), 0.0);
+        cl.parse_message(&update(2, [512.0, 0.0, 0.0]), 0.0);
+        let mut time = 1.15;
+        cl.relink_entities(&mut time, false, None, &mut particles);
+        assert_eq!(cl.visedicts[0].origin, [512.0, 0.0, 0.0]);
+
+        // the time is clamped to the last message
+        let mut time = 2.0;
+        cl.relink_entities(&mut time, false, None, &mut particles);
+        assert_eq!(time, 1.2);
+
+        // entities missing from the last message are removed
+        cl.parse_message(&ServerMessage::Time(1.3), 0.0);
+        cl.parse_message(&update(1, [0.0; 3]), 0.0);
+        let mut time = 1.3;
+        cl.relink_entities(&mut time, true, None, &mut particles);
+        assert!(cl.visedicts.is_empty());
+        assert_eq!(cl.entities[2].state.modelindex, 0);
+    }
+
+    #[test]
+    fn rocket_trail() {
+        let mut cl = ClientState::new();
+        let mut particles = Particles::new();
+        for message in &[serverinfo(), ServerMessage::SetView(1), ServerMessage::Time(1.0), update(2, [0.0; 3])] {
+            cl.parse_message(message, 0.0);
+        }
+        cl.model_flags = vec![0, 0, EF_ROCKET];
+        let mut time = 1.0;
+        cl.relink_entities(&mut time, false, None, &mut particles);
+        assert!(particles.particles().is_empty());
+
+        cl.parse_message(&ServerMessage::Time(1.1), 0.0);
+        cl.parse_message(&update(2, [64.0, 0.0, 0.0]), 0.0);
+        let mut time = 1.1;
+        cl.relink_entities(&mut time, false, None, &mut particles);
+        assert!(!particles.particles().is_empty());
+    }
+
+    #[test]
+    fn player_data() {
+        let mut cl = ClientState::new();
+        let mut particles = Particles::new();
+        let mut data = ClientData::new();
+        data.health = 100;
+        data.velocity = [320.0, 0.0, 0.0];
+        data.punchangle = [-2.0, 0.0, 0.0];
+        cl.parse_message(&ServerMessage::ClientData(data), 0.0);
+        cl.parse_message(&ServerMessage::UpdateStat { stat : protocol::STAT_TOTALMONSTERS, value : 20 }, 0.0);
+        cl.parse_message(&ServerMessage::KilledMonster, 0.0);
+        cl.parse_message(&ServerMessage::Intermission, 3.5);
+
+        assert_eq!(cl.stats[protocol::STAT_HEALTH as usize], 100);
+        assert_eq!(cl.stats[protocol::STAT_TOTALMONSTERS as usize], 20);
+        assert_eq!(cl.stats[protocol::STAT_MONSTERS as usize], 1);
+        assert_eq!(cl.punchangle, [-2.0, 0.0, 0.0]);
+        assert_eq!((cl.intermission, cl.completed_time), (1, 3.5));
+
+        let mut viewangles = [0.0; 3];
+        let mut time = 0.0;
+        cl.relink_entities(&mut time, true, Some((&[[0.0, 10.0, 0.0], [0.0, 350.0, 0.0]], &mut viewangles)), &mut particles);
+        assert_eq!(cl.velocity, [320.0, 0.0, 0.0]);
+        assert_eq!(viewangles, [0.0, 370.0, 0.0]);
+    }
+
+    #[test]
+    fn lights() {
+        let mut cl = ClientState::new();
+        let mut particles = Particles::new();
+        cl.parse_message(&ServerMessage::LightStyle { style : 1, map : "az".to_string() }, 0.0);
+        cl.parse_message(&ServerMessage::LightStyle { style : 64, map : "m".to_string() }, 0.0);
+        assert_eq!(cl.lightstyles[1], "az");
+        assert_eq!(cl.lightstyles.len(), server::MAX_LIGHTSTYLES);
+
+        let explosion = TempEntity::Point { kind : protocol::TE_EXPLOSION, origin : [0.0, 0.0, 64.0] };
+        cl.parse_message(&ServerMessage::TempEntity(explosion), 1.0);
+        let lights = cl.dlights.active(1.0);
+        assert_eq!(lights.len(), 1);
+        assert_eq!((lights[0].1.origin, lights[0].1.radius, lights[0].1.die), ([0.0, 0.0, 64.0], 350.0, 1.5));
+
+        // a muzzle flash lights up in front of the entity
+        cl.parse_message(&ServerMessage::Time(1.0), 1.0);
+        cl.parse_message(&ServerMessage::Update(EntityUpdate::delta(2, &EntityState::new(), &EntityState {
+            modelindex : 2,
+            effects : server::EF_MUZZLEFLASH,
+            ..EntityState::new()
+        }, false)), 1.0);
+        let mut time = 1.0;
+        cl.relink_entities(&mut time, true, None, &mut particles);
+        let lights = cl.dlights.active(1.0);
+        assert_eq!(lights.len(), 2);
+        let flash = lights[1].1;
+        assert_eq!((flash.key, flash.origin, flash.minlight), (2, [18.0, 0.0, 16.0], 32.0));
+        assert!(flash.radius >= 200.0 && flash.radius < 232.0);
+        assert!(cl.dlights.active(1.6).is_empty());
+    }
+}
@@ -0,0 +1,129 @@
+#![warn(missing_docs)]
+
+//! Command buffer and command line tokenizing.
+//!
+//! Original source can be found in cmd.c
+
+/// Buffer of command text waiting to be executed.
+pub struct CommandBuffer {
+    text : String,
+}
+
+impl CommandBuffer {
+    /// Creates an empty command buffer.
+    pub fn new() -> CommandBuffer {
+        CommandBuffer {
+            text : String::new(),
+        }
+    }
+
+    /// Adds command text at the end of the buffer.
+    pub fn add_text(&mut self, text : &str) {
+        self.text.push_str(text);
+        if !text.ends_with('\n') {
+            self.text.push('\n');
+        }
+    }
+
+    /// Removes the next command from the buffer. Commands are separated
+    /// by new lines or by semicolons outside of quotes.
+    pub fn next_command(&mut self) -> Option<String> {
+        if self.text.is_empty() {
+            return None;
+        }
+
+        let mut quotes = false;
+        let mut end = self.text.len();
+        for (i, c) in self.text.char_indices() {
+            if c == '"' {
+                quotes = !quotes;
+            }
+            if (c == ';' && !quotes) || c == '\n' {
+                end = i;
+                break;
+            }
+        }
+
+        let line = self.text[..end].to_string();
+        let rest = if end < self.text.len() { end + 1 } else { end };
+        self.text = self.text[rest..].to_string();
+        Some(line)
+    }
+}
+
+/// Splits a command line into arguments. Text in quotes is one argument,
+/// everything after // is a comment.
+pub fn tokenize(line : &str) -> Vec<String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+
+        let c = match chars.next() {
+            None => break,
+            Some(c) => c,
+        };
+
+        if c == '/' && chars.peek() == Some(&'/') {
+            break;
+        }
+
+        let mut arg = String::new();
+        if c == '"' {
+            while let Some(c) = chars.next() {
+                if c == '"' {
+                    break;
+                }
+                arg.push(c);
+            }
+        } else if is_single_char(c) {
+            arg.push(c);
+        } else {
+            arg.push(c);
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || is_single_char(c) {
+                    break;
+                }
+                arg.push(c);
+                chars.next();
+            }
+        }
+        args.push(arg);
+    }
+
+    args
+}
+
+/// Characters that always form a token on their own.
+fn is_single_char(c : char) -> bool {
+    match c {
+        '{' | '}' | '(' | ')' | '\'' | ':' => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_commands() {
+        let mut buf = CommandBuffer::new();
+        buf.add_text("screenshot; echo \"a;b\"");
+        buf.add_text("map e1m1\n");
+        assert_eq!(buf.next_command().unwrap(), "screenshot");
+        assert_eq!(buf.next_command().unwrap(), " echo \"a;b\"");
+        assert_eq!(buf.next_command().unwrap(), "map e1m1");
+        assert!(buf.next_command().is_none());
+    }
+
+    #[test]
+    fn tokenize_line() {
+        assert_eq!(tokenize("  bind x \"+forward; jump\" // comment"), vec!["bind", "x", "+forward; jump"]);
+        assert_eq!(tokenize("connect localhost:26000"), vec!["connect", "localhost", ":", "26000"]);
+        assert!(tokenize("").is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+#![warn(missing_docs)]
+
+//! Console variables.
+//!
+//! Original source can be found in cvar.c
+
+/// A named variable that can be changed from the console.
+#[derive(Clone)]
+pub struct Cvar {
+    /// Name of the variable.
+    pub name : String,
+    /// Value as set by the user.
+    pub string : String,
+    /// Value converted to a number, 0 if the string isn't a number.
+    pub value : f32,
+    /// Saved to the config file if set.
+    pub archive : bool,
+    /// Changes are announced to all clients if set.
+    pub server : bool,
+}
+
+/// List of all console variables.
+#[derive(Clone)]
+pub struct CvarList {
+    cvars : Vec<Cvar>,
+    changes : u32,
+}
+
+impl CvarList {
+    /// Creates an empty list.
+    pub fn new() -> CvarList {
+        CvarList {
+            cvars : Vec::new(),
+            changes : 0,
+        }
+    }
+
+    /// Adds a variable with its default value. Registering the same variable
+    /// again keeps the current value.
+    pub fn register(&mut self, name : &str, default : &str, archive : bool, server : bool) {
+        if self.find(name).is_some() {
+            return;
+        }
+        self.changes = self.changes.wrapping_add(1);
+        self.cvars.push(Cvar {
+            name : name.to_string(),
+            string : default.to_string(),
+            value : default.parse().unwrap_or(0.0),
+            archive : archive,
+            server : server,
+        });
+    }
+
+    /// Returns a variable.
+    pub fn find(&self, name : &str) -> Option<&Cvar> {
+        self.cvars.iter().find(|cvar| cvar.name == name)
+    }
+
+    /// Returns the numeric value of a variable, 0 if it doesn't exist.
+    pub fn value(&self, name : &str) -> f32 {
+        self.find(name).map_or(0.0, |cvar| cvar.value)
+    }
+
+    /// Returns the string value of a variable, an empty string if it doesn't exist.
+    pub fn string(&self, name : &str) -> &str {
+        self.find(name).map_or("", |cvar| cvar.string.as_str())
+    }
+
+    /// Changes the value of a variable. Returns false if it doesn't exist.
+    pub fn set(&mut self, name : &str, value : &str) -> bool {
+        match self.cvars.iter_mut().find(|cvar| cvar.name == name) {
+            Some(cvar) => {
+                self.changes = self.changes.wrapping_add(1);
+                cvar.string = value.to_string();
+                cvar.value = value.parse().unwrap_or(0.0);
+                if cvar.server {
+                    println!("\"{}\" changed to \"{}\"", cvar.name, cvar.string);
+                }
+                true
+            },
+            None => {
+                println!("Cvar_Set: variable {} not found", name);
+                false
+            },
+        }
+    }
+
+    /// Returns a number that changes whenever a variable is added or set,
+    /// copies of the list are out of date when it differs.
+    pub fn changes(&self) -> u32 {
+        self.changes
+    }
+
+    /// Changes the numeric value of a variable.
+    pub fn set_value(&mut self, name : &str, value : f32) -> bool {
+        self.set(name, &value.to_string())
+    }
+
+    /// Handles commands that are variable names. Without an argument the
+    /// value is printed, otherwise it's changed. Returns false if the
+    /// command isn't a variable.
+    pub fn command(&mut self, args : &[String]) -> bool {
+        let name = match args.first() {
+            Some(name) => name,
+            None => return false,
+        };
+        if self.find(name).is_none() {
+            return false;
+        }
+        match args.get(1) {
+            None => println!("\"{}\" is \"{}\"", name, self.string(name)),
+            Some(value) => { self.set(name, value); },
+        }
+        true
+    }
+
+    /// Returns all variables that should be saved to the config file.
+    pub fn archived(&self) -> Vec<&Cvar> {
+        self.cvars.iter().filter(|cvar| cvar.archive).collect()
+    }
+
+    /// Returns all variables that change the game rules of the server.
+    pub fn server_rules(&self) -> Vec<(String, String)> {
+        self.cvars.iter().filter(|cvar| cvar.server).map(|cvar| (cvar.name.clone(), cvar.string.clone())).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_get() {
+        let mut cvars = CvarList::new();
+        cvars.register("bgmvolume", "1", true, false);
+        assert_eq!(cvars.value("bgmvolume"), 1.0);
+        assert!(cvars.command(&["bgmvolume".to_string(), "0.5".to_string()]));
+        assert_eq!(cvars.value("bgmvolume"), 0.5);
+        let changes = cvars.changes();
+        cvars.register("bgmvolume", "1", true, false);
+        assert_eq!(cvars.value("bgmvolume"), 0.5);
+        assert_eq!(cvars.changes(), changes);
+        cvars.set("bgmvolume", "0.3");
+        assert!(cvars.changes() != changes);
+
+        assert!(!cvars.command(&["map".to_string()]));
+        assert!(!cvars.set("missing", "1"));
+        cvars.register("hostname", "UNNAMED", false, false);
+        assert_eq!(cvars.value("hostname"), 0.0);
+        assert_eq!(cvars.string("hostname"), "UNNAMED");
+        assert_eq!(cvars.archived().len(), 1);
+    }
+}
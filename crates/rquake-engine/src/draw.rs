@@ -0,0 +1,208 @@
+#![warn(missing_docs)]
+
+//! Pictures and characters drawn on top of the view.
+//!
+//! Original source can be found in draw.c and wad.c
+
+use std::collections::HashMap;
+use std::io::Cursor;
+
+use rquake_common::{BackBuffer, GameResources};
+use rquake_fs::{Palette, WadFile};
+use msg::MsgReader;
+
+/// Width and height of a character of the console font.
+pub const CHAR_SIZE : i32 = 8;
+
+/// Palette index of transparent pixels in pictures.
+const TRANSPARENT_COLOR : u8 = 255;
+
+/// Width and height of the font lump, 16x16 characters.
+const CONCHARS_SIZE : usize = 128;
+
+/// Picture made of palette indices.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pic {
+    /// Width in pixels.
+    pub width : usize,
+    /// Height in pixels.
+    pub height : usize,
+    /// Palette indices row by row.
+    pub data : Vec<u8>,
+}
+
+impl Pic {
+    /// Reads a picture with its width and height in front of the pixels,
+    /// the format of .lmp files and the pictures in gfx.wad.
+    pub fn read(data : &[u8]) -> Option<Pic> {
+        let mut msg = MsgReader::new(data);
+        let width = msg.read_long();
+        let height = msg.read_long();
+        if width <= 0 || height <= 0 || data.len() - msg.pos() < (width * height) as usize {
+            return None;
+        }
+        let (width, height) = (width as usize, height as usize);
+        Some(Pic {
+            width : width,
+            height : height,
+            data : data[8..8 + width * height].to_vec(),
+        })
+    }
+}
+
+/// The pictures of gfx.wad and the picture files that have been used.
+pub struct Draw {
+    wad : Option<(WadFile, Vec<u8>)>,
+    conchars : Option<Pic>,
+    cache : HashMap<String, Option<Pic>>,
+}
+
+impl Draw {
+    /// Creates the drawing state without any pictures.
+    pub fn new() -> Draw {
+        Draw {
+            wad : None,
+            conchars : None,
+            cache : HashMap::new(),
+        }
+    }
+
+    /// Loads gfx.wad with the console font and the status bar pictures.
+    pub fn init(&mut self, game_res : &mut GameResources) {
+        let data = match game_res.load_file("gfx.wad") {
+            Some(data) => data,
+            None => {
+                println!("W_LoadWadFile: couldn't load gfx.wad");
+                return;
+            }
+        };
+        match WadFile::read(&mut Cursor::new(&data[..])) {
+            Ok(wad) => self.wad = Some((wad, data)),
+            Err(_) => println!("Wad file gfx.wad doesn't have WAD2 id"),
+        }
+
+        // the font is a raw lump without a size
+        self.conchars = self.wad_lump("conchars")
+            .and_then(|data| data.get(..CONCHARS_SIZE * CONCHARS_SIZE))
+            .map(|data| Pic { width : CONCHARS_SIZE, height : CONCHARS_SIZE, data : data.to_vec() });
+    }
+
+    fn wad_lump(&self, name : &str) -> Option<&[u8]> {
+        let (ref wad, ref data) = *self.wad.as_ref()?;
+        let (pos, len) = wad.file_range(name)?;
+        data.get(pos..pos + len)
+    }
+
+    /// Returns a picture of gfx.wad.
+    pub fn pic_from_wad(&self, name : &str) -> Option<Pic> {
+        let pic = self.wad_lump(name).and_then(Pic::read);
+        if pic.is_none() {
+            println!("W_GetLumpinfo: {} not found", name);
+        }
+        pic
+    }
+
+    /// Returns a picture file, it's only loaded the first time.
+    pub fn cache_pic(&mut self, name : &str, game_res : &mut GameResources) -> Option<&Pic> {
+        if !self.cache.contains_key(name) {
+            let pic = game_res.load_file(name).and_then(|data| Pic::read(&data));
+            if pic.is_none() {
+                println!("Draw_CachePic: failed to load {}", name);
+            }
+            self.cache.insert(name.to_string(), pic);
+        }
+        self.cache[name].as_ref()
+    }
+
+    /// Returns the console font.
+    pub fn conchars(&self) -> Option<&Pic> {
+        self.conchars.as_ref()
+    }
+}
+
+/// Draws a picture, the parts outside of the back buffer are skipped.
+pub fn draw_pic(x : i32, y : i32, pic : &Pic, palette : &Palette, backbuffer : &mut BackBuffer) {
+    blit(x, y, pic, None, palette, backbuffer);
+}
+
+/// Draws a picture, pixels with color 255 are transparent.
+pub fn draw_trans_pic(x : i32, y : i32, pic : &Pic, palette : &Palette, backbuffer : &mut BackBuffer) {
+    blit(x, y, pic, Some(TRANSPARENT_COLOR), palette, backbuffer);
+}
+
+/// Draws a character of the console font, color 0 is transparent.
+pub fn draw_character(x : i32, y : i32, num : u8, conchars : &Pic, palette : &Palette, backbuffer : &mut BackBuffer) {
+    let size = CHAR_SIZE as usize;
+    let (row, col) = ((num >> 4) as usize, (num & 15) as usize);
+    let data = (0..size).flat_map(|line| {
+        let start = (row * size + line) * conchars.width + col * size;
+        conchars.data[start..start + size].iter().cloned()
+    }).collect();
+    blit(x, y, &Pic { width : size, height : size, data : data }, Some(0), palette, backbuffer);
+}
+
+/// Draws a string of the console font on one line.
+pub fn draw_string(x : i32, y : i32, text : &str, conchars : &Pic, palette : &Palette, backbuffer : &mut BackBuffer) {
+    for (i, c) in text.bytes().enumerate() {
+        draw_character(x + i as i32 * CHAR_SIZE, y, c, conchars, palette, backbuffer);
+    }
+}
+
+/// Fills a rectangle with a color.
+pub fn draw_fill(x : i32, y : i32, width : usize, height : usize, color : u8, palette : &Palette, backbuffer : &mut BackBuffer) {
+    blit(x, y, &Pic { width : width, height : height, data : vec![color; width * height] }, None, palette, backbuffer);
+}
+
+fn blit(x : i32, y : i32, pic : &Pic, transparent : Option<u8>, palette : &Palette, backbuffer : &mut BackBuffer) {
+    let width = backbuffer.get_width() as i32;
+    let height = backbuffer.get_height() as i32;
+    let buffer = backbuffer.get_buffer();
+    for (row, line) in pic.data.chunks(pic.width).take(pic.height).enumerate() {
+        let py = y + row as i32;
+        if py < 0 || py >= height {
+            continue;
+        }
+        for (col, &color) in line.iter().enumerate() {
+            let px = x + col as i32;
+            if px < 0 || px >= width || Some(color) == transparent {
+                continue;
+            }
+            buffer[(py * width + px) as usize] = palette.palette_lookup(color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_common::MemoryBackBuffer;
+
+    fn palette() -> Palette {
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        Palette::read(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    fn pictures() {
+        assert!(Pic::read(&[2, 0, 0, 0, 2, 0, 0, 0, 1, 2, 3]).is_none());
+        let pic = Pic::read(&[2, 0, 0, 0, 2, 0, 0, 0, 1, 255, 3, 4]).unwrap();
+        assert_eq!((pic.width, pic.height), (2, 2));
+
+        // transparent pixels and pixels outside of the buffer are skipped
+        let palette = palette();
+        let mut backbuffer = MemoryBackBuffer::new(3, 2);
+        draw_trans_pic(2, 0, &pic, &palette, &mut backbuffer);
+        let (one, three) = (palette.palette_lookup(1), palette.palette_lookup(3));
+        assert_eq!(backbuffer.get_buffer().to_vec(), vec![0, 0, one, 0, 0, three]);
+        draw_pic(-1, 1, &pic, &palette, &mut backbuffer);
+        assert_eq!(backbuffer.get_buffer()[3], palette.palette_lookup(255));
+
+        // character 17 is in the second row and column of the font
+        let mut conchars = Pic { width : 128, height : 128, data : vec![0; 128 * 128] };
+        conchars.data[8 * 128 + 8] = 5;
+        conchars.data[8 * 128 + 9] = 6;
+        let mut backbuffer = MemoryBackBuffer::new(3, 2);
+        draw_character(1, 1, 17, &conchars, &palette, &mut backbuffer);
+        assert_eq!(backbuffer.get_buffer().to_vec(), vec![0, 0, 0, 0, palette.palette_lookup(5), palette.palette_lookup(6)]);
+    }
+}
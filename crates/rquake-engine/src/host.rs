@@ -1,40 +1,1055 @@
 #![warn(missing_docs)]
 
-//! Code for the local server setup, teardown and game loop. 
-//! 
+//! Code for the local server setup, teardown and game loop.
+//!
 //! Original source can be found in host.c
 
-use rquake_common::{EventAction,GameResources};
-use snd::SoundEngine;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+use rquake_common::{BackBuffer,EventAction,GameResources};
+use rquake_fs::{BspFile, Palette};
+use snd::{self, SfxCache, SoundEngine, NUM_AMBIENTS};
+use cmd::{self, CommandBuffer};
+use cvar::CvarList;
+use screen::Screen;
+use cd_audio::CdAudio;
+use pr_exec::ProgsDat;
+use progs::Progs;
+use protocol::{self, EntityState, ServerMessage};
+use server::{self, Server};
+use sv_main::{self, SpawnParams};
+use sv_phys::{self, PhysicsParams};
+use sv_user;
+use host_cmd::{self, CommandParams};
+use cl_input::{self, Input};
+use cl_main::{self, ClientStatic};
+use cl_pred::{self, Prediction};
+use client::ClientState;
+use keys::KeyBindings;
+use model::BrushModel;
+use net::{self, Net, NetDriver};
+use net_loop::LoopDriver;
+use net_dgrm::{DatagramDriver, ServerSearch};
+use progdefs;
+use savegame::{SaveError, SaveGame};
+use view::{self, RefDef, View, ViewParams};
+use r_part::Particles;
+use r_main::{self, Renderer, RenderParams};
+use r_model::{self, WorldModel};
+use draw::Draw;
+use sbar::Sbar;
+
+const GAME_DIRECTORY : &'static str = "Id1";
+/// Seconds the slist command waits for answers.
+const SLIST_WAIT : f64 = 1.5;
 
 /// Local server instance.
 pub struct Host<'a> {
     game_res : &'a mut GameResources,
-    snd : &'a mut SoundEngine,
+    snd : Option<&'a mut SoundEngine>,
+    commands : CommandBuffer,
+    cvars : CvarList,
+    screen : Screen,
+    cd_audio : CdAudio,
+    sv : Server,
+    sv_cvar_changes : Option<u32>,
+    sv_status : Option<(u32, usize, f64)>,
+    progs : Option<Box<Progs>>,
+    keys : KeyBindings,
+    input : Input,
+    net : Net,
+    cls : ClientStatic,
+    cl : ClientState,
+    prediction : Prediction,
+    view : View,
+    particles : Particles,
+    palette : Option<Palette>,
+    renderer : Renderer,
+    draw : Draw,
+    sbar : Sbar,
+    level_states : HashMap<String, SaveGame>,
+    slist : Option<ServerSearch>,
+    showscores : bool,
+    dedicated : bool,
+    running : bool,
+    realtime : f64,
 }
 
 impl<'a> Host<'a> {
     /// Creates a new local server instance.
     pub fn new(game_res : &'a mut GameResources, snd : &'a mut SoundEngine) -> Host<'a> {
+        Host::create(game_res, Some(snd), 1, false)
+    }
+
+    /// Creates a server without sound and local client that only serves
+    /// network clients.
+    pub fn new_dedicated(game_res : &'a mut GameResources, max_clients : usize) -> Host<'a> {
+        Host::create(game_res, None, max_clients, true)
+    }
+
+    fn create(game_res : &'a mut GameResources, snd : Option<&'a mut SoundEngine>, max_clients : usize, dedicated : bool) -> Host<'a> {
+        let drivers : Vec<Box<NetDriver>> = vec![
+            Box::new(LoopDriver::new()),
+            Box::new(DatagramDriver::new(net::DEFAULT_NET_PORT, max_clients)),
+        ];
         Host {
             game_res : game_res,
             snd : snd,
+            commands : CommandBuffer::new(),
+            cvars : CvarList::new(),
+            screen : Screen::new(GAME_DIRECTORY),
+            cd_audio : CdAudio::new(),
+            sv : Server::new(max_clients),
+            sv_cvar_changes : None,
+            sv_status : None,
+            progs : None,
+            keys : KeyBindings::new(),
+            input : Input::new(),
+            net : Net::new(drivers),
+            cls : ClientStatic::new(GAME_DIRECTORY),
+            cl : ClientState::new(),
+            prediction : Prediction::new(),
+            view : View::new(),
+            particles : Particles::new(),
+            palette : None,
+            renderer : Renderer::new(),
+            draw : Draw::new(),
+            sbar : Sbar::new(),
+            level_states : HashMap::new(),
+            slist : None,
+            showscores : false,
+            dedicated : dedicated,
+            running : true,
+            realtime : 0.0,
         }
     }
-    
-    /// Initializes the server. 
+
+    /// Initializes the server.
     pub fn init(&mut self) {
-        self.game_res.add_game_directory("Id1");
-        self.snd.init();
+        self.cvars.register("volume", &snd::DEFAULT_VOLUME.to_string(), true, false);
+        self.cvars.register("bgmvolume", "1", true, false);
+        self.cvars.register("sys_ticrate", "0.05", false, false);
+        self.cvars.register("skill", "1", false, false);
+        self.cvars.register("cl_nolerp", "0", false, false);
+        self.cvars.register("developer", "0", false, false);
+        sv_main::register_cvars(&mut self.cvars);
+        sv_phys::register_cvars(&mut self.cvars);
+        sv_user::register_cvars(&mut self.cvars);
+        cl_input::register_cvars(&mut self.cvars);
+        cl_main::register_cvars(&mut self.cvars);
+        cl_pred::register_cvars(&mut self.cvars);
+        net::register_cvars(&mut self.cvars);
+        view::register_cvars(&mut self.cvars);
+        r_main::register_cvars(&mut self.cvars);
+        self.game_res.add_game_directory(GAME_DIRECTORY);
+        match self.game_res.load_file("progs.dat").map(|data| ProgsDat::read(&data)) {
+            Some(Ok(progs)) => self.set_progs(Box::new(progs)),
+            Some(Err(_)) => println!("Couldn't load progs.dat"),
+            None => println!("Couldn't find progs.dat"),
+        }
+        if !self.dedicated {
+            self.palette = self.game_res.load_file("gfx/palette.lmp").and_then(|data| Palette::read(&mut &data[..]).ok());
+            if let Some(colormap) = self.game_res.load_file("gfx/colormap.lmp") {
+                self.renderer.set_colormap(colormap);
+            }
+            self.draw.init(self.game_res);
+            self.sbar.init(&self.draw);
+        }
+        if let Some(ref mut snd) = self.snd {
+            snd.init();
+
+            // looped in water and sky leafs
+            for (index, name) in ["ambience/water1.wav", "ambience/wind2.wav"].iter().enumerate() {
+                let sfx = match snd.precache_sound(name) {
+                    Some(sfx) => snd.load_sound(sfx, self.game_res),
+                    None => None,
+                };
+                snd.set_ambient_sound(index, sfx);
+            }
+        }
+        if self.dedicated {
+            self.net.listen(true);
+        }
+    }
+
+    /// Returns false once the quit command has been executed.
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Returns true while a timedemo runs, frames shouldn't be limited then.
+    pub fn is_timedemo(&self) -> bool {
+        self.cls.is_timedemo()
+    }
+
+    /// Returns the camera of the last frame.
+    pub fn refdef(&self) -> &RefDef {
+        &self.view.refdef
+    }
+
+    /// Returns the entities to draw in the last frame.
+    pub fn render_entities(&self) -> &[EntityState] {
+        &self.cl.visedicts
+    }
+
+    /// Seconds between the frames of a dedicated server.
+    pub fn ticrate(&self) -> f32 {
+        self.cvars.value("sys_ticrate")
+    }
+
+    /// Adds console commands that will be executed during the next frame.
+    pub fn add_command(&mut self, text : &str) {
+        self.commands.add_text(text);
     }
-    
+
+    /// Sets the game logic run by the server.
+    pub fn set_progs(&mut self, progs : Box<Progs>) {
+        self.progs = Some(progs);
+    }
+
+    /// Writes every rendered frame to the given directory.
+    pub fn set_capture_dir(&mut self, dir : Option<&str>) {
+        self.screen.set_capture_dir(dir);
+    }
+
     /// Runs one frame iteration.
-    pub fn frame(&self, timestep : f32, actions : &[EventAction]) {
-        
-    } 
-    
+    /// Dedicated servers don't render and pass no backbuffer.
+    pub fn frame(&mut self, timestep : f32, actions : &[EventAction], backbuffer : Option<&mut BackBuffer>) {
+        self.realtime += timestep as f64;
+        for action in actions {
+            match *action {
+                EventAction::KeyDown(key) => self.keys.key_event(key, true, &mut self.commands),
+                EventAction::KeyUp(key) => self.keys.key_event(key, false, &mut self.commands),
+                EventAction::MouseMove(dx, dy) => self.input.mouse_move(dx, dy),
+                EventAction::ToggleFullscreen => {}
+            }
+        }
+        self.execute_commands();
+        self.poll_slist();
+        self.net.set_message_timeout(self.cvars.value("net_messagetimeout") as f64);
+
+        let cmd = self.input.create_cmd(timestep, &self.cvars);
+        if !self.dedicated {
+            self.cls.name = host_cmd::player_name(self.cvars.string("_cl_name"));
+            self.cls.color = self.cvars.value("_cl_color") as i32;
+            self.cls.send_cmd(&cmd, self.realtime);
+        }
+        if self.sv.active {
+            self.update_server_cvars();
+            if let Some(ref mut progs) = self.progs {
+                let params = PhysicsParams::new(&self.cvars, timestep);
+                Host::update_status(&mut self.sv_status, &self.sv, &self.cvars, &mut self.net, self.realtime);
+                self.sv.check_for_new_clients(&mut self.net, &mut **progs, self.realtime);
+                self.sv.read_client_messages(&mut **progs, &CommandParams::new(&self.cvars), self.realtime);
+                if !self.sv.paused {
+                    self.sv.run_clients(&params);
+                    self.sv.physics(&mut **progs, &params);
+                }
+                self.sv.send_client_messages(&mut **progs, self.realtime);
+            }
+            self.run_server_commands();
+        }
+        if !self.dedicated {
+            let params = ViewParams::new(&self.cvars, timestep);
+            let messages = self.cls.read_messages(timestep, &self.input.viewangles, self.realtime);
+            for message in &messages {
+                self.client_message(message, &params);
+            }
+            if self.cls.signon == protocol::SIGNONS && !self.cls.is_demo_playback() && self.cvars.value("cl_predict") != 0.0 {
+                let physics = PhysicsParams::new(&self.cvars, timestep);
+                self.prediction.predict(&self.cl, &cmd, self.cls.round_trip_time(), self.realtime, &physics);
+            } else {
+                self.prediction.reset();
+            }
+            if self.cls.signon == protocol::SIGNONS {
+                // a local server runs in sync with the client
+                let nolerp = self.cvars.value("cl_nolerp") != 0.0 || self.cls.is_timedemo() || self.sv.active;
+                let demo_angles = if self.cls.is_demo_playback() {
+                    Some((&self.cls.mviewangles, &mut self.input.viewangles))
+                } else {
+                    None
+                };
+                self.cl.relink_entities(&mut self.cls.time, nolerp, demo_angles, &mut self.particles);
+                if let Some(origin) = self.prediction.origin() {
+                    self.cl.entities[self.cl.viewentity].state.origin = origin;
+                }
+                self.view.calc_refdef(&mut self.cl, &self.input.viewangles, self.cls.time, &params);
+                self.particles.run(self.cls.time, timestep, self.cvars.value("sv_gravity"));
+                self.cl.dlights.decay(self.cls.time, timestep);
+            }
+        }
+        if let Some(ref mut snd) = self.snd {
+            // the ambient sounds fade to the levels of the leaf the view is in
+            let refdef = &self.view.refdef;
+            let levels = match self.renderer.world() {
+                Some(world) if self.cls.signon == protocol::SIGNONS => {
+                    let bsp = &world.bsp;
+                    bsp.leafs.get(bsp.point_in_leaf(&refdef.vieworg)).map_or([0; NUM_AMBIENTS], |leaf| leaf.ambient_level)
+                }
+                _ => [0; NUM_AMBIENTS],
+            };
+            snd.set_view_entity(self.cl.viewentity as i32);
+            snd.set_listener(refdef.vieworg, refdef.viewangles);
+            snd.set_ambient_levels(levels);
+            snd.set_volume(self.cvars.value("volume"), self.cvars.value("bgmvolume"));
+            snd.update(timestep);
+        }
+        if let Some(backbuffer) = backbuffer {
+            self.render_view(backbuffer);
+            self.screen.end_frame(backbuffer, self.palette.as_ref());
+        }
+    }
+
     /// Shuts down the local server.
     pub fn shutdown(&mut self) {
-        self.snd.shutdown();
+        self.cls.disconnect(self.realtime);
+        self.net.listen(false);
+        if let Some(ref mut snd) = self.snd {
+            snd.shutdown();
+        }
+    }
+
+    /// Draws the view of the player once the client is in the game.
+    fn render_view(&mut self, backbuffer : &mut BackBuffer) {
+        let palette = match self.palette {
+            Some(ref palette) if self.cls.signon == protocol::SIGNONS => palette,
+            _ => return,
+        };
+        let params = RenderParams::new(&self.cvars);
+        self.renderer.render_view(&self.view.refdef, &self.cl, self.cls.time, &params, palette, backbuffer);
+        self.particles.draw(&self.view.refdef, params.fov, palette, backbuffer, self.renderer.zbuffer());
+        self.renderer.warp_view(self.cls.time, backbuffer);
+
+        // 1 is the stats screen, 2 the end of an episode and 3 a cutscene
+        if self.showscores && self.cl.intermission == 0 && self.cl.gametype == protocol::GAME_DEATHMATCH {
+            self.sbar.deathmatch_overlay(&self.cl, &mut self.draw, self.game_res, palette, backbuffer);
+        }
+        if self.cl.intermission == 1 {
+            self.sbar.intermission_overlay(&self.cl, &mut self.draw, self.game_res, palette, backbuffer);
+            return;
+        }
+        if self.cl.intermission == 2 {
+            self.sbar.finale_overlay(&mut self.draw, self.game_res, palette, backbuffer);
+        }
+        if let Some(conchars) = self.draw.conchars() {
+            self.screen.draw_center_string(self.cls.time, self.cl.intermission != 0, conchars, palette, backbuffer);
+        }
+    }
+
+    /// Loads the map and the model flags of a new level.
+    fn new_map(&mut self, models : &[String]) {
+        let bsp = models.first().and_then(|name| {
+            let data = self.game_res.load_file(name)?;
+            match BspFile::read(&data) {
+                Ok(bsp) => Some((name, Rc::new(bsp))),
+                Err(_) => {
+                    println!("Couldn't load {}", name);
+                    None
+                }
+            }
+        });
+        self.renderer.new_map(bsp.as_ref().map(|&(name, ref bsp)| WorldModel::from_bsp(name, bsp.clone())));
+
+        // the brush models by model index, the map is model 1 and its
+        // inline models are named *1, *2, ...
+        let brushes : Vec<Rc<BrushModel>> = match bsp {
+            Some((_, bsp)) => BrushModel::from_bsp(&bsp).into_iter().map(Rc::new).collect(),
+            None => Vec::new(),
+        };
+        let mut brush_models = vec![None];
+        for (index, name) in models.iter().enumerate() {
+            let submodel = if index == 0 { Some(0) } else if name.starts_with('*') { name[1..].parse::<usize>().ok() } else { None };
+            brush_models.push(submodel.and_then(|submodel| brushes.get(submodel).cloned()));
+        }
+        self.prediction.new_map(brush_models);
+
+        let mut flags = vec![0];
+        for name in models {
+            let data = if name.ends_with(".mdl") { self.game_res.load_file(name) } else { None };
+            flags.push(data.and_then(|data| r_model::alias_model_flags(&data)).unwrap_or(0));
+        }
+        self.cl.model_flags = flags;
+    }
+
+    /// Handles a message from the server or a demo.
+    fn client_message(&mut self, message : &ServerMessage, params : &ViewParams) {
+        self.cl.parse_message(message, self.cls.time);
+        match *message {
+            ServerMessage::CdTrack { track, .. } => {
+                if let Some(ref mut snd) = self.snd {
+                    if track > 0 {
+                        self.cd_audio.play(track as u32, true, snd, self.game_res);
+                    } else {
+                        self.cd_audio.stop(snd);
+                    }
+                }
+            }
+            ServerMessage::ServerInfo { ref models, .. } => {
+                // release the sounds of the previous level
+                if let Some(ref mut snd) = self.snd {
+                    snd.stop_all_sounds();
+                    snd.clear_cache();
+
+                    // registered in order, the cache index is the sound
+                    // index of the messages. Index 0 stays unused.
+                    for name in &self.cl.sound_precache {
+                        snd.precache_sound(name);
+                    }
+                }
+                self.particles.clear();
+                if !self.dedicated {
+                    self.new_map(models);
+                }
+            }
+            ServerMessage::CenterPrint(ref text) | ServerMessage::Finale(ref text) | ServerMessage::Cutscene(ref text) => {
+                self.screen.center_print(text, self.cls.time);
+            }
+            ServerMessage::Print(ref text) => {
+                // chat and the server version start with a control character
+                print!("{}", text.trim_start_matches(|c| c == '\u{1}' || c == '\u{2}'));
+            }
+            ServerMessage::SetAngle(angles) => self.input.viewangles = angles,
+            ServerMessage::TempEntity(ref te) => self.particles.temp_entity(te, self.cls.time),
+            ServerMessage::Particle { ref origin, ref dir, count, color } => {
+                let count = if count == 255 { 1024 } else { count };
+                self.particles.run_particle_effect(origin, dir, color, count, self.cls.time);
+            }
+            ServerMessage::Sound(ref start) => {
+                if let Some(ref mut snd) = self.snd {
+                    if let Some(sfx) = load_sound(snd, start.sound, self.game_res) {
+                        snd.start_sound(start.entity, start.channel, &sfx, start.origin, start.volume as f32 / 255.0, start.attenuation);
+                    }
+                }
+            }
+            ServerMessage::StopSound { entity, channel } => {
+                if let Some(ref mut snd) = self.snd {
+                    snd.stop_sound(entity, channel);
+                }
+            }
+            ServerMessage::SpawnStaticSound { ref origin, sound, volume, attenuation } => {
+                if let Some(ref mut snd) = self.snd {
+                    if let Some(sfx) = load_sound(snd, sound, self.game_res) {
+                        snd.static_sound(&sfx, *origin, volume as f32, attenuation);
+                    }
+                }
+            }
+            ServerMessage::Damage { armor, blood, ref from } => self.view.parse_damage(&self.cl, armor, blood, from, params),
+            _ => {}
+        }
+    }
+
+    /// Loads a map and starts the server on it, returns false if the map
+    /// or the progs are missing.
+    fn spawn_server(&mut self, name : &str) -> bool {
+        let progs = match self.progs {
+            Some(ref mut progs) => progs,
+            None => {
+                println!("Couldn't spawn server {}: no progs loaded", name);
+                return false;
+            }
+        };
+        let filename = format!("maps/{}.bsp", name);
+        let bsp = match self.game_res.load_file(&filename).map(|data| BspFile::read(&data)) {
+            Some(Ok(bsp)) => bsp,
+            _ => {
+                println!("Couldn't spawn server {}", filename);
+                return false;
+            }
+        };
+        let params = SpawnParams::new(&self.cvars);
+        self.cvars.set("skill", &params.skill.to_string());
+        self.sv.cvars = self.cvars.clone();
+        self.sv_cvar_changes = Some(self.cvars.changes());
+        self.sv_status = None;
+        let physics = PhysicsParams::new(&self.cvars, 0.1);
+        self.sv.spawn_server(name, Rc::new(bsp), &params, &physics, &mut **progs);
+        self.run_server_commands();
+        self.sv.active
+    }
+
+    /// Copies the cvars for the progs if they have changed since the last
+    /// copy.
+    fn update_server_cvars(&mut self) {
+        if self.sv_cvar_changes != Some(self.cvars.changes()) {
+            self.sv.cvars = self.cvars.clone();
+            self.sv_cvar_changes = Some(self.cvars.changes());
+        }
+    }
+
+    /// Updates the state answered to server queries when a cvar changed or
+    /// a client connected or left, and once a second for the frags and
+    /// connect times.
+    fn update_status(built : &mut Option<(u32, usize, f64)>, sv : &Server, cvars : &CvarList, net : &mut Net, time : f64) {
+        let active = sv.clients.iter().filter(|c| c.active).count();
+        if let Some((changes, clients, built_time)) = *built {
+            if changes == cvars.changes() && clients == active && time - built_time < 1.0 {
+                return;
+            }
+        }
+        let status = sv.query_status(cvars.string("hostname"), cvars.server_rules(), time);
+        net.set_status(Some(&status));
+        *built = Some((cvars.changes(), active, time));
+    }
+
+    /// Shuts the server down after an error in the progs, otherwise the
+    /// commands sent by the progs are added to the command buffer.
+    fn run_server_commands(&mut self) {
+        if self.sv.program_error {
+            println!("Host_Error: Program error");
+            self.sv.commands.clear();
+            self.shutdown_server();
+            self.cls.disconnect(self.realtime);
+            return;
+        }
+        if !self.sv.commands.is_empty() {
+            let commands = ::std::mem::replace(&mut self.sv.commands, String::new());
+            self.commands.add_text(&commands);
+        }
+    }
+
+    /// Drops all clients of the local server.
+    fn shutdown_server(&mut self) {
+        if let Some(ref mut progs) = self.progs {
+            self.sv.shutdown(&mut **progs, self.realtime);
+        }
+        self.net.set_status(None);
+        self.sv_status = None;
+    }
+
+    /// Handles the map command, a new game is started on the map.
+    fn map_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("map <levelname> : start a new game");
+            return;
+        }
+        self.cls.disconnect(self.realtime);
+        self.shutdown_server();
+        self.level_states.clear();
+        self.sv.serverflags = 0.0;
+        if !self.spawn_server(&args[1]) {
+            return;
+        }
+        if !self.dedicated {
+            self.connect_cmd(&["connect".to_string(), "local".to_string()]);
+        }
+    }
+
+    /// Returns true if the local server runs a game that can change the level.
+    fn can_change_level(&self) -> bool {
+        if !self.sv.active || self.cls.is_demo_playback() || self.progs.is_none() {
+            println!("Only the server may changelevel");
+            return false;
+        }
+        true
+    }
+
+    /// Handles the changelevel command. The clients stay connected and
+    /// take their spawn parameters to the next level.
+    fn changelevel_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("changelevel <levelname> : continue game on a new level");
+            return;
+        }
+        if !self.can_change_level() {
+            return;
+        }
+        if let Some(ref mut progs) = self.progs {
+            self.sv.save_spawn_parms(&mut **progs);
+        }
+        self.spawn_server(&args[1]);
+    }
+
+    /// Handles the changelevel2 command. The state of the left level is
+    /// kept, going back to it continues where it was left.
+    fn changelevel2_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("changelevel2 <levelname> : continue game on a new level in the unit");
+            return;
+        }
+        if !self.can_change_level() {
+            return;
+        }
+        if let Some(ref mut progs) = self.progs {
+            self.sv.save_spawn_parms(&mut **progs);
+            let state = self.sv.save_game(self.cvars.value("skill") as i32, &**progs);
+            self.level_states.insert(self.sv.name.clone(), state);
+        }
+        if !self.spawn_server(&args[1]) {
+            return;
+        }
+
+        let spawn_parms : Vec<_> = self.sv.clients.iter().map(|c| c.spawn_parms).collect();
+        let result = match (self.progs.as_mut(), self.level_states.get(&args[1])) {
+            (Some(progs), Some(state)) => self.sv.load_game(state, &mut **progs),
+            _ => return,
+        };
+        if let Err(err) = result {
+            println!("ERROR: couldn't load level state: {}", err);
+            self.shutdown_server();
+            return;
+        }
+        // the clients enter the level again with the spawn parameters from
+        // the level that was left
+        self.sv.loadgame = false;
+        for (client, parms) in self.sv.clients.iter_mut().zip(spawn_parms) {
+            client.spawn_parms = parms;
+        }
+    }
+
+    /// Handles the restart command, the running level starts over.
+    fn restart_cmd(&mut self) {
+        if self.cls.is_demo_playback() || !self.sv.active {
+            return;
+        }
+        let name = self.sv.name.clone();
+        self.spawn_server(&name);
+    }
+
+    /// Handles the name command, the name is sent to the server when
+    /// connected.
+    fn name_cmd(&mut self, args : &[String]) {
+        if args.len() == 1 {
+            println!("\"name\" is \"{}\"", self.cvars.string("_cl_name"));
+            return;
+        }
+        let name = host_cmd::player_name(&args[1..].join(" "));
+        if name == self.cvars.string("_cl_name") {
+            return;
+        }
+        self.cvars.set("_cl_name", &name);
+        if self.cls.is_connected() {
+            self.cls.forward_to_server(&["name".to_string(), name]);
+        }
+    }
+
+    /// Handles the color command with the shirt and pants colors, a single
+    /// color is used for both.
+    fn color_cmd(&mut self, args : &[String]) {
+        if args.len() == 1 {
+            let color = self.cvars.value("_cl_color") as i32;
+            println!("\"color\" is \"{} {}\"", color >> 4, color & 15);
+            println!("color <0-13> [0-13]");
+            return;
+        }
+        let top = host_cmd::player_color(args[1].parse().unwrap_or(0));
+        let bottom = host_cmd::player_color(args.get(2).unwrap_or(&args[1]).parse().unwrap_or(0));
+        self.cvars.set("_cl_color", &(top * 16 + bottom).to_string());
+        if self.cls.is_connected() {
+            self.cls.forward_to_server(&["color".to_string(), top.to_string(), bottom.to_string()]);
+        }
+    }
+
+    /// Handles the say and say_team commands. The server console talks
+    /// directly to the clients.
+    fn say_cmd(&mut self, args : &[String]) {
+        if args.len() < 2 {
+            return;
+        }
+        if self.dedicated {
+            let params = CommandParams::new(&self.cvars);
+            self.sv.say(None, &args[1..].join(" "), args[0] == "say_team", &params);
+        } else {
+            self.cls.forward_to_server(args);
+        }
+    }
+
+    /// Handles the status command, clients ask the server.
+    fn status_cmd(&mut self, args : &[String]) {
+        if self.sv.active {
+            print!("{}", self.sv.status(self.cvars.string("hostname"), self.realtime));
+        } else {
+            self.cls.forward_to_server(args);
+        }
+    }
+
+    /// Handles the kick command with a player name or # and a slot number
+    /// and an optional message.
+    fn kick_cmd(&mut self, args : &[String]) {
+        let progs = match self.progs {
+            Some(ref mut progs) if self.sv.active => progs,
+            _ => {
+                self.cls.forward_to_server(args);
+                return;
+            }
+        };
+        let (client, start) = match self.sv.find_kicked_client(args) {
+            Some(found) => found,
+            None => return,
+        };
+        // the player of a listen server can't kick themselves
+        if client == 0 && !self.dedicated {
+            return;
+        }
+        let who = if self.dedicated { "Console".to_string() } else { self.cvars.string("_cl_name").to_string() };
+        let message = if args.len() > start { Some(args[start..].join(" ")) } else { None };
+        self.sv.kick_client(client, &who, message.as_ref().map(|m| m.as_str()), &mut **progs, self.realtime);
+    }
+
+    /// Handles the maxplayers command, it takes effect with the next map.
+    /// More than one player opens the server to network clients.
+    fn maxplayers_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("\"maxplayers\" is \"{}\"", self.sv.max_clients);
+            return;
+        }
+        if self.sv.active {
+            println!("maxplayers can not be changed while a server is running.");
+            return;
+        }
+        let mut n = args[1].parse::<usize>().unwrap_or(1).max(1);
+        if n > server::MAX_SCOREBOARD {
+            n = server::MAX_SCOREBOARD;
+            println!("maxplayers set to {}", n);
+        }
+        self.sv.set_max_clients(n);
+        self.net.listen(n > 1 || self.dedicated);
+        self.net.set_max_connections(if self.dedicated { n } else { n - 1 });
+        self.cvars.set("deathmatch", if n == 1 { "0" } else { "1" });
+    }
+
+    /// Handles the slist command, lists the servers on the local network.
+    fn slist_cmd(&mut self) {
+        if self.slist.is_some() {
+            println!("Already searching for servers.");
+            return;
+        }
+        println!("Looking for Quake servers...");
+        match ServerSearch::start_lan(net::DEFAULT_NET_PORT, self.realtime, SLIST_WAIT) {
+            Ok(search) => self.slist = Some(search),
+            Err(err) => println!("Network Error: {}", err),
+        }
+    }
+
+    /// Collects the answers of a running server search and prints the
+    /// servers once it's over.
+    fn poll_slist(&mut self) {
+        let done = match self.slist {
+            Some(ref mut search) => search.poll(self.realtime),
+            None => return,
+        };
+        let search = match done {
+            Ok(false) => return,
+            Ok(true) => self.slist.take().unwrap(),
+            Err(err) => {
+                println!("Network Error: {}", err);
+                self.slist = None;
+                return;
+            }
+        };
+        if search.servers().is_empty() {
+            println!("No Quake servers found.");
+            return;
+        }
+        println!("Server          Map             Users Ping");
+        println!("--------------- --------------- ----- ----");
+        for server in search.servers() {
+            let info = &server.info;
+            println!("{:<15.15} {:<15.15} {:2}/{:2} {:4}", info.hostname, info.map, info.players, info.max_players, (server.ping * 1000.0) as i32);
+        }
+    }
+
+    /// Handles the connect command.
+    fn connect_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("connect <server>");
+            return;
+        }
+        self.cls.disconnect(self.realtime);
+        match self.net.connect(&args[1], self.realtime) {
+            Some(socket) => self.cls.connect(socket, self.realtime),
+            None => println!("CL_Connect: connect failed"),
+        }
+    }
+
+    /// Handles the save command, only single player games can be saved.
+    fn save_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("save <savename> : save a game");
+            return;
+        }
+        if !self.sv.active || self.progs.is_none() {
+            println!("Not playing a local game.");
+            return;
+        }
+        if self.sv.max_clients != 1 {
+            println!("Can't save multiplayer games.");
+            return;
+        }
+        if args[1].contains("..") {
+            println!("Relative pathnames are not allowed.");
+            return;
+        }
+        if self.sv.clients[0].active && self.sv.edicts[1].v.float(progdefs::HEALTH) <= 0.0 {
+            println!("Can't savegame with a dead player");
+            return;
+        }
+
+        let name = Path::new(GAME_DIRECTORY).join(cl_main::default_extension(&args[1], "sav"));
+        let name = name.to_string_lossy().into_owned();
+        println!("Saving game to {}...", name);
+        let save = self.sv.save_game(self.cvars.value("skill") as i32, &**self.progs.as_ref().unwrap());
+        match File::create(&name).and_then(|mut file| save.write(&mut file)) {
+            Ok(()) => println!("done."),
+            Err(err) => println!("ERROR: couldn't open: {}", err),
+        }
     }
-}
\ No newline at end of file
+
+    /// Handles the load command. The map of the save game is started and
+    /// its entities are replaced by the saved ones.
+    fn load_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("load <savename> : load a game");
+            return;
+        }
+        let name = Path::new(GAME_DIRECTORY).join(cl_main::default_extension(&args[1], "sav"));
+        let name = name.to_string_lossy().into_owned();
+        println!("Loading game from {}...", name);
+        let save = match read_savegame(&name) {
+            Ok(save) => save,
+            Err(err) => {
+                println!("ERROR: couldn't load: {}", err);
+                return;
+            }
+        };
+        self.cvars.set("skill", &save.skill.to_string());
+
+        self.cls.disconnect(self.realtime);
+        self.shutdown_server();
+        self.level_states.clear();
+        if !self.spawn_server(&save.mapname) {
+            println!("Couldn't load map {}", save.mapname);
+            return;
+        }
+        let result = match self.progs {
+            Some(ref mut progs) => self.sv.load_game(&save, &mut **progs),
+            None => Ok(()),
+        };
+        if let Err(err) = result {
+            println!("ERROR: couldn't load: {}", err);
+            self.shutdown_server();
+            return;
+        }
+        if !self.dedicated {
+            self.connect_cmd(&["connect".to_string(), "local".to_string()]);
+        }
+    }
+
+    fn execute_commands(&mut self) {
+        while let Some(line) = self.commands.next_command() {
+            let args = cmd::tokenize(&line);
+            if args.is_empty() || self.cvars.command(&args) || self.input.command(&args) {
+                continue;
+            }
+            match args[0].as_str() {
+                "screenshot" => self.screen.screenshot_cmd(&args),
+                "soundlist" | "play" | "playvol" | "cd" if self.snd.is_none() => {}
+                "soundlist" => self.snd.as_mut().unwrap().soundlist_cmd(),
+                "play" => self.snd.as_mut().unwrap().play_cmd(&args, self.game_res),
+                "playvol" => self.snd.as_mut().unwrap().playvol_cmd(&args, self.game_res),
+                "cd" => self.cd_audio.cd_cmd(&args, self.snd.as_mut().unwrap(), self.game_res),
+                "bind" => self.keys.bind_cmd(&args),
+                "unbind" => self.keys.unbind_cmd(&args),
+                "unbindall" => self.keys.unbindall_cmd(),
+                "connect" => self.connect_cmd(&args),
+                "slist" => self.slist_cmd(),
+                "map" => self.map_cmd(&args),
+                "changelevel" => self.changelevel_cmd(&args),
+                "changelevel2" => self.changelevel2_cmd(&args),
+                "restart" => self.restart_cmd(),
+                "disconnect" => self.cls.disconnect(self.realtime),
+                "record" => self.cls.record_cmd(&args, &mut self.commands),
+                "stop" => self.cls.stop_cmd(),
+                "playdemo" => self.cls.playdemo_cmd(&args, self.game_res, self.realtime),
+                "timedemo" => self.cls.timedemo_cmd(&args, self.game_res, self.realtime),
+                "save" => self.save_cmd(&args),
+                "load" => self.load_cmd(&args),
+                "name" => self.name_cmd(&args),
+                "color" => self.color_cmd(&args),
+                "say" | "say_team" => self.say_cmd(&args),
+                "status" => self.status_cmd(&args),
+                "kick" => self.kick_cmd(&args),
+                "ping" if self.sv.active => print!("{}", self.sv.ping_times()),
+                "kill" | "ping" | "tell" | "pause" => self.cls.forward_to_server(&args),
+                "maxplayers" => self.maxplayers_cmd(&args),
+                "+showscores" => self.showscores = true,
+                "-showscores" => self.showscores = false,
+                "quit" => self.running = false,
+                _ => println!("Unknown command \"{}\"", args[0]),
+            }
+        }
+    }
+}
+
+/// Reads and parses a save game file.
+fn read_savegame(name : &str) -> Result<SaveGame, SaveError> {
+    let mut text = String::new();
+    File::open(name)?.read_to_string(&mut text)?;
+    SaveGame::parse(&text)
+}
+
+/// Returns the samples of a sound by its index in the server messages,
+/// index 0 is no sound.
+fn load_sound(snd : &mut SoundEngine, sound : i32, game_res : &mut GameResources) -> Option<Rc<SfxCache>> {
+    if sound <= 0 {
+        return None;
+    }
+    snd.load_sound(sound as usize, game_res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_common::{NativeSoundEngine, SoundFormat};
+    use pr_exec::ProgsBuilder;
+    use protocol::SoundStart;
+
+    struct Files(HashMap<String, Vec<u8>>);
+
+    impl GameResources for Files {
+        fn add_game_directory(&mut self, _path : &str) {}
+
+        fn load_file(&mut self, name : &str) -> Option<Vec<u8>> {
+            self.0.get(name).cloned()
+        }
+    }
+
+    struct NoSound;
+
+    impl NativeSoundEngine for NoSound {
+        fn init(&mut self) {}
+        fn shutdown(&mut self) {}
+        fn get_format(&self) -> Option<SoundFormat> {
+            None
+        }
+        fn get_dma_position(&mut self) -> usize {
+            0
+        }
+        fn write_samples(&mut self, _offset : usize, _samples : &[i16]) {}
+        fn update(&mut self, _timestep : f32) {}
+    }
+
+    /// Progs where every function the engine calls does nothing.
+    fn test_progs() -> Vec<u8> {
+        let mut builder = ProgsBuilder::new();
+        let null = builder.function("SUB_Null", &[[0, 0, 0, 0]]);
+        builder.function("worldspawn", &[[0, 0, 0, 0]]);
+        for global in progdefs::G_STARTFRAME..progdefs::G_SETCHANGEPARMS + 1 {
+            builder.set_global(global, null);
+        }
+        builder.build()
+    }
+
+    /// A map with an empty room above z = 0.
+    fn test_map() -> Vec<u8> {
+        let words = |values : &[i32]| values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect::<Vec<u8>>();
+        let shorts = |values : &[i16]| values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect::<Vec<u8>>();
+        let floats = |values : &[f32]| values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect::<Vec<u8>>();
+
+        let entities = b"{ \"classname\" \"worldspawn\" }\n{ \"classname\" \"info_player_start\" }\0".to_vec();
+        let mut planes = floats(&[0.0, 0.0, 1.0, 0.0]);
+        planes.extend(words(&[2]));
+        let mut nodes = words(&[0]);
+        nodes.extend(shorts(&[-2, -1, -64, -64, -64, 64, 64, 64, 0, 0]));
+        let mut leafs = words(&[-2, -1]);
+        leafs.extend(shorts(&[0; 8]));
+        leafs.extend(words(&[0]));
+        leafs.extend(words(&[-1, -1]));
+        leafs.extend(shorts(&[-64, -64, 0, 64, 64, 64, 0, 0]));
+        leafs.extend(words(&[0]));
+        let mut clipnodes = words(&[0]);
+        clipnodes.extend(shorts(&[-1, -2]));
+        let mut models = floats(&[-64.0, -64.0, -64.0, 64.0, 64.0, 64.0, 0.0, 0.0, 0.0]);
+        models.extend(words(&[0, 0, 0, 0, 1, 0, 0]));
+
+        // entities, planes, textures, vertexes, visibility, nodes, texinfo,
+        // faces, lighting, clipnodes, leafs, marksurfaces, edges, surfedges
+        // and models
+        let lumps = vec![entities, planes, Vec::new(), Vec::new(), Vec::new(), nodes, Vec::new(),
+                         Vec::new(), Vec::new(), clipnodes, leafs, Vec::new(), Vec::new(), Vec::new(), models];
+        let mut data = words(&[29]);
+        let mut offset = 4 + lumps.len() * 8;
+        for lump in &lumps {
+            data.extend(words(&[offset as i32, lump.len() as i32]));
+            offset += lump.len();
+        }
+        for lump in &lumps {
+            data.extend_from_slice(lump);
+        }
+        data
+    }
+
+    #[test]
+    fn map_command() {
+        let mut files = HashMap::new();
+        files.insert("progs.dat".to_string(), test_progs());
+        files.insert("maps/test.bsp".to_string(), test_map());
+        let mut game_res = Files(files);
+        let mut snd = SoundEngine::new(Box::new(NoSound));
+        let mut host = Host::new(&mut game_res, &mut snd);
+        host.init();
+        assert!(host.progs.is_some());
+
+        host.add_command("map test\n");
+        for _ in 0..10 {
+            host.frame(0.05, &[], None);
+        }
+        assert!(host.sv.active);
+        assert_eq!(host.sv.name, "test");
+        assert_eq!(host.sv.model_precache[1], "maps/test.bsp");
+        assert!(host.sv.clients[0].spawned);
+        assert_eq!(host.cls.signon, protocol::SIGNONS);
+
+        // the client stays connected when the level changes
+        host.add_command("changelevel test\n");
+        for _ in 0..10 {
+            host.frame(0.05, &[], None);
+        }
+        assert!(host.sv.active && host.sv.clients[0].spawned);
+        assert_eq!(host.cls.signon, protocol::SIGNONS);
+
+        // a missing map keeps the server down
+        host.add_command("map missing\n");
+        host.frame(0.05, &[], None);
+        assert!(!host.sv.active);
+        host.shutdown();
+    }
+
+    #[test]
+    fn server_sounds() {
+        let mut wave = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0\x11\x2b\0\0\x11\x2b\0\0\x01\0\x08\0data".to_vec();
+        wave.extend_from_slice(&[10, 0, 0, 0]);
+        wave.extend_from_slice(&[0xc0; 10]);
+        let mut files = HashMap::new();
+        files.insert("sound/weapons/ax1.wav".to_string(), wave);
+        let mut game_res = Files(files);
+        let mut snd = SoundEngine::new(Box::new(NoSound));
+        let mut host = Host::new(&mut game_res, &mut snd);
+        host.init();
+        let params = ViewParams::new(&host.cvars, 0.05);
+
+        // sound 1 is the first sound of the server info
+        host.client_message(&ServerMessage::ServerInfo {
+            protocol : protocol::PROTOCOL_VERSION,
+            maxclients : 1,
+            gametype : 0,
+            levelname : String::new(),
+            models : Vec::new(),
+            sounds : vec!["misc/null.wav".to_string(), "weapons/ax1.wav".to_string()],
+        }, &params);
+        host.client_message(&ServerMessage::SetView(1), &params);
+        let start = SoundStart { entity : 1, channel : 1, sound : 2, volume : 255, attenuation : 1.0, origin : [0.0; 3] };
+        host.client_message(&ServerMessage::Sound(start), &params);
+        assert_eq!(host.snd.as_ref().unwrap().playing_channels(), 1);
+
+        host.client_message(&ServerMessage::StopSound { entity : 1, channel : 1 }, &params);
+        assert_eq!(host.snd.as_ref().unwrap().playing_channels(), 0);
+
+        // missing sounds are skipped
+        host.client_message(&ServerMessage::Sound(SoundStart { sound : 1, ..start }), &params);
+        assert_eq!(host.snd.as_ref().unwrap().playing_channels(), 0);
+    }
+}
@@ -0,0 +1,340 @@
+#![warn(missing_docs)]
+
+//! Player names, colors, chat, kicking, pausing and the server status.
+//!
+//! Original source can be found in host_cmd.c
+
+use cvar::CvarList;
+use progdefs;
+use net::{PlayerInfo, ServerStatus};
+use progs::Progs;
+use protocol::ServerMessage;
+use server::{self, Server};
+use sv_main::VERSION;
+
+/// Longest player name.
+pub const MAX_NAME_LENGTH : usize = 15;
+
+/// Longest chat message including the name of the sender.
+const MAX_SAY_LENGTH : usize = 62;
+
+/// Highest color of the shirt and pants ranges.
+const MAX_COLOR : i32 = 13;
+
+/// Values of the cvars used by the player commands.
+#[derive(Clone, Debug)]
+pub struct CommandParams {
+    /// Name of the server, shown for messages of the server console.
+    pub hostname : String,
+    /// Team messages only go to players of the same team.
+    pub teamplay : bool,
+    /// Clients may pause the game.
+    pub pausable : bool,
+}
+
+impl CommandParams {
+    /// Reads the cvars.
+    pub fn new(cvars : &CvarList) -> CommandParams {
+        CommandParams {
+            hostname : cvars.string("hostname").to_string(),
+            teamplay : cvars.value("teamplay") != 0.0,
+            pausable : cvars.value("pausable") != 0.0,
+        }
+    }
+}
+
+/// Returns a player name cut to the maximum length.
+pub fn player_name(name : &str) -> String {
+    name.chars().take(MAX_NAME_LENGTH).collect()
+}
+
+/// Returns a chat message without the quotes around the text, cut to the
+/// maximum length.
+fn chat_message(prefix : String, text : &str) -> String {
+    let text = if text.len() > 1 && text.starts_with('"') && text.ends_with('"') {
+        &text[1..text.len() - 1]
+    } else {
+        text
+    };
+    let mut message : String = (prefix + text).chars().take(MAX_SAY_LENGTH).collect();
+    message.push('\n');
+    message
+}
+
+/// Returns a shirt or pants color in the range the translation supports.
+pub fn player_color(color : i32) -> i32 {
+    (color & 15).min(MAX_COLOR)
+}
+
+impl Server {
+    /// Changes the name of a client and tells all clients.
+    pub fn set_client_name(&mut self, client : usize, name : &str) {
+        let name = player_name(name);
+        {
+            let old = &self.clients[client].name;
+            if !old.is_empty() && old != "unconnected" && *old != name {
+                println!("{} renamed to {}", old, name);
+            }
+        }
+        let netname = self.strings.alloc(&name);
+        self.edicts[client + 1].v.set_int(progdefs::NETNAME, netname);
+        self.clients[client].name = name.clone();
+        ServerMessage::UpdateName { client : client as i32, name : name }.write(&mut self.reliable_datagram);
+    }
+
+    /// Changes the shirt and pants colors of a client and tells all
+    /// clients. The pants color is the team.
+    pub fn set_client_colors(&mut self, client : usize, top : i32, bottom : i32) {
+        let (top, bottom) = (player_color(top), player_color(bottom));
+        let colors = top * 16 + bottom;
+        self.clients[client].colors = colors;
+        self.edicts[client + 1].v.set_float(progdefs::TEAM, (bottom + 1) as f32);
+        ServerMessage::UpdateColors { client : client as i32, colors : colors }.write(&mut self.reliable_datagram);
+    }
+
+    /// Sends a chat message to all clients in the game. With teamplay, team
+    /// messages only go to the team of the sender. Messages without a
+    /// sender come from the server console.
+    pub fn say(&mut self, from : Option<usize>, text : &str, team_only : bool, params : &CommandParams) {
+        let prefix = match from {
+            Some(client) => format!("\u{1}{}: ", self.clients[client].name),
+            None => format!("\u{1}<{}> ", params.hostname),
+        };
+        let message = chat_message(prefix, text);
+
+        let team = from.map(|client| self.edicts[client + 1].v.float(progdefs::TEAM));
+        for (client, c) in self.clients.iter_mut().enumerate() {
+            if !c.active || !c.spawned {
+                continue;
+            }
+            if params.teamplay && team_only && team != Some(self.edicts[client + 1].v.float(progdefs::TEAM)) {
+                continue;
+            }
+            ServerMessage::Print(message.clone()).write(&mut c.message);
+        }
+    }
+
+    /// Sends a chat message from a client to the player with the given name.
+    pub fn tell(&mut self, from : usize, name : &str, text : &str) {
+        let message = chat_message(format!("{}: ", self.clients[from].name), text);
+        let to = self.clients.iter_mut().find(|c| c.active && c.spawned && c.name.eq_ignore_ascii_case(name));
+        if let Some(c) = to {
+            ServerMessage::Print(message).write(&mut c.message);
+        }
+    }
+
+    /// Lets the progs kill the player of a client.
+    pub fn kill(&mut self, client : usize, progs : &mut Progs) {
+        let ent = client + 1;
+        if self.edicts[ent].v.float(progdefs::HEALTH) <= 0.0 {
+            ServerMessage::Print("Can't suicide -- allready dead!\n".to_string()).write(&mut self.clients[client].message);
+            return;
+        }
+        self.globals.set_float(progdefs::G_TIME, self.time);
+        self.globals.set_int(progdefs::G_SELF, ent as i32);
+        let client_kill = self.globals.int(progdefs::G_CLIENTKILL);
+        self.execute(progs, client_kill);
+    }
+
+    /// Returns the average round trip times of the connected clients in
+    /// milliseconds.
+    pub fn ping_times(&self) -> String {
+        let mut text = "Client ping times:\n".to_string();
+        for c in self.clients.iter().filter(|c| c.active) {
+            let total : f32 = c.ping_times.iter().sum();
+            let ping = total / server::NUM_PING_TIMES as f32;
+            text += &format!("{:4} {}\n", (ping * 1000.0) as i32, c.name);
+        }
+        text
+    }
+
+    /// Pauses or continues the game and tells all clients who did it.
+    pub fn pause(&mut self, client : usize, params : &CommandParams) {
+        if !params.pausable {
+            ServerMessage::Print("Pause not allowed.\n".to_string()).write(&mut self.clients[client].message);
+            return;
+        }
+        self.paused = !self.paused;
+        let name = self.strings.get(self.edicts[client + 1].v.int(progdefs::NETNAME)).to_string();
+        let text = if self.paused {
+            format!("{} paused the game\n", name)
+        } else {
+            format!("{} unpaused the game\n", name)
+        };
+        ServerMessage::Print(text).write(&mut self.reliable_datagram);
+        ServerMessage::SetPause(self.paused).write(&mut self.reliable_datagram);
+    }
+
+    /// Returns the server name, map and the connected players with their
+    /// frags, connection time and address.
+    pub fn status(&self, hostname : &str, time : f64) -> String {
+        let active = self.clients.iter().filter(|c| c.active).count();
+        let mut text = format!("host:    {}\nversion: {:4.2}\nmap:     {}\nplayers: {} active ({} max)\n\n",
+                               hostname, VERSION, self.name, active, self.max_clients);
+        for (i, c) in self.clients.iter().enumerate().filter(|&(_, c)| c.active) {
+            let seconds = (time - c.connect_time) as i32;
+            let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+            let frags = self.edicts[i + 1].v.float(progdefs::FRAGS) as i32;
+            text += &format!("#{:<2} {:<16.16}  {:3}  {:2}:{:02}:{:02}\n", i + 1, c.name, frags, hours, minutes, seconds);
+            let address = c.netconnection.as_ref().map_or(String::new(), |socket| socket.address());
+            text += &format!("   {}\n", address);
+        }
+        text
+    }
+
+    /// Returns the state answered to server queries with the connected
+    /// players and the game rules.
+    pub fn query_status(&self, hostname : &str, rules : Vec<(String, String)>, time : f64) -> ServerStatus {
+        let players = self.clients.iter().enumerate().filter(|&(_, c)| c.active).map(|(i, c)| PlayerInfo {
+            name : c.name.clone(),
+            colors : c.colors,
+            frags : self.edicts[i + 1].v.float(progdefs::FRAGS) as i32,
+            connect_time : (time - c.connect_time) as i32,
+            address : c.netconnection.as_ref().map_or(String::new(), |socket| socket.address()),
+        }).collect();
+        ServerStatus {
+            hostname : hostname.to_string(),
+            map : self.name.clone(),
+            max_players : self.max_clients,
+            players : players,
+            rules : rules,
+        }
+    }
+
+    /// Drops a client and tells it who kicked it.
+    pub fn kick_client(&mut self, client : usize, who : &str, message : Option<&str>, progs : &mut Progs, time : f64) {
+        let text = match message {
+            Some(message) => format!("Kicked by {}: {}\n", who, message),
+            None => format!("Kicked by {}\n", who),
+        };
+        ServerMessage::Print(text).write(&mut self.clients[client].message);
+        self.drop_client(client, progs, time);
+    }
+
+    /// Returns the client named by the arguments of the kick command, a
+    /// name or # and a slot number, and where the message starts.
+    pub fn find_kicked_client(&self, args : &[String]) -> Option<(usize, usize)> {
+        if args.len() > 2 && args[1] == "#" {
+            let slot = args[2].parse::<usize>().unwrap_or(0);
+            if slot == 0 || slot > self.clients.len() || !self.clients[slot - 1].active {
+                return None;
+            }
+            return Some((slot - 1, 3));
+        }
+        let name = args.get(1)?;
+        self.clients.iter().position(|c| c.active && c.name.eq_ignore_ascii_case(name)).map(|client| (client, 2))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use msg::{MsgReader, SizeBuf};
+
+    fn printed(sv : &mut Server, client : usize) -> Vec<String> {
+        let data = sv.clients[client].message.data.clone();
+        sv.clients[client].message.clear();
+        let mut msg = MsgReader::new(&data);
+        let mut texts = Vec::new();
+        while let Some(message) = ServerMessage::read(&mut msg) {
+            if let ServerMessage::Print(text) = message {
+                texts.push(text);
+            }
+        }
+        texts
+    }
+
+    fn server() -> Server {
+        let mut sv = Server::new(3);
+        for client in 0..3 {
+            sv.clients[client].active = true;
+            sv.clients[client].spawned = true;
+        }
+        sv
+    }
+
+    #[test]
+    fn names_and_colors() {
+        let mut sv = server();
+        sv.set_client_name(0, "a very long player name");
+        sv.set_client_colors(0, 4, 17);
+        assert_eq!(sv.clients[0].name, "a very long pla");
+        assert_eq!(sv.strings.get(sv.edicts[1].v.int(progdefs::NETNAME)), "a very long pla");
+        assert_eq!(sv.clients[0].colors, 4 * 16 + 1);
+        assert_eq!(sv.edicts[1].v.float(progdefs::TEAM), 2.0);
+        assert_eq!(player_color(14), 13);
+
+        let mut buf = SizeBuf::new(64);
+        ServerMessage::UpdateColors { client : 0, colors : 65 }.write(&mut buf);
+        assert!(sv.reliable_datagram.data.ends_with(&buf.data));
+    }
+
+    #[test]
+    fn chat() {
+        let params = CommandParams { hostname : "lan".to_string(), teamplay : true, pausable : true };
+        let mut sv = server();
+        sv.clients[0].name = "ranger".to_string();
+        sv.set_client_colors(0, 0, 4);
+        sv.set_client_colors(1, 0, 4);
+        sv.set_client_colors(2, 0, 12);
+
+        sv.say(Some(0), "\"follow me\"", true, &params);
+        assert_eq!(printed(&mut sv, 1), vec!["\u{1}ranger: follow me\n"]);
+        assert!(printed(&mut sv, 2).is_empty());
+        sv.say(None, &"x".repeat(80), false, &params);
+        let text = printed(&mut sv, 2).remove(0);
+        assert!(text.starts_with("\u{1}<lan> xxx"));
+        assert_eq!(text.len(), 63);
+
+        printed(&mut sv, 1);
+        sv.tell(0, "nobody", "\"over here\"");
+        assert!(printed(&mut sv, 1).is_empty());
+        sv.clients[2].name = "grunt".to_string();
+        sv.tell(0, "Grunt", "over here");
+        assert_eq!(printed(&mut sv, 2), vec!["ranger: over here\n"]);
+    }
+
+    #[test]
+    fn ping_and_pause() {
+        let mut sv = server();
+        sv.clients[1].active = false;
+        sv.clients[0].name = "ranger".to_string();
+        sv.clients[0].ping_times = [0.05; server::NUM_PING_TIMES];
+        assert_eq!(sv.ping_times(), "Client ping times:\n  50 ranger\n   0 \n");
+
+        let mut params = CommandParams { hostname : String::new(), teamplay : false, pausable : false };
+        sv.pause(0, &params);
+        assert!(!sv.paused);
+        assert_eq!(printed(&mut sv, 0), vec!["Pause not allowed.\n"]);
+        params.pausable = true;
+        sv.set_client_name(0, "ranger");
+        sv.reliable_datagram.clear();
+        sv.pause(0, &params);
+        assert!(sv.paused);
+        let mut msg = MsgReader::new(&sv.reliable_datagram.data);
+        assert_eq!(ServerMessage::read(&mut msg), Some(ServerMessage::Print("ranger paused the game\n".to_string())));
+        assert_eq!(ServerMessage::read(&mut msg), Some(ServerMessage::SetPause(true)));
+    }
+
+    #[test]
+    fn status_and_kick() {
+        let mut sv = server();
+        sv.name = "dm4".to_string();
+        sv.clients[1].active = false;
+        sv.clients[2].name = "Grunt".to_string();
+        sv.clients[2].connect_time = 10.0;
+        sv.edicts[3].v.set_float(progdefs::FRAGS, 12.0);
+        let status = sv.status("lan", 3735.0);
+        assert!(status.starts_with("host:    lan\nversion: 1.09\nmap:     dm4\nplayers: 2 active (3 max)\n\n"));
+        assert!(status.contains("\n#3  Grunt              12   1:02:05\n"));
+        let query = sv.query_status("lan", vec![("teamplay".to_string(), "1".to_string())], 3735.0);
+        assert_eq!((query.map.as_str(), query.max_players, query.players.len()), ("dm4", 3, 2));
+        assert_eq!((query.players[1].frags, query.players[1].connect_time), (12, 3725));
+
+        let args = |text : &str| text.split(' ').map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(sv.find_kicked_client(&args("kick grunt")), Some((2, 2)));
+        assert_eq!(sv.find_kicked_client(&args("kick # 3 go away")), Some((2, 3)));
+        assert_eq!(sv.find_kicked_client(&args("kick # 2")), None);
+        assert_eq!(sv.find_kicked_client(&args("kick nobody")), None);
+    }
+}
@@ -0,0 +1,266 @@
+#![warn(missing_docs)]
+
+//! Key bindings that turn key events into console commands.
+//!
+//! Original source can be found in keys.c
+
+use rquake_common::keys::*;
+use cmd::CommandBuffer;
+
+const MAX_KEYS : usize = 256;
+
+/// Names of the special keys that can be used with bind.
+const KEY_NAMES : &'static [(&'static str, i32)] = &[
+    ("TAB", K_TAB),
+    ("ENTER", K_ENTER),
+    ("ESCAPE", K_ESCAPE),
+    ("SPACE", K_SPACE),
+    ("BACKSPACE", K_BACKSPACE),
+    ("UPARROW", K_UPARROW),
+    ("DOWNARROW", K_DOWNARROW),
+    ("LEFTARROW", K_LEFTARROW),
+    ("RIGHTARROW", K_RIGHTARROW),
+    ("ALT", K_ALT),
+    ("CTRL", K_CTRL),
+    ("SHIFT", K_SHIFT),
+    ("F1", K_F1),
+    ("F2", K_F1 + 1),
+    ("F3", K_F1 + 2),
+    ("F4", K_F1 + 3),
+    ("F5", K_F1 + 4),
+    ("F6", K_F1 + 5),
+    ("F7", K_F1 + 6),
+    ("F8", K_F1 + 7),
+    ("F9", K_F1 + 8),
+    ("F10", K_F1 + 9),
+    ("F11", K_F1 + 10),
+    ("F12", K_F1 + 11),
+    ("INS", K_INS),
+    ("DEL", K_DEL),
+    ("PGDN", K_PGDN),
+    ("PGUP", K_PGUP),
+    ("HOME", K_HOME),
+    ("END", K_END),
+    ("MOUSE1", K_MOUSE1),
+    ("MOUSE2", K_MOUSE2),
+    ("MOUSE3", K_MOUSE3),
+    ("MWHEELUP", K_MWHEELUP),
+    ("MWHEELDOWN", K_MWHEELDOWN),
+    ("PAUSE", K_PAUSE),
+    ("SEMICOLON", ';' as i32),
+];
+
+/// Bindings of the default configuration.
+const DEFAULT_BINDINGS : &'static [(&'static str, &'static str)] = &[
+    ("w", "+forward"),
+    ("s", "+back"),
+    ("a", "+moveleft"),
+    ("d", "+moveright"),
+    ("UPARROW", "+forward"),
+    ("DOWNARROW", "+back"),
+    ("LEFTARROW", "+left"),
+    ("RIGHTARROW", "+right"),
+    ("ALT", "+strafe"),
+    ("SHIFT", "+speed"),
+    ("CTRL", "+attack"),
+    ("SPACE", "+jump"),
+    ("PGUP", "+lookup"),
+    ("PGDN", "+lookdown"),
+    ("END", "centerview"),
+    ("MOUSE1", "+attack"),
+    ("MOUSE2", "+jump"),
+    ("1", "impulse 1"),
+    ("2", "impulse 2"),
+    ("3", "impulse 3"),
+    ("4", "impulse 4"),
+    ("5", "impulse 5"),
+    ("6", "impulse 6"),
+    ("7", "impulse 7"),
+    ("8", "impulse 8"),
+    ("/", "impulse 10"),
+];
+
+/// Commands bound to the keys and the keys that are currently held down.
+pub struct KeyBindings {
+    bindings : Vec<Option<String>>,
+    down : [bool; MAX_KEYS],
+}
+
+impl KeyBindings {
+    /// Creates the default key bindings.
+    pub fn new() -> KeyBindings {
+        let mut keys = KeyBindings {
+            bindings : vec![None; MAX_KEYS],
+            down : [false; MAX_KEYS],
+        };
+        for &(name, binding) in DEFAULT_BINDINGS {
+            if let Some(key) = string_to_keynum(name) {
+                keys.set_binding(key, Some(binding));
+            }
+        }
+        keys
+    }
+
+    /// Sets or clears the command bound to a key.
+    pub fn set_binding(&mut self, key : i32, binding : Option<&str>) {
+        if key >= 0 && (key as usize) < MAX_KEYS {
+            self.bindings[key as usize] = binding.map(|b| b.to_string());
+        }
+    }
+
+    /// Returns the command bound to a key.
+    pub fn binding(&self, key : i32) -> Option<&str> {
+        if key < 0 || key as usize >= MAX_KEYS {
+            return None;
+        }
+        self.bindings[key as usize].as_ref().map(|b| b.as_str())
+    }
+
+    /// Adds the command bound to a key to the command buffer. Bindings that
+    /// start with a + are sent as -command when the key is released, with
+    /// the key number so that two keys can hold the same button.
+    pub fn key_event(&mut self, key : i32, down : bool, commands : &mut CommandBuffer) {
+        if key < 0 || key as usize >= MAX_KEYS {
+            return;
+        }
+        let index = key as usize;
+
+        if down {
+            if self.down[index] {
+                // ignore auto repeats
+                return;
+            }
+            self.down[index] = true;
+            if let Some(ref binding) = self.bindings[index] {
+                if binding.starts_with('+') {
+                    commands.add_text(&format!("{} {}", binding, key));
+                } else {
+                    commands.add_text(binding);
+                }
+            }
+        } else {
+            if !self.down[index] {
+                return;
+            }
+            self.down[index] = false;
+            if let Some(ref binding) = self.bindings[index] {
+                if binding.starts_with('+') {
+                    commands.add_text(&format!("-{} {}", &binding[1..], key));
+                }
+            }
+        }
+    }
+
+    /// Releases all keys that are held down, e.g. when the window loses focus.
+    pub fn clear_states(&mut self, commands : &mut CommandBuffer) {
+        for key in 0..MAX_KEYS {
+            if self.down[key] {
+                self.key_event(key as i32, false, commands);
+            }
+        }
+    }
+
+    /// Implements the bind command.
+    pub fn bind_cmd(&mut self, args : &[String]) {
+        if args.len() < 2 {
+            println!("bind <key> [command] : attach a command to a key");
+            return;
+        }
+        let key = match string_to_keynum(&args[1]) {
+            Some(key) => key,
+            None => {
+                println!("\"{}\" isn't a valid key", args[1]);
+                return;
+            }
+        };
+
+        if args.len() == 2 {
+            match self.binding(key) {
+                Some(binding) => println!("\"{}\" = \"{}\"", args[1], binding),
+                None => println!("\"{}\" is not bound", args[1]),
+            }
+            return;
+        }
+
+        let binding = args[2..].join(" ");
+        self.set_binding(key, Some(&binding));
+    }
+
+    /// Implements the unbind command.
+    pub fn unbind_cmd(&mut self, args : &[String]) {
+        if args.len() != 2 {
+            println!("unbind <key> : remove commands from a key");
+            return;
+        }
+        match string_to_keynum(&args[1]) {
+            Some(key) => self.set_binding(key, None),
+            None => println!("\"{}\" isn't a valid key", args[1]),
+        }
+    }
+
+    /// Implements the unbindall command.
+    pub fn unbindall_cmd(&mut self) {
+        for binding in &mut self.bindings {
+            *binding = None;
+        }
+    }
+}
+
+/// Returns the key number of a single ASCII character or a key name like
+/// MOUSE1 or F5.
+pub fn string_to_keynum(name : &str) -> Option<i32> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c.is_ascii() {
+            return Some(c.to_ascii_lowercase() as i32);
+        }
+    }
+    KEY_NAMES.iter()
+        .find(|&&(key_name, _)| key_name.eq_ignore_ascii_case(name))
+        .map(|&(_, key)| key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_names() {
+        assert_eq!(string_to_keynum("W"), Some('w' as i32));
+        assert_eq!(string_to_keynum("mouse1"), Some(K_MOUSE1));
+        assert_eq!(string_to_keynum("F12"), Some(K_F1 + 11));
+        assert_eq!(string_to_keynum("nokey"), None);
+    }
+
+    #[test]
+    fn button_bindings() {
+        let mut keys = KeyBindings::new();
+        let mut commands = CommandBuffer::new();
+        keys.key_event('w' as i32, true, &mut commands);
+        keys.key_event('w' as i32, true, &mut commands);
+        keys.key_event('w' as i32, false, &mut commands);
+        keys.key_event('1' as i32, true, &mut commands);
+        keys.key_event('1' as i32, false, &mut commands);
+        assert_eq!(commands.next_command().unwrap(), "+forward 119");
+        assert_eq!(commands.next_command().unwrap(), "-forward 119");
+        assert_eq!(commands.next_command().unwrap(), "impulse 1");
+        assert!(commands.next_command().is_none());
+    }
+
+    #[test]
+    fn bind_and_unbind() {
+        let mut keys = KeyBindings::new();
+        keys.bind_cmd(&["bind".to_string(), "x".to_string(), "+jump".to_string()]);
+        assert_eq!(keys.binding('x' as i32), Some("+jump"));
+        keys.unbind_cmd(&["unbind".to_string(), "x".to_string()]);
+        assert_eq!(keys.binding('x' as i32), None);
+        keys.unbindall_cmd();
+        assert_eq!(keys.binding('w' as i32), None);
+
+        // releasing a key that was pressed before unbinding sends nothing
+        let mut commands = CommandBuffer::new();
+        keys.key_event(K_MOUSE1, true, &mut commands);
+        keys.clear_states(&mut commands);
+        assert!(commands.next_command().is_none());
+    }
+}
@@ -1,9 +1,83 @@
 #![crate_type= "lib"]
 
 extern crate rquake_common;
+extern crate rquake_fs;
+extern crate riff_wave;
 
-pub use snd::SoundEngine;
+pub use snd::{SoundEngine, SfxCache, DmaBuffer};
+pub use snd_wave::WaveFileSoundEngine;
 pub use host::Host;
+pub use screen::ImageFormat;
+pub use cvar::{Cvar, CvarList};
+pub use server::Server;
+pub use progs::{Edict, Fields, Progs, StringTable};
+pub use world::{AreaNode, AreaType, MoveClip};
+pub use model::BrushModel;
+pub use sv_phys::PhysicsParams;
+pub use cl_input::{Input, UserCmd};
+pub use cl_pred::Prediction;
+pub use keys::KeyBindings;
+pub use msg::{MsgReader, SizeBuf};
+pub use net::{Net, NetDriver, NetMessage, NetSocket, PlayerInfo, ServerStatus};
+pub use net_loop::LoopDriver;
+pub use net_dgrm::{DatagramDriver, FoundServer, ServerInfo, ServerSearch, query_server, query_server_info, search, search_lan};
+pub use savegame::{SaveGame, SaveError};
+pub use client::{ClientState, Entity, Score};
+pub use view::{RefDef, View};
+pub use r_part::{Particle, ParticleType, Particles};
+pub use r_main::{Renderer, RenderParams};
+pub use r_model::{RenderNode, Surface, TextureAnim, WorldModel};
+pub use r_light::{DLight, DLights};
+pub use r_surf::{SurfaceCache, SurfaceParams};
+pub use r_sky::Sky;
+pub use draw::{Draw, Pic};
+pub use sbar::Sbar;
+pub use sv_main::SpawnParams;
+pub use host_cmd::CommandParams;
+pub use pr_exec::ProgsDat;
+
+pub mod progdefs;
+pub mod server;
+pub mod trace;
+pub mod protocol;
 
 mod host;
 mod snd;
+mod snd_mix;
+mod snd_mem;
+mod snd_wave;
+mod cvar;
+mod cd_audio;
+mod cmd;
+mod screen;
+mod progs;
+mod pr_exec;
+mod pr_cmds;
+mod world;
+mod model;
+mod sv_phys;
+mod sv_main;
+mod sv_user;
+mod sv_move;
+mod host_cmd;
+mod cl_input;
+mod cl_main;
+mod cl_pred;
+mod cl_demo;
+mod client;
+mod view;
+mod r_part;
+mod r_main;
+mod r_model;
+mod r_light;
+mod r_surf;
+mod r_draw;
+mod r_sky;
+mod draw;
+mod sbar;
+mod keys;
+mod msg;
+mod net;
+mod net_loop;
+mod net_dgrm;
+mod savegame;
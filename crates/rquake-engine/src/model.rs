@@ -0,0 +1,122 @@
+#![warn(missing_docs)]
+
+//! Brush models used for collision detection.
+//!
+//! Original source can be found in model.c
+
+use std::rc::Rc;
+
+use rquake_common::Vec3;
+use rquake_fs::BspFile;
+use trace::{ClipNode, Hull, Plane};
+
+/// Number of clipping hulls: point sized, player sized and shambler sized.
+pub const MAX_HULLS : usize = 3;
+
+/// The world or a brush entity like a door.
+pub struct BrushModel {
+    /// Bounding box.
+    pub mins : Vec3,
+    /// Bounding box.
+    pub maxs : Vec3,
+    /// Clipping hulls for the different box sizes.
+    pub hulls : [Hull; MAX_HULLS],
+}
+
+impl BrushModel {
+    /// Creates the world model and the inline brush models *1, *2, ... of a map.
+    pub fn from_bsp(bsp : &BspFile) -> Vec<BrushModel> {
+        let planes : Rc<Vec<Plane>> = Rc::new(bsp.planes.iter().map(|plane| Plane {
+            normal : plane.normal,
+            dist : plane.dist,
+            kind : plane.kind,
+        }).collect());
+
+        // hull 0 is the drawing tree with leafs replaced by their contents
+        let hull0_nodes : Rc<Vec<ClipNode>> = Rc::new(bsp.nodes.iter().map(|node| {
+            let mut children = [0; 2];
+            for (child, &num) in children.iter_mut().zip(node.children.iter()) {
+                *child = if num < 0 {
+                    bsp.leafs.get((-1 - num as i32) as usize).map_or(0, |leaf| leaf.contents)
+                } else {
+                    num as i32
+                };
+            }
+            ClipNode { plane : node.plane as usize, children : children }
+        }).collect());
+
+        let clipnodes : Rc<Vec<ClipNode>> = Rc::new(bsp.clipnodes.iter().map(|node| ClipNode {
+            plane : node.plane as usize,
+            children : [node.children[0] as i32, node.children[1] as i32],
+        }).collect());
+
+        bsp.models.iter().map(|model| {
+            let hull = |nodes : &Rc<Vec<ClipNode>>, first : i32, clip_mins : Vec3, clip_maxs : Vec3| Hull {
+                clipnodes : nodes.clone(),
+                planes : planes.clone(),
+                first_clipnode : first,
+                clip_mins : clip_mins,
+                clip_maxs : clip_maxs,
+            };
+            BrushModel {
+                mins : model.mins,
+                maxs : model.maxs,
+                hulls : [hull(&hull0_nodes, model.headnode[0], [0.0; 3], [0.0; 3]),
+                         hull(&clipnodes, model.headnode[1], [-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]),
+                         hull(&clipnodes, model.headnode[2], [-32.0, -32.0, -24.0], [32.0, 32.0, 64.0])],
+            }
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_fs::{BspClipNode, BspLeaf, BspModel, BspNode, BspPlane};
+    use trace::{CONTENTS_EMPTY, CONTENTS_SOLID, CONTENTS_WATER};
+
+    fn leaf(contents : i32) -> BspLeaf {
+        BspLeaf {
+            contents : contents,
+            visofs : -1,
+            mins : [0; 3],
+            maxs : [0; 3],
+            first_marksurface : 0,
+            num_marksurfaces : 0,
+            ambient_level : [0; 4],
+        }
+    }
+
+    #[test]
+    fn hulls_from_bsp() {
+        let bsp = BspFile {
+            entities : String::new(),
+            planes : vec![BspPlane { normal : [0.0, 0.0, 1.0], dist : 0.0, kind : 2 },
+                          BspPlane { normal : [0.0, 0.0, 1.0], dist : 24.0, kind : 2 }],
+            visibility : Vec::new(),
+            nodes : vec![BspNode { plane : 0, children : [-2, -3], mins : [0; 3], maxs : [0; 3], first_face : 0, num_faces : 0 }],
+            clipnodes : vec![BspClipNode { plane : 1, children : [-1, -2] }],
+            leafs : vec![leaf(CONTENTS_SOLID), leaf(CONTENTS_EMPTY), leaf(CONTENTS_WATER)],
+            models : vec![BspModel {
+                mins : [-65.0; 3],
+                maxs : [65.0; 3],
+                origin : [0.0; 3],
+                headnode : [0; 4],
+                visleafs : 2,
+                first_face : 0,
+                num_faces : 0,
+            }],
+            ..BspFile::default()
+        };
+
+        let models = BrushModel::from_bsp(&bsp);
+        assert_eq!(models.len(), 1);
+        let hulls = &models[0].hulls;
+        assert_eq!(hulls[0].point_contents(0, [0.0, 0.0, 10.0]), CONTENTS_EMPTY);
+        assert_eq!(hulls[0].point_contents(0, [0.0, 0.0, -10.0]), CONTENTS_WATER);
+        assert_eq!(hulls[1].point_contents(0, [0.0, 0.0, 10.0]), CONTENTS_SOLID);
+        assert_eq!(hulls[1].clip_mins, [-16.0, -16.0, -24.0]);
+        assert_eq!(hulls[2].clip_maxs, [32.0, 32.0, 64.0]);
+        assert!(Rc::ptr_eq(&hulls[1].clipnodes, &hulls[2].clipnodes));
+    }
+}
@@ -0,0 +1,264 @@
+#![warn(missing_docs)]
+
+//! Message buffers with the primitives of the network protocol. All
+//! values are little endian.
+//!
+//! Original source can be found in common.c
+
+use rquake_common::Vec3;
+
+/// Longest string that can be read from a message.
+const MAX_STRING : usize = 2048;
+
+/// A message that is being written.
+pub struct SizeBuf {
+    /// Message data.
+    pub data : Vec<u8>,
+    maxsize : usize,
+    /// If false an overflow clears the buffer and prints an error,
+    /// otherwise the overflow is only recorded.
+    pub allow_overflow : bool,
+    /// Set if data has been dropped because the buffer was full.
+    pub overflowed : bool,
+}
+
+impl SizeBuf {
+    /// Creates an empty buffer holding at most `maxsize` bytes.
+    pub fn new(maxsize : usize) -> SizeBuf {
+        SizeBuf {
+            data : Vec::with_capacity(maxsize),
+            maxsize : maxsize,
+            allow_overflow : false,
+            overflowed : false,
+        }
+    }
+
+    /// Removes all data.
+    pub fn clear(&mut self) {
+        self.data.clear();
+        self.overflowed = false;
+    }
+
+    /// Number of bytes written.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns true if nothing has been written.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Maximum number of bytes.
+    pub fn maxsize(&self) -> usize {
+        self.maxsize
+    }
+
+    /// Appends raw bytes. Everything is dropped if they don't fit.
+    pub fn write(&mut self, data : &[u8]) {
+        if self.data.len() + data.len() > self.maxsize {
+            if !self.allow_overflow {
+                println!("SZ_GetSpace: overflow without allowoverflow set");
+            } else if data.len() > self.maxsize {
+                println!("SZ_GetSpace: {} is > full buffer size", data.len());
+            } else {
+                println!("SZ_GetSpace: overflow");
+            }
+            self.data.clear();
+            self.overflowed = true;
+            if data.len() > self.maxsize {
+                return;
+            }
+        }
+        self.data.extend_from_slice(data);
+    }
+
+    /// Writes a signed byte.
+    pub fn write_char(&mut self, c : i32) {
+        self.write(&[c as i8 as u8]);
+    }
+
+    /// Writes an unsigned byte.
+    pub fn write_byte(&mut self, c : i32) {
+        self.write(&[c as u8]);
+    }
+
+    /// Writes a 16 bit integer.
+    pub fn write_short(&mut self, c : i32) {
+        let c = c as i16;
+        self.write(&[c as u8, (c >> 8) as u8]);
+    }
+
+    /// Writes a 32 bit integer.
+    pub fn write_long(&mut self, c : i32) {
+        self.write(&[c as u8, (c >> 8) as u8, (c >> 16) as u8, (c >> 24) as u8]);
+    }
+
+    /// Writes a 32 bit float.
+    pub fn write_float(&mut self, f : f32) {
+        self.write_long(f.to_bits() as i32);
+    }
+
+    /// Writes a zero terminated string. Characters above 255 are replaced.
+    pub fn write_string(&mut self, s : &str) {
+        let bytes : Vec<u8> = s.chars().map(|c| if (c as u32) < 256 { c as u32 as u8 } else { b'?' })
+            .chain(Some(0)).collect();
+        self.write(&bytes);
+    }
+
+    /// Writes a coordinate with 1/8 unit precision.
+    pub fn write_coord(&mut self, f : f32) {
+        self.write_short((f * 8.0) as i32);
+    }
+
+    /// Writes an angle in degrees with 1/256 turn precision.
+    pub fn write_angle(&mut self, f : f32) {
+        self.write_byte(((f * 256.0 / 360.0) as i32) & 255);
+    }
+
+    /// Writes three coordinates.
+    pub fn write_vector(&mut self, v : &Vec3) {
+        for &c in v {
+            self.write_coord(c);
+        }
+    }
+}
+
+/// Reads the primitives from a received message. Reading past the end
+/// sets `badread` and returns -1.
+pub struct MsgReader<'a> {
+    data : &'a [u8],
+    pos : usize,
+    /// Set if a read went past the end of the message.
+    pub badread : bool,
+}
+
+impl<'a> MsgReader<'a> {
+    /// Starts reading a message.
+    pub fn new(data : &'a [u8]) -> MsgReader<'a> {
+        MsgReader {
+            data : data,
+            pos : 0,
+            badread : false,
+        }
+    }
+
+    /// Returns true if the whole message has been read.
+    pub fn at_end(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    /// Number of bytes read.
+    pub fn pos(&self) -> usize {
+        self.pos
+    }
+
+    fn bytes(&mut self, count : usize) -> Option<&'a [u8]> {
+        if self.pos + count > self.data.len() {
+            self.badread = true;
+            return None;
+        }
+        let bytes = &self.data[self.pos..self.pos + count];
+        self.pos += count;
+        Some(bytes)
+    }
+
+    /// Reads a signed byte.
+    pub fn read_char(&mut self) -> i32 {
+        self.bytes(1).map_or(-1, |b| b[0] as i8 as i32)
+    }
+
+    /// Reads an unsigned byte.
+    pub fn read_byte(&mut self) -> i32 {
+        self.bytes(1).map_or(-1, |b| b[0] as i32)
+    }
+
+    /// Reads a 16 bit integer.
+    pub fn read_short(&mut self) -> i32 {
+        self.bytes(2).map_or(-1, |b| (b[0] as u16 | (b[1] as u16) << 8) as i16 as i32)
+    }
+
+    /// Reads a 32 bit integer.
+    pub fn read_long(&mut self) -> i32 {
+        self.bytes(4).map_or(-1, |b| (b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24) as i32)
+    }
+
+    /// Reads a 32 bit float.
+    pub fn read_float(&mut self) -> f32 {
+        self.bytes(4).map_or(-1.0, |b| f32::from_bits(b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24))
+    }
+
+    /// Reads a zero terminated string.
+    pub fn read_string(&mut self) -> String {
+        let mut s = String::new();
+        loop {
+            let c = self.read_byte();
+            if c == -1 || c == 0 {
+                break;
+            }
+            s.push(c as u8 as char);
+            if s.len() >= MAX_STRING - 1 {
+                break;
+            }
+        }
+        s
+    }
+
+    /// Reads a coordinate.
+    pub fn read_coord(&mut self) -> f32 {
+        self.read_short() as f32 * (1.0 / 8.0)
+    }
+
+    /// Reads an angle in degrees.
+    pub fn read_angle(&mut self) -> f32 {
+        self.read_char() as f32 * (360.0 / 256.0)
+    }
+
+    /// Reads three coordinates.
+    pub fn read_vector(&mut self) -> Vec3 {
+        [self.read_coord(), self.read_coord(), self.read_coord()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn primitives() {
+        let mut buf = SizeBuf::new(64);
+        buf.write_char(-5);
+        buf.write_byte(200);
+        buf.write_short(-1234);
+        buf.write_long(0x12345678);
+        buf.write_float(1.5);
+        buf.write_string("hi\u{80}");
+        buf.write_coord(-10.125);
+        buf.write_angle(90.0);
+        assert_eq!(&buf.data[..4], &[251, 200, 0x2e, 0xfb]);
+
+        let mut msg = MsgReader::new(&buf.data);
+        assert_eq!(msg.read_char(), -5);
+        assert_eq!(msg.read_byte(), 200);
+        assert_eq!(msg.read_short(), -1234);
+        assert_eq!(msg.read_long(), 0x12345678);
+        assert_eq!(msg.read_float(), 1.5);
+        assert_eq!(msg.read_string(), "hi\u{80}");
+        assert_eq!(msg.read_coord(), -10.125);
+        assert_eq!(msg.read_angle(), 90.0);
+        assert!(msg.at_end() && !msg.badread);
+        assert_eq!(msg.read_byte(), -1);
+        assert!(msg.badread);
+    }
+
+    #[test]
+    fn overflow() {
+        let mut buf = SizeBuf::new(4);
+        buf.write_long(1);
+        assert!(!buf.overflowed);
+        buf.allow_overflow = true;
+        buf.write_short(2);
+        assert!(buf.overflowed);
+        assert_eq!(buf.data, vec![2, 0]);
+    }
+}
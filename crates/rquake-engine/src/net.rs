@@ -0,0 +1,183 @@
+#![warn(missing_docs)]
+
+//! Network drivers for the connections between client and server.
+//!
+//! Original source can be found in net.h and net_main.c
+
+use cvar::CvarList;
+
+/// Largest message that can be sent reliably.
+pub const NET_MAXMESSAGE : usize = 8192;
+
+/// Largest message the server writes for one client.
+pub const MAX_MSGLEN : usize = 8000;
+
+/// Default UDP port of servers.
+pub const DEFAULT_NET_PORT : u16 = 26000;
+
+/// A message received from the other side of a connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetMessage {
+    /// Message that is resent until it's acknowledged.
+    Reliable(Vec<u8>),
+    /// Message that may be lost.
+    Unreliable(Vec<u8>),
+    /// The connection has been closed or timed out.
+    Disconnected,
+}
+
+/// A player as seen by server queries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlayerInfo {
+    /// Name of the player.
+    pub name : String,
+    /// Shirt color in the high and pants color in the low 4 bits.
+    pub colors : i32,
+    /// Frags of the player.
+    pub frags : i32,
+    /// Seconds since the player connected.
+    pub connect_time : i32,
+    /// Network address of the player.
+    pub address : String,
+}
+
+/// The state of a server that is answered to server queries.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerStatus {
+    /// Name of the server.
+    pub hostname : String,
+    /// Name of the running map.
+    pub map : String,
+    /// Number of player slots.
+    pub max_players : usize,
+    /// Connected players.
+    pub players : Vec<PlayerInfo>,
+    /// Names and values of the cvars of the game rules.
+    pub rules : Vec<(String, String)>,
+}
+
+/// One side of a connection. All methods take the current time in seconds
+/// for resending lost messages and timeouts.
+pub trait NetSocket {
+    /// Address of the other side.
+    fn address(&self) -> String;
+
+    /// Returns the next received message or `None` if there is none.
+    fn get_message(&mut self, time : f64) -> Option<NetMessage>;
+
+    /// Sends a reliable message. Only one reliable message can be in flight,
+    /// returns false if the message could not be sent.
+    fn send_message(&mut self, data : &[u8], time : f64) -> bool;
+
+    /// Sends a message that may be lost.
+    fn send_unreliable_message(&mut self, data : &[u8], time : f64) -> bool;
+
+    /// Returns true if the last reliable message has been acknowledged.
+    fn can_send_message(&mut self, time : f64) -> bool;
+
+    /// Closes the connection.
+    fn close(&mut self);
+
+    /// Returns the estimated seconds until a message is answered by the
+    /// other side, 0 if nothing has been measured.
+    fn round_trip_time(&self) -> f64 {
+        0.0
+    }
+}
+
+/// A kind of connection, e.g. loopback or UDP.
+pub trait NetDriver {
+    /// Name of the driver.
+    fn name(&self) -> &'static str;
+
+    /// Starts or stops accepting new connections.
+    fn listen(&mut self, state : bool);
+
+    /// Connects to a server. Returns `None` if the driver can't handle the
+    /// address or the server didn't accept the connection.
+    fn connect(&mut self, host : &str, time : f64) -> Option<Box<NetSocket>>;
+
+    /// Returns a new connection from a client if there is one.
+    fn check_new_connections(&mut self, time : f64) -> Option<Box<NetSocket>>;
+
+    /// Limits the number of connections, further clients are rejected.
+    fn set_max_connections(&mut self, _max_connections : usize) {
+    }
+
+    /// Sets the server state for answering queries, `None` if no server
+    /// is running.
+    fn set_status(&mut self, _status : Option<&ServerStatus>) {
+    }
+
+    /// Sets the seconds without a message until a connection is closed.
+    fn set_message_timeout(&mut self, _timeout : f64) {
+    }
+}
+
+/// All network drivers, tried in order.
+pub struct Net {
+    drivers : Vec<Box<NetDriver>>,
+}
+
+impl Net {
+    /// Creates the network layer with the given drivers.
+    pub fn new(drivers : Vec<Box<NetDriver>>) -> Net {
+        Net {
+            drivers : drivers,
+        }
+    }
+
+    /// Starts or stops accepting new connections on all drivers.
+    pub fn listen(&mut self, state : bool) {
+        for driver in &mut self.drivers {
+            driver.listen(state);
+        }
+    }
+
+    /// Connects to a server with the first driver that accepts the address.
+    pub fn connect(&mut self, host : &str, time : f64) -> Option<Box<NetSocket>> {
+        for driver in &mut self.drivers {
+            if let Some(socket) = driver.connect(host, time) {
+                return Some(socket);
+            }
+        }
+        None
+    }
+
+    /// Limits the number of connections of every driver.
+    pub fn set_max_connections(&mut self, max_connections : usize) {
+        for driver in &mut self.drivers {
+            driver.set_max_connections(max_connections);
+        }
+    }
+
+    /// Sets the server state that is answered to queries by every driver.
+    pub fn set_status(&mut self, status : Option<&ServerStatus>) {
+        for driver in &mut self.drivers {
+            driver.set_status(status);
+        }
+    }
+
+    /// Sets the connection timeout of every driver.
+    pub fn set_message_timeout(&mut self, timeout : f64) {
+        for driver in &mut self.drivers {
+            driver.set_message_timeout(timeout);
+        }
+    }
+
+    /// Returns a new connection from any driver.
+    pub fn check_new_connections(&mut self, time : f64) -> Option<Box<NetSocket>> {
+        for driver in &mut self.drivers {
+            if let Some(socket) = driver.check_new_connections(time) {
+                return Some(socket);
+            }
+        }
+        None
+    }
+}
+
+/// Registers the network cvars.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("net_messagetimeout", "300", false, false);
+    cvars.register("hostname", "UNNAMED", false, false);
+}
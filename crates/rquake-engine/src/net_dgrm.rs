@@ -0,0 +1,1170 @@
+#![warn(missing_docs)]
+
+//! UDP driver with the datagram protocol of NetQuake. Reliable messages are
+//! split into numbered datagrams that are resent until they are acknowledged,
+//! unreliable messages are numbered to drop stale ones. New connections are
+//! negotiated with control packets on the listening port, afterwards every
+//! client talks to its own server port. Servers also answer queries for
+//! their name, players and rules, which is used to find servers on the LAN.
+//!
+//! Original source can be found in net_dgrm.c and net_udp.c
+
+use std::cell::Cell;
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::rc::Rc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use msg::{MsgReader, SizeBuf};
+use net::{NetDriver, NetMessage, NetSocket, PlayerInfo, ServerStatus, DEFAULT_NET_PORT, NET_MAXMESSAGE};
+
+/// Largest payload of one datagram.
+pub const MAX_DATAGRAM : usize = 1024;
+
+/// Version of the datagram protocol.
+pub const NET_PROTOCOL_VERSION : i32 = 3;
+
+const NET_HEADERSIZE : usize = 8;
+
+const NETFLAG_LENGTH_MASK : u32 = 0x0000_ffff;
+const NETFLAG_DATA : u32 = 0x0001_0000;
+const NETFLAG_ACK : u32 = 0x0002_0000;
+const NETFLAG_EOM : u32 = 0x0008_0000;
+const NETFLAG_UNRELIABLE : u32 = 0x0010_0000;
+const NETFLAG_CTL : u32 = 0x8000_0000;
+
+const CCREQ_CONNECT : i32 = 0x01;
+const CCREQ_SERVER_INFO : i32 = 0x02;
+const CCREQ_PLAYER_INFO : i32 = 0x03;
+const CCREQ_RULE_INFO : i32 = 0x04;
+const CCREP_ACCEPT : i32 = 0x81;
+const CCREP_REJECT : i32 = 0x82;
+const CCREP_SERVER_INFO : i32 = 0x83;
+const CCREP_PLAYER_INFO : i32 = 0x84;
+const CCREP_RULE_INFO : i32 = 0x85;
+
+/// Seconds until an unacknowledged datagram is sent again.
+const RESEND_TIME : f64 = 1.0;
+/// Seconds to wait for the answer to a connection request.
+const CONNECT_WAIT : f64 = 2.5;
+/// Seconds in which a repeated connection request gets the same answer.
+const DUPLICATE_CONNECT_TIME : f64 = 2.0;
+/// Number of times a query is sent before giving up.
+const QUERY_TRIES : u32 = 3;
+
+/// Answer of a server to the server info query.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+    /// Address of the server as it knows it.
+    pub address : String,
+    /// Name of the server.
+    pub hostname : String,
+    /// Name of the running map.
+    pub map : String,
+    /// Number of connected players.
+    pub players : usize,
+    /// Number of player slots.
+    pub max_players : usize,
+    /// Version of the datagram protocol.
+    pub protocol : i32,
+}
+
+/// A server found by a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FoundServer {
+    /// Address the answer came from, used to connect.
+    pub address : SocketAddr,
+    /// The answer to the server info query.
+    pub info : ServerInfo,
+    /// Seconds until the answer arrived.
+    pub ping : f64,
+}
+
+/// Driver for UDP connections.
+pub struct DatagramDriver {
+    socket : Option<UdpSocket>,
+    port : u16,
+    max_connections : usize,
+    active : Rc<Cell<usize>>,
+    recent : Vec<(SocketAddr, u16, f64)>,
+    status : Option<ServerStatus>,
+    message_timeout : Rc<Cell<f64>>,
+}
+
+impl DatagramDriver {
+    /// Creates the driver. Servers listen on `port`, 0 picks a free port.
+    pub fn new(port : u16, max_connections : usize) -> DatagramDriver {
+        DatagramDriver {
+            socket : None,
+            port : port,
+            max_connections : max_connections,
+            active : Rc::new(Cell::new(0)),
+            recent : Vec::new(),
+            status : None,
+            message_timeout : Rc::new(Cell::new(300.0)),
+        }
+    }
+
+    /// Port of the listening socket or the configured port.
+    pub fn port(&self) -> u16 {
+        self.socket.as_ref()
+            .and_then(|socket| socket.local_addr().ok())
+            .map_or(self.port, |addr| addr.port())
+    }
+
+    /// Answers a control packet on the listening socket.
+    fn send_control(socket : &UdpSocket, buf : &SizeBuf, addr : &SocketAddr) {
+        let mut packet = buf.data.clone();
+        let control = NETFLAG_CTL | (packet.len() as u32 & NETFLAG_LENGTH_MASK);
+        packet[..4].copy_from_slice(&big_long(control));
+        if let Err(err) = socket.send_to(&packet, addr) {
+            println!("Failed to send control packet to {}: {}", addr, err);
+        }
+    }
+
+    fn reject(socket : &UdpSocket, addr : &SocketAddr, reason : &str) {
+        let mut buf = SizeBuf::new(MAX_DATAGRAM);
+        buf.write_long(0);
+        buf.write_byte(CCREP_REJECT);
+        buf.write_string(reason);
+        DatagramDriver::send_control(socket, &buf, addr);
+    }
+
+    fn accept(socket : &UdpSocket, addr : &SocketAddr, port : u16) {
+        let mut buf = SizeBuf::new(MAX_DATAGRAM);
+        buf.write_long(0);
+        buf.write_byte(CCREP_ACCEPT);
+        buf.write_long(port as i32);
+        DatagramDriver::send_control(socket, &buf, addr);
+    }
+
+    /// Answers the server, player and rule queries. Nothing is sent back
+    /// for unknown players or requests from other games.
+    fn answer_query(socket : &UdpSocket, status : &ServerStatus, command : i32, msg : &mut MsgReader, addr : &SocketAddr) {
+        let mut buf = SizeBuf::new(MAX_DATAGRAM);
+        buf.write_long(0);
+        match command {
+            CCREQ_SERVER_INFO => {
+                if msg.read_string() != "QUAKE" || msg.read_byte() != NET_PROTOCOL_VERSION {
+                    return;
+                }
+                let address = local_address(socket, addr);
+                buf.write_byte(CCREP_SERVER_INFO);
+                buf.write_string(&address);
+                buf.write_string(&status.hostname);
+                buf.write_string(&status.map);
+                buf.write_byte(status.players.len() as i32);
+                buf.write_byte(status.max_players as i32);
+                buf.write_byte(NET_PROTOCOL_VERSION);
+            }
+            CCREQ_PLAYER_INFO => {
+                let number = msg.read_byte();
+                let player = match status.players.get(number as usize) {
+                    Some(player) => player,
+                    None => return,
+                };
+                buf.write_byte(CCREP_PLAYER_INFO);
+                buf.write_byte(number);
+                buf.write_string(&player.name);
+                buf.write_long(player.colors);
+                buf.write_long(player.frags);
+                buf.write_long(player.connect_time);
+                buf.write_string(&player.address);
+            }
+            CCREQ_RULE_INFO => {
+                // the rule after the given one, the first for an empty name
+                let prev = msg.read_string();
+                let next = if prev.is_empty() {
+                    status.rules.first()
+                } else {
+                    status.rules.iter().skip_while(|&&(ref name, _)| *name != prev).nth(1)
+                };
+                buf.write_byte(CCREP_RULE_INFO);
+                if let Some(&(ref name, ref value)) = next {
+                    buf.write_string(name);
+                    buf.write_string(value);
+                }
+            }
+            _ => return,
+        }
+        DatagramDriver::send_control(socket, &buf, addr);
+    }
+}
+
+impl NetDriver for DatagramDriver {
+    fn name(&self) -> &'static str {
+        "Datagram"
+    }
+
+    fn listen(&mut self, state : bool) {
+        if !state {
+            self.socket = None;
+            return;
+        }
+        if self.socket.is_some() {
+            return;
+        }
+        match open_socket(self.port) {
+            Some(socket) => self.socket = Some(socket),
+            None => println!("Failed to listen on port {}", self.port),
+        }
+    }
+
+    fn connect(&mut self, host : &str, time : f64) -> Option<Box<NetSocket>> {
+        let server_addr = match resolve(host) {
+            Some(addr) => addr,
+            None => return None,
+        };
+        let socket = match open_socket(0) {
+            Some(socket) => socket,
+            None => return None,
+        };
+
+        // send the connection request
+        let mut request = SizeBuf::new(MAX_DATAGRAM);
+        request.write_long(0);
+        request.write_byte(CCREQ_CONNECT);
+        request.write_string("QUAKE");
+        request.write_byte(NET_PROTOCOL_VERSION);
+        let control = NETFLAG_CTL | (request.len() as u32 & NETFLAG_LENGTH_MASK);
+        request.data[..4].copy_from_slice(&big_long(control));
+
+        let mut packet = [0; MAX_DATAGRAM + NET_HEADERSIZE];
+        let mut reply = None;
+        for _ in 0..3 {
+            if let Err(err) = socket.send_to(&request.data, server_addr) {
+                println!("Network Error: {}", err);
+                return None;
+            }
+
+            let start = Instant::now();
+            while reply.is_none() && duration_secs(start.elapsed()) < CONNECT_WAIT {
+                match socket.recv_from(&mut packet) {
+                    Ok((len, addr)) => {
+                        // is it from the right place?
+                        if addr == server_addr && len >= 4 {
+                            let control = read_big_long(&packet[..4]);
+                            if control & !NETFLAG_LENGTH_MASK == NETFLAG_CTL && (control & NETFLAG_LENGTH_MASK) as usize == len {
+                                reply = Some(packet[..len].to_vec());
+                            }
+                        }
+                    }
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => sleep(Duration::from_millis(1)),
+                    Err(err) => {
+                        println!("Network Error: {}", err);
+                        return None;
+                    }
+                }
+            }
+            if reply.is_some() {
+                break;
+            }
+            println!("still trying...");
+        }
+
+        let reply = match reply {
+            Some(reply) => reply,
+            None => {
+                println!("No Response");
+                return None;
+            }
+        };
+        let mut msg = MsgReader::new(&reply);
+        msg.read_long();
+        match msg.read_byte() {
+            CCREP_ACCEPT => {
+                let mut addr = server_addr;
+                addr.set_port(msg.read_long() as u16);
+                println!("Connection accepted");
+                Some(Box::new(DatagramSocket::new(socket, addr, time, self.message_timeout.clone(), None)))
+            }
+            CCREP_REJECT => {
+                print!("{}", msg.read_string());
+                None
+            }
+            _ => {
+                println!("Bad Response");
+                None
+            }
+        }
+    }
+
+    fn check_new_connections(&mut self, time : f64) -> Option<Box<NetSocket>> {
+        let listen_socket = match self.socket {
+            Some(ref socket) => socket,
+            None => return None,
+        };
+
+        let mut packet = [0; MAX_DATAGRAM + NET_HEADERSIZE];
+        let (len, addr) = match listen_socket.recv_from(&mut packet) {
+            Ok(result) => result,
+            Err(_) => return None,
+        };
+        if len < 4 {
+            return None;
+        }
+        let control = read_big_long(&packet[..4]);
+        if control & !NETFLAG_LENGTH_MASK != NETFLAG_CTL || (control & NETFLAG_LENGTH_MASK) as usize != len {
+            return None;
+        }
+
+        let mut msg = MsgReader::new(&packet[..len]);
+        msg.read_long();
+        let command = msg.read_byte();
+        if command != CCREQ_CONNECT {
+            if let Some(ref status) = self.status {
+                DatagramDriver::answer_query(listen_socket, status, command, &mut msg, &addr);
+            }
+            return None;
+        }
+        if msg.read_string() != "QUAKE" {
+            return None;
+        }
+        if msg.read_byte() != NET_PROTOCOL_VERSION {
+            DatagramDriver::reject(listen_socket, &addr, "Incompatible version.\n");
+            return None;
+        }
+
+        // is this a duplicate connection request?
+        self.recent.retain(|&(_, _, accept_time)| time - accept_time < DUPLICATE_CONNECT_TIME);
+        if let Some(&(_, port, _)) = self.recent.iter().find(|&&(recent_addr, _, _)| recent_addr == addr) {
+            DatagramDriver::accept(listen_socket, &addr, port);
+            return None;
+        }
+
+        if self.active.get() >= self.max_connections {
+            DatagramDriver::reject(listen_socket, &addr, "Server is full.\n");
+            return None;
+        }
+
+        // allocate a network socket for the client
+        let socket = match open_socket(0) {
+            Some(socket) => socket,
+            None => {
+                DatagramDriver::reject(listen_socket, &addr, "Server is full.\n");
+                return None;
+            }
+        };
+        let port = match socket.local_addr() {
+            Ok(local) => local.port(),
+            Err(_) => return None,
+        };
+
+        // send him back the info about the server connection he has been allocated
+        DatagramDriver::accept(listen_socket, &addr, port);
+        self.recent.push((addr, port, time));
+        self.active.set(self.active.get() + 1);
+        Some(Box::new(DatagramSocket::new(socket, addr, time, self.message_timeout.clone(), Some(self.active.clone()))))
+    }
+
+    fn set_max_connections(&mut self, max_connections : usize) {
+        self.max_connections = max_connections;
+    }
+
+    fn set_status(&mut self, status : Option<&ServerStatus>) {
+        self.status = status.cloned();
+    }
+
+    fn set_message_timeout(&mut self, timeout : f64) {
+        self.message_timeout.set(timeout);
+    }
+}
+
+/// A UDP connection.
+struct DatagramSocket {
+    socket : Option<UdpSocket>,
+    addr : SocketAddr,
+    can_send : bool,
+    send_next : bool,
+    send_sequence : u32,
+    unreliable_send_sequence : u32,
+    ack_sequence : u32,
+    receive_sequence : u32,
+    unreliable_receive_sequence : u32,
+    send_message : Vec<u8>,
+    receive_message : Vec<u8>,
+    last_send_time : f64,
+    last_message_time : f64,
+    /// Shared with the driver so changes apply to open connections.
+    message_timeout : Rc<Cell<f64>>,
+    resent : bool,
+    round_trip_time : f64,
+    active : Option<Rc<Cell<usize>>>,
+}
+
+impl DatagramSocket {
+    fn new(socket : UdpSocket, addr : SocketAddr, time : f64, message_timeout : Rc<Cell<f64>>, active : Option<Rc<Cell<usize>>>) -> DatagramSocket {
+        DatagramSocket {
+            socket : Some(socket),
+            addr : addr,
+            can_send : true,
+            send_next : false,
+            send_sequence : 0,
+            unreliable_send_sequence : 0,
+            ack_sequence : 0,
+            receive_sequence : 0,
+            unreliable_receive_sequence : 0,
+            send_message : Vec::new(),
+            receive_message : Vec::new(),
+            last_send_time : time,
+            last_message_time : time,
+            message_timeout : message_timeout,
+            resent : false,
+            round_trip_time : 0.0,
+            active : active,
+        }
+    }
+
+    fn write(&mut self, flags : u32, sequence : u32, data : &[u8]) -> bool {
+        let mut packet = Vec::with_capacity(NET_HEADERSIZE + data.len());
+        packet.extend_from_slice(&big_long((NET_HEADERSIZE + data.len()) as u32 | flags));
+        packet.extend_from_slice(&big_long(sequence));
+        packet.extend_from_slice(data);
+        match self.socket {
+            Some(ref socket) => socket.send_to(&packet, self.addr).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Sends the current datagram of the reliable message with the given sequence.
+    fn send_chunk(&mut self, sequence : u32, time : f64) -> bool {
+        let (len, eom) = if self.send_message.len() <= MAX_DATAGRAM {
+            (self.send_message.len(), NETFLAG_EOM)
+        } else {
+            (MAX_DATAGRAM, 0)
+        };
+        let chunk = self.send_message[..len].to_vec();
+        self.send_next = false;
+        self.last_send_time = time;
+        self.write(NETFLAG_DATA | eom, sequence, &chunk)
+    }
+
+    fn send_message_next(&mut self, time : f64) -> bool {
+        let sequence = self.send_sequence;
+        self.send_sequence = self.send_sequence.wrapping_add(1);
+        self.resent = false;
+        self.send_chunk(sequence, time)
+    }
+
+    fn resend_message(&mut self, time : f64) -> bool {
+        let sequence = self.send_sequence.wrapping_sub(1);
+        self.resent = true;
+        self.send_chunk(sequence, time)
+    }
+}
+
+impl NetSocket for DatagramSocket {
+    fn address(&self) -> String {
+        self.addr.to_string()
+    }
+
+    fn get_message(&mut self, time : f64) -> Option<NetMessage> {
+        if self.socket.is_none() {
+            return Some(NetMessage::Disconnected);
+        }
+        if !self.can_send && time - self.last_send_time > RESEND_TIME {
+            self.resend_message(time);
+        }
+
+        let mut result = None;
+        let mut packet = [0; MAX_DATAGRAM + NET_HEADERSIZE];
+        loop {
+            let (len, addr) = match self.socket.as_ref().map(|socket| socket.recv_from(&mut packet)) {
+                Some(Ok(received)) => received,
+                Some(Err(ref err)) if err.kind() == ErrorKind::WouldBlock => break,
+                _ => {
+                    println!("Read error");
+                    self.close();
+                    return Some(NetMessage::Disconnected);
+                }
+            };
+
+            if addr != self.addr {
+                println!("Forged packet received from {}", addr);
+                continue;
+            }
+            if len < NET_HEADERSIZE {
+                continue;
+            }
+
+            let header = read_big_long(&packet[..4]);
+            let flags = header & !NETFLAG_LENGTH_MASK;
+            let length = ((header & NETFLAG_LENGTH_MASK) as usize).min(len);
+            if flags & NETFLAG_CTL != 0 || length < NET_HEADERSIZE {
+                continue;
+            }
+            let sequence = read_big_long(&packet[4..8]);
+            let data = &packet[NET_HEADERSIZE..length];
+
+            if flags & NETFLAG_UNRELIABLE != 0 {
+                if sequence < self.unreliable_receive_sequence {
+                    // stale datagram
+                    break;
+                }
+                self.unreliable_receive_sequence = sequence.wrapping_add(1);
+                result = Some(NetMessage::Unreliable(data.to_vec()));
+                break;
+            }
+
+            if flags & NETFLAG_ACK != 0 {
+                if sequence != self.send_sequence.wrapping_sub(1) || sequence != self.ack_sequence {
+                    // stale or duplicate ack
+                    continue;
+                }
+                self.ack_sequence = self.ack_sequence.wrapping_add(1);
+
+                // resent datagrams don't tell which copy was acknowledged
+                if !self.resent {
+                    let sample = time - self.last_send_time;
+                    self.round_trip_time = if self.round_trip_time == 0.0 {
+                        sample
+                    } else {
+                        self.round_trip_time * 0.875 + sample * 0.125
+                    };
+                }
+                let sent = self.send_message.len().min(MAX_DATAGRAM);
+                self.send_message.drain(..sent);
+                if self.send_message.is_empty() {
+                    self.can_send = true;
+                } else {
+                    self.send_next = true;
+                }
+                continue;
+            }
+
+            if flags & NETFLAG_DATA != 0 {
+                let data = data.to_vec();
+                self.write(NETFLAG_ACK, sequence, &[]);
+                if sequence != self.receive_sequence {
+                    // duplicate of a datagram that has already been received
+                    continue;
+                }
+                self.receive_sequence = self.receive_sequence.wrapping_add(1);
+                self.receive_message.extend_from_slice(&data);
+                if flags & NETFLAG_EOM != 0 {
+                    let message = self.receive_message.split_off(0);
+                    result = Some(NetMessage::Reliable(message));
+                    break;
+                }
+            }
+        }
+
+        if self.send_next {
+            self.send_message_next(time);
+        }
+
+        if result.is_some() {
+            self.last_message_time = time;
+        } else if time - self.last_message_time > self.message_timeout.get() {
+            println!("Connection to {} timed out", self.addr);
+            self.close();
+            return Some(NetMessage::Disconnected);
+        }
+        result
+    }
+
+    fn send_message(&mut self, data : &[u8], time : f64) -> bool {
+        if !self.can_send {
+            println!("SendMessage: called with canSend == false");
+            return false;
+        }
+        if data.len() > NET_MAXMESSAGE {
+            println!("SendMessage: message too big {}", data.len());
+            return false;
+        }
+        self.send_message = data.to_vec();
+        self.can_send = false;
+        self.send_message_next(time)
+    }
+
+    fn send_unreliable_message(&mut self, data : &[u8], _time : f64) -> bool {
+        if data.len() > MAX_DATAGRAM {
+            println!("SendUnreliableMessage: message too big {}", data.len());
+            return false;
+        }
+        let sequence = self.unreliable_send_sequence;
+        self.unreliable_send_sequence = self.unreliable_send_sequence.wrapping_add(1);
+        self.write(NETFLAG_UNRELIABLE, sequence, data)
+    }
+
+    fn can_send_message(&mut self, time : f64) -> bool {
+        if self.send_next {
+            self.send_message_next(time);
+        }
+        self.socket.is_some() && self.can_send
+    }
+
+    fn close(&mut self) {
+        if self.socket.take().is_some() {
+            if let Some(ref active) = self.active {
+                active.set(active.get() - 1);
+            }
+        }
+    }
+
+    fn round_trip_time(&self) -> f64 {
+        self.round_trip_time
+    }
+}
+
+impl Drop for DatagramSocket {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+/// Asks a server for its name, map and number of players.
+pub fn query_server_info(host : &str, timeout : Duration) -> io::Result<ServerInfo> {
+    let (socket, addr) = query_socket(host)?;
+    server_info(&socket, &addr, timeout)
+}
+
+/// Asks a server for its name and map, its players and its game rules.
+pub fn query_server(host : &str, timeout : Duration) -> io::Result<ServerStatus> {
+    let (socket, addr) = query_socket(host)?;
+    let info = server_info(&socket, &addr, timeout)?;
+
+    let mut players = Vec::new();
+    for number in 0..info.players {
+        let mut request = control_request(CCREQ_PLAYER_INFO);
+        request.write_byte(number as i32);
+        let reply = query(&socket, &addr, &finish_control(request), CCREP_PLAYER_INFO, timeout)?;
+        let mut msg = MsgReader::new(reply.get(2..).unwrap_or(&[]));
+        players.push(PlayerInfo {
+            name : msg.read_string(),
+            colors : msg.read_long(),
+            frags : msg.read_long(),
+            connect_time : msg.read_long(),
+            address : msg.read_string(),
+        });
+    }
+
+    // the rules are asked for one after the other until the answer is empty
+    let mut rules : Vec<(String, String)> = Vec::new();
+    loop {
+        let mut request = control_request(CCREQ_RULE_INFO);
+        request.write_string(rules.last().map_or("", |&(ref name, _)| name.as_str()));
+        let reply = query(&socket, &addr, &finish_control(request), CCREP_RULE_INFO, timeout)?;
+        if reply.len() <= 1 {
+            break;
+        }
+        let mut msg = MsgReader::new(&reply[1..]);
+        rules.push((msg.read_string(), msg.read_string()));
+    }
+
+    Ok(ServerStatus {
+        hostname : info.hostname,
+        map : info.map,
+        max_players : info.max_players,
+        players : players,
+        rules : rules,
+    })
+}
+
+/// Broadcasts a server info query on the local network and returns all
+/// servers that answer within `wait`.
+pub fn search_lan(port : u16, wait : Duration) -> io::Result<Vec<FoundServer>> {
+    search((Ipv4Addr::new(255, 255, 255, 255), port).into(), wait)
+}
+
+/// Sends a server info query to an address, which may be a broadcast
+/// address, and returns all servers that answer within `wait`.
+pub fn search(addr : SocketAddr, wait : Duration) -> io::Result<Vec<FoundServer>> {
+    let start = Instant::now();
+    let mut search = ServerSearch::start(addr, 0.0, duration_secs(wait))?;
+    while !search.poll(duration_secs(start.elapsed()))? {
+        sleep(Duration::from_millis(1));
+    }
+    Ok(search.servers)
+}
+
+/// A server search that collects the answers while the game keeps running.
+pub struct ServerSearch {
+    socket : UdpSocket,
+    start : f64,
+    wait : f64,
+    servers : Vec<FoundServer>,
+}
+
+impl ServerSearch {
+    /// Broadcasts a server info query on the local network.
+    pub fn start_lan(port : u16, time : f64, wait : f64) -> io::Result<ServerSearch> {
+        ServerSearch::start((Ipv4Addr::new(255, 255, 255, 255), port).into(), time, wait)
+    }
+
+    /// Sends a server info query to an address, which may be a broadcast
+    /// address. Answers are collected for `wait` seconds.
+    pub fn start(addr : SocketAddr, time : f64, wait : f64) -> io::Result<ServerSearch> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_nonblocking(true)?;
+        socket.set_broadcast(true)?;
+        socket.send_to(&finish_control(server_info_request()), addr)?;
+        Ok(ServerSearch {
+            socket : socket,
+            start : time,
+            wait : wait,
+            servers : Vec::new(),
+        })
+    }
+
+    /// Reads the answers that arrived, returns true once the search is over.
+    pub fn poll(&mut self, time : f64) -> io::Result<bool> {
+        while let Some((reply, from)) = read_control(&self.socket)? {
+            // skip malformed packets, there may be answers behind them
+            let reply = match reply {
+                Some(reply) => reply,
+                None => continue,
+            };
+            if reply.first() != Some(&(CCREP_SERVER_INFO as u8)) || self.servers.iter().any(|server| server.address == from) {
+                continue;
+            }
+            self.servers.push(FoundServer {
+                address : from,
+                info : read_server_info(&reply),
+                ping : time - self.start,
+            });
+        }
+        Ok(time - self.start >= self.wait)
+    }
+
+    /// Returns the servers found so far.
+    pub fn servers(&self) -> &[FoundServer] {
+        &self.servers
+    }
+}
+
+fn query_socket(host : &str) -> io::Result<(UdpSocket, SocketAddr)> {
+    let addr = resolve(host).ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("unknown host {}", host)))?;
+    let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+    socket.set_nonblocking(true)?;
+    Ok((socket, addr))
+}
+
+fn server_info(socket : &UdpSocket, addr : &SocketAddr, timeout : Duration) -> io::Result<ServerInfo> {
+    let reply = query(socket, addr, &finish_control(server_info_request()), CCREP_SERVER_INFO, timeout)?;
+    Ok(read_server_info(&reply))
+}
+
+fn server_info_request() -> SizeBuf {
+    let mut request = control_request(CCREQ_SERVER_INFO);
+    request.write_string("QUAKE");
+    request.write_byte(NET_PROTOCOL_VERSION);
+    request
+}
+
+fn read_server_info(reply : &[u8]) -> ServerInfo {
+    let mut msg = MsgReader::new(&reply[1..]);
+    ServerInfo {
+        address : msg.read_string(),
+        hostname : msg.read_string(),
+        map : msg.read_string(),
+        players : msg.read_byte() as usize,
+        max_players : msg.read_byte() as usize,
+        protocol : msg.read_byte(),
+    }
+}
+
+/// Starts a control packet with room for the header.
+fn control_request(command : i32) -> SizeBuf {
+    let mut request = SizeBuf::new(MAX_DATAGRAM);
+    request.write_long(0);
+    request.write_byte(command);
+    request
+}
+
+/// Writes the header of a control packet.
+fn finish_control(request : SizeBuf) -> Vec<u8> {
+    let mut packet = request.data;
+    let control = NETFLAG_CTL | (packet.len() as u32 & NETFLAG_LENGTH_MASK);
+    packet[..4].copy_from_slice(&big_long(control));
+    packet
+}
+
+/// Returns the next packet and its sender, `None` if there is nothing to
+/// read. The packet is a control packet without its header or `None` if
+/// it isn't a valid control packet.
+fn read_control(socket : &UdpSocket) -> io::Result<Option<(Option<Vec<u8>>, SocketAddr)>> {
+    let mut packet = [0; MAX_DATAGRAM + NET_HEADERSIZE];
+    match socket.recv_from(&mut packet) {
+        Ok((len, from)) => {
+            if len <= 4 {
+                return Ok(Some((None, from)));
+            }
+            let control = read_big_long(&packet[..4]);
+            if control & !NETFLAG_LENGTH_MASK != NETFLAG_CTL || (control & NETFLAG_LENGTH_MASK) as usize != len {
+                return Ok(Some((None, from)));
+            }
+            Ok(Some((Some(packet[4..len].to_vec()), from)))
+        }
+        Err(ref err) if err.kind() == ErrorKind::WouldBlock => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Sends a query until the server answers with the expected reply and
+/// returns the reply without the header.
+fn query(socket : &UdpSocket, addr : &SocketAddr, packet : &[u8], reply_command : i32, timeout : Duration) -> io::Result<Vec<u8>> {
+    for _ in 0..QUERY_TRIES {
+        socket.send_to(packet, addr)?;
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            match read_control(socket)? {
+                Some((Some(reply), from)) => {
+                    if from == *addr && reply[0] as i32 == reply_command {
+                        return Ok(reply);
+                    }
+                }
+                Some((None, _)) => {}
+                None => sleep(Duration::from_millis(1)),
+            }
+        }
+    }
+    Err(io::Error::new(ErrorKind::TimedOut, "No Response"))
+}
+
+/// Returns the address of a socket as the peer reaches it. Sockets on all
+/// interfaces use the address of the interface that routes to the peer.
+fn local_address(socket : &UdpSocket, peer : &SocketAddr) -> String {
+    let mut addr = match socket.local_addr() {
+        Ok(addr) => addr,
+        Err(_) => return String::new(),
+    };
+    if addr.ip().is_unspecified() {
+        // connecting a UDP socket picks the route without sending anything
+        let route = UdpSocket::bind(("0.0.0.0", 0)).and_then(|route| {
+            route.connect(peer)?;
+            route.local_addr()
+        });
+        if let Ok(route) = route {
+            addr.set_ip(route.ip());
+        }
+    }
+    addr.to_string()
+}
+
+/// Opens a non blocking UDP socket on all interfaces.
+fn open_socket(port : u16) -> Option<UdpSocket> {
+    let socket = match UdpSocket::bind(("0.0.0.0", port)) {
+        Ok(socket) => socket,
+        Err(err) => {
+            println!("UDP_OpenSocket: {}", err);
+            return None;
+        }
+    };
+    if let Err(err) = socket.set_nonblocking(true) {
+        println!("UDP_OpenSocket: {}", err);
+        return None;
+    }
+    Some(socket)
+}
+
+/// Resolves host names with an optional port to an IPv4 address.
+fn resolve(host : &str) -> Option<SocketAddr> {
+    let addrs = if host.contains(':') {
+        host.to_socket_addrs()
+    } else {
+        (host, DEFAULT_NET_PORT).to_socket_addrs()
+    };
+    match addrs {
+        Ok(mut addrs) => addrs.find(|addr| addr.is_ipv4()),
+        Err(_) => None,
+    }
+}
+
+fn big_long(value : u32) -> [u8; 4] {
+    [(value >> 24) as u8, (value >> 16) as u8, (value >> 8) as u8, value as u8]
+}
+
+fn read_big_long(data : &[u8]) -> u32 {
+    (data[0] as u32) << 24 | (data[1] as u32) << 16 | (data[2] as u32) << 8 | data[3] as u32
+}
+
+fn duration_secs(duration : Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+
+    /// Raw UDP socket that plays the client side of the protocol.
+    fn raw_client() -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        socket
+    }
+
+    fn connect_request(version : i32) -> Vec<u8> {
+        let mut buf = SizeBuf::new(MAX_DATAGRAM);
+        buf.write_long(0);
+        buf.write_byte(CCREQ_CONNECT);
+        buf.write_string("QUAKE");
+        buf.write_byte(version);
+        let control = NETFLAG_CTL | buf.len() as u32;
+        buf.data[..4].copy_from_slice(&big_long(control));
+        buf.data
+    }
+
+    fn packet(flags : u32, sequence : u32, data : &[u8]) -> Vec<u8> {
+        let mut packet = big_long((NET_HEADERSIZE + data.len()) as u32 | flags).to_vec();
+        packet.extend_from_slice(&big_long(sequence));
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    fn receive(socket : &UdpSocket) -> (u32, u32, Vec<u8>) {
+        let mut data = [0; 2048];
+        let (len, _) = socket.recv_from(&mut data).unwrap();
+        let header = read_big_long(&data[..4]);
+        (header & !NETFLAG_LENGTH_MASK, read_big_long(&data[4..8]), data[8..len].to_vec())
+    }
+
+    fn receive_control(socket : &UdpSocket) -> Vec<u8> {
+        let mut data = [0; 2048];
+        let (len, _) = socket.recv_from(&mut data).unwrap();
+        assert_eq!(read_big_long(&data[..4]), NETFLAG_CTL | len as u32);
+        data[4..len].to_vec()
+    }
+
+    fn poll(socket : &mut NetSocket, time : f64) -> Option<NetMessage> {
+        for _ in 0..1000 {
+            if let Some(message) = socket.get_message(time) {
+                return Some(message);
+            }
+            sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    fn poll_connection(driver : &mut DatagramDriver, time : f64) -> Option<Box<NetSocket>> {
+        for _ in 0..1000 {
+            if let Some(socket) = driver.check_new_connections(time) {
+                return Some(socket);
+            }
+            sleep(Duration::from_millis(1));
+        }
+        None
+    }
+
+    #[test]
+    fn handshake_and_resend() {
+        let mut driver = DatagramDriver::new(0, 1);
+        driver.listen(true);
+        let server_addr : SocketAddr = ([127, 0, 0, 1], driver.port()).into();
+        let client = raw_client();
+
+        // wrong protocol version
+        client.send_to(&connect_request(2), server_addr).unwrap();
+        sleep(Duration::from_millis(20));
+        assert!(driver.check_new_connections(0.0).is_none());
+        assert_eq!(receive_control(&client)[0] as i32, CCREP_REJECT);
+
+        client.send_to(&connect_request(NET_PROTOCOL_VERSION), server_addr).unwrap();
+        let mut server = poll_connection(&mut driver, 0.0).unwrap();
+        let reply = receive_control(&client);
+        let mut msg = MsgReader::new(&reply);
+        assert_eq!(msg.read_byte(), CCREP_ACCEPT);
+        let client_port = msg.read_long() as u16;
+        let connection_addr : SocketAddr = ([127, 0, 0, 1], client_port).into();
+
+        // the request is repeated because the answer got lost
+        client.send_to(&connect_request(NET_PROTOCOL_VERSION), server_addr).unwrap();
+        sleep(Duration::from_millis(20));
+        assert!(driver.check_new_connections(0.5).is_none());
+        assert_eq!(receive_control(&client), vec![CCREP_ACCEPT as u8, client_port as u8, (client_port >> 8) as u8, 0, 0]);
+
+        // a second client doesn't fit
+        let other = raw_client();
+        other.send_to(&connect_request(NET_PROTOCOL_VERSION), server_addr).unwrap();
+        sleep(Duration::from_millis(20));
+        assert!(driver.check_new_connections(3.0).is_none());
+        assert_eq!(receive_control(&other)[0] as i32, CCREP_REJECT);
+
+        // reliable data is acknowledged, duplicates are ignored
+        client.send_to(&packet(NETFLAG_DATA | NETFLAG_EOM, 0, b"spawn"), connection_addr).unwrap();
+        client.send_to(&packet(NETFLAG_DATA | NETFLAG_EOM, 0, b"spawn"), connection_addr).unwrap();
+        assert_eq!(poll(&mut *server, 1.0), Some(NetMessage::Reliable(b"spawn".to_vec())));
+        assert_eq!(receive(&client), (NETFLAG_ACK, 0, Vec::new()));
+        sleep(Duration::from_millis(20));
+        assert_eq!(server.get_message(1.0), None);
+        assert_eq!(receive(&client), (NETFLAG_ACK, 0, Vec::new()));
+
+        // unacknowledged data is sent again
+        assert!(server.send_message(b"serverinfo", 1.0));
+        assert!(!server.can_send_message(1.0));
+        assert_eq!(receive(&client), (NETFLAG_DATA | NETFLAG_EOM, 0, b"serverinfo".to_vec()));
+        assert_eq!(server.get_message(1.5), None);
+        assert_eq!(server.get_message(2.5), None);
+        assert_eq!(receive(&client), (NETFLAG_DATA | NETFLAG_EOM, 0, b"serverinfo".to_vec()));
+        client.send_to(&packet(NETFLAG_ACK, 0, &[]), connection_addr).unwrap();
+        sleep(Duration::from_millis(20));
+        assert_eq!(server.get_message(2.5), None);
+        assert!(server.can_send_message(2.5));
+        assert_eq!(server.round_trip_time(), 0.0);
+
+        // the time until the acknowledgement is measured
+        assert!(server.send_message(b"signon", 2.5));
+        assert_eq!(receive(&client), (NETFLAG_DATA | NETFLAG_EOM, 1, b"signon".to_vec()));
+        client.send_to(&packet(NETFLAG_ACK, 1, &[]), connection_addr).unwrap();
+        sleep(Duration::from_millis(20));
+        assert_eq!(server.get_message(2.75), None);
+        assert_eq!(server.round_trip_time(), 0.25);
+
+        // stale unreliable messages are dropped
+        client.send_to(&packet(NETFLAG_UNRELIABLE, 5, b"new"), connection_addr).unwrap();
+        client.send_to(&packet(NETFLAG_UNRELIABLE, 4, b"old"), connection_addr).unwrap();
+        assert_eq!(poll(&mut *server, 3.0), Some(NetMessage::Unreliable(b"new".to_vec())));
+        sleep(Duration::from_millis(20));
+        assert_eq!(server.get_message(3.0), None);
+
+        // sequence numbers from the peer wrap around instead of overflowing
+        client.send_to(&packet(NETFLAG_UNRELIABLE, u32::max_value(), b"last"), connection_addr).unwrap();
+        assert_eq!(poll(&mut *server, 3.0), Some(NetMessage::Unreliable(b"last".to_vec())));
+
+        // the connection times out without messages, the timeout can be
+        // changed for open connections
+        assert_eq!(server.get_message(300.0), None);
+        driver.set_message_timeout(10.0);
+        assert_eq!(server.get_message(14.0), Some(NetMessage::Disconnected));
+
+        // the slot is free again
+        drop(server);
+        other.send_to(&connect_request(NET_PROTOCOL_VERSION), server_addr).unwrap();
+        assert!(poll_connection(&mut driver, 400.0).is_some());
+    }
+
+    #[test]
+    fn client_server_over_localhost() {
+        let (port_tx, port_rx) = mpsc::channel();
+
+        // echo server
+        let server = thread::spawn(move || {
+            let mut driver = DatagramDriver::new(0, 4);
+            driver.listen(true);
+            port_tx.send(driver.port()).unwrap();
+            let mut socket = poll_connection(&mut driver, 0.0).unwrap();
+            loop {
+                match poll(&mut *socket, 0.0) {
+                    Some(NetMessage::Reliable(data)) => {
+                        if data == b"quit" {
+                            break;
+                        }
+                        while !socket.can_send_message(0.0) {
+                            socket.get_message(0.0);
+                            sleep(Duration::from_millis(1));
+                        }
+                        assert!(socket.send_message(&data, 0.0));
+                    }
+                    Some(NetMessage::Unreliable(data)) => {
+                        socket.send_unreliable_message(&data, 0.0);
+                    }
+                    _ => panic!("connection lost"),
+                }
+            }
+            // wait until the quit message is acknowledged
+            sleep(Duration::from_millis(50));
+            socket.get_message(0.0);
+        });
+
+        let port = port_rx.recv().unwrap();
+        let mut driver = DatagramDriver::new(0, 0);
+        let mut client = driver.connect(&format!("127.0.0.1:{}", port), 0.0).unwrap();
+
+        // a message that is split into three datagrams
+        let big : Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        assert!(client.send_message(&big, 0.0));
+        assert_eq!(poll(&mut *client, 0.0), Some(NetMessage::Reliable(big)));
+
+        assert!(client.send_unreliable_message(b"ping", 0.0));
+        assert_eq!(poll(&mut *client, 0.0), Some(NetMessage::Unreliable(b"ping".to_vec())));
+
+        while !client.can_send_message(0.0) {
+            client.get_message(0.0);
+            sleep(Duration::from_millis(1));
+        }
+        assert!(client.send_message(b"quit", 0.0));
+        while !client.can_send_message(0.0) {
+            client.get_message(0.0);
+            sleep(Duration::from_millis(1));
+        }
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn server_queries() {
+        let (port_tx, port_rx) = mpsc::channel();
+        let (quit_tx, quit_rx) = mpsc::channel();
+
+        let server = thread::spawn(move || {
+            let mut driver = DatagramDriver::new(0, 4);
+            driver.listen(true);
+            driver.set_status(Some(&ServerStatus {
+                hostname : "lan party".to_string(),
+                map : "dm6".to_string(),
+                max_players : 4,
+                players : vec![PlayerInfo {
+                    name : "ranger".to_string(),
+                    colors : 0x4c,
+                    frags : 7,
+                    connect_time : 90,
+                    address : "LOCAL".to_string(),
+                }],
+                rules : vec![("teamplay".to_string(), "1".to_string()), ("sv_gravity".to_string(), "800".to_string())],
+            }));
+            port_tx.send(driver.port()).unwrap();
+            while quit_rx.try_recv().is_err() {
+                assert!(driver.check_new_connections(0.0).is_none());
+                sleep(Duration::from_millis(1));
+            }
+        });
+
+        let port = port_rx.recv().unwrap();
+        let host = format!("127.0.0.1:{}", port);
+        let timeout = Duration::from_secs(1);
+        let info = query_server_info(&host, timeout).unwrap();
+        assert_eq!((info.hostname.as_str(), info.map.as_str()), ("lan party", "dm6"));
+        assert_eq!((info.players, info.max_players, info.protocol), (1, 4, NET_PROTOCOL_VERSION));
+        assert_eq!(info.address, host);
+
+        let status = query_server(&host, timeout).unwrap();
+        assert_eq!(status.players[0].name, "ranger");
+        assert_eq!((status.players[0].colors, status.players[0].frags, status.players[0].connect_time), (0x4c, 7, 90));
+        assert_eq!(status.rules, vec![("teamplay".to_string(), "1".to_string()), ("sv_gravity".to_string(), "800".to_string())]);
+
+        let servers = search(([127, 0, 0, 1], port).into(), Duration::from_millis(200)).unwrap();
+        assert_eq!(servers.len(), 1);
+        assert_eq!(servers[0].info, info);
+        assert!(servers[0].ping < 0.2);
+
+        quit_tx.send(()).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn search_skips_bad_packets() {
+        let server = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut search = ServerSearch::start(addr, 0.0, 1.0).unwrap();
+        let mut request = [0; MAX_DATAGRAM];
+        let (_, client) = server.recv_from(&mut request).unwrap();
+
+        let mut reply = control_request(CCREP_SERVER_INFO);
+        reply.write_string("127.0.0.1");
+        reply.write_string("lan party");
+        reply.write_string("dm6");
+        reply.write_byte(1);
+        reply.write_byte(4);
+        reply.write_byte(NET_PROTOCOL_VERSION);
+        server.send_to(b"junk", client).unwrap();
+        server.send_to(&finish_control(reply), client).unwrap();
+
+        // both packets are read by the same poll
+        sleep(Duration::from_millis(50));
+        assert!(!search.poll(0.1).unwrap());
+        assert_eq!(search.servers().len(), 1);
+        assert_eq!(search.servers()[0].info.hostname, "lan party");
+    }
+}
@@ -0,0 +1,207 @@
+#![warn(missing_docs)]
+
+//! Loopback driver connecting the local client with the local server. A
+//! latency can be simulated to try out network play locally.
+//!
+//! Original source can be found in net_loop.c
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use net::{NetDriver, NetMessage, NetSocket, NET_MAXMESSAGE};
+
+/// Messages with the time they arrive.
+type MessageQueue = Rc<RefCell<VecDeque<(f64, NetMessage)>>>;
+
+/// Driver for the "local" address. The local server always accepts the
+/// local client, even when it doesn't listen for network clients.
+pub struct LoopDriver {
+    pending : Option<LoopSocket>,
+    latency : f64,
+}
+
+impl LoopDriver {
+    /// Creates the loopback driver.
+    pub fn new() -> LoopDriver {
+        LoopDriver::with_latency(0.0)
+    }
+
+    /// Creates a loopback driver that delivers messages the given number
+    /// of seconds after they have been sent.
+    pub fn with_latency(latency : f64) -> LoopDriver {
+        LoopDriver {
+            pending : None,
+            latency : latency,
+        }
+    }
+}
+
+impl NetDriver for LoopDriver {
+    fn name(&self) -> &'static str {
+        "Loopback"
+    }
+
+    fn listen(&mut self, _state : bool) {
+    }
+
+    fn connect(&mut self, host : &str, _time : f64) -> Option<Box<NetSocket>> {
+        if host != "local" {
+            return None;
+        }
+
+        let to_server : MessageQueue = Rc::new(RefCell::new(VecDeque::new()));
+        let to_client : MessageQueue = Rc::new(RefCell::new(VecDeque::new()));
+        let client_can_send = Rc::new(Cell::new(0.0));
+        let server_can_send = Rc::new(Cell::new(0.0));
+        let connected = Rc::new(Cell::new(true));
+
+        let client = LoopSocket {
+            name : "localhost",
+            incoming : to_client.clone(),
+            outgoing : to_server.clone(),
+            can_send : client_can_send.clone(),
+            peer_can_send : server_can_send.clone(),
+            connected : connected.clone(),
+            latency : self.latency,
+        };
+        self.pending = Some(LoopSocket {
+            name : "LOCAL",
+            incoming : to_server,
+            outgoing : to_client,
+            can_send : server_can_send,
+            peer_can_send : client_can_send,
+            connected : connected,
+            latency : self.latency,
+        });
+        Some(Box::new(client))
+    }
+
+    fn check_new_connections(&mut self, _time : f64) -> Option<Box<NetSocket>> {
+        self.pending.take().map(|socket| Box::new(socket) as Box<NetSocket>)
+    }
+}
+
+/// One side of a loopback connection.
+struct LoopSocket {
+    name : &'static str,
+    incoming : MessageQueue,
+    outgoing : MessageQueue,
+    /// Time the acknowledgement of the last reliable message arrives.
+    can_send : Rc<Cell<f64>>,
+    peer_can_send : Rc<Cell<f64>>,
+    connected : Rc<Cell<bool>>,
+    latency : f64,
+}
+
+impl NetSocket for LoopSocket {
+    fn address(&self) -> String {
+        self.name.to_string()
+    }
+
+    fn get_message(&mut self, time : f64) -> Option<NetMessage> {
+        let message = {
+            let mut incoming = self.incoming.borrow_mut();
+            match incoming.front() {
+                Some(&(arrival, _)) if arrival > time => return None,
+                _ => incoming.pop_front().map(|(_, message)| message),
+            }
+        };
+        match message {
+            Some(NetMessage::Reliable(data)) => {
+                // the other side can send its next reliable message once the
+                // acknowledgement arrives
+                self.peer_can_send.set(time + self.latency);
+                Some(NetMessage::Reliable(data))
+            }
+            Some(message) => Some(message),
+            None if !self.connected.get() => Some(NetMessage::Disconnected),
+            None => None,
+        }
+    }
+
+    fn send_message(&mut self, data : &[u8], time : f64) -> bool {
+        if !self.connected.get() {
+            return false;
+        }
+        if data.len() > NET_MAXMESSAGE {
+            println!("Loop_SendMessage: overflow");
+            return false;
+        }
+        self.outgoing.borrow_mut().push_back((time + self.latency, NetMessage::Reliable(data.to_vec())));
+        self.can_send.set(f64::INFINITY);
+        true
+    }
+
+    fn send_unreliable_message(&mut self, data : &[u8], time : f64) -> bool {
+        if !self.connected.get() {
+            return false;
+        }
+        self.outgoing.borrow_mut().push_back((time + self.latency, NetMessage::Unreliable(data.to_vec())));
+        true
+    }
+
+    fn can_send_message(&mut self, time : f64) -> bool {
+        self.connected.get() && time >= self.can_send.get()
+    }
+
+    fn close(&mut self) {
+        self.connected.set(false);
+        self.incoming.borrow_mut().clear();
+    }
+
+    fn round_trip_time(&self) -> f64 {
+        self.latency * 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback() {
+        let mut driver = LoopDriver::new();
+        assert!(driver.connect("127.0.0.1", 0.0).is_none());
+        let mut client = driver.connect("local", 0.0).unwrap();
+        driver.listen(false);
+        let mut server = driver.check_new_connections(0.0).unwrap();
+        assert!(driver.check_new_connections(0.0).is_none());
+        assert_eq!(server.address(), "LOCAL");
+
+        assert!(client.send_message(b"prespawn", 0.0));
+        assert!(!client.can_send_message(0.0));
+        assert!(client.send_unreliable_message(b"move", 0.0));
+        assert!(server.send_unreliable_message(b"time", 0.0));
+
+        assert_eq!(server.get_message(0.0), Some(NetMessage::Reliable(b"prespawn".to_vec())));
+        assert!(client.can_send_message(0.0));
+        assert_eq!(server.get_message(0.0), Some(NetMessage::Unreliable(b"move".to_vec())));
+        assert_eq!(server.get_message(0.0), None);
+        assert_eq!(client.get_message(0.0), Some(NetMessage::Unreliable(b"time".to_vec())));
+
+        server.close();
+        assert!(!client.can_send_message(0.0));
+        assert!(!client.send_message(b"spawn", 0.0));
+        assert_eq!(client.get_message(0.0), Some(NetMessage::Disconnected));
+    }
+
+    #[test]
+    fn latency() {
+        let mut driver = LoopDriver::with_latency(0.1);
+        let mut client = driver.connect("local", 0.0).unwrap();
+        let mut server = driver.check_new_connections(0.0).unwrap();
+        assert_eq!(client.round_trip_time(), 0.2);
+
+        assert!(client.send_message(b"prespawn", 1.0));
+        assert!(client.send_unreliable_message(b"move", 1.05));
+        assert_eq!(server.get_message(1.05), None);
+        assert_eq!(server.get_message(1.1), Some(NetMessage::Reliable(b"prespawn".to_vec())));
+        assert_eq!(server.get_message(1.1), None);
+        assert_eq!(server.get_message(1.2), Some(NetMessage::Unreliable(b"move".to_vec())));
+
+        // the acknowledgement takes as long as the message
+        assert!(!client.can_send_message(1.15));
+        assert!(client.can_send_message(1.3));
+    }
+}
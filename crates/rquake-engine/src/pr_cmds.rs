@@ -0,0 +1,874 @@
+#![warn(missing_docs)]
+
+//! Builtin functions called by the QuakeC programs.
+//!
+//! Original source can be found in pr_cmds.c
+
+use std::f32::consts::PI;
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{angle_vectors, dot_product, vector_add, vector_length, vector_normalize, vector_scale, vector_subtract};
+use rquake_fs::leaf_visible;
+use msg::SizeBuf;
+use net_dgrm::MAX_DATAGRAM;
+use pr_exec::ProgsDat;
+use progdefs::{self, OFS_PARM0, OFS_RETURN};
+use protocol::{EntityState, ServerMessage};
+use server::{self, Server};
+use world::MoveClip;
+
+/// Unreliable message to all clients.
+const MSG_BROADCAST : i32 = 0;
+/// Reliable message to the client in msg_entity.
+const MSG_ONE : i32 = 1;
+/// Reliable message to all clients.
+const MSG_ALL : i32 = 2;
+/// Signon message sent to clients when they connect.
+const MSG_INIT : i32 = 3;
+
+/// Entities that can be aimed at.
+const DAMAGE_AIM : f32 = 2.0;
+
+fn float(sv : &Server, parm : usize) -> f32 {
+    sv.globals.float(OFS_PARM0 + parm * 3)
+}
+
+fn vector(sv : &Server, parm : usize) -> Vec3 {
+    sv.globals.vector(OFS_PARM0 + parm * 3)
+}
+
+fn int(sv : &Server, parm : usize) -> i32 {
+    sv.globals.int(OFS_PARM0 + parm * 3)
+}
+
+fn string(sv : &Server, parm : usize) -> String {
+    sv.strings.get(int(sv, parm)).to_string()
+}
+
+fn return_float(sv : &mut Server, value : f32) {
+    sv.globals.set_float(OFS_RETURN, value);
+}
+
+fn return_vector(sv : &mut Server, value : Vec3) {
+    sv.globals.set_vector(OFS_RETURN, value);
+}
+
+fn return_int(sv : &mut Server, value : i32) {
+    sv.globals.set_int(OFS_RETURN, value);
+}
+
+/// Returns an entity parameter, bad entities stop the programs.
+fn edict(vm : &mut ProgsDat, sv : &mut Server, parm : usize) -> Option<usize> {
+    let ent = int(sv, parm);
+    if ent < 0 || ent as usize >= sv.edicts.len() {
+        vm.run_error(sv, &format!("bad entity {}", ent));
+        return None;
+    }
+    Some(ent as usize)
+}
+
+/// Returns the entity running the function.
+fn self_edict(vm : &mut ProgsDat, sv : &mut Server) -> Option<usize> {
+    let ent = sv.globals.int(progdefs::G_SELF);
+    if ent < 0 || ent as usize >= sv.edicts.len() {
+        vm.run_error(sv, &format!("bad self {}", ent));
+        return None;
+    }
+    Some(ent as usize)
+}
+
+/// Concatenates the string parameters starting at first.
+fn var_string(vm : &ProgsDat, sv : &Server, first : usize) -> String {
+    (first..vm.argc()).map(|parm| string(sv, parm)).collect()
+}
+
+/// Calls a builtin by its number.
+pub fn call_builtin(num : i32, vm : &mut ProgsDat, sv : &mut Server) {
+    match num {
+        1 => makevectors(sv),
+        2 => setorigin(vm, sv),
+        3 => setmodel(vm, sv),
+        4 => setsize(vm, sv),
+        6 => println!("break statement"),
+        7 => random(vm, sv),
+        8 => sound(vm, sv),
+        9 => normalize(sv),
+        10 => error(vm, sv),
+        11 => objerror(vm, sv),
+        12 => vlen(sv),
+        13 => vectoyaw(sv),
+        14 => spawn(vm, sv),
+        15 => remove(vm, sv),
+        16 => traceline(vm, sv),
+        17 => checkclient(vm, sv),
+        18 => find(vm, sv),
+        19 | 76 => precache_sound(vm, sv),
+        20 | 75 => precache_model(vm, sv),
+        21 => stuffcmd(vm, sv),
+        22 => findradius(sv),
+        23 => bprint(vm, sv),
+        24 => sprint(vm, sv),
+        25 => dprint(vm, sv),
+        26 => ftos(sv),
+        27 => vtos(sv),
+        28 => vm.print_edicts(sv),
+        29 => vm.set_trace(true),
+        30 => vm.set_trace(false),
+        31 => {
+            if let Some(ent) = edict(vm, sv, 0) {
+                vm.print_edict(sv, ent);
+            }
+        }
+        32 => walkmove(vm, sv),
+        34 => droptofloor(vm, sv),
+        35 => {
+            let (style, map) = (float(sv, 0) as i32, string(sv, 1));
+            sv.light_style(style, &map);
+        }
+        36 => {
+            let f = float(sv, 0);
+            return_float(sv, if f > 0.0 { (f + 0.5) as i32 } else { (f - 0.5) as i32 } as f32);
+        }
+        37 => {
+            let f = float(sv, 0);
+            return_float(sv, f.floor());
+        }
+        38 => {
+            let f = float(sv, 0);
+            return_float(sv, f.ceil());
+        }
+        40 => {
+            if let Some(ent) = edict(vm, sv, 0) {
+                let bottom = sv.check_bottom(ent);
+                return_float(sv, if bottom { 1.0 } else { 0.0 });
+            }
+        }
+        41 => {
+            let contents = sv.point_contents(vector(sv, 0));
+            return_float(sv, contents as f32);
+        }
+        43 => {
+            let f = float(sv, 0);
+            return_float(sv, f.abs());
+        }
+        44 => aim(vm, sv),
+        45 => {
+            let value = sv.cvars.value(&string(sv, 0));
+            return_float(sv, value);
+        }
+        46 => {
+            let text = string(sv, 0);
+            sv.commands.push_str(&text);
+        }
+        47 => nextent(vm, sv),
+        48 => particle(sv),
+        49 => {
+            if let Some(ent) = self_edict(vm, sv) {
+                sv.change_yaw(ent);
+            }
+        }
+        51 => vectoangles(sv),
+        52 ..= 59 => write(num, vm, sv),
+        67 => movetogoal(vm, sv),
+        68 | 77 => {
+            let file = int(sv, 0);
+            return_int(sv, file);
+        }
+        69 => makestatic(vm, sv),
+        70 => changelevel(sv),
+        72 => cvar_set(sv),
+        73 => centerprint(vm, sv),
+        74 => ambientsound(sv),
+        78 => setspawnparms(vm, sv),
+        0 | 5 | 33 | 39 | 42 | 50 | 60 ..= 66 | 71 => vm.run_error(sv, "unimplemented bulitin"),
+        _ => vm.run_error(sv, "Bad builtin call number"),
+    }
+}
+
+/// Sets v_forward, v_right and v_up for the given angles.
+fn makevectors(sv : &mut Server) {
+    let (forward, right, up) = angle_vectors(&vector(sv, 0));
+    sv.globals.set_vector(progdefs::G_V_FORWARD, forward);
+    sv.globals.set_vector(progdefs::G_V_RIGHT, right);
+    sv.globals.set_vector(progdefs::G_V_UP, up);
+}
+
+/// Moves an entity without touching triggers.
+fn setorigin(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match edict(vm, sv, 0) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let origin = vector(sv, 1);
+    sv.edicts[ent].v.set_vector(progdefs::ORIGIN, origin);
+    sv.link_edict(ent, false, vm);
+}
+
+/// Changes the bounding box of an entity and relinks it.
+fn set_min_max_size(vm : &mut ProgsDat, sv : &mut Server, ent : usize, mins : Vec3, maxs : Vec3) {
+    if (0..3).any(|i| mins[i] > maxs[i]) {
+        vm.run_error(sv, "backwards mins/maxs");
+        return;
+    }
+    {
+        let v = &mut sv.edicts[ent].v;
+        v.set_vector(progdefs::MINS, mins);
+        v.set_vector(progdefs::MAXS, maxs);
+        v.set_vector(progdefs::SIZE, vector_subtract(&maxs, &mins));
+    }
+    sv.link_edict(ent, false, vm);
+}
+
+/// Changes the model of an entity, brush models also set the size.
+fn setmodel(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match edict(vm, sv, 0) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let (model, name) = (int(sv, 1), string(sv, 1));
+
+    // check to see if model was properly precached
+    let index = match sv.model_precache.iter().position(|m| *m == name) {
+        Some(index) if !name.is_empty() => index,
+        _ => {
+            vm.run_error(sv, &format!("no precache: {}", name));
+            return;
+        }
+    };
+    sv.edicts[ent].v.set_int(progdefs::MODEL, model);
+    sv.edicts[ent].v.set_float(progdefs::MODELINDEX, index as f32);
+
+    let size = match sv.models.get(index) {
+        Some(&Some(ref model)) => (model.mins, model.maxs),
+        _ => ([0.0; 3], [0.0; 3]),
+    };
+    set_min_max_size(vm, sv, ent, size.0, size.1);
+}
+
+/// Changes the bounding box of an entity.
+fn setsize(vm : &mut ProgsDat, sv : &mut Server) {
+    if let Some(ent) = edict(vm, sv, 0) {
+        let (mins, maxs) = (vector(sv, 1), vector(sv, 2));
+        set_min_max_size(vm, sv, ent, mins, maxs);
+    }
+}
+
+/// Returns a number from 0 to 1.
+fn random(vm : &mut ProgsDat, sv : &mut Server) {
+    let value = vm.random();
+    return_float(sv, value);
+}
+
+/// Starts a sound on an entity for all clients.
+fn sound(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match edict(vm, sv, 0) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let (channel, sample) = (float(sv, 1) as i32, string(sv, 2));
+    let (volume, attenuation) = ((float(sv, 3) * 255.0) as i32, float(sv, 4));
+    sv.start_sound(ent, channel, &sample, volume, attenuation);
+}
+
+fn normalize(sv : &mut Server) {
+    let mut v = vector(sv, 0);
+    if vector_length(&v) == 0.0 {
+        v = [0.0; 3];
+    } else {
+        vector_normalize(&mut v);
+    }
+    return_vector(sv, v);
+}
+
+fn vlen(sv : &mut Server) {
+    let length = vector_length(&vector(sv, 0));
+    return_float(sv, length);
+}
+
+fn vectoyaw(sv : &mut Server) {
+    let v = vector(sv, 0);
+    let mut yaw = 0.0;
+    if v[1] != 0.0 || v[0] != 0.0 {
+        yaw = (v[1].atan2(v[0]) * 180.0 / PI) as i32 as f32;
+        if yaw < 0.0 {
+            yaw += 360.0;
+        }
+    }
+    return_float(sv, yaw);
+}
+
+fn vectoangles(sv : &mut Server) {
+    let v = vector(sv, 0);
+    let (pitch, yaw);
+    if v[1] == 0.0 && v[0] == 0.0 {
+        yaw = 0.0;
+        pitch = if v[2] > 0.0 { 90.0 } else { 270.0 };
+    } else {
+        let mut y = (v[1].atan2(v[0]) * 180.0 / PI) as i32 as f32;
+        if y < 0.0 {
+            y += 360.0;
+        }
+        let forward = (v[0] * v[0] + v[1] * v[1]).sqrt();
+        let mut p = (v[2].atan2(forward) * 180.0 / PI) as i32 as f32;
+        if p < 0.0 {
+            p += 360.0;
+        }
+        yaw = y;
+        pitch = p;
+    }
+    return_vector(sv, [pitch, yaw, 0.0]);
+}
+
+/// Prints the error with the entity running the function and stops the
+/// programs.
+fn error(vm : &mut ProgsDat, sv : &mut Server) {
+    let text = var_string(vm, sv, 0);
+    println!("======SERVER ERROR:\n{}", text);
+    let ent = sv.globals.int(progdefs::G_SELF);
+    if ent >= 0 {
+        vm.print_edict(sv, ent as usize);
+    }
+    vm.run_error(sv, "Program error");
+}
+
+/// Like error, but also removes the entity running the function.
+fn objerror(vm : &mut ProgsDat, sv : &mut Server) {
+    let text = var_string(vm, sv, 0);
+    println!("======OBJECT ERROR:\n{}", text);
+    if let Some(ent) = self_edict(vm, sv) {
+        vm.print_edict(sv, ent);
+        sv.free_edict(ent);
+        vm.run_error(sv, "Program error");
+    }
+}
+
+fn spawn(vm : &mut ProgsDat, sv : &mut Server) {
+    match sv.alloc_edict() {
+        Some(ent) => return_int(sv, ent as i32),
+        None => vm.run_error(sv, "ED_Alloc: no free edicts"),
+    }
+}
+
+fn remove(vm : &mut ProgsDat, sv : &mut Server) {
+    if let Some(ent) = edict(vm, sv, 0) {
+        sv.free_edict(ent);
+    }
+}
+
+/// Stores the result of a trace in the trace globals.
+fn set_trace_globals(sv : &mut Server, trace : &::trace::Trace) {
+    let g = &mut sv.globals;
+    g.set_float(progdefs::G_TRACE_ALLSOLID, if trace.allsolid { 1.0 } else { 0.0 });
+    g.set_float(progdefs::G_TRACE_STARTSOLID, if trace.startsolid { 1.0 } else { 0.0 });
+    g.set_float(progdefs::G_TRACE_FRACTION, trace.fraction);
+    g.set_float(progdefs::G_TRACE_INWATER, if trace.inwater { 1.0 } else { 0.0 });
+    g.set_float(progdefs::G_TRACE_INOPEN, if trace.inopen { 1.0 } else { 0.0 });
+    g.set_vector(progdefs::G_TRACE_ENDPOS, trace.endpos);
+    g.set_vector(progdefs::G_TRACE_PLANE_NORMAL, trace.plane_normal);
+    g.set_float(progdefs::G_TRACE_PLANE_DIST, trace.plane_dist);
+    g.set_int(progdefs::G_TRACE_ENT, trace.ent.unwrap_or(0) as i32);
+}
+
+/// Traces a line, entities passing nomonsters only clip against brush models.
+fn traceline(vm : &mut ProgsDat, sv : &mut Server) {
+    let (v1, v2, nomonsters) = (vector(sv, 0), vector(sv, 1), float(sv, 2) != 0.0);
+    let ent = match edict(vm, sv, 3) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let clip = if nomonsters { MoveClip::NoMonsters } else { MoveClip::Normal };
+    let trace = sv.trace_move(v1, [0.0; 3], [0.0; 3], v2, clip, Some(ent));
+    set_trace_globals(sv, &trace);
+}
+
+/// Returns the leaf the eyes of an entity are in.
+fn view_leaf(sv : &Server, ent : usize) -> usize {
+    let v = &sv.edicts[ent].v;
+    let view = vector_add(&v.vector(progdefs::ORIGIN), &v.vector(progdefs::VIEW_OFS));
+    match sv.worldmodel {
+        Some(ref bsp) => bsp.point_in_leaf(&view),
+        None => 0,
+    }
+}
+
+/// Picks the next living client and stores what it sees.
+fn new_check_client(sv : &mut Server, check : usize) -> usize {
+    // cycle to the next one
+    let check = check.max(1).min(sv.max_clients);
+    let mut i = if check == sv.max_clients { 1 } else { check + 1 };
+    loop {
+        if i == sv.max_clients + 1 {
+            i = 1;
+        }
+        if i == check {
+            // didn't find anything else
+            break;
+        }
+        let v = &sv.edicts[i].v;
+        if !sv.edicts[i].free && v.float(progdefs::HEALTH) > 0.0 && v.float(progdefs::FLAGS) as i32 & server::FL_NOTARGET == 0 {
+            // found a valid client, could be the same as the last one
+            break;
+        }
+        i += 1;
+    }
+
+    // get the PVS for the entity
+    let leaf = view_leaf(sv, i);
+    sv.checkpvs = match sv.worldmodel {
+        Some(ref bsp) => bsp.leaf_pvs(leaf),
+        None => Vec::new(),
+    };
+    i
+}
+
+/// Returns a client that could see the entity running the function, or the
+/// world. A different client is checked every 0.1 seconds, so monsters
+/// don't all go after the same player.
+fn checkclient(vm : &mut ProgsDat, sv : &mut Server) {
+    // find a new check if on a new frame
+    if sv.time - sv.lastchecktime >= 0.1 {
+        let lastcheck = sv.lastcheck;
+        sv.lastcheck = new_check_client(sv, lastcheck);
+        sv.lastchecktime = sv.time;
+    }
+
+    // return check if it might be visible
+    let ent = sv.lastcheck;
+    if sv.edicts[ent].free || sv.edicts[ent].v.float(progdefs::HEALTH) <= 0.0 {
+        return_int(sv, 0);
+        return;
+    }
+
+    // if current entity can't possibly see the check entity, return 0
+    let self_ent = match self_edict(vm, sv) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let leaf = view_leaf(sv, self_ent);
+    if !leaf_visible(&sv.checkpvs, leaf) {
+        return_int(sv, 0);
+        return;
+    }
+    return_int(sv, ent as i32);
+}
+
+/// Returns the next entity after the given one whose string field matches.
+fn find(vm : &mut ProgsDat, sv : &mut Server) {
+    let start = match edict(vm, sv, 0) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let (field, s) = (int(sv, 1) as usize, string(sv, 2));
+    let found = (start + 1..sv.edicts.len()).find(|&ent| {
+        let e = &sv.edicts[ent];
+        !e.free && field < e.v.len() && sv.strings.get(e.v.int(field)) == s
+    });
+    return_int(sv, found.unwrap_or(0) as i32);
+}
+
+/// Returns a chain of all solid entities within the radius.
+fn findradius(sv : &mut Server) {
+    let (org, rad) = (vector(sv, 0), float(sv, 1));
+    let mut chain = 0;
+    for ent in 1..sv.edicts.len() {
+        if sv.edicts[ent].free {
+            continue;
+        }
+        let v = &mut sv.edicts[ent].v;
+        if v.float(progdefs::SOLID) == server::SOLID_NOT {
+            continue;
+        }
+        let (origin, mins, maxs) = (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS));
+        let eorg : Vec3 = [
+            org[0] - (origin[0] + (mins[0] + maxs[0]) * 0.5),
+            org[1] - (origin[1] + (mins[1] + maxs[1]) * 0.5),
+            org[2] - (origin[2] + (mins[2] + maxs[2]) * 0.5),
+        ];
+        if vector_length(&eorg) > rad {
+            continue;
+        }
+        v.set_int(progdefs::CHAIN, chain);
+        chain = ent as i32;
+    }
+    return_int(sv, chain);
+}
+
+/// Precaching is only allowed while the entities are spawned.
+fn check_precache(vm : &mut ProgsDat, sv : &mut Server) -> bool {
+    if sv.active {
+        vm.run_error(sv, "PF_Precache_*: Precache can only be done in spawn functions");
+        return false;
+    }
+    true
+}
+
+fn precache_sound(vm : &mut ProgsDat, sv : &mut Server) {
+    let (s, name) = (int(sv, 0), string(sv, 0));
+    return_int(sv, s);
+    if !check_precache(vm, sv) || sv.sound_precache.iter().any(|sound| *sound == name) {
+        return;
+    }
+    if sv.sound_precache.len() >= server::MAX_SOUNDS {
+        vm.run_error(sv, "PF_precache_sound: overflow");
+        return;
+    }
+    sv.sound_precache.push(name);
+}
+
+fn precache_model(vm : &mut ProgsDat, sv : &mut Server) {
+    let (s, name) = (int(sv, 0), string(sv, 0));
+    return_int(sv, s);
+    if !check_precache(vm, sv) || sv.model_precache.iter().any(|model| *model == name) {
+        return;
+    }
+    if sv.model_precache.len() >= server::MAX_MODELS {
+        vm.run_error(sv, "PF_precache_model: overflow");
+        return;
+    }
+    // only the brush models of the map are used for clipping
+    sv.model_precache.push(name);
+    sv.models.push(None);
+}
+
+/// Returns the client slot of an entity parameter.
+fn client(sv : &Server, parm : usize) -> Option<usize> {
+    let ent = int(sv, parm);
+    if ent < 1 || ent as usize > sv.max_clients {
+        None
+    } else {
+        Some(ent as usize - 1)
+    }
+}
+
+/// Sends a command to a client.
+fn stuffcmd(vm : &mut ProgsDat, sv : &mut Server) {
+    let client = match client(sv, 0) {
+        Some(client) => client,
+        None => {
+            vm.run_error(sv, "Parm 0 not a client");
+            return;
+        }
+    };
+    let text = string(sv, 1);
+    ServerMessage::StuffText(text).write(&mut sv.clients[client].message);
+}
+
+/// Prints to all clients.
+fn bprint(vm : &mut ProgsDat, sv : &mut Server) {
+    let message = ServerMessage::Print(var_string(vm, sv, 0));
+    for c in sv.clients.iter_mut().filter(|c| c.spawned) {
+        message.write(&mut c.message);
+    }
+}
+
+/// Prints to a single client.
+fn sprint(vm : &mut ProgsDat, sv : &mut Server) {
+    match client(sv, 0) {
+        Some(client) => {
+            let message = ServerMessage::Print(var_string(vm, sv, 1));
+            message.write(&mut sv.clients[client].message);
+        }
+        None => println!("tried to sprint to a non-client"),
+    }
+}
+
+/// Prints a centered message on a single client.
+fn centerprint(vm : &mut ProgsDat, sv : &mut Server) {
+    match client(sv, 0) {
+        Some(client) => {
+            let message = ServerMessage::CenterPrint(var_string(vm, sv, 1));
+            message.write(&mut sv.clients[client].message);
+        }
+        None => println!("tried to sprint to a non-client"),
+    }
+}
+
+/// Prints to the server console in developer mode.
+fn dprint(vm : &mut ProgsDat, sv : &mut Server) {
+    if sv.cvars.value("developer") != 0.0 {
+        print!("{}", var_string(vm, sv, 0));
+    }
+}
+
+fn ftos(sv : &mut Server) {
+    let v = float(sv, 0);
+    let s = if v == v as i32 as f32 { format!("{}", v as i32) } else { format!("{:5.1}", v) };
+    let index = sv.strings.set_temp(&s);
+    return_int(sv, index);
+}
+
+fn vtos(sv : &mut Server) {
+    let v = vector(sv, 0);
+    let index = sv.strings.set_temp(&format!("'{:5.1} {:5.1} {:5.1}'", v[0], v[1], v[2]));
+    return_int(sv, index);
+}
+
+/// Moves the entity running the function a step in the yaw direction,
+/// returns 1 if the move succeeded.
+fn walkmove(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match self_edict(vm, sv) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let (yaw, dist) = (float(sv, 0), float(sv, 1));
+    if sv.edicts[ent].v.float(progdefs::FLAGS) as i32 & (server::FL_ONGROUND | server::FL_FLY | server::FL_SWIM) == 0 {
+        return_float(sv, 0.0);
+        return;
+    }
+    let yaw = yaw * PI * 2.0 / 360.0;
+    let mov = [yaw.cos() * dist, yaw.sin() * dist, 0.0];
+
+    // the move may call touch functions that change self
+    let oldself = sv.globals.int(progdefs::G_SELF);
+    let moved = sv.move_step(ent, mov, true, vm);
+    sv.globals.set_int(progdefs::G_SELF, oldself);
+    return_float(sv, if moved { 1.0 } else { 0.0 });
+}
+
+/// Moves the entity running the function towards its goal.
+fn movetogoal(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match self_edict(vm, sv) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let dist = float(sv, 0);
+    let mut seed = vm.seed();
+    sv.move_to_goal(ent, dist, vm, &mut seed);
+    vm.set_seed(seed);
+}
+
+/// Drops the entity running the function onto the floor up to 256 units
+/// below, returns 1 if it landed.
+fn droptofloor(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match self_edict(vm, sv) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let (origin, mins, maxs) = {
+        let v = &sv.edicts[ent].v;
+        (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS))
+    };
+    let end = [origin[0], origin[1], origin[2] - 256.0];
+    let trace = sv.trace_move(origin, mins, maxs, end, MoveClip::Normal, Some(ent));
+    if trace.fraction == 1.0 || trace.allsolid {
+        return_float(sv, 0.0);
+        return;
+    }
+    sv.edicts[ent].v.set_vector(progdefs::ORIGIN, trace.endpos);
+    sv.link_edict(ent, false, vm);
+    {
+        let v = &mut sv.edicts[ent].v;
+        let flags = v.float(progdefs::FLAGS) as i32 | server::FL_ONGROUND;
+        v.set_float(progdefs::FLAGS, flags as f32);
+        v.set_int(progdefs::GROUNDENTITY, trace.ent.unwrap_or(0) as i32);
+    }
+    return_float(sv, 1.0);
+}
+
+/// Returns the direction to shoot in, autoaiming at the entity closest to
+/// v_forward that can take damage.
+fn aim(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match edict(vm, sv, 0) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let mut start = sv.edicts[ent].v.vector(progdefs::ORIGIN);
+    start[2] += 20.0;
+    let teamplay = sv.cvars.value("teamplay") != 0.0;
+    let team = sv.edicts[ent].v.float(progdefs::TEAM);
+
+    // try sending a trace straight
+    let forward = sv.globals.vector(progdefs::G_V_FORWARD);
+    let end = [start[0] + 2048.0 * forward[0], start[1] + 2048.0 * forward[1], start[2] + 2048.0 * forward[2]];
+    let trace = sv.trace_move(start, [0.0; 3], [0.0; 3], end, MoveClip::Normal, Some(ent));
+    if let Some(hit) = trace.ent {
+        let v = &sv.edicts[hit].v;
+        if hit != 0 && v.float(progdefs::TAKEDAMAGE) == DAMAGE_AIM && (!teamplay || team <= 0.0 || team != v.float(progdefs::TEAM)) {
+            return_vector(sv, forward);
+            return;
+        }
+    }
+
+    // try all possible entities
+    let mut bestdist = sv.cvars.value("sv_aim");
+    let mut bestent = None;
+    for check in 1..sv.edicts.len() {
+        let v = &sv.edicts[check].v;
+        if sv.edicts[check].free || v.float(progdefs::TAKEDAMAGE) != DAMAGE_AIM || check == ent {
+            continue;
+        }
+        if teamplay && team > 0.0 && team == v.float(progdefs::TEAM) {
+            // don't aim at teammate
+            continue;
+        }
+        let (origin, mins, maxs) = (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS));
+        let end = [
+            origin[0] + 0.5 * (mins[0] + maxs[0]),
+            origin[1] + 0.5 * (mins[1] + maxs[1]),
+            origin[2] + 0.5 * (mins[2] + maxs[2]),
+        ];
+        let mut dir = vector_subtract(&end, &start);
+        vector_normalize(&mut dir);
+        let dist = dot_product(&dir, &forward);
+        if dist < bestdist {
+            // to far to turn
+            continue;
+        }
+        let trace = sv.trace_move(start, [0.0; 3], [0.0; 3], end, MoveClip::Normal, Some(ent));
+        if trace.ent == Some(check) {
+            // can shoot at this one
+            bestdist = dist;
+            bestent = Some(check);
+        }
+    }
+
+    match bestent {
+        Some(best) => {
+            let dir = vector_subtract(&sv.edicts[best].v.vector(progdefs::ORIGIN), &sv.edicts[ent].v.vector(progdefs::ORIGIN));
+            let dist = dot_product(&dir, &forward);
+            let mut end = vector_scale(&forward, dist);
+            end[2] = dir[2];
+            vector_normalize(&mut end);
+            return_vector(sv, end);
+        }
+        None => return_vector(sv, forward),
+    }
+}
+
+/// Returns the next used entity, or the world after the last one.
+fn nextent(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match edict(vm, sv, 0) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let next = (ent + 1..sv.edicts.len()).find(|&i| !sv.edicts[i].free);
+    return_int(sv, next.unwrap_or(0) as i32);
+}
+
+/// Starts a particle effect on all clients.
+fn particle(sv : &mut Server) {
+    let (origin, dir, color, count) = (vector(sv, 0), vector(sv, 1), float(sv, 2) as i32, float(sv, 3) as i32);
+    if sv.datagram.len() > MAX_DATAGRAM - 16 {
+        return;
+    }
+    ServerMessage::Particle { origin : origin, dir : dir, count : count, color : color }.write(&mut sv.datagram);
+}
+
+/// Returns the message a Write builtin writes to.
+fn write_dest<'a>(vm : &mut ProgsDat, sv : &'a mut Server) -> Option<&'a mut SizeBuf> {
+    match float(sv, 0) as i32 {
+        MSG_BROADCAST => Some(&mut sv.datagram),
+        MSG_ONE => {
+            let ent = sv.globals.int(progdefs::G_MSG_ENTITY);
+            if ent < 1 || ent as usize > sv.max_clients {
+                vm.run_error(sv, "WriteDest: not a client");
+                return None;
+            }
+            Some(&mut sv.clients[ent as usize - 1].message)
+        }
+        MSG_ALL => Some(&mut sv.reliable_datagram),
+        MSG_INIT => Some(&mut sv.signon),
+        _ => {
+            vm.run_error(sv, "WriteDest: bad destination");
+            None
+        }
+    }
+}
+
+/// Writes a value to a message, used by the WriteByte to WriteEntity builtins.
+fn write(num : i32, vm : &mut ProgsDat, sv : &mut Server) {
+    let (value, s) = (float(sv, 1), string(sv, 1));
+    let ent = int(sv, 1);
+    let buf = match write_dest(vm, sv) {
+        Some(buf) => buf,
+        None => return,
+    };
+    match num {
+        52 => buf.write_byte(value as i32),
+        53 => buf.write_char(value as i32),
+        54 => buf.write_short(value as i32),
+        55 => buf.write_long(value as i32),
+        56 => buf.write_coord(value),
+        57 => buf.write_angle(value),
+        58 => buf.write_string(&s),
+        _ => buf.write_short(ent),
+    }
+}
+
+/// Sends an entity that never changes to the clients as part of the
+/// signon and removes it.
+fn makestatic(vm : &mut ProgsDat, sv : &mut Server) {
+    let ent = match edict(vm, sv, 0) {
+        Some(ent) => ent,
+        None => return,
+    };
+    let state = {
+        let v = &sv.edicts[ent].v;
+        EntityState {
+            origin : v.vector(progdefs::ORIGIN),
+            angles : v.vector(progdefs::ANGLES),
+            modelindex : sv.model_index(sv.strings.get(v.int(progdefs::MODEL))),
+            frame : v.float(progdefs::FRAME) as i32,
+            colormap : v.float(progdefs::COLORMAP) as i32,
+            skin : v.float(progdefs::SKIN) as i32,
+            effects : 0,
+        }
+    };
+    ServerMessage::SpawnStatic(state).write(&mut sv.signon);
+
+    // throw the entity away now
+    sv.free_edict(ent);
+}
+
+/// Changes the level once, the host runs the changelevel command after the
+/// frame.
+fn changelevel(sv : &mut Server) {
+    // make sure we don't issue two changelevels
+    if sv.changelevel_issued {
+        return;
+    }
+    sv.changelevel_issued = true;
+    let level = string(sv, 0);
+    sv.commands.push_str(&format!("changelevel {}\n", level));
+}
+
+/// Changes a cvar, the host's cvars are updated after the frame.
+fn cvar_set(sv : &mut Server) {
+    let (name, value) = (string(sv, 0), string(sv, 1));
+    if sv.cvars.set(&name, &value) {
+        sv.commands.push_str(&format!("{} \"{}\"\n", name, value));
+    } else {
+        println!("Cvar_Set: variable {} not found", name);
+    }
+}
+
+/// Starts a looping sound at a fixed position on all clients.
+fn ambientsound(sv : &mut Server) {
+    let (pos, samp) = (vector(sv, 0), string(sv, 1));
+    let (volume, attenuation) = ((float(sv, 2) * 255.0) as i32, float(sv, 3));
+
+    // check to see if samp was properly precached
+    let sound = match sv.sound_precache.iter().skip(1).position(|s| *s == samp) {
+        Some(index) => index as i32 + 1,
+        None => {
+            println!("no precache: {}", samp);
+            return;
+        }
+    };
+    ServerMessage::SpawnStaticSound { origin : pos, sound : sound, volume : volume, attenuation : attenuation }.write(&mut sv.signon);
+}
+
+/// Copies the spawn parameters of a client to parm1 to parm16.
+fn setspawnparms(vm : &mut ProgsDat, sv : &mut Server) {
+    let client = match client(sv, 0) {
+        Some(client) => client,
+        None => {
+            vm.run_error(sv, "Entity is not a client");
+            return;
+        }
+    };
+    for i in 0..server::NUM_SPAWN_PARMS {
+        let parm = sv.clients[client].spawn_parms[i];
+        sv.globals.set_float(progdefs::G_PARM1 + i, parm);
+    }
+}
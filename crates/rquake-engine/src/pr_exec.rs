@@ -0,0 +1,1063 @@
+#![warn(missing_docs)]
+
+//! Loader and interpreter of the compiled QuakeC programs in progs.dat.
+//!
+//! Original source can be found in pr_edict.c, pr_exec.c and pr_comp.h
+
+use std::borrow::Cow;
+
+use rquake_fs::ReadError;
+use pr_cmds;
+use progdefs::{self, Def, DefType};
+use progs::{Progs, StringTable};
+use server::Server;
+
+/// Version of the progs.dat format.
+const PROG_VERSION : i32 = 6;
+/// Checksum of the engine fields and globals the progs have been compiled with.
+const PROGHEADER_CRC : i32 = 5927;
+/// Maximum number of nested QuakeC function calls.
+const MAX_STACK_DEPTH : usize = 32;
+/// Maximum number of saved locals of the nested function calls.
+const LOCALSTACK_SIZE : usize = 2048;
+/// Number of statements a single call may run before it's stopped.
+const MAX_RUNAWAY : i32 = 100000;
+
+/// Global definitions with this bit are saved in save games.
+pub const DEF_SAVEGLOBAL : u16 = 1 << 15;
+
+/// No value.
+pub const EV_VOID : u16 = 0;
+/// Index of a string.
+pub const EV_STRING : u16 = 1;
+/// Float value.
+pub const EV_FLOAT : u16 = 2;
+/// Three floats.
+pub const EV_VECTOR : u16 = 3;
+/// Entity number.
+pub const EV_ENTITY : u16 = 4;
+/// Offset of an entity field.
+pub const EV_FIELD : u16 = 5;
+/// Function number.
+pub const EV_FUNCTION : u16 = 6;
+/// Entity field of an entity.
+pub const EV_POINTER : u16 = 7;
+
+const OP_DONE : u16 = 0;
+const OP_MUL_F : u16 = 1;
+const OP_MUL_V : u16 = 2;
+const OP_MUL_FV : u16 = 3;
+const OP_MUL_VF : u16 = 4;
+const OP_DIV_F : u16 = 5;
+const OP_ADD_F : u16 = 6;
+const OP_ADD_V : u16 = 7;
+const OP_SUB_F : u16 = 8;
+const OP_SUB_V : u16 = 9;
+const OP_EQ_F : u16 = 10;
+const OP_EQ_V : u16 = 11;
+const OP_EQ_S : u16 = 12;
+const OP_EQ_E : u16 = 13;
+const OP_EQ_FNC : u16 = 14;
+const OP_NE_F : u16 = 15;
+const OP_NE_V : u16 = 16;
+const OP_NE_S : u16 = 17;
+const OP_NE_E : u16 = 18;
+const OP_NE_FNC : u16 = 19;
+const OP_LE : u16 = 20;
+const OP_GE : u16 = 21;
+const OP_LT : u16 = 22;
+const OP_GT : u16 = 23;
+const OP_LOAD_F : u16 = 24;
+const OP_LOAD_V : u16 = 25;
+const OP_LOAD_S : u16 = 26;
+const OP_LOAD_ENT : u16 = 27;
+const OP_LOAD_FLD : u16 = 28;
+const OP_LOAD_FNC : u16 = 29;
+const OP_ADDRESS : u16 = 30;
+const OP_STORE_F : u16 = 31;
+const OP_STORE_V : u16 = 32;
+const OP_STORE_S : u16 = 33;
+const OP_STORE_ENT : u16 = 34;
+const OP_STORE_FLD : u16 = 35;
+const OP_STORE_FNC : u16 = 36;
+const OP_STOREP_F : u16 = 37;
+const OP_STOREP_V : u16 = 38;
+const OP_STOREP_S : u16 = 39;
+const OP_STOREP_ENT : u16 = 40;
+const OP_STOREP_FLD : u16 = 41;
+const OP_STOREP_FNC : u16 = 42;
+const OP_RETURN : u16 = 43;
+const OP_NOT_F : u16 = 44;
+const OP_NOT_V : u16 = 45;
+const OP_NOT_S : u16 = 46;
+const OP_NOT_ENT : u16 = 47;
+const OP_NOT_FNC : u16 = 48;
+const OP_IF : u16 = 49;
+const OP_IFNOT : u16 = 50;
+const OP_CALL0 : u16 = 51;
+const OP_CALL8 : u16 = 59;
+const OP_STATE : u16 = 60;
+const OP_GOTO : u16 = 61;
+const OP_AND : u16 = 62;
+const OP_OR : u16 = 63;
+const OP_BITAND : u16 = 64;
+const OP_BITOR : u16 = 65;
+
+const OP_NAMES : [&'static str; 66] = [
+    "DONE", "MUL_F", "MUL_V", "MUL_FV", "MUL_VF", "DIV", "ADD_F", "ADD_V", "SUB_F", "SUB_V",
+    "EQ_F", "EQ_V", "EQ_S", "EQ_E", "EQ_FNC", "NE_F", "NE_V", "NE_S", "NE_E", "NE_FNC",
+    "LE", "GE", "LT", "GT", "INDIRECT", "INDIRECT", "INDIRECT", "INDIRECT", "INDIRECT", "INDIRECT",
+    "ADDRESS", "STORE_F", "STORE_V", "STORE_S", "STORE_ENT", "STORE_FLD", "STORE_FNC",
+    "STOREP_F", "STOREP_V", "STOREP_S", "STOREP_ENT", "STOREP_FLD", "STOREP_FNC",
+    "RETURN", "NOT_F", "NOT_V", "NOT_S", "NOT_ENT", "NOT_FNC", "IF", "IFNOT",
+    "CALL0", "CALL1", "CALL2", "CALL3", "CALL4", "CALL5", "CALL6", "CALL7", "CALL8",
+    "STATE", "GOTO", "AND", "OR", "BITAND", "BITOR",
+];
+
+/// A single instruction with up to three global offsets.
+#[derive(Clone, Copy, Debug)]
+struct Statement {
+    op : u16,
+    a : i16,
+    b : i16,
+    c : i16,
+}
+
+/// A QuakeC function or a builtin, builtins have a negative first
+/// statement.
+#[derive(Clone, Debug)]
+struct Function {
+    first_statement : i32,
+    parm_start : usize,
+    locals : usize,
+    name : i32,
+    file : i32,
+    numparms : usize,
+    parm_size : [u8; 8],
+}
+
+/// Definition of a global or an entity field.
+#[derive(Clone, Copy, Debug)]
+pub struct DDef {
+    /// One of the EV_ types, globals may have DEF_SAVEGLOBAL set.
+    pub kind : u16,
+    /// Offset of the global or field.
+    pub ofs : usize,
+    /// Offset of the name in the strings.
+    pub name : i32,
+}
+
+/// Call of a QuakeC function that is running.
+#[derive(Clone, Copy, Debug)]
+struct StackEntry {
+    statement : usize,
+    function : usize,
+}
+
+/// The compiled QuakeC programs.
+pub struct ProgsDat {
+    statements : Vec<Statement>,
+    functions : Vec<Function>,
+    globaldefs : Vec<DDef>,
+    fielddefs : Vec<DDef>,
+    strings : Vec<u8>,
+    globals : Vec<u32>,
+    entity_fields : usize,
+    stack : Vec<StackEntry>,
+    localstack : Vec<u32>,
+    xfunction : usize,
+    xstatement : usize,
+    argc : usize,
+    trace : bool,
+    seed : u32,
+}
+
+fn read_i32(data : &[u8], ofs : usize) -> Result<i32, ReadError> {
+    match data.get(ofs..ofs + 4) {
+        Some(bytes) => Ok(bytes[0] as i32 | (bytes[1] as i32) << 8 | (bytes[2] as i32) << 16 | (bytes[3] as i32) << 24),
+        None => Err(ReadError::ParseError),
+    }
+}
+
+fn read_u16(data : &[u8], ofs : usize) -> Result<u16, ReadError> {
+    match data.get(ofs..ofs + 2) {
+        Some(bytes) => Ok(bytes[0] as u16 | (bytes[1] as u16) << 8),
+        None => Err(ReadError::ParseError),
+    }
+}
+
+/// Reads count entries of the given size at the offset of a header value.
+fn read_lump<T, F>(data : &[u8], ofs : i32, count : i32, size : usize, read_entry : F) -> Result<Vec<T>, ReadError>
+    where F : Fn(&[u8]) -> Result<T, ReadError> {
+    if ofs < 0 || count < 0 {
+        return Err(ReadError::ParseError);
+    }
+    let start = ofs as usize;
+    let end = start + count as usize * size;
+    let lump = data.get(start..end).ok_or(ReadError::ParseError)?;
+    lump.chunks(size).map(read_entry).collect()
+}
+
+fn read_def(entry : &[u8]) -> Result<DDef, ReadError> {
+    Ok(DDef {
+        kind : read_u16(entry, 0)?,
+        ofs : read_u16(entry, 2)? as usize,
+        name : read_i32(entry, 4)?,
+    })
+}
+
+/// Returns a global, offsets outside of the globals read as 0.
+fn get(g : &[u32], ofs : usize) -> u32 {
+    g.get(ofs).cloned().unwrap_or(0)
+}
+
+fn get_f(g : &[u32], ofs : usize) -> f32 {
+    f32::from_bits(get(g, ofs))
+}
+
+fn get_v(g : &[u32], ofs : usize) -> [f32; 3] {
+    [get_f(g, ofs), get_f(g, ofs + 1), get_f(g, ofs + 2)]
+}
+
+/// Changes a global, offsets outside of the globals are ignored.
+fn set(g : &mut [u32], ofs : usize, value : u32) {
+    if let Some(v) = g.get_mut(ofs) {
+        *v = value;
+    }
+}
+
+fn set_f(g : &mut [u32], ofs : usize, value : f32) {
+    set(g, ofs, value.to_bits());
+}
+
+fn set_bool(g : &mut [u32], ofs : usize, value : bool) {
+    set_f(g, ofs, if value { 1.0 } else { 0.0 });
+}
+
+/// Formats a float like printf's %5.1f.
+fn float_string(value : f32) -> String {
+    format!("{:5.1}", value)
+}
+
+impl ProgsDat {
+    /// Reads progs.dat, the programs have to be compiled with the fields and
+    /// globals of the engine.
+    pub fn read(data : &[u8]) -> Result<ProgsDat, ReadError> {
+        let mut header = [0i32; 15];
+        for (i, value) in header.iter_mut().enumerate() {
+            *value = read_i32(data, i * 4)?;
+        }
+        let [version, crc, ofs_statements, numstatements, ofs_globaldefs, numglobaldefs, ofs_fielddefs, numfielddefs,
+             ofs_functions, numfunctions, ofs_strings, numstrings, ofs_globals, numglobals, entityfields] = header;
+        if version != PROG_VERSION {
+            println!("progs.dat has wrong version number ({} should be {})", version, PROG_VERSION);
+            return Err(ReadError::ParseError);
+        }
+        if crc != PROGHEADER_CRC {
+            println!("progs.dat system vars have been modified, progdefs.h is out of date");
+            return Err(ReadError::ParseError);
+        }
+        if (numglobals as usize) < progdefs::GLOBAL_COUNT || (entityfields as usize) < progdefs::ENTITY_FIELDS {
+            println!("progs.dat doesn't define the engine globals and fields");
+            return Err(ReadError::ParseError);
+        }
+
+        let statements = read_lump(data, ofs_statements, numstatements, 8, |entry| {
+            Ok(Statement {
+                op : read_u16(entry, 0)?,
+                a : read_u16(entry, 2)? as i16,
+                b : read_u16(entry, 4)? as i16,
+                c : read_u16(entry, 6)? as i16,
+            })
+        })?;
+        let functions = read_lump(data, ofs_functions, numfunctions, 36, |entry| {
+            let mut parm_size = [0; 8];
+            parm_size.copy_from_slice(&entry[28..36]);
+            Ok(Function {
+                first_statement : read_i32(entry, 0)?,
+                parm_start : read_i32(entry, 4)?.max(0) as usize,
+                locals : read_i32(entry, 8)?.max(0) as usize,
+                name : read_i32(entry, 16)?,
+                file : read_i32(entry, 20)?,
+                numparms : read_i32(entry, 24)?.max(0).min(8) as usize,
+                parm_size : parm_size,
+            })
+        })?;
+        // string 0 is the empty string
+        let strings = read_lump(data, ofs_strings, numstrings, 1, |entry| Ok(entry[0]))?;
+        if strings.first() != Some(&0) {
+            println!("progs.dat doesn't start with an empty string");
+            return Err(ReadError::ParseError);
+        }
+
+        // functions have to start inside of the statements, statement 0 is
+        // only used by the null function
+        for function in functions.iter().skip(1) {
+            let first = function.first_statement;
+            if first == 0 || (first > 0 && first as usize >= statements.len()) {
+                println!("progs.dat has a function outside of the statements");
+                return Err(ReadError::ParseError);
+            }
+        }
+        if statements.last().map_or(false, |st| st.op != OP_DONE && st.op != OP_RETURN) {
+            println!("progs.dat doesn't end with a return");
+            return Err(ReadError::ParseError);
+        }
+
+        Ok(ProgsDat {
+            statements : statements,
+            functions : functions,
+            globaldefs : read_lump(data, ofs_globaldefs, numglobaldefs, 8, read_def)?,
+            fielddefs : read_lump(data, ofs_fielddefs, numfielddefs, 8, read_def)?,
+            strings : strings,
+            globals : read_lump(data, ofs_globals, numglobals, 4, |entry| Ok(read_i32(entry, 0)? as u32))?,
+            entity_fields : entityfields as usize,
+            stack : Vec::new(),
+            localstack : Vec::new(),
+            xfunction : 0,
+            xstatement : 0,
+            argc : 0,
+            trace : false,
+            seed : 1,
+        })
+    }
+
+    /// Returns a string of the progs.
+    fn string(&self, ofs : i32) -> String {
+        if ofs < 0 {
+            return String::new();
+        }
+        self.strings.iter().skip(ofs as usize).take_while(|&&c| c != 0).map(|&c| c as char).collect()
+    }
+
+    /// Returns the name of a definition.
+    pub fn def_name(&self, def : &DDef) -> String {
+        self.string(def.name)
+    }
+
+    /// Converts the definitions with one of the given types, vector
+    /// components like origin_x are skipped.
+    fn defs(&self, defs : &[DDef], types : &[u16]) -> Vec<Def> {
+        defs.iter().filter_map(|def| {
+            let name = self.def_name(def);
+            if name.len() > 2 && name.as_bytes()[name.len() - 2] == b'_' {
+                return None;
+            }
+            let kind = match def.kind & !DEF_SAVEGLOBAL {
+                kind if !types.contains(&kind) => return None,
+                EV_STRING => DefType::String,
+                EV_FLOAT => DefType::Float,
+                EV_VECTOR => DefType::Vector,
+                EV_ENTITY => DefType::Entity,
+                EV_FUNCTION => DefType::Function,
+                _ => return None,
+            };
+            Some(Def { name : Cow::Owned(name), ofs : def.ofs, kind : kind })
+        }).collect()
+    }
+
+    /// Returns the number of parameters passed to the running builtin.
+    pub fn argc(&self) -> usize {
+        self.argc
+    }
+
+    /// Returns a random number from 0 to 1.
+    pub fn random(&mut self) -> f32 {
+        (::r_part::rand(&mut self.seed) & 0x7fff) as f32 / 0x7fff as f32
+    }
+
+    /// Returns the seed of the random numbers, monster movement continues
+    /// the same sequence.
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    /// Changes the seed of the random numbers.
+    pub fn set_seed(&mut self, seed : u32) {
+        self.seed = seed;
+    }
+
+    /// Turns printing every statement on or off.
+    pub fn set_trace(&mut self, trace : bool) {
+        self.trace = trace;
+    }
+
+    /// Formats a value of the given type.
+    fn value_string(&self, sv : &Server, kind : u16, values : &[u32]) -> String {
+        let value = values.first().cloned().unwrap_or(0);
+        match kind & !DEF_SAVEGLOBAL {
+            EV_STRING => sv.strings.get(value as i32).to_string(),
+            EV_ENTITY => format!("entity {}", value as i32),
+            EV_FUNCTION => format!("{}()", self.function_name(value as i32).unwrap_or_default()),
+            EV_FIELD => {
+                let name = self.fielddefs.iter().find(|def| def.ofs == value as usize).map(|def| self.def_name(def));
+                format!(".{}", name.unwrap_or_default())
+            }
+            EV_VOID => "void".to_string(),
+            EV_FLOAT => float_string(f32::from_bits(value)),
+            EV_VECTOR => {
+                let v : Vec<f32> = (0..3).map(|i| f32::from_bits(values.get(i).cloned().unwrap_or(0))).collect();
+                format!("'{} {} {}'", float_string(v[0]), float_string(v[1]), float_string(v[2]))
+            }
+            EV_POINTER => "pointer".to_string(),
+            kind => format!("bad type {}", kind),
+        }
+    }
+
+    /// Prints the fields of an entity that aren't zero.
+    pub fn print_edict(&self, sv : &Server, ent : usize) {
+        let e = match sv.edicts.get(ent) {
+            Some(e) => e,
+            None => return,
+        };
+        println!("\nEDICT {}:", ent);
+        if e.free {
+            println!("FREE");
+            return;
+        }
+        for def in &self.fielddefs {
+            let name = self.def_name(def);
+            // skip the components of vectors
+            if name.ends_with("_x") || name.ends_with("_y") || name.ends_with("_z") {
+                continue;
+            }
+            let size = if def.kind == EV_VECTOR { 3 } else { 1 };
+            let values = match e.v.raw().get(def.ofs..def.ofs + size) {
+                Some(values) => values,
+                None => continue,
+            };
+            if values.iter().all(|&value| value == 0) {
+                continue;
+            }
+            println!("{:<15}{}", name, self.value_string(sv, def.kind, values));
+        }
+    }
+
+    /// Prints all entities.
+    pub fn print_edicts(&self, sv : &Server) {
+        println!("{} entities", sv.edicts.len());
+        for ent in 0..sv.edicts.len() {
+            self.print_edict(sv, ent);
+        }
+    }
+
+    /// Prints a statement with its operands.
+    fn print_statement(&self, sv : &Server, st : &Statement) {
+        let name = OP_NAMES.get(st.op as usize).cloned().unwrap_or("BAD");
+        let mut line = format!("{:<10}", name);
+        let operand = |ofs : i16| {
+            let ofs = ofs as u16 as usize;
+            match self.globaldefs.iter().find(|def| def.ofs == ofs) {
+                Some(def) => {
+                    let size = if def.kind & !DEF_SAVEGLOBAL == EV_VECTOR { 3 } else { 1 };
+                    let values : Vec<u32> = (0..size).map(|i| get(sv.globals.raw(), ofs + i)).collect();
+                    format!("{}({})", self.def_name(def), self.value_string(sv, def.kind, &values))
+                }
+                None => format!("{}(???)", ofs),
+            }
+        };
+        match st.op {
+            OP_IF | OP_IFNOT => line += &format!("{}branch {}", operand(st.a), st.b),
+            OP_GOTO => line += &format!("branch {}", st.a),
+            OP_STORE_F ..= OP_STORE_FNC => line += &format!("{}{}", operand(st.a), operand(st.b)),
+            _ => {
+                for &ofs in &[st.a, st.b, st.c] {
+                    if ofs != 0 {
+                        line += &operand(ofs);
+                    }
+                }
+            }
+        }
+        println!("{}", line);
+    }
+
+    /// Prints the running functions.
+    fn stack_trace(&self) {
+        if self.stack.is_empty() {
+            println!("<NO STACK>");
+            return;
+        }
+        let calls = self.stack.iter().skip(1).map(|entry| entry.function).chain(Some(self.xfunction));
+        for function in calls.collect::<Vec<_>>().into_iter().rev() {
+            match self.functions.get(function) {
+                Some(f) if function != 0 => println!("{:>12} : {}", self.string(f.file), self.string(f.name)),
+                _ => println!("<NO FUNCTION>"),
+            }
+        }
+    }
+
+    /// Prints the failed statement and the running functions and stops the
+    /// programs. The host shuts the server down.
+    pub fn run_error(&mut self, sv : &mut Server, message : &str) {
+        if let Some(st) = self.statements.get(self.xstatement).cloned() {
+            self.print_statement(sv, &st);
+        }
+        self.stack_trace();
+        println!("{}", message);
+        self.stack.clear();
+        self.localstack.clear();
+        sv.program_error = true;
+    }
+
+    /// Saves the locals of a function, copies the parameters into them and
+    /// returns the statement before the first one of the function.
+    fn enter_function(&mut self, sv : &mut Server, function : usize) -> Option<usize> {
+        self.stack.push(StackEntry { statement : self.xstatement, function : self.xfunction });
+        if self.stack.len() >= MAX_STACK_DEPTH {
+            self.run_error(sv, "stack overflow");
+            return None;
+        }
+
+        // save off any locals that the new function steps on
+        let f = self.functions[function].clone();
+        if self.localstack.len() + f.locals > LOCALSTACK_SIZE {
+            self.run_error(sv, "PR_ExecuteProgram: locals stack overflow");
+            return None;
+        }
+        let g = sv.globals.raw_mut();
+        for i in 0..f.locals {
+            self.localstack.push(get(g, f.parm_start + i));
+        }
+
+        // copy parameters
+        let mut o = f.parm_start;
+        for i in 0..f.numparms {
+            for j in 0..f.parm_size[i] as usize {
+                let value = get(g, progdefs::OFS_PARM0 + i * 3 + j);
+                set(g, o, value);
+                o += 1;
+            }
+        }
+
+        self.xfunction = function;
+        Some(f.first_statement as usize - 1)
+    }
+
+    /// Restores the locals of the function and returns the statement it was
+    /// called from.
+    fn leave_function(&mut self, sv : &mut Server) -> Option<usize> {
+        let entry = match self.stack.pop() {
+            Some(entry) => entry,
+            None => {
+                self.run_error(sv, "prog stack underflow");
+                return None;
+            }
+        };
+
+        // restore locals from the stack
+        let f = &self.functions[self.xfunction];
+        let start = self.localstack.len().saturating_sub(f.locals);
+        let g = sv.globals.raw_mut();
+        for (i, &value) in self.localstack[start..].iter().enumerate() {
+            set(g, f.parm_start + i, value);
+        }
+        self.localstack.truncate(start);
+
+        self.xfunction = entry.function;
+        Some(entry.statement)
+    }
+
+    /// Returns the entity and field addressed by a pointer, None if it's
+    /// outside of the entities.
+    fn pointer(&self, sv : &Server, ptr : u32, i : usize) -> Option<(usize, usize)> {
+        let ent = ptr as usize / sv.entity_fields;
+        let ofs = ptr as usize % sv.entity_fields + i;
+        if ent < sv.edicts.len() && ofs < sv.entity_fields {
+            Some((ent, ofs))
+        } else {
+            None
+        }
+    }
+
+    /// Runs a function until it returns, builtins may call this again.
+    fn execute_program(&mut self, sv : &mut Server, fnum : i32) {
+        if fnum <= 0 || fnum as usize >= self.functions.len() {
+            let self_ent = sv.globals.int(progdefs::G_SELF);
+            if self_ent != 0 {
+                self.print_edict(sv, self_ent as usize);
+            }
+            self.run_error(sv, "PR_ExecuteProgram: NULL function");
+            return;
+        }
+
+        let mut runaway = MAX_RUNAWAY;
+        let exitdepth = self.stack.len();
+        let mut s = match self.enter_function(sv, fnum as usize) {
+            Some(s) => s,
+            None => return,
+        };
+
+        loop {
+            // next statement
+            s += 1;
+            let st = match self.statements.get(s) {
+                Some(&st) => st,
+                None => {
+                    self.run_error(sv, "PR_ExecuteProgram: statement out of range");
+                    return;
+                }
+            };
+            self.xstatement = s;
+            runaway -= 1;
+            if runaway == 0 {
+                self.run_error(sv, "runaway loop error");
+                return;
+            }
+            if self.trace {
+                self.print_statement(sv, &st);
+            }
+
+            let a = st.a as u16 as usize;
+            let b = st.b as u16 as usize;
+            let c = st.c as u16 as usize;
+            match st.op {
+                OP_ADD_F | OP_SUB_F | OP_MUL_F | OP_DIV_F | OP_BITAND | OP_BITOR | OP_AND | OP_OR |
+                OP_LE | OP_GE | OP_LT | OP_GT | OP_EQ_F | OP_NE_F => {
+                    let g = sv.globals.raw_mut();
+                    let (x, y) = (get_f(g, a), get_f(g, b));
+                    let result = match st.op {
+                        OP_ADD_F => x + y,
+                        OP_SUB_F => x - y,
+                        OP_MUL_F => x * y,
+                        OP_DIV_F => x / y,
+                        OP_BITAND => (x as i32 & y as i32) as f32,
+                        OP_BITOR => (x as i32 | y as i32) as f32,
+                        OP_AND => if x != 0.0 && y != 0.0 { 1.0 } else { 0.0 },
+                        OP_OR => if x != 0.0 || y != 0.0 { 1.0 } else { 0.0 },
+                        OP_LE => if x <= y { 1.0 } else { 0.0 },
+                        OP_GE => if x >= y { 1.0 } else { 0.0 },
+                        OP_LT => if x < y { 1.0 } else { 0.0 },
+                        OP_GT => if x > y { 1.0 } else { 0.0 },
+                        OP_EQ_F => if x == y { 1.0 } else { 0.0 },
+                        _ => if x != y { 1.0 } else { 0.0 },
+                    };
+                    set_f(g, c, result);
+                }
+                OP_ADD_V | OP_SUB_V => {
+                    let g = sv.globals.raw_mut();
+                    let (x, y) = (get_v(g, a), get_v(g, b));
+                    for i in 0..3 {
+                        set_f(g, c + i, if st.op == OP_ADD_V { x[i] + y[i] } else { x[i] - y[i] });
+                    }
+                }
+                OP_MUL_V => {
+                    let g = sv.globals.raw_mut();
+                    let (x, y) = (get_v(g, a), get_v(g, b));
+                    set_f(g, c, x[0] * y[0] + x[1] * y[1] + x[2] * y[2]);
+                }
+                OP_MUL_FV | OP_MUL_VF => {
+                    let g = sv.globals.raw_mut();
+                    let (f, v) = if st.op == OP_MUL_FV { (get_f(g, a), get_v(g, b)) } else { (get_f(g, b), get_v(g, a)) };
+                    for i in 0..3 {
+                        set_f(g, c + i, f * v[i]);
+                    }
+                }
+                OP_NOT_F => {
+                    let g = sv.globals.raw_mut();
+                    let value = get_f(g, a) == 0.0;
+                    set_bool(g, c, value);
+                }
+                OP_NOT_V => {
+                    let g = sv.globals.raw_mut();
+                    let value = get_v(g, a) == [0.0; 3];
+                    set_bool(g, c, value);
+                }
+                OP_NOT_S => {
+                    let value = sv.strings.get(get(sv.globals.raw(), a) as i32).is_empty();
+                    set_bool(sv.globals.raw_mut(), c, value);
+                }
+                OP_NOT_FNC | OP_NOT_ENT => {
+                    let g = sv.globals.raw_mut();
+                    let value = get(g, a) == 0;
+                    set_bool(g, c, value);
+                }
+                OP_EQ_V | OP_NE_V => {
+                    let g = sv.globals.raw_mut();
+                    let equal = get_v(g, a) == get_v(g, b);
+                    set_bool(g, c, equal == (st.op == OP_EQ_V));
+                }
+                OP_EQ_S | OP_NE_S => {
+                    let equal = {
+                        let g = sv.globals.raw();
+                        sv.strings.get(get(g, a) as i32) == sv.strings.get(get(g, b) as i32)
+                    };
+                    set_bool(sv.globals.raw_mut(), c, equal == (st.op == OP_EQ_S));
+                }
+                OP_EQ_E | OP_EQ_FNC | OP_NE_E | OP_NE_FNC => {
+                    let g = sv.globals.raw_mut();
+                    let equal = get(g, a) == get(g, b);
+                    set_bool(g, c, equal == (st.op == OP_EQ_E || st.op == OP_EQ_FNC));
+                }
+                OP_STORE_F | OP_STORE_ENT | OP_STORE_FLD | OP_STORE_S | OP_STORE_FNC => {
+                    let g = sv.globals.raw_mut();
+                    let value = get(g, a);
+                    set(g, b, value);
+                }
+                OP_STORE_V => {
+                    let g = sv.globals.raw_mut();
+                    for i in 0..3 {
+                        let value = get(g, a + i);
+                        set(g, b + i, value);
+                    }
+                }
+                OP_STOREP_F | OP_STOREP_ENT | OP_STOREP_FLD | OP_STOREP_S | OP_STOREP_FNC | OP_STOREP_V => {
+                    let size = if st.op == OP_STOREP_V { 3 } else { 1 };
+                    let ptr = get(sv.globals.raw(), b);
+                    for i in 0..size {
+                        match self.pointer(sv, ptr, i) {
+                            Some((ent, ofs)) => {
+                                let value = get(sv.globals.raw(), a + i);
+                                sv.edicts[ent].v.raw_mut()[ofs] = value;
+                            }
+                            None => {
+                                self.run_error(sv, "PR_ExecuteProgram: bad pointer");
+                                return;
+                            }
+                        }
+                    }
+                }
+                OP_ADDRESS => {
+                    let (ent, field) = (get(sv.globals.raw(), a) as usize, get(sv.globals.raw(), b) as usize);
+                    if ent == 0 && sv.active {
+                        self.run_error(sv, "assignment to world entity");
+                        return;
+                    }
+                    if ent >= sv.edicts.len() || field >= sv.entity_fields {
+                        self.run_error(sv, "PR_ExecuteProgram: bad entity or field");
+                        return;
+                    }
+                    let ptr = (ent * sv.entity_fields + field) as u32;
+                    set(sv.globals.raw_mut(), c, ptr);
+                }
+                OP_LOAD_F | OP_LOAD_FLD | OP_LOAD_ENT | OP_LOAD_S | OP_LOAD_FNC | OP_LOAD_V => {
+                    let size = if st.op == OP_LOAD_V { 3 } else { 1 };
+                    let (ent, field) = (get(sv.globals.raw(), a) as usize, get(sv.globals.raw(), b) as usize);
+                    let values = match sv.edicts.get(ent).and_then(|e| e.v.raw().get(field..field + size)) {
+                        Some(values) => values.to_vec(),
+                        None => {
+                            self.run_error(sv, "PR_ExecuteProgram: bad entity or field");
+                            return;
+                        }
+                    };
+                    let g = sv.globals.raw_mut();
+                    for (i, value) in values.into_iter().enumerate() {
+                        set(g, c + i, value);
+                    }
+                }
+                OP_IFNOT => {
+                    if get(sv.globals.raw(), a) == 0 {
+                        s = (s as isize + st.b as isize - 1) as usize;
+                    }
+                }
+                OP_IF => {
+                    if get(sv.globals.raw(), a) != 0 {
+                        s = (s as isize + st.b as isize - 1) as usize;
+                    }
+                }
+                OP_GOTO => {
+                    s = (s as isize + st.a as isize - 1) as usize;
+                }
+                OP_CALL0 ..= OP_CALL8 => {
+                    self.argc = (st.op - OP_CALL0) as usize;
+                    let newf = get(sv.globals.raw(), a) as usize;
+                    let first_statement = match self.functions.get(newf) {
+                        Some(f) if newf != 0 => f.first_statement,
+                        _ => {
+                            self.run_error(sv, "NULL function");
+                            return;
+                        }
+                    };
+                    if first_statement < 0 {
+                        // negative statements are built in functions
+                        pr_cmds::call_builtin(-first_statement, self, sv);
+                        if sv.program_error {
+                            return;
+                        }
+                    } else {
+                        self.xstatement = s;
+                        s = match self.enter_function(sv, newf) {
+                            Some(s) => s,
+                            None => return,
+                        };
+                    }
+                }
+                OP_DONE | OP_RETURN => {
+                    let g = sv.globals.raw_mut();
+                    for i in 0..3 {
+                        let value = get(g, a + i);
+                        set(g, progdefs::OFS_RETURN + i, value);
+                    }
+                    s = match self.leave_function(sv) {
+                        Some(s) => s,
+                        None => return,
+                    };
+                    if self.stack.len() == exitdepth {
+                        // all done
+                        return;
+                    }
+                }
+                OP_STATE => {
+                    let ent = sv.globals.int(progdefs::G_SELF) as usize;
+                    if ent >= sv.edicts.len() {
+                        self.run_error(sv, "PR_ExecuteProgram: bad entity");
+                        return;
+                    }
+                    let time = sv.globals.float(progdefs::G_TIME);
+                    let (frame, think) = (get_f(sv.globals.raw(), a), get(sv.globals.raw(), b));
+                    let v = &mut sv.edicts[ent].v;
+                    v.set_float(progdefs::NEXTTHINK, time + 0.1);
+                    v.set_float(progdefs::FRAME, frame);
+                    v.set_int(progdefs::THINK, think as i32);
+                }
+                op => {
+                    self.run_error(sv, &format!("Bad opcode {}", op));
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Progs for ProgsDat {
+    fn execute(&mut self, sv : &mut Server, function : i32) {
+        if !sv.program_error {
+            self.execute_program(sv, function);
+        }
+    }
+
+    fn function_name(&self, function : i32) -> Option<String> {
+        match self.functions.get(function as usize) {
+            Some(f) if function > 0 => Some(self.string(f.name)),
+            _ => None,
+        }
+    }
+
+    fn find_function(&self, name : &str) -> Option<i32> {
+        self.functions.iter().position(|f| self.string(f.name) == name).map(|function| function as i32)
+    }
+
+    fn reset(&mut self, sv : &mut Server) {
+        sv.entity_fields = self.entity_fields;
+        sv.globals = ::progs::Fields::new(self.globals.len());
+        sv.globals.raw_mut().copy_from_slice(&self.globals);
+        sv.strings = StringTable::with_progs(&self.strings);
+        self.stack.clear();
+        self.localstack.clear();
+        self.xfunction = 0;
+        self.xstatement = 0;
+    }
+
+    fn field_defs(&self) -> Vec<Def> {
+        self.defs(&self.fielddefs, &[EV_STRING, EV_FLOAT, EV_VECTOR, EV_ENTITY, EV_FUNCTION])
+    }
+
+    fn save_global_defs(&self) -> Vec<Def> {
+        let saved : Vec<DDef> = self.globaldefs.iter().filter(|def| def.kind & DEF_SAVEGLOBAL != 0).cloned().collect();
+        self.defs(&saved, &[EV_STRING, EV_FLOAT, EV_ENTITY])
+    }
+}
+
+/// Writes progs.dat files for tests, function 0 is the null function and
+/// the engine globals come first.
+#[cfg(test)]
+pub struct ProgsBuilder {
+    statements : Vec<[u16; 4]>,
+    functions : Vec<(i32, i32)>,
+    globaldefs : Vec<(u16, u16, i32)>,
+    fielddefs : Vec<(u16, u16, i32)>,
+    strings : Vec<u8>,
+    globals : Vec<u32>,
+    entity_fields : usize,
+}
+
+#[cfg(test)]
+impl ProgsBuilder {
+    /// Creates progs with the engine globals and fields.
+    pub fn new() -> ProgsBuilder {
+        ProgsBuilder {
+            statements : vec![[OP_DONE, 0, 0, 0]],
+            functions : vec![(0, 0)],
+            globaldefs : Vec::new(),
+            fielddefs : Vec::new(),
+            strings : vec![0],
+            globals : vec![0; progdefs::GLOBAL_COUNT],
+            entity_fields : progdefs::ENTITY_FIELDS,
+        }
+    }
+
+    /// Adds the definition of a global.
+    pub fn global_def(&mut self, kind : u16, ofs : usize, name : &str) {
+        let name = self.string(name);
+        self.globaldefs.push((kind, ofs as u16, name));
+    }
+
+    /// Adds the definition of a field, fields past the engine fields make
+    /// the entities larger.
+    pub fn field_def(&mut self, kind : u16, ofs : usize, name : &str) {
+        let name = self.string(name);
+        self.fielddefs.push((kind, ofs as u16, name));
+        let size = if kind == EV_VECTOR { 3 } else { 1 };
+        self.entity_fields = self.entity_fields.max(ofs + size);
+    }
+
+    /// Adds a string and returns its offset.
+    pub fn string(&mut self, s : &str) -> i32 {
+        let ofs = self.strings.len() as i32;
+        self.strings.extend(s.bytes());
+        self.strings.push(0);
+        ofs
+    }
+
+    /// Adds a global and returns its offset.
+    pub fn global(&mut self, value : u32) -> u16 {
+        self.globals.push(value);
+        self.globals.len() as u16 - 1
+    }
+
+    /// Changes a global, like the functions the engine calls.
+    pub fn set_global(&mut self, ofs : usize, value : u32) {
+        self.globals[ofs] = value;
+    }
+
+    /// Adds a float global and returns its offset.
+    pub fn float(&mut self, value : f32) -> u16 {
+        self.global(value.to_bits())
+    }
+
+    /// Adds a builtin and returns its function number.
+    pub fn builtin(&mut self, name : &str, num : i32) -> u32 {
+        let name = self.string(name);
+        self.functions.push((-num, name));
+        self.functions.len() as u32 - 1
+    }
+
+    /// Adds a function without parameters and returns its number.
+    pub fn function(&mut self, name : &str, statements : &[[u16; 4]]) -> u32 {
+        let name = self.string(name);
+        self.functions.push((self.statements.len() as i32, name));
+        self.statements.extend_from_slice(statements);
+        self.functions.len() as u32 - 1
+    }
+
+    /// Returns the progs.dat file.
+    pub fn build(&self) -> Vec<u8> {
+        let mut lumps : Vec<(Vec<u8>, usize)> = Vec::new();
+        let words = |values : &[i32]| values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect::<Vec<u8>>();
+        let shorts = |values : &[u16]| values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect::<Vec<u8>>();
+        lumps.push((self.statements.iter().flat_map(|st| shorts(st)).collect(), self.statements.len()));
+        let defs = |defs : &[(u16, u16, i32)]| defs.iter().flat_map(|&(kind, ofs, name)| {
+            let mut def = shorts(&[kind, ofs]);
+            def.extend(words(&[name]));
+            def
+        }).collect::<Vec<u8>>();
+        lumps.push((defs(&self.globaldefs), self.globaldefs.len()));
+        lumps.push((defs(&self.fielddefs), self.fielddefs.len()));
+        lumps.push((self.functions.iter().flat_map(|&(first, name)| {
+            let mut function = words(&[first, 0, 0, 0, name, 0, 0]);
+            function.extend_from_slice(&[0; 8]);
+            function
+        }).collect(), self.functions.len()));
+        lumps.push((self.strings.clone(), self.strings.len()));
+        lumps.push((self.globals.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect(), self.globals.len()));
+
+        let mut header = vec![PROG_VERSION, PROGHEADER_CRC];
+        let mut data = Vec::new();
+        for &(ref lump, count) in &lumps {
+            header.push((15 * 4 + data.len()) as i32);
+            header.push(count as i32);
+            data.extend_from_slice(lump);
+        }
+        header.push(self.entity_fields as i32);
+        let mut file = words(&header);
+        file.extend(data);
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(builder : &ProgsBuilder) -> (ProgsDat, Server) {
+        let mut progs = ProgsDat::read(&builder.build()).unwrap();
+        let mut sv = Server::new(1);
+        progs.reset(&mut sv);
+        (progs, sv)
+    }
+
+    #[test]
+    fn reject_bad_header() {
+        assert!(ProgsDat::read(&[0; 10]).is_err());
+        let mut data = ProgsBuilder::new().build();
+        data[0] = 7;
+        assert!(ProgsDat::read(&data).is_err());
+        data[0] = 6;
+        assert!(ProgsDat::read(&data).is_ok());
+    }
+
+    #[test]
+    fn loop_and_return() {
+        // sums the numbers from 0 to 4
+        let mut builder = ProgsBuilder::new();
+        let (i, total, one, limit, cond) = (builder.float(0.0), builder.float(0.0), builder.float(1.0), builder.float(5.0), builder.float(0.0));
+        let sum = builder.function("sum", &[
+            [OP_ADD_F, total, i, total],
+            [OP_ADD_F, i, one, i],
+            [OP_LT, i, limit, cond],
+            [OP_IF, cond, -3i16 as u16, 0],
+            [OP_RETURN, total, 0, 0],
+        ]);
+        let (mut progs, mut sv) = load(&builder);
+        assert_eq!(progs.find_function("sum"), Some(sum as i32));
+        assert_eq!(progs.function_name(sum as i32), Some("sum".to_string()));
+        progs.execute(&mut sv, sum as i32);
+        assert!(!sv.program_error);
+        assert_eq!(sv.globals.float(progdefs::OFS_RETURN), 10.0);
+    }
+
+    #[test]
+    fn builtins_and_fields() {
+        // spawns an entity, sets its health and converts it to a string
+        let mut builder = ProgsBuilder::new();
+        let spawn = builder.builtin("spawn", 14);
+        let ftos = builder.builtin("ftos", 26);
+        let (spawn, ftos) = (builder.global(spawn), builder.global(ftos));
+        let (ent, health, ptr, hundred) = (builder.global(0), builder.global(progdefs::HEALTH as u32), builder.global(0), builder.float(100.0));
+        let result = builder.global(0);
+        let ret = progdefs::OFS_RETURN as u16;
+        let function = builder.function("spawn_thing", &[
+            [OP_CALL0, spawn, 0, 0],
+            [OP_STORE_ENT, ret, ent, 0],
+            [OP_ADDRESS, ent, health, ptr],
+            [OP_STOREP_F, hundred, ptr, 0],
+            [OP_STORE_F, hundred, progdefs::OFS_PARM0 as u16, 0],
+            [OP_CALL0 + 1, ftos, 0, 0],
+            [OP_STORE_S, ret, result, 0],
+            [OP_DONE, 0, 0, 0],
+        ]);
+        let (mut progs, mut sv) = load(&builder);
+        progs.execute(&mut sv, function as i32);
+        assert!(!sv.program_error);
+        let ent = sv.globals.int(ent as usize) as usize;
+        assert_eq!(ent, 2);
+        assert_eq!(sv.edicts[ent].v.float(progdefs::HEALTH), 100.0);
+        assert_eq!(sv.strings.get(sv.globals.int(result as usize)), "100");
+    }
+
+    #[test]
+    fn errors_stop_the_programs() {
+        let mut builder = ProgsBuilder::new();
+        let bad = builder.builtin("bad", 200);
+        let bad = builder.global(bad);
+        let call_bad = builder.function("call_bad", &[[OP_CALL0, bad, 0, 0], [OP_DONE, 0, 0, 0]]);
+        let forever = builder.function("forever", &[[OP_GOTO, 0, 0, 0], [OP_DONE, 0, 0, 0]]);
+        let (mut progs, mut sv) = load(&builder);
+
+        progs.execute(&mut sv, call_bad as i32);
+        assert!(sv.program_error);
+        sv.program_error = false;
+        progs.execute(&mut sv, forever as i32);
+        assert!(sv.program_error);
+        assert!(progs.stack.is_empty());
+    }
+}
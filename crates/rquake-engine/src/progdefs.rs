@@ -0,0 +1,267 @@
+#![allow(missing_docs)]
+
+//! Offsets of the entity fields and globals shared with the QuakeC programs.
+//!
+//! Every field and global is 4 bytes, vectors use 3 consecutive slots.
+//! The layout has to match progs.dat, see progdefs.h of the original source.
+
+use std::borrow::Cow;
+
+/// Number of fields every progs.dat has to define.
+pub const ENTITY_FIELDS : usize = 105;
+
+// entity fields (entvars_t)
+pub const MODELINDEX : usize = 0;
+pub const ABSMIN : usize = 1;
+pub const ABSMAX : usize = 4;
+pub const LTIME : usize = 7;
+pub const MOVETYPE : usize = 8;
+pub const SOLID : usize = 9;
+pub const ORIGIN : usize = 10;
+pub const OLDORIGIN : usize = 13;
+pub const VELOCITY : usize = 16;
+pub const ANGLES : usize = 19;
+pub const AVELOCITY : usize = 22;
+pub const PUNCHANGLE : usize = 25;
+pub const CLASSNAME : usize = 28;
+pub const MODEL : usize = 29;
+pub const FRAME : usize = 30;
+pub const SKIN : usize = 31;
+pub const EFFECTS : usize = 32;
+pub const MINS : usize = 33;
+pub const MAXS : usize = 36;
+pub const SIZE : usize = 39;
+pub const TOUCH : usize = 42;
+pub const USE : usize = 43;
+pub const THINK : usize = 44;
+pub const BLOCKED : usize = 45;
+pub const NEXTTHINK : usize = 46;
+pub const GROUNDENTITY : usize = 47;
+pub const HEALTH : usize = 48;
+pub const FRAGS : usize = 49;
+pub const WEAPON : usize = 50;
+pub const WEAPONMODEL : usize = 51;
+pub const WEAPONFRAME : usize = 52;
+pub const CURRENTAMMO : usize = 53;
+pub const AMMO_SHELLS : usize = 54;
+pub const AMMO_NAILS : usize = 55;
+pub const AMMO_ROCKETS : usize = 56;
+pub const AMMO_CELLS : usize = 57;
+pub const ITEMS : usize = 58;
+pub const TAKEDAMAGE : usize = 59;
+pub const CHAIN : usize = 60;
+pub const DEADFLAG : usize = 61;
+pub const VIEW_OFS : usize = 62;
+pub const BUTTON0 : usize = 65;
+pub const BUTTON1 : usize = 66;
+pub const BUTTON2 : usize = 67;
+pub const IMPULSE : usize = 68;
+pub const FIXANGLE : usize = 69;
+pub const V_ANGLE : usize = 70;
+pub const IDEALPITCH : usize = 73;
+pub const NETNAME : usize = 74;
+pub const ENEMY : usize = 75;
+pub const FLAGS : usize = 76;
+pub const COLORMAP : usize = 77;
+pub const TEAM : usize = 78;
+pub const MAX_HEALTH : usize = 79;
+pub const TELEPORT_TIME : usize = 80;
+pub const ARMORTYPE : usize = 81;
+pub const ARMORVALUE : usize = 82;
+pub const WATERLEVEL : usize = 83;
+pub const WATERTYPE : usize = 84;
+pub const IDEAL_YAW : usize = 85;
+pub const YAW_SPEED : usize = 86;
+pub const AIMENT : usize = 87;
+pub const GOALENTITY : usize = 88;
+pub const SPAWNFLAGS : usize = 89;
+pub const TARGET : usize = 90;
+pub const TARGETNAME : usize = 91;
+pub const DMG_TAKE : usize = 92;
+pub const DMG_SAVE : usize = 93;
+pub const DMG_INFLICTOR : usize = 94;
+pub const OWNER : usize = 95;
+pub const MOVEDIR : usize = 96;
+pub const MESSAGE : usize = 99;
+pub const SOUNDS : usize = 100;
+pub const NOISE : usize = 101;
+pub const NOISE1 : usize = 102;
+pub const NOISE2 : usize = 103;
+pub const NOISE3 : usize = 104;
+
+/// Number of globals every progs.dat has to define.
+pub const GLOBAL_COUNT : usize = 92;
+
+// globals (globalvars_t), the first 28 slots are reserved for
+// function parameters and return values
+pub const OFS_RETURN : usize = 1;
+pub const OFS_PARM0 : usize = 4;
+pub const G_SELF : usize = 28;
+pub const G_OTHER : usize = 29;
+pub const G_WORLD : usize = 30;
+pub const G_TIME : usize = 31;
+pub const G_FRAMETIME : usize = 32;
+pub const G_FORCE_RETOUCH : usize = 33;
+pub const G_MAPNAME : usize = 34;
+pub const G_DEATHMATCH : usize = 35;
+pub const G_COOP : usize = 36;
+pub const G_TEAMPLAY : usize = 37;
+pub const G_SERVERFLAGS : usize = 38;
+pub const G_TOTAL_SECRETS : usize = 39;
+pub const G_TOTAL_MONSTERS : usize = 40;
+pub const G_FOUND_SECRETS : usize = 41;
+pub const G_KILLED_MONSTERS : usize = 42;
+/// parm1 to parm16 follow each other.
+pub const G_PARM1 : usize = 43;
+pub const G_V_FORWARD : usize = 59;
+pub const G_V_UP : usize = 62;
+pub const G_V_RIGHT : usize = 65;
+pub const G_TRACE_ALLSOLID : usize = 68;
+pub const G_TRACE_STARTSOLID : usize = 69;
+pub const G_TRACE_FRACTION : usize = 70;
+pub const G_TRACE_ENDPOS : usize = 71;
+pub const G_TRACE_PLANE_NORMAL : usize = 74;
+pub const G_TRACE_PLANE_DIST : usize = 77;
+pub const G_TRACE_ENT : usize = 78;
+pub const G_TRACE_INOPEN : usize = 79;
+pub const G_TRACE_INWATER : usize = 80;
+pub const G_MSG_ENTITY : usize = 81;
+pub const G_MAIN : usize = 82;
+pub const G_STARTFRAME : usize = 83;
+pub const G_PLAYERPRETHINK : usize = 84;
+pub const G_PLAYERPOSTTHINK : usize = 85;
+pub const G_CLIENTKILL : usize = 86;
+pub const G_CLIENTCONNECT : usize = 87;
+pub const G_PUTCLIENTINSERVER : usize = 88;
+pub const G_CLIENTDISCONNECT : usize = 89;
+pub const G_SETNEWPARMS : usize = 90;
+pub const G_SETCHANGEPARMS : usize = 91;
+
+/// Type of an entity field or global.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DefType {
+    String,
+    Float,
+    Vector,
+    Entity,
+    Function,
+}
+
+/// Name, offset and type of an entity field or global.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Def {
+    pub name : Cow<'static, str>,
+    pub ofs : usize,
+    pub kind : DefType,
+}
+
+/// The entity fields every progs.dat defines, used when the progs don't
+/// provide their own definitions.
+pub const FIELD_DEFS : &'static [Def] = &[
+    Def { name : Cow::Borrowed("modelindex"), ofs : MODELINDEX, kind : DefType::Float },
+    Def { name : Cow::Borrowed("absmin"), ofs : ABSMIN, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("absmax"), ofs : ABSMAX, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("ltime"), ofs : LTIME, kind : DefType::Float },
+    Def { name : Cow::Borrowed("movetype"), ofs : MOVETYPE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("solid"), ofs : SOLID, kind : DefType::Float },
+    Def { name : Cow::Borrowed("origin"), ofs : ORIGIN, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("oldorigin"), ofs : OLDORIGIN, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("velocity"), ofs : VELOCITY, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("angles"), ofs : ANGLES, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("avelocity"), ofs : AVELOCITY, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("punchangle"), ofs : PUNCHANGLE, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("classname"), ofs : CLASSNAME, kind : DefType::String },
+    Def { name : Cow::Borrowed("model"), ofs : MODEL, kind : DefType::String },
+    Def { name : Cow::Borrowed("frame"), ofs : FRAME, kind : DefType::Float },
+    Def { name : Cow::Borrowed("skin"), ofs : SKIN, kind : DefType::Float },
+    Def { name : Cow::Borrowed("effects"), ofs : EFFECTS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("mins"), ofs : MINS, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("maxs"), ofs : MAXS, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("size"), ofs : SIZE, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("touch"), ofs : TOUCH, kind : DefType::Function },
+    Def { name : Cow::Borrowed("use"), ofs : USE, kind : DefType::Function },
+    Def { name : Cow::Borrowed("think"), ofs : THINK, kind : DefType::Function },
+    Def { name : Cow::Borrowed("blocked"), ofs : BLOCKED, kind : DefType::Function },
+    Def { name : Cow::Borrowed("nextthink"), ofs : NEXTTHINK, kind : DefType::Float },
+    Def { name : Cow::Borrowed("groundentity"), ofs : GROUNDENTITY, kind : DefType::Entity },
+    Def { name : Cow::Borrowed("health"), ofs : HEALTH, kind : DefType::Float },
+    Def { name : Cow::Borrowed("frags"), ofs : FRAGS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("weapon"), ofs : WEAPON, kind : DefType::Float },
+    Def { name : Cow::Borrowed("weaponmodel"), ofs : WEAPONMODEL, kind : DefType::String },
+    Def { name : Cow::Borrowed("weaponframe"), ofs : WEAPONFRAME, kind : DefType::Float },
+    Def { name : Cow::Borrowed("currentammo"), ofs : CURRENTAMMO, kind : DefType::Float },
+    Def { name : Cow::Borrowed("ammo_shells"), ofs : AMMO_SHELLS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("ammo_nails"), ofs : AMMO_NAILS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("ammo_rockets"), ofs : AMMO_ROCKETS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("ammo_cells"), ofs : AMMO_CELLS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("items"), ofs : ITEMS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("takedamage"), ofs : TAKEDAMAGE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("chain"), ofs : CHAIN, kind : DefType::Entity },
+    Def { name : Cow::Borrowed("deadflag"), ofs : DEADFLAG, kind : DefType::Float },
+    Def { name : Cow::Borrowed("view_ofs"), ofs : VIEW_OFS, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("button0"), ofs : BUTTON0, kind : DefType::Float },
+    Def { name : Cow::Borrowed("button1"), ofs : BUTTON1, kind : DefType::Float },
+    Def { name : Cow::Borrowed("button2"), ofs : BUTTON2, kind : DefType::Float },
+    Def { name : Cow::Borrowed("impulse"), ofs : IMPULSE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("fixangle"), ofs : FIXANGLE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("v_angle"), ofs : V_ANGLE, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("idealpitch"), ofs : IDEALPITCH, kind : DefType::Float },
+    Def { name : Cow::Borrowed("netname"), ofs : NETNAME, kind : DefType::String },
+    Def { name : Cow::Borrowed("enemy"), ofs : ENEMY, kind : DefType::Entity },
+    Def { name : Cow::Borrowed("flags"), ofs : FLAGS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("colormap"), ofs : COLORMAP, kind : DefType::Float },
+    Def { name : Cow::Borrowed("team"), ofs : TEAM, kind : DefType::Float },
+    Def { name : Cow::Borrowed("max_health"), ofs : MAX_HEALTH, kind : DefType::Float },
+    Def { name : Cow::Borrowed("teleport_time"), ofs : TELEPORT_TIME, kind : DefType::Float },
+    Def { name : Cow::Borrowed("armortype"), ofs : ARMORTYPE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("armorvalue"), ofs : ARMORVALUE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("waterlevel"), ofs : WATERLEVEL, kind : DefType::Float },
+    Def { name : Cow::Borrowed("watertype"), ofs : WATERTYPE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("ideal_yaw"), ofs : IDEAL_YAW, kind : DefType::Float },
+    Def { name : Cow::Borrowed("yaw_speed"), ofs : YAW_SPEED, kind : DefType::Float },
+    Def { name : Cow::Borrowed("aiment"), ofs : AIMENT, kind : DefType::Entity },
+    Def { name : Cow::Borrowed("goalentity"), ofs : GOALENTITY, kind : DefType::Entity },
+    Def { name : Cow::Borrowed("spawnflags"), ofs : SPAWNFLAGS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("target"), ofs : TARGET, kind : DefType::String },
+    Def { name : Cow::Borrowed("targetname"), ofs : TARGETNAME, kind : DefType::String },
+    Def { name : Cow::Borrowed("dmg_take"), ofs : DMG_TAKE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("dmg_save"), ofs : DMG_SAVE, kind : DefType::Float },
+    Def { name : Cow::Borrowed("dmg_inflictor"), ofs : DMG_INFLICTOR, kind : DefType::Entity },
+    Def { name : Cow::Borrowed("owner"), ofs : OWNER, kind : DefType::Entity },
+    Def { name : Cow::Borrowed("movedir"), ofs : MOVEDIR, kind : DefType::Vector },
+    Def { name : Cow::Borrowed("message"), ofs : MESSAGE, kind : DefType::String },
+    Def { name : Cow::Borrowed("sounds"), ofs : SOUNDS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("noise"), ofs : NOISE, kind : DefType::String },
+    Def { name : Cow::Borrowed("noise1"), ofs : NOISE1, kind : DefType::String },
+    Def { name : Cow::Borrowed("noise2"), ofs : NOISE2, kind : DefType::String },
+    Def { name : Cow::Borrowed("noise3"), ofs : NOISE3, kind : DefType::String },
+];
+
+/// The globals stored in save games, used when the progs don't provide
+/// their own definitions.
+pub const SAVE_GLOBAL_DEFS : &'static [Def] = &[
+    Def { name : Cow::Borrowed("deathmatch"), ofs : G_DEATHMATCH, kind : DefType::Float },
+    Def { name : Cow::Borrowed("coop"), ofs : G_COOP, kind : DefType::Float },
+    Def { name : Cow::Borrowed("teamplay"), ofs : G_TEAMPLAY, kind : DefType::Float },
+    Def { name : Cow::Borrowed("serverflags"), ofs : G_SERVERFLAGS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("total_secrets"), ofs : G_TOTAL_SECRETS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("total_monsters"), ofs : G_TOTAL_MONSTERS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("found_secrets"), ofs : G_FOUND_SECRETS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("killed_monsters"), ofs : G_KILLED_MONSTERS, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm1"), ofs : G_PARM1, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm2"), ofs : G_PARM1 + 1, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm3"), ofs : G_PARM1 + 2, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm4"), ofs : G_PARM1 + 3, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm5"), ofs : G_PARM1 + 4, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm6"), ofs : G_PARM1 + 5, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm7"), ofs : G_PARM1 + 6, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm8"), ofs : G_PARM1 + 7, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm9"), ofs : G_PARM1 + 8, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm10"), ofs : G_PARM1 + 9, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm11"), ofs : G_PARM1 + 10, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm12"), ofs : G_PARM1 + 11, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm13"), ofs : G_PARM1 + 12, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm14"), ofs : G_PARM1 + 13, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm15"), ofs : G_PARM1 + 14, kind : DefType::Float },
+    Def { name : Cow::Borrowed("parm16"), ofs : G_PARM1 + 15, kind : DefType::Float },
+];
@@ -0,0 +1,321 @@
+#![warn(missing_docs)]
+
+//! Entity and global storage shared with the QuakeC programs.
+//!
+//! Original source can be found in pr_edict.c
+
+use rquake_common::Vec3;
+use progdefs::{self, Def};
+use protocol::EntityState;
+use server::{self, Server};
+
+/// Interface to the QuakeC programs that implement the game logic.
+pub trait Progs {
+    /// Runs a QuakeC function. Function 0 is the null function.
+    fn execute(&mut self, sv : &mut Server, function : i32);
+
+    /// Returns the name of a function, used by save games.
+    fn function_name(&self, _function : i32) -> Option<String> {
+        None
+    }
+
+    /// Returns the function with the given name.
+    fn find_function(&self, _name : &str) -> Option<i32> {
+        None
+    }
+
+    /// Sets up the globals, entity fields and strings for a new map.
+    fn reset(&mut self, _sv : &mut Server) {}
+
+    /// Returns the entity fields that are parsed from maps and stored in
+    /// save games.
+    fn field_defs(&self) -> Vec<Def> {
+        progdefs::FIELD_DEFS.to_vec()
+    }
+
+    /// Returns the globals stored in save games.
+    fn save_global_defs(&self) -> Vec<Def> {
+        progdefs::SAVE_GLOBAL_DEFS.to_vec()
+    }
+}
+
+/// Storage of 4 byte values addressed by their offset, used for the entity
+/// fields and the globals. Strings, functions and entity references are
+/// integers, entities are referenced by their number.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fields {
+    values : Vec<u32>,
+}
+
+impl Fields {
+    /// Creates count zeroed values.
+    pub fn new(count : usize) -> Fields {
+        Fields {
+            values : vec![0; count],
+        }
+    }
+
+    /// Returns the number of values.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Sets all values to 0.
+    pub fn clear(&mut self) {
+        for value in self.values.iter_mut() {
+            *value = 0;
+        }
+    }
+
+    /// Returns a float.
+    pub fn float(&self, ofs : usize) -> f32 {
+        f32::from_bits(self.values[ofs])
+    }
+
+    /// Changes a float.
+    pub fn set_float(&mut self, ofs : usize, value : f32) {
+        self.values[ofs] = value.to_bits();
+    }
+
+    /// Returns a vector.
+    pub fn vector(&self, ofs : usize) -> Vec3 {
+        [self.float(ofs), self.float(ofs + 1), self.float(ofs + 2)]
+    }
+
+    /// Changes a vector.
+    pub fn set_vector(&mut self, ofs : usize, value : Vec3) {
+        for i in 0..3 {
+            self.set_float(ofs + i, value[i]);
+        }
+    }
+
+    /// Returns a string, function or entity reference.
+    pub fn int(&self, ofs : usize) -> i32 {
+        self.values[ofs] as i32
+    }
+
+    /// Changes a string, function or entity reference.
+    pub fn set_int(&mut self, ofs : usize, value : i32) {
+        self.values[ofs] = value as u32;
+    }
+
+    /// Returns the raw values.
+    pub fn raw(&self) -> &[u32] {
+        &self.values
+    }
+
+    /// Returns the raw values for changing.
+    pub fn raw_mut(&mut self) -> &mut [u32] {
+        &mut self.values
+    }
+}
+
+/// Strings referenced by entity fields and globals. The strings of the
+/// progs are addressed by their byte offset, strings created by the engine
+/// and the progs follow them. String 0 is the empty string.
+pub struct StringTable {
+    text : String,
+    offsets : Vec<usize>,
+    strings : Vec<String>,
+}
+
+impl StringTable {
+    /// Creates a table only holding the empty string.
+    pub fn new() -> StringTable {
+        StringTable::with_progs(&[0])
+    }
+
+    /// Creates a table holding the null terminated strings of the progs,
+    /// the first byte has to be a 0.
+    pub fn with_progs(data : &[u8]) -> StringTable {
+        let mut text = String::with_capacity(data.len());
+        let mut offsets = Vec::with_capacity(data.len());
+        for &c in data {
+            offsets.push(text.len());
+            text.push(c as char);
+        }
+        StringTable {
+            text : text,
+            offsets : offsets,
+            strings : vec![String::new()],
+        }
+    }
+
+    /// Returns a string, unknown strings are empty.
+    pub fn get(&self, index : i32) -> &str {
+        if index < 0 {
+            return "";
+        }
+        let index = index as usize;
+        match self.offsets.get(index) {
+            Some(&start) => {
+                let rest = &self.text[start..];
+                &rest[..rest.find('\0').unwrap_or(rest.len())]
+            }
+            None => self.strings.get(index - self.offsets.len()).map_or("", |s| s.as_str()),
+        }
+    }
+
+    /// Adds a string and returns its index. Known strings are reused.
+    pub fn alloc(&mut self, s : &str) -> i32 {
+        if s.is_empty() {
+            return 0;
+        }
+        let base = self.offsets.len();
+        if let Some(index) = self.strings.iter().skip(1).position(|known| known == s) {
+            return (base + index + 1) as i32;
+        }
+        self.strings.push(s.to_string());
+        (base + self.strings.len() - 1) as i32
+    }
+
+    /// Replaces the temporary string returned by ftos and vtos and returns
+    /// its index.
+    pub fn set_temp(&mut self, s : &str) -> i32 {
+        self.strings[0] = s.to_string();
+        self.offsets.len() as i32
+    }
+}
+
+/// A server side entity.
+pub struct Edict {
+    /// Unused entity that can be reallocated.
+    pub free : bool,
+    /// Server time the entity was freed.
+    pub freetime : f32,
+    /// Area node the entity is linked into.
+    pub area : Option<usize>,
+    /// Leafs of the world the entity touches, used to check if clients can
+    /// see it.
+    pub leafnums : Vec<usize>,
+    /// Fields defined by the QuakeC programs.
+    pub v : Fields,
+    /// State the entity updates sent to the clients are relative to.
+    pub baseline : EntityState,
+}
+
+impl Edict {
+    /// Creates a zeroed entity.
+    pub fn new(entity_fields : usize) -> Edict {
+        Edict {
+            free : false,
+            freetime : 0.0,
+            area : None,
+            leafnums : Vec::new(),
+            v : Fields::new(entity_fields),
+            baseline : EntityState::new(),
+        }
+    }
+}
+
+impl Server {
+    /// Returns a free entity. Freed entities are reused after half a second
+    /// so clients don't interpolate between the old and the new entity.
+    pub fn alloc_edict(&mut self) -> Option<usize> {
+        for i in self.max_clients + 1..self.edicts.len() {
+            let reusable = {
+                let e = &self.edicts[i];
+                e.free && (e.freetime < 2.0 || self.time - e.freetime > 0.5)
+            };
+            if reusable {
+                self.clear_edict(i);
+                return Some(i);
+            }
+        }
+
+        if self.edicts.len() >= server::MAX_EDICTS {
+            println!("ED_Alloc: no free edicts");
+            return None;
+        }
+        self.edicts.push(Edict::new(self.entity_fields));
+        Some(self.edicts.len() - 1)
+    }
+
+    /// Marks an entity as free and removes it from the world.
+    pub fn free_edict(&mut self, ent : usize) {
+        self.unlink_edict(ent);
+
+        let time = self.time;
+        let e = &mut self.edicts[ent];
+        e.free = true;
+        e.freetime = time;
+        e.v.set_int(progdefs::MODEL, 0);
+        e.v.set_float(progdefs::TAKEDAMAGE, 0.0);
+        e.v.set_float(progdefs::MODELINDEX, 0.0);
+        e.v.set_float(progdefs::COLORMAP, 0.0);
+        e.v.set_float(progdefs::SKIN, 0.0);
+        e.v.set_float(progdefs::FRAME, 0.0);
+        e.v.set_vector(progdefs::ORIGIN, [0.0; 3]);
+        e.v.set_vector(progdefs::ANGLES, [0.0; 3]);
+        e.v.set_float(progdefs::NEXTTHINK, -1.0);
+        e.v.set_float(progdefs::SOLID, server::SOLID_NOT);
+    }
+
+    /// Zeroes all fields of an entity and marks it as used.
+    pub fn clear_edict(&mut self, ent : usize) {
+        let e = &mut self.edicts[ent];
+        e.v.clear();
+        e.free = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_and_strings() {
+        let mut fields = Fields::new(progdefs::ENTITY_FIELDS);
+        fields.set_vector(progdefs::ORIGIN, [1.0, -2.0, 3.5]);
+        fields.set_int(progdefs::GROUNDENTITY, -1);
+        assert_eq!(fields.vector(progdefs::ORIGIN), [1.0, -2.0, 3.5]);
+        assert_eq!(fields.float(progdefs::ORIGIN + 2), 3.5);
+        assert_eq!(fields.int(progdefs::GROUNDENTITY), -1);
+
+        let mut strings = StringTable::new();
+        let light = strings.alloc("light");
+        assert!(light > 0);
+        assert_eq!(strings.alloc("light"), light);
+        assert_eq!(strings.get(light), "light");
+        assert_eq!(strings.get(0), "");
+        assert_eq!(strings.get(1000), "");
+
+        // strings of the progs are addressed by their offset
+        let mut strings = StringTable::with_progs(b"\0self\0\xe9\0");
+        assert_eq!(strings.get(1), "self");
+        assert_eq!(strings.get(3), "lf");
+        assert_eq!(strings.get(6), "\u{e9}");
+        let temp = strings.set_temp("1.5");
+        assert_eq!(temp, 8);
+        assert_eq!(strings.get(temp), "1.5");
+        assert_eq!(strings.alloc("light"), 9);
+        strings.set_temp("2");
+        assert_eq!(strings.get(temp), "2");
+        assert_eq!(strings.get(9), "light");
+    }
+
+    #[test]
+    fn reuse_freed_edicts_after_delay() {
+        let mut sv = Server::new(1);
+        sv.time = 5.0;
+        let a = sv.alloc_edict().unwrap();
+        let b = sv.alloc_edict().unwrap();
+        assert_eq!((a, b), (2, 3));
+
+        sv.edicts[a].v.set_float(progdefs::HEALTH, 100.0);
+        sv.free_edict(a);
+        assert!(sv.edicts[a].free);
+        assert_eq!(sv.edicts[a].v.float(progdefs::NEXTTHINK), -1.0);
+        assert_eq!(sv.alloc_edict(), Some(4));
+
+        sv.time = 5.6;
+        assert_eq!(sv.alloc_edict(), Some(a));
+        assert!(!sv.edicts[a].free);
+        assert_eq!(sv.edicts[a].v.float(progdefs::HEALTH), 0.0);
+    }
+}
@@ -0,0 +1,1201 @@
+#![allow(missing_docs)]
+
+//! Messages of the NetQuake protocol between client and server.
+//!
+//! Original source can be found in protocol.h, cl_parse.c and sv_main.c
+
+use rquake_common::Vec3;
+use cl_input::UserCmd;
+use msg::{MsgReader, SizeBuf};
+
+pub const PROTOCOL_VERSION : i32 = 15;
+
+/// Number of signon messages the client has to receive before it's in the game.
+pub const SIGNONS : i32 = 4;
+
+pub const DEFAULT_VIEWHEIGHT : i32 = 22;
+
+pub const GAME_COOP : i32 = 0;
+pub const GAME_DEATHMATCH : i32 = 1;
+
+// client stats changed with svc_updatestat
+pub const STAT_HEALTH : i32 = 0;
+pub const STAT_FRAGS : i32 = 1;
+pub const STAT_WEAPON : i32 = 2;
+pub const STAT_AMMO : i32 = 3;
+pub const STAT_ARMOR : i32 = 4;
+pub const STAT_WEAPONFRAME : i32 = 5;
+pub const STAT_SHELLS : i32 = 6;
+pub const STAT_NAILS : i32 = 7;
+pub const STAT_ROCKETS : i32 = 8;
+pub const STAT_CELLS : i32 = 9;
+pub const STAT_ACTIVEWEAPON : i32 = 10;
+pub const STAT_TOTALSECRETS : i32 = 11;
+pub const STAT_TOTALMONSTERS : i32 = 12;
+pub const STAT_SECRETS : i32 = 13;
+pub const STAT_MONSTERS : i32 = 14;
+pub const MAX_CL_STATS : usize = 32;
+
+// bits of an entity update, the low byte has U_SIGNAL set to mark fast updates
+pub const U_MOREBITS : i32 = 1 << 0;
+pub const U_ORIGIN1 : i32 = 1 << 1;
+pub const U_ORIGIN2 : i32 = 1 << 2;
+pub const U_ORIGIN3 : i32 = 1 << 3;
+pub const U_ANGLE2 : i32 = 1 << 4;
+pub const U_NOLERP : i32 = 1 << 5;
+pub const U_FRAME : i32 = 1 << 6;
+pub const U_SIGNAL : i32 = 1 << 7;
+pub const U_ANGLE1 : i32 = 1 << 8;
+pub const U_ANGLE3 : i32 = 1 << 9;
+pub const U_MODEL : i32 = 1 << 10;
+pub const U_COLORMAP : i32 = 1 << 11;
+pub const U_SKIN : i32 = 1 << 12;
+pub const U_EFFECTS : i32 = 1 << 13;
+pub const U_LONGENTITY : i32 = 1 << 14;
+
+// bits of svc_clientdata
+pub const SU_VIEWHEIGHT : i32 = 1 << 0;
+pub const SU_IDEALPITCH : i32 = 1 << 1;
+pub const SU_PUNCH1 : i32 = 1 << 2;
+pub const SU_PUNCH2 : i32 = 1 << 3;
+pub const SU_PUNCH3 : i32 = 1 << 4;
+pub const SU_VELOCITY1 : i32 = 1 << 5;
+pub const SU_VELOCITY2 : i32 = 1 << 6;
+pub const SU_VELOCITY3 : i32 = 1 << 7;
+pub const SU_ITEMS : i32 = 1 << 9;
+pub const SU_ONGROUND : i32 = 1 << 10;
+pub const SU_INWATER : i32 = 1 << 11;
+pub const SU_WEAPONFRAME : i32 = 1 << 12;
+pub const SU_ARMOR : i32 = 1 << 13;
+pub const SU_WEAPON : i32 = 1 << 14;
+
+// bits of svc_sound
+pub const SND_VOLUME : i32 = 1 << 0;
+pub const SND_ATTENUATION : i32 = 1 << 1;
+
+pub const DEFAULT_SOUND_PACKET_VOLUME : i32 = 255;
+pub const DEFAULT_SOUND_PACKET_ATTENUATION : f32 = 1.0;
+
+// server to client
+pub const SVC_BAD : i32 = 0;
+pub const SVC_NOP : i32 = 1;
+pub const SVC_DISCONNECT : i32 = 2;
+pub const SVC_UPDATESTAT : i32 = 3;
+pub const SVC_VERSION : i32 = 4;
+pub const SVC_SETVIEW : i32 = 5;
+pub const SVC_SOUND : i32 = 6;
+pub const SVC_TIME : i32 = 7;
+pub const SVC_PRINT : i32 = 8;
+pub const SVC_STUFFTEXT : i32 = 9;
+pub const SVC_SETANGLE : i32 = 10;
+pub const SVC_SERVERINFO : i32 = 11;
+pub const SVC_LIGHTSTYLE : i32 = 12;
+pub const SVC_UPDATENAME : i32 = 13;
+pub const SVC_UPDATEFRAGS : i32 = 14;
+pub const SVC_CLIENTDATA : i32 = 15;
+pub const SVC_STOPSOUND : i32 = 16;
+pub const SVC_UPDATECOLORS : i32 = 17;
+pub const SVC_PARTICLE : i32 = 18;
+pub const SVC_DAMAGE : i32 = 19;
+pub const SVC_SPAWNSTATIC : i32 = 20;
+pub const SVC_SPAWNBASELINE : i32 = 22;
+pub const SVC_TEMP_ENTITY : i32 = 23;
+pub const SVC_SETPAUSE : i32 = 24;
+pub const SVC_SIGNONNUM : i32 = 25;
+pub const SVC_CENTERPRINT : i32 = 26;
+pub const SVC_KILLEDMONSTER : i32 = 27;
+pub const SVC_FOUNDSECRET : i32 = 28;
+pub const SVC_SPAWNSTATICSOUND : i32 = 29;
+pub const SVC_INTERMISSION : i32 = 30;
+pub const SVC_FINALE : i32 = 31;
+pub const SVC_CDTRACK : i32 = 32;
+pub const SVC_SELLSCREEN : i32 = 33;
+pub const SVC_CUTSCENE : i32 = 34;
+
+// client to server
+pub const CLC_BAD : i32 = 0;
+pub const CLC_NOP : i32 = 1;
+pub const CLC_DISCONNECT : i32 = 2;
+pub const CLC_MOVE : i32 = 3;
+pub const CLC_STRINGCMD : i32 = 4;
+
+// temp entity events
+pub const TE_SPIKE : i32 = 0;
+pub const TE_SUPERSPIKE : i32 = 1;
+pub const TE_GUNSHOT : i32 = 2;
+pub const TE_EXPLOSION : i32 = 3;
+pub const TE_TAREXPLOSION : i32 = 4;
+pub const TE_LIGHTNING1 : i32 = 5;
+pub const TE_LIGHTNING2 : i32 = 6;
+pub const TE_WIZSPIKE : i32 = 7;
+pub const TE_KNIGHTSPIKE : i32 = 8;
+pub const TE_LIGHTNING3 : i32 = 9;
+pub const TE_LAVASPLASH : i32 = 10;
+pub const TE_TELEPORT : i32 = 11;
+pub const TE_EXPLOSION2 : i32 = 12;
+pub const TE_BEAM : i32 = 13;
+
+/// State of an entity as seen by the client. Baselines use the same
+/// state so that updates only need to contain the changed fields.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EntityState {
+    pub origin : Vec3,
+    pub angles : Vec3,
+    pub modelindex : i32,
+    pub frame : i32,
+    pub colormap : i32,
+    pub skin : i32,
+    pub effects : i32,
+}
+
+impl EntityState {
+    /// Creates an empty state.
+    pub fn new() -> EntityState {
+        EntityState {
+            origin : [0.0; 3],
+            angles : [0.0; 3],
+            modelindex : 0,
+            frame : 0,
+            colormap : 0,
+            skin : 0,
+            effects : 0,
+        }
+    }
+
+    fn write(&self, buf : &mut SizeBuf) {
+        buf.write_byte(self.modelindex);
+        buf.write_byte(self.frame);
+        buf.write_byte(self.colormap);
+        buf.write_byte(self.skin);
+        for i in 0..3 {
+            buf.write_coord(self.origin[i]);
+            buf.write_angle(self.angles[i]);
+        }
+    }
+
+    fn read(msg : &mut MsgReader) -> EntityState {
+        let mut state = EntityState::new();
+        state.modelindex = msg.read_byte();
+        state.frame = msg.read_byte();
+        state.colormap = msg.read_byte();
+        state.skin = msg.read_byte();
+        for i in 0..3 {
+            state.origin[i] = msg.read_coord();
+            state.angles[i] = msg.read_angle();
+        }
+        state
+    }
+}
+
+/// Delta compressed entity update, fields that are `None` keep their
+/// baseline value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityUpdate {
+    pub entity : i32,
+    pub modelindex : Option<i32>,
+    pub frame : Option<i32>,
+    pub colormap : Option<i32>,
+    pub skin : Option<i32>,
+    pub effects : Option<i32>,
+    pub origin : [Option<f32>; 3],
+    pub angles : [Option<f32>; 3],
+    /// Don't interpolate the movement, used for monsters moving in steps.
+    pub nolerp : bool,
+}
+
+impl EntityUpdate {
+    /// Creates an update with the fields of `state` that differ from `baseline`.
+    pub fn delta(entity : i32, baseline : &EntityState, state : &EntityState, nolerp : bool) -> EntityUpdate {
+        let changed = |base : i32, value : i32| if base != value { Some(value) } else { None };
+        let mut update = EntityUpdate {
+            entity : entity,
+            modelindex : changed(baseline.modelindex, state.modelindex),
+            frame : changed(baseline.frame, state.frame),
+            colormap : changed(baseline.colormap, state.colormap),
+            skin : changed(baseline.skin, state.skin),
+            effects : changed(baseline.effects, state.effects),
+            origin : [None; 3],
+            angles : [None; 3],
+            nolerp : nolerp,
+        };
+        for i in 0..3 {
+            let miss = state.origin[i] - baseline.origin[i];
+            if miss < -0.1 || miss > 0.1 {
+                update.origin[i] = Some(state.origin[i]);
+            }
+            if state.angles[i] != baseline.angles[i] {
+                update.angles[i] = Some(state.angles[i]);
+            }
+        }
+        update
+    }
+
+    /// Returns the baseline with the fields of the update applied.
+    pub fn apply(&self, baseline : &EntityState) -> EntityState {
+        let mut state = *baseline;
+        state.modelindex = self.modelindex.unwrap_or(baseline.modelindex);
+        state.frame = self.frame.unwrap_or(baseline.frame);
+        state.colormap = self.colormap.unwrap_or(baseline.colormap);
+        state.skin = self.skin.unwrap_or(baseline.skin);
+        state.effects = self.effects.unwrap_or(baseline.effects);
+        for i in 0..3 {
+            state.origin[i] = self.origin[i].unwrap_or(baseline.origin[i]);
+            state.angles[i] = self.angles[i].unwrap_or(baseline.angles[i]);
+        }
+        state
+    }
+
+    fn bits(&self) -> i32 {
+        let mut bits = 0;
+        let flags = [(self.origin[0].is_some(), U_ORIGIN1), (self.origin[1].is_some(), U_ORIGIN2),
+                     (self.origin[2].is_some(), U_ORIGIN3), (self.angles[0].is_some(), U_ANGLE1),
+                     (self.angles[1].is_some(), U_ANGLE2), (self.angles[2].is_some(), U_ANGLE3),
+                     (self.nolerp, U_NOLERP), (self.modelindex.is_some(), U_MODEL),
+                     (self.frame.is_some(), U_FRAME), (self.colormap.is_some(), U_COLORMAP),
+                     (self.skin.is_some(), U_SKIN), (self.effects.is_some(), U_EFFECTS),
+                     (self.entity >= 256, U_LONGENTITY)];
+        for &(set, bit) in &flags {
+            if set {
+                bits |= bit;
+            }
+        }
+        if bits >= 256 {
+            bits |= U_MOREBITS;
+        }
+        bits
+    }
+
+    fn write(&self, buf : &mut SizeBuf) {
+        let bits = self.bits();
+        buf.write_byte(bits | U_SIGNAL);
+        if bits & U_MOREBITS != 0 {
+            buf.write_byte(bits >> 8);
+        }
+        if bits & U_LONGENTITY != 0 {
+            buf.write_short(self.entity);
+        } else {
+            buf.write_byte(self.entity);
+        }
+
+        for value in &[self.modelindex, self.frame, self.colormap, self.skin, self.effects] {
+            if let Some(value) = *value {
+                buf.write_byte(value);
+            }
+        }
+        for i in 0..3 {
+            if let Some(origin) = self.origin[i] {
+                buf.write_coord(origin);
+            }
+            if let Some(angle) = self.angles[i] {
+                buf.write_angle(angle);
+            }
+        }
+    }
+
+    fn read(cmd : i32, msg : &mut MsgReader) -> EntityUpdate {
+        let mut bits = cmd & 127;
+        if bits & U_MOREBITS != 0 {
+            bits |= msg.read_byte() << 8;
+        }
+        let entity = if bits & U_LONGENTITY != 0 { msg.read_short() } else { msg.read_byte() };
+
+        let byte_field = |bit : i32, msg : &mut MsgReader| if bits & bit != 0 { Some(msg.read_byte()) } else { None };
+        let modelindex = byte_field(U_MODEL, msg);
+        let frame = byte_field(U_FRAME, msg);
+        let colormap = byte_field(U_COLORMAP, msg);
+        let skin = byte_field(U_SKIN, msg);
+        let effects = byte_field(U_EFFECTS, msg);
+
+        let mut origin = [None; 3];
+        let mut angles = [None; 3];
+        let origin_bits = [U_ORIGIN1, U_ORIGIN2, U_ORIGIN3];
+        let angle_bits = [U_ANGLE1, U_ANGLE2, U_ANGLE3];
+        for i in 0..3 {
+            if bits & origin_bits[i] != 0 {
+                origin[i] = Some(msg.read_coord());
+            }
+            if bits & angle_bits[i] != 0 {
+                angles[i] = Some(msg.read_angle());
+            }
+        }
+
+        EntityUpdate {
+            entity : entity,
+            modelindex : modelindex,
+            frame : frame,
+            colormap : colormap,
+            skin : skin,
+            effects : effects,
+            origin : origin,
+            angles : angles,
+            nolerp : bits & U_NOLERP != 0,
+        }
+    }
+}
+
+/// Player state that is sent to the client every frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClientData {
+    pub viewheight : i32,
+    pub idealpitch : i32,
+    pub punchangle : Vec3,
+    /// Velocity, only sent with a precision of 16 units per second.
+    pub velocity : Vec3,
+    pub items : i32,
+    pub onground : bool,
+    pub inwater : bool,
+    pub weaponframe : i32,
+    pub armor : i32,
+    /// Model index of the weapon.
+    pub weapon : i32,
+    pub health : i32,
+    pub ammo : i32,
+    pub shells : i32,
+    pub nails : i32,
+    pub rockets : i32,
+    pub cells : i32,
+    pub activeweapon : i32,
+}
+
+impl ClientData {
+    /// Creates client data with the default values of the optional fields.
+    pub fn new() -> ClientData {
+        ClientData {
+            viewheight : DEFAULT_VIEWHEIGHT,
+            idealpitch : 0,
+            punchangle : [0.0; 3],
+            velocity : [0.0; 3],
+            items : 0,
+            onground : false,
+            inwater : false,
+            weaponframe : 0,
+            armor : 0,
+            weapon : 0,
+            health : 0,
+            ammo : 0,
+            shells : 0,
+            nails : 0,
+            rockets : 0,
+            cells : 0,
+            activeweapon : 0,
+        }
+    }
+
+    fn write(&self, buf : &mut SizeBuf) {
+        let mut bits = SU_ITEMS;
+        if self.viewheight != DEFAULT_VIEWHEIGHT {
+            bits |= SU_VIEWHEIGHT;
+        }
+        if self.idealpitch != 0 {
+            bits |= SU_IDEALPITCH;
+        }
+        for i in 0..3 {
+            if self.punchangle[i] != 0.0 {
+                bits |= SU_PUNCH1 << i;
+            }
+            if (self.velocity[i] / 16.0) as i32 != 0 {
+                bits |= SU_VELOCITY1 << i;
+            }
+        }
+        if self.onground {
+            bits |= SU_ONGROUND;
+        }
+        if self.inwater {
+            bits |= SU_INWATER;
+        }
+        if self.weaponframe != 0 {
+            bits |= SU_WEAPONFRAME;
+        }
+        if self.armor != 0 {
+            bits |= SU_ARMOR;
+        }
+        if self.weapon != 0 {
+            bits |= SU_WEAPON;
+        }
+
+        buf.write_short(bits);
+        if bits & SU_VIEWHEIGHT != 0 {
+            buf.write_char(self.viewheight);
+        }
+        if bits & SU_IDEALPITCH != 0 {
+            buf.write_char(self.idealpitch);
+        }
+        for i in 0..3 {
+            if bits & (SU_PUNCH1 << i) != 0 {
+                buf.write_char(self.punchangle[i] as i32);
+            }
+            if bits & (SU_VELOCITY1 << i) != 0 {
+                buf.write_char((self.velocity[i] / 16.0) as i32);
+            }
+        }
+        buf.write_long(self.items);
+        if bits & SU_WEAPONFRAME != 0 {
+            buf.write_byte(self.weaponframe);
+        }
+        if bits & SU_ARMOR != 0 {
+            buf.write_byte(self.armor);
+        }
+        if bits & SU_WEAPON != 0 {
+            buf.write_byte(self.weapon);
+        }
+        buf.write_short(self.health);
+        buf.write_byte(self.ammo);
+        buf.write_byte(self.shells);
+        buf.write_byte(self.nails);
+        buf.write_byte(self.rockets);
+        buf.write_byte(self.cells);
+        buf.write_byte(self.activeweapon);
+    }
+
+    fn read(msg : &mut MsgReader) -> ClientData {
+        let bits = msg.read_short();
+        let mut data = ClientData::new();
+        if bits & SU_VIEWHEIGHT != 0 {
+            data.viewheight = msg.read_char();
+        }
+        if bits & SU_IDEALPITCH != 0 {
+            data.idealpitch = msg.read_char();
+        }
+        for i in 0..3 {
+            if bits & (SU_PUNCH1 << i) != 0 {
+                data.punchangle[i] = msg.read_char() as f32;
+            }
+            if bits & (SU_VELOCITY1 << i) != 0 {
+                data.velocity[i] = msg.read_char() as f32 * 16.0;
+            }
+        }
+        data.items = msg.read_long();
+        data.onground = bits & SU_ONGROUND != 0;
+        data.inwater = bits & SU_INWATER != 0;
+        if bits & SU_WEAPONFRAME != 0 {
+            data.weaponframe = msg.read_byte();
+        }
+        if bits & SU_ARMOR != 0 {
+            data.armor = msg.read_byte();
+        }
+        if bits & SU_WEAPON != 0 {
+            data.weapon = msg.read_byte();
+        }
+        data.health = msg.read_short();
+        data.ammo = msg.read_byte();
+        data.shells = msg.read_byte();
+        data.nails = msg.read_byte();
+        data.rockets = msg.read_byte();
+        data.cells = msg.read_byte();
+        data.activeweapon = msg.read_byte();
+        data
+    }
+}
+
+/// A sound started at the position of an entity.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SoundStart {
+    pub entity : i32,
+    pub channel : i32,
+    pub sound : i32,
+    /// Volume from 0 to 255.
+    pub volume : i32,
+    pub attenuation : f32,
+    pub origin : Vec3,
+}
+
+/// Short lived effects.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TempEntity {
+    /// Impacts, explosions and other effects at a point, the kind is one of the TE_ constants.
+    Point { kind : i32, origin : Vec3 },
+    /// Lightning bolts and beams from an entity.
+    Beam { kind : i32, entity : i32, start : Vec3, end : Vec3 },
+    /// Explosion with a range of palette colors.
+    Explosion2 { origin : Vec3, color_start : i32, color_length : i32 },
+}
+
+impl TempEntity {
+    fn write(&self, buf : &mut SizeBuf) {
+        match *self {
+            TempEntity::Point { kind, ref origin } => {
+                buf.write_byte(kind);
+                buf.write_vector(origin);
+            }
+            TempEntity::Beam { kind, entity, ref start, ref end } => {
+                buf.write_byte(kind);
+                buf.write_short(entity);
+                buf.write_vector(start);
+                buf.write_vector(end);
+            }
+            TempEntity::Explosion2 { ref origin, color_start, color_length } => {
+                buf.write_byte(TE_EXPLOSION2);
+                buf.write_vector(origin);
+                buf.write_byte(color_start);
+                buf.write_byte(color_length);
+            }
+        }
+    }
+
+    fn read(msg : &mut MsgReader) -> Option<TempEntity> {
+        let kind = msg.read_byte();
+        match kind {
+            TE_SPIKE | TE_SUPERSPIKE | TE_GUNSHOT | TE_EXPLOSION | TE_TAREXPLOSION |
+            TE_WIZSPIKE | TE_KNIGHTSPIKE | TE_LAVASPLASH | TE_TELEPORT => Some(TempEntity::Point {
+                kind : kind,
+                origin : msg.read_vector(),
+            }),
+            TE_LIGHTNING1 | TE_LIGHTNING2 | TE_LIGHTNING3 | TE_BEAM => {
+                let entity = msg.read_short();
+                let start = msg.read_vector();
+                Some(TempEntity::Beam { kind : kind, entity : entity, start : start, end : msg.read_vector() })
+            }
+            TE_EXPLOSION2 => {
+                let origin = msg.read_vector();
+                let color_start = msg.read_byte();
+                Some(TempEntity::Explosion2 { origin : origin, color_start : color_start, color_length : msg.read_byte() })
+            }
+            _ => {
+                println!("CL_ParseTEnt: bad type {}", kind);
+                None
+            }
+        }
+    }
+}
+
+/// Messages sent from the server to the client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ServerMessage {
+    Nop,
+    Disconnect,
+    UpdateStat { stat : i32, value : i32 },
+    Version(i32),
+    SetView(i32),
+    Sound(SoundStart),
+    Time(f32),
+    Print(String),
+    StuffText(String),
+    SetAngle(Vec3),
+    /// First signon message with the precached models and sounds.
+    ServerInfo {
+        protocol : i32,
+        maxclients : i32,
+        gametype : i32,
+        levelname : String,
+        models : Vec<String>,
+        sounds : Vec<String>,
+    },
+    LightStyle { style : i32, map : String },
+    UpdateName { client : i32, name : String },
+    UpdateFrags { client : i32, frags : i32 },
+    ClientData(ClientData),
+    StopSound { entity : i32, channel : i32 },
+    UpdateColors { client : i32, colors : i32 },
+    /// Particle effect, direction is stored with a precision of 1/16.
+    Particle { origin : Vec3, dir : Vec3, count : i32, color : i32 },
+    Damage { armor : i32, blood : i32, from : Vec3 },
+    SpawnStatic(EntityState),
+    SpawnBaseline { entity : i32, baseline : EntityState },
+    TempEntity(TempEntity),
+    SetPause(bool),
+    SignonNum(i32),
+    CenterPrint(String),
+    KilledMonster,
+    FoundSecret,
+    SpawnStaticSound { origin : Vec3, sound : i32, volume : i32, attenuation : f32 },
+    Intermission,
+    Finale(String),
+    CdTrack { track : i32, looptrack : i32 },
+    SellScreen,
+    Cutscene(String),
+    /// Entity update, these messages have the high bit of the command byte set.
+    Update(EntityUpdate),
+}
+
+impl ServerMessage {
+    /// Appends the message to a buffer.
+    pub fn write(&self, buf : &mut SizeBuf) {
+        match *self {
+            ServerMessage::Nop => buf.write_byte(SVC_NOP),
+            ServerMessage::Disconnect => buf.write_byte(SVC_DISCONNECT),
+            ServerMessage::UpdateStat { stat, value } => {
+                buf.write_byte(SVC_UPDATESTAT);
+                buf.write_byte(stat);
+                buf.write_long(value);
+            }
+            ServerMessage::Version(version) => {
+                buf.write_byte(SVC_VERSION);
+                buf.write_long(version);
+            }
+            ServerMessage::SetView(entity) => {
+                buf.write_byte(SVC_SETVIEW);
+                buf.write_short(entity);
+            }
+            ServerMessage::Sound(ref sound) => {
+                let mut field_mask = 0;
+                if sound.volume != DEFAULT_SOUND_PACKET_VOLUME {
+                    field_mask |= SND_VOLUME;
+                }
+                if sound.attenuation != DEFAULT_SOUND_PACKET_ATTENUATION {
+                    field_mask |= SND_ATTENUATION;
+                }
+                buf.write_byte(SVC_SOUND);
+                buf.write_byte(field_mask);
+                if field_mask & SND_VOLUME != 0 {
+                    buf.write_byte(sound.volume);
+                }
+                if field_mask & SND_ATTENUATION != 0 {
+                    buf.write_byte((sound.attenuation * 64.0) as i32);
+                }
+                buf.write_short((sound.entity << 3) | sound.channel);
+                buf.write_byte(sound.sound);
+                buf.write_vector(&sound.origin);
+            }
+            ServerMessage::Time(time) => {
+                buf.write_byte(SVC_TIME);
+                buf.write_float(time);
+            }
+            ServerMessage::Print(ref text) => {
+                buf.write_byte(SVC_PRINT);
+                buf.write_string(text);
+            }
+            ServerMessage::StuffText(ref text) => {
+                buf.write_byte(SVC_STUFFTEXT);
+                buf.write_string(text);
+            }
+            ServerMessage::SetAngle(ref angles) => {
+                buf.write_byte(SVC_SETANGLE);
+                for &angle in angles {
+                    buf.write_angle(angle);
+                }
+            }
+            ServerMessage::ServerInfo { protocol, maxclients, gametype, ref levelname, ref models, ref sounds } => {
+                buf.write_byte(SVC_SERVERINFO);
+                buf.write_long(protocol);
+                buf.write_byte(maxclients);
+                buf.write_byte(gametype);
+                buf.write_string(levelname);
+                for name in models {
+                    buf.write_string(name);
+                }
+                buf.write_byte(0);
+                for name in sounds {
+                    buf.write_string(name);
+                }
+                buf.write_byte(0);
+            }
+            ServerMessage::LightStyle { style, ref map } => {
+                buf.write_byte(SVC_LIGHTSTYLE);
+                buf.write_byte(style);
+                buf.write_string(map);
+            }
+            ServerMessage::UpdateName { client, ref name } => {
+                buf.write_byte(SVC_UPDATENAME);
+                buf.write_byte(client);
+                buf.write_string(name);
+            }
+            ServerMessage::UpdateFrags { client, frags } => {
+                buf.write_byte(SVC_UPDATEFRAGS);
+                buf.write_byte(client);
+                buf.write_short(frags);
+            }
+            ServerMessage::ClientData(ref data) => {
+                buf.write_byte(SVC_CLIENTDATA);
+                data.write(buf);
+            }
+            ServerMessage::StopSound { entity, channel } => {
+                buf.write_byte(SVC_STOPSOUND);
+                buf.write_short((entity << 3) | channel);
+            }
+            ServerMessage::UpdateColors { client, colors } => {
+                buf.write_byte(SVC_UPDATECOLORS);
+                buf.write_byte(client);
+                buf.write_byte(colors);
+            }
+            ServerMessage::Particle { ref origin, ref dir, count, color } => {
+                buf.write_byte(SVC_PARTICLE);
+                buf.write_vector(origin);
+                for &d in dir {
+                    buf.write_char(((d * 16.0) as i32).max(-128).min(127));
+                }
+                buf.write_byte(count);
+                buf.write_byte(color);
+            }
+            ServerMessage::Damage { armor, blood, ref from } => {
+                buf.write_byte(SVC_DAMAGE);
+                buf.write_byte(armor);
+                buf.write_byte(blood);
+                buf.write_vector(from);
+            }
+            ServerMessage::SpawnStatic(ref state) => {
+                buf.write_byte(SVC_SPAWNSTATIC);
+                state.write(buf);
+            }
+            ServerMessage::SpawnBaseline { entity, ref baseline } => {
+                buf.write_byte(SVC_SPAWNBASELINE);
+                buf.write_short(entity);
+                baseline.write(buf);
+            }
+            ServerMessage::TempEntity(ref tent) => {
+                buf.write_byte(SVC_TEMP_ENTITY);
+                tent.write(buf);
+            }
+            ServerMessage::SetPause(paused) => {
+                buf.write_byte(SVC_SETPAUSE);
+                buf.write_byte(paused as i32);
+            }
+            ServerMessage::SignonNum(signon) => {
+                buf.write_byte(SVC_SIGNONNUM);
+                buf.write_byte(signon);
+            }
+            ServerMessage::CenterPrint(ref text) => {
+                buf.write_byte(SVC_CENTERPRINT);
+                buf.write_string(text);
+            }
+            ServerMessage::KilledMonster => buf.write_byte(SVC_KILLEDMONSTER),
+            ServerMessage::FoundSecret => buf.write_byte(SVC_FOUNDSECRET),
+            ServerMessage::SpawnStaticSound { ref origin, sound, volume, attenuation } => {
+                buf.write_byte(SVC_SPAWNSTATICSOUND);
+                buf.write_vector(origin);
+                buf.write_byte(sound);
+                buf.write_byte(volume);
+                buf.write_byte((attenuation * 64.0) as i32);
+            }
+            ServerMessage::Intermission => buf.write_byte(SVC_INTERMISSION),
+            ServerMessage::Finale(ref text) => {
+                buf.write_byte(SVC_FINALE);
+                buf.write_string(text);
+            }
+            ServerMessage::CdTrack { track, looptrack } => {
+                buf.write_byte(SVC_CDTRACK);
+                buf.write_byte(track);
+                buf.write_byte(looptrack);
+            }
+            ServerMessage::SellScreen => buf.write_byte(SVC_SELLSCREEN),
+            ServerMessage::Cutscene(ref text) => {
+                buf.write_byte(SVC_CUTSCENE);
+                buf.write_string(text);
+            }
+            ServerMessage::Update(ref update) => update.write(buf),
+        }
+    }
+
+    /// Reads the next message. Returns `None` at the end of the message
+    /// or if the message can't be parsed, in which case `msg.badread` is set.
+    pub fn read(msg : &mut MsgReader) -> Option<ServerMessage> {
+        if msg.at_end() {
+            return None;
+        }
+        let cmd = msg.read_byte();
+        if cmd & U_SIGNAL != 0 {
+            return Some(ServerMessage::Update(EntityUpdate::read(cmd, msg)));
+        }
+
+        let message = match cmd {
+            SVC_NOP => ServerMessage::Nop,
+            SVC_DISCONNECT => ServerMessage::Disconnect,
+            SVC_UPDATESTAT => {
+                let stat = msg.read_byte();
+                ServerMessage::UpdateStat { stat : stat, value : msg.read_long() }
+            }
+            SVC_VERSION => ServerMessage::Version(msg.read_long()),
+            SVC_SETVIEW => ServerMessage::SetView(msg.read_short()),
+            SVC_SOUND => {
+                let field_mask = msg.read_byte();
+                let volume = if field_mask & SND_VOLUME != 0 { msg.read_byte() } else { DEFAULT_SOUND_PACKET_VOLUME };
+                let attenuation = if field_mask & SND_ATTENUATION != 0 {
+                    msg.read_byte() as f32 / 64.0
+                } else {
+                    DEFAULT_SOUND_PACKET_ATTENUATION
+                };
+                let channel = msg.read_short();
+                let sound = msg.read_byte();
+                ServerMessage::Sound(SoundStart {
+                    entity : channel >> 3,
+                    channel : channel & 7,
+                    sound : sound,
+                    volume : volume,
+                    attenuation : attenuation,
+                    origin : msg.read_vector(),
+                })
+            }
+            SVC_TIME => ServerMessage::Time(msg.read_float()),
+            SVC_PRINT => ServerMessage::Print(msg.read_string()),
+            SVC_STUFFTEXT => ServerMessage::StuffText(msg.read_string()),
+            SVC_SETANGLE => ServerMessage::SetAngle([msg.read_angle(), msg.read_angle(), msg.read_angle()]),
+            SVC_SERVERINFO => {
+                let protocol = msg.read_long();
+                let maxclients = msg.read_byte();
+                let gametype = msg.read_byte();
+                let levelname = msg.read_string();
+                let read_list = |msg : &mut MsgReader| {
+                    let mut list = Vec::new();
+                    loop {
+                        let name = msg.read_string();
+                        if name.is_empty() || msg.badread {
+                            break;
+                        }
+                        list.push(name);
+                    }
+                    list
+                };
+                let models = read_list(msg);
+                let sounds = read_list(msg);
+                ServerMessage::ServerInfo {
+                    protocol : protocol,
+                    maxclients : maxclients,
+                    gametype : gametype,
+                    levelname : levelname,
+                    models : models,
+                    sounds : sounds,
+                }
+            }
+            SVC_LIGHTSTYLE => {
+                let style = msg.read_byte();
+                ServerMessage::LightStyle { style : style, map : msg.read_string() }
+            }
+            SVC_UPDATENAME => {
+                let client = msg.read_byte();
+                ServerMessage::UpdateName { client : client, name : msg.read_string() }
+            }
+            SVC_UPDATEFRAGS => {
+                let client = msg.read_byte();
+                ServerMessage::UpdateFrags { client : client, frags : msg.read_short() }
+            }
+            SVC_CLIENTDATA => ServerMessage::ClientData(ClientData::read(msg)),
+            SVC_STOPSOUND => {
+                let channel = msg.read_short();
+                ServerMessage::StopSound { entity : channel >> 3, channel : channel & 7 }
+            }
+            SVC_UPDATECOLORS => {
+                let client = msg.read_byte();
+                ServerMessage::UpdateColors { client : client, colors : msg.read_byte() }
+            }
+            SVC_PARTICLE => {
+                let origin = msg.read_vector();
+                let mut dir = [0.0; 3];
+                for d in &mut dir {
+                    *d = msg.read_char() as f32 * (1.0 / 16.0);
+                }
+                let count = msg.read_byte();
+                ServerMessage::Particle { origin : origin, dir : dir, count : count, color : msg.read_byte() }
+            }
+            SVC_DAMAGE => {
+                let armor = msg.read_byte();
+                let blood = msg.read_byte();
+                ServerMessage::Damage { armor : armor, blood : blood, from : msg.read_vector() }
+            }
+            SVC_SPAWNSTATIC => ServerMessage::SpawnStatic(EntityState::read(msg)),
+            SVC_SPAWNBASELINE => {
+                let entity = msg.read_short();
+                ServerMessage::SpawnBaseline { entity : entity, baseline : EntityState::read(msg) }
+            }
+            SVC_TEMP_ENTITY => match TempEntity::read(msg) {
+                Some(tent) => ServerMessage::TempEntity(tent),
+                None => {
+                    msg.badread = true;
+                    return None;
+                }
+            },
+            SVC_SETPAUSE => ServerMessage::SetPause(msg.read_byte() != 0),
+            SVC_SIGNONNUM => ServerMessage::SignonNum(msg.read_byte()),
+            SVC_CENTERPRINT => ServerMessage::CenterPrint(msg.read_string()),
+            SVC_KILLEDMONSTER => ServerMessage::KilledMonster,
+            SVC_FOUNDSECRET => ServerMessage::FoundSecret,
+            SVC_SPAWNSTATICSOUND => {
+                let origin = msg.read_vector();
+                let sound = msg.read_byte();
+                let volume = msg.read_byte();
+                ServerMessage::SpawnStaticSound {
+                    origin : origin,
+                    sound : sound,
+                    volume : volume,
+                    attenuation : msg.read_byte() as f32 / 64.0,
+                }
+            }
+            SVC_INTERMISSION => ServerMessage::Intermission,
+            SVC_FINALE => ServerMessage::Finale(msg.read_string()),
+            SVC_CDTRACK => {
+                let track = msg.read_byte();
+                ServerMessage::CdTrack { track : track, looptrack : msg.read_byte() }
+            }
+            SVC_SELLSCREEN => ServerMessage::SellScreen,
+            SVC_CUTSCENE => ServerMessage::Cutscene(msg.read_string()),
+            _ => {
+                println!("CL_ParseServerMessage: Illegible server message {}", cmd);
+                msg.badread = true;
+                return None;
+            }
+        };
+
+        if msg.badread {
+            println!("CL_ParseServerMessage: Bad server message");
+            return None;
+        }
+        Some(message)
+    }
+}
+
+/// Messages sent from the client to the server.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientMessage {
+    Nop,
+    Disconnect,
+    /// Move command with the time of the last received server message.
+    Move { time : f32, cmd : UserCmd },
+    StringCmd(String),
+}
+
+impl ClientMessage {
+    /// Appends the message to a buffer.
+    pub fn write(&self, buf : &mut SizeBuf) {
+        match *self {
+            ClientMessage::Nop => buf.write_byte(CLC_NOP),
+            ClientMessage::Disconnect => buf.write_byte(CLC_DISCONNECT),
+            ClientMessage::Move { time, ref cmd } => {
+                buf.write_byte(CLC_MOVE);
+                buf.write_float(time);
+                for &angle in &cmd.viewangles {
+                    buf.write_angle(angle);
+                }
+                buf.write_short(cmd.forwardmove as i32);
+                buf.write_short(cmd.sidemove as i32);
+                buf.write_short(cmd.upmove as i32);
+                buf.write_byte(cmd.buttons);
+                buf.write_byte(cmd.impulse);
+            }
+            ClientMessage::StringCmd(ref text) => {
+                buf.write_byte(CLC_STRINGCMD);
+                buf.write_string(text);
+            }
+        }
+    }
+
+    /// Reads the next message. Returns `None` at the end of the message
+    /// or if the message can't be parsed, in which case `msg.badread` is set.
+    pub fn read(msg : &mut MsgReader) -> Option<ClientMessage> {
+        if msg.at_end() {
+            return None;
+        }
+        let cmd = msg.read_byte();
+        let message = match cmd {
+            CLC_NOP => ClientMessage::Nop,
+            CLC_DISCONNECT => ClientMessage::Disconnect,
+            CLC_MOVE => {
+                let time = msg.read_float();
+                let mut cmd = UserCmd::new();
+                for angle in &mut cmd.viewangles {
+                    *angle = msg.read_angle();
+                }
+                cmd.forwardmove = msg.read_short() as f32;
+                cmd.sidemove = msg.read_short() as f32;
+                cmd.upmove = msg.read_short() as f32;
+                cmd.buttons = msg.read_byte();
+                cmd.impulse = msg.read_byte();
+                ClientMessage::Move { time : time, cmd : cmd }
+            }
+            CLC_STRINGCMD => ClientMessage::StringCmd(msg.read_string()),
+            _ => {
+                println!("SV_ReadClientMessage: unknown command char {}", cmd);
+                msg.badread = true;
+                return None;
+            }
+        };
+
+        if msg.badread {
+            println!("SV_ReadClientMessage: badread");
+            return None;
+        }
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(messages : &[ServerMessage]) {
+        let mut buf = SizeBuf::new(1024);
+        for message in messages {
+            message.write(&mut buf);
+        }
+        let mut msg = MsgReader::new(&buf.data);
+        for message in messages {
+            assert_eq!(ServerMessage::read(&mut msg).as_ref(), Some(message));
+        }
+        assert!(ServerMessage::read(&mut msg).is_none());
+        assert!(!msg.badread);
+    }
+
+    fn state() -> EntityState {
+        EntityState {
+            origin : [10.5, -20.0, 300.125],
+            angles : [0.0, 90.0, -45.0],
+            modelindex : 5,
+            frame : 3,
+            colormap : 1,
+            skin : 2,
+            effects : 0,
+        }
+    }
+
+    #[test]
+    fn simple_messages() {
+        round_trip(&[
+            ServerMessage::Nop,
+            ServerMessage::Disconnect,
+            ServerMessage::UpdateStat { stat : 3, value : -70000 },
+            ServerMessage::Version(PROTOCOL_VERSION),
+            ServerMessage::SetView(1),
+            ServerMessage::Time(12.25),
+            ServerMessage::Print("hello\n".to_string()),
+            ServerMessage::StuffText("reconnect\n".to_string()),
+            ServerMessage::SetAngle([-45.0, 180.0 - 360.0, 0.0]),
+            ServerMessage::LightStyle { style : 10, map : "mmamammmmammamamaaamammma".to_string() },
+            ServerMessage::UpdateName { client : 2, name : "player".to_string() },
+            ServerMessage::UpdateFrags { client : 2, frags : -3 },
+            ServerMessage::StopSound { entity : 300, channel : 4 },
+            ServerMessage::UpdateColors { client : 1, colors : 0x4f },
+            ServerMessage::SetPause(true),
+            ServerMessage::SignonNum(2),
+            ServerMessage::CenterPrint("The Slipgate Complex".to_string()),
+            ServerMessage::KilledMonster,
+            ServerMessage::FoundSecret,
+            ServerMessage::Intermission,
+            ServerMessage::Finale("The end".to_string()),
+            ServerMessage::CdTrack { track : 4, looptrack : 4 },
+            ServerMessage::SellScreen,
+            ServerMessage::Cutscene("".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn signon_messages() {
+        round_trip(&[
+            ServerMessage::ServerInfo {
+                protocol : PROTOCOL_VERSION,
+                maxclients : 4,
+                gametype : GAME_DEATHMATCH,
+                levelname : "the Necropolis".to_string(),
+                models : vec!["maps/e1m3.bsp".to_string(), "*1".to_string(), "progs/player.mdl".to_string()],
+                sounds : vec!["weapons/r_exp3.wav".to_string()],
+            },
+            ServerMessage::SpawnBaseline { entity : 1, baseline : state() },
+            ServerMessage::SpawnStatic(state()),
+            ServerMessage::SpawnStaticSound { origin : [1.0, 2.0, 3.0], sound : 7, volume : 255, attenuation : 3.0 },
+        ]);
+    }
+
+    #[test]
+    fn effect_messages() {
+        round_trip(&[
+            ServerMessage::Sound(SoundStart {
+                entity : 1, channel : 1, sound : 3, volume : 255, attenuation : 1.0, origin : [0.0, 8.0, -8.0],
+            }),
+            ServerMessage::Sound(SoundStart {
+                entity : 500, channel : 7, sound : 200, volume : 128, attenuation : 0.5, origin : [4000.0, -4000.0, 0.125],
+            }),
+            ServerMessage::Particle { origin : [1.0, 2.0, 3.0], dir : [0.5, -1.0, 0.0], count : 20, color : 73 },
+            ServerMessage::Damage { armor : 5, blood : 20, from : [-100.0, 50.0, 24.0] },
+            ServerMessage::TempEntity(TempEntity::Point { kind : TE_GUNSHOT, origin : [1.0, 2.0, 3.0] }),
+            ServerMessage::TempEntity(TempEntity::Point { kind : TE_TELEPORT, origin : [-1.0, 0.0, 3.0] }),
+            ServerMessage::TempEntity(TempEntity::Beam { kind : TE_LIGHTNING2, entity : 1, start : [0.0; 3], end : [600.0, 0.0, 0.0] }),
+            ServerMessage::TempEntity(TempEntity::Explosion2 { origin : [0.0, 0.0, 64.0], color_start : 100, color_length : 8 }),
+        ]);
+    }
+
+    #[test]
+    fn client_data() {
+        let mut data = ClientData::new();
+        data.items = 0x4001;
+        data.health = 100;
+        data.ammo = 25;
+        data.shells = 25;
+        data.activeweapon = 1;
+        round_trip(&[ServerMessage::ClientData(data)]);
+
+        data.viewheight = -10;
+        data.idealpitch = 15;
+        data.punchangle = [-2.0, 0.0, 0.0];
+        data.velocity = [320.0, -160.0, 0.0];
+        data.onground = true;
+        data.inwater = true;
+        data.weaponframe = 4;
+        data.armor = 200;
+        data.weapon = 12;
+        data.health = -20;
+        round_trip(&[ServerMessage::ClientData(data)]);
+    }
+
+    #[test]
+    fn entity_updates() {
+        let baseline = state();
+
+        // nothing changed, only the entity number is sent
+        let update = EntityUpdate::delta(3, &baseline, &baseline, false);
+        let mut buf = SizeBuf::new(64);
+        ServerMessage::Update(update.clone()).write(&mut buf);
+        assert_eq!(buf.data, vec![U_SIGNAL as u8, 3]);
+
+        let mut moved = baseline;
+        moved.origin[1] = 64.0;
+        moved.angles[1] = 180.0 - 360.0;
+        moved.frame = 4;
+        moved.effects = 8;
+        let update = EntityUpdate::delta(300, &baseline, &moved, true);
+        assert_eq!(update.origin, [None, Some(64.0), None]);
+        assert_eq!(update.modelindex, None);
+        round_trip(&[ServerMessage::Update(update.clone())]);
+        assert_eq!(update.apply(&baseline), moved);
+
+        // small origin changes are not sent
+        let mut jitter = baseline;
+        jitter.origin[0] += 0.05;
+        assert_eq!(EntityUpdate::delta(3, &baseline, &jitter, false).origin, [None; 3]);
+
+        let mut changed = baseline;
+        changed.modelindex = 0;
+        changed.colormap = 3;
+        changed.skin = 0;
+        changed.angles = [-45.0, 0.0, 0.0];
+        round_trip(&[ServerMessage::Update(EntityUpdate::delta(7, &baseline, &changed, false))]);
+    }
+
+    #[test]
+    fn client_messages() {
+        let mut cmd = UserCmd::new();
+        cmd.viewangles = [-45.0, 90.0, 0.0];
+        cmd.forwardmove = 400.0;
+        cmd.sidemove = -350.0;
+        cmd.upmove = 200.0;
+        cmd.buttons = 3;
+        cmd.impulse = 10;
+        let messages = [
+            ClientMessage::Nop,
+            ClientMessage::Move { time : 5.5, cmd : cmd },
+            ClientMessage::StringCmd("say hi".to_string()),
+            ClientMessage::Disconnect,
+        ];
+
+        let mut buf = SizeBuf::new(128);
+        for message in &messages {
+            message.write(&mut buf);
+        }
+        let mut msg = MsgReader::new(&buf.data);
+        for message in &messages {
+            assert_eq!(ClientMessage::read(&mut msg).as_ref(), Some(message));
+        }
+        assert!(ClientMessage::read(&mut msg).is_none());
+        assert!(!msg.badread);
+    }
+
+    #[test]
+    fn bad_messages() {
+        let mut msg = MsgReader::new(&[SVC_UPDATESTAT as u8, 1, 2]);
+        assert!(ServerMessage::read(&mut msg).is_none());
+        assert!(msg.badread);
+
+        let mut msg = MsgReader::new(&[21]);
+        assert!(ServerMessage::read(&mut msg).is_none());
+        assert!(msg.badread);
+
+        let mut msg = MsgReader::new(&[CLC_MOVE as u8, 0]);
+        assert!(ClientMessage::read(&mut msg).is_none());
+        assert!(msg.badread);
+    }
+}
@@ -0,0 +1,385 @@
+#![warn(missing_docs)]
+
+//! Clipping and drawing of textured polygons with perspective correct
+//! texture coordinates and a z-buffer, and the underwater screen warp.
+//!
+//! Original source can be found in r_draw.c and d_scan.c
+
+use std::f32::consts::PI;
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{angle_vectors, dot_product, vector_subtract};
+use rquake_fs::{BspTexture, Palette};
+use r_sky::Sky;
+use view::RefDef;
+
+/// Polygons are clipped at this distance in front of the eye.
+const NEAR_CLIP : f32 = 0.01;
+
+/// Scale of the inverse depth stored in the z-buffer.
+const ZI_SCALE : f32 = 32768.0;
+
+// turbulence of liquid surfaces and the underwater view
+const CYCLE : i32 = 128;
+const TURB_SPEED : f32 = 20.0;
+const TURB_AMP : f32 = 8.0;
+const WARP_AMP : i32 = 3;
+
+/// Camera values used to project all polygons of a frame.
+pub struct Camera {
+    /// Position of the eye.
+    pub origin : Vec3,
+    /// View direction.
+    pub forward : Vec3,
+    /// Right of the view direction.
+    pub right : Vec3,
+    /// Up from the view direction.
+    pub up : Vec3,
+    /// Pixels per unit at a distance of one unit.
+    pub scale : f32,
+    xcenter : f32,
+    ycenter : f32,
+    width : i32,
+    height : i32,
+}
+
+impl Camera {
+    /// Sets up the projection of a view with the horizontal field of view
+    /// in degrees.
+    pub fn new(refdef : &RefDef, fov_x : f32, width : u32, height : u32) -> Camera {
+        let (forward, right, up) = angle_vectors(&refdef.viewangles);
+        Camera {
+            origin : refdef.vieworg,
+            forward : forward,
+            right : right,
+            up : up,
+            scale : width as f32 / 2.0 / (fov_x.max(1.0).min(179.0) * PI / 360.0).tan(),
+            xcenter : width as f32 / 2.0 - 0.5,
+            ycenter : height as f32 / 2.0 - 0.5,
+            width : width as i32,
+            height : height as i32,
+        }
+    }
+
+    /// Returns the direction of a pixel used to look up the sky.
+    fn sky_dir(&self, u : f32, v : f32) -> Vec3 {
+        let size = self.width.max(self.height) as f32;
+        let wu = 8192.0 * (u - (self.width >> 1) as f32) / size;
+        let wv = 8192.0 * ((self.height >> 1) as f32 - v) / size;
+        let mut dir = [0.0; 3];
+        for i in 0..3 {
+            dir[i] = 4096.0 * self.forward[i] + wu * self.right[i] + wv * self.up[i];
+        }
+        dir
+    }
+
+    /// Returns a world point in view space, x is right, y is up and z is
+    /// the depth.
+    fn transform(&self, p : &Vec3) -> Vec3 {
+        let local = vector_subtract(p, &self.origin);
+        [dot_product(&local, &self.right), dot_product(&local, &self.up), dot_product(&local, &self.forward)]
+    }
+}
+
+/// Source of the texels of a polygon.
+pub enum Texels<'a> {
+    /// Lit surface from the cache, coordinates are clamped to its size.
+    Cached {
+        /// Palette indices.
+        pixels : &'a [u8],
+        /// Width in texels.
+        width : usize,
+        /// Height in texels.
+        height : usize,
+    },
+    /// Texture repeated over the polygon without lighting.
+    Tiled(&'a BspTexture),
+    /// Liquid texture warped by sine waves moving with the client time.
+    Turbulent(&'a BspTexture, f32),
+    /// Scrolling sky at the client time, looked up by view direction.
+    Sky(&'a Sky, f32),
+}
+
+/// Returns the texel of a texture repeated in all directions.
+fn tiled_texel(texture : &BspTexture, s : f32, t : f32) -> u8 {
+    let (w, h) = (texture.width as i32, texture.height as i32);
+    if w == 0 || h == 0 {
+        return 0;
+    }
+    let s = (s.floor() as i32).rem_euclid(w) as usize;
+    let t = (t.floor() as i32).rem_euclid(h) as usize;
+    texture.pixels[t * w as usize + s]
+}
+
+/// Returns an offset from 0 to 2 * amp that moves along a sine wave.
+fn turb(x : i32, phase : i32, amp : f32) -> f32 {
+    amp + (((x + phase) & (CYCLE - 1)) as f32 * 2.0 * PI / CYCLE as f32).sin() * amp
+}
+
+impl<'a> Texels<'a> {
+    fn texel(&self, s : f32, t : f32) -> u8 {
+        match *self {
+            Texels::Cached { pixels, width, height } => {
+                if width == 0 || height == 0 {
+                    return 0;
+                }
+                let s = (s.max(0.0) as usize).min(width - 1);
+                let t = (t.max(0.0) as usize).min(height - 1);
+                pixels[t * width + s]
+            }
+            Texels::Tiled(texture) => tiled_texel(texture, s, t),
+            Texels::Turbulent(texture, time) => {
+                let phase = (time * TURB_SPEED) as i32;
+                let sturb = s + turb(t.floor() as i32, phase, TURB_AMP);
+                let tturb = t + turb(s.floor() as i32, phase, TURB_AMP);
+                tiled_texel(texture, sturb, tturb)
+            }
+            // sky texels depend on the view direction
+            Texels::Sky(..) => 0,
+        }
+    }
+}
+
+/// Polygon to draw with its plane and texture axes.
+pub struct Polygon<'a> {
+    /// Corners in world space.
+    pub verts : &'a [Vec3],
+    /// Normal of the plane.
+    pub normal : Vec3,
+    /// Distance of the plane from the origin.
+    pub dist : f32,
+    /// The s and t texture axes with the offset as fourth value.
+    pub vecs : [[f32; 4]; 2],
+    /// Subtracted from the texture coordinates.
+    pub texturemins : [i32; 2],
+}
+
+/// Value that changes linearly across the screen.
+#[derive(Clone, Copy, Debug)]
+struct Gradient {
+    du : f32,
+    dv : f32,
+    origin : f32,
+}
+
+impl Gradient {
+    fn at(&self, u : f32, v : f32) -> f32 {
+        self.origin + self.du * u + self.dv * v
+    }
+}
+
+/// Clips a polygon against a plane, the parts where dot(p, plane) +
+/// plane[3] is negative are removed.
+pub fn clip_polygon(verts : &[Vec3], plane : [f32; 4]) -> Vec<Vec3> {
+    let side = |p : &Vec3| p[0] * plane[0] + p[1] * plane[1] + p[2] * plane[2] + plane[3];
+    let mut out = Vec::with_capacity(verts.len() + 1);
+    for (i, p) in verts.iter().enumerate() {
+        let q = &verts[(i + 1) % verts.len()];
+        let (dp, dq) = (side(p), side(q));
+        if dp >= 0.0 {
+            out.push(*p);
+        }
+        if (dp >= 0.0) != (dq >= 0.0) {
+            let frac = dp / (dp - dq);
+            out.push([p[0] + frac * (q[0] - p[0]), p[1] + frac * (q[1] - p[1]), p[2] + frac * (q[2] - p[2])]);
+        }
+    }
+    out
+}
+
+/// Draws a convex polygon, pixels are only written if they are closer
+/// than the z-buffer.
+pub fn draw_polygon(camera : &Camera, poly : &Polygon, texels : &Texels, palette : &Palette, buffer : &mut [u32], zbuffer : &mut [i32]) {
+    let size = (camera.width * camera.height) as usize;
+    if poly.verts.len() < 3 || buffer.len() < size || zbuffer.len() < size {
+        return;
+    }
+
+    // 1/z, s/z and t/z are linear in screen space, derived from the plane
+    let dist = poly.dist - dot_product(&poly.normal, &camera.origin);
+    if dist == 0.0 {
+        return;
+    }
+    let (scale, xc, yc) = (camera.scale, camera.xcenter, camera.ycenter);
+    let nr = dot_product(&poly.normal, &camera.right);
+    let nu = dot_product(&poly.normal, &camera.up);
+    let nf = dot_product(&poly.normal, &camera.forward);
+    let zi = Gradient {
+        du : nr / (scale * dist),
+        dv : -nu / (scale * dist),
+        origin : (nf - xc * nr / scale + yc * nu / scale) / dist,
+    };
+    let mut st = [zi; 2];
+    for ((g, vecs), &mins) in st.iter_mut().zip(poly.vecs.iter()).zip(poly.texturemins.iter()) {
+        let axis = [vecs[0], vecs[1], vecs[2]];
+        let (ar, au, af) = (dot_product(&camera.right, &axis), dot_product(&camera.up, &axis), dot_product(&camera.forward, &axis));
+        let ofs = dot_product(&camera.origin, &axis) + vecs[3] - mins as f32;
+        *g = Gradient {
+            du : ofs * zi.du + ar / scale,
+            dv : ofs * zi.dv - au / scale,
+            origin : ofs * zi.origin + (-xc * ar + yc * au) / scale + af,
+        };
+    }
+
+    // clip against the near plane and the screen edges in view space
+    let (w, h) = (camera.width as f32, camera.height as f32);
+    let mut verts : Vec<Vec3> = poly.verts.iter().map(|v| camera.transform(v)).collect();
+    for plane in &[[0.0, 0.0, 1.0, -NEAR_CLIP], [scale, 0.0, xc + 0.5, 0.0], [-scale, 0.0, w - xc - 0.5, 0.0],
+                   [0.0, -scale, yc + 0.5, 0.0], [0.0, scale, h - yc - 0.5, 0.0]] {
+        verts = clip_polygon(&verts, *plane);
+        if verts.len() < 3 {
+            return;
+        }
+    }
+    let screen : Vec<(f32, f32)> = verts.iter().map(|v| (xc + scale * v[0] / v[2], yc - scale * v[1] / v[2])).collect();
+
+    let ymin = screen.iter().fold(h, |m, p| m.min(p.1));
+    let ymax = screen.iter().fold(-1.0f32, |m, p| m.max(p.1));
+    let first = (ymin.ceil() as i32).max(0);
+    let last = (ymax.ceil() as i32).min(camera.height);
+    for y in first..last {
+        let fy = y as f32;
+        let mut left = w;
+        let mut right = -1.0f32;
+        for (i, p) in screen.iter().enumerate() {
+            let q = screen[(i + 1) % screen.len()];
+            if (p.1 <= fy && q.1 > fy) || (q.1 <= fy && p.1 > fy) {
+                let x = p.0 + (fy - p.1) * (q.0 - p.0) / (q.1 - p.1);
+                left = left.min(x);
+                right = right.max(x);
+            }
+        }
+        let x0 = (left.ceil() as i32).max(0);
+        let x1 = (right.ceil() as i32).min(camera.width);
+        for x in x0..x1 {
+            let (fx, ofs) = (x as f32, (y * camera.width + x) as usize);
+            let izf = zi.at(fx, fy);
+            if izf <= 0.0 {
+                continue;
+            }
+            let izi = (izf * ZI_SCALE) as i32;
+            if zbuffer[ofs] > izi {
+                continue;
+            }
+            let texel = match *texels {
+                Texels::Sky(sky, time) => sky.texel(&camera.sky_dir(fx, fy), time),
+                _ => {
+                    let z = 1.0 / izf;
+                    texels.texel(st[0].at(fx, fy) * z, st[1].at(fx, fy) * z)
+                }
+            };
+            zbuffer[ofs] = izi;
+            buffer[ofs] = palette.palette_lookup(texel);
+        }
+    }
+}
+
+/// Distorts the view with sine waves, used when the eye is under water.
+pub fn warp_screen(buffer : &mut [u32], width : u32, height : u32, time : f32) {
+    let (w, h) = (width as usize, height as usize);
+    if w == 0 || h == 0 || buffer.len() < w * h {
+        return;
+    }
+    let amp = WARP_AMP as usize;
+    let rows : Vec<usize> = (0..h + amp * 2).map(|v| (v as f32 * h as f32 / (h + amp * 2) as f32) as usize).collect();
+    let columns : Vec<usize> = (0..w + amp * 2).map(|u| (u as f32 * w as f32 / (w + amp * 2) as f32) as usize).collect();
+    let phase = (time * TURB_SPEED) as i32 & (CYCLE - 1);
+    let offset = |i : usize| turb(i as i32, phase, WARP_AMP as f32) as usize;
+
+    let source = buffer[..w * h].to_vec();
+    for v in 0..h {
+        let turb_v = offset(v);
+        for u in 0..w {
+            buffer[v * w + u] = source[rows[v + offset(u)] * w + columns[u + turb_v]];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn palette() -> Palette {
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        Palette::read(&mut &data[..]).unwrap()
+    }
+
+    /// Wall facing the camera at x = 100, 64 units wide and 32 high,
+    /// textured with s along y and t along -z.
+    fn wall<'a>(verts : &'a [Vec3]) -> Polygon<'a> {
+        Polygon {
+            verts : verts,
+            normal : [-1.0, 0.0, 0.0],
+            dist : -100.0,
+            vecs : [[0.0, -1.0, 0.0, 32.0], [0.0, 0.0, -1.0, 16.0]],
+            texturemins : [0, 0],
+        }
+    }
+
+    #[test]
+    fn projection_and_depth() {
+        let verts = [[100.0, 32.0, 16.0], [100.0, -32.0, 16.0], [100.0, -32.0, -16.0], [100.0, 32.0, -16.0]];
+        let pixels : Vec<u8> = (0..64 * 32).map(|i| if i % 64 < 32 { 1 } else { 2 }).collect();
+        let texels = Texels::Cached { pixels : &pixels, width : 64, height : 32 };
+        let camera = Camera::new(&RefDef::new(), 90.0, 320, 200);
+        let mut buffer = vec![0; 320 * 200];
+        let mut zbuffer = vec![0; 320 * 200];
+        draw_polygon(&camera, &wall(&verts), &texels, &palette(), &mut buffer, &mut zbuffer);
+
+        // 64 units at a distance of 100 cover 102 pixels with a scale of 160
+        assert_eq!(camera.scale.round(), 160.0);
+        let row = &buffer[100 * 320..101 * 320];
+        assert_eq!(row.iter().filter(|&&c| c != 0).count(), 102);
+        assert_eq!(row[150], 0x010101);
+        assert_eq!(row[170], 0x020202);
+        assert_eq!(zbuffer[100 * 320 + 160], 327);
+        assert_eq!(buffer.iter().filter(|&&c| c != 0).count(), 102 * 52);
+
+        // a farther polygon is hidden
+        let far = [[200.0, 32.0, 16.0], [200.0, -32.0, 16.0], [200.0, -32.0, -16.0], [200.0, 32.0, -16.0]];
+        let mut poly = wall(&far);
+        poly.dist = -200.0;
+        let black = vec![0; 64 * 32];
+        draw_polygon(&camera, &poly, &Texels::Cached { pixels : &black, width : 64, height : 32 }, &palette(),
+                     &mut buffer, &mut zbuffer);
+        assert_eq!(buffer[100 * 320 + 150], 0x010101);
+    }
+
+    #[test]
+    fn clipping() {
+        // a floor below the eye reaching behind the camera
+        let verts = [[-100.0, -100.0, -32.0], [-100.0, 100.0, -32.0], [1000.0, 100.0, -32.0], [1000.0, -100.0, -32.0]];
+        let poly = Polygon {
+            verts : &verts,
+            normal : [0.0, 0.0, 1.0],
+            dist : -32.0,
+            vecs : [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]],
+            texturemins : [0, 0],
+        };
+        let texture = BspTexture { name : "floor".to_string(), width : 16, height : 16, pixels : vec![3; 256] };
+        let camera = Camera::new(&RefDef::new(), 90.0, 320, 200);
+        let mut buffer = vec![0; 320 * 200];
+        let mut zbuffer = vec![0; 320 * 200];
+        draw_polygon(&camera, &poly, &Texels::Tiled(&texture), &palette(), &mut buffer, &mut zbuffer);
+
+        // the floor fills the bottom of the screen up to the horizon
+        assert_eq!(buffer[199 * 320], 0x030303);
+        assert_eq!(buffer[199 * 320 + 319], 0x030303);
+        assert_eq!(buffer[90 * 320 + 160], 0);
+        assert!(zbuffer[199 * 320 + 160] > zbuffer[120 * 320 + 160]);
+    }
+
+    #[test]
+    fn turbulence_and_warp() {
+        let texture = BspTexture { name : "*water".to_string(), width : 64, height : 64, pixels : (0..4096).map(|i| (i % 64) as u8).collect() };
+        // s is moved by a sine wave along t that moves with the time
+        assert_eq!(Texels::Turbulent(&texture, 0.0).texel(0.0, 0.0), 8);
+        assert_eq!(Texels::Turbulent(&texture, 0.0).texel(0.0, 32.0), 16);
+        assert_eq!(Texels::Turbulent(&texture, 1.6).texel(0.0, 0.0), 16);
+
+        let mut buffer : Vec<u32> = (0..320 * 200).collect();
+        warp_screen(&mut buffer, 320, 200, 0.0);
+        assert_eq!(buffer[0], 2 * 320 + 2);
+        assert!(buffer.iter().all(|&c| c < 320 * 200));
+    }
+}
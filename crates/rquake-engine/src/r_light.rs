@@ -0,0 +1,225 @@
+#![warn(missing_docs)]
+
+//! Animated light styles and dynamic lights from muzzle flashes,
+//! rockets and explosions.
+//!
+//! Original source can be found in r_light.c and cl_main.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{dot_product, vector_subtract};
+use r_model::WorldModel;
+use server::MAX_LIGHTSTYLES;
+
+/// Maximum number of dynamic lights.
+pub const MAX_DLIGHTS : usize = 32;
+
+/// Value of a light style without a string, lightmaps are used as they are.
+const NORMAL_LIGHT : i32 = 256;
+
+/// Returns the current values of all light styles in 8.8 fixed point.
+/// The strings are animated at 10 letters per second, 'a' is dark, 'm'
+/// is normal and 'z' is about twice as bright.
+pub fn animate_light(lightstyles : &[String], time : f32) -> [i32; MAX_LIGHTSTYLES] {
+    let i = (time * 10.0) as usize;
+    let mut values = [NORMAL_LIGHT; MAX_LIGHTSTYLES];
+    for (value, style) in values.iter_mut().zip(lightstyles.iter()) {
+        let map = style.as_bytes();
+        if !map.is_empty() {
+            *value = (map[i % map.len()] as i32 - 'a' as i32) * 22;
+        }
+    }
+    values
+}
+
+/// Light that changes the lightmaps of nearby surfaces.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DLight {
+    /// Center of the light.
+    pub origin : Vec3,
+    /// Distance that is lit.
+    pub radius : f32,
+    /// Client time when the light goes out.
+    pub die : f32,
+    /// Radius lost per second.
+    pub decay : f32,
+    /// Lights with less than this radius left at a surface don't light it.
+    pub minlight : f32,
+    /// Number of the entity that owns the light, 0 for none.
+    pub key : i32,
+}
+
+impl DLight {
+    fn new(key : i32) -> DLight {
+        DLight {
+            origin : [0.0; 3],
+            radius : 0.0,
+            die : 0.0,
+            decay : 0.0,
+            minlight : 0.0,
+            key : key,
+        }
+    }
+}
+
+/// All dynamic lights of the client.
+pub struct DLights {
+    lights : Vec<DLight>,
+}
+
+impl DLights {
+    /// Creates the dynamic lights, all of them are out.
+    pub fn new() -> DLights {
+        DLights {
+            lights : vec![DLight::new(0); MAX_DLIGHTS],
+        }
+    }
+
+    /// Returns a cleared light for the given entity. An entity reuses its
+    /// light, otherwise a light that went out is taken.
+    pub fn alloc(&mut self, key : i32, time : f32) -> &mut DLight {
+        // first look for an exact key match
+        let index = if key != 0 { self.lights.iter().position(|dl| dl.key == key) } else { None };
+        // then look for anything else
+        let index = index.or_else(|| self.lights.iter().position(|dl| dl.die < time)).unwrap_or(0);
+        self.lights[index] = DLight::new(key);
+        &mut self.lights[index]
+    }
+
+    /// Shrinks the lights by their decay.
+    pub fn decay(&mut self, time : f32, frametime : f32) {
+        for dl in &mut self.lights {
+            if dl.die < time || dl.radius == 0.0 {
+                continue;
+            }
+            dl.radius = (dl.radius - frametime * dl.decay).max(0.0);
+        }
+    }
+
+    /// Returns the light in a slot, the slot is the bit used to mark the
+    /// surfaces it touches.
+    pub fn get(&self, index : usize) -> Option<&DLight> {
+        self.lights.get(index)
+    }
+
+    /// Returns the lights seen from a brush entity at an origin, used to
+    /// light its surfaces in the space of its model.
+    pub fn relative_to(&self, origin : &Vec3) -> DLights {
+        DLights {
+            lights : self.lights.iter().map(|dl| DLight { origin : vector_subtract(&dl.origin, origin), ..*dl }).collect(),
+        }
+    }
+
+    /// Returns the slots and the lights that are on.
+    pub fn active(&self, time : f32) -> Vec<(usize, DLight)> {
+        self.lights.iter().enumerate().filter(|&(_, dl)| dl.die >= time && dl.radius != 0.0).map(|(i, dl)| (i, *dl)).collect()
+    }
+}
+
+/// Sets the bit of a light for every surface within its radius, the
+/// walk starts at the given node of the world model.
+pub fn mark_lights(model : &WorldModel, light : &DLight, bit : u32, node : i32, dlightbits : &mut [u32]) {
+    if node < 0 {
+        return;
+    }
+    let node = match model.nodes.get(node as usize) {
+        Some(node) => node,
+        None => return,
+    };
+    let plane = &model.planes[node.plane];
+    let dist = dot_product(&light.origin, &plane.normal) - plane.dist;
+    if dist > light.radius {
+        mark_lights(model, light, bit, node.children[0], dlightbits);
+        return;
+    }
+    if dist < -light.radius {
+        mark_lights(model, light, bit, node.children[1], dlightbits);
+        return;
+    }
+
+    // mark the polygons
+    let end = (node.first_surface + node.num_surfaces).min(dlightbits.len());
+    for bits in &mut dlightbits[node.first_surface.min(end)..end] {
+        *bits |= bit;
+    }
+    mark_lights(model, light, bit, node.children[0], dlightbits);
+    mark_lights(model, light, bit, node.children[1], dlightbits);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use rquake_fs::BspFile;
+    use r_model::RenderNode;
+    use trace::Plane;
+
+    #[test]
+    fn light_styles() {
+        let mut styles = vec![String::new(); MAX_LIGHTSTYLES];
+        styles[0] = "m".to_string();
+        styles[1] = "az".to_string();
+        let values = animate_light(&styles, 0.05);
+        assert_eq!(values[0], 264);
+        assert_eq!(values[1], 0);
+        assert_eq!(values[2], NORMAL_LIGHT);
+        assert_eq!(animate_light(&styles, 0.15)[1], 550);
+        assert_eq!(animate_light(&styles, 0.25)[1], 0);
+    }
+
+    #[test]
+    fn alloc_and_decay() {
+        let mut dlights = DLights::new();
+        {
+            let dl = dlights.alloc(5, 1.0);
+            dl.radius = 350.0;
+            dl.die = 1.5;
+            dl.decay = 300.0;
+        }
+        dlights.alloc(0, 1.0).die = 2.0;
+        assert_eq!(dlights.active(1.0).len(), 1);
+
+        // an entity reuses its light
+        {
+            let dl = dlights.alloc(5, 1.2);
+            dl.radius = 200.0;
+            dl.die = 1.5;
+        }
+        assert_eq!(dlights.active(1.2), vec![(0, DLight { radius : 200.0, die : 1.5, key : 5, ..DLight::new(0) })]);
+
+        let dl = dlights.alloc(7, 1.2);
+        dl.radius = 350.0;
+        dl.die = 1.5;
+        dl.decay = 300.0;
+        dlights.decay(1.2, 0.5);
+        assert_eq!(dlights.get(2).unwrap().radius, 200.0);
+        dlights.decay(1.2, 1.0);
+        assert_eq!(dlights.get(2).unwrap().radius, 0.0);
+        assert_eq!(dlights.active(1.2).len(), 1);
+    }
+
+    #[test]
+    fn marks_surfaces_in_radius() {
+        let mut model = WorldModel::from_bsp("", Rc::new(BspFile::default()));
+        model.planes = vec![Plane { normal : [1.0, 0.0, 0.0], dist : 0.0, kind : 0 },
+                            Plane { normal : [1.0, 0.0, 0.0], dist : 256.0, kind : 0 }];
+        model.nodes = vec![RenderNode { plane : 0, children : [1, -1], first_surface : 0, num_surfaces : 2 },
+                           RenderNode { plane : 1, children : [-1, -1], first_surface : 2, num_surfaces : 1 }];
+
+        let mut light = DLight::new(0);
+        light.origin = [100.0, 0.0, 0.0];
+        light.radius = 200.0;
+        let mut bits = vec![0; 3];
+        mark_lights(&model, &light, 4, 0, &mut bits);
+        assert_eq!(bits, vec![4, 4, 4]);
+
+        light.origin = [-300.0, 0.0, 0.0];
+        let mut bits = vec![0; 3];
+        mark_lights(&model, &light, 1, 0, &mut bits);
+        assert_eq!(bits, vec![0; 3]);
+
+        light.origin = [300.0, 0.0, 0.0];
+        let mut bits = vec![0; 3];
+        mark_lights(&model, &light, 1, 0, &mut bits);
+        assert_eq!(bits, vec![0, 0, 1]);
+    }
+}
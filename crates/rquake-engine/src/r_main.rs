@@ -0,0 +1,494 @@
+#![warn(missing_docs)]
+
+//! Setup of a frame and drawing of the world and the brush entities,
+//! including the sky, liquids and the underwater view.
+//!
+//! Original source can be found in r_main.c and r_bsp.c
+
+use rquake_common::{BackBuffer, Vec3};
+use rquake_common::mathlib::{angle_vectors, dot_product, vector_add};
+use rquake_fs::Palette;
+use client::ClientState;
+use cvar::CvarList;
+use r_draw::{self, Camera, Polygon, Texels};
+use r_light;
+use r_model::{WorldModel, SURF_DRAWSKY, SURF_DRAWTILED, SURF_DRAWTURB, SURF_PLANEBACK};
+use r_sky::Sky;
+use r_surf::{SurfaceCache, SurfaceParams};
+use trace::{CONTENTS_SOLID, CONTENTS_WATER};
+use view::RefDef;
+
+/// Surfaces closer to edge on than this are not drawn.
+const BACKFACE_EPSILON : f32 = 0.01;
+
+/// Cvar values used to draw a frame.
+pub struct RenderParams {
+    /// Horizontal field of view in degrees.
+    pub fov : f32,
+    /// Debug settings of the surface lighting.
+    pub surface : SurfaceParams,
+    /// The view is warped when the eye is in a liquid.
+    pub waterwarp : bool,
+}
+
+impl RenderParams {
+    /// Reads the values from the cvars.
+    pub fn new(cvars : &CvarList) -> RenderParams {
+        RenderParams {
+            fov : cvars.value("fov"),
+            surface : SurfaceParams {
+                fullbright : cvars.value("r_fullbright") != 0.0,
+                lightmap : cvars.value("r_lightmap") != 0.0,
+            },
+            waterwarp : cvars.value("r_waterwarp") != 0.0,
+        }
+    }
+}
+
+/// Registers the cvars of the renderer.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("r_fullbright", "0", false, false);
+    cvars.register("r_lightmap", "0", false, false);
+    cvars.register("r_waterwarp", "1", false, false);
+}
+
+/// Draws the world of the current map.
+pub struct Renderer {
+    world : Option<WorldModel>,
+    cache : SurfaceCache,
+    dlightbits : Vec<u32>,
+    zbuffer : Vec<i32>,
+    sky : Option<Sky>,
+    warp : bool,
+}
+
+impl Renderer {
+    /// Creates a renderer without a map.
+    pub fn new() -> Renderer {
+        Renderer {
+            world : None,
+            cache : SurfaceCache::new(),
+            dlightbits : Vec::new(),
+            zbuffer : Vec::new(),
+            sky : None,
+            warp : false,
+        }
+    }
+
+    /// Sets the table of 64 light levels for each palette color, surfaces
+    /// are drawn without lighting until it's set.
+    pub fn set_colormap(&mut self, colormap : Vec<u8>) {
+        self.cache.set_colormap(colormap);
+    }
+
+    /// Warps the finished view if the eye was in a liquid when the world
+    /// was drawn.
+    pub fn warp_view(&self, time : f32, backbuffer : &mut BackBuffer) {
+        if self.warp {
+            let (width, height) = (backbuffer.get_width(), backbuffer.get_height());
+            r_draw::warp_screen(backbuffer.get_buffer(), width, height, time);
+        }
+    }
+
+    /// Returns the model of the current map.
+    pub fn world(&self) -> Option<&WorldModel> {
+        self.world.as_ref()
+    }
+
+    /// Returns the inverse depth of every pixel of the last frame, scaled
+    /// by 0x8000. Particles are drawn on top of the world with it.
+    pub fn zbuffer(&mut self) -> &mut [i32] {
+        &mut self.zbuffer
+    }
+
+    /// Starts a new map, the cached surfaces of the previous one are
+    /// removed.
+    pub fn new_map(&mut self, world : Option<WorldModel>) {
+        self.cache.clear();
+        self.dlightbits = vec![0; world.as_ref().map_or(0, |world| world.surfaces.len())];
+        self.sky = world.as_ref()
+            .and_then(|world| world.textures.iter().find(|texture| texture.name.starts_with("sky")))
+            .and_then(Sky::new);
+        self.world = world;
+    }
+
+    /// Clears the view and draws the world and the brush entities with
+    /// the current light styles and dynamic lights.
+    pub fn render_view(&mut self, refdef : &RefDef, cl : &ClientState, time : f32, params : &RenderParams,
+                       palette : &Palette, backbuffer : &mut BackBuffer) {
+        let (width, height) = (backbuffer.get_width(), backbuffer.get_height());
+        let buffer = backbuffer.get_buffer();
+        for pixel in buffer.iter_mut() {
+            *pixel = 0;
+        }
+        self.zbuffer.clear();
+        self.zbuffer.resize((width * height) as usize, 0);
+
+        let world = match self.world {
+            Some(ref world) => world,
+            None => return,
+        };
+        self.warp = params.waterwarp && world.point_contents(&refdef.vieworg) <= CONTENTS_WATER;
+        let lightstyles = r_light::animate_light(&cl.lightstyles, time);
+        self.cache.begin_frame(&params.surface);
+        for bits in &mut self.dlightbits {
+            *bits = 0;
+        }
+
+        // the world is drawn first, then the brush entities
+        if world.submodels.is_empty() {
+            return;
+        }
+        let mut models = vec![(0, [0.0; 3], [0.0; 3], 0)];
+        for ent in &cl.visedicts {
+            let name = cl.model_precache.get(ent.modelindex as usize).map_or("", |name| &name[..]);
+            if name.starts_with('*') {
+                match name[1..].parse::<usize>() {
+                    Ok(submodel) if submodel > 0 && submodel < world.submodels.len() => {
+                        models.push((submodel, ent.origin, ent.angles, ent.frame));
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        // only the world surfaces in leafs visible from the eye are drawn
+        let visible = world.visible_surfaces(&refdef.vieworg);
+        let camera = Camera::new(refdef, params.fov, width, height);
+        let world_headnode = world.submodels[0].headnode[0];
+        for (submodel, origin, angles, frame) in models {
+            let model = &world.submodels[submodel];
+            let axes = angle_vectors(&angles);
+
+            // mark the surfaces touched by dynamic lights
+            let relative;
+            let dlights = if submodel == 0 {
+                &cl.dlights
+            } else {
+                relative = cl.dlights.relative_to(&origin);
+                &relative
+            };
+            for (i, light) in dlights.active(time) {
+                r_light::mark_lights(world, &light, 1 << i, model.headnode[0], &mut self.dlightbits);
+            }
+
+            let first = model.first_face.max(0) as usize;
+            for index in first..(first + model.num_faces.max(0) as usize).min(world.surfaces.len()) {
+                if submodel == 0 && !visible[index] {
+                    continue;
+                }
+                let surf = &world.surfaces[index];
+                let plane = &world.planes[surf.plane];
+                let normal = rotate(&plane.normal, &axes);
+                let dist = plane.dist + dot_product(&origin, &normal);
+                let mut dot = dot_product(&camera.origin, &normal) - dist;
+                if surf.flags & SURF_PLANEBACK != 0 {
+                    dot = -dot;
+                }
+                if dot <= BACKFACE_EPSILON {
+                    continue;
+                }
+
+                let info = &world.texinfo[surf.texinfo];
+                let mut vecs = info.vecs;
+                for v in &mut vecs {
+                    let axis = rotate(&[v[0], v[1], v[2]], &axes);
+                    *v = [axis[0], axis[1], axis[2], v[3] - dot_product(&origin, &axis)];
+                }
+                let texture = world.texture_animation(info.miptex as usize, frame, time);
+                let texels = if surf.flags & SURF_DRAWSKY != 0 {
+                    match self.sky {
+                        Some(ref sky) => Texels::Sky(sky, time),
+                        None => Texels::Tiled(&world.textures[texture]),
+                    }
+                } else if surf.flags & SURF_DRAWTURB != 0 {
+                    Texels::Turbulent(&world.textures[texture], time)
+                } else {
+                    let cached = self.cache.get(world, index, texture, &lightstyles, dlights, self.dlightbits[index]);
+                    Texels::Cached { pixels : &cached.pixels, width : cached.width, height : cached.height }
+                };
+
+                // brush entities are cut by the world, the parts in solid
+                // space are never seen
+                let verts : Vec<Vec3> = surf.verts.iter().map(|v| vector_add(&origin, &rotate(v, &axes))).collect();
+                let mut pieces = Vec::new();
+                if submodel == 0 {
+                    pieces.push(verts);
+                } else {
+                    clip_to_world(world, verts, world_headnode, &mut pieces);
+                }
+                for verts in &pieces {
+                    let poly = Polygon {
+                        verts : verts,
+                        normal : normal,
+                        dist : dist,
+                        vecs : vecs,
+                        texturemins : if surf.flags & SURF_DRAWTILED != 0 { [0, 0] } else { surf.texturemins },
+                    };
+                    r_draw::draw_polygon(&camera, &poly, &texels, palette, buffer, &mut self.zbuffer);
+                }
+            }
+        }
+    }
+}
+
+/// Returns a vector of a model in world space, the axes are the forward,
+/// right and up vectors of the entity angles.
+fn rotate(v : &Vec3, axes : &(Vec3, Vec3, Vec3)) -> Vec3 {
+    let (ref forward, ref right, ref up) = *axes;
+    let mut out = [0.0; 3];
+    for i in 0..3 {
+        out[i] = v[0] * forward[i] - v[1] * right[i] + v[2] * up[i];
+    }
+    out
+}
+
+/// Splits a polygon by the planes of the world starting at a node, the
+/// parts in solid leafs are dropped.
+fn clip_to_world(world : &WorldModel, verts : Vec<Vec3>, node : i32, pieces : &mut Vec<Vec<Vec3>>) {
+    if node < 0 {
+        let contents = world.bsp.leafs.get((-1 - node) as usize).map_or(CONTENTS_SOLID, |leaf| leaf.contents);
+        if contents != CONTENTS_SOLID {
+            pieces.push(verts);
+        }
+        return;
+    }
+    let node = match world.nodes.get(node as usize) {
+        Some(node) => node,
+        None => return,
+    };
+    let plane = &world.planes[node.plane];
+    let (n, dist) = (plane.normal, plane.dist);
+    let front = r_draw::clip_polygon(&verts, [n[0], n[1], n[2], -dist]);
+    let back = r_draw::clip_polygon(&verts, [-n[0], -n[1], -n[2], dist]);
+    if front.len() >= 3 {
+        clip_to_world(world, front, node.children[0], pieces);
+    }
+    if back.len() >= 3 {
+        clip_to_world(world, back, node.children[1], pieces);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_common::MemoryBackBuffer;
+    use std::rc::Rc;
+    use rquake_fs::{BspFile, BspLeaf, BspModel, BspNode, BspPlane, BspTexInfo, BspTexture};
+    use r_model::{Surface, TextureAnim};
+    use protocol::EntityState;
+    use trace::CONTENTS_EMPTY;
+
+    fn leaf(contents : i32) -> BspLeaf {
+        BspLeaf {
+            contents : contents,
+            visofs : -1,
+            mins : [-128; 3],
+            maxs : [128; 3],
+            first_marksurface : 0,
+            num_marksurfaces : 0,
+            ambient_level : [0; 4],
+        }
+    }
+
+    fn texture(name : &str, texel : u8) -> BspTexture {
+        BspTexture { name : name.to_string(), width : 16, height : 16, pixels : vec![texel; 256] }
+    }
+
+    fn submodel(headnode : i32, first_face : i32) -> BspModel {
+        BspModel {
+            mins : [-128.0; 3],
+            maxs : [128.0; 3],
+            origin : [0.0; 3],
+            headnode : [headnode, 0, 0, 0],
+            visleafs : 1,
+            first_face : first_face,
+            num_faces : 1,
+        }
+    }
+
+    /// Map with a single wall at x = 100 facing the origin, lit by style 0,
+    /// with solid space behind it. Leaf 2 behind x = -200 can't see the
+    /// wall. Brush model 1 is a 16 unit square door facing -x with an
+    /// animated texture.
+    fn world_with(contents : i32) -> WorldModel {
+        let plane = |dist| BspPlane { normal : [-1.0, 0.0, 0.0], dist : dist, kind : 0 };
+        let node = |plane, children| BspNode { plane : plane, children : children, mins : [0; 3], maxs : [0; 3],
+                                               first_face : 0, num_faces : 0 };
+        let bsp = BspFile {
+            planes : vec![plane(-100.0), plane(0.0), plane(200.0)],
+            nodes : vec![BspNode { num_faces : 1, ..node(0, [1, -1]) }, node(2, [-3, -2])],
+            leafs : vec![leaf(CONTENTS_SOLID),
+                         BspLeaf { visofs : 0, num_marksurfaces : 1, ..leaf(contents) },
+                         BspLeaf { visofs : 1, ..leaf(CONTENTS_EMPTY) }],
+            marksurfaces : vec![0],
+            visibility : vec![0x01, 0x02],
+            ..BspFile::default()
+        };
+        let mut world = WorldModel::from_bsp("maps/test.bsp", Rc::new(bsp));
+        world.textures = vec![texture("wall", 0), texture("+0door", 5), texture("+1door", 9)];
+        world.anims = vec![None, Some(TextureAnim { frames : vec![1, 2], alternate : vec![] }),
+                           Some(TextureAnim { frames : vec![1, 2], alternate : vec![] })];
+        world.texinfo = vec![BspTexInfo { vecs : [[0.0, -1.0, 0.0, 32.0], [0.0, 0.0, -1.0, 16.0]], miptex : 0, flags : 0 },
+                             BspTexInfo { vecs : [[0.0, -1.0, 0.0, 8.0], [0.0, 0.0, -1.0, 8.0]], miptex : 1, flags : 0 }];
+        world.lightdata = vec![128; 15];
+        world.surfaces = vec![Surface {
+            plane : 0,
+            flags : 0,
+            verts : vec![[100.0, 32.0, 16.0], [100.0, -32.0, 16.0], [100.0, -32.0, -16.0], [100.0, 32.0, -16.0]],
+            texinfo : 0,
+            texturemins : [0, 0],
+            extents : [64, 32],
+            styles : [0, 255, 255, 255],
+            lightofs : Some(0),
+        }, Surface {
+            plane : 1,
+            flags : 0,
+            verts : vec![[0.0, 8.0, 8.0], [0.0, -8.0, 8.0], [0.0, -8.0, -8.0], [0.0, 8.0, -8.0]],
+            texinfo : 1,
+            texturemins : [0, 0],
+            extents : [16, 16],
+            styles : [0, 255, 255, 255],
+            lightofs : Some(0),
+        }];
+        world.submodels = vec![submodel(0, 0), submodel(-1, 1)];
+        world
+    }
+
+    fn world() -> WorldModel {
+        world_with(CONTENTS_EMPTY)
+    }
+
+    fn render(renderer : &mut Renderer, cl : &ClientState, time : f32, params : &RenderParams) -> u32 {
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::read(&mut &data[..]).unwrap();
+        let mut backbuffer = MemoryBackBuffer::new(320, 200);
+        renderer.render_view(&RefDef::new(), cl, time, params, &palette, &mut backbuffer);
+        backbuffer.get_buffer()[100 * 320 + 160]
+    }
+
+    #[test]
+    fn lit_world() {
+        let mut renderer = Renderer::new();
+        renderer.set_colormap((0..64 * 256).map(|i| (i / 256) as u8).collect());
+        renderer.new_map(Some(world()));
+        let mut cl = ClientState::new();
+        cl.lightstyles[0] = "ma".to_string();
+        let mut params = RenderParams {
+            fov : 90.0,
+            surface : SurfaceParams { fullbright : false, lightmap : false },
+            waterwarp : true,
+        };
+
+        // the light style is animated at 10 Hz
+        assert_eq!(render(&mut renderer, &cl, 0.0, &params), 0x1e1e1e);
+        assert_eq!(render(&mut renderer, &cl, 0.1, &params), 0x3f3f3f);
+        assert_eq!(renderer.zbuffer()[100 * 320 + 160], 327);
+
+        // a dynamic light brightens the wall until it dies
+        {
+            let dl = cl.dlights.alloc(1, 0.0);
+            dl.origin = [90.0, 0.0, 0.0];
+            dl.radius = 200.0;
+            dl.die = 0.5;
+        }
+        assert!(render(&mut renderer, &cl, 0.2, &params) < 0x1e1e1e);
+        assert_eq!(render(&mut renderer, &cl, 0.6, &params), 0x1e1e1e);
+
+        params.surface.fullbright = true;
+        assert_eq!(render(&mut renderer, &cl, 0.7, &params), 0);
+    }
+
+    #[test]
+    fn potentially_visible_surfaces() {
+        let mut renderer = Renderer::new();
+        renderer.new_map(Some(world()));
+        assert_eq!(renderer.world().unwrap().visible_surfaces(&[0.0; 3]), vec![true, false]);
+        assert_eq!(renderer.world().unwrap().visible_surfaces(&[-300.0, 0.0, 0.0]), vec![false, false]);
+
+        // the wall isn't drawn from the leaf that can't see it
+        let cl = ClientState::new();
+        let params = RenderParams {
+            fov : 90.0,
+            surface : SurfaceParams { fullbright : true, lightmap : false },
+            waterwarp : true,
+        };
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::read(&mut &data[..]).unwrap();
+        let mut backbuffer = MemoryBackBuffer::new(320, 200);
+        let mut refdef = RefDef::new();
+        renderer.render_view(&refdef, &cl, 0.0, &params, &palette, &mut backbuffer);
+        assert!(renderer.zbuffer()[100 * 320 + 160] != 0);
+        refdef.vieworg = [-300.0, 0.0, 0.0];
+        renderer.render_view(&refdef, &cl, 0.0, &params, &palette, &mut backbuffer);
+        assert_eq!(renderer.zbuffer()[100 * 320 + 160], 0);
+    }
+
+    #[test]
+    fn liquids() {
+        let mut water = world_with(CONTENTS_WATER);
+        water.textures[0].name = "*water1".to_string();
+        water.textures[0].pixels = (0..256).map(|i| i as u8).collect();
+        water.surfaces[0].flags = SURF_DRAWTURB | SURF_DRAWTILED;
+        let mut renderer = Renderer::new();
+        renderer.new_map(Some(water));
+        let cl = ClientState::new();
+        let mut params = RenderParams {
+            fov : 90.0,
+            surface : SurfaceParams { fullbright : false, lightmap : false },
+            waterwarp : true,
+        };
+
+        // the water is drawn without lighting and moves over time
+        let still = render(&mut renderer, &cl, 0.0, &params);
+        assert!(still != 0);
+        assert!(render(&mut renderer, &cl, 0.3, &params) != still);
+        assert!(renderer.warp);
+
+        params.waterwarp = false;
+        render(&mut renderer, &cl, 0.0, &params);
+        assert!(!renderer.warp);
+
+        params.waterwarp = true;
+        renderer.new_map(Some(world()));
+        render(&mut renderer, &cl, 0.0, &params);
+        assert!(!renderer.warp);
+    }
+
+    #[test]
+    fn brush_entities() {
+        let mut renderer = Renderer::new();
+        renderer.new_map(Some(world()));
+        let mut cl = ClientState::new();
+        cl.model_precache = vec![String::new(), "maps/test.bsp".to_string(), "*1".to_string()];
+        let params = RenderParams {
+            fov : 90.0,
+            surface : SurfaceParams { fullbright : true, lightmap : false },
+            waterwarp : true,
+        };
+        assert_eq!(render(&mut renderer, &cl, 0.0, &params), 0);
+
+        // the door in front of the wall with its texture animated at 5 Hz
+        cl.visedicts.push(EntityState { origin : [50.0, 0.0, 0.0], modelindex : 2, ..EntityState::new() });
+        assert_eq!(render(&mut renderer, &cl, 0.0, &params), 0x050505);
+        assert_eq!(renderer.zbuffer()[100 * 320 + 160], 655);
+        assert_eq!(render(&mut renderer, &cl, 0.2, &params), 0x090909);
+        assert_eq!(render(&mut renderer, &cl, 0.4, &params), 0x050505);
+
+        // turned around it faces away from the view
+        cl.visedicts[0].angles = [0.0, 180.0, 0.0];
+        assert_eq!(render(&mut renderer, &cl, 0.0, &params), 0);
+        cl.visedicts[0].angles = [0.0, 0.0, 0.0];
+
+        // moved into the wall only the part in front of it is left
+        let world = renderer.world().unwrap();
+        let mut pieces = Vec::new();
+        let verts = vec![[90.0, 0.0, -8.0], [110.0, 0.0, -8.0], [110.0, 0.0, 8.0], [90.0, 0.0, 8.0]];
+        clip_to_world(world, verts, 0, &mut pieces);
+        assert_eq!(pieces.len(), 1);
+        assert!(pieces[0].iter().all(|v| v[0] <= 100.0));
+        assert!(pieces[0].iter().any(|v| v[0] == 100.0));
+        clip_to_world(world, vec![[120.0, 0.0, -8.0], [130.0, 0.0, -8.0], [130.0, 0.0, 8.0]], 0, &mut pieces);
+        assert_eq!(pieces.len(), 1);
+    }
+}
@@ -0,0 +1,449 @@
+#![warn(missing_docs)]
+
+//! Brush models prepared for drawing, with the surfaces, their lightmap
+//! extents and the animated textures.
+//!
+//! Original source can be found in model.c
+
+use std::rc::Rc;
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::dot_product;
+use rquake_fs::{leaf_visible, BspFile, BspModel, BspTexInfo, BspTexture, MAXLIGHTMAPS, TEX_SPECIAL};
+use trace::{Plane, CONTENTS_SOLID};
+
+// surface flags
+pub const SURF_PLANEBACK : i32 = 2;
+pub const SURF_DRAWSKY : i32 = 4;
+pub const SURF_DRAWTURB : i32 = 0x10;
+pub const SURF_DRAWTILED : i32 = 0x20;
+
+/// Maximum size of a lightmapped surface in texels.
+const MAX_SURFACE_EXTENTS : i32 = 512;
+
+/// Frames per second of animated textures.
+const ANIM_FPS : f32 = 5.0;
+
+/// Maximum number of frames of a texture animation.
+const MAX_ANIM_FRAMES : usize = 10;
+
+/// Alias model header values.
+const IDPOLYHEADER : &'static [u8] = b"IDPO";
+const ALIAS_VERSION : i32 = 6;
+const ALIAS_FLAGS_OFFSET : usize = 72;
+
+/// Textured polygon of a brush model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Surface {
+    /// Index of the plane.
+    pub plane : usize,
+    /// Combination of the SURF_ flags.
+    pub flags : i32,
+    /// Corners of the polygon.
+    pub verts : Vec<Vec3>,
+    /// Index of the texture info.
+    pub texinfo : usize,
+    /// Smallest texture coordinates, a multiple of 16.
+    pub texturemins : [i32; 2],
+    /// Size in texels, a multiple of 16.
+    pub extents : [i32; 2],
+    /// Light styles of the lightmaps, 255 for unused ones.
+    pub styles : [u8; MAXLIGHTMAPS],
+    /// Offset of the lightmaps in the light data, None for surfaces
+    /// without lightmaps.
+    pub lightofs : Option<usize>,
+}
+
+impl Surface {
+    /// Returns the number of lightmap samples per row and column, there
+    /// is one sample every 16 texels.
+    pub fn lightmap_size(&self) -> (usize, usize) {
+        ((self.extents[0] >> 4) as usize + 1, (self.extents[1] >> 4) as usize + 1)
+    }
+}
+
+/// Frames of an animated texture. Textures named +0 to +9 form the main
+/// sequence and +a to +j the alternate one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextureAnim {
+    /// Textures of the sequence the texture belongs to.
+    pub frames : Vec<usize>,
+    /// Textures of the other sequence, used by entities with a nonzero
+    /// frame.
+    pub alternate : Vec<usize>,
+}
+
+/// Node of the drawing tree with the surfaces on its plane.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderNode {
+    /// Index of the splitting plane.
+    pub plane : usize,
+    /// Front and back child, negative numbers are leafs -1 - leaf.
+    pub children : [i32; 2],
+    /// First surface on the plane.
+    pub first_surface : usize,
+    /// Number of surfaces on the plane.
+    pub num_surfaces : usize,
+}
+
+/// The world and the inline brush models of a map.
+pub struct WorldModel {
+    /// Name of the map file.
+    pub name : String,
+    /// Splitting planes.
+    pub planes : Vec<Plane>,
+    /// Textures, missing textures are replaced by a checkerboard.
+    pub textures : Vec<BspTexture>,
+    /// Animation of each texture, None for still textures.
+    pub anims : Vec<Option<TextureAnim>>,
+    /// Texture mappings.
+    pub texinfo : Vec<BspTexInfo>,
+    /// Polygons of all models.
+    pub surfaces : Vec<Surface>,
+    /// Drawing tree.
+    pub nodes : Vec<RenderNode>,
+    /// The map file, used for the leafs and their visibility.
+    pub bsp : Rc<BspFile>,
+    /// Lightmaps with one byte per sample.
+    pub lightdata : Vec<u8>,
+    /// The world and the brush entities.
+    pub submodels : Vec<BspModel>,
+}
+
+impl WorldModel {
+    /// Builds the surfaces of a map.
+    pub fn from_bsp(name : &str, bsp : Rc<BspFile>) -> WorldModel {
+        let planes : Vec<Plane> = bsp.planes.iter().map(|plane| Plane {
+            normal : plane.normal,
+            dist : plane.dist,
+            kind : plane.kind,
+        }).collect();
+        let mut textures : Vec<BspTexture> = bsp.textures.iter().map(|texture| match *texture {
+            Some(ref texture) => texture.clone(),
+            None => notexture(),
+        }).collect();
+
+        // faces with a bad texture info or texture use the checkerboard
+        let mut texinfo = bsp.texinfo.clone();
+        let valid_texinfo = |index : i16| index >= 0 && (index as usize) < bsp.texinfo.len();
+        if bsp.faces.iter().any(|face| !valid_texinfo(face.texinfo)) {
+            texinfo.push(BspTexInfo { vecs : [[0.0; 4]; 2], miptex : -1, flags : TEX_SPECIAL });
+        }
+        for info in &mut texinfo {
+            if info.miptex < 0 || info.miptex as usize >= textures.len() {
+                info.miptex = textures.len() as i32;
+            }
+        }
+        if texinfo.iter().any(|info| info.miptex as usize == textures.len()) {
+            textures.push(notexture());
+        }
+
+        let surfaces = bsp.faces.iter().map(|face| {
+            let index = if valid_texinfo(face.texinfo) { face.texinfo as usize } else { texinfo.len() - 1 };
+            let info = &texinfo[index];
+            let mut flags = if face.side != 0 { SURF_PLANEBACK } else { 0 };
+            let name = textures.get(info.miptex as usize).map_or("", |texture| &texture.name[..]);
+            if name.starts_with("sky") {
+                flags |= SURF_DRAWSKY | SURF_DRAWTILED;
+            } else if name.starts_with('*') {
+                flags |= SURF_DRAWTURB | SURF_DRAWTILED;
+            }
+
+            let verts = face_verts(&bsp, face.first_edge, face.num_edges);
+            let (texturemins, extents) = surface_extents(&verts, info);
+            let mut lightofs = if face.lightofs >= 0 { Some(face.lightofs as usize) } else { None };
+            if info.flags & TEX_SPECIAL == 0 && extents.iter().any(|&e| e > MAX_SURFACE_EXTENTS) {
+                println!("Bad surface extents");
+                lightofs = None;
+            }
+            Surface {
+                plane : face.plane as usize,
+                flags : flags,
+                verts : verts,
+                texinfo : index,
+                texturemins : texturemins,
+                extents : extents,
+                styles : face.styles,
+                lightofs : lightofs,
+            }
+        }).collect();
+
+        WorldModel {
+            name : name.to_string(),
+            planes : planes,
+            anims : texture_anims(&textures),
+            textures : textures,
+            texinfo : texinfo,
+            surfaces : surfaces,
+            nodes : bsp.nodes.iter().map(|node| RenderNode {
+                plane : node.plane as usize,
+                children : [node.children[0] as i32, node.children[1] as i32],
+                first_surface : node.first_face as usize,
+                num_surfaces : node.num_faces as usize,
+            }).collect(),
+            lightdata : bsp.lighting.clone(),
+            submodels : bsp.models.clone(),
+            bsp : bsp,
+        }
+    }
+
+    /// Returns the texture to draw in place of a texture at the client
+    /// time. Animated textures change 5 times a second, entities with a
+    /// nonzero frame switch to the alternate sequence.
+    pub fn texture_animation(&self, texture : usize, frame : i32, time : f32) -> usize {
+        let anim = match self.anims.get(texture) {
+            Some(&Some(ref anim)) => anim,
+            _ => return texture,
+        };
+        let frames = if frame != 0 && !anim.alternate.is_empty() { &anim.alternate } else { &anim.frames };
+        if frames.is_empty() {
+            return texture;
+        }
+        frames[(time.max(0.0) * ANIM_FPS) as usize % frames.len()]
+    }
+
+    /// Returns the contents of the leaf containing a point.
+    pub fn point_contents(&self, p : &Vec3) -> i32 {
+        self.bsp.leafs.get(self.bsp.point_in_leaf(p)).map_or(CONTENTS_SOLID, |leaf| leaf.contents)
+    }
+
+    /// Returns a flag for every surface that is in a leaf potentially
+    /// visible from a point.
+    pub fn visible_surfaces(&self, org : &Vec3) -> Vec<bool> {
+        let pvs = self.bsp.leaf_pvs(self.bsp.point_in_leaf(org));
+        let mut visible = vec![false; self.surfaces.len()];
+        for (index, leaf) in self.bsp.leafs.iter().enumerate() {
+            if !leaf_visible(&pvs, index) {
+                continue;
+            }
+            let first = leaf.first_marksurface as usize;
+            let marks = self.bsp.marksurfaces.iter().skip(first).take(leaf.num_marksurfaces as usize);
+            for &surface in marks {
+                if let Some(flag) = visible.get_mut(surface as usize) {
+                    *flag = true;
+                }
+            }
+        }
+        visible
+    }
+}
+
+/// Returns the flags of an alias model file, like the trails of rockets
+/// and gibs. Returns None if the data isn't an alias model.
+pub fn alias_model_flags(data : &[u8]) -> Option<i32> {
+    if data.len() < ALIAS_FLAGS_OFFSET + 4 || &data[0..4] != IDPOLYHEADER {
+        return None;
+    }
+    let read_i32 = |ofs : usize| {
+        (data[ofs] as i32) | (data[ofs + 1] as i32) << 8 | (data[ofs + 2] as i32) << 16 | (data[ofs + 3] as i32) << 24
+    };
+    if read_i32(4) != ALIAS_VERSION {
+        return None;
+    }
+    Some(read_i32(ALIAS_FLAGS_OFFSET))
+}
+
+/// Returns the sequence and the frame of an animated texture name.
+fn anim_frame(name : &str) -> Option<(usize, usize)> {
+    let name = name.as_bytes();
+    if name.len() < 2 || name[0] != b'+' {
+        return None;
+    }
+    let num = name[1].to_ascii_lowercase();
+    if num.is_ascii_digit() {
+        Some((0, (num - b'0') as usize))
+    } else if num >= b'a' && num < b'a' + MAX_ANIM_FRAMES as u8 {
+        Some((1, (num - b'a') as usize))
+    } else {
+        None
+    }
+}
+
+/// Links the frames of the animated textures. Sequences with a missing
+/// frame are not animated.
+fn texture_anims(textures : &[BspTexture]) -> Vec<Option<TextureAnim>> {
+    let mut anims = vec![None; textures.len()];
+    let mut done = vec![false; textures.len()];
+    for (i, texture) in textures.iter().enumerate() {
+        if done[i] {
+            continue;
+        }
+        if anim_frame(&texture.name).is_none() {
+            if texture.name.starts_with('+') {
+                println!("Bad animating texture {}", texture.name);
+            }
+            continue;
+        }
+
+        // find all the frames of both sequences
+        let mut sequences = [[None; MAX_ANIM_FRAMES]; 2];
+        for (j, other) in textures.iter().enumerate() {
+            if let Some((seq, frame)) = anim_frame(&other.name) {
+                if other.name[2..] == texture.name[2..] {
+                    sequences[seq][frame] = Some(j);
+                    done[j] = true;
+                }
+            }
+        }
+        let mut frames = [Vec::new(), Vec::new()];
+        let mut missing = false;
+        for (frames, sequence) in frames.iter_mut().zip(sequences.iter()) {
+            let count = sequence.iter().rposition(Option::is_some).map_or(0, |n| n + 1);
+            *frames = sequence[..count].iter().filter_map(|&j| j).collect();
+            missing |= frames.len() != count;
+        }
+        if missing {
+            println!("Missing frame of {}", texture.name);
+            continue;
+        }
+
+        for (seq, frames_of_seq) in frames.iter().enumerate() {
+            for &j in frames_of_seq {
+                anims[j] = Some(TextureAnim {
+                    frames : frames_of_seq.clone(),
+                    alternate : frames[seq ^ 1].clone(),
+                });
+            }
+        }
+    }
+    anims
+}
+
+/// Returns the corners of a face by following its edges.
+fn face_verts(bsp : &BspFile, first_edge : i32, num_edges : i16) -> Vec<Vec3> {
+    let mut verts = Vec::with_capacity(num_edges.max(0) as usize);
+    for i in 0..num_edges.max(0) as i32 {
+        let e = match bsp.surfedges.get((first_edge + i) as usize) {
+            Some(&e) => e,
+            None => return Vec::new(),
+        };
+        let vertex = if e >= 0 {
+            bsp.edges.get(e as usize).map(|edge| edge[0])
+        } else {
+            bsp.edges.get((-e) as usize).map(|edge| edge[1])
+        };
+        match vertex.and_then(|v| bsp.vertexes.get(v as usize)) {
+            Some(&v) => verts.push(v),
+            None => return Vec::new(),
+        }
+    }
+    verts
+}
+
+/// Returns the smallest texture coordinates and the size of a face,
+/// rounded to the 16 texels covered by a lightmap sample.
+fn surface_extents(verts : &[Vec3], info : &BspTexInfo) -> ([i32; 2], [i32; 2]) {
+    let mut mins = [999999.0f32; 2];
+    let mut maxs = [-99999.0f32; 2];
+    for v in verts {
+        for j in 0..2 {
+            let vec = [info.vecs[j][0], info.vecs[j][1], info.vecs[j][2]];
+            let val = dot_product(v, &vec) + info.vecs[j][3];
+            mins[j] = mins[j].min(val);
+            maxs[j] = maxs[j].max(val);
+        }
+    }
+    if verts.is_empty() {
+        return ([0; 2], [0; 2]);
+    }
+
+    let mut texturemins = [0; 2];
+    let mut extents = [0; 2];
+    for i in 0..2 {
+        let bmin = (mins[i] / 16.0).floor() as i32;
+        let bmax = (maxs[i] / 16.0).ceil() as i32;
+        texturemins[i] = bmin * 16;
+        extents[i] = (bmax - bmin) * 16;
+    }
+    (texturemins, extents)
+}
+
+/// Checkerboard used for textures that are missing in the map.
+fn notexture() -> BspTexture {
+    BspTexture {
+        name : "notexture".to_string(),
+        width : 16,
+        height : 16,
+        pixels : (0..256).map(|i| if (i / 16 < 8) ^ (i % 16 < 8) { 0 } else { 0xff }).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_fs::BspFace;
+
+    #[test]
+    fn surfaces_from_bsp() {
+        let bsp = BspFile {
+            textures : vec![Some(BspTexture { name : "*water".to_string(), width : 16, height : 16, pixels : vec![1; 256] }),
+                            None],
+            vertexes : vec![[0.0, 0.0, 0.0], [40.0, 0.0, 0.0], [40.0, 20.0, 0.0], [0.0, 20.0, 0.0]],
+            texinfo : vec![BspTexInfo { vecs : [[1.0, 0.0, 0.0, 8.0], [0.0, 1.0, 0.0, 0.0]], miptex : 1, flags : 0 },
+                           BspTexInfo { vecs : [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]], miptex : 0, flags : TEX_SPECIAL }],
+            edges : vec![[0, 0], [0, 1], [1, 2], [2, 3], [3, 0]],
+            surfedges : vec![1, 2, 3, 4, -4, -3, -2, -1],
+            faces : vec![BspFace { plane : 0, side : 0, first_edge : 0, num_edges : 4, texinfo : 0,
+                                   styles : [0, 255, 255, 255], lightofs : 12 },
+                         BspFace { plane : 0, side : 1, first_edge : 4, num_edges : 4, texinfo : 1,
+                                   styles : [255; 4], lightofs : -1 }],
+            ..BspFile::default()
+        };
+
+        let model = WorldModel::from_bsp("maps/test.bsp", Rc::new(bsp));
+        let surf = &model.surfaces[0];
+        assert_eq!(surf.verts, vec![[0.0, 0.0, 0.0], [40.0, 0.0, 0.0], [40.0, 20.0, 0.0], [0.0, 20.0, 0.0]]);
+        assert_eq!(surf.texturemins, [0, 0]);
+        assert_eq!(surf.extents, [48, 32]);
+        assert_eq!(surf.lightmap_size(), (4, 3));
+        assert_eq!(surf.lightofs, Some(12));
+        assert_eq!(surf.flags, 0);
+        assert_eq!(model.textures[1].name, "notexture");
+
+        let water = &model.surfaces[1];
+        assert_eq!(water.verts[1], [0.0, 20.0, 0.0]);
+        assert_eq!(water.flags, SURF_PLANEBACK | SURF_DRAWTURB | SURF_DRAWTILED);
+        assert_eq!(water.lightofs, None);
+    }
+
+    #[test]
+    fn alias_flags() {
+        let mut data = vec![0; 84];
+        data[0..4].copy_from_slice(b"IDPO");
+        data[4] = 6;
+        data[72] = 1;
+        assert_eq!(alias_model_flags(&data), Some(1));
+        data[4] = 5;
+        assert_eq!(alias_model_flags(&data), None);
+        assert_eq!(alias_model_flags(b"IBSP"), None);
+    }
+
+    #[test]
+    fn texture_animation() {
+        let texture = |name : &str| Some(BspTexture { name : name.to_string(), width : 16, height : 16, pixels : vec![0; 256] });
+        let bsp = BspFile {
+            textures : vec![texture("+0slip"), texture("wall"), texture("+1slip"), texture("+Aslip"), texture("+2slip"),
+                            texture("+0lava"), texture("+2lava")],
+            ..BspFile::default()
+        };
+        let model = WorldModel::from_bsp("maps/test.bsp", Rc::new(bsp));
+        assert_eq!(model.anims[1], None);
+        assert_eq!(model.anims[2], Some(TextureAnim { frames : vec![0, 2, 4], alternate : vec![3] }));
+
+        // 5 frames per second starting at the first frame of the sequence
+        assert_eq!(model.texture_animation(2, 0, 0.0), 0);
+        assert_eq!(model.texture_animation(2, 0, 0.25), 2);
+        assert_eq!(model.texture_animation(0, 0, 0.5), 4);
+        assert_eq!(model.texture_animation(4, 0, 0.6), 0);
+        assert_eq!(model.texture_animation(1, 0, 0.6), 1);
+
+        // a nonzero entity frame switches between the sequences
+        assert_eq!(model.texture_animation(0, 1, 0.0), 3);
+        assert_eq!(model.texture_animation(3, 1, 0.25), 2);
+        assert_eq!(model.texture_animation(3, 0, 0.25), 3);
+
+        // a missing frame leaves the textures still
+        assert_eq!(model.anims[5], None);
+        assert_eq!(model.texture_animation(6, 0, 0.5), 6);
+    }
+}
@@ -0,0 +1,685 @@
+#![warn(missing_docs)]
+
+//! Particle effects like explosions, trails and splashes, simulated on
+//! the CPU and drawn as small squares.
+//!
+//! Original source can be found in r_part.c and d_part.c
+
+use std::f32::consts::PI;
+
+use rquake_common::{BackBuffer, Vec3};
+use rquake_common::mathlib::{angle_vectors, dot_product, vector_length, vector_normalize, vector_subtract};
+use rquake_fs::Palette;
+use protocol::{self, TempEntity};
+use view::RefDef;
+
+/// Maximum number of active particles.
+pub const MAX_PARTICLES : usize = 2048;
+
+/// Particles closer to the eye than this aren't drawn.
+const PARTICLE_Z_CLIP : f32 = 8.0;
+
+/// Number of particles of the field around an entity, one for every
+/// vertex normal of the alias models.
+const NUM_VERTEX_NORMALS : usize = 162;
+/// Radius of the particle field.
+const FIELD_DIST : f32 = 64.0;
+/// Length of the swings of the field particles.
+const BEAM_LENGTH : f32 = 16.0;
+
+// model flags that leave trails
+pub const EF_ROCKET : i32 = 1;
+pub const EF_GRENADE : i32 = 2;
+pub const EF_GIB : i32 = 4;
+pub const EF_TRACER : i32 = 16;
+pub const EF_ZOMGIB : i32 = 32;
+pub const EF_TRACER2 : i32 = 64;
+pub const EF_TRACER3 : i32 = 128;
+
+// trail types, adding 128 makes the trail denser
+pub const TRAIL_ROCKET : i32 = 0;
+pub const TRAIL_SMOKE : i32 = 1;
+pub const TRAIL_BLOOD : i32 = 2;
+pub const TRAIL_TRACER : i32 = 3;
+pub const TRAIL_SLIGHT_BLOOD : i32 = 4;
+pub const TRAIL_TRACER2 : i32 = 5;
+pub const TRAIL_VOOR : i32 = 6;
+
+const RAMP1 : [i32; 8] = [0x6f, 0x6d, 0x6b, 0x69, 0x67, 0x65, 0x63, 0x61];
+const RAMP2 : [i32; 8] = [0x6f, 0x6e, 0x6d, 0x6c, 0x6b, 0x6a, 0x68, 0x66];
+const RAMP3 : [i32; 6] = [0x6d, 0x6b, 6, 5, 4, 3];
+
+/// How a particle moves and changes its color.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParticleType {
+    /// Doesn't move on its own.
+    Static,
+    /// Falls down.
+    Grav,
+    /// Falls down slowly.
+    SlowGrav,
+    /// Rises and fades through the fire colors.
+    Fire,
+    /// Accelerates and fades through the explosion colors.
+    Explode,
+    /// Slows down and fades through the explosion colors.
+    Explode2,
+    /// Accelerates.
+    Blob,
+    /// Slows down horizontally.
+    Blob2,
+}
+
+/// A single particle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle {
+    /// Position.
+    pub org : Vec3,
+    /// Palette index.
+    pub color : i32,
+    /// Velocity in units per second.
+    pub vel : Vec3,
+    /// Position in the color ramp of fire and explosion particles.
+    pub ramp : f32,
+    /// Client time when the particle is removed.
+    pub die : f32,
+    /// Movement type.
+    pub kind : ParticleType,
+}
+
+impl Particle {
+    fn new(org : Vec3, color : i32, die : f32, kind : ParticleType) -> Particle {
+        Particle {
+            org : org,
+            color : color,
+            vel : [0.0; 3],
+            ramp : 0.0,
+            die : die,
+            kind : kind,
+        }
+    }
+}
+
+/// Position and size of a particle on the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Projection {
+    u : i32,
+    v : i32,
+    izi : i32,
+    pix : i32,
+}
+
+/// Camera values used to project all particles of a frame.
+struct ParticleView {
+    origin : Vec3,
+    right : Vec3,
+    up : Vec3,
+    forward : Vec3,
+    xcenter : f32,
+    ycenter : f32,
+    width : i32,
+    height : i32,
+    pix_shift : i32,
+    pix_min : i32,
+    pix_max : i32,
+}
+
+impl ParticleView {
+    fn new(refdef : &RefDef, fov_x : f32, width : u32, height : u32) -> ParticleView {
+        let (forward, right, up) = angle_vectors(&refdef.viewangles);
+        let scale = width as f32 / 2.0 / (fov_x.max(1.0).min(179.0) * PI / 360.0).tan();
+        let size = width as f32 / 320.0;
+        ParticleView {
+            origin : refdef.vieworg,
+            right : [right[0] * scale, right[1] * scale, right[2] * scale],
+            up : [up[0] * scale, up[1] * scale, up[2] * scale],
+            forward : forward,
+            xcenter : width as f32 / 2.0 - 0.5,
+            ycenter : height as f32 / 2.0 - 0.5,
+            width : width as i32,
+            height : height as i32,
+            pix_shift : 8 - (size + 0.5) as i32,
+            pix_min : (size as i32).max(1),
+            pix_max : (width as f32 / (320.0 / 4.0) + 0.5) as i32,
+        }
+    }
+
+    /// Returns the screen position, inverse depth and size of a particle,
+    /// None if it's behind the camera or off screen.
+    fn project(&self, org : &Vec3) -> Option<Projection> {
+        let local = vector_subtract(org, &self.origin);
+        let z = dot_product(&local, &self.forward);
+        if z < PARTICLE_Z_CLIP {
+            return None;
+        }
+        let zi = 1.0 / z;
+        let u = (self.xcenter + zi * dot_product(&local, &self.right) + 0.5) as i32;
+        let v = (self.ycenter - zi * dot_product(&local, &self.up) + 0.5) as i32;
+        if u < 0 || v < 0 || u >= self.width || v >= self.height {
+            return None;
+        }
+        let izi = (zi * 32768.0) as i32;
+        let pix = (izi >> self.pix_shift.max(0)).max(self.pix_min).min(self.pix_max);
+        Some(Projection { u : u, v : v, izi : izi, pix : pix })
+    }
+}
+
+/// Returns a random number from 0 to 32767 like the C library rand.
+pub fn rand(seed : &mut u32) -> i32 {
+    *seed = seed.wrapping_mul(214013).wrapping_add(2531011);
+    ((*seed >> 16) & 0x7fff) as i32
+}
+
+/// All active particles.
+pub struct Particles {
+    particles : Vec<Particle>,
+    seed : u32,
+    tracercount : i32,
+    normals : Vec<Vec3>,
+    avelocities : Vec<Vec3>,
+}
+
+impl Particles {
+    /// Creates an empty particle system.
+    pub fn new() -> Particles {
+        Particles {
+            particles : Vec::new(),
+            seed : 1,
+            tracercount : 0,
+            normals : vertex_normals(),
+            avelocities : Vec::new(),
+        }
+    }
+
+    /// Returns the active particles.
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    /// Removes all particles, used when a new level starts.
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    /// Returns the next random number of the particle effects.
+    fn rand(&mut self) -> i32 {
+        rand(&mut self.seed)
+    }
+
+    /// Returns a random offset in the range -range / 2 to range / 2 - 1
+    /// for every axis.
+    fn rand_offset(&mut self, org : &Vec3, range : i32) -> Vec3 {
+        let mut out = *org;
+        for o in &mut out {
+            *o += (self.rand() % range - range / 2) as f32;
+        }
+        out
+    }
+
+    /// Returns a random velocity of up to 256 units per second along every axis.
+    fn rand_vel(&mut self) -> Vec3 {
+        let mut vel = [0.0; 3];
+        for v in &mut vel {
+            *v = (self.rand() % 512 - 256) as f32;
+        }
+        vel
+    }
+
+    /// Adds a particle, returns false if the maximum is reached.
+    fn add(&mut self, p : Particle) -> bool {
+        if self.particles.len() >= MAX_PARTICLES {
+            return false;
+        }
+        self.particles.push(p);
+        true
+    }
+
+    /// Adds an explosion particle, every other one expands faster.
+    fn add_explode(&mut self, org : &Vec3, die : f32, i : i32) -> bool {
+        let kind = if i & 1 != 0 { ParticleType::Explode } else { ParticleType::Explode2 };
+        let mut p = Particle::new(self.rand_offset(org, 32), RAMP1[0], die, kind);
+        p.ramp = (self.rand() & 3) as f32;
+        p.vel = self.rand_vel();
+        self.add(p)
+    }
+
+    /// Rocket explosion.
+    pub fn explosion(&mut self, org : &Vec3, time : f32) {
+        for i in 0..1024 {
+            if !self.add_explode(org, time + 5.0, i) {
+                return;
+            }
+        }
+    }
+
+    /// Explosion with a range of palette colors.
+    pub fn explosion2(&mut self, org : &Vec3, color_start : i32, color_length : i32, time : f32) {
+        let color_length = color_length.max(1);
+        for i in 0..512 {
+            let mut p = Particle::new(self.rand_offset(org, 32), color_start + i % color_length, time + 0.3, ParticleType::Blob);
+            p.vel = self.rand_vel();
+            if !self.add(p) {
+                return;
+            }
+        }
+    }
+
+    /// Tarbaby explosion.
+    pub fn blob_explosion(&mut self, org : &Vec3, time : f32) {
+        for i in 0..1024 {
+            let die = time + 1.0 + (self.rand() & 8) as f32 * 0.05;
+            let (kind, color) = if i & 1 != 0 {
+                (ParticleType::Blob, 66 + self.rand() % 6)
+            } else {
+                (ParticleType::Blob2, 150 + self.rand() % 6)
+            };
+            let mut p = Particle::new(self.rand_offset(org, 32), color, die, kind);
+            p.vel = self.rand_vel();
+            if !self.add(p) {
+                return;
+            }
+        }
+    }
+
+    /// Impacts and the effects of the particle builtin. A count of 1024 is
+    /// a rocket explosion.
+    pub fn run_particle_effect(&mut self, org : &Vec3, dir : &Vec3, color : i32, count : i32, time : f32) {
+        for i in 0..count {
+            let die = time + 0.1 * (self.rand() % 5) as f32;
+            let added = if count == 1024 {
+                self.add_explode(org, die, i)
+            } else {
+                let color = (color & !7) + (self.rand() & 7);
+                let mut p = Particle::new(self.rand_offset(org, 16), color, die, ParticleType::SlowGrav);
+                p.vel = [dir[0] * 15.0, dir[1] * 15.0, dir[2] * 15.0];
+                self.add(p)
+            };
+            if !added {
+                return;
+            }
+        }
+    }
+
+    /// Lava ball splash.
+    pub fn lava_splash(&mut self, org : &Vec3, time : f32) {
+        for i in -16..16 {
+            for j in -16..16 {
+                let die = time + 2.0 + (self.rand() & 31) as f32 * 0.02;
+                let color = 224 + (self.rand() & 7);
+                let mut dir = [(j * 8 + (self.rand() & 7)) as f32, (i * 8 + (self.rand() & 7)) as f32, 256.0];
+                let pos = [org[0] + dir[0], org[1] + dir[1], org[2] + (self.rand() & 63) as f32];
+                let mut p = Particle::new(pos, color, die, ParticleType::SlowGrav);
+                vector_normalize(&mut dir);
+                let vel = (50 + (self.rand() & 63)) as f32;
+                p.vel = [dir[0] * vel, dir[1] * vel, dir[2] * vel];
+                if !self.add(p) {
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Teleport splash.
+    pub fn teleport_splash(&mut self, org : &Vec3, time : f32) {
+        for i in (-16..16).filter(|i| i % 4 == 0) {
+            for j in (-16..16).filter(|j| j % 4 == 0) {
+                for k in (-24..32).filter(|k| k % 4 == 0) {
+                    let die = time + 0.2 + (self.rand() & 7) as f32 * 0.02;
+                    let color = 7 + (self.rand() & 7);
+                    let mut dir = [(j * 8) as f32, (i * 8) as f32, (k * 8) as f32];
+                    let pos = [org[0] + (i + (self.rand() & 3)) as f32,
+                               org[1] + (j + (self.rand() & 3)) as f32,
+                               org[2] + (k + (self.rand() & 3)) as f32];
+                    let mut p = Particle::new(pos, color, die, ParticleType::SlowGrav);
+                    vector_normalize(&mut dir);
+                    let vel = (50 + (self.rand() & 63)) as f32;
+                    p.vel = [dir[0] * vel, dir[1] * vel, dir[2] * vel];
+                    if !self.add(p) {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Leaves a trail of the given type from start to end.
+    pub fn rocket_trail(&mut self, start : &Vec3, end : &Vec3, trail : i32, time : f32) {
+        let mut vec = vector_subtract(end, start);
+        let mut len = vector_normalize(&mut vec);
+        let (dec, trail) = if trail < 128 { (3.0, trail) } else { (1.0, trail - 128) };
+
+        let mut start = *start;
+        while len > 0.0 {
+            len -= dec;
+            let p = match trail {
+                TRAIL_ROCKET | TRAIL_SMOKE => {
+                    let ramp = if trail == TRAIL_ROCKET { self.rand() & 3 } else { (self.rand() & 3) + 2 };
+                    let mut p = Particle::new(self.rand_offset(&start, 6), RAMP3[ramp as usize], time + 2.0, ParticleType::Fire);
+                    p.ramp = ramp as f32;
+                    p
+                }
+                TRAIL_BLOOD | TRAIL_SLIGHT_BLOOD => {
+                    if trail == TRAIL_SLIGHT_BLOOD {
+                        len -= 3.0;
+                    }
+                    let color = 67 + (self.rand() & 3);
+                    Particle::new(self.rand_offset(&start, 6), color, time + 2.0, ParticleType::Grav)
+                }
+                TRAIL_TRACER | TRAIL_TRACER2 => {
+                    let base = if trail == TRAIL_TRACER { 52 } else { 230 };
+                    let mut p = Particle::new(start, base + ((self.tracercount & 4) << 1), time + 0.5, ParticleType::Static);
+                    self.tracercount += 1;
+                    p.vel = if self.tracercount & 1 != 0 {
+                        [30.0 * vec[1], 30.0 * -vec[0], 0.0]
+                    } else {
+                        [30.0 * -vec[1], 30.0 * vec[0], 0.0]
+                    };
+                    p
+                }
+                TRAIL_VOOR => {
+                    let color = 9 * 16 + 8 + (self.rand() & 3);
+                    Particle::new(self.rand_offset(&start, 16), color, time + 0.3, ParticleType::Static)
+                }
+                _ => return,
+            };
+            if !self.add(p) {
+                return;
+            }
+            for i in 0..3 {
+                start[i] += vec[i];
+            }
+        }
+    }
+
+    /// Leaves the trail of a moving model with the given model flags.
+    pub fn model_trail(&mut self, flags : i32, start : &Vec3, end : &Vec3, time : f32) {
+        let trail = if flags & EF_GIB != 0 {
+            TRAIL_BLOOD
+        } else if flags & EF_ZOMGIB != 0 {
+            TRAIL_SLIGHT_BLOOD
+        } else if flags & EF_TRACER != 0 {
+            TRAIL_TRACER
+        } else if flags & EF_TRACER2 != 0 {
+            TRAIL_TRACER2
+        } else if flags & EF_ROCKET != 0 {
+            TRAIL_ROCKET
+        } else if flags & EF_GRENADE != 0 {
+            TRAIL_SMOKE
+        } else if flags & EF_TRACER3 != 0 {
+            TRAIL_VOOR
+        } else {
+            return;
+        };
+        self.rocket_trail(start, end, trail, time);
+    }
+
+    /// Surrounds an entity with a field of particles that swing around the
+    /// vertex normals for one frame.
+    pub fn entity_particles(&mut self, origin : &Vec3, time : f32) {
+        if self.avelocities.is_empty() {
+            for _ in 0..NUM_VERTEX_NORMALS {
+                let v = [self.rand() & 255, self.rand() & 255, self.rand() & 255];
+                self.avelocities.push([v[0] as f32 * 0.01, v[1] as f32 * 0.01, v[2] as f32 * 0.01]);
+            }
+        }
+        for i in 0..self.normals.len() {
+            let (normal, av) = (self.normals[i], self.avelocities[i]);
+            let (sy, cy) = (time * av[0]).sin_cos();
+            let (sp, cp) = (time * av[1]).sin_cos();
+            let forward = [cp * cy, cp * sy, -sp];
+            let mut org = [0.0; 3];
+            for j in 0..3 {
+                org[j] = origin[j] + normal[j] * FIELD_DIST + forward[j] * BEAM_LENGTH;
+            }
+            if !self.add(Particle::new(org, 0x6f, time + 0.01, ParticleType::Explode)) {
+                return;
+            }
+        }
+    }
+
+    /// Starts the particles of a temporary entity. Beams have no particles.
+    pub fn temp_entity(&mut self, te : &TempEntity, time : f32) {
+        match *te {
+            TempEntity::Point { kind, ref origin } => match kind {
+                protocol::TE_WIZSPIKE => self.run_particle_effect(origin, &[0.0; 3], 20, 30, time),
+                protocol::TE_KNIGHTSPIKE => self.run_particle_effect(origin, &[0.0; 3], 226, 20, time),
+                protocol::TE_SPIKE => self.run_particle_effect(origin, &[0.0; 3], 0, 10, time),
+                protocol::TE_SUPERSPIKE | protocol::TE_GUNSHOT => self.run_particle_effect(origin, &[0.0; 3], 0, 20, time),
+                protocol::TE_EXPLOSION => self.explosion(origin, time),
+                protocol::TE_TAREXPLOSION => self.blob_explosion(origin, time),
+                protocol::TE_LAVASPLASH => self.lava_splash(origin, time),
+                protocol::TE_TELEPORT => self.teleport_splash(origin, time),
+                _ => {}
+            },
+            TempEntity::Explosion2 { ref origin, color_start, color_length } => {
+                self.explosion2(origin, color_start, color_length, time);
+            }
+            TempEntity::Beam { .. } => {}
+        }
+    }
+
+    /// Moves the particles and removes the ones that died.
+    pub fn run(&mut self, time : f32, frametime : f32, gravity : f32) {
+        let time3 = frametime * 15.0;
+        let time2 = frametime * 10.0;
+        let time1 = frametime * 5.0;
+        let grav = frametime * gravity * 0.05;
+        let dvel = 4.0 * frametime;
+
+        self.particles.retain(|p| p.die >= time);
+        for p in &mut self.particles {
+            for i in 0..3 {
+                p.org[i] += p.vel[i] * frametime;
+            }
+
+            match p.kind {
+                ParticleType::Static => {}
+                ParticleType::Fire => {
+                    p.ramp += time1;
+                    if p.ramp >= 6.0 {
+                        p.die = -1.0;
+                    } else {
+                        p.color = RAMP3[p.ramp as usize];
+                    }
+                    p.vel[2] += grav;
+                }
+                ParticleType::Explode => {
+                    p.ramp += time2;
+                    if p.ramp >= 8.0 {
+                        p.die = -1.0;
+                    } else {
+                        p.color = RAMP1[p.ramp as usize];
+                    }
+                    for v in &mut p.vel {
+                        *v += *v * dvel;
+                    }
+                    p.vel[2] -= grav;
+                }
+                ParticleType::Explode2 => {
+                    p.ramp += time3;
+                    if p.ramp >= 8.0 {
+                        p.die = -1.0;
+                    } else {
+                        p.color = RAMP2[p.ramp as usize];
+                    }
+                    for v in &mut p.vel {
+                        *v -= *v * frametime;
+                    }
+                    p.vel[2] -= grav;
+                }
+                ParticleType::Blob => {
+                    for v in &mut p.vel {
+                        *v += *v * dvel;
+                    }
+                    p.vel[2] -= grav;
+                }
+                ParticleType::Blob2 => {
+                    for v in &mut p.vel[..2] {
+                        *v -= *v * dvel;
+                    }
+                    p.vel[2] -= grav;
+                }
+                ParticleType::Grav | ParticleType::SlowGrav => p.vel[2] -= grav,
+            }
+        }
+    }
+
+    /// Draws the particles as squares that get bigger when they are
+    /// closer. The z buffer holds the inverse depth scaled by 0x8000 of
+    /// every pixel, 0 is infinitely far away.
+    pub fn draw(&self, refdef : &RefDef, fov_x : f32, palette : &Palette, backbuffer : &mut BackBuffer, zbuffer : &mut [i32]) {
+        let width = backbuffer.get_width();
+        let height = backbuffer.get_height();
+        let view = ParticleView::new(refdef, fov_x, width, height);
+        let buffer = backbuffer.get_buffer();
+        if buffer.len() < (width * height) as usize || zbuffer.len() < buffer.len() {
+            return;
+        }
+
+        for p in &self.particles {
+            let proj = match view.project(&p.org) {
+                Some(proj) => proj,
+                None => continue,
+            };
+            let color = palette.palette_lookup(p.color as u8);
+            for v in proj.v..(proj.v + proj.pix).min(view.height) {
+                for u in proj.u..(proj.u + proj.pix).min(view.width) {
+                    let ofs = (v * view.width + u) as usize;
+                    if zbuffer[ofs] <= proj.izi {
+                        zbuffer[ofs] = proj.izi;
+                        buffer[ofs] = color;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns the corners of an icosahedron with every edge split into four,
+/// 162 directions spread evenly like the vertex normals of the alias models.
+fn vertex_normals() -> Vec<Vec3> {
+    let (x, z) = (0.525731, 0.850651);
+    let corners = [[-x, 0.0, z], [x, 0.0, z], [-x, 0.0, -z], [x, 0.0, -z], [0.0, z, x], [0.0, z, -x],
+                   [0.0, -z, x], [0.0, -z, -x], [z, x, 0.0], [-z, x, 0.0], [z, -x, 0.0], [-z, -x, 0.0]];
+    let faces = [[0, 4, 1], [0, 9, 4], [9, 5, 4], [4, 5, 8], [4, 8, 1], [8, 10, 1], [8, 3, 10], [5, 3, 8], [5, 2, 3], [2, 7, 3],
+                 [7, 10, 3], [7, 6, 10], [7, 11, 6], [11, 0, 6], [0, 1, 6], [6, 1, 10], [9, 0, 11], [9, 11, 2], [9, 2, 5], [7, 2, 11]];
+    let mut normals : Vec<Vec3> = Vec::with_capacity(NUM_VERTEX_NORMALS);
+    for face in &faces {
+        for i in 0..5 {
+            for j in 0..5 - i {
+                let k = 4 - i - j;
+                let mut n = [0.0; 3];
+                for axis in 0..3 {
+                    n[axis] = corners[face[0]][axis] * k as f32 + corners[face[1]][axis] * i as f32 + corners[face[2]][axis] * j as f32;
+                }
+                vector_normalize(&mut n);
+                if !normals.iter().any(|other| vector_length(&vector_subtract(other, &n)) < 0.001) {
+                    normals.push(n);
+                }
+            }
+        }
+    }
+    normals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_common::MemoryBackBuffer;
+
+    fn palette() -> Palette {
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        Palette::read(&mut &data[..]).unwrap()
+    }
+
+    #[test]
+    fn explosion_fades() {
+        let mut particles = Particles::new();
+        particles.explosion(&[0.0; 3], 1.0);
+        assert_eq!(particles.particles().len(), 1024);
+        assert!(particles.particles().iter().all(|p| p.color == RAMP1[0]));
+
+        particles.run(1.1, 0.1, 800.0);
+        let p = particles.particles()[1];
+        assert_eq!(p.kind, ParticleType::Explode);
+        assert!(RAMP1.contains(&p.color));
+
+        // the explosion colors have run out after a second
+        for i in 0..10 {
+            particles.run(1.2 + i as f32 * 0.1, 0.1, 800.0);
+        }
+        particles.run(2.4, 0.1, 800.0);
+        assert!(particles.particles().is_empty());
+    }
+
+    #[test]
+    fn limits_and_gravity() {
+        let mut particles = Particles::new();
+        particles.explosion(&[0.0; 3], 0.0);
+        particles.explosion(&[0.0; 3], 0.0);
+        particles.explosion(&[0.0; 3], 0.0);
+        assert_eq!(particles.particles().len(), MAX_PARTICLES);
+
+        let mut particles = Particles::new();
+        particles.run_particle_effect(&[0.0; 3], &[0.0, 0.0, 1.0], 73, 10, 0.0);
+        assert_eq!(particles.particles().len(), 10);
+        for p in particles.particles() {
+            assert_eq!(p.kind, ParticleType::SlowGrav);
+            assert_eq!(p.color & !7, 72);
+            assert_eq!(p.vel, [0.0, 0.0, 15.0]);
+        }
+        particles.run(0.0, 0.1, 800.0);
+        assert_eq!(particles.particles()[0].vel[2], 15.0 - 4.0);
+    }
+
+    #[test]
+    fn trails() {
+        let mut particles = Particles::new();
+        particles.model_trail(EF_ROCKET, &[0.0; 3], &[30.0, 0.0, 0.0], 0.0);
+        assert_eq!(particles.particles().len(), 10);
+        assert!(particles.particles().iter().all(|p| p.kind == ParticleType::Fire));
+
+        let mut particles = Particles::new();
+        particles.rocket_trail(&[0.0; 3], &[10.0, 0.0, 0.0], TRAIL_TRACER + 128, 0.0);
+        assert_eq!(particles.particles().len(), 10);
+        assert_eq!(particles.particles()[0].vel, [0.0, -30.0, 0.0]);
+        assert_eq!(particles.particles()[1].vel, [0.0, 30.0, 0.0]);
+        assert_eq!(particles.particles()[4].color, 60);
+
+        let mut particles = Particles::new();
+        particles.model_trail(0, &[0.0; 3], &[30.0, 0.0, 0.0], 0.0);
+        assert!(particles.particles().is_empty());
+    }
+
+    #[test]
+    fn entity_field() {
+        let mut particles = Particles::new();
+        particles.entity_particles(&[100.0, 0.0, 0.0], 1.0);
+        assert_eq!(particles.particles().len(), NUM_VERTEX_NORMALS);
+        for p in particles.particles() {
+            let dist = vector_length(&vector_subtract(&p.org, &[100.0, 0.0, 0.0]));
+            assert!(dist >= FIELD_DIST - BEAM_LENGTH - 0.01 && dist <= FIELD_DIST + BEAM_LENGTH + 0.01);
+            assert_eq!(p.die, 1.01);
+        }
+    }
+
+    #[test]
+    fn draw_depth_tested() {
+        let mut particles = Particles::new();
+        particles.add(Particle::new([100.0, 0.0, 0.0], 4, 1.0, ParticleType::Static));
+        particles.add(Particle::new([200.0, 0.0, 0.0], 8, 1.0, ParticleType::Static));
+        particles.add(Particle::new([-100.0, 0.0, 0.0], 12, 1.0, ParticleType::Static));
+
+        let mut backbuffer = MemoryBackBuffer::new(320, 200);
+        let mut zbuffer = vec![0; 320 * 200];
+        particles.draw(&RefDef::new(), 90.0, &palette(), &mut backbuffer, &mut zbuffer);
+
+        // the closer particle covers the other one in the center
+        let buffer = backbuffer.get_buffer();
+        assert_eq!(buffer[100 * 320 + 160], 0x040404);
+        assert_eq!(zbuffer[100 * 320 + 160], 32768 / 100);
+        assert_eq!(buffer.iter().filter(|&&c| c == 0x040404).count(), 4);
+        assert!(!buffer.contains(&0x080808));
+        assert!(!buffer.contains(&0x0c0c0c));
+    }
+}
@@ -0,0 +1,90 @@
+#![warn(missing_docs)]
+
+//! Two layer scrolling sky drawn on the sky surfaces of a map.
+//!
+//! Original source can be found in r_sky.c and d_sky.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::vector_normalize;
+use rquake_fs::BspTexture;
+
+/// Size of a sky layer in texels.
+const SKYSIZE : usize = 128;
+
+/// Scroll speed of the back layer in texels per second, the front layer
+/// moves twice as fast.
+const SKYSPEED : f32 = 8.0;
+
+/// The layers of the sky texture.
+pub struct Sky {
+    /// Right half of the texture, always visible.
+    back : Vec<u8>,
+    /// Left half of the texture, color 0 is transparent.
+    front : Vec<u8>,
+}
+
+impl Sky {
+    /// Splits a 256x128 sky texture into its layers.
+    pub fn new(texture : &BspTexture) -> Option<Sky> {
+        let (width, height) = (texture.width as usize, texture.height as usize);
+        if width != SKYSIZE * 2 || height != SKYSIZE || texture.pixels.len() < width * height {
+            println!("Bad sky texture {} ({}x{})", texture.name, width, height);
+            return None;
+        }
+        let mut back = Vec::with_capacity(SKYSIZE * SKYSIZE);
+        let mut front = Vec::with_capacity(SKYSIZE * SKYSIZE);
+        for row in texture.pixels.chunks(width).take(SKYSIZE) {
+            front.extend_from_slice(&row[..SKYSIZE]);
+            back.extend_from_slice(&row[SKYSIZE..]);
+        }
+        Some(Sky {
+            back : back,
+            front : front,
+        })
+    }
+
+    /// Returns the sky color in a view direction. The direction is
+    /// flattened so the sky looks like a dome far above.
+    pub fn texel(&self, dir : &Vec3, time : f32) -> u8 {
+        let mut end = [dir[0], dir[1], dir[2] * 3.0];
+        vector_normalize(&mut end);
+        let shift = time * SKYSPEED;
+        let s = shift + 6.0 * (SKYSIZE / 2 - 1) as f32 * end[0];
+        let t = shift + 6.0 * (SKYSIZE / 2 - 1) as f32 * end[1];
+
+        let wrap = |x : f32| (x.floor() as i32 & (SKYSIZE as i32 - 1)) as usize;
+        let front = self.front[wrap(t + shift) * SKYSIZE + wrap(s + shift)];
+        if front != 0 {
+            front
+        } else {
+            self.back[wrap(t) * SKYSIZE + wrap(s)]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn layers() {
+        // the front layer has a single cloud texel at 10, 10
+        let mut pixels = vec![0; 256 * 128];
+        for row in pixels.chunks_mut(256) {
+            for texel in &mut row[128..] {
+                *texel = 7;
+            }
+        }
+        pixels[10 * 256 + 10] = 3;
+        let sky = Sky::new(&BspTexture { name : "sky1".to_string(), width : 256, height : 128, pixels : pixels }).unwrap();
+
+        // the front layer scrolls over the back layer and wraps around
+        let up = [0.0, 0.0, 1.0];
+        assert_eq!(sky.texel(&up, 0.0), 7);
+        assert_eq!(sky.texel(&up, 10.5 / 16.0), 3);
+        assert_eq!(sky.texel(&up, 11.5 / 16.0), 7);
+        assert_eq!(sky.texel(&up, 138.5 / 16.0), 3);
+
+        assert!(Sky::new(&BspTexture { name : "sky2".to_string(), width : 64, height : 64, pixels : vec![0; 4096] }).is_none());
+    }
+}
@@ -0,0 +1,350 @@
+#![warn(missing_docs)]
+
+//! Lighting of surface textures. Lit surfaces are kept in a cache until
+//! a light style they use changes or a dynamic light touches them.
+//!
+//! Original source can be found in r_surf.c and d_surf.c
+
+use std::collections::HashMap;
+
+use rquake_common::mathlib::dot_product;
+use rquake_fs::{BspTexture, MAXLIGHTMAPS};
+use r_light::{DLights, MAX_DLIGHTS};
+use r_model::{Surface, WorldModel};
+
+/// Texel drawn on every surface with r_lightmap.
+const LIGHTMAP_TEXEL : u8 = 15;
+
+/// Number of bits of the light in the colormap rows.
+const VID_CBITS : i32 = 6;
+
+/// Debug settings that change how surfaces are lit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SurfaceParams {
+    /// Every surface is drawn at full brightness.
+    pub fullbright : bool,
+    /// Surfaces are drawn with a plain texture to show the lighting.
+    pub lightmap : bool,
+}
+
+/// Surface with the lighting applied to its texture.
+pub struct CachedSurface {
+    /// Width in texels.
+    pub width : usize,
+    /// Height in texels.
+    pub height : usize,
+    /// Lit palette indices.
+    pub pixels : Vec<u8>,
+    texture : usize,
+    lightadj : [i32; MAXLIGHTMAPS],
+    dlight : bool,
+}
+
+/// Lit surfaces by surface index.
+pub struct SurfaceCache {
+    surfaces : HashMap<usize, CachedSurface>,
+    params : Option<SurfaceParams>,
+    colormap : Vec<u8>,
+}
+
+impl SurfaceCache {
+    /// Creates an empty cache.
+    pub fn new() -> SurfaceCache {
+        SurfaceCache {
+            surfaces : HashMap::new(),
+            params : None,
+            colormap : Vec::new(),
+        }
+    }
+
+    /// Sets the table of 64 light levels for each palette color, surfaces
+    /// are drawn without lighting until it's set.
+    pub fn set_colormap(&mut self, colormap : Vec<u8>) {
+        self.colormap = colormap;
+        self.surfaces.clear();
+    }
+
+    /// Removes all surfaces, used when a new map is loaded.
+    pub fn clear(&mut self) {
+        self.surfaces.clear();
+    }
+
+    /// Returns true if no surface is cached.
+    pub fn is_empty(&self) -> bool {
+        self.surfaces.is_empty()
+    }
+
+    /// Starts a frame, a change of the debug settings flushes the cache.
+    pub fn begin_frame(&mut self, params : &SurfaceParams) {
+        if self.params != Some(*params) {
+            self.surfaces.clear();
+            self.params = Some(*params);
+        }
+    }
+
+    /// Returns the lit texture of a surface drawn with the given texture
+    /// of the model. It's rebuilt if the texture or the values of its light
+    /// styles changed or dynamic lights touched it this frame or the last
+    /// time it was built.
+    pub fn get(&mut self, model : &WorldModel, index : usize, texture : usize, lightstyles : &[i32], dlights : &DLights,
+               dlightbits : u32) -> &CachedSurface {
+        let surf = &model.surfaces[index];
+        let mut lightadj = [0; MAXLIGHTMAPS];
+        for (adj, &style) in lightadj.iter_mut().zip(surf.styles.iter()) {
+            if style != 255 {
+                *adj = lightstyles.get(style as usize).cloned().unwrap_or(0);
+            }
+        }
+
+        let valid = match self.surfaces.get(&index) {
+            Some(cache) => !cache.dlight && dlightbits == 0 && cache.texture == texture && cache.lightadj == lightadj,
+            None => false,
+        };
+        if !valid {
+            let params = self.params.unwrap_or(SurfaceParams { fullbright : false, lightmap : false });
+            let blocklights = build_lightmap(model, surf, &lightadj, dlights, dlightbits, &params);
+            let (width, height) = (surf.extents[0].max(0) as usize, surf.extents[1].max(0) as usize);
+            self.surfaces.insert(index, CachedSurface {
+                width : width,
+                height : height,
+                pixels : draw_surface(&model.textures[texture], surf, &blocklights, &self.colormap, &params),
+                texture : texture,
+                lightadj : lightadj,
+                dlight : dlightbits != 0,
+            });
+        }
+        &self.surfaces[&index]
+    }
+}
+
+/// Returns the light of every lightmap sample of a surface, combined
+/// from the lightmaps of its styles and the dynamic lights. The values
+/// are 0 for the brightest and 0x3fc0 for the darkest colormap row.
+pub fn build_lightmap(model : &WorldModel, surf : &Surface, lightadj : &[i32; MAXLIGHTMAPS], dlights : &DLights,
+                      dlightbits : u32, params : &SurfaceParams) -> Vec<i32> {
+    let (smax, tmax) = surf.lightmap_size();
+    let size = smax * tmax;
+    let mut blocklights = vec![0; size];
+    if params.fullbright || model.lightdata.is_empty() {
+        return blocklights;
+    }
+
+    // add all the lightmaps
+    if let Some(mut ofs) = surf.lightofs {
+        for (&style, &scale) in surf.styles.iter().zip(lightadj.iter()) {
+            if style == 255 || ofs + size > model.lightdata.len() {
+                break;
+            }
+            for (light, &sample) in blocklights.iter_mut().zip(model.lightdata[ofs..ofs + size].iter()) {
+                *light += sample as i32 * scale;
+            }
+            ofs += size;
+        }
+    }
+
+    // add all the dynamic lights
+    if dlightbits != 0 {
+        add_dynamic_lights(model, surf, dlights, dlightbits, &mut blocklights);
+    }
+
+    // bound, invert, and shift
+    for light in &mut blocklights {
+        *light = ((255 * 256 - *light) >> (8 - VID_CBITS)).max(1 << 6);
+    }
+    blocklights
+}
+
+/// Adds the dynamic lights marked for a surface, the light falls off
+/// with the distance from the point closest to the light.
+fn add_dynamic_lights(model : &WorldModel, surf : &Surface, dlights : &DLights, dlightbits : u32, blocklights : &mut [i32]) {
+    let (smax, tmax) = surf.lightmap_size();
+    let plane = &model.planes[surf.plane];
+    let tex = &model.texinfo[surf.texinfo];
+
+    for lnum in 0..MAX_DLIGHTS {
+        if dlightbits & (1 << lnum) == 0 {
+            continue;
+        }
+        let dl = match dlights.get(lnum) {
+            Some(dl) => dl,
+            None => continue,
+        };
+        let dist = dot_product(&dl.origin, &plane.normal) - plane.dist;
+        let rad = dl.radius - dist.abs();
+        if rad < dl.minlight {
+            continue;
+        }
+        let minlight = rad - dl.minlight;
+
+        let mut impact = [0.0; 3];
+        for i in 0..3 {
+            impact[i] = dl.origin[i] - plane.normal[i] * dist;
+        }
+        let mut local = [0.0; 2];
+        for j in 0..2 {
+            let vec = [tex.vecs[j][0], tex.vecs[j][1], tex.vecs[j][2]];
+            local[j] = dot_product(&impact, &vec) + tex.vecs[j][3] - surf.texturemins[j] as f32;
+        }
+
+        for t in 0..tmax {
+            let td = ((local[1] - (t * 16) as f32) as i32).abs();
+            for s in 0..smax {
+                let sd = ((local[0] - (s * 16) as f32) as i32).abs();
+                let dist = if sd > td { sd + (td >> 1) } else { td + (sd >> 1) } as f32;
+                if dist < minlight {
+                    blocklights[t * smax + s] += ((rad - dist) * 256.0) as i32;
+                }
+            }
+        }
+    }
+}
+
+/// Applies the lightmap to the texture of a surface. The light is
+/// interpolated between the samples 16 texels apart and picks the
+/// colormap row.
+fn draw_surface(texture : &BspTexture, surf : &Surface, blocklights : &[i32], colormap : &[u8], params : &SurfaceParams) -> Vec<u8> {
+    let (width, height) = (surf.extents[0].max(0) as usize, surf.extents[1].max(0) as usize);
+    let (smax, _) = surf.lightmap_size();
+    let tw = texture.width as i32;
+    let th = texture.height as i32;
+    let mut pixels = Vec::with_capacity(width * height);
+    if tw == 0 || th == 0 {
+        return vec![0; width * height];
+    }
+
+    for v in 0..height {
+        let tv = (v as i32 + surf.texturemins[1]).rem_euclid(th) as usize;
+        let (row, fv) = (v >> 4, (v & 15) as i32);
+        for u in 0..width {
+            let tu = (u as i32 + surf.texturemins[0]).rem_euclid(tw) as usize;
+            let texel = if params.lightmap { LIGHTMAP_TEXEL } else { texture.pixels[tv * tw as usize + tu] };
+
+            let (col, fu) = (u >> 4, (u & 15) as i32);
+            let sample = |s : usize, t : usize| blocklights[t * smax + s];
+            let left = sample(col, row) + ((sample(col, row + 1) - sample(col, row)) * fv >> 4);
+            let right = sample(col + 1, row) + ((sample(col + 1, row + 1) - sample(col + 1, row)) * fv >> 4);
+            let light = left + ((right - left) * fu >> 4);
+            pixels.push(colormap.get((light & 0xff00) as usize + texel as usize).cloned().unwrap_or(texel));
+        }
+    }
+    pixels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use rquake_fs::{BspFile, BspTexInfo};
+    use r_model::WorldModel;
+    use trace::Plane;
+
+    /// Builds a 32x16 surface on the z = 0 plane with a lightmap of 3x2
+    /// samples using style 0.
+    fn model() -> WorldModel {
+        let mut model = WorldModel::from_bsp("", Rc::new(BspFile::default()));
+        model.planes = vec![Plane { normal : [0.0, 0.0, 1.0], dist : 0.0, kind : 2 }];
+        model.textures = vec![BspTexture { name : "wall".to_string(), width : 16, height : 16, pixels : (0..=255).collect() }];
+        model.texinfo = vec![BspTexInfo { vecs : [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]], miptex : 0, flags : 0 }];
+        model.lightdata = vec![128; 6];
+        model.surfaces = vec![Surface {
+            plane : 0,
+            flags : 0,
+            verts : vec![[0.0, 0.0, 0.0], [32.0, 0.0, 0.0], [32.0, 16.0, 0.0], [0.0, 16.0, 0.0]],
+            texinfo : 0,
+            texturemins : [0, 0],
+            extents : [32, 16],
+            styles : [0, 255, 255, 255],
+            lightofs : Some(0),
+        }];
+        model
+    }
+
+    /// Colormap where the row is stored in every entry.
+    fn colormap() -> Vec<u8> {
+        (0..64 * 256).map(|i| (i / 256) as u8).collect()
+    }
+
+    fn params() -> SurfaceParams {
+        SurfaceParams { fullbright : false, lightmap : false }
+    }
+
+    #[test]
+    fn lightmap_styles() {
+        let model = model();
+        let dlights = DLights::new();
+        let surf = &model.surfaces[0];
+        let light = build_lightmap(&model, surf, &[256, 0, 0, 0], &dlights, 0, &params());
+        assert_eq!(light, vec![(255 * 256 - 128 * 256) >> 2; 6]);
+
+        // a dark style uses the darkest row
+        let light = build_lightmap(&model, surf, &[0, 0, 0, 0], &dlights, 0, &params());
+        assert_eq!(light, vec![0x3fc0; 6]);
+
+        let fullbright = SurfaceParams { fullbright : true, lightmap : false };
+        assert_eq!(build_lightmap(&model, surf, &[0, 0, 0, 0], &dlights, 0, &fullbright), vec![0; 6]);
+    }
+
+    #[test]
+    fn dynamic_lights() {
+        let model = model();
+        let mut dlights = DLights::new();
+        {
+            let dl = dlights.alloc(1, 0.0);
+            dl.origin = [0.0, 0.0, 16.0];
+            dl.radius = 100.0;
+            dl.die = 1.0;
+        }
+        let surf = &model.surfaces[0];
+        let light = build_lightmap(&model, surf, &[256, 0, 0, 0], &dlights, 1, &params());
+        // the closest sample is the brightest
+        assert_eq!(light[0], (255 * 256 - 128 * 256 - 84 * 256) >> 2);
+        assert!(light[2] > light[0] && light[2] < (255 * 256 - 128 * 256) >> 2);
+
+        // not marked for the surface
+        let unlit = build_lightmap(&model, surf, &[256, 0, 0, 0], &dlights, 2, &params());
+        assert_eq!(unlit, vec![(255 * 256 - 128 * 256) >> 2; 6]);
+    }
+
+    #[test]
+    fn cache_invalidation() {
+        let model = model();
+        let mut dlights = DLights::new();
+        let mut cache = SurfaceCache::new();
+        cache.set_colormap(colormap());
+        cache.begin_frame(&params());
+        let mut styles = [256; 64];
+
+        {
+            let surf = cache.get(&model, 0, 0, &styles, &dlights, 0);
+            assert_eq!((surf.width, surf.height), (32, 16));
+            assert_eq!(surf.pixels.len(), 32 * 16);
+            assert_eq!(surf.pixels[0], ((255 * 256 - 128 * 256) >> 2 >> 8) as u8);
+        }
+        let lightadj = cache.surfaces[&0].lightadj;
+        assert_eq!(lightadj, [256, 0, 0, 0]);
+
+        // a changed light style rebuilds the surface
+        styles[0] = 0;
+        assert_eq!(cache.get(&model, 0, 0, &styles, &dlights, 0).pixels[0], 63);
+
+        // a dynamic light rebuilds it for this and the next frame
+        {
+            let dl = dlights.alloc(1, 0.0);
+            dl.origin = [0.0, 0.0, 16.0];
+            dl.radius = 400.0;
+            dl.die = 1.0;
+        }
+        assert_eq!(cache.get(&model, 0, 0, &styles, &dlights, 1).pixels[0], 0);
+        assert!(cache.surfaces[&0].dlight);
+        assert_eq!(cache.get(&model, 0, 0, &styles, &dlights, 0).pixels[0], 63);
+        assert!(!cache.surfaces[&0].dlight);
+
+        // the debug settings flush the cache
+        cache.begin_frame(&SurfaceParams { fullbright : true, lightmap : false });
+        assert!(cache.is_empty());
+        assert_eq!(cache.get(&model, 0, 0, &styles, &dlights, 0).pixels[0], 0);
+        cache.begin_frame(&SurfaceParams { fullbright : true, lightmap : true });
+        let surf = cache.get(&model, 0, 0, &styles, &dlights, 0);
+        assert!(surf.pixels.iter().all(|&p| p == 0));
+    }
+}
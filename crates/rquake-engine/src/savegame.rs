@@ -0,0 +1,625 @@
+#![warn(missing_docs)]
+
+//! Save games in the text format of the original engine: a header with
+//! the comment, spawn parameters, skill, map, time and light styles,
+//! followed by the globals and all entities as { "key" "value" } blocks.
+//! The entity lists of maps use the same blocks.
+//!
+//! Original source can be found in host_cmd.c and pr_edict.c
+
+use std::error;
+use std::fmt;
+use std::io::{self, Write};
+
+use rquake_fs::{parse_entities, EntityDef, ReadError};
+use progdefs::{self, Def, DefType};
+use progs::{Edict, Fields, Progs};
+use server::{self, Server};
+
+/// Version written to the first line.
+pub const SAVEGAME_VERSION : i32 = 5;
+
+/// Length of the comment shown in the load menu.
+pub const SAVEGAME_COMMENT_LENGTH : usize = 39;
+
+/// Errors when reading or applying a save game.
+#[derive(Debug)]
+pub enum SaveError {
+    /// Error from std::io.
+    Io(io::Error),
+    /// The save game has a different version.
+    Version(i32),
+    /// The file ended in the header.
+    UnexpectedEnd,
+    /// A header value is not a number.
+    BadNumber(String),
+    /// The globals and entities couldn't be parsed.
+    Entities(ReadError),
+    /// A field or global has a value that doesn't match its type.
+    BadValue(String, String),
+    /// A function field names a function the progs don't have.
+    UnknownFunction(String),
+    /// The save game has no world or more entities than allowed.
+    BadEdictCount(usize),
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f : &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Io(ref err) => err.fmt(f),
+            SaveError::Version(version) => write!(f, "Savegame is version {}, not {}", version, SAVEGAME_VERSION),
+            SaveError::UnexpectedEnd => write!(f, "Unexpected end of savegame"),
+            SaveError::BadNumber(ref value) => write!(f, "Bad number {} in savegame", value),
+            SaveError::Entities(ref err) => err.fmt(f),
+            SaveError::BadValue(ref key, ref value) => write!(f, "Bad value \"{}\" for {}", value, key),
+            SaveError::UnknownFunction(ref name) => write!(f, "Can't find function {}", name),
+            SaveError::BadEdictCount(count) => write!(f, "Bad number of edicts: {}", count),
+        }
+    }
+}
+
+impl error::Error for SaveError {
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SaveError::Io(ref err) => Some(err),
+            SaveError::Entities(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(err : io::Error) -> SaveError {
+        SaveError::Io(err)
+    }
+}
+
+impl From<ReadError> for SaveError {
+    fn from(err : ReadError) -> SaveError {
+        SaveError::Entities(err)
+    }
+}
+
+/// Content of a save game.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveGame {
+    /// Level name and kills, spaces are replaced by underscores.
+    pub comment : String,
+    /// Spawn parameters of the first client.
+    pub spawn_parms : [f32; server::NUM_SPAWN_PARMS],
+    /// Skill the map was started with.
+    pub skill : i32,
+    /// Map name without path and extension.
+    pub mapname : String,
+    /// Server time.
+    pub time : f32,
+    /// All light styles, unset styles are stored as "m".
+    pub lightstyles : Vec<String>,
+    /// The saved globals.
+    pub globals : EntityDef,
+    /// All entities, free entities have no fields.
+    pub edicts : Vec<EntityDef>,
+}
+
+/// Splits the whitespace separated header values of a save game.
+struct Header<'a> {
+    text : &'a str,
+}
+
+impl<'a> Header<'a> {
+    fn word(&mut self) -> Result<&'a str, SaveError> {
+        let text = self.text.trim_start();
+        if text.is_empty() {
+            return Err(SaveError::UnexpectedEnd);
+        }
+        let end = text.find(char::is_whitespace).unwrap_or(text.len());
+        self.text = &text[end..];
+        Ok(&text[..end])
+    }
+
+    fn float(&mut self) -> Result<f32, SaveError> {
+        let word = self.word()?;
+        word.parse().map_err(|_| SaveError::BadNumber(word.to_string()))
+    }
+}
+
+/// Formats a float like printf's %f.
+fn float_string(value : f32) -> String {
+    format!("{:.6}", value)
+}
+
+impl SaveGame {
+    /// Parses a save game.
+    pub fn parse(text : &str) -> Result<SaveGame, SaveError> {
+        let mut header = Header { text : text };
+        let version = header.float()? as i32;
+        if version != SAVEGAME_VERSION {
+            return Err(SaveError::Version(version));
+        }
+        let comment = header.word()?.to_string();
+        let mut spawn_parms = [0.0; server::NUM_SPAWN_PARMS];
+        for parm in spawn_parms.iter_mut() {
+            *parm = header.float()?;
+        }
+        let skill = header.float()?;
+        let mapname = header.word()?.to_string();
+        let time = header.float()?;
+        let mut lightstyles = Vec::with_capacity(server::MAX_LIGHTSTYLES);
+        for _ in 0..server::MAX_LIGHTSTYLES {
+            lightstyles.push(header.word()?.to_string());
+        }
+
+        let mut edicts = parse_entities(header.text)?;
+        if edicts.len() < 2 || edicts.len() > server::MAX_EDICTS + 1 {
+            return Err(SaveError::BadEdictCount(edicts.len().saturating_sub(1)));
+        }
+        let globals = edicts.remove(0);
+
+        Ok(SaveGame {
+            comment : comment,
+            spawn_parms : spawn_parms,
+            skill : (skill + 0.1) as i32,
+            mapname : mapname,
+            time : time,
+            lightstyles : lightstyles,
+            globals : globals,
+            edicts : edicts,
+        })
+    }
+
+    /// Writes the save game.
+    pub fn write(&self, out : &mut Write) -> io::Result<()> {
+        writeln!(out, "{}", SAVEGAME_VERSION)?;
+        writeln!(out, "{}", self.comment)?;
+        for &parm in &self.spawn_parms {
+            writeln!(out, "{}", float_string(parm))?;
+        }
+        writeln!(out, "{}", self.skill)?;
+        writeln!(out, "{}", self.mapname)?;
+        writeln!(out, "{}", float_string(self.time))?;
+        for style in &self.lightstyles {
+            writeln!(out, "{}", style)?;
+        }
+        for block in Some(&self.globals).into_iter().chain(self.edicts.iter()) {
+            writeln!(out, "{{")?;
+            for &(ref key, ref value) in &block.fields {
+                writeln!(out, "\"{}\" \"{}\"", key, value)?;
+            }
+            writeln!(out, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Server {
+    /// Creates the save game comment from the level name and the kills.
+    fn savegame_comment(&self) -> String {
+        let mut text : Vec<char> = vec![' '; SAVEGAME_COMMENT_LENGTH];
+        let levelname = self.strings.get(self.edicts[0].v.int(progdefs::MESSAGE));
+        for (c, l) in text.iter_mut().zip(levelname.chars()) {
+            *c = l;
+        }
+        let kills = format!("kills:{:3}/{:3}", self.globals.float(progdefs::G_KILLED_MONSTERS) as i32,
+                            self.globals.float(progdefs::G_TOTAL_MONSTERS) as i32);
+        for (c, k) in text.iter_mut().skip(22).zip(kills.chars()) {
+            *c = k;
+        }
+        // convert space to _ so the comment is one word
+        text.into_iter().map(|c| if c == ' ' { '_' } else { c }).collect()
+    }
+
+    /// Returns the value of a field or global as written to save games,
+    /// None if it's zero.
+    fn value_string(&self, v : &Fields, def : &Def, progs : &Progs) -> Option<String> {
+        let size = if def.kind == DefType::Vector { 3 } else { 1 };
+        if v.raw()[def.ofs..def.ofs + size].iter().all(|&value| value == 0) {
+            return None;
+        }
+        Some(match def.kind {
+            DefType::String => self.strings.get(v.int(def.ofs)).to_string(),
+            DefType::Float => float_string(v.float(def.ofs)),
+            DefType::Vector => {
+                let vec = v.vector(def.ofs);
+                format!("{} {} {}", float_string(vec[0]), float_string(vec[1]), float_string(vec[2]))
+            }
+            DefType::Entity => v.int(def.ofs).to_string(),
+            DefType::Function => {
+                let function = v.int(def.ofs);
+                progs.function_name(function).unwrap_or_else(|| function.to_string())
+            }
+        })
+    }
+
+    /// Parses the value of a field or global.
+    fn parse_value(&mut self, ent : Option<usize>, def : &Def, value : &str, edict_count : usize, progs : &Progs) -> Result<(), SaveError> {
+        let bad_value = || SaveError::BadValue(def.name.to_string(), value.to_string());
+        let float = |s : &str| s.parse::<f32>().map_err(|_| bad_value());
+        let string = match def.kind {
+            DefType::String => Some(self.strings.alloc(&value.replace("\\n", "\n"))),
+            _ => None,
+        };
+
+        let v = match ent {
+            Some(ent) => &mut self.edicts[ent].v,
+            None => &mut self.globals,
+        };
+        match def.kind {
+            DefType::String => v.set_int(def.ofs, string.unwrap_or(0)),
+            DefType::Float => v.set_float(def.ofs, float(value)?),
+            DefType::Vector => {
+                let values : Vec<&str> = value.split_whitespace().collect();
+                if values.len() != 3 {
+                    return Err(bad_value());
+                }
+                v.set_vector(def.ofs, [float(values[0])?, float(values[1])?, float(values[2])?]);
+            }
+            DefType::Entity => match value.parse::<usize>() {
+                Ok(e) if e < edict_count => v.set_int(def.ofs, e as i32),
+                _ => return Err(bad_value()),
+            },
+            DefType::Function => {
+                let function = progs.find_function(value).or_else(|| value.parse().ok());
+                match function {
+                    Some(function) => v.set_int(def.ofs, function),
+                    None => return Err(SaveError::UnknownFunction(value.to_string())),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets a field of an entity from a key and value pair, returns false
+    /// if there is no field with that name.
+    fn parse_field(&mut self, ent : usize, key : &str, value : &str, fields : &[Def], edict_count : usize, progs : &Progs) -> Result<bool, SaveError> {
+        // keys starting with _ are comments
+        if key.starts_with('_') {
+            return Ok(true);
+        }
+        // "angle" is a shortcut for the yaw
+        let (key, value) = if key == "angle" {
+            ("angles", format!("0 {} 0", value))
+        } else {
+            (key, value.to_string())
+        };
+        match fields.iter().find(|def| def.name == key) {
+            Some(field) => {
+                self.parse_value(Some(ent), field, &value, edict_count, progs)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Stores the running game.
+    pub fn save_game(&self, skill : i32, progs : &Progs) -> SaveGame {
+        let mut globals = EntityDef::default();
+        for def in &progs.save_global_defs() {
+            // globals are always written, even if they are zero
+            let value = self.value_string(&self.globals, def, progs).unwrap_or_else(|| float_string(0.0));
+            globals.fields.push((def.name.to_string(), value));
+        }
+
+        let fields = progs.field_defs();
+        let edicts = self.edicts.iter().map(|e| {
+            let mut def = EntityDef::default();
+            if !e.free {
+                for field in &fields {
+                    if let Some(value) = self.value_string(&e.v, field, progs) {
+                        def.fields.push((field.name.to_string(), value));
+                    }
+                }
+            }
+            def
+        }).collect();
+
+        SaveGame {
+            comment : self.savegame_comment(),
+            spawn_parms : self.clients[0].spawn_parms,
+            skill : skill,
+            mapname : self.name.clone(),
+            time : self.time,
+            lightstyles : self.lightstyles.iter().map(|style| {
+                if style.is_empty() { "m".to_string() } else { style.clone() }
+            }).collect(),
+            globals : globals,
+            edicts : edicts,
+        }
+    }
+
+    /// Restores the globals, entities and light styles of a save game on
+    /// the freshly spawned map. Unknown fields and globals are skipped.
+    pub fn load_game(&mut self, save : &SaveGame, progs : &mut Progs) -> Result<(), SaveError> {
+        let count = save.edicts.len();
+        if count <= self.max_clients {
+            return Err(SaveError::BadEdictCount(count));
+        }
+
+        let globals = progs.save_global_defs();
+        for &(ref key, ref value) in &save.globals.fields {
+            match globals.iter().find(|def| def.name == key.as_str()) {
+                Some(def) => self.parse_value(None, def, value, count, progs)?,
+                None => println!("'{}' is not a global", key),
+            }
+        }
+
+        // unlink the entities of the spawned map before replacing them
+        for ent in 0..self.edicts.len() {
+            self.unlink_edict(ent);
+        }
+        self.edicts.truncate(count);
+        while self.edicts.len() < count {
+            self.edicts.push(Edict::new(self.entity_fields));
+        }
+
+        let fields = progs.field_defs();
+        for (ent, def) in save.edicts.iter().enumerate() {
+            self.edicts[ent].v.clear();
+            self.edicts[ent].free = def.fields.is_empty();
+            self.edicts[ent].freetime = 0.0;
+            for &(ref key, ref value) in &def.fields {
+                if !self.parse_field(ent, key, value, &fields, count, progs)? {
+                    println!("'{}' is not a field", key);
+                }
+            }
+        }
+
+        // link the entities into the world
+        for ent in 0..count {
+            if !self.edicts[ent].free {
+                self.link_edict(ent, false, progs);
+            }
+        }
+
+        for (style, saved) in self.lightstyles.iter_mut().zip(save.lightstyles.iter()) {
+            *style = saved.clone();
+        }
+        self.time = save.time;
+        self.clients[0].spawn_parms = save.spawn_parms;
+        // pause until the client is in the game
+        self.loadgame = true;
+        self.paused = true;
+        Ok(())
+    }
+
+    /// Creates the entities of a map and calls their spawn functions. The
+    /// first entity is the world. Entities flagged for a different skill
+    /// or for single player in deathmatch are removed, returns their number.
+    pub fn load_entities(&mut self, entities : &[EntityDef], skill : i32, progs : &mut Progs) -> usize {
+        self.globals.set_float(progdefs::G_TIME, self.time);
+        let deathmatch = self.globals.float(progdefs::G_DEATHMATCH) != 0.0;
+        let skill_flag = match skill {
+            0 => server::SPAWNFLAG_NOT_EASY,
+            1 => server::SPAWNFLAG_NOT_MEDIUM,
+            _ => server::SPAWNFLAG_NOT_HARD,
+        };
+
+        let fields = progs.field_defs();
+        let mut inhibit = 0;
+        for (i, def) in entities.iter().enumerate() {
+            let ent = if i == 0 {
+                0
+            } else {
+                match self.alloc_edict() {
+                    Some(ent) => ent,
+                    None => break,
+                }
+            };
+            for &(ref key, ref value) in &def.fields {
+                match self.parse_field(ent, key, value, &fields, server::MAX_EDICTS, progs) {
+                    Ok(true) => {}
+                    Ok(false) => println!("'{}' is not a field", key),
+                    Err(err) => println!("ED_ParseEdict: {}", err),
+                }
+            }
+
+            // remove things from different skill levels or deathmatch
+            let spawnflags = self.edicts[ent].v.float(progdefs::SPAWNFLAGS) as i32;
+            let flag = if deathmatch { server::SPAWNFLAG_NOT_DEATHMATCH } else { skill_flag };
+            if spawnflags & flag != 0 {
+                self.free_edict(ent);
+                inhibit += 1;
+                continue;
+            }
+
+            // immediately call the spawn function
+            let classname = self.strings.get(self.edicts[ent].v.int(progdefs::CLASSNAME)).to_string();
+            if classname.is_empty() {
+                println!("No classname for entity {}", ent);
+                self.free_edict(ent);
+                continue;
+            }
+            match progs.find_function(&classname) {
+                Some(function) => {
+                    self.globals.set_int(progdefs::G_SELF, ent as i32);
+                    self.execute(progs, function);
+                }
+                None => {
+                    println!("No spawn function for {}", classname);
+                    self.free_edict(ent);
+                }
+            }
+        }
+        println!("{} entities inhibited", inhibit);
+        inhibit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pr_exec::{ProgsBuilder, ProgsDat, DEF_SAVEGLOBAL, EV_FLOAT, EV_STRING, EV_VECTOR};
+
+    struct Functions;
+
+    impl Progs for Functions {
+        fn execute(&mut self, _sv : &mut Server, _function : i32) {}
+
+        fn function_name(&self, function : i32) -> Option<String> {
+            match function {
+                7 => Some("SUB_Remove".to_string()),
+                _ => None,
+            }
+        }
+
+        fn find_function(&self, name : &str) -> Option<i32> {
+            match name {
+                "SUB_Remove" => Some(7),
+                "worldspawn" => Some(8),
+                "info_player_deathmatch" => Some(9),
+                _ => None,
+            }
+        }
+    }
+
+    fn test_server() -> Server {
+        let mut sv = Server::new(1);
+        sv.name = "e1m1".to_string();
+        sv.time = 12.5;
+        sv.lightstyles[0] = "abc".to_string();
+        sv.clients[0].spawn_parms[0] = 4097.0;
+        sv.globals.set_float(progdefs::G_TOTAL_MONSTERS, 37.0);
+        sv.globals.set_float(progdefs::G_KILLED_MONSTERS, 2.0);
+        let message = sv.strings.alloc("the Slipgate Complex");
+        sv.edicts[0].v.set_int(progdefs::MESSAGE, message);
+
+        let ent = sv.alloc_edict().unwrap();
+        let classname = sv.strings.alloc("info_notnull");
+        let v = &mut sv.edicts[ent].v;
+        v.set_int(progdefs::CLASSNAME, classname);
+        v.set_vector(progdefs::ORIGIN, [16.0, -32.5, 0.0]);
+        v.set_float(progdefs::HEALTH, 100.0);
+        v.set_int(progdefs::OWNER, 1);
+        v.set_int(progdefs::THINK, 7);
+        sv
+    }
+
+    #[test]
+    fn save_and_load() {
+        let sv = test_server();
+        let save = sv.save_game(1, &Functions);
+        assert_eq!(save.comment, "the_Slipgate_Complex__kills:__2/_37____");
+        assert_eq!(save.edicts.len(), 3);
+        assert_eq!(save.edicts[2].get("origin"), Some("16.000000 -32.500000 0.000000"));
+        assert_eq!(save.edicts[2].get("think"), Some("SUB_Remove"));
+        assert_eq!(save.edicts[2].get("solid"), None);
+
+        let mut text = Vec::new();
+        save.write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("5\nthe_Slipgate_Complex__kills:__2/_37____\n4097.000000\n"));
+        assert!(text.contains("\n1\ne1m1\n12.500000\nabc\nm\n"));
+        assert!(text.contains("{\n\"deathmatch\" \"0.000000\"\n"));
+        let parsed = SaveGame::parse(&text).unwrap();
+        assert_eq!(parsed, save);
+
+        let mut loaded = Server::new(1);
+        loaded.load_game(&parsed, &mut Functions).unwrap();
+        assert_eq!(loaded.time, 12.5);
+        assert_eq!(loaded.lightstyles[0], "abc");
+        assert_eq!(loaded.clients[0].spawn_parms[0], 4097.0);
+        assert_eq!(loaded.globals.float(progdefs::G_TOTAL_MONSTERS), 37.0);
+        assert_eq!(loaded.edicts.len(), 3);
+        let v = &loaded.edicts[2].v;
+        assert_eq!(loaded.strings.get(v.int(progdefs::CLASSNAME)), "info_notnull");
+        assert_eq!(v.vector(progdefs::ORIGIN), [16.0, -32.5, 0.0]);
+        assert_eq!(v.int(progdefs::OWNER), 1);
+        assert_eq!(v.int(progdefs::THINK), 7);
+        assert_eq!(v.vector(progdefs::ABSMIN), [15.0, -33.5, -1.0]);
+    }
+
+    #[test]
+    fn map_entities() {
+        let entities = parse_entities("{ \"classname\" \"worldspawn\" \"message\" \"the Necropolis\" \"_comment\" \"x\" }\n\
+                                       { \"classname\" \"info_player_deathmatch\" \"angle\" \"90\" \"wait\" \"2\" }\n\
+                                       { \"classname\" \"info_player_deathmatch\" \"spawnflags\" \"2048\" }\n\
+                                       { \"classname\" \"light\" }\n\
+                                       { \"origin\" \"0 0 0\" }").unwrap();
+        let mut sv = Server::new(1);
+        sv.globals.set_float(progdefs::G_DEATHMATCH, 1.0);
+        assert_eq!(sv.load_entities(&entities, 1, &mut Functions), 1);
+
+        // entities without a classname or spawn function are removed too
+        assert_eq!(sv.active_edicts(), 3);
+        assert_eq!(sv.strings.get(sv.edicts[0].v.int(progdefs::MESSAGE)), "the Necropolis");
+        assert_eq!(sv.edicts[2].v.vector(progdefs::ANGLES), [0.0, 90.0, 0.0]);
+        assert!(sv.edicts[3].free);
+
+        // single player entities only check the skill
+        let mut sv = Server::new(1);
+        assert_eq!(sv.load_entities(&entities[..3], 1, &mut Functions), 0);
+        assert_eq!(sv.active_edicts(), 4);
+    }
+
+    #[test]
+    fn fields_of_the_progs() {
+        let mut builder = ProgsBuilder::new();
+        builder.function("func_door", &[[0, 0, 0, 0]]);
+        builder.field_def(EV_STRING, progdefs::CLASSNAME, "classname");
+        builder.field_def(EV_VECTOR, progdefs::ORIGIN, "origin");
+        builder.field_def(EV_FLOAT, progdefs::ORIGIN, "origin_x");
+        builder.field_def(EV_FLOAT, progdefs::ENTITY_FIELDS, "speed");
+        builder.global_def(EV_FLOAT | DEF_SAVEGLOBAL, progdefs::G_DEATHMATCH, "deathmatch");
+        builder.global_def(EV_FLOAT, progdefs::G_TIME, "time");
+        let mut progs = ProgsDat::read(&builder.build()).unwrap();
+        let mut sv = Server::new(1);
+        progs.reset(&mut sv);
+        sv.edicts = (0..2).map(|_| Edict::new(sv.entity_fields)).collect();
+
+        let entities = parse_entities("{ \"classname\" \"func_door\" }\n\
+                                       { \"classname\" \"func_door\" \"origin\" \"0 8 0\" \"speed\" \"100\" }").unwrap();
+        assert_eq!(sv.load_entities(&entities, 1, &mut progs), 0);
+        assert_eq!(sv.edicts[2].v.float(progdefs::ENTITY_FIELDS), 100.0);
+
+        // vector components and globals without the save flag are skipped
+        sv.globals.set_float(progdefs::G_DEATHMATCH, 1.0);
+        let save = sv.save_game(1, &progs);
+        assert_eq!(save.globals.fields, vec![("deathmatch".to_string(), "1.000000".to_string())]);
+        assert_eq!(save.edicts[2].fields.len(), 3);
+        assert_eq!(save.edicts[2].get("speed"), Some("100.000000"));
+
+        let mut loaded = Server::new(1);
+        progs.reset(&mut loaded);
+        loaded.load_game(&save, &mut progs).unwrap();
+        assert!(loaded.loadgame);
+        assert_eq!(loaded.globals.float(progdefs::G_DEATHMATCH), 1.0);
+        assert_eq!(loaded.edicts[2].v.vector(progdefs::ORIGIN), [0.0, 8.0, 0.0]);
+        assert_eq!(loaded.edicts[2].v.float(progdefs::ENTITY_FIELDS), 100.0);
+    }
+
+    #[test]
+    fn bad_savegames() {
+        let mut text = Vec::new();
+        test_server().save_game(1, &Functions).write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+
+        match SaveGame::parse(&text.replacen("5", "6", 1)) {
+            Err(SaveError::Version(6)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        match SaveGame::parse("5\ncomment\n1.0\n") {
+            Err(SaveError::UnexpectedEnd) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        match SaveGame::parse(&text.replace("4097.000000", "lots")) {
+            Err(SaveError::BadNumber(ref value)) if value == "lots" => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        match SaveGame::parse(&text.replace("\"owner\" \"1\"\n}", "\"owner\"")) {
+            Err(SaveError::Entities(_)) => {}
+            result => panic!("unexpected {:?}", result),
+        }
+
+        let mut sv = Server::new(1);
+        let save = SaveGame::parse(&text.replace("\"owner\" \"1\"", "\"owner\" \"9\"")).unwrap();
+        match sv.load_game(&save, &mut Functions) {
+            Err(SaveError::BadValue(ref key, ref value)) if key == "owner" && value == "9" => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        let save = SaveGame::parse(&text.replace("SUB_Remove", "SUB_Null")).unwrap();
+        match sv.load_game(&save, &mut Functions) {
+            Err(SaveError::UnknownFunction(ref name)) if name == "SUB_Null" => {}
+            result => panic!("unexpected {:?}", result),
+        }
+        assert_eq!(SaveError::Version(6).to_string(), "Savegame is version 6, not 5");
+    }
+}
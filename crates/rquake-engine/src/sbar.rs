@@ -0,0 +1,170 @@
+#![warn(missing_docs)]
+
+//! Big numbers and the screens shown when a level or an episode is
+//! completed.
+//!
+//! Original source can be found in sbar.c
+
+use std::cmp::Reverse;
+use std::iter;
+
+use rquake_common::{BackBuffer, GameResources};
+use rquake_fs::Palette;
+use client::ClientState;
+use draw::{self, Draw, Pic};
+use protocol;
+
+/// Index of the minus sign in the number pictures.
+const STAT_MINUS : usize = 10;
+
+/// Width of a digit of the number pictures.
+const DIGIT_WIDTH : i32 = 24;
+
+/// Pictures of the status bar.
+pub struct Sbar {
+    nums : Vec<Option<Pic>>,
+    colon : Option<Pic>,
+    slash : Option<Pic>,
+}
+
+impl Sbar {
+    /// Creates the status bar without pictures.
+    pub fn new() -> Sbar {
+        Sbar {
+            nums : Vec::new(),
+            colon : None,
+            slash : None,
+        }
+    }
+
+    /// Takes the pictures from gfx.wad.
+    pub fn init(&mut self, draw : &Draw) {
+        self.nums = (0..10).map(|i| draw.pic_from_wad(&format!("num_{}", i)))
+            .chain(iter::once(draw.pic_from_wad("num_minus")))
+            .collect();
+        self.colon = draw.pic_from_wad("num_colon");
+        self.slash = draw.pic_from_wad("num_slash");
+    }
+
+    fn draw_digit(&self, x : i32, y : i32, frame : usize, palette : &Palette, backbuffer : &mut BackBuffer) {
+        if let Some(&Some(ref pic)) = self.nums.get(frame) {
+            draw::draw_trans_pic(x, y, pic, palette, backbuffer);
+        }
+    }
+
+    /// Draws a number right aligned in a field of digits. Only the last
+    /// digits of longer numbers are drawn.
+    fn draw_num(&self, x : i32, y : i32, num : i32, digits : usize, palette : &Palette, backbuffer : &mut BackBuffer) {
+        let text = num.to_string();
+        let text = &text[text.len().saturating_sub(digits)..];
+        let mut x = x + DIGIT_WIDTH * (digits - text.len()) as i32;
+        for c in text.bytes() {
+            let frame = if c == b'-' { STAT_MINUS } else { (c - b'0') as usize };
+            self.draw_digit(x, y, frame, palette, backbuffer);
+            x += DIGIT_WIDTH;
+        }
+    }
+
+    /// Draws the level completed screen with the time, the found secrets
+    /// and the killed monsters.
+    pub fn intermission_overlay(&self, cl : &ClientState, draw : &mut Draw, game_res : &mut GameResources, palette : &Palette, backbuffer : &mut BackBuffer) {
+        if cl.gametype == protocol::GAME_DEATHMATCH {
+            self.deathmatch_overlay(cl, draw, game_res, palette, backbuffer);
+            return;
+        }
+        if let Some(pic) = draw.cache_pic("gfx/complete.lmp", game_res) {
+            draw::draw_pic(64, 24, pic, palette, backbuffer);
+        }
+        if let Some(pic) = draw.cache_pic("gfx/inter.lmp", game_res) {
+            draw::draw_trans_pic(0, 56, pic, palette, backbuffer);
+        }
+
+        // time
+        let minutes = (cl.completed_time / 60.0) as i32;
+        let seconds = cl.completed_time as i32 - minutes * 60;
+        self.draw_num(160, 64, minutes, 3, palette, backbuffer);
+        if let Some(ref colon) = self.colon {
+            draw::draw_trans_pic(234, 64, colon, palette, backbuffer);
+        }
+        self.draw_digit(246, 64, (seconds / 10) as usize, palette, backbuffer);
+        self.draw_digit(266, 64, (seconds % 10) as usize, palette, backbuffer);
+
+        let counts = [
+            (104, protocol::STAT_SECRETS, protocol::STAT_TOTALSECRETS),
+            (144, protocol::STAT_MONSTERS, protocol::STAT_TOTALMONSTERS),
+        ];
+        for &(y, stat, total) in &counts {
+            self.draw_num(160, y, cl.stats[stat as usize], 3, palette, backbuffer);
+            if let Some(ref slash) = self.slash {
+                draw::draw_trans_pic(232, y, slash, palette, backbuffer);
+            }
+            self.draw_num(240, y, cl.stats[total as usize], 3, palette, backbuffer);
+        }
+    }
+
+    /// Draws the players sorted by their frags with their colors.
+    pub fn deathmatch_overlay(&self, cl : &ClientState, draw : &mut Draw, game_res : &mut GameResources, palette : &Palette, backbuffer : &mut BackBuffer) {
+        let offset = (backbuffer.get_width() as i32 - 320) / 2;
+        if let Some(pic) = draw.cache_pic("gfx/ranking.lmp", game_res) {
+            draw::draw_pic(offset + (320 - pic.width as i32) / 2, 8, pic, palette, backbuffer);
+        }
+        let conchars = match draw.conchars() {
+            Some(conchars) => conchars,
+            None => return,
+        };
+
+        let mut order : Vec<usize> = (0..cl.scores.len()).filter(|&i| !cl.scores[i].name.is_empty()).collect();
+        order.sort_by_key(|&i| Reverse(cl.scores[i].frags));
+        let x = 80 + offset;
+        for (row, &i) in order.iter().enumerate() {
+            let score = &cl.scores[i];
+            let y = 40 + row as i32 * 10;
+
+            // the middle of the shirt and pants ranges
+            let top = (score.colors & 0xf0) as u8 + 8;
+            let bottom = ((score.colors & 15) << 4) as u8 + 8;
+            draw::draw_fill(x, y, 40, 4, top, palette, backbuffer);
+            draw::draw_fill(x, y + 4, 40, 4, bottom, palette, backbuffer);
+
+            draw::draw_string(x + 8, y, &format!("{:3}", score.frags), conchars, palette, backbuffer);
+            if i + 1 == cl.viewentity {
+                draw::draw_character(x - 8, y, 12, conchars, palette, backbuffer);
+            }
+            draw::draw_string(x + 64, y, &score.name, conchars, palette, backbuffer);
+        }
+    }
+
+    /// Draws the picture above the end of episode text.
+    pub fn finale_overlay(&self, draw : &mut Draw, game_res : &mut GameResources, palette : &Palette, backbuffer : &mut BackBuffer) {
+        if let Some(pic) = draw.cache_pic("gfx/finale.lmp", game_res) {
+            let x = (backbuffer.get_width() as i32 - pic.width as i32) / 2;
+            draw::draw_trans_pic(x, 16, pic, palette, backbuffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_common::MemoryBackBuffer;
+
+    #[test]
+    fn numbers() {
+        // every digit is a single pixel of its own color
+        let mut sbar = Sbar::new();
+        sbar.nums = (0..11).map(|i| Some(Pic { width : 1, height : 1, data : vec![i as u8 + 1] })).collect();
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::read(&mut &data[..]).unwrap();
+
+        let mut backbuffer = MemoryBackBuffer::new(96, 1);
+        sbar.draw_num(0, 0, 42, 3, &palette, &mut backbuffer);
+        sbar.draw_num(72, 0, -7, 1, &palette, &mut backbuffer);
+        let colors : Vec<u32> = [0, 24, 48, 72].iter().map(|&x| backbuffer.get_buffer()[x]).collect();
+        assert_eq!(colors, vec![0, palette.palette_lookup(5), palette.palette_lookup(3), palette.palette_lookup(8)]);
+
+        // the minus sign is dropped when there is no room
+        let mut backbuffer = MemoryBackBuffer::new(48, 1);
+        sbar.draw_num(0, 0, -12, 2, &palette, &mut backbuffer);
+        assert_eq!(backbuffer.get_buffer()[0], palette.palette_lookup(2));
+    }
+}
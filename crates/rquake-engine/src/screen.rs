@@ -0,0 +1,238 @@
+#![warn(missing_docs)]
+
+//! Screen updates, centered messages, screenshots and frame capturing.
+//!
+//! Original source can be found in screen.c
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use rquake_common::BackBuffer;
+use rquake_fs::{write_pcx, write_tga, Palette};
+use draw::{self, Pic, CHAR_SIZE};
+
+/// Seconds a centered message is shown.
+const CENTER_TIME : f32 = 2.0;
+
+/// Characters per second of the text shown at the end of an episode.
+const PRINT_SPEED : f32 = 8.0;
+
+/// Maximum number of characters of a line of a centered message.
+const CENTER_LINE_LENGTH : usize = 40;
+
+/// File formats for screenshots.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// 8 bit PCX file with the game palette.
+    Pcx,
+    /// 24 bit TGA file.
+    Tga,
+}
+
+impl ImageFormat {
+    /// Returns the file extension for the format.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            ImageFormat::Pcx => "pcx",
+            ImageFormat::Tga => "tga",
+        }
+    }
+}
+
+/// Screen state.
+pub struct Screen {
+    game_dir : String,
+    screenshots : Vec<ImageFormat>,
+    capture_dir : Option<String>,
+    capture_frame : u32,
+    center_string : String,
+    center_time_start : f32,
+}
+
+impl Screen {
+    /// Creates the screen state. Screenshots are written to game_dir.
+    pub fn new(game_dir : &str) -> Screen {
+        Screen {
+            game_dir : game_dir.to_string(),
+            screenshots : Vec::new(),
+            capture_dir : None,
+            capture_frame : 0,
+            center_string : String::new(),
+            center_time_start : 0.0,
+        }
+    }
+
+    /// Shows a message in the center of the screen for a few seconds.
+    pub fn center_print(&mut self, text : &str, time : f32) {
+        self.center_string = text.to_string();
+        self.center_time_start = time;
+    }
+
+    /// Draws the centered message. During an intermission it stays and
+    /// is revealed one character at a time.
+    pub fn draw_center_string(&self, time : f32, intermission : bool, conchars : &Pic, palette : &Palette, backbuffer : &mut BackBuffer) {
+        if self.center_string.is_empty() || (!intermission && time - self.center_time_start > CENTER_TIME) {
+            return;
+        }
+        let mut remaining = if intermission {
+            (PRINT_SPEED * (time - self.center_time_start)).max(0.0) as usize
+        } else {
+            usize::max_value()
+        };
+
+        let lines : Vec<&[u8]> = self.center_string.as_bytes().split(|&c| c == b'\n').collect();
+        let mut y = if lines.len() <= 4 { (backbuffer.get_height() as f32 * 0.35) as i32 } else { 48 };
+        for line in lines {
+            let line = &line[..line.len().min(CENTER_LINE_LENGTH)];
+            let mut x = (backbuffer.get_width() as i32 - line.len() as i32 * CHAR_SIZE) / 2;
+            for &c in line {
+                draw::draw_character(x, y, c, conchars, palette, backbuffer);
+                if remaining == 0 {
+                    return;
+                }
+                remaining -= 1;
+                x += CHAR_SIZE;
+            }
+            y += CHAR_SIZE;
+        }
+    }
+
+    /// Writes a screenshot at the end of the current frame.
+    pub fn request_screenshot(&mut self, format : ImageFormat) {
+        self.screenshots.push(format);
+    }
+
+    /// Writes every frame to the given directory, or stops capturing if dir is None.
+    pub fn set_capture_dir(&mut self, dir : Option<&str>) {
+        self.capture_dir = dir.map(|d| d.to_string());
+        self.capture_frame = 0;
+    }
+
+    /// Handles the screenshot command.
+    pub fn screenshot_cmd(&mut self, args : &[String]) {
+        let format = match args.get(1).map(|a| a.as_str()) {
+            None | Some("pcx") => ImageFormat::Pcx,
+            Some("tga") => ImageFormat::Tga,
+            Some(other) => {
+                println!("Unknown screenshot format {}, use pcx or tga", other);
+                return;
+            },
+        };
+        self.request_screenshot(format);
+    }
+
+    /// Called after a frame has been drawn into the back buffer. PCX files
+    /// need the palette the frame was drawn with.
+    pub fn end_frame(&mut self, backbuffer : &mut BackBuffer, palette : Option<&Palette>) {
+        for format in self.screenshots.drain(..) {
+            match screenshot_name(&self.game_dir, format) {
+                None => println!("SCR_ScreenShot_f: Couldn't create a {} file", format.extension()),
+                Some(name) => match write_image(backbuffer, &name, format, palette) {
+                    Ok(_) => println!("Wrote {}", name),
+                    Err(err) => println!("Failed to write {}: {}", name, err),
+                },
+            }
+        }
+
+        if let Some(ref dir) = self.capture_dir {
+            let name = format!("{}/frame{:06}.tga", dir, self.capture_frame);
+            if let Err(err) = write_image(backbuffer, &name, ImageFormat::Tga, palette) {
+                println!("Failed to write {}: {}", name, err);
+            }
+            self.capture_frame += 1;
+        }
+    }
+}
+
+/// Returns the first unused file name quake00 to quake99 in dir.
+fn screenshot_name(dir : &str, format : ImageFormat) -> Option<String> {
+    (0..100)
+        .map(|i| format!("{}/quake{:02}.{}", dir, i, format.extension()))
+        .find(|name| !Path::new(name).exists())
+}
+
+/// Writes the content of the back buffer to an image file. PCX files are
+/// written with palette indices and fail without a palette.
+pub fn write_image(backbuffer : &mut BackBuffer, filename : &str, format : ImageFormat, palette : Option<&Palette>) -> io::Result<()> {
+    let width = backbuffer.get_width();
+    let height = backbuffer.get_height();
+    match format {
+        ImageFormat::Pcx => {
+            let palette = palette.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no palette loaded"))?;
+            let pixels = palette_indices(backbuffer.get_buffer(), palette);
+            write_pcx(&mut BufWriter::new(File::create(filename)?), width, height, &pixels, palette)
+        },
+        ImageFormat::Tga => write_tga(&mut BufWriter::new(File::create(filename)?), width, height, backbuffer.get_buffer()),
+    }
+}
+
+/// Converts RGB pixels back to the palette indices they were drawn with.
+fn palette_indices(pixels : &[u32], palette : &Palette) -> Vec<u8> {
+    let mut known = HashMap::new();
+    pixels.iter().map(|&pixel| *known.entry(pixel).or_insert_with(|| palette.find_color(pixel))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use rquake_common::MemoryBackBuffer;
+
+    #[test]
+    fn center_string() {
+        // every character has a pixel in its top left corner
+        let mut conchars = Pic { width : 128, height : 128, data : vec![0; 128 * 128] };
+        for c in 0..256 {
+            conchars.data[(c / 16) * 8 * 128 + (c % 16) * 8] = 1;
+        }
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::read(&mut &data[..]).unwrap();
+        let drawn = |screen : &Screen, time : f32, intermission : bool| {
+            let mut backbuffer = MemoryBackBuffer::new(320, 200);
+            screen.draw_center_string(time, intermission, &conchars, &palette, &mut backbuffer);
+            let buffer = backbuffer.get_buffer();
+            (buffer.iter().filter(|&&color| color != 0).count(), buffer[70 * 320 + 148] != 0)
+        };
+
+        let mut screen = Screen::new(".");
+        screen.center_print("abc\nde", 1.0);
+        assert_eq!(drawn(&screen, 1.0, false), (5, true));
+        assert_eq!(drawn(&screen, 3.5, false), (0, false));
+
+        // the finale text is revealed at 8 characters per second
+        assert_eq!(drawn(&screen, 1.25, true), (3, true));
+        assert_eq!(drawn(&screen, 30.0, true), (5, true));
+    }
+
+    #[test]
+    fn capture_frames() {
+        let dir = env::temp_dir().join("rquake_capture_test");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let dir = dir.to_str().unwrap();
+
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::read(&mut &data[..]).unwrap();
+        let mut backbuffer = MemoryBackBuffer::new(4, 2);
+        backbuffer.get_buffer()[1] = palette.palette_lookup(200);
+        backbuffer.get_buffer()[2] = 0x00102030;
+        let mut screen = Screen::new(dir);
+        screen.set_capture_dir(Some(dir));
+        screen.request_screenshot(ImageFormat::Pcx);
+        screen.end_frame(&mut backbuffer, Some(&palette));
+        screen.request_screenshot(ImageFormat::Pcx);
+        screen.end_frame(&mut backbuffer, Some(&palette));
+
+        // the pixels are stored as the closest palette colors
+        let pcx = fs::read(format!("{}/quake00.pcx", dir)).unwrap();
+        assert_eq!(&pcx[128..132], &[0x00, 0xc1, 200, 0x20]);
+        assert_eq!(pcx.len(), 128 + 5 + 2 + 1 + 768);
+        assert!(Path::new(&format!("{}/quake01.pcx", dir)).exists());
+        assert_eq!(fs::metadata(format!("{}/frame000001.tga", dir)).unwrap().len(), 18 + 4 * 2 * 3);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
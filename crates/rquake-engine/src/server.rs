@@ -0,0 +1,291 @@
+#![warn(missing_docs)]
+
+//! Server state holding the entities of the running map.
+//!
+//! Original source can be found in server.h and sv_main.c
+
+use std::rc::Rc;
+
+use rquake_fs::BspFile;
+use cl_input::UserCmd;
+use cvar::CvarList;
+use model::BrushModel;
+use msg::SizeBuf;
+use net::{NetSocket, MAX_MSGLEN};
+use net_dgrm::MAX_DATAGRAM;
+use progdefs;
+use progs::{Edict, Fields, StringTable};
+use world::AreaNode;
+
+/// Maximum number of entities.
+pub const MAX_EDICTS : usize = 600;
+/// Maximum number of precached models.
+pub const MAX_MODELS : usize = 256;
+/// Maximum number of precached sounds.
+pub const MAX_SOUNDS : usize = 256;
+/// Maximum number of leafs an entity is visible in.
+pub const MAX_ENT_LEAFS : usize = 16;
+/// Number of light styles.
+pub const MAX_LIGHTSTYLES : usize = 64;
+/// Maximum number of client slots.
+pub const MAX_SCOREBOARD : usize = 16;
+/// Number of parameters kept when a client changes the level.
+pub const NUM_SPAWN_PARMS : usize = 16;
+/// Number of round trip times the ping of a client is averaged over.
+pub const NUM_PING_TIMES : usize = 16;
+
+/// Never moves.
+pub const MOVETYPE_NONE : f32 = 0.0;
+/// Moves without clipping, only the angles change.
+pub const MOVETYPE_ANGLENOCLIP : f32 = 1.0;
+/// Only the angles change.
+pub const MOVETYPE_ANGLECLIP : f32 = 2.0;
+/// Player movement, gravity and stepping.
+pub const MOVETYPE_WALK : f32 = 3.0;
+/// Monster movement, gravity only applies when falling.
+pub const MOVETYPE_STEP : f32 = 4.0;
+/// No gravity, clips against the world.
+pub const MOVETYPE_FLY : f32 = 5.0;
+/// Gravity and stops on the ground.
+pub const MOVETYPE_TOSS : f32 = 6.0;
+/// Doors, platforms and trains, moves other entities.
+pub const MOVETYPE_PUSH : f32 = 7.0;
+/// No gravity and no clipping.
+pub const MOVETYPE_NOCLIP : f32 = 8.0;
+/// Like fly, but with a larger box when clipping against monsters.
+pub const MOVETYPE_FLYMISSILE : f32 = 9.0;
+/// Gravity and bounces off walls.
+pub const MOVETYPE_BOUNCE : f32 = 10.0;
+
+/// No interaction with other entities.
+pub const SOLID_NOT : f32 = 0.0;
+/// Touches other entities, but doesn't block them.
+pub const SOLID_TRIGGER : f32 = 1.0;
+/// Blocks and touches other entities.
+pub const SOLID_BBOX : f32 = 2.0;
+/// Like bbox, but only touches on the edges. Used by monsters.
+pub const SOLID_SLIDEBOX : f32 = 3.0;
+/// Brush model, clips against the model hulls.
+pub const SOLID_BSP : f32 = 4.0;
+
+/// Flying monster, not affected by gravity.
+pub const FL_FLY : i32 = 1;
+/// Swimming monster, stays in water.
+pub const FL_SWIM : i32 = 2;
+/// Moved by conveyor belts.
+pub const FL_CONVEYOR : i32 = 4;
+/// Player entity.
+pub const FL_CLIENT : i32 = 8;
+/// Entity is in water.
+pub const FL_INWATER : i32 = 16;
+/// Monster entity.
+pub const FL_MONSTER : i32 = 32;
+/// Doesn't take damage.
+pub const FL_GODMODE : i32 = 64;
+/// Ignored by monsters.
+pub const FL_NOTARGET : i32 = 128;
+/// Items get a larger touch box.
+pub const FL_ITEM : i32 = 256;
+/// Standing on the ground.
+pub const FL_ONGROUND : i32 = 512;
+/// Not all corners are on the ground.
+pub const FL_PARTIALGROUND : i32 = 1024;
+/// Jumping out of water.
+pub const FL_WATERJUMP : i32 = 2048;
+/// The jump button has been released since the last jump.
+pub const FL_JUMPRELEASED : i32 = 4096;
+
+/// Not spawned in easy skill.
+pub const SPAWNFLAG_NOT_EASY : i32 = 256;
+/// Not spawned in medium skill.
+pub const SPAWNFLAG_NOT_MEDIUM : i32 = 512;
+/// Not spawned in hard and nightmare skill.
+pub const SPAWNFLAG_NOT_HARD : i32 = 1024;
+/// Not spawned in deathmatch.
+pub const SPAWNFLAG_NOT_DEATHMATCH : i32 = 2048;
+
+/// Particle field around the entity.
+pub const EF_BRIGHTFIELD : i32 = 1;
+/// Light flash for one frame when firing.
+pub const EF_MUZZLEFLASH : i32 = 2;
+/// Bright dynamic light.
+pub const EF_BRIGHTLIGHT : i32 = 4;
+/// Dim dynamic light.
+pub const EF_DIMLIGHT : i32 = 8;
+
+/// A client slot of the server.
+pub struct Client {
+    /// A client is connected to the slot.
+    pub active : bool,
+    /// The client has entered the game and is moved by its commands.
+    pub spawned : bool,
+    /// Last move command received from the client.
+    pub cmd : UserCmd,
+    /// Player name.
+    pub name : String,
+    /// Shirt color in the high and pants color in the low 4 bits.
+    pub colors : i32,
+    /// Connection to the client, None for unconnected slots.
+    pub netconnection : Option<Box<NetSocket>>,
+    /// Reliable messages that are sent with the next frame.
+    pub message : SizeBuf,
+    /// Unreliable messages for this client only, cleared every frame.
+    pub datagram : SizeBuf,
+    /// Real time of the last received message.
+    pub last_message : f64,
+    /// Real time the client connected.
+    pub connect_time : f64,
+    /// Frags last sent to the clients.
+    pub old_frags : i32,
+    /// Parameters copied to parm1 to parm16 when the client spawns.
+    pub spawn_parms : [f32; NUM_SPAWN_PARMS],
+    /// Round trip times of the last move commands.
+    pub ping_times : [f32; NUM_PING_TIMES],
+    /// Number of move commands received.
+    pub num_pings : usize,
+}
+
+impl Client {
+    /// Creates an unconnected client slot.
+    pub fn new() -> Client {
+        let mut message = SizeBuf::new(MAX_MSGLEN);
+        message.allow_overflow = true;
+        Client {
+            active : false,
+            spawned : false,
+            cmd : UserCmd::new(),
+            name : String::new(),
+            colors : 0,
+            netconnection : None,
+            message : message,
+            datagram : SizeBuf::new(MAX_DATAGRAM),
+            last_message : 0.0,
+            connect_time : 0.0,
+            old_frags : 0,
+            spawn_parms : [0.0; NUM_SPAWN_PARMS],
+            ping_times : [0.0; NUM_PING_TIMES],
+            num_pings : 0,
+        }
+    }
+}
+
+/// The running map. Entity 0 is the world, the following entities
+/// are reserved for the clients.
+pub struct Server {
+    /// A map is running.
+    pub active : bool,
+    /// Server time in seconds.
+    pub time : f32,
+    /// Number of client slots.
+    pub max_clients : usize,
+    /// The client slots, client n uses entity n + 1.
+    pub clients : Vec<Client>,
+    /// Number of fields of every entity.
+    pub entity_fields : usize,
+    /// All entities, including free ones.
+    pub edicts : Vec<Edict>,
+    /// Globals shared with the QuakeC programs.
+    pub globals : Fields,
+    /// Strings referenced by fields and globals.
+    pub strings : StringTable,
+    /// Tree used to find entities by their bounding box.
+    pub areanodes : Vec<AreaNode>,
+    /// Models by model index, None for models that aren't brush models.
+    /// Model 1 is the world.
+    pub models : Vec<Option<Rc<BrushModel>>>,
+    /// Map name without path and extension.
+    pub name : String,
+    /// Model names by model index, index 0 is unused.
+    pub model_precache : Vec<String>,
+    /// Sound names by sound index, index 0 is unused.
+    pub sound_precache : Vec<String>,
+    /// Light style strings, "a" is dark and "z" is twice the normal brightness.
+    pub lightstyles : Vec<String>,
+    /// Unreliable messages for all clients, cleared every frame.
+    pub datagram : SizeBuf,
+    /// Reliable messages for all clients.
+    pub reliable_datagram : SizeBuf,
+    /// Baselines and static entities sent to every client on prespawn.
+    pub signon : SizeBuf,
+    /// Flags like the collected runes, kept when the level changes.
+    pub serverflags : f32,
+    /// Map of the running level, used for visibility checks.
+    pub worldmodel : Option<Rc<BspFile>>,
+    /// Potentially hearable set of every leaf of the world, sounds are only
+    /// sent to the clients that can hear them.
+    pub phs : Vec<Vec<u8>>,
+    /// Copy of the host's cvars read by the progs, updated every frame.
+    pub cvars : CvarList,
+    /// Commands from the progs the host runs after the frame.
+    pub commands : String,
+    /// A changelevel command has been sent to the host.
+    pub changelevel_issued : bool,
+    /// The entities were restored from a save game, spawning clients keep
+    /// their saved entity.
+    pub loadgame : bool,
+    /// The game is paused, the clients and the world don't move.
+    pub paused : bool,
+    /// The progs stopped with an error, the host shuts the server down.
+    pub program_error : bool,
+    /// Client that checkclient returns to the monsters.
+    pub lastcheck : usize,
+    /// Server time the check client was picked.
+    pub lastchecktime : f32,
+    /// Potentially visible set of the check client.
+    pub checkpvs : Vec<u8>,
+}
+
+impl Server {
+    /// Creates a server with the world and the client entities.
+    pub fn new(max_clients : usize) -> Server {
+        let mut sv = Server {
+            active : false,
+            time : 0.0,
+            max_clients : max_clients,
+            clients : (0..max_clients).map(|_| Client::new()).collect(),
+            entity_fields : progdefs::ENTITY_FIELDS,
+            edicts : Vec::new(),
+            globals : Fields::new(progdefs::GLOBAL_COUNT),
+            strings : StringTable::new(),
+            areanodes : Vec::new(),
+            models : Vec::new(),
+            name : String::new(),
+            model_precache : vec![String::new()],
+            sound_precache : vec![String::new()],
+            lightstyles : vec![String::new(); MAX_LIGHTSTYLES],
+            datagram : SizeBuf::new(MAX_DATAGRAM),
+            reliable_datagram : SizeBuf::new(MAX_DATAGRAM),
+            signon : SizeBuf::new(MAX_MSGLEN),
+            serverflags : 0.0,
+            worldmodel : None,
+            phs : Vec::new(),
+            cvars : CvarList::new(),
+            commands : String::new(),
+            changelevel_issued : false,
+            loadgame : false,
+            paused : false,
+            program_error : false,
+            lastcheck : 0,
+            lastchecktime : 0.0,
+            checkpvs : Vec::new(),
+        };
+        for _ in 0..max_clients + 1 {
+            sv.edicts.push(Edict::new(progdefs::ENTITY_FIELDS));
+        }
+        sv.clear_world([-4096.0; 3], [4096.0; 3]);
+        sv
+    }
+
+    /// Changes the number of client slots, only used while no map runs.
+    pub fn set_max_clients(&mut self, max_clients : usize) {
+        self.max_clients = max_clients;
+        self.clients = (0..max_clients).map(|_| Client::new()).collect();
+        self.edicts = (0..max_clients + 1).map(|_| Edict::new(self.entity_fields)).collect();
+        self.clear_world([-4096.0; 3], [4096.0; 3]);
+    }
+
+    /// Returns the number of entities in use.
+    pub fn active_edicts(&self) -> usize {
+        self.edicts.iter().filter(|e| !e.free).count()
+    }
+}
@@ -1,28 +1,710 @@
 #![warn(missing_docs)]
 
-use rquake_common::NativeSoundEngine;
+//! Sound engine: channel management and spatialization.
+//!
+//! Original source can be found in snd_dma.c
 
+use std::rc::Rc;
+
+use rquake_common::{GameResources, NativeSoundEngine, SoundFormat};
+use rquake_common::Vec3;
+use rquake_common::mathlib::{angle_vectors, dot_product, vector_normalize, vector_subtract};
+use rquake_fs::Sound;
+use snd_mix;
+use snd_mem::SoundCache;
+
+/// Total number of channels.
+pub const MAX_CHANNELS : usize = 128;
+/// Number of channels for dynamic sounds, the rest is used for static sounds.
+pub const MAX_DYNAMIC_CHANNELS : usize = 8;
+/// Number of ambient channels (water, sky, slime, lava).
+pub const NUM_AMBIENTS : usize = 4;
+
+/// Sounds further away than this are silent at attenuation 1.
+const SOUND_NOMINAL_CLIP_DIST : f32 = 1000.0;
+/// Sample rate of the Quake sound effects.
+const DEFAULT_RATE : u32 = 11025;
+/// Seconds of sound that are mixed in advance.
+const MIXAHEAD : f32 = 0.1;
+/// Default value of the volume cvar.
+pub const DEFAULT_VOLUME : f32 = 0.7;
+const AMBIENT_LEVEL : f32 = 0.3;
+const AMBIENT_FADE : f32 = 100.0;
+
+/// Sound effect samples prepared for mixing.
+pub struct SfxCache {
+    /// Sample rate of the data.
+    pub rate : u32,
+    /// Bytes per sample in the original file.
+    pub width : u32,
+    /// Sample where looping restarts, None if the sound isn't looped.
+    pub loop_start : Option<usize>,
+    /// Signed 16 bit mono samples.
+    pub data : Vec<i16>,
+}
+
+impl SfxCache {
+    /// Resamples a sound to the output rate and mixes all channels into one.
+    pub fn from_sound(sound : &Sound, rate : u32) -> SfxCache {
+        let sound = if sound.rate != rate { sound.resample(rate) } else { sound.clone() };
+        let channels = sound.channels as usize;
+        let data = sound.samples.chunks(channels)
+            .map(|frame| (frame.iter().map(|&s| s as i32).sum::<i32>() / channels as i32) as i16)
+            .collect();
+        SfxCache {
+            rate : rate,
+            width : sound.width,
+            loop_start : sound.loop_start,
+            data : data,
+        }
+    }
+}
+
+/// A channel playing a single sound.
+#[derive(Clone, Default)]
+pub struct Channel {
+    /// Sound that is played, None if the channel is free.
+    pub sfx : Option<Rc<SfxCache>>,
+    /// Volume of the left speaker, 0-255.
+    pub leftvol : i32,
+    /// Volume of the right speaker, 0-255.
+    pub rightvol : i32,
+    /// Sample position in 16.16 fixed point.
+    pub pos : u64,
+    /// Entity that started the sound.
+    pub entnum : i32,
+    /// Channel of the entity. 0 never overrides other sounds.
+    pub entchannel : i32,
+    /// Position of the sound source.
+    pub origin : Vec3,
+    /// Attenuation per unit of distance.
+    pub dist_mult : f32,
+    /// Volume before spatialization, 0-255.
+    pub master_vol : i32,
+}
+
+impl Channel {
+    /// Returns the number of samples left in the sound.
+    fn remaining(&self) -> usize {
+        match self.sfx {
+            Some(ref sfx) => sfx.data.len().saturating_sub((self.pos >> 16) as usize),
+            None => 0,
+        }
+    }
+}
+
+/// Ring buffer the mixer writes to and native sound engines copy from.
+pub struct DmaBuffer {
+    /// Output sample rate.
+    pub rate : u32,
+    /// Number of output channels, 1 or 2.
+    pub channels : u32,
+    /// Interleaved signed 16 bit samples.
+    pub buffer : Vec<i16>,
+}
+
+impl DmaBuffer {
+    /// Creates a silent ring buffer in the format of the sound device.
+    pub fn new(format : &SoundFormat) -> DmaBuffer {
+        DmaBuffer {
+            rate : format.rate,
+            channels : format.channels,
+            buffer : vec![0; format.frames * format.channels as usize],
+        }
+    }
+
+    /// Returns the size of the buffer in sample frames.
+    pub fn frames(&self) -> usize {
+        self.buffer.len() / self.channels as usize
+    }
+}
+
+/// Music track that is mixed together with the sound effects.
+pub struct MusicChannel {
+    /// Stereo samples in the output rate.
+    pub samples : Vec<i16>,
+    /// Sample frame that is played next.
+    pub pos : usize,
+    /// Restart at the beginning when the track ends.
+    pub looping : bool,
+    /// Paused tracks don't advance.
+    pub paused : bool,
+}
+
+impl MusicChannel {
+    /// Returns true if a non looping track has ended.
+    pub fn finished(&self) -> bool {
+        !self.looping && self.pos * 2 >= self.samples.len()
+    }
+}
+
+/// Position and orientation of the listener.
+struct Listener {
+    origin : Vec3,
+    right : Vec3,
+}
+
+/// Portable sound engine. Mixes all channels into a ring buffer.
 pub struct SoundEngine {
     native_snd : Box<NativeSoundEngine>,
+    channels : Vec<Channel>,
+    /// Channels in use: ambients, dynamic channels and static channels.
+    total_channels : usize,
+    /// Ring buffer in the format of the device, None if there is no device.
+    dma : Option<DmaBuffer>,
+    /// Sample frames already mixed.
+    painted_time : u64,
+    /// Number of times the device played the whole ring buffer.
+    buffers : u64,
+    /// Last play position of the device.
+    old_position : usize,
+    listener : Listener,
+    view_entity : i32,
+    ambient_sfx : [Option<Rc<SfxCache>>; NUM_AMBIENTS],
+    ambient_levels : [u8; NUM_AMBIENTS],
+    cache : SoundCache,
+    music : Option<MusicChannel>,
+    volume : f32,
+    music_volume : f32,
+    /// Entity number for sounds started by the play command.
+    play_hash : i32,
 }
 
 impl SoundEngine {
-    /// Creates a sound eninge. Only one of those should exist.
+    /// Creates a sound engine. Only one of those should exist.
     pub fn new(native_snd : Box<NativeSoundEngine>) -> SoundEngine {
-        SoundEngine{ native_snd : native_snd, }
+        SoundEngine {
+            native_snd : native_snd,
+            channels : vec![Channel::default(); MAX_CHANNELS],
+            total_channels : MAX_DYNAMIC_CHANNELS + NUM_AMBIENTS,
+            dma : None,
+            painted_time : 0,
+            buffers : 0,
+            old_position : 0,
+            listener : Listener { origin : [0.0; 3], right : [0.0, -1.0, 0.0] },
+            view_entity : 0,
+            ambient_sfx : [None, None, None, None],
+            ambient_levels : [0; NUM_AMBIENTS],
+            cache : SoundCache::new(),
+            music : None,
+            volume : DEFAULT_VOLUME,
+            music_volume : 1.0,
+            play_hash : 345,
+        }
     }
 
     /// Initializes the sound engine.
     pub fn init(&mut self) {
         self.native_snd.init();
+        match self.native_snd.get_format() {
+            Some(format) if format.bits != 16 => {
+                // the mixer only writes 16 bit samples
+                println!("Sound: {} bit devices aren't supported, sound disabled.", format.bits);
+                self.native_snd.shutdown();
+            },
+            Some(format) => {
+                println!("Sound: {} Hz, {} channels, {} bits", format.rate, format.channels, format.bits);
+                self.dma = Some(DmaBuffer::new(&format));
+            },
+            None => println!("No sound device available, sound disabled."),
+        }
+    }
+
+    /// Returns the ring buffer with the mixed samples.
+    pub fn dma_buffer(&self) -> Option<&DmaBuffer> {
+        self.dma.as_ref()
+    }
+
+    /// Returns the sample rate sounds should be resampled to.
+    pub fn output_rate(&self) -> u32 {
+        self.dma.as_ref().map_or(DEFAULT_RATE, |dma| dma.rate)
     }
 
-    pub fn update(&mut self) {
-        
+    /// Returns the number of sample frames mixed so far.
+    pub fn painted_time(&self) -> u64 {
+        self.painted_time
+    }
+
+    /// Sets the entity the listener is attached to. Its sounds are never spatialized.
+    pub fn set_view_entity(&mut self, entnum : i32) {
+        self.view_entity = entnum;
+    }
+
+    /// Moves the listener. Angles are used for stereo panning.
+    pub fn set_listener(&mut self, origin : Vec3, angles : Vec3) {
+        let (_, right, _) = angle_vectors(&angles);
+        self.listener = Listener { origin : origin, right : right };
+    }
+
+    /// Sets the looped sound of an ambient channel.
+    pub fn set_ambient_sound(&mut self, index : usize, sfx : Option<Rc<SfxCache>>) {
+        self.ambient_sfx[index] = sfx;
+    }
+
+    /// Sets the ambient sound levels of the leaf the listener is in.
+    pub fn set_ambient_levels(&mut self, levels : [u8; NUM_AMBIENTS]) {
+        self.ambient_levels = levels;
+    }
+
+    /// Sets the volume of sound effects and music, 0 to 1.
+    pub fn set_volume(&mut self, volume : f32, music_volume : f32) {
+        self.volume = volume;
+        self.music_volume = music_volume;
+    }
+
+    /// Starts playing a music track, replacing the current one.
+    pub fn start_music(&mut self, sound : &Sound, looping : bool) {
+        let rate = self.output_rate();
+        let sound = if sound.rate != rate { sound.resample(rate) } else { sound.clone() };
+        let channels = sound.channels as usize;
+        let mut samples = Vec::with_capacity(sound.length * 2);
+        for frame in sound.samples.chunks(channels) {
+            samples.push(frame[0]);
+            samples.push(if channels > 1 { frame[1] } else { frame[0] });
+        }
+        self.music = Some(MusicChannel {
+            samples : samples,
+            pos : 0,
+            looping : looping,
+            paused : false,
+        });
+    }
+
+    /// Stops the music.
+    pub fn stop_music(&mut self) {
+        self.music = None;
+    }
+
+    /// Pauses or resumes the music.
+    pub fn pause_music(&mut self, paused : bool) {
+        if let Some(ref mut music) = self.music {
+            music.paused = paused;
+        }
+    }
+
+    /// Returns true if a music track is playing or paused.
+    pub fn is_music_active(&self) -> bool {
+        self.music.is_some()
+    }
+
+    /// Registers a sound, the samples are loaded when it's played the first time.
+    /// Returns the index of the sound or None if there are too many sounds.
+    pub fn precache_sound(&mut self, name : &str) -> Option<usize> {
+        self.cache.find_name(name)
+    }
+
+    /// Returns the samples of a registered sound, loading them if necessary.
+    pub fn load_sound(&mut self, index : usize, game_res : &mut GameResources) -> Option<Rc<SfxCache>> {
+        let rate = self.output_rate();
+        self.cache.load(index, game_res, rate)
+    }
+
+    /// Releases all sounds, e.g. on map change.
+    pub fn clear_cache(&mut self) {
+        self.cache.clear();
+    }
+
+    /// Handles the soundlist command.
+    pub fn soundlist_cmd(&self) {
+        self.cache.print_list();
+    }
+
+    /// Handles the play command: plays sounds at full volume.
+    pub fn play_cmd(&mut self, args : &[String], game_res : &mut GameResources) {
+        for name in args.iter().skip(1) {
+            self.play_local(name, 1.0, game_res);
+        }
+    }
+
+    /// Handles the playvol command: plays sounds given as name volume pairs.
+    pub fn playvol_cmd(&mut self, args : &[String], game_res : &mut GameResources) {
+        for pair in args[1..].chunks(2) {
+            let volume = pair.get(1).and_then(|v| v.parse().ok()).unwrap_or(1.0);
+            self.play_local(&pair[0], volume, game_res);
+        }
+    }
+
+    /// Plays a sound at the position of the listener.
+    fn play_local(&mut self, name : &str, volume : f32, game_res : &mut GameResources) {
+        let name = if name.contains('.') { name.to_string() } else { format!("{}.wav", name) };
+        let rate = self.output_rate();
+        if let Some(sfx) = self.cache.load_by_name(&name, game_res, rate) {
+            let origin = self.listener.origin;
+            let entnum = self.play_hash;
+            self.play_hash += 1;
+            self.start_sound(entnum, 0, &sfx, origin, volume, 1.0);
+        }
+    }
+
+    /// Starts a sound on an entity channel.
+    pub fn start_sound(&mut self, entnum : i32, entchannel : i32, sfx : &Rc<SfxCache>, origin : Vec3, volume : f32, attenuation : f32) {
+        let index = match self.pick_channel(entnum, entchannel) {
+            Some(index) => index,
+            None => return,
+        };
+
+        self.channels[index] = Channel {
+            sfx : None,
+            origin : origin,
+            dist_mult : attenuation / SOUND_NOMINAL_CLIP_DIST,
+            master_vol : (volume * 255.0) as i32,
+            entnum : entnum,
+            entchannel : entchannel,
+            leftvol : 0,
+            rightvol : 0,
+            pos : 0,
+        };
+        self.spatialize(index);
+        if self.channels[index].leftvol == 0 && self.channels[index].rightvol == 0 {
+            return;     // not audible at all
+        }
+        self.channels[index].sfx = Some(sfx.clone());
+    }
+
+    /// Returns the number of channels that are playing a sound.
+    pub fn playing_channels(&self) -> usize {
+        self.channels[..self.total_channels].iter().filter(|ch| ch.sfx.is_some()).count()
+    }
+
+    /// Stops the sound on an entity channel.
+    pub fn stop_sound(&mut self, entnum : i32, entchannel : i32) {
+        for ch in self.channels[..MAX_DYNAMIC_CHANNELS + NUM_AMBIENTS].iter_mut() {
+            if ch.entnum == entnum && ch.entchannel == entchannel {
+                ch.sfx = None;
+                return;
+            }
+        }
+    }
+
+    /// Starts a looped sound that plays until all sounds are stopped. The
+    /// volume goes from 0 to 255.
+    pub fn static_sound(&mut self, sfx : &Rc<SfxCache>, origin : Vec3, volume : f32, attenuation : f32) {
+        if self.total_channels == MAX_CHANNELS {
+            println!("total_channels == MAX_CHANNELS");
+            return;
+        }
+        if sfx.loop_start.is_none() {
+            println!("Sound not looped");
+            return;
+        }
+
+        let index = self.total_channels;
+        self.total_channels += 1;
+        self.channels[index] = Channel {
+            sfx : Some(sfx.clone()),
+            origin : origin,
+            master_vol : volume as i32,
+            dist_mult : attenuation / SOUND_NOMINAL_CLIP_DIST,
+            .. Channel::default()
+        };
+        self.spatialize(index);
+    }
+
+    /// Stops all sounds and removes the static sounds.
+    pub fn stop_all_sounds(&mut self) {
+        self.total_channels = MAX_DYNAMIC_CHANNELS + NUM_AMBIENTS;
+        for ch in self.channels.iter_mut() {
+            *ch = Channel::default();
+        }
+        if let Some(ref mut dma) = self.dma {
+            for sample in dma.buffer.iter_mut() {
+                *sample = 0;
+            }
+            let frames = dma.frames();
+            self.native_snd.write_samples(0, &dma.buffer[..frames * dma.channels as usize]);
+        }
+    }
+
+    /// Updates the channel volumes, mixes new samples into the ring buffer
+    /// and passes them on to the sound device.
+    pub fn update(&mut self, timestep : f32) {
+        if self.dma.is_none() {
+            return;
+        }
+        self.native_snd.update(timestep);
+        self.update_ambient_sounds(timestep);
+
+        for index in NUM_AMBIENTS..self.total_channels {
+            if self.channels[index].sfx.is_some() {
+                self.spatialize(index);
+            }
+        }
+
+        let sound_time = self.get_sound_time();
+        let dma = self.dma.as_mut().unwrap();
+        // never fall behind what has been played already
+        if self.painted_time < sound_time {
+            self.painted_time = sound_time;
+        }
+        let mut end_time = sound_time + (MIXAHEAD * dma.rate as f32) as u64;
+        // don't overwrite samples that haven't been played yet
+        end_time = end_time.min(sound_time + dma.frames() as u64);
+        if end_time <= self.painted_time {
+            return;
+        }
+
+        let count = (end_time - self.painted_time) as usize;
+        snd_mix::paint_channels(&mut self.channels[..self.total_channels], self.music.as_mut(), dma,
+                                self.painted_time, count,
+                                (self.volume * 256.0) as i32, (self.music_volume * 256.0) as i32);
+        if self.music.as_ref().map_or(false, |music| music.finished()) {
+            self.music = None;
+        }
+
+        // copy the new samples to the device, split where the ring buffer wraps
+        let frames = dma.frames();
+        let channels = dma.channels as usize;
+        let mut start = (self.painted_time % frames as u64) as usize;
+        let mut left = count;
+        while left > 0 {
+            let n = left.min(frames - start);
+            self.native_snd.write_samples(start, &dma.buffer[start * channels..(start + n) * channels]);
+            start = 0;
+            left -= n;
+        }
+        self.painted_time = end_time;
     }
 
     /// Shuts down any sound processing.
     pub fn shutdown(&mut self) {
         self.native_snd.shutdown();
-    }    
-}
\ No newline at end of file
+    }
+
+    /// Returns the number of sample frames played by the device.
+    fn get_sound_time(&mut self) -> u64 {
+        let frames = match self.dma {
+            Some(ref dma) => dma.frames() as u64,
+            None => return 0,
+        };
+        let position = self.native_snd.get_dma_position();
+        // the device wrapped around since the last update
+        if position < self.old_position {
+            self.buffers += 1;
+        }
+        self.old_position = position;
+        self.buffers * frames + position as u64
+    }
+
+    /// Picks a dynamic channel for a new sound. Returns None if all channels
+    /// are busy with sounds that shouldn't be interrupted.
+    fn pick_channel(&self, entnum : i32, entchannel : i32) -> Option<usize> {
+        let mut first_to_die = None;
+        let mut life_left = usize::max_value();
+
+        for index in NUM_AMBIENTS..NUM_AMBIENTS + MAX_DYNAMIC_CHANNELS {
+            let ch = &self.channels[index];
+            // channel 0 never overrides, -1 overrides any channel of the entity
+            if entchannel != 0 && ch.entnum == entnum && (ch.entchannel == entchannel || entchannel == -1) {
+                return Some(index);
+            }
+
+            // don't let monster sounds override player sounds
+            if ch.entnum == self.view_entity && entnum != self.view_entity && ch.sfx.is_some() {
+                continue;
+            }
+
+            if ch.remaining() < life_left {
+                life_left = ch.remaining();
+                first_to_die = Some(index);
+            }
+        }
+
+        first_to_die
+    }
+
+    /// Calculates the left and right volume of a channel.
+    fn spatialize(&mut self, index : usize) {
+        let stereo = self.dma.as_ref().map_or(true, |dma| dma.channels == 2);
+        let listener_origin = self.listener.origin;
+        let listener_right = self.listener.right;
+        let view_entity = self.view_entity;
+        let ch = &mut self.channels[index];
+
+        // anything coming from the view entity will always be full volume
+        if ch.entnum == view_entity {
+            ch.leftvol = ch.master_vol;
+            ch.rightvol = ch.master_vol;
+            return;
+        }
+
+        let mut source_vec = vector_subtract(&ch.origin, &listener_origin);
+        let dist = vector_normalize(&mut source_vec) * ch.dist_mult;
+        let dot = dot_product(&listener_right, &source_vec);
+
+        let (lscale, rscale) = if stereo { (1.0 - dot, 1.0 + dot) } else { (1.0, 1.0) };
+        let vol = ch.master_vol as f32 * (1.0 - dist);
+        ch.rightvol = ((vol * rscale) as i32).max(0);
+        ch.leftvol = ((vol * lscale) as i32).max(0);
+    }
+
+    /// Fades the ambient channels towards the levels of the current leaf.
+    fn update_ambient_sounds(&mut self, timestep : f32) {
+        for i in 0..NUM_AMBIENTS {
+            let ch = &mut self.channels[i];
+            ch.sfx = self.ambient_sfx[i].clone();
+
+            let level = AMBIENT_LEVEL * self.ambient_levels[i] as f32;
+            let level = if level < 8.0 { 0 } else { level as i32 };
+            let fade = (timestep * AMBIENT_FADE) as i32;
+
+            // don't adjust volume too fast
+            if ch.master_vol < level {
+                ch.master_vol = (ch.master_vol + fade).min(level);
+            } else if ch.master_vol > level {
+                ch.master_vol = (ch.master_vol - fade).max(level);
+            }
+            ch.leftvol = ch.master_vol;
+            ch.rightvol = ch.master_vol;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use snd_wave::WaveFileSoundEngine;
+
+    /// Sound device that only counts played samples.
+    struct NullSoundEngine {
+        position : f32,
+        bits : u32,
+    }
+
+    impl NativeSoundEngine for NullSoundEngine {
+        fn init(&mut self) {}
+        fn shutdown(&mut self) {}
+        fn get_format(&self) -> Option<SoundFormat> {
+            Some(SoundFormat { rate : DEFAULT_RATE, channels : 2, bits : self.bits, frames : 0x4000 })
+        }
+        fn get_dma_position(&mut self) -> usize {
+            self.position as usize % 0x4000
+        }
+        fn write_samples(&mut self, _offset : usize, _samples : &[i16]) {}
+        fn update(&mut self, timestep : f32) {
+            self.position += timestep * DEFAULT_RATE as f32;
+        }
+    }
+
+    fn create_engine() -> SoundEngine {
+        let mut snd = SoundEngine::new(Box::new(NullSoundEngine { position : 0.0, bits : 16 }));
+        snd.init();
+        snd
+    }
+
+    fn constant_sfx(len : usize, loop_start : Option<usize>) -> Rc<SfxCache> {
+        Rc::new(SfxCache { rate : DEFAULT_RATE, width : 2, loop_start : loop_start, data : vec![1000; len] })
+    }
+
+    #[test]
+    fn channel_override() {
+        let mut snd = create_engine();
+        let sfx = constant_sfx(100, None);
+        snd.start_sound(1, 1, &sfx, [0.0; 3], 1.0, 1.0);
+        snd.start_sound(2, 1, &sfx, [0.0; 3], 1.0, 1.0);
+        snd.start_sound(1, 1, &sfx, [0.0; 3], 1.0, 1.0);
+        let busy = snd.channels.iter().filter(|ch| ch.sfx.is_some()).count();
+        assert_eq!(busy, 2);
+
+        // channel 0 never overrides
+        snd.start_sound(1, 0, &sfx, [0.0; 3], 1.0, 1.0);
+        snd.start_sound(1, 0, &sfx, [0.0; 3], 1.0, 1.0);
+        let busy = snd.channels.iter().filter(|ch| ch.sfx.is_some()).count();
+        assert_eq!(busy, 4);
+    }
+
+    #[test]
+    fn stereo_panning() {
+        let mut snd = create_engine();
+        snd.set_view_entity(1);
+        snd.set_listener([0.0; 3], [0.0, 90.0, 0.0]);
+        let sfx = constant_sfx(100, None);
+        // listener looks along +y, so +x is to the right
+        snd.start_sound(2, 1, &sfx, [100.0, 0.0, 0.0], 1.0, 1.0);
+        let ch = &snd.channels[NUM_AMBIENTS];
+        assert!(ch.rightvol > ch.leftvol);
+        assert_eq!(ch.leftvol, 0);
+
+        // too far away
+        snd.start_sound(3, 1, &sfx, [2000.0, 0.0, 0.0], 1.0, 1.0);
+        assert_eq!(snd.channels.iter().filter(|ch| ch.sfx.is_some()).count(), 1);
+    }
+
+    #[test]
+    fn mix_into_ring_buffer() {
+        let mut snd = create_engine();
+        snd.set_view_entity(1);
+        let sfx = Rc::new(SfxCache { rate : DEFAULT_RATE * 2, width : 2, loop_start : None, data : vec![1000; 200] });
+        snd.start_sound(1, 1, &sfx, [0.0; 3], 1.0, 0.0);
+        snd.update(0.0);
+        assert_eq!(snd.painted_time(), (MIXAHEAD * DEFAULT_RATE as f32) as u64);
+
+        // 200 samples at twice the output rate last for 100 frames
+        let dma = snd.dma_buffer().unwrap();
+        let expected = ((1000 * 255 >> 8) * (DEFAULT_VOLUME * 256.0) as i32 >> 8) as i16;
+        assert_eq!(dma.buffer[0], expected);
+        assert_eq!(dma.buffer[1], expected);
+        assert_eq!(dma.buffer[99 * 2], expected);
+        assert_eq!(dma.buffer[100 * 2], 0);
+        assert!(snd.channels[NUM_AMBIENTS].sfx.is_none());
+
+        // the mixer can't feed 8 bit devices
+        let mut snd = SoundEngine::new(Box::new(NullSoundEngine { position : 0.0, bits : 8 }));
+        snd.init();
+        assert!(snd.dma_buffer().is_none());
+    }
+
+    #[test]
+    fn write_to_wave_file() {
+        let filename = env::temp_dir().join("rquake_snd_test.wav");
+        let filename = filename.to_str().unwrap();
+        let mut snd = SoundEngine::new(Box::new(WaveFileSoundEngine::new(filename, DEFAULT_RATE, 2)));
+        snd.init();
+        snd.set_view_entity(1);
+        snd.start_sound(1, 1, &constant_sfx(2000, None), [0.0; 3], 1.0, 0.0);
+        for _ in 0..10 {
+            snd.update(0.1);
+        }
+        snd.shutdown();
+
+        // 44 bytes header + 1 second of 16 bit stereo samples
+        assert_eq!(fs::metadata(filename).unwrap().len(), 44 + DEFAULT_RATE as u64 * 4);
+        let _ = fs::remove_file(filename);
+    }
+
+    #[test]
+    fn mix_music() {
+        let mut snd = create_engine();
+        let track = Sound { rate : DEFAULT_RATE, width : 2, channels : 1, loop_start : None, length : 10, samples : vec![256; 10] };
+        snd.start_music(&track, false);
+        snd.set_volume(1.0, 0.5);
+        snd.update(0.0);
+        {
+            let dma = snd.dma_buffer().unwrap();
+            assert_eq!(dma.buffer[0], 128);
+            assert_eq!(dma.buffer[19], 128);
+            assert_eq!(dma.buffer[20], 0);
+        }
+        assert!(!snd.is_music_active());
+
+        snd.start_music(&track, true);
+        snd.pause_music(true);
+        snd.update(0.5);
+        assert!(snd.is_music_active());
+        assert_eq!(snd.dma_buffer().unwrap().buffer[6000], 0);
+    }
+
+    #[test]
+    fn static_sounds_loop() {
+        let mut snd = create_engine();
+        snd.static_sound(&constant_sfx(10, None), [0.0; 3], 255.0, 1.0);
+        assert_eq!(snd.total_channels, MAX_DYNAMIC_CHANNELS + NUM_AMBIENTS);
+        snd.static_sound(&constant_sfx(10, Some(0)), [0.0; 3], 255.0, 1.0);
+        snd.update(0.0);
+        let dma = snd.dma_buffer().unwrap();
+        assert!(dma.buffer[500] != 0);
+        snd.stop_all_sounds();
+        assert_eq!(snd.total_channels, MAX_DYNAMIC_CHANNELS + NUM_AMBIENTS);
+    }
+}
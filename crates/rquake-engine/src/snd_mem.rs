@@ -0,0 +1,162 @@
+#![warn(missing_docs)]
+
+//! Cache for sound effects that are loaded on demand.
+//!
+//! Original source can be found in snd_mem.c and snd_dma.c
+
+use std::io::Cursor;
+use std::rc::Rc;
+
+use rquake_common::GameResources;
+use rquake_fs::Sound;
+use snd::SfxCache;
+
+/// Maximum number of different sounds.
+pub const MAX_SFX : usize = 512;
+
+/// A known sound, the samples are loaded the first time it is played.
+struct Sfx {
+    name : String,
+    cache : Option<Rc<SfxCache>>,
+}
+
+/// Maps sound names to loaded sounds. Sounds are identified by the
+/// index of their name, which is also used as precache index on the network.
+pub struct SoundCache {
+    sounds : Vec<Sfx>,
+}
+
+impl SoundCache {
+    /// Creates an empty cache.
+    pub fn new() -> SoundCache {
+        SoundCache {
+            sounds : Vec::new(),
+        }
+    }
+
+    /// Returns the index of a sound, adding it if it's unknown.
+    /// Names are relative to the sound directory, e.g. weapons/rocket1i.wav.
+    pub fn find_name(&mut self, name : &str) -> Option<usize> {
+        if let Some(index) = self.sounds.iter().position(|sfx| sfx.name == name) {
+            return Some(index);
+        }
+        if self.sounds.len() == MAX_SFX {
+            println!("S_FindName: out of sfx_t");
+            return None;
+        }
+        self.sounds.push(Sfx { name : name.to_string(), cache : None });
+        Some(self.sounds.len() - 1)
+    }
+
+    /// Returns the samples of a sound, loading them if necessary.
+    pub fn load(&mut self, index : usize, game_res : &mut GameResources, rate : u32) -> Option<Rc<SfxCache>> {
+        let sfx = match self.sounds.get_mut(index) {
+            Some(sfx) => sfx,
+            None => return None,
+        };
+        if let Some(ref cache) = sfx.cache {
+            return Some(cache.clone());
+        }
+
+        let data = match game_res.load_file(&format!("sound/{}", sfx.name)) {
+            Some(data) => data,
+            None => {
+                println!("Couldn't load sound/{}", sfx.name);
+                return None;
+            },
+        };
+        match Sound::read(&mut Cursor::new(data)) {
+            Ok(sound) => {
+                let cache = Rc::new(SfxCache::from_sound(&sound, rate));
+                sfx.cache = Some(cache.clone());
+                Some(cache)
+            },
+            Err(err) => {
+                println!("Failed to read sound/{}: {}", sfx.name, err);
+                None
+            },
+        }
+    }
+
+    /// Returns the loaded samples of a sound by name.
+    pub fn load_by_name(&mut self, name : &str, game_res : &mut GameResources, rate : u32) -> Option<Rc<SfxCache>> {
+        match self.find_name(name) {
+            Some(index) => self.load(index, game_res, rate),
+            None => None,
+        }
+    }
+
+    /// Forgets all sounds, e.g. when a new map is loaded. Sounds that are still
+    /// playing are freed when their channel is done.
+    pub fn clear(&mut self) {
+        self.sounds.clear();
+    }
+
+    /// Returns the number of bytes used by loaded samples.
+    pub fn memory_used(&self) -> usize {
+        self.sounds.iter()
+            .filter_map(|sfx| sfx.cache.as_ref())
+            .map(|cache| cache.data.len() * 2)
+            .sum()
+    }
+
+    /// Prints all loaded sounds and their size.
+    pub fn print_list(&self) {
+        for sfx in &self.sounds {
+            if let Some(ref cache) = sfx.cache {
+                let looped = if cache.loop_start.is_some() { "L" } else { " " };
+                println!("{}({:2}b) {:6} : {}", looped, cache.width * 8, cache.data.len() * 2, sfx.name);
+            }
+        }
+        println!("Total resident: {}", self.memory_used());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct TestResources {
+        files : HashMap<String, Vec<u8>>,
+        loaded : usize,
+    }
+
+    impl GameResources for TestResources {
+        fn add_game_directory(&mut self, _path : &str) {}
+        fn load_file(&mut self, name : &str) -> Option<Vec<u8>> {
+            self.loaded += 1;
+            self.files.get(name).cloned()
+        }
+    }
+
+    fn wave_file(samples : &[u8]) -> Vec<u8> {
+        let mut data = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0\x11\x2b\0\0\x11\x2b\0\0\x01\0\x08\0data".to_vec();
+        data.extend_from_slice(&[samples.len() as u8, 0, 0, 0]);
+        data.extend_from_slice(samples);
+        data
+    }
+
+    #[test]
+    fn load_on_demand() {
+        let mut files = HashMap::new();
+        files.insert("sound/weapons/rocket1i.wav".to_string(), wave_file(&[0x80; 10]));
+        let mut res = TestResources { files : files, loaded : 0 };
+        let mut cache = SoundCache::new();
+
+        let index = cache.find_name("weapons/rocket1i.wav").unwrap();
+        assert_eq!(cache.find_name("weapons/rocket1i.wav"), Some(index));
+        assert_eq!(res.loaded, 0);
+        assert_eq!(cache.memory_used(), 0);
+
+        let sfx = cache.load(index, &mut res, 22050).unwrap();
+        assert_eq!(sfx.data.len(), 20);
+        cache.load(index, &mut res, 22050).unwrap();
+        assert_eq!(res.loaded, 1);
+        assert_eq!(cache.memory_used(), 40);
+
+        assert!(cache.load_by_name("missing.wav", &mut res, 22050).is_none());
+        cache.clear();
+        assert_eq!(cache.memory_used(), 0);
+    }
+}
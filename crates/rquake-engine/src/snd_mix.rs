@@ -0,0 +1,94 @@
+#![warn(missing_docs)]
+
+//! Mixes the sound channels into the output ring buffer.
+//!
+//! Original source can be found in snd_mix.c
+
+use snd::{Channel, DmaBuffer, MusicChannel};
+
+/// Mixes count sample frames of all channels and the music and writes them
+/// to the ring buffer, starting at frame painted_time. Volumes are in 1/256 units.
+pub fn paint_channels(channels : &mut [Channel], music : Option<&mut MusicChannel>, dma : &mut DmaBuffer,
+                      painted_time : u64, count : usize, volume : i32, music_volume : i32) {
+    let mut paintbuffer = vec![(0i32, 0i32); count];
+    for ch in channels.iter_mut() {
+        paint_channel(ch, dma.rate, &mut paintbuffer);
+    }
+    for frame in paintbuffer.iter_mut() {
+        frame.0 = (frame.0 * volume) >> 8;
+        frame.1 = (frame.1 * volume) >> 8;
+    }
+    if let Some(music) = music {
+        paint_music(music, &mut paintbuffer, music_volume);
+    }
+    transfer_paint_buffer(&paintbuffer, dma, painted_time);
+}
+
+/// Adds the stereo samples of the music track to the paint buffer.
+fn paint_music(music : &mut MusicChannel, paintbuffer : &mut [(i32, i32)], volume : i32) {
+    if music.paused {
+        return;
+    }
+    let frames = music.samples.len() / 2;
+    for frame in paintbuffer.iter_mut() {
+        if music.pos >= frames {
+            if !music.looping || frames == 0 {
+                return;
+            }
+            music.pos = 0;
+        }
+        frame.0 += (music.samples[music.pos * 2] as i32 * volume) >> 8;
+        frame.1 += (music.samples[music.pos * 2 + 1] as i32 * volume) >> 8;
+        music.pos += 1;
+    }
+}
+
+/// Adds the samples of one channel to the paint buffer. The sound is resampled
+/// to the output rate, the channel is freed when a non looping sound ends.
+fn paint_channel(ch : &mut Channel, output_rate : u32, paintbuffer : &mut [(i32, i32)]) {
+    let sfx = match ch.sfx {
+        Some(ref sfx) => sfx.clone(),
+        None => return,
+    };
+    let length = sfx.data.len() as u64;
+    let step = ((sfx.rate as u64) << 16) / output_rate as u64;
+    let leftvol = ch.leftvol.min(255);
+    let rightvol = ch.rightvol.min(255);
+
+    for frame in paintbuffer.iter_mut() {
+        while ch.pos >> 16 >= length {
+            match sfx.loop_start {
+                Some(start) if (start as u64) < length => {
+                    ch.pos -= (length - start as u64) << 16;
+                },
+                _ => {
+                    ch.sfx = None;
+                    return;
+                },
+            }
+        }
+
+        let sample = sfx.data[(ch.pos >> 16) as usize] as i32;
+        frame.0 += (sample * leftvol) >> 8;
+        frame.1 += (sample * rightvol) >> 8;
+        ch.pos += step;
+    }
+}
+
+/// Copies the paint buffer into the ring buffer.
+fn transfer_paint_buffer(paintbuffer : &[(i32, i32)], dma : &mut DmaBuffer, painted_time : u64) {
+    let frames = dma.frames() as u64;
+    let channels = dma.channels as usize;
+
+    for (i, &(left, right)) in paintbuffer.iter().enumerate() {
+        let index = ((painted_time + i as u64) % frames) as usize * channels;
+        dma.buffer[index] = clamp_sample(left);
+        if channels == 2 {
+            dma.buffer[index + 1] = clamp_sample(right);
+        }
+    }
+}
+
+fn clamp_sample(value : i32) -> i16 {
+    value.max(i16::min_value() as i32).min(i16::max_value() as i32) as i16
+}
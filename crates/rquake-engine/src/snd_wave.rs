@@ -0,0 +1,129 @@
+#![warn(missing_docs)]
+
+//! Sound device that writes everything it plays to a wave file.
+
+use std::fs::File;
+use std::io::BufWriter;
+
+use riff_wave::WaveWriter;
+use rquake_common::{NativeSoundEngine, SoundFormat};
+
+/// Sound device without sound card. The play position advances with the
+/// frame time and every played sample frame is appended to a wave file.
+pub struct WaveFileSoundEngine {
+    filename : String,
+    writer : Option<WaveWriter<BufWriter<File>>>,
+    format : SoundFormat,
+    buffer : Vec<i16>,
+    /// Sample frames written to the file.
+    played : u64,
+    /// Sample frames that should have been played by now.
+    time : f64,
+}
+
+impl WaveFileSoundEngine {
+    /// Creates a device with 16 bit samples. The file is created in init.
+    pub fn new(filename : &str, rate : u32, channels : u32) -> WaveFileSoundEngine {
+        let frames = 0x4000;
+        WaveFileSoundEngine {
+            filename : filename.to_string(),
+            writer : None,
+            format : SoundFormat {
+                rate : rate,
+                channels : channels,
+                bits : 16,
+                frames : frames,
+            },
+            buffer : vec![0; frames * channels as usize],
+            played : 0,
+            time : 0.0,
+        }
+    }
+}
+
+impl NativeSoundEngine for WaveFileSoundEngine {
+    fn init(&mut self) {
+        let file = match File::create(&self.filename) {
+            Ok(file) => file,
+            Err(err) => {
+                println!("Failed to create {}: {}", self.filename, err);
+                return;
+            },
+        };
+        match WaveWriter::new(self.format.channels as u16, self.format.rate, 16, BufWriter::new(file)) {
+            Ok(writer) => self.writer = Some(writer),
+            Err(err) => println!("Failed to write {}: {}", self.filename, err),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        // dropping the writer updates the header
+        self.writer = None;
+    }
+
+    fn get_format(&self) -> Option<SoundFormat> {
+        self.writer.as_ref().map(|_| self.format)
+    }
+
+    fn get_dma_position(&mut self) -> usize {
+        (self.played % self.format.frames as u64) as usize
+    }
+
+    fn write_samples(&mut self, offset : usize, samples : &[i16]) {
+        let start = offset * self.format.channels as usize;
+        self.buffer[start..start + samples.len()].copy_from_slice(samples);
+    }
+
+    fn update(&mut self, timestep : f32) {
+        self.time += timestep as f64 * self.format.rate as f64;
+        let writer = match self.writer {
+            Some(ref mut writer) => writer,
+            None => return,
+        };
+
+        let channels = self.format.channels as usize;
+        while self.played < self.time as u64 {
+            let start = (self.played % self.format.frames as u64) as usize * channels;
+            for &sample in &self.buffer[start..start + channels] {
+                if let Err(err) = writer.write_sample_i16(sample) {
+                    println!("Failed to write {}: {}", self.filename, err);
+                    self.writer = None;
+                    return;
+                }
+            }
+            self.played += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use riff_wave::WaveReader;
+
+    #[test]
+    fn write_played_samples() {
+        let filename = env::temp_dir().join("rquake_wavewriter_test.wav");
+        let filename = filename.to_str().unwrap();
+        let mut snd = WaveFileSoundEngine::new(filename, 1000, 2);
+        assert!(snd.get_format().is_none());
+        snd.init();
+        assert_eq!(snd.get_format().unwrap().frames, 0x4000);
+
+        snd.write_samples(0, &[1, -1, 2, -2]);
+        snd.update(0.0015);
+        assert_eq!(snd.get_dma_position(), 1);
+        snd.update(0.0015);
+        assert_eq!(snd.get_dma_position(), 3);
+        snd.shutdown();
+
+        let mut reader = WaveReader::new(File::open(filename).unwrap()).unwrap();
+        assert_eq!(reader.pcm_format.num_channels, 2);
+        let samples : Vec<i16> = (0..6).map(|_| reader.read_sample_i16().unwrap()).collect();
+        assert_eq!(samples, vec![1, -1, 2, -2, 0, 0]);
+        assert!(reader.read_sample_i16().is_err());
+        let _ = fs::remove_file(filename);
+    }
+}
@@ -0,0 +1,1068 @@
+#![warn(missing_docs)]
+
+//! Starting maps, connections of the network clients, their signon and
+//! the messages sent to them every frame.
+//!
+//! Original source can be found in sv_main.c, sv_user.c and host_cmd.c
+
+use std::rc::Rc;
+
+use rquake_common::mathlib::vector_add;
+use rquake_fs::{leaf_visible, parse_entities, BspFile};
+use cmd;
+use cvar::CvarList;
+use host_cmd::CommandParams;
+use model::BrushModel;
+use msg::{MsgReader, SizeBuf};
+use net::{Net, NetMessage, NetSocket, MAX_MSGLEN};
+use net_dgrm::MAX_DATAGRAM;
+use progdefs;
+use progs::{Edict, Progs};
+use protocol::{self, ClientData, ClientMessage, EntityState, EntityUpdate, ServerMessage, SoundStart};
+use server::{self, Client, Server};
+use sv_phys::PhysicsParams;
+
+/// Engine version reported to connecting clients.
+pub const VERSION : f32 = 1.09;
+
+/// Values of the game mode cvars when a map is started.
+#[derive(Clone, Copy, Debug)]
+pub struct SpawnParams {
+    /// Skill from 0 (easy) to 3 (nightmare).
+    pub skill : i32,
+    /// Deathmatch mode, 0 for cooperative or single player games.
+    pub deathmatch : f32,
+    /// Cooperative mode.
+    pub coop : f32,
+}
+
+impl SpawnParams {
+    /// Reads the game mode cvars, the skill is rounded and clamped.
+    pub fn new(cvars : &CvarList) -> SpawnParams {
+        let skill = (cvars.value("skill") + 0.5) as i32;
+        SpawnParams {
+            skill : skill.max(0).min(3),
+            deathmatch : cvars.value("deathmatch"),
+            coop : cvars.value("coop"),
+        }
+    }
+}
+
+/// Registers the game mode cvars.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("deathmatch", "0", false, false);
+    cvars.register("coop", "0", false, false);
+    cvars.register("teamplay", "0", false, true);
+    cvars.register("sv_aim", "0.93", false, false);
+    cvars.register("pausable", "1", false, false);
+}
+
+impl Server {
+    /// Starts a map. The models and sounds of the previous map are released,
+    /// connected clients are kept and get the new server info.
+    pub fn spawn_server(&mut self, name : &str, bsp : Rc<BspFile>, params : &SpawnParams, physics : &PhysicsParams, progs : &mut Progs) {
+        println!("SpawnServer: {}", name);
+        self.active = false;
+        self.name = name.to_string();
+        self.time = 1.0;
+        self.changelevel_issued = false;
+        self.loadgame = false;
+        self.paused = false;
+        self.program_error = false;
+        self.lastcheck = 0;
+        self.lastchecktime = 0.0;
+
+        // load the progs to get the entity field count
+        progs.reset(self);
+
+        // the world and its inline models are the first models
+        let models = BrushModel::from_bsp(&bsp);
+        self.model_precache = vec![String::new(), format!("maps/{}.bsp", name)];
+        self.model_precache.extend((1..models.len()).map(|i| format!("*{}", i)));
+        self.models = vec![None];
+        self.models.extend(models.into_iter().map(|model| Some(Rc::new(model))));
+        self.sound_precache = vec![String::new()];
+        self.lightstyles = vec![String::new(); server::MAX_LIGHTSTYLES];
+        self.datagram.clear();
+        self.reliable_datagram.clear();
+        self.signon.clear();
+        self.phs = bsp.calc_phs();
+        self.worldmodel = Some(bsp.clone());
+
+        // only the world and the client entities are left
+        self.edicts.truncate(self.max_clients + 1);
+        for e in &mut self.edicts {
+            *e = Edict::new(self.entity_fields);
+        }
+        let (mins, maxs) = match self.models.get(1) {
+            Some(&Some(ref world)) => (world.mins, world.maxs),
+            _ => ([-4096.0; 3], [4096.0; 3]),
+        };
+        self.clear_world(mins, maxs);
+
+        let model = self.strings.alloc(&self.model_precache[1]);
+        {
+            let v = &mut self.edicts[0].v;
+            v.set_int(progdefs::MODEL, model);
+            v.set_float(progdefs::MODELINDEX, 1.0);
+            v.set_float(progdefs::SOLID, server::SOLID_BSP);
+            v.set_float(progdefs::MOVETYPE, server::MOVETYPE_PUSH);
+        }
+        let mapname = self.strings.alloc(name);
+        self.globals.set_int(progdefs::G_MAPNAME, mapname);
+        self.globals.set_float(progdefs::G_DEATHMATCH, params.deathmatch);
+        self.globals.set_float(progdefs::G_COOP, params.coop);
+        self.globals.set_float(progdefs::G_SERVERFLAGS, self.serverflags);
+
+        match parse_entities(&bsp.entities) {
+            Ok(entities) => {
+                self.load_entities(&entities, params.skill, progs);
+            }
+            Err(err) => println!("Couldn't parse the entities of {}: {}", name, err),
+        }
+        self.active = true;
+
+        // run two frames to allow everything to settle
+        for _ in 0..2 {
+            self.physics(progs, physics);
+        }
+        self.create_baseline();
+
+        // send serverinfo to all connected clients
+        for client in 0..self.clients.len() {
+            if self.clients[client].active {
+                self.clients[client].spawned = false;
+                self.send_serverinfo(client);
+            }
+        }
+        println!("Server spawned.");
+    }
+
+    /// Stores the server flags and asks the progs for the parameters each
+    /// client takes to the next level.
+    pub fn save_spawn_parms(&mut self, progs : &mut Progs) {
+        self.serverflags = self.globals.float(progdefs::G_SERVERFLAGS);
+        for client in 0..self.clients.len() {
+            if !self.clients[client].active {
+                continue;
+            }
+            // call the progs to get the values that carry over
+            self.globals.set_int(progdefs::G_SELF, client as i32 + 1);
+            let set_change_parms = self.globals.int(progdefs::G_SETCHANGEPARMS);
+            self.execute(progs, set_change_parms);
+            for i in 0..server::NUM_SPAWN_PARMS {
+                self.clients[client].spawn_parms[i] = self.globals.float(progdefs::G_PARM1 + i);
+            }
+        }
+    }
+
+    /// Drops all clients and stops the map.
+    pub fn shutdown(&mut self, progs : &mut Progs, time : f64) {
+        if !self.active {
+            return;
+        }
+        self.active = false;
+        for client in 0..self.clients.len() {
+            if self.clients[client].active {
+                self.drop_client(client, progs, time);
+            }
+        }
+    }
+
+    /// Writes the baselines of the client entities and all entities with a
+    /// model to the signon message, updates are sent relative to them.
+    fn create_baseline(&mut self) {
+        for ent in 0..self.edicts.len() {
+            let e = &self.edicts[ent];
+            if e.free {
+                continue;
+            }
+            let client = ent > 0 && ent <= self.max_clients;
+            if !client && e.v.float(progdefs::MODELINDEX) == 0.0 {
+                continue;
+            }
+
+            // clients always use the player model and their own colormap
+            let baseline = EntityState {
+                origin : e.v.vector(progdefs::ORIGIN),
+                angles : e.v.vector(progdefs::ANGLES),
+                modelindex : if client {
+                    self.model_index("progs/player.mdl")
+                } else {
+                    self.model_index(self.strings.get(e.v.int(progdefs::MODEL)))
+                },
+                frame : e.v.float(progdefs::FRAME) as i32,
+                colormap : if client { ent as i32 } else { 0 },
+                skin : e.v.float(progdefs::SKIN) as i32,
+                effects : 0,
+            };
+            ServerMessage::SpawnBaseline { entity : ent as i32, baseline : baseline }.write(&mut self.signon);
+            self.edicts[ent].baseline = baseline;
+        }
+    }
+
+    /// Accepts new connections into free client slots.
+    pub fn check_for_new_clients(&mut self, net : &mut Net, progs : &mut Progs, time : f64) {
+        while let Some(mut socket) = net.check_new_connections(time) {
+            match self.clients.iter().position(|c| !c.active) {
+                Some(client) => self.connect_client(client, socket, progs, time),
+                None => {
+                    println!("SV_CheckForNewClients: no free clients");
+                    socket.close();
+                }
+            }
+        }
+    }
+
+    /// Initializes a client slot for a new connection and sends the
+    /// first signon message.
+    pub fn connect_client(&mut self, client : usize, socket : Box<NetSocket>, progs : &mut Progs, time : f64) {
+        println!("Client {} connected", socket.address());
+
+        let mut c = Client::new();
+        c.active = true;
+        c.name = "unconnected".to_string();
+        c.netconnection = Some(socket);
+        c.last_message = time;
+        c.connect_time = time;
+        self.clients[client] = c;
+
+        // call the progs to get default spawn parms for the new client
+        let set_new_parms = self.globals.int(progdefs::G_SETNEWPARMS);
+        self.execute(progs, set_new_parms);
+        for i in 0..server::NUM_SPAWN_PARMS {
+            self.clients[client].spawn_parms[i] = self.globals.float(progdefs::G_PARM1 + i);
+        }
+
+        self.send_serverinfo(client);
+    }
+
+    /// Sends the server info with the precache lists, the music and the
+    /// view entity.
+    fn send_serverinfo(&mut self, client : usize) {
+        let gametype = if self.globals.float(progdefs::G_DEATHMATCH) != 0.0 {
+            protocol::GAME_DEATHMATCH
+        } else {
+            protocol::GAME_COOP
+        };
+        let track = self.edicts[0].v.float(progdefs::SOUNDS) as i32;
+        let messages = [
+            ServerMessage::Print(format!("\u{2}\nVERSION {:.2} SERVER\n", VERSION)),
+            ServerMessage::ServerInfo {
+                protocol : protocol::PROTOCOL_VERSION,
+                maxclients : self.max_clients as i32,
+                gametype : gametype,
+                levelname : self.strings.get(self.edicts[0].v.int(progdefs::MESSAGE)).to_string(),
+                models : self.model_precache[1..].to_vec(),
+                sounds : self.sound_precache[1..].to_vec(),
+            },
+            ServerMessage::CdTrack { track : track, looptrack : track },
+            ServerMessage::SetView(client as i32 + 1),
+            ServerMessage::SignonNum(1),
+        ];
+        for message in &messages {
+            message.write(&mut self.clients[client].message);
+        }
+    }
+
+    /// Sends the last messages to a client, calls ClientDisconnect if it
+    /// was in the game and tells the other clients that it left.
+    pub fn drop_client(&mut self, client : usize, progs : &mut Progs, time : f64) {
+        {
+            let c = &mut self.clients[client];
+            if let Some(ref mut socket) = c.netconnection {
+                if socket.can_send_message(time) {
+                    ServerMessage::Disconnect.write(&mut c.message);
+                    socket.send_message(&c.message.data, time);
+                }
+            }
+        }
+
+        if self.clients[client].spawned {
+            // call the prog function for removing a client
+            let saveself = self.globals.int(progdefs::G_SELF);
+            self.globals.set_int(progdefs::G_SELF, client as i32 + 1);
+            let client_disconnect = self.globals.int(progdefs::G_CLIENTDISCONNECT);
+            self.execute(progs, client_disconnect);
+            self.globals.set_int(progdefs::G_SELF, saveself);
+        }
+        println!("Client {} removed", self.clients[client].name);
+
+        if let Some(mut socket) = self.clients[client].netconnection.take() {
+            socket.close();
+        }
+        self.clients[client] = Client::new();
+
+        // send notification to all other clients
+        let messages = [
+            ServerMessage::UpdateName { client : client as i32, name : String::new() },
+            ServerMessage::UpdateFrags { client : client as i32, frags : 0 },
+            ServerMessage::UpdateColors { client : client as i32, colors : 0 },
+        ];
+        for other in self.clients.iter_mut().filter(|c| c.active) {
+            for message in &messages {
+                message.write(&mut other.message);
+            }
+        }
+    }
+
+    /// Reads the messages of all clients. Clients are dropped when they
+    /// disconnect or send a bad message.
+    pub fn read_client_messages(&mut self, progs : &mut Progs, params : &CommandParams, time : f64) {
+        for client in 0..self.clients.len() {
+            if !self.clients[client].active {
+                continue;
+            }
+            if !self.read_client_message(client, progs, params, time) {
+                self.drop_client(client, progs, time);
+            }
+        }
+    }
+
+    /// Returns false if the client should be dropped.
+    fn read_client_message(&mut self, client : usize, progs : &mut Progs, params : &CommandParams, time : f64) -> bool {
+        loop {
+            let message = match self.clients[client].netconnection {
+                Some(ref mut socket) => socket.get_message(time),
+                None => return true,
+            };
+            let data = match message {
+                None => return true,
+                Some(NetMessage::Disconnected) => return false,
+                Some(NetMessage::Reliable(data)) | Some(NetMessage::Unreliable(data)) => data,
+            };
+            self.clients[client].last_message = time;
+
+            let mut msg = MsgReader::new(&data);
+            while let Some(message) = ClientMessage::read(&mut msg) {
+                match message {
+                    ClientMessage::Nop => {}
+                    ClientMessage::Disconnect => return false,
+                    ClientMessage::Move { time, ref cmd } => self.read_client_move(client, time, cmd),
+                    ClientMessage::StringCmd(ref text) => self.client_command(client, text, progs, params, time),
+                }
+            }
+            if msg.badread {
+                return false;
+            }
+        }
+    }
+
+    /// Executes a command sent by a client.
+    fn client_command(&mut self, client : usize, text : &str, progs : &mut Progs, params : &CommandParams, time : f64) {
+        let args = cmd::tokenize(text);
+        if args.is_empty() {
+            return;
+        }
+        match args[0].as_str() {
+            "prespawn" => self.prespawn_cmd(client),
+            "spawn" => self.spawn_cmd(client, progs),
+            "begin" => self.clients[client].spawned = true,
+            "name" if args.len() > 1 => self.set_client_name(client, &args[1..].join(" ")),
+            "color" if args.len() > 1 => {
+                let top = args[1].parse().unwrap_or(0);
+                let bottom = args.get(2).map_or(top, |bottom| bottom.parse().unwrap_or(0));
+                self.set_client_colors(client, top, bottom);
+            }
+            "say" | "say_team" => self.say(Some(client), &args[1..].join(" "), args[0] == "say_team", params),
+            "tell" if args.len() > 2 => self.tell(client, &args[1], &args[2..].join(" ")),
+            "status" => {
+                for line in self.status(&params.hostname, time).lines() {
+                    ServerMessage::Print(format!("{}\n", line)).write(&mut self.clients[client].message);
+                }
+            }
+            "kill" => self.kill(client, progs),
+            "ping" => {
+                let text = self.ping_times();
+                ServerMessage::Print(text).write(&mut self.clients[client].message);
+            }
+            "pause" => self.pause(client, params),
+            _ => println!("{} tried to {}", self.clients[client].name, text),
+        }
+    }
+
+    /// Sends the baselines and static entities.
+    fn prespawn_cmd(&mut self, client : usize) {
+        if self.clients[client].spawned {
+            println!("prespawn not valid -- allready spawned");
+            return;
+        }
+        self.clients[client].message.write(&self.signon.data);
+        ServerMessage::SignonNum(2).write(&mut self.clients[client].message);
+    }
+
+    /// Puts the client into the game and sends the state of the other clients.
+    fn spawn_cmd(&mut self, client : usize, progs : &mut Progs) {
+        if self.clients[client].spawned {
+            println!("Spawn not valid -- allready spawned");
+            return;
+        }
+
+        let ent = client + 1;
+        // loaded games are fully initialized already, they are paused until
+        // the client is in the game
+        if self.loadgame {
+            self.paused = false;
+        } else {
+            // set up the edict
+            let name = self.clients[client].name.clone();
+            let colors = self.clients[client].colors;
+            let netname = self.strings.alloc(&name);
+            {
+                let v = &mut self.edicts[ent].v;
+                v.clear();
+                v.set_float(progdefs::COLORMAP, ent as f32);
+                v.set_float(progdefs::TEAM, ((colors & 15) + 1) as f32);
+                v.set_int(progdefs::NETNAME, netname);
+            }
+
+            // copy spawn parms out of the client
+            for i in 0..server::NUM_SPAWN_PARMS {
+                self.globals.set_float(progdefs::G_PARM1 + i, self.clients[client].spawn_parms[i]);
+            }
+
+            // call the spawn function
+            self.globals.set_float(progdefs::G_TIME, self.time);
+            self.globals.set_int(progdefs::G_SELF, ent as i32);
+            let client_connect = self.globals.int(progdefs::G_CLIENTCONNECT);
+            self.execute(progs, client_connect);
+            println!("{} entered the game", name);
+            let put_client_in_server = self.globals.int(progdefs::G_PUTCLIENTINSERVER);
+            self.execute(progs, put_client_in_server);
+        }
+
+        // send time and the names, colors and frag counts of all clients
+        let mut messages = vec![ServerMessage::Time(self.time)];
+        for (i, other) in self.clients.iter().enumerate() {
+            messages.push(ServerMessage::UpdateName { client : i as i32, name : other.name.clone() });
+            messages.push(ServerMessage::UpdateFrags { client : i as i32, frags : self.edicts[i + 1].v.float(progdefs::FRAGS) as i32 });
+            messages.push(ServerMessage::UpdateColors { client : i as i32, colors : other.colors });
+        }
+
+        // send all current light styles
+        for (i, map) in self.lightstyles.iter().enumerate() {
+            messages.push(ServerMessage::LightStyle { style : i as i32, map : map.clone() });
+        }
+
+        // send some stats
+        let stats = [
+            (protocol::STAT_TOTALSECRETS, progdefs::G_TOTAL_SECRETS),
+            (protocol::STAT_TOTALMONSTERS, progdefs::G_TOTAL_MONSTERS),
+            (protocol::STAT_SECRETS, progdefs::G_FOUND_SECRETS),
+            (protocol::STAT_MONSTERS, progdefs::G_KILLED_MONSTERS),
+        ];
+        for &(stat, global) in &stats {
+            messages.push(ServerMessage::UpdateStat { stat : stat, value : self.globals.float(global) as i32 });
+        }
+
+        // send a fixangle, never send a roll angle
+        let mut angles = self.edicts[ent].v.vector(progdefs::ANGLES);
+        angles[2] = 0.0;
+        messages.push(ServerMessage::SetAngle(angles));
+
+        let mut buf = SizeBuf::new(MAX_MSGLEN);
+        for message in &messages {
+            message.write(&mut buf);
+        }
+        self.write_client_data(client, &mut buf);
+        ServerMessage::SignonNum(3).write(&mut buf);
+        self.clients[client].message.write(&buf.data);
+    }
+
+    /// Changes a light style and sends it to all active clients, used by
+    /// the lightstyle builtin.
+    pub fn light_style(&mut self, style : i32, map : &str) {
+        if style < 0 || style as usize >= server::MAX_LIGHTSTYLES {
+            println!("PF_lightstyle: bad style {}", style);
+            return;
+        }
+        self.lightstyles[style as usize] = map.to_string();
+
+        // send message to all clients on this server
+        if !self.active {
+            return;
+        }
+        let message = ServerMessage::LightStyle { style : style, map : map.to_string() };
+        for client in self.clients.iter_mut().filter(|client| client.active || client.spawned) {
+            message.write(&mut client.message);
+        }
+    }
+
+    /// Sends a sound of an entity to all clients with the unreliable
+    /// messages of the frame. The volume goes from 0 to 255.
+    pub fn start_sound(&mut self, ent : usize, channel : i32, sample : &str, volume : i32, attenuation : f32) {
+        if volume < 0 || volume > 255 {
+            println!("SV_StartSound: volume = {}", volume);
+            return;
+        }
+        if attenuation < 0.0 || attenuation > 4.0 {
+            println!("SV_StartSound: attenuation = {}", attenuation);
+            return;
+        }
+        if channel < 0 || channel > 7 {
+            println!("SV_StartSound: channel = {}", channel);
+            return;
+        }
+        // find precache number for sound
+        let sound = match self.sound_precache.iter().skip(1).position(|s| s == sample) {
+            Some(index) => index as i32 + 1,
+            None => {
+                println!("SV_StartSound: {} not precacheed", sample);
+                return;
+            }
+        };
+
+        let v = &self.edicts[ent].v;
+        let (origin, mins, maxs) = (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS));
+        let start = SoundStart {
+            entity : ent as i32,
+            channel : channel,
+            sound : sound,
+            volume : volume,
+            attenuation : attenuation,
+            origin : [
+                origin[0] + 0.5 * (mins[0] + maxs[0]),
+                origin[1] + 0.5 * (mins[1] + maxs[1]),
+                origin[2] + 0.5 * (mins[2] + maxs[2]),
+            ],
+        };
+
+        // only the clients in the potentially hearable set get the sound
+        let phs = &self.phs;
+        let hearable = self.worldmodel.as_ref().and_then(|bsp| phs.get(bsp.point_in_leaf(&start.origin)));
+        for (client, c) in self.clients.iter_mut().enumerate() {
+            if !c.spawned || c.datagram.len() > MAX_DATAGRAM - 16 {
+                continue;
+            }
+            if let (Some(bsp), Some(phs)) = (self.worldmodel.as_ref(), hearable) {
+                let v = &self.edicts[client + 1].v;
+                let ear = vector_add(&v.vector(progdefs::ORIGIN), &v.vector(progdefs::VIEW_OFS));
+                if !leaf_visible(phs, bsp.point_in_leaf(&ear)) {
+                    continue;
+                }
+            }
+            ServerMessage::Sound(start).write(&mut c.datagram);
+        }
+    }
+
+    /// Returns the index of a precached model, 0 for no model.
+    pub fn model_index(&self, name : &str) -> i32 {
+        if name.is_empty() {
+            return 0;
+        }
+        match self.model_precache.iter().position(|m| m == name) {
+            Some(index) => index as i32,
+            None => {
+                println!("SV_ModelIndex: model {} not precached", name);
+                0
+            }
+        }
+    }
+
+    /// Writes the damage, fixangle and player state of a client.
+    fn write_client_data(&mut self, client : usize, buf : &mut SizeBuf) {
+        let ent = client + 1;
+
+        // send a damage message
+        let dmg_take = self.edicts[ent].v.float(progdefs::DMG_TAKE);
+        let dmg_save = self.edicts[ent].v.float(progdefs::DMG_SAVE);
+        if dmg_take != 0.0 || dmg_save != 0.0 {
+            let other = &self.edicts[self.edicts[ent].v.int(progdefs::DMG_INFLICTOR) as usize].v;
+            let origin = other.vector(progdefs::ORIGIN);
+            let mins = other.vector(progdefs::MINS);
+            let maxs = other.vector(progdefs::MAXS);
+            let from = [
+                origin[0] + 0.5 * (mins[0] + maxs[0]),
+                origin[1] + 0.5 * (mins[1] + maxs[1]),
+                origin[2] + 0.5 * (mins[2] + maxs[2]),
+            ];
+            ServerMessage::Damage { armor : dmg_save as i32, blood : dmg_take as i32, from : from }.write(buf);
+            self.edicts[ent].v.set_float(progdefs::DMG_TAKE, 0.0);
+            self.edicts[ent].v.set_float(progdefs::DMG_SAVE, 0.0);
+        }
+
+        // a fixangle might get lost in a dropped packet
+        if self.edicts[ent].v.float(progdefs::FIXANGLE) != 0.0 {
+            ServerMessage::SetAngle(self.edicts[ent].v.vector(progdefs::ANGLES)).write(buf);
+            self.edicts[ent].v.set_float(progdefs::FIXANGLE, 0.0);
+        }
+
+        let v = &self.edicts[ent].v;
+        let flags = v.float(progdefs::FLAGS) as i32;
+        let serverflags = self.globals.float(progdefs::G_SERVERFLAGS) as i32;
+        let data = ClientData {
+            viewheight : v.vector(progdefs::VIEW_OFS)[2] as i32,
+            idealpitch : v.float(progdefs::IDEALPITCH) as i32,
+            punchangle : v.vector(progdefs::PUNCHANGLE),
+            velocity : v.vector(progdefs::VELOCITY),
+            items : v.float(progdefs::ITEMS) as i32 | serverflags << 28,
+            onground : flags & server::FL_ONGROUND != 0,
+            inwater : flags & server::FL_INWATER != 0,
+            weaponframe : v.float(progdefs::WEAPONFRAME) as i32,
+            armor : v.float(progdefs::ARMORVALUE) as i32,
+            weapon : self.model_index(self.strings.get(v.int(progdefs::WEAPONMODEL))),
+            health : v.float(progdefs::HEALTH) as i32,
+            ammo : v.float(progdefs::CURRENTAMMO) as i32,
+            shells : v.float(progdefs::AMMO_SHELLS) as i32,
+            nails : v.float(progdefs::AMMO_NAILS) as i32,
+            rockets : v.float(progdefs::AMMO_ROCKETS) as i32,
+            cells : v.float(progdefs::AMMO_CELLS) as i32,
+            activeweapon : v.float(progdefs::WEAPON) as i32,
+        };
+        ServerMessage::ClientData(data).write(buf);
+    }
+
+    /// Writes the updates of all entities with a model in the potentially
+    /// visible set of the client relative to their baselines. The entity of
+    /// the client is always sent.
+    fn write_entities(&self, client : usize, buf : &mut SizeBuf) {
+        let clent = client + 1;
+
+        // find the client's PVS
+        let pvs = self.worldmodel.as_ref().map(|bsp| {
+            let v = &self.edicts[clent].v;
+            bsp.fat_pvs(&vector_add(&v.vector(progdefs::ORIGIN), &v.vector(progdefs::VIEW_OFS)), 8.0)
+        });
+
+        for (ent, e) in self.edicts.iter().enumerate().skip(1) {
+            if e.free {
+                continue;
+            }
+            if ent != clent {
+                // ignore ents without visible models
+                if e.v.float(progdefs::MODELINDEX) == 0.0 || self.strings.get(e.v.int(progdefs::MODEL)).is_empty() {
+                    continue;
+                }
+                if let Some(ref pvs) = pvs {
+                    if !e.leafnums.iter().any(|&leaf| leaf_visible(pvs, leaf)) {
+                        continue;
+                    }
+                }
+            }
+            if buf.maxsize() - buf.len() < 16 {
+                println!("packet overflow");
+                return;
+            }
+
+            let state = EntityState {
+                origin : e.v.vector(progdefs::ORIGIN),
+                angles : e.v.vector(progdefs::ANGLES),
+                modelindex : e.v.float(progdefs::MODELINDEX) as i32,
+                frame : e.v.float(progdefs::FRAME) as i32,
+                colormap : e.v.float(progdefs::COLORMAP) as i32,
+                skin : e.v.float(progdefs::SKIN) as i32,
+                effects : e.v.float(progdefs::EFFECTS) as i32,
+            };
+            let nolerp = e.v.float(progdefs::MOVETYPE) == server::MOVETYPE_STEP;
+            ServerMessage::Update(EntityUpdate::delta(ent as i32, &e.baseline, &state, nolerp)).write(buf);
+        }
+    }
+
+    /// Sends the unreliable state of the frame, returns false if the
+    /// connection failed.
+    fn send_client_datagram(&mut self, client : usize, time : f64) -> bool {
+        let mut buf = SizeBuf::new(MAX_DATAGRAM);
+        ServerMessage::Time(self.time).write(&mut buf);
+        self.write_client_data(client, &mut buf);
+        self.write_entities(client, &mut buf);
+
+        // copy the server datagram if there is space
+        if buf.len() + self.datagram.len() < buf.maxsize() {
+            buf.write(&self.datagram.data);
+        }
+        if buf.len() + self.clients[client].datagram.len() < buf.maxsize() {
+            buf.write(&self.clients[client].datagram.data);
+        }
+
+        match self.clients[client].netconnection {
+            Some(ref mut socket) => socket.send_unreliable_message(&buf.data, time),
+            None => true,
+        }
+    }
+
+    /// Sends the messages of the frame to all clients.
+    pub fn send_client_messages(&mut self, progs : &mut Progs, time : f64) {
+        // tell all clients about changed frags
+        for client in 0..self.clients.len() {
+            let frags = self.edicts[client + 1].v.float(progdefs::FRAGS) as i32;
+            if self.clients[client].old_frags != frags {
+                ServerMessage::UpdateFrags { client : client as i32, frags : frags }.write(&mut self.reliable_datagram);
+                self.clients[client].old_frags = frags;
+            }
+        }
+
+        for c in self.clients.iter_mut().filter(|c| c.active) {
+            c.message.write(&self.reliable_datagram.data);
+        }
+        self.reliable_datagram.clear();
+
+        for client in 0..self.clients.len() {
+            if !self.clients[client].active {
+                continue;
+            }
+            if self.clients[client].spawned && !self.send_client_datagram(client, time) {
+                self.drop_client(client, progs, time);
+                continue;
+            }
+
+            if self.clients[client].message.overflowed {
+                println!("WARNING: reliable overflow for {}", self.clients[client].name);
+                self.drop_client(client, progs, time);
+                continue;
+            }
+
+            let sent = {
+                let c = &mut self.clients[client];
+                match c.netconnection {
+                    Some(ref mut socket) if !c.message.is_empty() => {
+                        if socket.can_send_message(time) {
+                            let sent = socket.send_message(&c.message.data, time);
+                            c.message.clear();
+                            sent
+                        } else {
+                            true
+                        }
+                    }
+                    _ => true,
+                }
+            };
+            if !sent {
+                self.drop_client(client, progs, time);
+            }
+        }
+
+        // clear muzzle flashes
+        for e in self.edicts.iter_mut().filter(|e| !e.free) {
+            let effects = e.v.float(progdefs::EFFECTS) as i32;
+            e.v.set_float(progdefs::EFFECTS, (effects & !server::EF_MUZZLEFLASH) as f32);
+        }
+        self.datagram.clear();
+        for c in &mut self.clients {
+            c.datagram.clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rquake_fs::{BspLeaf, BspModel, BspNode, BspPlane, MAX_MAP_HULLS};
+    use net::{NetDriver, NetSocket};
+    use net_loop::LoopDriver;
+    use sv_phys;
+
+    /// Records the called functions.
+    struct Recorder {
+        calls : Vec<(i32, i32)>,
+    }
+
+    impl Progs for Recorder {
+        fn execute(&mut self, sv : &mut Server, function : i32) {
+            self.calls.push((function, sv.globals.int(progdefs::G_SELF)));
+        }
+
+        fn find_function(&self, name : &str) -> Option<i32> {
+            match name {
+                "worldspawn" => Some(9),
+                "info_player_start" => Some(10),
+                "func_door" => Some(11),
+                _ => None,
+            }
+        }
+    }
+
+    fn setup() -> (Server, Net, Recorder, Box<NetSocket>) {
+        let mut sv = Server::new(2);
+        sv.active = true;
+        sv.model_precache.push("maps/e1m1.bsp".to_string());
+        sv.model_precache.push("progs/player.mdl".to_string());
+        sv.globals.set_int(progdefs::G_CLIENTCONNECT, 1);
+        sv.globals.set_int(progdefs::G_PUTCLIENTINSERVER, 2);
+        sv.globals.set_int(progdefs::G_CLIENTDISCONNECT, 3);
+
+        let mut driver = LoopDriver::new();
+        driver.listen(true);
+        let socket = driver.connect("local", 0.0).unwrap();
+        let net = Net::new(vec![Box::new(driver)]);
+        (sv, net, Recorder { calls : Vec::new() }, socket)
+    }
+
+    fn send(socket : &mut NetSocket, message : ClientMessage) {
+        let mut buf = SizeBuf::new(MAX_MSGLEN);
+        message.write(&mut buf);
+        assert!(socket.send_message(&buf.data, 0.0));
+    }
+
+    fn receive(socket : &mut NetSocket) -> Vec<ServerMessage> {
+        let mut messages = Vec::new();
+        while let Some(message) = socket.get_message(0.0) {
+            let data = match message {
+                NetMessage::Reliable(data) | NetMessage::Unreliable(data) => data,
+                NetMessage::Disconnected => break,
+            };
+            let mut msg = MsgReader::new(&data);
+            while let Some(message) = ServerMessage::read(&mut msg) {
+                messages.push(message);
+            }
+        }
+        messages
+    }
+
+    fn frame(sv : &mut Server, net : &mut Net, progs : &mut Recorder) {
+        sv.check_for_new_clients(net, progs, 0.0);
+        let params = CommandParams { hostname : String::new(), teamplay : false, pausable : true };
+        sv.read_client_messages(progs, &params, 0.0);
+        sv.send_client_messages(progs, 0.0);
+    }
+
+    #[test]
+    fn signon() {
+        let (mut sv, mut net, mut progs, mut socket) = setup();
+        frame(&mut sv, &mut net, &mut progs);
+        assert!(sv.clients[0].active && !sv.clients[1].active);
+        let messages = receive(&mut *socket);
+        match messages[1] {
+            ServerMessage::ServerInfo { maxclients, ref models, .. } => {
+                assert_eq!(maxclients, 2);
+                assert_eq!(models.len(), 2);
+            }
+            ref message => panic!("unexpected {:?}", message),
+        }
+        assert_eq!(&messages[3..], &[ServerMessage::SetView(1), ServerMessage::SignonNum(1)]);
+
+        send(&mut *socket, ClientMessage::StringCmd("prespawn".to_string()));
+        frame(&mut sv, &mut net, &mut progs);
+        assert_eq!(receive(&mut *socket), vec![ServerMessage::SignonNum(2)]);
+
+        send(&mut *socket, ClientMessage::StringCmd("spawn".to_string()));
+        frame(&mut sv, &mut net, &mut progs);
+        assert_eq!(progs.calls, vec![(1, 1), (2, 1)]);
+        let messages = receive(&mut *socket);
+        assert_eq!(messages.last(), Some(&ServerMessage::SignonNum(3)));
+        assert!(messages.iter().any(|m| *m == ServerMessage::UpdateStat { stat : protocol::STAT_MONSTERS, value : 0 }));
+        assert!(messages.iter().any(|m| *m == ServerMessage::LightStyle { style : 63, map : String::new() }));
+
+        send(&mut *socket, ClientMessage::StringCmd("begin".to_string()));
+        frame(&mut sv, &mut net, &mut progs);
+        assert!(sv.clients[0].spawned);
+        receive(&mut *socket);
+        sv.light_style(0, "az");
+        sv.light_style(64, "m");
+        assert_eq!(sv.lightstyles[0], "az");
+        frame(&mut sv, &mut net, &mut progs);
+        assert!(receive(&mut *socket).contains(&ServerMessage::LightStyle { style : 0, map : "az".to_string() }));
+
+        sv.edicts[1].v.set_vector(progdefs::ORIGIN, [64.0, 0.0, 0.0]);
+        frame(&mut sv, &mut net, &mut progs);
+        let messages = receive(&mut *socket);
+        assert_eq!(messages[0], ServerMessage::Time(0.0));
+        match messages.last() {
+            Some(&ServerMessage::Update(ref update)) => {
+                assert_eq!(update.entity, 1);
+                assert_eq!(update.origin, [Some(64.0), None, None]);
+            }
+            message => panic!("unexpected {:?}", message),
+        }
+    }
+
+    #[test]
+    fn spawn_into_loaded_game() {
+        let (mut sv, mut net, mut progs, mut socket) = setup();
+        sv.loadgame = true;
+        sv.edicts[1].v.set_float(progdefs::HEALTH, 42.0);
+        frame(&mut sv, &mut net, &mut progs);
+        receive(&mut *socket);
+        send(&mut *socket, ClientMessage::StringCmd("prespawn".to_string()));
+        send(&mut *socket, ClientMessage::StringCmd("spawn".to_string()));
+        frame(&mut sv, &mut net, &mut progs);
+
+        // the saved player entity is kept
+        assert!(progs.calls.is_empty());
+        assert_eq!(sv.edicts[1].v.float(progdefs::HEALTH), 42.0);
+        assert_eq!(receive(&mut *socket).last(), Some(&ServerMessage::SignonNum(3)));
+    }
+
+    #[test]
+    fn cull_entities_outside_pvs() {
+        let (mut sv, _, mut progs, _) = setup();
+
+        // leafs 1 to 3 are split at x = 0 and x = 100, each leaf only sees
+        // its neighbours
+        let plane = |dist| BspPlane { normal : [1.0, 0.0, 0.0], dist : dist, kind : 0 };
+        let node = |plane, children| BspNode { plane : plane, children : children, mins : [0; 3], maxs : [0; 3], first_face : 0, num_faces : 0 };
+        let leaf = |contents, visofs| BspLeaf {
+            contents : contents,
+            visofs : visofs,
+            mins : [0; 3],
+            maxs : [0; 3],
+            first_marksurface : 0,
+            num_marksurfaces : 0,
+            ambient_level : [0; 4],
+        };
+        sv.worldmodel = Some(Rc::new(BspFile {
+            planes : vec![plane(0.0), plane(100.0)],
+            nodes : vec![node(0, [1, -2]), node(1, [-4, -3])],
+            leafs : vec![leaf(-2, -1), leaf(-1, 0), leaf(-1, 1), leaf(-1, 2)],
+            visibility : vec![0x03, 0x07, 0x06],
+            ..BspFile::default()
+        }));
+
+        sv.edicts[1].v.set_vector(progdefs::ORIGIN, [-50.0, 0.0, 0.0]);
+        let model = sv.strings.alloc("progs/player.mdl");
+        for &x in &[50.0, 150.0] {
+            let ent = sv.alloc_edict().unwrap();
+            {
+                let v = &mut sv.edicts[ent].v;
+                v.set_int(progdefs::MODEL, model);
+                v.set_float(progdefs::MODELINDEX, 2.0);
+                v.set_vector(progdefs::ORIGIN, [x, 0.0, 0.0]);
+            }
+            sv.link_edict(ent, false, &mut progs);
+        }
+
+        // the entity two leafs away isn't sent
+        let mut buf = SizeBuf::new(MAX_DATAGRAM);
+        sv.write_entities(0, &mut buf);
+        let mut msg = MsgReader::new(&buf.data);
+        let mut entities = Vec::new();
+        while let Some(ServerMessage::Update(update)) = ServerMessage::read(&mut msg) {
+            entities.push(update.entity);
+        }
+        assert_eq!(entities, vec![1, 3]);
+    }
+
+    #[test]
+    fn sounds_in_phs() {
+        let (mut sv, _, _, _) = setup();
+
+        // leafs 1 to 4 are split at x = 0, 100 and 200, each leaf only sees
+        // its neighbours and hears the leafs seen by them
+        let plane = |dist| BspPlane { normal : [1.0, 0.0, 0.0], dist : dist, kind : 0 };
+        let node = |plane, children| BspNode { plane : plane, children : children, mins : [0; 3], maxs : [0; 3], first_face : 0, num_faces : 0 };
+        let leaf = |contents, visofs| BspLeaf {
+            contents : contents,
+            visofs : visofs,
+            mins : [0; 3],
+            maxs : [0; 3],
+            first_marksurface : 0,
+            num_marksurfaces : 0,
+            ambient_level : [0; 4],
+        };
+        let bsp = BspFile {
+            planes : vec![plane(0.0), plane(100.0), plane(200.0)],
+            nodes : vec![node(0, [1, -2]), node(1, [2, -3]), node(2, [-5, -4])],
+            leafs : vec![leaf(-2, -1), leaf(-1, 0), leaf(-1, 1), leaf(-1, 2), leaf(-1, 3)],
+            visibility : vec![0x03, 0x07, 0x0e, 0x0c],
+            ..BspFile::default()
+        };
+        sv.phs = bsp.calc_phs();
+        sv.worldmodel = Some(Rc::new(bsp));
+        sv.sound_precache.push("weapons/ax1.wav".to_string());
+        sv.clients[0].active = true;
+        sv.clients[0].spawned = true;
+        sv.edicts[1].v.set_vector(progdefs::ORIGIN, [-50.0, 0.0, 0.0]);
+
+        // the sound two leafs away is heard, three leafs away it isn't
+        let ent = sv.alloc_edict().unwrap();
+        for &(x, heard) in &[(150.0, true), (250.0, false)] {
+            sv.edicts[ent].v.set_vector(progdefs::ORIGIN, [x, 0.0, 0.0]);
+            sv.start_sound(ent, 1, "weapons/ax1.wav", 255, 1.0);
+            let mut msg = MsgReader::new(&sv.clients[0].datagram.data);
+            match ServerMessage::read(&mut msg) {
+                Some(ServerMessage::Sound(start)) => assert!(heard && start.origin == [x, 0.0, 0.0]),
+                _ => assert!(!heard),
+            }
+            sv.clients[0].datagram.clear();
+        }
+
+        // clients that aren't in the game don't get sounds
+        sv.clients[0].spawned = false;
+        sv.edicts[ent].v.set_vector(progdefs::ORIGIN, [-50.0, 0.0, 0.0]);
+        sv.start_sound(ent, 1, "weapons/ax1.wav", 255, 1.0);
+        assert!(sv.clients[0].datagram.is_empty());
+    }
+
+    fn bsp_model(size : f32) -> BspModel {
+        BspModel {
+            mins : [-size; 3],
+            maxs : [size; 3],
+            origin : [0.0; 3],
+            headnode : [0; MAX_MAP_HULLS],
+            visleafs : 0,
+            first_face : 0,
+            num_faces : 0,
+        }
+    }
+
+    #[test]
+    fn changelevel() {
+        let (mut sv, mut net, mut progs, mut socket) = setup();
+        frame(&mut sv, &mut net, &mut progs);
+        receive(&mut *socket);
+
+        // the values set by SetChangeParms are kept by the client
+        sv.globals.set_int(progdefs::G_SETCHANGEPARMS, 4);
+        sv.globals.set_float(progdefs::G_SERVERFLAGS, 3.0);
+        sv.globals.set_float(progdefs::G_PARM1 + 2, 25.0);
+        sv.save_spawn_parms(&mut progs);
+        assert_eq!(progs.calls, vec![(4, 1)]);
+        assert_eq!(sv.serverflags, 3.0);
+        assert_eq!(sv.clients[0].spawn_parms[2], 25.0);
+        sv.globals.set_float(progdefs::G_SERVERFLAGS, 0.0);
+
+        let mut bsp = BspFile::default();
+        bsp.entities = "{\n\"classname\" \"worldspawn\"\n}\n\
+                        {\n\"classname\" \"info_player_start\"\n\"origin\" \"0 0 24\"\n}\n\
+                        {\n\"classname\" \"func_door\"\n\"model\" \"*1\"\n\"spawnflags\" \"256\"\n}\n".to_string();
+        bsp.models = vec![bsp_model(512.0), bsp_model(32.0)];
+        let mut cvars = CvarList::new();
+        sv_phys::register_cvars(&mut cvars);
+        let params = SpawnParams { skill : 0, deathmatch : 0.0, coop : 1.0 };
+        progs.calls.clear();
+        sv.spawn_server("e1m2", Rc::new(bsp), &params, &PhysicsParams::new(&cvars, 0.1), &mut progs);
+
+        // the door isn't spawned in easy skill
+        assert!(sv.active);
+        assert_eq!(progs.calls, vec![(9, 0), (10, 3)]);
+        assert_eq!(sv.active_edicts(), 4);
+        assert_eq!(sv.edicts[3].v.vector(progdefs::ORIGIN), [0.0, 0.0, 24.0]);
+        assert_eq!(sv.model_precache, vec!["", "maps/e1m2.bsp", "*1"]);
+        assert_eq!(sv.strings.get(sv.globals.int(progdefs::G_MAPNAME)), "e1m2");
+        assert_eq!(sv.globals.float(progdefs::G_SERVERFLAGS), 3.0);
+        assert_eq!(sv.globals.float(progdefs::G_COOP), 1.0);
+        assert!((sv.time - 1.2).abs() < 1e-5);
+
+        // the connected client signs on again with its spawn parms
+        assert!(sv.clients[0].active && !sv.clients[0].spawned);
+        assert_eq!(sv.clients[0].spawn_parms[2], 25.0);
+        frame(&mut sv, &mut net, &mut progs);
+        match receive(&mut *socket)[1] {
+            ServerMessage::ServerInfo { ref models, .. } => assert_eq!(models, &["maps/e1m2.bsp", "*1"]),
+            ref message => panic!("unexpected {:?}", message),
+        }
+        send(&mut *socket, ClientMessage::StringCmd("prespawn".to_string()));
+        frame(&mut sv, &mut net, &mut progs);
+        let messages = receive(&mut *socket);
+        assert!(messages.iter().any(|m| match *m {
+            ServerMessage::SpawnBaseline { entity : 2, ref baseline } => baseline.colormap == 2,
+            _ => false,
+        }));
+        assert_eq!(messages.last(), Some(&ServerMessage::SignonNum(2)));
+    }
+
+    #[test]
+    fn disconnect() {
+        let (mut sv, mut net, mut progs, mut socket) = setup();
+        frame(&mut sv, &mut net, &mut progs);
+        sv.clients[0].spawned = true;
+        receive(&mut *socket);
+
+        send(&mut *socket, ClientMessage::Disconnect);
+        frame(&mut sv, &mut net, &mut progs);
+        assert!(!sv.clients[0].active);
+        assert!(sv.clients[0].netconnection.is_none());
+        assert_eq!(progs.calls, vec![(3, 1)]);
+        assert_eq!(receive(&mut *socket), vec![ServerMessage::Disconnect]);
+        assert_eq!(socket.get_message(0.0), Some(NetMessage::Disconnected));
+    }
+}
@@ -0,0 +1,408 @@
+#![warn(missing_docs)]
+
+//! Monster movement with discrete steps, used by the walkmove and
+//! movetogoal builtins.
+//!
+//! Original source can be found in sv_move.c and pr_cmds.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{anglemod, vector_add};
+use progdefs;
+use progs::Progs;
+use r_part;
+use server::{self, Server};
+use sv_phys::STEPSIZE;
+use trace;
+use world::MoveClip;
+
+/// No direction in the chase direction search.
+const DI_NODIR : f32 = -1.0;
+
+impl Server {
+    /// Returns true if the entity stands on the ground with all corners,
+    /// the corners may be a step height below the center.
+    pub fn check_bottom(&self, ent : usize) -> bool {
+        let (mins, maxs) = {
+            let v = &self.edicts[ent].v;
+            let origin = v.vector(progdefs::ORIGIN);
+            (vector_add(&origin, &v.vector(progdefs::MINS)), vector_add(&origin, &v.vector(progdefs::MAXS)))
+        };
+        let corner = |x : usize, y : usize, z : f32| {
+            [if x == 1 { maxs[0] } else { mins[0] }, if y == 1 { maxs[1] } else { mins[1] }, z]
+        };
+
+        // if all of the points under the corners are solid world, don't bother
+        // with the tougher checks
+        let solid = (0..4).all(|i| self.point_contents(corner(i & 1, i >> 1, mins[2] - 1.0)) == trace::CONTENTS_SOLID);
+        if solid {
+            return true;
+        }
+
+        // the midpoint must be within 16 of the bottom
+        let start = [(mins[0] + maxs[0]) * 0.5, (mins[1] + maxs[1]) * 0.5, mins[2]];
+        let stop = [start[0], start[1], start[2] - 2.0 * STEPSIZE];
+        let trace = self.trace_move(start, [0.0; 3], [0.0; 3], stop, MoveClip::NoMonsters, Some(ent));
+        if trace.fraction == 1.0 {
+            return false;
+        }
+        let mid = trace.endpos[2];
+
+        // the corners must be within 16 of the midpoint
+        for i in 0..4 {
+            let start = corner(i & 1, i >> 1, start[2]);
+            let stop = corner(i & 1, i >> 1, stop[2]);
+            let trace = self.trace_move(start, [0.0; 3], [0.0; 3], stop, MoveClip::NoMonsters, Some(ent));
+            if trace.fraction == 1.0 || mid - trace.endpos[2] > STEPSIZE {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Tries a step of a monster. Walking monsters step up and down stairs
+    /// and don't walk off edges, flying and swimming monsters move towards
+    /// the height of their enemy. Returns false if the move isn't possible.
+    pub fn move_step(&mut self, ent : usize, mov : Vec3, relink : bool, progs : &mut Progs) -> bool {
+        let (oldorg, mins, maxs, flags) = {
+            let v = &self.edicts[ent].v;
+            (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS), v.float(progdefs::FLAGS) as i32)
+        };
+        let mut neworg = vector_add(&oldorg, &mov);
+
+        // flying monsters don't step up
+        if flags & (server::FL_SWIM | server::FL_FLY) != 0 {
+            let enemy = self.edicts[ent].v.int(progdefs::ENEMY) as usize;
+            // try one move with vertical motion, then one without
+            for i in 0..2 {
+                neworg = vector_add(&oldorg, &mov);
+                if i == 0 && enemy != 0 {
+                    let dz = oldorg[2] - self.edicts[enemy].v.float(progdefs::ORIGIN + 2);
+                    if dz > 40.0 {
+                        neworg[2] -= 8.0;
+                    }
+                    if dz < 30.0 {
+                        neworg[2] += 8.0;
+                    }
+                }
+                let trace = self.trace_move(oldorg, mins, maxs, neworg, MoveClip::Normal, Some(ent));
+                if trace.fraction == 1.0 {
+                    if flags & server::FL_SWIM != 0 && self.point_contents(trace.endpos) == trace::CONTENTS_EMPTY {
+                        // swim monster left water
+                        return false;
+                    }
+                    self.edicts[ent].v.set_vector(progdefs::ORIGIN, trace.endpos);
+                    if relink {
+                        self.link_edict(ent, true, progs);
+                    }
+                    return true;
+                }
+                if enemy == 0 {
+                    break;
+                }
+            }
+            return false;
+        }
+
+        // push down from a step height above the wished position
+        neworg[2] += STEPSIZE;
+        let mut end = neworg;
+        end[2] -= STEPSIZE * 2.0;
+
+        let mut trace = self.trace_move(neworg, mins, maxs, end, MoveClip::Normal, Some(ent));
+        if trace.allsolid {
+            return false;
+        }
+        if trace.startsolid {
+            neworg[2] -= STEPSIZE;
+            trace = self.trace_move(neworg, mins, maxs, end, MoveClip::Normal, Some(ent));
+            if trace.allsolid || trace.startsolid {
+                return false;
+            }
+        }
+        if trace.fraction == 1.0 {
+            // if the monster had the ground pulled out, go ahead and fall
+            if flags & server::FL_PARTIALGROUND != 0 {
+                self.edicts[ent].v.set_vector(progdefs::ORIGIN, vector_add(&oldorg, &mov));
+                if relink {
+                    self.link_edict(ent, true, progs);
+                }
+                self.edicts[ent].v.set_float(progdefs::FLAGS, (flags & !server::FL_ONGROUND) as f32);
+                return true;
+            }
+            // walked off an edge
+            return false;
+        }
+
+        // check point traces down for dangling corners
+        self.edicts[ent].v.set_vector(progdefs::ORIGIN, trace.endpos);
+        if !self.check_bottom(ent) {
+            if flags & server::FL_PARTIALGROUND != 0 {
+                // the entity had the floor mostly pulled out from underneath
+                // it and is trying to correct
+                if relink {
+                    self.link_edict(ent, true, progs);
+                }
+                return true;
+            }
+            self.edicts[ent].v.set_vector(progdefs::ORIGIN, oldorg);
+            return false;
+        }
+
+        {
+            let v = &mut self.edicts[ent].v;
+            v.set_float(progdefs::FLAGS, (flags & !server::FL_PARTIALGROUND) as f32);
+            v.set_int(progdefs::GROUNDENTITY, trace.ent.unwrap_or(0) as i32);
+        }
+
+        // the move is ok
+        if relink {
+            self.link_edict(ent, true, progs);
+        }
+        true
+    }
+
+    /// Turns an entity towards its ideal yaw by at most its yaw speed.
+    pub fn change_yaw(&mut self, ent : usize) {
+        let v = &mut self.edicts[ent].v;
+        let current = anglemod(v.float(progdefs::ANGLES + 1));
+        let ideal = v.float(progdefs::IDEAL_YAW);
+        let speed = v.float(progdefs::YAW_SPEED);
+        if current == ideal {
+            return;
+        }
+
+        let mut mov = ideal - current;
+        if ideal > current {
+            if mov >= 180.0 {
+                mov -= 360.0;
+            }
+        } else if mov <= -180.0 {
+            mov += 360.0;
+        }
+        let mov = if mov > 0.0 { mov.min(speed) } else { mov.max(-speed) };
+        v.set_float(progdefs::ANGLES + 1, anglemod(current + mov));
+    }
+
+    /// Turns towards a direction and takes a step if the entity is facing it.
+    /// Returns false if the step isn't possible.
+    fn step_direction(&mut self, ent : usize, yaw : f32, dist : f32, progs : &mut Progs) -> bool {
+        self.edicts[ent].v.set_float(progdefs::IDEAL_YAW, yaw);
+        self.change_yaw(ent);
+
+        let (sin, cos) = (yaw.to_radians()).sin_cos();
+        let oldorigin = self.edicts[ent].v.vector(progdefs::ORIGIN);
+        if self.move_step(ent, [cos * dist, sin * dist, 0.0], false, progs) {
+            let delta = self.edicts[ent].v.float(progdefs::ANGLES + 1) - self.edicts[ent].v.float(progdefs::IDEAL_YAW);
+            if delta > 45.0 && delta < 315.0 {
+                // not turned far enough, so don't take the step
+                self.edicts[ent].v.set_vector(progdefs::ORIGIN, oldorigin);
+            }
+            self.link_edict(ent, true, progs);
+            return true;
+        }
+        self.link_edict(ent, true, progs);
+        false
+    }
+
+    /// Picks a new direction towards the enemy, trying the direct route
+    /// first and turning around last.
+    fn new_chase_dir(&mut self, actor : usize, enemy : usize, dist : f32, progs : &mut Progs, seed : &mut u32) {
+        let olddir = anglemod(((self.edicts[actor].v.float(progdefs::IDEAL_YAW) / 45.0) as i32 * 45) as f32);
+        let turnaround = anglemod(olddir - 180.0);
+
+        let deltax = self.edicts[enemy].v.float(progdefs::ORIGIN) - self.edicts[actor].v.float(progdefs::ORIGIN);
+        let deltay = self.edicts[enemy].v.float(progdefs::ORIGIN + 1) - self.edicts[actor].v.float(progdefs::ORIGIN + 1);
+        let mut d1 = if deltax > 10.0 { 0.0 } else if deltax < -10.0 { 180.0 } else { DI_NODIR };
+        let mut d2 = if deltay < -10.0 { 270.0 } else if deltay > 10.0 { 90.0 } else { DI_NODIR };
+
+        // try the direct route
+        if d1 != DI_NODIR && d2 != DI_NODIR {
+            let tdir = if d1 == 0.0 {
+                if d2 == 90.0 { 45.0 } else { 315.0 }
+            } else if d2 == 90.0 {
+                135.0
+            } else {
+                215.0
+            };
+            if tdir != turnaround && self.step_direction(actor, tdir, dist, progs) {
+                return;
+            }
+        }
+
+        // try the other directions
+        if r_part::rand(seed) & 1 != 0 || deltay.abs() as i32 > deltax.abs() as i32 {
+            ::std::mem::swap(&mut d1, &mut d2);
+        }
+        for &dir in &[d1, d2] {
+            if dir != DI_NODIR && dir != turnaround && self.step_direction(actor, dir, dist, progs) {
+                return;
+            }
+        }
+
+        // there is no direct path to the player, so pick another direction
+        if olddir != DI_NODIR && self.step_direction(actor, olddir, dist, progs) {
+            return;
+        }
+
+        // randomly determine the direction of the search
+        let dirs : Vec<f32> = if r_part::rand(seed) & 1 != 0 {
+            (0..8).map(|i| (i * 45) as f32).collect()
+        } else {
+            (0..8).rev().map(|i| (i * 45) as f32).collect()
+        };
+        for dir in dirs {
+            if dir != turnaround && self.step_direction(actor, dir, dist, progs) {
+                return;
+            }
+        }
+
+        if turnaround != DI_NODIR && self.step_direction(actor, turnaround, dist, progs) {
+            return;
+        }
+
+        // can't move
+        self.edicts[actor].v.set_float(progdefs::IDEAL_YAW, olddir);
+
+        // if a bridge was pulled out from underneath a monster, it may not
+        // have a valid standing position at all
+        if !self.check_bottom(actor) {
+            let flags = self.edicts[actor].v.float(progdefs::FLAGS) as i32;
+            self.edicts[actor].v.set_float(progdefs::FLAGS, (flags | server::FL_PARTIALGROUND) as f32);
+        }
+    }
+
+    /// Returns true if the goal is within dist of the entity.
+    fn close_enough(&self, ent : usize, goal : usize, dist : f32) -> bool {
+        let v = &self.edicts[ent].v;
+        let g = &self.edicts[goal].v;
+        (0..3).all(|i| {
+            g.float(progdefs::ABSMIN + i) <= v.float(progdefs::ABSMAX + i) + dist &&
+            g.float(progdefs::ABSMAX + i) >= v.float(progdefs::ABSMIN + i) - dist
+        })
+    }
+
+    /// Moves a monster a step towards its goal entity, picking a new
+    /// direction when it's blocked.
+    pub fn move_to_goal(&mut self, ent : usize, dist : f32, progs : &mut Progs, seed : &mut u32) {
+        let flags = self.edicts[ent].v.float(progdefs::FLAGS) as i32;
+        if flags & (server::FL_ONGROUND | server::FL_FLY | server::FL_SWIM) == 0 {
+            return;
+        }
+        let goal = self.edicts[ent].v.int(progdefs::GOALENTITY) as usize;
+
+        // if the next step hits the enemy, return immediately
+        if self.edicts[ent].v.int(progdefs::ENEMY) != 0 && self.close_enough(ent, goal, dist) {
+            return;
+        }
+
+        // bump around...
+        let ideal_yaw = self.edicts[ent].v.float(progdefs::IDEAL_YAW);
+        if r_part::rand(seed) & 3 == 1 || !self.step_direction(ent, ideal_yaw, dist, progs) {
+            self.new_chase_dir(ent, goal, dist, progs, seed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use model::BrushModel;
+    use trace::{ClipNode, Hull, Plane, CONTENTS_EMPTY, CONTENTS_SOLID};
+
+    struct NoProgs;
+
+    impl Progs for NoProgs {
+        fn execute(&mut self, _sv : &mut Server, _function : i32) {}
+    }
+
+    fn plane(axis : usize, dist : f32) -> Plane {
+        let mut normal = [0.0; 3];
+        normal[axis] = 1.0;
+        Plane { normal : normal, dist : dist, kind : axis as i32 }
+    }
+
+    /// Floor at z = 0 for x < 100 and a pit for x >= 100, expanded by the
+    /// given box.
+    fn ledge_hull(clip_mins : Vec3, clip_maxs : Vec3) -> Hull {
+        Hull {
+            clipnodes : Rc::new(vec![
+                ClipNode { plane : 0, children : [CONTENTS_EMPTY, 1] },
+                ClipNode { plane : 1, children : [CONTENTS_EMPTY, CONTENTS_SOLID] },
+            ]),
+            planes : Rc::new(vec![
+                plane(2, -clip_mins[2]),
+                plane(0, 100.0 - clip_mins[0]),
+            ]),
+            first_clipnode : 0,
+            clip_mins : clip_mins,
+            clip_maxs : clip_maxs,
+        }
+    }
+
+    fn test_server() -> (Server, usize) {
+        let mut sv = Server::new(1);
+        sv.models = vec![None, Some(Rc::new(BrushModel {
+            mins : [-4096.0; 3],
+            maxs : [4096.0; 3],
+            hulls : [ledge_hull([0.0; 3], [0.0; 3]),
+                     ledge_hull([-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]),
+                     ledge_hull([-32.0, -32.0, -24.0], [32.0, 32.0, 64.0])],
+        }))];
+        {
+            let world = &mut sv.edicts[0].v;
+            world.set_float(progdefs::MODELINDEX, 1.0);
+            world.set_float(progdefs::SOLID, server::SOLID_BSP);
+        }
+        let ent = sv.alloc_edict().unwrap();
+        let v = &mut sv.edicts[ent].v;
+        v.set_vector(progdefs::ORIGIN, [0.0, 0.0, 24.0]);
+        v.set_vector(progdefs::MINS, [-16.0, -16.0, -24.0]);
+        v.set_vector(progdefs::MAXS, [16.0, 16.0, 32.0]);
+        v.set_vector(progdefs::SIZE, [32.0, 32.0, 56.0]);
+        v.set_float(progdefs::SOLID, server::SOLID_SLIDEBOX);
+        v.set_float(progdefs::FLAGS, (server::FL_ONGROUND | server::FL_MONSTER) as f32);
+        v.set_float(progdefs::YAW_SPEED, 20.0);
+        (sv, ent)
+    }
+
+    #[test]
+    fn steps_stop_at_ledges() {
+        let (mut sv, ent) = test_server();
+        assert!(sv.check_bottom(ent));
+        assert!(sv.move_step(ent, [50.0, 0.0, 0.0], true, &mut NoProgs));
+        let origin = sv.edicts[ent].v.vector(progdefs::ORIGIN);
+        assert!(origin[0] == 50.0 && (origin[2] - 24.0).abs() < 0.1);
+
+        // the next step would leave a corner over the pit
+        assert!(!sv.move_step(ent, [50.0, 0.0, 0.0], true, &mut NoProgs));
+        assert_eq!(sv.edicts[ent].v.vector(progdefs::ORIGIN), origin);
+        assert!(sv.move_step(ent, [0.0, -50.0, 0.0], true, &mut NoProgs));
+    }
+
+    #[test]
+    fn turn_towards_ideal_yaw() {
+        let (mut sv, ent) = test_server();
+        sv.edicts[ent].v.set_float(progdefs::IDEAL_YAW, 270.0);
+        sv.change_yaw(ent);
+        assert!((sv.edicts[ent].v.float(progdefs::ANGLES + 1) - 340.0).abs() < 0.01);
+        sv.edicts[ent].v.set_float(progdefs::ANGLES + 1, 260.0);
+        sv.change_yaw(ent);
+        assert!((sv.edicts[ent].v.float(progdefs::ANGLES + 1) - 270.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn chase_goal() {
+        let (mut sv, ent) = test_server();
+        let goal = sv.alloc_edict().unwrap();
+        sv.edicts[goal].v.set_vector(progdefs::ORIGIN, [-200.0, 0.0, 24.0]);
+        sv.edicts[ent].v.set_int(progdefs::GOALENTITY, goal as i32);
+        sv.edicts[ent].v.set_float(progdefs::ANGLES + 1, 180.0);
+        sv.edicts[ent].v.set_float(progdefs::IDEAL_YAW, 180.0);
+        let mut seed = 1;
+        for _ in 0..10 {
+            sv.move_to_goal(ent, 10.0, &mut NoProgs, &mut seed);
+        }
+        assert!(sv.edicts[ent].v.float(progdefs::ORIGIN) < -90.0);
+    }
+}
@@ -0,0 +1,1146 @@
+#![warn(missing_docs)]
+
+//! Movement of the server side entities.
+//!
+//! Original source can be found in sv_phys.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{angle_vectors, cross_product, dot_product, vector_add, vector_ma, vector_scale, vector_subtract};
+use cvar::CvarList;
+use progdefs;
+use progs::Progs;
+use server::{self, Server};
+use trace::{self, Trace};
+use world::MoveClip;
+
+const STOP_EPSILON : f32 = 0.1;
+const MAX_CLIP_PLANES : usize = 5;
+/// Height of the steps entities can walk up.
+pub const STEPSIZE : f32 = 18.0;
+
+/// Values of the physics cvars for one frame.
+#[derive(Clone, Copy, Debug)]
+pub struct PhysicsParams {
+    /// Length of the frame in seconds.
+    pub frametime : f32,
+    /// Gravity in units per second squared.
+    pub gravity : f32,
+    /// Maximum speed along every axis.
+    pub maxvelocity : f32,
+    /// Disables walking up steps.
+    pub nostep : bool,
+    /// Ground friction of players.
+    pub friction : f32,
+    /// Speed below which friction acts like the speed is this value.
+    pub stopspeed : f32,
+    /// Friction multiplier near ledges.
+    pub edgefriction : f32,
+    /// Maximum running speed of players.
+    pub maxspeed : f32,
+    /// Acceleration of players.
+    pub accelerate : f32,
+    /// Maximum view roll when strafing.
+    pub rollangle : f32,
+    /// Strafing speed at which the view roll is at its maximum.
+    pub rollspeed : f32,
+}
+
+impl PhysicsParams {
+    /// Reads the physics cvars.
+    pub fn new(cvars : &CvarList, frametime : f32) -> PhysicsParams {
+        PhysicsParams {
+            frametime : frametime,
+            gravity : cvars.value("sv_gravity"),
+            maxvelocity : cvars.value("sv_maxvelocity"),
+            nostep : cvars.value("sv_nostep") != 0.0,
+            friction : cvars.value("sv_friction"),
+            stopspeed : cvars.value("sv_stopspeed"),
+            edgefriction : cvars.value("edgefriction"),
+            maxspeed : cvars.value("sv_maxspeed"),
+            accelerate : cvars.value("sv_accelerate"),
+            rollangle : cvars.value("cl_rollangle"),
+            rollspeed : cvars.value("cl_rollspeed"),
+        }
+    }
+}
+
+/// Registers the physics cvars.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("sv_friction", "4", false, true);
+    cvars.register("sv_stopspeed", "100", false, false);
+    cvars.register("sv_gravity", "800", false, true);
+    cvars.register("sv_maxvelocity", "2000", false, false);
+    cvars.register("sv_nostep", "0", false, false);
+}
+
+/// Slides a velocity along a plane. Returns 1 if the plane is a floor and
+/// 2 if it's a wall or step.
+fn clip_velocity(vel : Vec3, normal : Vec3, overbounce : f32) -> (Vec3, i32) {
+    let mut blocked = 0;
+    if normal[2] > 0.0 {
+        blocked |= 1;
+    }
+    if normal[2] == 0.0 {
+        blocked |= 2;
+    }
+
+    let backoff = dot_product(&vel, &normal) * overbounce;
+    let mut out = [0.0; 3];
+    for i in 0..3 {
+        out[i] = vel[i] - normal[i] * backoff;
+        if out[i] > -STOP_EPSILON && out[i] < STOP_EPSILON {
+            out[i] = 0.0;
+        }
+    }
+    (out, blocked)
+}
+
+impl Server {
+    /// Runs one frame of physics for all entities and advances the server time.
+    pub fn physics(&mut self, progs : &mut Progs, params : &PhysicsParams) {
+        // let the progs know that a new frame has started
+        self.globals.set_int(progdefs::G_SELF, 0);
+        self.globals.set_int(progdefs::G_OTHER, 0);
+        self.globals.set_float(progdefs::G_TIME, self.time);
+        let start_frame = self.globals.int(progdefs::G_STARTFRAME);
+        self.execute(progs, start_frame);
+
+        // entities spawned during the frame move in the same frame
+        let mut ent = 0;
+        while ent < self.edicts.len() {
+            if !self.edicts[ent].free {
+                if self.globals.float(progdefs::G_FORCE_RETOUCH) != 0.0 {
+                    // force retouch even for stationary entities
+                    self.link_edict(ent, true, progs);
+                }
+
+                let movetype = self.edicts[ent].v.float(progdefs::MOVETYPE);
+                if ent > 0 && ent <= self.max_clients {
+                    self.physics_client(ent, progs, params);
+                } else if movetype == server::MOVETYPE_PUSH {
+                    self.physics_pusher(ent, progs, params);
+                } else if movetype == server::MOVETYPE_NONE {
+                    self.run_think(ent, progs, params);
+                } else if movetype == server::MOVETYPE_NOCLIP {
+                    self.physics_noclip(ent, progs, params);
+                } else if movetype == server::MOVETYPE_STEP {
+                    self.physics_step(ent, progs, params);
+                } else if movetype == server::MOVETYPE_TOSS || movetype == server::MOVETYPE_BOUNCE ||
+                    movetype == server::MOVETYPE_FLY || movetype == server::MOVETYPE_FLYMISSILE {
+                    self.physics_toss(ent, progs, params);
+                } else {
+                    println!("SV_Physics: bad movetype {}", movetype);
+                }
+            }
+            ent += 1;
+        }
+
+        let force_retouch = self.globals.float(progdefs::G_FORCE_RETOUCH);
+        if force_retouch != 0.0 {
+            self.globals.set_float(progdefs::G_FORCE_RETOUCH, force_retouch - 1.0);
+        }
+        self.time += params.frametime;
+    }
+
+    /// Runs a QuakeC function unless it's the null function.
+    pub fn execute(&mut self, progs : &mut Progs, function : i32) {
+        if function != 0 {
+            progs.execute(self, function);
+        }
+    }
+
+    /// Clears invalid velocities and origins and clamps the velocity.
+    fn check_velocity(&mut self, ent : usize, params : &PhysicsParams) {
+        let mut velocity = self.edicts[ent].v.vector(progdefs::VELOCITY);
+        let mut origin = self.edicts[ent].v.vector(progdefs::ORIGIN);
+        for i in 0..3 {
+            if velocity[i].is_nan() {
+                println!("Got a NaN velocity on {}", self.strings.get(self.edicts[ent].v.int(progdefs::CLASSNAME)));
+                velocity[i] = 0.0;
+            }
+            if origin[i].is_nan() {
+                println!("Got a NaN origin on {}", self.strings.get(self.edicts[ent].v.int(progdefs::CLASSNAME)));
+                origin[i] = 0.0;
+            }
+            velocity[i] = velocity[i].max(-params.maxvelocity).min(params.maxvelocity);
+        }
+        self.edicts[ent].v.set_vector(progdefs::VELOCITY, velocity);
+        self.edicts[ent].v.set_vector(progdefs::ORIGIN, origin);
+    }
+
+    /// Runs the think function if nextthink falls into this frame. Returns
+    /// false if the entity has been removed by it.
+    fn run_think(&mut self, ent : usize, progs : &mut Progs, params : &PhysicsParams) -> bool {
+        let mut thinktime = self.edicts[ent].v.float(progdefs::NEXTTHINK);
+        if thinktime <= 0.0 || thinktime > self.time + params.frametime {
+            return true;
+        }
+        // don't let things stay in the past
+        if thinktime < self.time {
+            thinktime = self.time;
+        }
+
+        self.edicts[ent].v.set_float(progdefs::NEXTTHINK, 0.0);
+        self.globals.set_float(progdefs::G_TIME, thinktime);
+        self.globals.set_int(progdefs::G_SELF, ent as i32);
+        self.globals.set_int(progdefs::G_OTHER, 0);
+        let think = self.edicts[ent].v.int(progdefs::THINK);
+        self.execute(progs, think);
+        !self.edicts[ent].free
+    }
+
+    /// Runs the touch functions of two colliding entities.
+    fn impact(&mut self, e1 : usize, e2 : usize, progs : &mut Progs) {
+        let old_self = self.globals.int(progdefs::G_SELF);
+        let old_other = self.globals.int(progdefs::G_OTHER);
+
+        self.globals.set_float(progdefs::G_TIME, self.time);
+        for &(a, b) in &[(e1, e2), (e2, e1)] {
+            let touch = self.edicts[a].v.int(progdefs::TOUCH);
+            if touch != 0 && self.edicts[a].v.float(progdefs::SOLID) != server::SOLID_NOT {
+                self.globals.set_int(progdefs::G_SELF, a as i32);
+                self.globals.set_int(progdefs::G_OTHER, b as i32);
+                progs.execute(self, touch);
+            }
+        }
+
+        self.globals.set_int(progdefs::G_SELF, old_self);
+        self.globals.set_int(progdefs::G_OTHER, old_other);
+    }
+
+    /// Moves an entity along its velocity and slides it along the planes it
+    /// hits. Returns the blocked flags of clip_velocity, 7 if the entity got
+    /// stuck, and the trace of the last wall that was hit.
+    fn fly_move(&mut self, ent : usize, time : f32, progs : &mut Progs) -> (i32, Option<Trace>) {
+        let mut blocked = 0;
+        let mut steptrace = None;
+        let primal_velocity = self.edicts[ent].v.vector(progdefs::VELOCITY);
+        let mut original_velocity = primal_velocity;
+        let mut planes : Vec<Vec3> = Vec::with_capacity(MAX_CLIP_PLANES);
+        let mut time_left = time;
+
+        for _ in 0..4 {
+            let (origin, mins, maxs, velocity) = {
+                let v = &self.edicts[ent].v;
+                (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS), v.vector(progdefs::VELOCITY))
+            };
+            if velocity == [0.0; 3] {
+                break;
+            }
+
+            let end = vector_ma(&origin, time_left, &velocity);
+            let trace = self.trace_move(origin, mins, maxs, end, MoveClip::Normal, Some(ent));
+            if trace.allsolid {
+                // entity is trapped in another solid
+                self.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0; 3]);
+                return (3, steptrace);
+            }
+            if trace.fraction > 0.0 {
+                // actually covered some distance
+                self.edicts[ent].v.set_vector(progdefs::ORIGIN, trace.endpos);
+                original_velocity = velocity;
+                planes.clear();
+            }
+            if trace.fraction == 1.0 {
+                // moved the entire distance
+                break;
+            }
+
+            let hit = trace.ent.unwrap_or(0);
+            if trace.plane_normal[2] > 0.7 {
+                // floor
+                blocked |= 1;
+                if self.edicts[hit].v.float(progdefs::SOLID) == server::SOLID_BSP {
+                    let v = &mut self.edicts[ent].v;
+                    let flags = v.float(progdefs::FLAGS) as i32 | server::FL_ONGROUND;
+                    v.set_float(progdefs::FLAGS, flags as f32);
+                    v.set_int(progdefs::GROUNDENTITY, hit as i32);
+                }
+            }
+            if trace.plane_normal[2] == 0.0 {
+                // step, saved for the player wall friction
+                blocked |= 2;
+                steptrace = Some(trace.clone());
+            }
+
+            // run the impact function
+            self.impact(ent, hit, progs);
+            if self.edicts[ent].free {
+                break;
+            }
+
+            time_left -= time_left * trace.fraction;
+
+            // cliped to another plane
+            if planes.len() >= MAX_CLIP_PLANES {
+                self.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0; 3]);
+                return (3, steptrace);
+            }
+            planes.push(trace.plane_normal);
+
+            // modify original_velocity so it parallels all of the clip planes
+            let mut new_velocity = None;
+            for (i, plane) in planes.iter().enumerate() {
+                let (clipped, _) = clip_velocity(original_velocity, *plane, 1.0);
+                let parallel = planes.iter().enumerate()
+                    .all(|(j, other)| j == i || dot_product(&clipped, other) >= 0.0);
+                if parallel {
+                    new_velocity = Some(clipped);
+                    break;
+                }
+            }
+
+            let velocity = match new_velocity {
+                // go along this plane
+                Some(velocity) => velocity,
+                None => {
+                    // go along the crease
+                    if planes.len() != 2 {
+                        self.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0; 3]);
+                        return (7, steptrace);
+                    }
+                    let dir = cross_product(&planes[0], &planes[1]);
+                    let velocity = self.edicts[ent].v.vector(progdefs::VELOCITY);
+                    vector_scale(&dir, dot_product(&dir, &velocity))
+                },
+            };
+
+            // if the velocity is against the original velocity, stop dead
+            // to avoid tiny oscilations in sloping corners
+            if dot_product(&velocity, &primal_velocity) <= 0.0 {
+                self.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0; 3]);
+                return (blocked, steptrace);
+            }
+            self.edicts[ent].v.set_vector(progdefs::VELOCITY, velocity);
+        }
+        (blocked, steptrace)
+    }
+
+    fn add_gravity(&mut self, ent : usize, params : &PhysicsParams) {
+        let v = &mut self.edicts[ent].v;
+        let velocity = v.float(progdefs::VELOCITY + 2) - params.gravity * params.frametime;
+        v.set_float(progdefs::VELOCITY + 2, velocity);
+    }
+
+    /// Moves an entity without sliding and runs the touch functions.
+    fn push_entity(&mut self, ent : usize, push : Vec3, progs : &mut Progs) -> Trace {
+        let (origin, mins, maxs, movetype, solid) = {
+            let v = &self.edicts[ent].v;
+            (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS),
+             v.float(progdefs::MOVETYPE), v.float(progdefs::SOLID))
+        };
+        let end = vector_add(&origin, &push);
+
+        let clip = if movetype == server::MOVETYPE_FLYMISSILE {
+            MoveClip::Missile
+        } else if solid == server::SOLID_TRIGGER || solid == server::SOLID_NOT {
+            // only clip against bmodels
+            MoveClip::NoMonsters
+        } else {
+            MoveClip::Normal
+        };
+        let trace = self.trace_move(origin, mins, maxs, end, clip, Some(ent));
+
+        self.edicts[ent].v.set_vector(progdefs::ORIGIN, trace.endpos);
+        self.link_edict(ent, true, progs);
+        if let Some(hit) = trace.ent {
+            self.impact(ent, hit, progs);
+        }
+        trace
+    }
+
+    /// Moves a pusher and everything standing on it or in its way.
+    fn push_move(&mut self, pusher : usize, movetime : f32, progs : &mut Progs) {
+        let velocity = self.edicts[pusher].v.vector(progdefs::VELOCITY);
+        let ltime = self.edicts[pusher].v.float(progdefs::LTIME);
+        if velocity == [0.0; 3] {
+            self.edicts[pusher].v.set_float(progdefs::LTIME, ltime + movetime);
+            return;
+        }
+
+        let movement = vector_scale(&velocity, movetime);
+        let mins = vector_add(&self.edicts[pusher].v.vector(progdefs::ABSMIN), &movement);
+        let maxs = vector_add(&self.edicts[pusher].v.vector(progdefs::ABSMAX), &movement);
+        let pushorig = self.edicts[pusher].v.vector(progdefs::ORIGIN);
+
+        // move the pusher to its final position
+        self.edicts[pusher].v.set_vector(progdefs::ORIGIN, vector_add(&pushorig, &movement));
+        self.edicts[pusher].v.set_float(progdefs::LTIME, ltime + movetime);
+        self.link_edict(pusher, false, progs);
+
+        // see if any solid entities are inside the final position
+        let mut moved : Vec<(usize, Vec3)> = Vec::new();
+        for check in 1..self.edicts.len() {
+            let (movetype, flags, groundentity, absmin, absmax) = {
+                let e = &self.edicts[check];
+                if e.free {
+                    continue;
+                }
+                (e.v.float(progdefs::MOVETYPE), e.v.float(progdefs::FLAGS) as i32, e.v.int(progdefs::GROUNDENTITY),
+                 e.v.vector(progdefs::ABSMIN), e.v.vector(progdefs::ABSMAX))
+            };
+            if movetype == server::MOVETYPE_PUSH || movetype == server::MOVETYPE_NONE || movetype == server::MOVETYPE_NOCLIP {
+                continue;
+            }
+
+            // an entity standing on the pusher will definitely be moved
+            if flags & server::FL_ONGROUND == 0 || groundentity != pusher as i32 {
+                if (0..3).any(|i| absmin[i] >= maxs[i] || absmax[i] <= mins[i]) {
+                    continue;
+                }
+                // see if the entity's box is inside the pusher's final position
+                if self.test_entity_position(check).is_none() {
+                    continue;
+                }
+            }
+
+            // remove the onground flag for non-players
+            if movetype != server::MOVETYPE_WALK {
+                self.edicts[check].v.set_float(progdefs::FLAGS, (flags & !server::FL_ONGROUND) as f32);
+            }
+
+            let entorig = self.edicts[check].v.vector(progdefs::ORIGIN);
+            moved.push((check, entorig));
+
+            // try moving the contacted entity
+            self.edicts[pusher].v.set_float(progdefs::SOLID, server::SOLID_NOT);
+            self.push_entity(check, movement, progs);
+            self.edicts[pusher].v.set_float(progdefs::SOLID, server::SOLID_BSP);
+
+            // if it is still inside the pusher, block
+            if self.test_entity_position(check).is_some() {
+                let (check_mins, check_maxs, solid) = {
+                    let v = &self.edicts[check].v;
+                    (v.vector(progdefs::MINS), v.vector(progdefs::MAXS), v.float(progdefs::SOLID))
+                };
+                if check_mins[0] == check_maxs[0] {
+                    continue;
+                }
+                if solid == server::SOLID_NOT || solid == server::SOLID_TRIGGER {
+                    // corpse
+                    let v = &mut self.edicts[check].v;
+                    let squashed = [0.0, 0.0, check_mins[2]];
+                    v.set_vector(progdefs::MINS, squashed);
+                    v.set_vector(progdefs::MAXS, squashed);
+                    continue;
+                }
+
+                // fail the move
+                self.edicts[check].v.set_vector(progdefs::ORIGIN, entorig);
+                self.link_edict(check, true, progs);
+                self.edicts[pusher].v.set_vector(progdefs::ORIGIN, pushorig);
+                self.link_edict(pusher, false, progs);
+                self.edicts[pusher].v.set_float(progdefs::LTIME, ltime);
+
+                // if the pusher has a blocked function, call it, otherwise
+                // just stay in place until the obstacle is gone
+                let blocked = self.edicts[pusher].v.int(progdefs::BLOCKED);
+                if blocked != 0 {
+                    self.globals.set_int(progdefs::G_SELF, pusher as i32);
+                    self.globals.set_int(progdefs::G_OTHER, check as i32);
+                    progs.execute(self, blocked);
+                }
+
+                // move back any entities we already moved
+                for &(ent, origin) in &moved {
+                    self.edicts[ent].v.set_vector(progdefs::ORIGIN, origin);
+                    self.link_edict(ent, false, progs);
+                }
+                return;
+            }
+        }
+    }
+
+    /// Doors, platforms and trains. They move in their local time ltime,
+    /// which stops while they are blocked.
+    fn physics_pusher(&mut self, ent : usize, progs : &mut Progs, params : &PhysicsParams) {
+        let oldltime = self.edicts[ent].v.float(progdefs::LTIME);
+        let thinktime = self.edicts[ent].v.float(progdefs::NEXTTHINK);
+        let movetime = if thinktime < oldltime + params.frametime {
+            (thinktime - oldltime).max(0.0)
+        } else {
+            params.frametime
+        };
+
+        if movetime != 0.0 {
+            // advances ltime if not blocked
+            self.push_move(ent, movetime, progs);
+        }
+
+        if thinktime > oldltime && thinktime <= self.edicts[ent].v.float(progdefs::LTIME) {
+            self.edicts[ent].v.set_float(progdefs::NEXTTHINK, 0.0);
+            self.globals.set_float(progdefs::G_TIME, self.time);
+            self.globals.set_int(progdefs::G_SELF, ent as i32);
+            self.globals.set_int(progdefs::G_OTHER, 0);
+            let think = self.edicts[ent].v.int(progdefs::THINK);
+            self.execute(progs, think);
+        }
+    }
+
+    /// Moves a player out of a solid, trying the last good position first.
+    fn check_stuck(&mut self, ent : usize, progs : &mut Progs) {
+        if self.test_entity_position(ent).is_none() {
+            let origin = self.edicts[ent].v.vector(progdefs::ORIGIN);
+            self.edicts[ent].v.set_vector(progdefs::OLDORIGIN, origin);
+            return;
+        }
+
+        let org = self.edicts[ent].v.vector(progdefs::ORIGIN);
+        let oldorigin = self.edicts[ent].v.vector(progdefs::OLDORIGIN);
+        self.edicts[ent].v.set_vector(progdefs::ORIGIN, oldorigin);
+        if self.test_entity_position(ent).is_none() {
+            println!("Unstuck.");
+            self.link_edict(ent, true, progs);
+            return;
+        }
+
+        for z in 0..18 {
+            for i in -1..2 {
+                for j in -1..2 {
+                    let origin = [org[0] + i as f32, org[1] + j as f32, org[2] + z as f32];
+                    self.edicts[ent].v.set_vector(progdefs::ORIGIN, origin);
+                    if self.test_entity_position(ent).is_none() {
+                        println!("Unstuck.");
+                        self.link_edict(ent, true, progs);
+                        return;
+                    }
+                }
+            }
+        }
+
+        self.edicts[ent].v.set_vector(progdefs::ORIGIN, org);
+        println!("player is stuck.");
+    }
+
+    /// Updates waterlevel and watertype of a player. Returns true if the
+    /// player is at least waist deep in water.
+    fn check_water(&mut self, ent : usize) -> bool {
+        let (origin, mins, maxs, view_ofs) = {
+            let v = &self.edicts[ent].v;
+            (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS), v.vector(progdefs::VIEW_OFS))
+        };
+
+        let mut waterlevel = 0.0;
+        let mut watertype = trace::CONTENTS_EMPTY;
+        let mut point = [origin[0], origin[1], origin[2] + mins[2] + 1.0];
+        let contents = self.point_contents(point);
+        if contents <= trace::CONTENTS_WATER {
+            watertype = contents;
+            waterlevel = 1.0;
+            point[2] = origin[2] + (mins[2] + maxs[2]) * 0.5;
+            if self.point_contents(point) <= trace::CONTENTS_WATER {
+                waterlevel = 2.0;
+                point[2] = origin[2] + view_ofs[2];
+                if self.point_contents(point) <= trace::CONTENTS_WATER {
+                    waterlevel = 3.0;
+                }
+            }
+        }
+
+        let v = &mut self.edicts[ent].v;
+        v.set_float(progdefs::WATERLEVEL, waterlevel);
+        v.set_float(progdefs::WATERTYPE, watertype as f32);
+        waterlevel > 1.0
+    }
+
+    /// Slows a player down when running into a wall at a steep angle.
+    fn wall_friction(&mut self, ent : usize, trace : &Trace) {
+        let v = &mut self.edicts[ent].v;
+        let (forward, _, _) = angle_vectors(&v.vector(progdefs::V_ANGLE));
+        let d = dot_product(&trace.plane_normal, &forward) + 0.5;
+        if d >= 0.0 {
+            return;
+        }
+
+        // cut the tangential velocity
+        let velocity = v.vector(progdefs::VELOCITY);
+        let into = vector_scale(&trace.plane_normal, dot_product(&trace.plane_normal, &velocity));
+        let side = vector_subtract(&velocity, &into);
+        v.set_float(progdefs::VELOCITY, side[0] * (1.0 + d));
+        v.set_float(progdefs::VELOCITY + 1, side[1] * (1.0 + d));
+    }
+
+    /// Player has come to a dead stop, possibly due to the limited precision
+    /// of the clipping hulls. Tries moving a bit in every direction.
+    fn try_unstick(&mut self, ent : usize, oldvel : Vec3, progs : &mut Progs) -> (i32, Option<Trace>) {
+        let oldorg = self.edicts[ent].v.vector(progdefs::ORIGIN);
+        let dirs = [[2.0, 0.0], [0.0, 2.0], [-2.0, 0.0], [0.0, -2.0],
+                    [2.0, 2.0], [-2.0, 2.0], [2.0, -2.0], [-2.0, -2.0]];
+
+        for dir in &dirs {
+            // try pushing a little in an axial direction
+            self.push_entity(ent, [dir[0], dir[1], 0.0], progs);
+
+            // retry the original move
+            self.edicts[ent].v.set_vector(progdefs::VELOCITY, [oldvel[0], oldvel[1], 0.0]);
+            let result = self.fly_move(ent, 0.1, progs);
+
+            let origin = self.edicts[ent].v.vector(progdefs::ORIGIN);
+            if (oldorg[1] - origin[1]).abs() > 4.0 || (oldorg[0] - origin[0]).abs() > 4.0 {
+                return result;
+            }
+
+            // go back to the original pos and try again
+            self.edicts[ent].v.set_vector(progdefs::ORIGIN, oldorg);
+        }
+
+        // still not moving
+        self.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0; 3]);
+        (7, None)
+    }
+
+    /// Only used by players. Does a regular slide move unless it looks like
+    /// the player ran into a step.
+    fn walk_move(&mut self, ent : usize, progs : &mut Progs, params : &PhysicsParams) {
+        let flags = self.edicts[ent].v.float(progdefs::FLAGS) as i32;
+        let oldonground = flags & server::FL_ONGROUND != 0;
+        self.edicts[ent].v.set_float(progdefs::FLAGS, (flags & !server::FL_ONGROUND) as f32);
+
+        let oldorg = self.edicts[ent].v.vector(progdefs::ORIGIN);
+        let oldvel = self.edicts[ent].v.vector(progdefs::VELOCITY);
+
+        let (clip, _) = self.fly_move(ent, params.frametime, progs);
+        if clip & 2 == 0 {
+            // move didn't block on a step
+            return;
+        }
+        let (waterlevel, movetype, flags) = {
+            let v = &self.edicts[ent].v;
+            (v.float(progdefs::WATERLEVEL), v.float(progdefs::MOVETYPE), v.float(progdefs::FLAGS) as i32)
+        };
+        if !oldonground && waterlevel == 0.0 {
+            // don't stair up while jumping
+            return;
+        }
+        if movetype != server::MOVETYPE_WALK || params.nostep || flags & server::FL_WATERJUMP != 0 {
+            // gibbed by a trigger
+            return;
+        }
+
+        let nosteporg = self.edicts[ent].v.vector(progdefs::ORIGIN);
+        let nostepvel = self.edicts[ent].v.vector(progdefs::VELOCITY);
+
+        // try moving up and forward to go up a step
+        self.edicts[ent].v.set_vector(progdefs::ORIGIN, oldorg);
+        let upmove = [0.0, 0.0, STEPSIZE];
+        let downmove = [0.0, 0.0, -STEPSIZE + oldvel[2] * params.frametime];
+
+        // move up
+        self.push_entity(ent, upmove, progs);
+
+        // move forward
+        self.edicts[ent].v.set_vector(progdefs::VELOCITY, [oldvel[0], oldvel[1], 0.0]);
+        let (mut clip, mut steptrace) = self.fly_move(ent, params.frametime, progs);
+
+        // check for stuckness, possibly due to the limited precision of floats in the clipping hulls
+        if clip != 0 {
+            let origin = self.edicts[ent].v.vector(progdefs::ORIGIN);
+            if (oldorg[1] - origin[1]).abs() < 0.03125 && (oldorg[0] - origin[0]).abs() < 0.03125 {
+                // stepping up didn't make any progress
+                let result = self.try_unstick(ent, oldvel, progs);
+                clip = result.0;
+                steptrace = result.1;
+            }
+        }
+
+        // extra friction based on view angle
+        if clip & 2 != 0 {
+            if let Some(ref steptrace) = steptrace {
+                self.wall_friction(ent, steptrace);
+            }
+        }
+
+        // move down
+        let downtrace = self.push_entity(ent, downmove, progs);
+        if downtrace.plane_normal[2] > 0.7 {
+            if self.edicts[ent].v.float(progdefs::SOLID) == server::SOLID_BSP {
+                let v = &mut self.edicts[ent].v;
+                let flags = v.float(progdefs::FLAGS) as i32 | server::FL_ONGROUND;
+                v.set_float(progdefs::FLAGS, flags as f32);
+                v.set_int(progdefs::GROUNDENTITY, downtrace.ent.unwrap_or(0) as i32);
+            }
+        } else {
+            // if the push down didn't end up on good ground, use the move without
+            // the step up. This happens near wall / slope combinations, and can
+            // cause players to walk up walls
+            self.edicts[ent].v.set_vector(progdefs::ORIGIN, nosteporg);
+            self.edicts[ent].v.set_vector(progdefs::VELOCITY, nostepvel);
+        }
+    }
+
+    /// Player movement, surrounded by the PlayerPreThink and PlayerPostThink functions.
+    pub fn physics_client(&mut self, ent : usize, progs : &mut Progs, params : &PhysicsParams) {
+        if !self.clients[ent - 1].active {
+            // unconnected slot
+            return;
+        }
+
+        // call standard client pre-think
+        self.globals.set_float(progdefs::G_TIME, self.time);
+        self.globals.set_int(progdefs::G_SELF, ent as i32);
+        let pre_think = self.globals.int(progdefs::G_PLAYERPRETHINK);
+        self.execute(progs, pre_think);
+
+        // do a move
+        self.check_velocity(ent, params);
+
+        // decide which move type
+        let movetype = self.edicts[ent].v.float(progdefs::MOVETYPE);
+        if movetype == server::MOVETYPE_NONE {
+            if !self.run_think(ent, progs, params) {
+                return;
+            }
+        } else if movetype == server::MOVETYPE_WALK {
+            if !self.run_think(ent, progs, params) {
+                return;
+            }
+            let waterjump = self.edicts[ent].v.float(progdefs::FLAGS) as i32 & server::FL_WATERJUMP != 0;
+            if !self.check_water(ent) && !waterjump {
+                self.add_gravity(ent, params);
+            }
+            self.check_stuck(ent, progs);
+            self.walk_move(ent, progs, params);
+        } else if movetype == server::MOVETYPE_TOSS || movetype == server::MOVETYPE_BOUNCE {
+            self.physics_toss(ent, progs, params);
+        } else if movetype == server::MOVETYPE_FLY {
+            if !self.run_think(ent, progs, params) {
+                return;
+            }
+            self.fly_move(ent, params.frametime, progs);
+        } else if movetype == server::MOVETYPE_NOCLIP {
+            if !self.run_think(ent, progs, params) {
+                return;
+            }
+            let v = &mut self.edicts[ent].v;
+            let origin = vector_ma(&v.vector(progdefs::ORIGIN), params.frametime, &v.vector(progdefs::VELOCITY));
+            v.set_vector(progdefs::ORIGIN, origin);
+        } else {
+            println!("SV_Physics_client: bad movetype {}", movetype);
+            return;
+        }
+
+        // call standard player post-think
+        self.link_edict(ent, true, progs);
+        self.globals.set_float(progdefs::G_TIME, self.time);
+        self.globals.set_int(progdefs::G_SELF, ent as i32);
+        let post_think = self.globals.int(progdefs::G_PLAYERPOSTTHINK);
+        self.execute(progs, post_think);
+    }
+
+    /// A moving entity that doesn't obey gravity and doesn't clip.
+    fn physics_noclip(&mut self, ent : usize, progs : &mut Progs, params : &PhysicsParams) {
+        if !self.run_think(ent, progs, params) {
+            return;
+        }
+        {
+            let v = &mut self.edicts[ent].v;
+            let angles = vector_ma(&v.vector(progdefs::ANGLES), params.frametime, &v.vector(progdefs::AVELOCITY));
+            let origin = vector_ma(&v.vector(progdefs::ORIGIN), params.frametime, &v.vector(progdefs::VELOCITY));
+            v.set_vector(progdefs::ANGLES, angles);
+            v.set_vector(progdefs::ORIGIN, origin);
+        }
+        self.link_edict(ent, false, progs);
+    }
+
+    /// Updates the water type of a tossed entity.
+    fn check_water_transition(&mut self, ent : usize) {
+        let contents = self.point_contents(self.edicts[ent].v.vector(progdefs::ORIGIN));
+        let v = &mut self.edicts[ent].v;
+
+        let watertype = v.float(progdefs::WATERTYPE);
+        if watertype == 0.0 {
+            // just spawned here
+            v.set_float(progdefs::WATERTYPE, contents as f32);
+            v.set_float(progdefs::WATERLEVEL, 1.0);
+            return;
+        }
+
+        // play a splash when crossing the surface
+        let crossed = (contents <= trace::CONTENTS_WATER) == (watertype == trace::CONTENTS_EMPTY as f32);
+        if contents <= trace::CONTENTS_WATER {
+            v.set_float(progdefs::WATERTYPE, contents as f32);
+            v.set_float(progdefs::WATERLEVEL, 1.0);
+        } else {
+            v.set_float(progdefs::WATERTYPE, trace::CONTENTS_EMPTY as f32);
+            v.set_float(progdefs::WATERLEVEL, contents as f32);
+        }
+        if crossed {
+            self.start_sound(ent, 0, "misc/h2ohit1.wav", 255, 1.0);
+        }
+    }
+
+    /// Toss, bounce and fly movement. When onground, do nothing.
+    fn physics_toss(&mut self, ent : usize, progs : &mut Progs, params : &PhysicsParams) {
+        if !self.run_think(ent, progs, params) {
+            return;
+        }
+        if self.edicts[ent].v.float(progdefs::FLAGS) as i32 & server::FL_ONGROUND != 0 {
+            return;
+        }
+
+        self.check_velocity(ent, params);
+
+        let movetype = self.edicts[ent].v.float(progdefs::MOVETYPE);
+        if movetype != server::MOVETYPE_FLY && movetype != server::MOVETYPE_FLYMISSILE {
+            self.add_gravity(ent, params);
+        }
+
+        let movement = {
+            let v = &mut self.edicts[ent].v;
+            let angles = vector_ma(&v.vector(progdefs::ANGLES), params.frametime, &v.vector(progdefs::AVELOCITY));
+            v.set_vector(progdefs::ANGLES, angles);
+            vector_scale(&v.vector(progdefs::VELOCITY), params.frametime)
+        };
+        let trace = self.push_entity(ent, movement, progs);
+        if trace.fraction == 1.0 || self.edicts[ent].free {
+            return;
+        }
+
+        let backoff = if movetype == server::MOVETYPE_BOUNCE { 1.5 } else { 1.0 };
+        let v = &mut self.edicts[ent].v;
+        let (velocity, _) = clip_velocity(v.vector(progdefs::VELOCITY), trace.plane_normal, backoff);
+        v.set_vector(progdefs::VELOCITY, velocity);
+
+        // stop if on ground
+        if trace.plane_normal[2] > 0.7 && (velocity[2] < 60.0 || movetype != server::MOVETYPE_BOUNCE) {
+            let flags = v.float(progdefs::FLAGS) as i32 | server::FL_ONGROUND;
+            v.set_float(progdefs::FLAGS, flags as f32);
+            v.set_int(progdefs::GROUNDENTITY, trace.ent.unwrap_or(0) as i32);
+            v.set_vector(progdefs::VELOCITY, [0.0; 3]);
+            v.set_vector(progdefs::AVELOCITY, [0.0; 3]);
+        }
+
+        self.check_water_transition(ent);
+    }
+
+    /// Monsters freefall when they don't have a ground entity, otherwise all
+    /// movement is done with discrete steps by the QuakeC functions.
+    fn physics_step(&mut self, ent : usize, progs : &mut Progs, params : &PhysicsParams) {
+        let flags = self.edicts[ent].v.float(progdefs::FLAGS) as i32;
+        if flags & (server::FL_ONGROUND | server::FL_FLY | server::FL_SWIM) == 0 {
+            let hitsound = self.edicts[ent].v.vector(progdefs::VELOCITY)[2] < params.gravity * -0.1;
+            self.add_gravity(ent, params);
+            self.check_velocity(ent, params);
+            self.fly_move(ent, params.frametime, progs);
+            self.link_edict(ent, true, progs);
+
+            // just hit ground
+            if hitsound && self.edicts[ent].v.float(progdefs::FLAGS) as i32 & server::FL_ONGROUND != 0 {
+                self.start_sound(ent, 0, "demon/dland2.wav", 255, 1.0);
+            }
+        }
+
+        self.run_think(ent, progs, params);
+        self.check_water_transition(ent);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+    use model::BrushModel;
+    use trace::{ClipNode, Hull, Plane, CONTENTS_EMPTY, CONTENTS_SOLID, CONTENTS_WATER};
+
+    /// Records the function calls and removes entities whose think function is 99.
+    struct Recorder {
+        calls : Vec<(i32, i32, i32, f32)>,
+    }
+
+    impl Progs for Recorder {
+        fn execute(&mut self, sv : &mut Server, function : i32) {
+            let self_ent = sv.globals.int(progdefs::G_SELF);
+            self.calls.push((function, self_ent, sv.globals.int(progdefs::G_OTHER), sv.globals.float(progdefs::G_TIME)));
+            if function == 99 {
+                sv.free_edict(self_ent as usize);
+            }
+        }
+    }
+
+    fn plane(axis : usize, dist : f32) -> Plane {
+        let mut normal = [0.0; 3];
+        normal[axis] = 1.0;
+        Plane { normal : normal, dist : dist, kind : axis as i32 }
+    }
+
+    /// Floor at z = 0, a step up to z = 16 for x >= 64 and water below
+    /// z = -100 for x < -500, expanded by the given box.
+    fn stairs_hull(clip_mins : Vec3, clip_maxs : Vec3) -> Hull {
+        Hull {
+            clipnodes : Rc::new(vec![
+                ClipNode { plane : 0, children : [1, 3] },
+                ClipNode { plane : 1, children : [2, CONTENTS_EMPTY] },
+                ClipNode { plane : 2, children : [CONTENTS_EMPTY, CONTENTS_SOLID] },
+                ClipNode { plane : 3, children : [CONTENTS_SOLID, 4] },
+                ClipNode { plane : 4, children : [CONTENTS_EMPTY, CONTENTS_WATER] },
+            ]),
+            planes : Rc::new(vec![
+                plane(2, -clip_mins[2]),
+                plane(0, 64.0 - clip_maxs[0]),
+                plane(2, 16.0 - clip_mins[2]),
+                plane(0, -500.0 - clip_mins[0]),
+                plane(2, -100.0 - clip_mins[2]),
+            ]),
+            first_clipnode : 0,
+            clip_mins : clip_mins,
+            clip_maxs : clip_maxs,
+        }
+    }
+
+    fn test_server() -> Server {
+        let mut sv = Server::new(1);
+        sv.models = vec![None, Some(Rc::new(BrushModel {
+            mins : [-4096.0; 3],
+            maxs : [4096.0; 3],
+            hulls : [stairs_hull([0.0; 3], [0.0; 3]),
+                     stairs_hull([-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]),
+                     stairs_hull([-32.0, -32.0, -24.0], [32.0, 32.0, 64.0])],
+        }))];
+        let world = &mut sv.edicts[0].v;
+        world.set_float(progdefs::MODELINDEX, 1.0);
+        world.set_float(progdefs::SOLID, server::SOLID_BSP);
+        world.set_float(progdefs::MOVETYPE, server::MOVETYPE_PUSH);
+        sv
+    }
+
+    fn params() -> PhysicsParams {
+        let mut cvars = CvarList::new();
+        register_cvars(&mut cvars);
+        PhysicsParams::new(&cvars, 0.05)
+    }
+
+    fn spawn(sv : &mut Server, origin : Vec3, movetype : f32, solid : f32) -> usize {
+        let ent = sv.alloc_edict().unwrap();
+        let v = &mut sv.edicts[ent].v;
+        v.set_vector(progdefs::ORIGIN, origin);
+        v.set_vector(progdefs::MINS, [-8.0; 3]);
+        v.set_vector(progdefs::MAXS, [8.0; 3]);
+        v.set_vector(progdefs::SIZE, [16.0; 3]);
+        v.set_float(progdefs::MOVETYPE, movetype);
+        v.set_float(progdefs::SOLID, solid);
+        ent
+    }
+
+    #[test]
+    fn toss_lands_on_floor() {
+        let mut sv = test_server();
+        let mut progs = Recorder { calls : Vec::new() };
+        let ent = spawn(&mut sv, [0.0, 0.0, 100.0], server::MOVETYPE_TOSS, server::SOLID_BBOX);
+        sv.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0, 100.0, 0.0]);
+        sv.link_edict(ent, false, &mut progs);
+
+        for _ in 0..40 {
+            sv.physics(&mut progs, &params());
+        }
+        let v = &sv.edicts[ent].v;
+        assert!(v.float(progdefs::FLAGS) as i32 & server::FL_ONGROUND != 0);
+        assert_eq!(v.vector(progdefs::VELOCITY), [0.0; 3]);
+        assert!((v.float(progdefs::ORIGIN + 2) - 8.0).abs() < 0.1);
+        assert_eq!(v.float(progdefs::WATERTYPE), CONTENTS_EMPTY as f32);
+        assert!((sv.time - 2.0).abs() < 0.001);
+
+        // the same sequence of timesteps gives the same result
+        let first = v.vector(progdefs::ORIGIN);
+        let mut sv = test_server();
+        let ent = spawn(&mut sv, [0.0, 0.0, 100.0], server::MOVETYPE_TOSS, server::SOLID_BBOX);
+        sv.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0, 100.0, 0.0]);
+        sv.link_edict(ent, false, &mut progs);
+        for _ in 0..40 {
+            sv.physics(&mut progs, &params());
+        }
+        assert_eq!(sv.edicts[ent].v.vector(progdefs::ORIGIN), first);
+    }
+
+    #[test]
+    fn bounce_and_fall_into_water() {
+        let mut sv = test_server();
+        let mut progs = Recorder { calls : Vec::new() };
+        let ent = spawn(&mut sv, [0.0, 0.0, 50.0], server::MOVETYPE_BOUNCE, server::SOLID_BBOX);
+        sv.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0, 0.0, -400.0]);
+        sv.link_edict(ent, false, &mut progs);
+
+        let mut max_up = 0.0f32;
+        for _ in 0..10 {
+            sv.physics(&mut progs, &params());
+            max_up = max_up.max(sv.edicts[ent].v.float(progdefs::VELOCITY + 2));
+        }
+        assert!(max_up > 150.0);
+
+        let ent = spawn(&mut sv, [-600.0, 0.0, 50.0], server::MOVETYPE_STEP, server::SOLID_NOT);
+        sv.link_edict(ent, false, &mut progs);
+        sv.physics(&mut progs, &params());
+        assert_eq!(sv.edicts[ent].v.float(progdefs::WATERTYPE), CONTENTS_EMPTY as f32);
+        for _ in 0..30 {
+            sv.physics(&mut progs, &params());
+        }
+        assert_eq!(sv.edicts[ent].v.float(progdefs::WATERTYPE), CONTENTS_WATER as f32);
+    }
+
+    #[test]
+    fn think_scheduling() {
+        let mut sv = test_server();
+        let mut progs = Recorder { calls : Vec::new() };
+        sv.time = 1.0;
+        let ent = spawn(&mut sv, [0.0, 0.0, 100.0], server::MOVETYPE_NONE, server::SOLID_NOT);
+        sv.edicts[ent].v.set_int(progdefs::THINK, 5);
+        sv.edicts[ent].v.set_float(progdefs::NEXTTHINK, 1.12);
+        let removed = spawn(&mut sv, [0.0, 0.0, 100.0], server::MOVETYPE_FLY, server::SOLID_NOT);
+        sv.edicts[removed].v.set_int(progdefs::THINK, 99);
+        sv.edicts[removed].v.set_float(progdefs::NEXTTHINK, 0.5);
+        sv.edicts[removed].v.set_vector(progdefs::VELOCITY, [0.0, 0.0, 100.0]);
+
+        sv.physics(&mut progs, &params());
+        assert_eq!(progs.calls, vec![(99, removed as i32, 0, 1.0)]);
+        assert!(sv.edicts[removed].free);
+        assert_eq!(sv.edicts[removed].v.vector(progdefs::ORIGIN), [0.0; 3]);
+
+        progs.calls.clear();
+        sv.physics(&mut progs, &params());
+        assert!(progs.calls.is_empty());
+        sv.physics(&mut progs, &params());
+        assert_eq!(progs.calls, vec![(5, ent as i32, 0, 1.12)]);
+        assert_eq!(sv.edicts[ent].v.float(progdefs::NEXTTHINK), 0.0);
+    }
+
+    #[test]
+    fn missile_impact() {
+        let mut sv = test_server();
+        let mut progs = Recorder { calls : Vec::new() };
+        let ent = spawn(&mut sv, [0.0, 0.0, 40.0], server::MOVETYPE_FLYMISSILE, server::SOLID_BBOX);
+        sv.edicts[ent].v.set_int(progdefs::TOUCH, 3);
+        sv.edicts[ent].v.set_vector(progdefs::VELOCITY, [200.0, 100.0, -800.0]);
+        sv.link_edict(ent, false, &mut progs);
+
+        sv.physics(&mut progs, &params());
+        let v = &sv.edicts[ent].v;
+        assert!((v.float(progdefs::ORIGIN + 2) - 8.0).abs() < 0.1);
+        assert_eq!(v.vector(progdefs::VELOCITY), [0.0; 3]);
+        assert_eq!(progs.calls, vec![(3, ent as i32, 0, 0.0)]);
+
+        // no gravity for missiles
+        sv.edicts[ent].v.set_float(progdefs::FLAGS, 0.0);
+        sv.edicts[ent].v.set_vector(progdefs::VELOCITY, [0.0, 100.0, 0.0]);
+        sv.physics(&mut progs, &params());
+        assert_eq!(sv.edicts[ent].v.vector(progdefs::VELOCITY), [0.0, 100.0, 0.0]);
+    }
+
+    #[test]
+    fn walk_up_step() {
+        let mut sv = test_server();
+        let mut progs = Recorder { calls : Vec::new() };
+        sv.clients[0].active = true;
+        sv.globals.set_int(progdefs::G_PLAYERPRETHINK, 10);
+        sv.globals.set_int(progdefs::G_PLAYERPOSTTHINK, 11);
+        {
+            let v = &mut sv.edicts[1].v;
+            v.set_vector(progdefs::ORIGIN, [0.0, 0.0, 24.03125]);
+            v.set_vector(progdefs::MINS, [-16.0, -16.0, -24.0]);
+            v.set_vector(progdefs::MAXS, [16.0, 16.0, 32.0]);
+            v.set_vector(progdefs::SIZE, [32.0, 32.0, 56.0]);
+            v.set_vector(progdefs::VIEW_OFS, [0.0, 0.0, 22.0]);
+            v.set_vector(progdefs::VELOCITY, [320.0, 0.0, 0.0]);
+            v.set_float(progdefs::MOVETYPE, server::MOVETYPE_WALK);
+            v.set_float(progdefs::SOLID, server::SOLID_SLIDEBOX);
+            v.set_float(progdefs::FLAGS, (server::FL_ONGROUND | server::FL_CLIENT) as f32);
+        }
+        sv.link_edict(1, false, &mut progs);
+
+        for _ in 0..10 {
+            sv.physics(&mut progs, &params());
+        }
+        let v = &sv.edicts[1].v;
+        assert!(v.float(progdefs::ORIGIN) > 100.0);
+        assert!((v.float(progdefs::ORIGIN + 2) - 40.0).abs() < 0.1);
+        assert!(v.float(progdefs::FLAGS) as i32 & server::FL_ONGROUND != 0);
+        assert_eq!(&progs.calls[..2], &[(10, 1, 0, 0.0), (11, 1, 0, 0.0)]);
+    }
+
+    /// Adds a 64x64x16 platform model and returns its model index.
+    fn add_platform_model(sv : &mut Server) -> usize {
+        let mins = [-32.0, -32.0, -8.0];
+        let maxs = [32.0, 32.0, 8.0];
+        let hull = |clip_mins : Vec3, clip_maxs : Vec3| {
+            let mut hull = Hull::for_box(vector_subtract(&mins, &clip_maxs), vector_subtract(&maxs, &clip_mins));
+            hull.clip_mins = clip_mins;
+            hull.clip_maxs = clip_maxs;
+            hull
+        };
+        sv.models.push(Some(Rc::new(BrushModel {
+            mins : mins,
+            maxs : maxs,
+            hulls : [hull([0.0; 3], [0.0; 3]),
+                     hull([-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]),
+                     hull([-32.0, -32.0, -24.0], [32.0, 32.0, 64.0])],
+        })));
+        sv.models.len() - 1
+    }
+
+    /// Spawns a platform moving up and a box standing on it.
+    fn spawn_platform(sv : &mut Server, progs : &mut Recorder) -> (usize, usize) {
+        let modelindex = add_platform_model(sv);
+        let plat = spawn(sv, [0.0, 0.0, 8.0], server::MOVETYPE_PUSH, server::SOLID_BSP);
+        {
+            let v = &mut sv.edicts[plat].v;
+            v.set_float(progdefs::MODELINDEX, modelindex as f32);
+            v.set_vector(progdefs::MINS, [-32.0, -32.0, -8.0]);
+            v.set_vector(progdefs::MAXS, [32.0, 32.0, 8.0]);
+            v.set_vector(progdefs::VELOCITY, [0.0, 0.0, 100.0]);
+            v.set_int(progdefs::BLOCKED, 8);
+            v.set_int(progdefs::THINK, 7);
+            v.set_float(progdefs::NEXTTHINK, 0.3);
+        }
+        sv.link_edict(plat, false, progs);
+
+        let cargo = spawn(sv, [0.0, 0.0, 24.5], server::MOVETYPE_STEP, server::SOLID_BBOX);
+        {
+            let v = &mut sv.edicts[cargo].v;
+            v.set_float(progdefs::FLAGS, server::FL_ONGROUND as f32);
+            v.set_int(progdefs::GROUNDENTITY, plat as i32);
+        }
+        sv.link_edict(cargo, false, progs);
+        (plat, cargo)
+    }
+
+    #[test]
+    fn pusher_carries_entities() {
+        let mut sv = test_server();
+        let mut progs = Recorder { calls : Vec::new() };
+        let (plat, cargo) = spawn_platform(&mut sv, &mut progs);
+
+        for _ in 0..5 {
+            sv.physics(&mut progs, &params());
+        }
+        let plat_z = sv.edicts[plat].v.float(progdefs::ORIGIN + 2);
+        assert!((plat_z - 33.0).abs() < 0.01);
+        assert!((sv.edicts[plat].v.float(progdefs::LTIME) - 0.25).abs() < 0.001);
+        let height = sv.edicts[cargo].v.float(progdefs::ORIGIN + 2) - plat_z;
+        assert!(height >= 16.0 && height < 17.0);
+
+        // the think function runs when the local time reaches nextthink
+        for _ in 0..5 {
+            sv.physics(&mut progs, &params());
+        }
+        assert_eq!(progs.calls.len(), 1);
+        assert_eq!((progs.calls[0].0, progs.calls[0].1), (7, plat as i32));
+        assert_eq!(sv.edicts[plat].v.float(progdefs::NEXTTHINK), 0.0);
+    }
+
+    #[test]
+    fn pusher_blocked() {
+        let mut sv = test_server();
+        let mut progs = Recorder { calls : Vec::new() };
+        let (plat, cargo) = spawn_platform(&mut sv, &mut progs);
+
+        // an obstacle right above the box that can't be pushed
+        let obstacle = spawn(&mut sv, [0.0, 0.0, 41.0], server::MOVETYPE_NONE, server::SOLID_BBOX);
+        sv.link_edict(obstacle, false, &mut progs);
+
+        sv.physics(&mut progs, &params());
+        assert_eq!(sv.edicts[plat].v.float(progdefs::ORIGIN + 2), 8.0);
+        assert_eq!(sv.edicts[plat].v.float(progdefs::LTIME), 0.0);
+        // pushed back and dropped onto the platform again
+        let cargo_z = sv.edicts[cargo].v.float(progdefs::ORIGIN + 2);
+        assert!(cargo_z >= 24.0 && cargo_z <= 24.5);
+        assert_eq!(progs.calls, vec![(8, plat as i32, cargo as i32, 0.0)]);
+    }
+}
@@ -0,0 +1,423 @@
+#![warn(missing_docs)]
+
+//! Server side handling of the client move commands.
+//!
+//! Original source can be found in sv_user.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{angle_vectors, dot_product, vector_add, vector_length, vector_ma, vector_normalize, vector_scale, PITCH, ROLL, YAW};
+use cl_input::UserCmd;
+use cvar::CvarList;
+use progdefs;
+use progs::Progs;
+use server::{self, Server};
+use sv_phys::PhysicsParams;
+use world::MoveClip;
+
+/// Registers the player movement cvars.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("edgefriction", "2", false, false);
+    cvars.register("sv_maxspeed", "320", false, true);
+    cvars.register("sv_accelerate", "10", false, false);
+}
+
+/// Returns the view roll for strafing with the given velocity.
+fn calc_roll(angles : &Vec3, velocity : &Vec3, params : &PhysicsParams) -> f32 {
+    let (_, right, _) = angle_vectors(angles);
+    let side = dot_product(velocity, &right);
+    let sign = if side < 0.0 { -1.0 } else { 1.0 };
+    let side = side.abs();
+
+    if side < params.rollspeed {
+        side * params.rollangle / params.rollspeed * sign
+    } else {
+        params.rollangle * sign
+    }
+}
+
+impl Server {
+    /// Stores the move command of a client and copies the view angles,
+    /// buttons and impulse to its entity.
+    pub fn read_client_move(&mut self, client : usize, time : f32, cmd : &UserCmd) {
+        {
+            let c = &mut self.clients[client];
+            c.ping_times[c.num_pings % server::NUM_PING_TIMES] = self.time - time;
+            c.num_pings += 1;
+            c.cmd = *cmd;
+        }
+
+        let v = &mut self.edicts[client + 1].v;
+        v.set_vector(progdefs::V_ANGLE, cmd.viewangles);
+        v.set_float(progdefs::BUTTON0, (cmd.buttons & 1) as f32);
+        v.set_float(progdefs::BUTTON2, ((cmd.buttons & 2) >> 1) as f32);
+        if cmd.impulse != 0 {
+            v.set_float(progdefs::IMPULSE, cmd.impulse as f32);
+        }
+    }
+
+    /// Turns the move commands of all spawned clients into player velocities.
+    pub fn run_clients(&mut self, params : &PhysicsParams) {
+        for client in 0..self.clients.len() {
+            if !self.clients[client].active {
+                continue;
+            }
+            if !self.clients[client].spawned {
+                // clear client movement until a new packet is received
+                self.clients[client].cmd = UserCmd::new();
+                continue;
+            }
+            self.client_think(client, params);
+        }
+    }
+
+    /// Runs one move command of a client and moves its player without the
+    /// rest of the world, used to predict the player on the client.
+    pub fn run_player_move(&mut self, client : usize, cmd : &UserCmd, progs : &mut Progs, params : &PhysicsParams) {
+        let time = self.time;
+        self.read_client_move(client, time, cmd);
+        self.client_think(client, params);
+        self.physics_client(client + 1, progs, params);
+        self.time += params.frametime;
+    }
+
+    /// The player view angle and velocity are updated from the move command.
+    fn client_think(&mut self, client : usize, params : &PhysicsParams) {
+        let ent = client + 1;
+        if self.edicts[ent].v.float(progdefs::MOVETYPE) == server::MOVETYPE_NONE {
+            return;
+        }
+        let onground = self.edicts[ent].v.float(progdefs::FLAGS) as i32 & server::FL_ONGROUND != 0;
+
+        self.drop_punch_angle(ent, params);
+
+        // if dead, behave differently
+        if self.edicts[ent].v.float(progdefs::HEALTH) <= 0.0 {
+            return;
+        }
+
+        // show 1/3 the pitch angle and all the roll angle
+        {
+            let v = &mut self.edicts[ent].v;
+            let v_angle = vector_add(&v.vector(progdefs::V_ANGLE), &v.vector(progdefs::PUNCHANGLE));
+            let mut angles = v.vector(progdefs::ANGLES);
+            angles[ROLL] = calc_roll(&angles, &v.vector(progdefs::VELOCITY), params) * 4.0;
+            if v.float(progdefs::FIXANGLE) == 0.0 {
+                angles[PITCH] = -v_angle[PITCH] / 3.0;
+                angles[YAW] = v_angle[YAW];
+            }
+            v.set_vector(progdefs::ANGLES, angles);
+        }
+
+        if self.edicts[ent].v.float(progdefs::FLAGS) as i32 & server::FL_WATERJUMP != 0 {
+            self.water_jump(ent);
+            return;
+        }
+
+        // walk
+        let (waterlevel, movetype) = {
+            let v = &self.edicts[ent].v;
+            (v.float(progdefs::WATERLEVEL), v.float(progdefs::MOVETYPE))
+        };
+        if waterlevel >= 2.0 && movetype != server::MOVETYPE_NOCLIP {
+            self.water_move(client, params);
+            return;
+        }
+        self.air_move(client, onground, params);
+    }
+
+    /// Lets the punch angle of a hit or a weapon kick fade out.
+    fn drop_punch_angle(&mut self, ent : usize, params : &PhysicsParams) {
+        let v = &mut self.edicts[ent].v;
+        let mut punchangle = v.vector(progdefs::PUNCHANGLE);
+        let mut len = vector_normalize(&mut punchangle);
+        len -= 10.0 * params.frametime;
+        if len < 0.0 {
+            len = 0.0;
+        }
+        v.set_vector(progdefs::PUNCHANGLE, vector_scale(&punchangle, len));
+    }
+
+    /// Keeps the velocity of a player jumping out of water until the jump ends.
+    fn water_jump(&mut self, ent : usize) {
+        let time = self.time;
+        let v = &mut self.edicts[ent].v;
+        if time > v.float(progdefs::TELEPORT_TIME) || v.float(progdefs::WATERLEVEL) == 0.0 {
+            let flags = v.float(progdefs::FLAGS) as i32 & !server::FL_WATERJUMP;
+            v.set_float(progdefs::FLAGS, flags as f32);
+            v.set_float(progdefs::TELEPORT_TIME, 0.0);
+        }
+        let movedir = v.vector(progdefs::MOVEDIR);
+        v.set_float(progdefs::VELOCITY, movedir[0]);
+        v.set_float(progdefs::VELOCITY + 1, movedir[1]);
+    }
+
+    /// Swimming, the player moves along the view direction.
+    fn water_move(&mut self, client : usize, params : &PhysicsParams) {
+        let cmd = self.clients[client].cmd;
+        let v = &mut self.edicts[client + 1].v;
+
+        // user intentions
+        let (forward, right, _) = angle_vectors(&v.vector(progdefs::V_ANGLE));
+        let mut wishvel = [0.0; 3];
+        for i in 0..3 {
+            wishvel[i] = forward[i] * cmd.forwardmove + right[i] * cmd.sidemove;
+        }
+        if cmd.forwardmove == 0.0 && cmd.sidemove == 0.0 && cmd.upmove == 0.0 {
+            // drift towards bottom
+            wishvel[2] -= 60.0;
+        } else {
+            wishvel[2] += cmd.upmove;
+        }
+
+        let mut wishspeed = vector_length(&wishvel);
+        if wishspeed > params.maxspeed {
+            wishvel = vector_scale(&wishvel, params.maxspeed / wishspeed);
+            wishspeed = params.maxspeed;
+        }
+        wishspeed *= 0.7;
+
+        // water friction
+        let mut velocity = v.vector(progdefs::VELOCITY);
+        let speed = vector_length(&velocity);
+        let mut newspeed = 0.0;
+        if speed != 0.0 {
+            newspeed = speed - params.frametime * speed * params.friction;
+            if newspeed < 0.0 {
+                newspeed = 0.0;
+            }
+            velocity = vector_scale(&velocity, newspeed / speed);
+        }
+
+        // water acceleration
+        if wishspeed != 0.0 {
+            let addspeed = wishspeed - newspeed;
+            if addspeed > 0.0 {
+                vector_normalize(&mut wishvel);
+                let mut accelspeed = params.accelerate * wishspeed * params.frametime;
+                if accelspeed > addspeed {
+                    accelspeed = addspeed;
+                }
+                velocity = vector_ma(&velocity, accelspeed, &wishvel);
+            }
+        }
+        v.set_vector(progdefs::VELOCITY, velocity);
+    }
+
+    /// Walking, flying and noclip movement.
+    fn air_move(&mut self, client : usize, onground : bool, params : &PhysicsParams) {
+        let ent = client + 1;
+        let cmd = self.clients[client].cmd;
+        let (angles, teleport_time, movetype) = {
+            let v = &self.edicts[ent].v;
+            (v.vector(progdefs::ANGLES), v.float(progdefs::TELEPORT_TIME), v.float(progdefs::MOVETYPE))
+        };
+
+        let (forward, right, _) = angle_vectors(&angles);
+        let mut fmove = cmd.forwardmove;
+        let smove = cmd.sidemove;
+
+        // hack to not let you back into teleporter
+        if self.time < teleport_time && fmove < 0.0 {
+            fmove = 0.0;
+        }
+
+        let mut wishvel = [0.0; 3];
+        for i in 0..3 {
+            wishvel[i] = forward[i] * fmove + right[i] * smove;
+        }
+        wishvel[2] = if movetype != server::MOVETYPE_WALK { cmd.upmove } else { 0.0 };
+
+        let mut wishdir = wishvel;
+        let mut wishspeed = vector_normalize(&mut wishdir);
+        if wishspeed > params.maxspeed {
+            wishvel = vector_scale(&wishvel, params.maxspeed / wishspeed);
+            wishspeed = params.maxspeed;
+        }
+
+        if movetype == server::MOVETYPE_NOCLIP {
+            // noclip
+            self.edicts[ent].v.set_vector(progdefs::VELOCITY, wishvel);
+        } else if onground {
+            self.user_friction(ent, params);
+            self.accelerate(ent, wishspeed, &wishdir, params);
+        } else {
+            // not on ground, so little effect on velocity
+            self.air_accelerate(ent, wishspeed, wishvel, params);
+        }
+    }
+
+    /// Slows down a player on the ground, more if the player is running
+    /// towards a ledge.
+    fn user_friction(&mut self, ent : usize, params : &PhysicsParams) {
+        let (origin, mins, mut velocity) = {
+            let v = &self.edicts[ent].v;
+            (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::VELOCITY))
+        };
+        let speed = (velocity[0] * velocity[0] + velocity[1] * velocity[1]).sqrt();
+        if speed == 0.0 {
+            return;
+        }
+
+        // if the leading edge is over a dropoff, increase friction
+        let start = [origin[0] + velocity[0] / speed * 16.0,
+                     origin[1] + velocity[1] / speed * 16.0,
+                     origin[2] + mins[2]];
+        let stop = [start[0], start[1], start[2] - 34.0];
+        let trace = self.trace_move(start, [0.0; 3], [0.0; 3], stop, MoveClip::NoMonsters, Some(ent));
+
+        let friction = if trace.fraction == 1.0 {
+            params.friction * params.edgefriction
+        } else {
+            params.friction
+        };
+
+        // apply friction
+        let control = if speed < params.stopspeed { params.stopspeed } else { speed };
+        let mut newspeed = speed - params.frametime * control * friction;
+        if newspeed < 0.0 {
+            newspeed = 0.0;
+        }
+        newspeed /= speed;
+
+        velocity = vector_scale(&velocity, newspeed);
+        self.edicts[ent].v.set_vector(progdefs::VELOCITY, velocity);
+    }
+
+    /// Accelerates a player on the ground towards the wished direction.
+    fn accelerate(&mut self, ent : usize, wishspeed : f32, wishdir : &Vec3, params : &PhysicsParams) {
+        let v = &mut self.edicts[ent].v;
+        let velocity = v.vector(progdefs::VELOCITY);
+        let currentspeed = dot_product(&velocity, wishdir);
+        let addspeed = wishspeed - currentspeed;
+        if addspeed <= 0.0 {
+            return;
+        }
+        let mut accelspeed = params.accelerate * params.frametime * wishspeed;
+        if accelspeed > addspeed {
+            accelspeed = addspeed;
+        }
+        v.set_vector(progdefs::VELOCITY, vector_ma(&velocity, accelspeed, wishdir));
+    }
+
+    /// Air control, the speed along the wished direction is limited to 30.
+    fn air_accelerate(&mut self, ent : usize, wishspeed : f32, mut wishveloc : Vec3, params : &PhysicsParams) {
+        let mut wishspd = vector_normalize(&mut wishveloc);
+        if wishspd > 30.0 {
+            wishspd = 30.0;
+        }
+
+        let v = &mut self.edicts[ent].v;
+        let velocity = v.vector(progdefs::VELOCITY);
+        let currentspeed = dot_product(&velocity, &wishveloc);
+        let addspeed = wishspd - currentspeed;
+        if addspeed <= 0.0 {
+            return;
+        }
+        let mut accelspeed = params.accelerate * wishspeed * params.frametime;
+        if accelspeed > addspeed {
+            accelspeed = addspeed;
+        }
+        v.set_vector(progdefs::VELOCITY, vector_ma(&velocity, accelspeed, &wishveloc));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sv_phys;
+
+    fn params() -> PhysicsParams {
+        let mut cvars = CvarList::new();
+        sv_phys::register_cvars(&mut cvars);
+        register_cvars(&mut cvars);
+        PhysicsParams::new(&cvars, 0.1)
+    }
+
+    fn test_server() -> Server {
+        let mut sv = Server::new(1);
+        sv.clients[0].active = true;
+        sv.clients[0].spawned = true;
+        let v = &mut sv.edicts[1].v;
+        v.set_float(progdefs::MOVETYPE, server::MOVETYPE_WALK);
+        v.set_float(progdefs::HEALTH, 100.0);
+        v.set_vector(progdefs::MINS, [-16.0, -16.0, -24.0]);
+        v.set_vector(progdefs::MAXS, [16.0, 16.0, 32.0]);
+        sv
+    }
+
+    fn forward_cmd(yaw : f32, forwardmove : f32) -> UserCmd {
+        let mut cmd = UserCmd::new();
+        cmd.viewangles = [30.0, yaw, 0.0];
+        cmd.forwardmove = forwardmove;
+        cmd.buttons = 3;
+        cmd.impulse = 7;
+        cmd
+    }
+
+    #[test]
+    fn read_move() {
+        let mut sv = test_server();
+        sv.read_client_move(0, 0.0, &forward_cmd(90.0, 400.0));
+        let v = &sv.edicts[1].v;
+        assert_eq!(v.vector(progdefs::V_ANGLE), [30.0, 90.0, 0.0]);
+        assert_eq!(v.float(progdefs::BUTTON0), 1.0);
+        assert_eq!(v.float(progdefs::BUTTON2), 1.0);
+        assert_eq!(v.float(progdefs::IMPULSE), 7.0);
+        assert_eq!(sv.clients[0].cmd.forwardmove, 400.0);
+    }
+
+    #[test]
+    fn ground_acceleration() {
+        let mut sv = test_server();
+        let params = params();
+        sv.edicts[1].v.set_float(progdefs::FLAGS, server::FL_ONGROUND as f32);
+        sv.read_client_move(0, 0.0, &forward_cmd(0.0, 400.0));
+        sv.run_clients(&params);
+
+        // the speed is limited by sv_maxspeed, pitch is shown at a third
+        let v = &sv.edicts[1].v;
+        assert_eq!(v.vector(progdefs::ANGLES), [-10.0, 0.0, 0.0]);
+        let velocity = v.vector(progdefs::VELOCITY);
+        assert!((velocity[0] - 320.0).abs() < 0.01);
+        assert!(velocity[1].abs() < 0.01 && velocity[2] == 0.0);
+
+        // friction without input, doubled by edgefriction without a floor
+        sv.read_client_move(0, 0.0, &forward_cmd(0.0, 0.0));
+        sv.run_clients(&params);
+        let velocity = sv.edicts[1].v.vector(progdefs::VELOCITY);
+        assert!((velocity[0] - 320.0 * 0.2).abs() < 0.01);
+    }
+
+    #[test]
+    fn air_control() {
+        let mut sv = test_server();
+        let params = params();
+        sv.edicts[1].v.set_vector(progdefs::VELOCITY, [0.0, 0.0, -100.0]);
+        sv.read_client_move(0, 0.0, &forward_cmd(90.0, 200.0));
+        sv.run_clients(&params);
+        let velocity = sv.edicts[1].v.vector(progdefs::VELOCITY);
+        assert!(velocity[0].abs() < 0.01);
+        assert!((velocity[1] - 30.0).abs() < 0.01);
+        assert_eq!(velocity[2], -100.0);
+    }
+
+    #[test]
+    fn swimming_and_spawn() {
+        let mut sv = test_server();
+        let params = params();
+        sv.edicts[1].v.set_float(progdefs::WATERLEVEL, 3.0);
+        sv.read_client_move(0, 0.0, &UserCmd::new());
+        sv.run_clients(&params);
+        // sink slowly without input
+        let velocity = sv.edicts[1].v.vector(progdefs::VELOCITY);
+        assert!(velocity[0] == 0.0 && velocity[1] == 0.0);
+        assert!((velocity[2] + 42.0).abs() < 0.01);
+
+        // clients that haven't spawned are not moved and lose their command
+        sv.clients[0].spawned = false;
+        sv.read_client_move(0, 0.0, &forward_cmd(0.0, 200.0));
+        sv.run_clients(&params);
+        assert_eq!(sv.clients[0].cmd, UserCmd::new());
+        assert_eq!(sv.edicts[1].v.vector(progdefs::VELOCITY), velocity);
+    }
+}
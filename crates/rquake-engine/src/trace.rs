@@ -0,0 +1,324 @@
+#![warn(missing_docs)]
+
+//! Sweeps axis aligned boxes through clipping hulls.
+//!
+//! Original source can be found in world.c
+
+use std::rc::Rc;
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::dot_product;
+
+/// Contents of empty space.
+pub const CONTENTS_EMPTY : i32 = -1;
+/// Contents of walls.
+pub const CONTENTS_SOLID : i32 = -2;
+/// Contents of water.
+pub const CONTENTS_WATER : i32 = -3;
+/// Contents of slime.
+pub const CONTENTS_SLIME : i32 = -4;
+/// Contents of lava.
+pub const CONTENTS_LAVA : i32 = -5;
+/// Contents of the sky.
+pub const CONTENTS_SKY : i32 = -6;
+/// First of the water currents, they are treated as water.
+pub const CONTENTS_CURRENT_0 : i32 = -9;
+/// Last of the water currents.
+pub const CONTENTS_CURRENT_DOWN : i32 = -14;
+
+/// Distance the end position is kept away from a plane.
+const DIST_EPSILON : f32 = 0.03125;
+
+/// Splitting plane of a hull.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Plane {
+    /// Normal of the plane.
+    pub normal : Vec3,
+    /// Distance from the origin.
+    pub dist : f32,
+    /// 0, 1 or 2 for planes along the x, y or z axis.
+    pub kind : i32,
+}
+
+/// Node of a clipping hull.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipNode {
+    /// Index of the splitting plane.
+    pub plane : usize,
+    /// Front and back child. Negative numbers are contents.
+    pub children : [i32; 2],
+}
+
+/// BSP tree that has been expanded by the size of a box, so the box can
+/// be traced as a point.
+#[derive(Clone, Debug)]
+pub struct Hull {
+    /// Nodes, can be shared by several hulls.
+    pub clipnodes : Rc<Vec<ClipNode>>,
+    /// Planes, can be shared by several hulls.
+    pub planes : Rc<Vec<Plane>>,
+    /// Root node of the hull.
+    pub first_clipnode : i32,
+    /// Box the hull has been expanded by.
+    pub clip_mins : Vec3,
+    /// Box the hull has been expanded by.
+    pub clip_maxs : Vec3,
+}
+
+/// Result of a trace.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Trace {
+    /// The whole trace was in solid.
+    pub allsolid : bool,
+    /// The start position was in solid.
+    pub startsolid : bool,
+    /// Passed through empty space.
+    pub inopen : bool,
+    /// Passed through water, slime or lava.
+    pub inwater : bool,
+    /// Part of the move that was completed, 1 if nothing was hit.
+    pub fraction : f32,
+    /// Final position.
+    pub endpos : Vec3,
+    /// Normal of the plane that was hit.
+    pub plane_normal : Vec3,
+    /// Distance of the plane that was hit.
+    pub plane_dist : f32,
+    /// Entity that was hit.
+    pub ent : Option<usize>,
+}
+
+impl Trace {
+    /// Creates the result of a move that hasn't hit anything yet.
+    pub fn new(end : Vec3) -> Trace {
+        Trace {
+            allsolid : true,
+            startsolid : false,
+            inopen : false,
+            inwater : false,
+            fraction : 1.0,
+            endpos : end,
+            plane_normal : [0.0; 3],
+            plane_dist : 0.0,
+            ent : None,
+        }
+    }
+}
+
+impl Hull {
+    /// Creates a hull for a box, used to clip against entities that aren't brush models.
+    pub fn for_box(mins : Vec3, maxs : Vec3) -> Hull {
+        let mut clipnodes = Vec::with_capacity(6);
+        let mut planes = Vec::with_capacity(6);
+        for i in 0..6 {
+            let side = i & 1;
+            let axis = i >> 1;
+            let mut children = [0; 2];
+            children[side] = CONTENTS_EMPTY;
+            children[side ^ 1] = if i != 5 { i as i32 + 1 } else { CONTENTS_SOLID };
+            clipnodes.push(ClipNode { plane : i, children : children });
+
+            let mut normal = [0.0; 3];
+            normal[axis] = 1.0;
+            planes.push(Plane {
+                normal : normal,
+                dist : if side == 0 { maxs[axis] } else { mins[axis] },
+                kind : axis as i32,
+            });
+        }
+
+        Hull {
+            clipnodes : Rc::new(clipnodes),
+            planes : Rc::new(planes),
+            first_clipnode : 0,
+            clip_mins : [0.0; 3],
+            clip_maxs : [0.0; 3],
+        }
+    }
+
+    /// Returns the contents at a point, starting at the given node.
+    pub fn point_contents(&self, mut num : i32, p : Vec3) -> i32 {
+        while num >= 0 {
+            let node = &self.clipnodes[num as usize];
+            let d = self.plane_distance(node.plane, p);
+            num = if d < 0.0 { node.children[1] } else { node.children[0] };
+        }
+        num
+    }
+
+    /// Sweeps a point from start to end through the hull.
+    pub fn trace(&self, start : Vec3, end : Vec3) -> Trace {
+        let mut trace = Trace::new(end);
+        self.recursive_check(self.first_clipnode, 0.0, 1.0, start, end, &mut trace);
+        trace
+    }
+
+    /// Traces the part of the move between p1 and p2 through the given node.
+    /// Returns false if the move hit something.
+    fn recursive_check(&self, num : i32, p1f : f32, p2f : f32, p1 : Vec3, p2 : Vec3, trace : &mut Trace) -> bool {
+        // check for empty
+        if num < 0 {
+            if num != CONTENTS_SOLID {
+                trace.allsolid = false;
+                if num == CONTENTS_EMPTY {
+                    trace.inopen = true;
+                } else {
+                    trace.inwater = true;
+                }
+            } else {
+                trace.startsolid = true;
+            }
+            return true;
+        }
+
+        // find the point distances
+        let node = self.clipnodes[num as usize];
+        let t1 = self.plane_distance(node.plane, p1);
+        let t2 = self.plane_distance(node.plane, p2);
+
+        if t1 >= 0.0 && t2 >= 0.0 {
+            return self.recursive_check(node.children[0], p1f, p2f, p1, p2, trace);
+        }
+        if t1 < 0.0 && t2 < 0.0 {
+            return self.recursive_check(node.children[1], p1f, p2f, p1, p2, trace);
+        }
+
+        // put the crosspoint DIST_EPSILON units on the near side
+        let mut frac = if t1 < 0.0 {
+            (t1 + DIST_EPSILON) / (t1 - t2)
+        } else {
+            (t1 - DIST_EPSILON) / (t1 - t2)
+        };
+        frac = frac.max(0.0).min(1.0);
+
+        let mut midf = p1f + (p2f - p1f) * frac;
+        let mut mid = lerp(p1, p2, frac);
+        let side = if t1 < 0.0 { 1 } else { 0 };
+
+        // move up to the node
+        if !self.recursive_check(node.children[side], p1f, midf, p1, mid, trace) {
+            return false;
+        }
+
+        // go past the node
+        if self.point_contents(node.children[side ^ 1], mid) != CONTENTS_SOLID {
+            return self.recursive_check(node.children[side ^ 1], midf, p2f, mid, p2, trace);
+        }
+
+        // never got out of the solid area
+        if trace.allsolid {
+            return false;
+        }
+
+        // the other side of the node is solid, this is the impact point
+        let plane = &self.planes[node.plane];
+        if side == 0 {
+            trace.plane_normal = plane.normal;
+            trace.plane_dist = plane.dist;
+        } else {
+            trace.plane_normal = [-plane.normal[0], -plane.normal[1], -plane.normal[2]];
+            trace.plane_dist = -plane.dist;
+        }
+
+        while self.point_contents(self.first_clipnode, mid) == CONTENTS_SOLID {
+            // shouldn't really happen, but does occasionally
+            frac -= 0.1;
+            if frac < 0.0 {
+                trace.fraction = midf;
+                trace.endpos = mid;
+                println!("backup past 0");
+                return false;
+            }
+            midf = p1f + (p2f - p1f) * frac;
+            mid = lerp(p1, p2, frac);
+        }
+
+        trace.fraction = midf;
+        trace.endpos = mid;
+        false
+    }
+
+    fn plane_distance(&self, plane : usize, p : Vec3) -> f32 {
+        let plane = &self.planes[plane];
+        if plane.kind < 3 {
+            p[plane.kind as usize] - plane.dist
+        } else {
+            dot_product(&plane.normal, &p) - plane.dist
+        }
+    }
+}
+
+fn lerp(p1 : Vec3, p2 : Vec3, frac : f32) -> Vec3 {
+    [p1[0] + frac * (p2[0] - p1[0]),
+     p1[1] + frac * (p2[1] - p1[1]),
+     p1[2] + frac * (p2[2] - p1[2])]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hull with solid below z = 0 and water above z = 100.
+    fn floor_hull() -> Hull {
+        Hull {
+            clipnodes : Rc::new(vec![ClipNode { plane : 0, children : [1, CONTENTS_SOLID] },
+                                     ClipNode { plane : 1, children : [CONTENTS_WATER, CONTENTS_EMPTY] }]),
+            planes : Rc::new(vec![Plane { normal : [0.0, 0.0, 1.0], dist : 0.0, kind : 2 },
+                                  Plane { normal : [0.0, 0.0, 1.0], dist : 100.0, kind : 3 }]),
+            first_clipnode : 0,
+            clip_mins : [0.0; 3],
+            clip_maxs : [0.0; 3],
+        }
+    }
+
+    #[test]
+    fn point_contents() {
+        let hull = floor_hull();
+        assert_eq!(hull.point_contents(0, [0.0, 0.0, -1.0]), CONTENTS_SOLID);
+        assert_eq!(hull.point_contents(0, [0.0, 0.0, 50.0]), CONTENTS_EMPTY);
+        assert_eq!(hull.point_contents(0, [0.0, 0.0, 150.0]), CONTENTS_WATER);
+
+        let hull = Hull::for_box([-16.0, -16.0, -24.0], [16.0, 16.0, 32.0]);
+        assert_eq!(hull.point_contents(0, [0.0, 0.0, 0.0]), CONTENTS_SOLID);
+        assert_eq!(hull.point_contents(0, [15.0, -15.0, 31.0]), CONTENTS_SOLID);
+        assert_eq!(hull.point_contents(0, [17.0, 0.0, 0.0]), CONTENTS_EMPTY);
+        assert_eq!(hull.point_contents(0, [0.0, 0.0, -25.0]), CONTENTS_EMPTY);
+    }
+
+    #[test]
+    fn trace_against_floor() {
+        let hull = floor_hull();
+        let trace = hull.trace([0.0, 0.0, 10.0], [0.0, 0.0, -10.0]);
+        assert!(!trace.allsolid && !trace.startsolid && trace.inopen);
+        assert_eq!(trace.fraction, (10.0 - DIST_EPSILON) / 20.0);
+        assert_eq!(trace.endpos, [0.0, 0.0, DIST_EPSILON]);
+        assert_eq!(trace.plane_normal, [0.0, 0.0, 1.0]);
+        assert_eq!(trace.plane_dist, 0.0);
+
+        let trace = hull.trace([0.0, 0.0, 10.0], [20.0, 0.0, 200.0]);
+        assert_eq!(trace.fraction, 1.0);
+        assert_eq!(trace.endpos, [20.0, 0.0, 200.0]);
+        assert!(trace.inopen && trace.inwater);
+
+        let trace = hull.trace([0.0, 0.0, -10.0], [0.0, 0.0, 10.0]);
+        assert!(trace.startsolid && !trace.allsolid);
+        assert_eq!(trace.fraction, 1.0);
+
+        let trace = hull.trace([0.0, 0.0, -10.0], [5.0, 0.0, -20.0]);
+        assert!(trace.startsolid && trace.allsolid);
+    }
+
+    #[test]
+    fn trace_against_box() {
+        let hull = Hull::for_box([-16.0; 3], [16.0; 3]);
+        let trace = hull.trace([-100.0, 0.0, 0.0], [100.0, 0.0, 0.0]);
+        assert_eq!(trace.plane_normal, [-1.0, 0.0, 0.0]);
+        assert_eq!(trace.plane_dist, 16.0);
+        assert_eq!(trace.endpos[0], -16.0 - DIST_EPSILON);
+        assert!(trace.fraction > 0.41 && trace.fraction < 0.42);
+
+        let trace = hull.trace([0.0, 100.0, 0.0], [0.0, 50.0, 0.0]);
+        assert_eq!(trace.fraction, 1.0);
+        assert!(!trace.allsolid);
+    }
+}
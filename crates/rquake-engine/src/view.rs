@@ -0,0 +1,351 @@
+#![warn(missing_docs)]
+
+//! Player view: the eye position with bobbing, stair smoothing, strafe
+//! roll, damage kicks and the weapon model.
+//!
+//! Original source can be found in view.c
+
+use std::f32::consts::PI;
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{angle_vectors, dot_product, vector_normalize, vector_subtract, PITCH, ROLL, YAW};
+use client::ClientState;
+use cvar::CvarList;
+use protocol::{self, EntityState};
+
+/// Maximum view height change per second when walking up stairs.
+const STEP_SPEED : f32 = 80.0;
+
+/// Values of the view cvars for one frame.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewParams {
+    /// Length of the frame in seconds.
+    pub frametime : f32,
+    /// Amount of bobbing while walking.
+    pub bob : f32,
+    /// Length of a bob cycle in seconds.
+    pub bobcycle : f32,
+    /// Part of the bob cycle spent moving up.
+    pub bobup : f32,
+    /// Maximum view roll when strafing.
+    pub rollangle : f32,
+    /// Strafing speed at which the view roll is at its maximum.
+    pub rollspeed : f32,
+    /// Duration of a damage kick in seconds.
+    pub kicktime : f32,
+    /// Roll of a damage kick.
+    pub kickroll : f32,
+    /// Pitch of a damage kick.
+    pub kickpitch : f32,
+}
+
+impl ViewParams {
+    /// Reads the view cvars.
+    pub fn new(cvars : &CvarList, frametime : f32) -> ViewParams {
+        ViewParams {
+            frametime : frametime,
+            bob : cvars.value("cl_bob"),
+            bobcycle : cvars.value("cl_bobcycle"),
+            bobup : cvars.value("cl_bobup"),
+            rollangle : cvars.value("cl_rollangle"),
+            rollspeed : cvars.value("cl_rollspeed"),
+            kicktime : cvars.value("v_kicktime"),
+            kickroll : cvars.value("v_kickroll"),
+            kickpitch : cvars.value("v_kickpitch"),
+        }
+    }
+}
+
+/// Registers the view cvars.
+pub fn register_cvars(cvars : &mut CvarList) {
+    cvars.register("cl_bob", "0.02", false, false);
+    cvars.register("cl_bobcycle", "0.6", false, false);
+    cvars.register("cl_bobup", "0.5", false, false);
+    cvars.register("v_kicktime", "0.5", false, false);
+    cvars.register("v_kickroll", "0.6", false, false);
+    cvars.register("v_kickpitch", "0.6", false, false);
+    cvars.register("fov", "90", false, false);
+}
+
+/// Position of the camera and the weapon model.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RefDef {
+    /// Eye position.
+    pub vieworg : Vec3,
+    /// View direction in degrees.
+    pub viewangles : Vec3,
+    /// Weapon model in front of the camera, None if no weapon is drawn.
+    pub viewmodel : Option<EntityState>,
+}
+
+impl RefDef {
+    /// Creates a view at the origin.
+    pub fn new() -> RefDef {
+        RefDef {
+            vieworg : [0.0; 3],
+            viewangles : [0.0; 3],
+            viewmodel : None,
+        }
+    }
+}
+
+/// View effects that last over several frames.
+pub struct View {
+    /// Camera of the current frame.
+    pub refdef : RefDef,
+    dmg_time : f32,
+    dmg_roll : f32,
+    dmg_pitch : f32,
+    oldz : f32,
+}
+
+/// Returns the view height offset of bobbing while walking.
+fn calc_bob(cl : &ClientState, time : f32, params : &ViewParams) -> f32 {
+    if params.bobcycle <= 0.0 || params.bobup <= 0.0 || params.bobup >= 1.0 {
+        return 0.0;
+    }
+    let cycle = (time - (time / params.bobcycle).floor() * params.bobcycle) / params.bobcycle;
+    let cycle = if cycle < params.bobup {
+        PI * cycle / params.bobup
+    } else {
+        PI + PI * (cycle - params.bobup) / (1.0 - params.bobup)
+    };
+
+    // bob is proportional to velocity in the xy plane
+    // (don't count Z, or jumping messes it up)
+    let velocity = cl.velocity;
+    let bob = (velocity[0] * velocity[0] + velocity[1] * velocity[1]).sqrt() * params.bob;
+    let bob = bob * 0.3 + bob * 0.7 * cycle.sin();
+    bob.max(-7.0).min(4.0)
+}
+
+/// Returns the view roll for strafing with the given velocity.
+fn calc_roll(angles : &Vec3, velocity : &Vec3, params : &ViewParams) -> f32 {
+    let (_, right, _) = angle_vectors(angles);
+    let side = dot_product(velocity, &right);
+    let sign = if side < 0.0 { -1.0 } else { 1.0 };
+    let side = side.abs();
+
+    if side < params.rollspeed {
+        side * params.rollangle / params.rollspeed * sign
+    } else {
+        params.rollangle * sign
+    }
+}
+
+impl View {
+    /// Creates a view without active effects.
+    pub fn new() -> View {
+        View {
+            refdef : RefDef::new(),
+            dmg_time : 0.0,
+            dmg_roll : 0.0,
+            dmg_pitch : 0.0,
+            oldz : 0.0,
+        }
+    }
+
+    /// Starts a view kick away from the origin of the damage.
+    pub fn parse_damage(&mut self, cl : &ClientState, armor : i32, blood : i32, from : &Vec3, params : &ViewParams) {
+        let count = (blood as f32 * 0.5 + armor as f32 * 0.5).max(10.0);
+        let ent = match cl.entities.get(cl.viewentity) {
+            Some(ent) => ent,
+            None => return,
+        };
+
+        let mut from = vector_subtract(from, &ent.state.origin);
+        vector_normalize(&mut from);
+        let (forward, right, _) = angle_vectors(&ent.state.angles);
+        self.dmg_roll = count * dot_product(&from, &right) * params.kickroll;
+        self.dmg_pitch = count * dot_product(&from, &forward) * params.kickpitch;
+        self.dmg_time = params.kicktime;
+    }
+
+    /// Places the camera at the eyes of the view entity and the weapon in
+    /// front of it. The view entity is turned to the view angles.
+    pub fn calc_refdef(&mut self, cl : &mut ClientState, viewangles : &Vec3, time : f32, params : &ViewParams) {
+        let viewentity = cl.viewentity;
+        if viewentity >= cl.entities.len() {
+            return;
+        }
+
+        if cl.intermission != 0 {
+            // the view entity is placed at the intermission camera
+            let ent = &cl.entities[viewentity].state;
+            self.refdef = RefDef {
+                vieworg : ent.origin,
+                viewangles : ent.angles,
+                viewmodel : None,
+            };
+            return;
+        }
+
+        // the entity is pitched the other way than the view
+        let origin = {
+            let ent = &mut cl.entities[viewentity].state;
+            ent.angles[YAW] = viewangles[YAW];
+            ent.angles[PITCH] = -viewangles[PITCH];
+            ent.origin
+        };
+
+        let bob = calc_bob(cl, time, params);
+
+        // never let it sit exactly on a node line, because a water plane can
+        // disappear when viewed with the eye exactly on it
+        let mut vieworg = origin;
+        vieworg[2] += cl.viewheight + bob;
+        for v in &mut vieworg {
+            *v += 1.0 / 32.0;
+        }
+
+        let mut angles = *viewangles;
+        angles[ROLL] += calc_roll(&cl.entities[viewentity].state.angles, &cl.velocity, params);
+        if self.dmg_time > 0.0 {
+            angles[ROLL] += self.dmg_time / params.kicktime * self.dmg_roll;
+            angles[PITCH] += self.dmg_time / params.kicktime * self.dmg_pitch;
+            self.dmg_time -= params.frametime;
+        }
+        if cl.stats[protocol::STAT_HEALTH as usize] <= 0 {
+            // dead view angle
+            angles[ROLL] = 80.0;
+        }
+
+        // set up the gun position
+        let mut gun_angles = *viewangles;
+        gun_angles[PITCH] = -viewangles[PITCH];
+        let (forward, _, _) = angle_vectors(viewangles);
+        let mut gun_origin = origin;
+        gun_origin[2] += cl.viewheight;
+        for i in 0..3 {
+            gun_origin[i] += forward[i] * bob * 0.4;
+        }
+        gun_origin[2] += bob;
+        // fudge position around to keep amount of weapon visible
+        gun_origin[2] += 2.0;
+
+        for i in 0..3 {
+            angles[i] += cl.punchangle[i];
+        }
+
+        // smooth out stair step ups
+        if cl.onground && origin[2] - self.oldz > 0.0 {
+            self.oldz += params.frametime * STEP_SPEED;
+            if self.oldz > origin[2] {
+                self.oldz = origin[2];
+            }
+            if origin[2] - self.oldz > 12.0 {
+                self.oldz = origin[2] - 12.0;
+            }
+            vieworg[2] += self.oldz - origin[2];
+            gun_origin[2] += self.oldz - origin[2];
+        } else {
+            self.oldz = origin[2];
+        }
+
+        let weapon = cl.stats[protocol::STAT_WEAPON as usize];
+        let viewmodel = if weapon > 0 && cl.stats[protocol::STAT_HEALTH as usize] > 0 {
+            Some(EntityState {
+                origin : gun_origin,
+                angles : gun_angles,
+                modelindex : weapon,
+                frame : cl.stats[protocol::STAT_WEAPONFRAME as usize],
+                ..EntityState::new()
+            })
+        } else {
+            None
+        };
+
+        self.refdef = RefDef {
+            vieworg : vieworg,
+            viewangles : angles,
+            viewmodel : viewmodel,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use client::Entity;
+
+    fn params() -> ViewParams {
+        let mut cvars = CvarList::new();
+        register_cvars(&mut cvars);
+        cvars.register("cl_rollspeed", "200", false, false);
+        cvars.register("cl_rollangle", "2.0", false, false);
+        ViewParams::new(&cvars, 0.1)
+    }
+
+    fn client() -> ClientState {
+        let mut cl = ClientState::new();
+        cl.viewentity = 1;
+        cl.entities = vec![Entity::new(); 2];
+        cl.entities[1].state.origin = [0.0, 0.0, 24.0];
+        cl.stats[protocol::STAT_HEALTH as usize] = 100;
+        cl.stats[protocol::STAT_WEAPON as usize] = 3;
+        cl
+    }
+
+    #[test]
+    fn standing_view() {
+        let mut cl = client();
+        let mut view = View::new();
+        view.calc_refdef(&mut cl, &[10.0, 90.0, 0.0], 0.0, &params());
+        assert_eq!(view.refdef.vieworg, [1.0 / 32.0, 1.0 / 32.0, 24.0 + 22.0 + 1.0 / 32.0]);
+        assert_eq!(view.refdef.viewangles, [10.0, 90.0, 0.0]);
+        assert_eq!(cl.entities[1].state.angles, [-10.0, 90.0, 0.0]);
+        let viewmodel = view.refdef.viewmodel.unwrap();
+        assert_eq!(viewmodel.modelindex, 3);
+        assert_eq!(viewmodel.origin, [0.0, 0.0, 48.0]);
+
+        cl.stats[protocol::STAT_HEALTH as usize] = 0;
+        view.calc_refdef(&mut cl, &[10.0, 90.0, 0.0], 0.0, &params());
+        assert_eq!(view.refdef.viewangles[ROLL], 80.0);
+        assert!(view.refdef.viewmodel.is_none());
+    }
+
+    #[test]
+    fn bob_and_roll() {
+        let mut cl = client();
+        cl.velocity = [0.0, 320.0, 0.0];
+        let params = params();
+        // a quarter into the up cycle
+        let bob = calc_bob(&cl, 0.15, &params);
+        let expected = 320.0 * 0.02 * 0.3 + 320.0 * 0.02 * 0.7 * (PI * 0.5).sin();
+        assert!((bob - expected.min(4.0)).abs() < 0.001);
+
+        // strafing right rolls the view
+        assert_eq!(calc_roll(&[0.0, 0.0, 0.0], &[0.0, -320.0, 0.0], &params), 2.0);
+        assert_eq!(calc_roll(&[0.0, 0.0, 0.0], &[0.0, 100.0, 0.0], &params), -1.0);
+    }
+
+    #[test]
+    fn damage_kick() {
+        let mut cl = client();
+        let params = params();
+        let mut view = View::new();
+        // hit from the front pitches the view up
+        view.parse_damage(&cl, 0, 20, &[100.0, 0.0, 24.0], &params);
+        view.calc_refdef(&mut cl, &[0.0; 3], 0.0, &params);
+        assert!((view.refdef.viewangles[PITCH] - 10.0 * 0.6).abs() < 0.001);
+        view.calc_refdef(&mut cl, &[0.0; 3], 0.0, &params);
+        assert!((view.refdef.viewangles[PITCH] - 0.4 / 0.5 * 10.0 * 0.6).abs() < 0.001);
+    }
+
+    #[test]
+    fn stair_smoothing() {
+        let mut cl = client();
+        let params = params();
+        let mut view = View::new();
+        view.calc_refdef(&mut cl, &[0.0; 3], 0.0, &params);
+        cl.onground = true;
+        cl.entities[1].state.origin[2] += 16.0;
+        view.calc_refdef(&mut cl, &[0.0; 3], 0.0, &params);
+        // the view moves up by 80 units per second
+        assert!((view.refdef.vieworg[2] - (24.0 + 8.0 + 22.0 + 1.0 / 32.0)).abs() < 0.001);
+        cl.entities[1].state.origin[2] += 32.0;
+        view.calc_refdef(&mut cl, &[0.0; 3], 0.0, &params);
+        // but lags behind by at most 12 units
+        assert!((view.refdef.vieworg[2] - (72.0 - 12.0 + 22.0 + 1.0 / 32.0)).abs() < 0.001);
+    }
+}
@@ -0,0 +1,534 @@
+#![warn(missing_docs)]
+
+//! Area node tree used to find entities by their bounding box.
+//!
+//! Original source can be found in world.c
+
+use rquake_common::Vec3;
+use rquake_common::mathlib::{vector_add, vector_subtract};
+use progdefs;
+use progs::Progs;
+use server::{self, Server};
+use trace::{self, Hull, Trace};
+
+const AREA_DEPTH : usize = 4;
+
+/// Kind of entities returned by an area query.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AreaType {
+    /// Entities that block movement.
+    Solid,
+    /// Entities that only trigger touch functions.
+    Triggers,
+}
+
+/// How a move is clipped against entities.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoveClip {
+    /// Clips against everything.
+    Normal,
+    /// Only clips against brush models.
+    NoMonsters,
+    /// Uses a larger box when clipping against monsters.
+    Missile,
+}
+
+/// Node of the area tree. The world is split in half along the x or y axis
+/// at every level, entities are linked into the smallest node containing them.
+pub struct AreaNode {
+    axis : Option<usize>,
+    dist : f32,
+    children : [usize; 2],
+    trigger_edicts : Vec<usize>,
+    solid_edicts : Vec<usize>,
+}
+
+impl Server {
+    /// Rebuilds the area tree for a world of the given size and removes all links.
+    pub fn clear_world(&mut self, mins : Vec3, maxs : Vec3) {
+        self.areanodes.clear();
+        for e in self.edicts.iter_mut() {
+            e.area = None;
+        }
+        create_area_node(&mut self.areanodes, 0, mins, maxs);
+    }
+
+    /// Removes an entity from the area tree.
+    pub fn unlink_edict(&mut self, ent : usize) {
+        if let Some(node) = self.edicts[ent].area.take() {
+            let node = &mut self.areanodes[node];
+            node.trigger_edicts.retain(|&e| e != ent);
+            node.solid_edicts.retain(|&e| e != ent);
+        }
+    }
+
+    /// Updates the absolute bounding box of an entity and links it into the
+    /// area tree. Triggers touching the entity are run if touch_triggers is set.
+    pub fn link_edict(&mut self, ent : usize, touch_triggers : bool, progs : &mut Progs) {
+        self.unlink_edict(ent);
+        if ent == 0 || self.edicts[ent].free {
+            return;
+        }
+
+        let (absmin, absmax, solid) = {
+            let v = &self.edicts[ent].v;
+            let origin = v.vector(progdefs::ORIGIN);
+            let mins = v.vector(progdefs::MINS);
+            let maxs = v.vector(progdefs::MAXS);
+            let mut absmin = [0.0; 3];
+            let mut absmax = [0.0; 3];
+            for i in 0..3 {
+                absmin[i] = origin[i] + mins[i];
+                absmax[i] = origin[i] + maxs[i];
+            }
+
+            // items are picked up from a bit further away, everything else is
+            // expanded a bit to catch entities touching on the edges
+            if v.float(progdefs::FLAGS) as i32 & server::FL_ITEM != 0 {
+                for i in 0..2 {
+                    absmin[i] -= 15.0;
+                    absmax[i] += 15.0;
+                }
+            } else {
+                for i in 0..3 {
+                    absmin[i] -= 1.0;
+                    absmax[i] += 1.0;
+                }
+            }
+            (absmin, absmax, v.float(progdefs::SOLID))
+        };
+        {
+            let v = &mut self.edicts[ent].v;
+            v.set_vector(progdefs::ABSMIN, absmin);
+            v.set_vector(progdefs::ABSMAX, absmax);
+        }
+
+        // link to PVS leafs
+        let leafnums = match self.worldmodel {
+            Some(ref bsp) if self.edicts[ent].v.float(progdefs::MODELINDEX) != 0.0 => bsp.box_leafs(&absmin, &absmax, server::MAX_ENT_LEAFS),
+            _ => Vec::new(),
+        };
+        self.edicts[ent].leafnums = leafnums;
+
+        if solid == server::SOLID_NOT || self.areanodes.is_empty() {
+            return;
+        }
+
+        let mut index = 0;
+        while let Some(axis) = self.areanodes[index].axis {
+            let node = &self.areanodes[index];
+            if absmin[axis] > node.dist {
+                index = node.children[0];
+            } else if absmax[axis] < node.dist {
+                index = node.children[1];
+            } else {
+                break;
+            }
+        }
+
+        if solid == server::SOLID_TRIGGER {
+            self.areanodes[index].trigger_edicts.push(ent);
+        } else {
+            self.areanodes[index].solid_edicts.push(ent);
+        }
+        self.edicts[ent].area = Some(index);
+
+        if touch_triggers {
+            self.touch_links(ent, progs);
+        }
+    }
+
+    /// Returns all linked entities of the given type whose absolute bounding
+    /// box intersects the given box.
+    pub fn area_edicts(&self, mins : Vec3, maxs : Vec3, area_type : AreaType) -> Vec<usize> {
+        let mut list = Vec::new();
+        if !self.areanodes.is_empty() {
+            self.area_edicts_r(0, mins, maxs, area_type, &mut list);
+        }
+        list
+    }
+
+    fn area_edicts_r(&self, index : usize, mins : Vec3, maxs : Vec3, area_type : AreaType, list : &mut Vec<usize>) {
+        let node = &self.areanodes[index];
+        let edicts = match area_type {
+            AreaType::Solid => &node.solid_edicts,
+            AreaType::Triggers => &node.trigger_edicts,
+        };
+        for &ent in edicts {
+            let v = &self.edicts[ent].v;
+            if boxes_overlap(mins, maxs, v.vector(progdefs::ABSMIN), v.vector(progdefs::ABSMAX)) {
+                list.push(ent);
+            }
+        }
+
+        if let Some(axis) = node.axis {
+            if maxs[axis] > node.dist {
+                self.area_edicts_r(node.children[0], mins, maxs, area_type, list);
+            }
+            if mins[axis] < node.dist {
+                self.area_edicts_r(node.children[1], mins, maxs, area_type, list);
+            }
+        }
+    }
+
+    /// Returns the contents at a point in the world, water currents are water.
+    pub fn point_contents(&self, p : Vec3) -> i32 {
+        let contents = self.true_point_contents(p);
+        if contents <= trace::CONTENTS_CURRENT_0 && contents >= trace::CONTENTS_CURRENT_DOWN {
+            trace::CONTENTS_WATER
+        } else {
+            contents
+        }
+    }
+
+    /// Returns the contents at a point in the world.
+    pub fn true_point_contents(&self, p : Vec3) -> i32 {
+        match self.models.get(1) {
+            Some(&Some(ref world)) => world.hulls[0].point_contents(world.hulls[0].first_clipnode, p),
+            _ => trace::CONTENTS_EMPTY,
+        }
+    }
+
+    /// Returns the entity blocking the position of an entity, or None if it's free.
+    pub fn test_entity_position(&self, ent : usize) -> Option<usize> {
+        let (origin, mins, maxs) = {
+            let v = &self.edicts[ent].v;
+            (v.vector(progdefs::ORIGIN), v.vector(progdefs::MINS), v.vector(progdefs::MAXS))
+        };
+        let trace = self.trace_move(origin, mins, maxs, origin, MoveClip::Normal, Some(ent));
+        if trace.startsolid {
+            Some(trace.ent.unwrap_or(0))
+        } else {
+            None
+        }
+    }
+
+    /// Moves a box from start to end and clips it against the world and all
+    /// solid entities except pass_edict and the entities it owns or is owned by.
+    pub fn trace_move(&self, start : Vec3, mins : Vec3, maxs : Vec3, end : Vec3,
+                      clip : MoveClip, pass_edict : Option<usize>) -> Trace {
+        // clip to world, there's nothing to clip against before a map is loaded
+        let mut trace = if self.edicts[0].v.float(progdefs::SOLID) == server::SOLID_BSP {
+            self.clip_move_to_entity(0, start, mins, maxs, end)
+        } else {
+            let mut trace = Trace::new(end);
+            trace.allsolid = false;
+            trace
+        };
+
+        let (mins2, maxs2) = if clip == MoveClip::Missile {
+            ([-15.0; 3], [15.0; 3])
+        } else {
+            (mins, maxs)
+        };
+
+        // create the bounding box of the entire move
+        let mut boxmins = [0.0; 3];
+        let mut boxmaxs = [0.0; 3];
+        for i in 0..3 {
+            boxmins[i] = start[i].min(end[i]) + mins2[i] - 1.0;
+            boxmaxs[i] = start[i].max(end[i]) + maxs2[i] + 1.0;
+        }
+
+        // clip to entities
+        for touch in self.area_edicts(boxmins, boxmaxs, AreaType::Solid) {
+            let v = &self.edicts[touch].v;
+            let solid = v.float(progdefs::SOLID);
+            if solid == server::SOLID_NOT || Some(touch) == pass_edict {
+                continue;
+            }
+            if clip == MoveClip::NoMonsters && solid != server::SOLID_BSP {
+                continue;
+            }
+            if let Some(pass) = pass_edict {
+                let pass_v = &self.edicts[pass].v;
+                // points never interact
+                if pass_v.float(progdefs::SIZE) != 0.0 && v.float(progdefs::SIZE) == 0.0 {
+                    continue;
+                }
+                // don't clip against own missiles or the owner
+                if v.int(progdefs::OWNER) == pass as i32 || pass_v.int(progdefs::OWNER) == touch as i32 {
+                    continue;
+                }
+            }
+            if trace.allsolid {
+                break;
+            }
+
+            let mut t = if v.float(progdefs::FLAGS) as i32 & server::FL_MONSTER != 0 {
+                self.clip_move_to_entity(touch, start, mins2, maxs2, end)
+            } else {
+                self.clip_move_to_entity(touch, start, mins, maxs, end)
+            };
+            if t.allsolid || t.startsolid || t.fraction < trace.fraction {
+                t.ent = Some(touch);
+                t.startsolid |= trace.startsolid;
+                trace = t;
+            } else if t.startsolid {
+                trace.startsolid = true;
+            }
+        }
+        trace
+    }
+
+    /// Moves a box from start to end and clips it against a single entity.
+    pub fn clip_move_to_entity(&self, ent : usize, start : Vec3, mins : Vec3, maxs : Vec3, end : Vec3) -> Trace {
+        let (hull, offset) = self.hull_for_entity(ent, mins, maxs);
+        let start_l = vector_subtract(&start, &offset);
+        let end_l = vector_subtract(&end, &offset);
+
+        let mut trace = hull.trace(start_l, end_l);
+        if trace.fraction != 1.0 {
+            trace.endpos = vector_add(&trace.endpos, &offset);
+        } else {
+            trace.endpos = end;
+        }
+        if trace.fraction < 1.0 || trace.startsolid {
+            trace.ent = Some(ent);
+        }
+        trace
+    }
+
+    /// Returns the hull to clip a box of the given size against an entity and
+    /// the offset of the hull. Brush models use the best fitting hull, other
+    /// entities a box expanded by the size of the moving box.
+    fn hull_for_entity(&self, ent : usize, mins : Vec3, maxs : Vec3) -> (Hull, Vec3) {
+        let v = &self.edicts[ent].v;
+        let origin = v.vector(progdefs::ORIGIN);
+
+        if v.float(progdefs::SOLID) == server::SOLID_BSP {
+            let model = self.models.get(v.float(progdefs::MODELINDEX) as usize);
+            match model {
+                Some(&Some(ref model)) => {
+                    let size = maxs[0] - mins[0];
+                    let hull = if size < 3.0 {
+                        &model.hulls[0]
+                    } else if size <= 32.0 {
+                        &model.hulls[1]
+                    } else {
+                        &model.hulls[2]
+                    };
+                    // calculate an offset value to center the origin
+                    let offset = vector_add(&vector_subtract(&hull.clip_mins, &mins), &origin);
+                    return (hull.clone(), offset);
+                },
+                _ => println!("SOLID_BSP with a non bsp model"),
+            }
+        }
+
+        let hullmins = vector_subtract(&v.vector(progdefs::MINS), &maxs);
+        let hullmaxs = vector_subtract(&v.vector(progdefs::MAXS), &mins);
+        (Hull::for_box(hullmins, hullmaxs), origin)
+    }
+
+    /// Runs the touch functions of all triggers touching the entity.
+    fn touch_links(&mut self, ent : usize, progs : &mut Progs) {
+        let (absmin, absmax) = {
+            let v = &self.edicts[ent].v;
+            (v.vector(progdefs::ABSMIN), v.vector(progdefs::ABSMAX))
+        };
+
+        // a touch function can unlink or free entities, so the list
+        // is collected first and every trigger is checked again
+        for touch in self.area_edicts(absmin, absmax, AreaType::Triggers) {
+            if touch == ent {
+                continue;
+            }
+            let function = {
+                let e = &self.edicts[touch];
+                if e.free || e.v.float(progdefs::SOLID) != server::SOLID_TRIGGER ||
+                    !boxes_overlap(absmin, absmax, e.v.vector(progdefs::ABSMIN), e.v.vector(progdefs::ABSMAX)) {
+                    continue;
+                }
+                e.v.int(progdefs::TOUCH)
+            };
+            if function == 0 {
+                continue;
+            }
+
+            let old_self = self.globals.int(progdefs::G_SELF);
+            let old_other = self.globals.int(progdefs::G_OTHER);
+            let time = self.time;
+            self.globals.set_int(progdefs::G_SELF, touch as i32);
+            self.globals.set_int(progdefs::G_OTHER, ent as i32);
+            self.globals.set_float(progdefs::G_TIME, time);
+            progs.execute(self, function);
+            self.globals.set_int(progdefs::G_SELF, old_self);
+            self.globals.set_int(progdefs::G_OTHER, old_other);
+        }
+    }
+}
+
+/// Builds the tree recursively and returns the index of the created node.
+fn create_area_node(nodes : &mut Vec<AreaNode>, depth : usize, mins : Vec3, maxs : Vec3) -> usize {
+    let index = nodes.len();
+    nodes.push(AreaNode {
+        axis : None,
+        dist : 0.0,
+        children : [0; 2],
+        trigger_edicts : Vec::new(),
+        solid_edicts : Vec::new(),
+    });
+    if depth == AREA_DEPTH {
+        return index;
+    }
+
+    let axis = if maxs[0] - mins[0] > maxs[1] - mins[1] { 0 } else { 1 };
+    let dist = 0.5 * (maxs[axis] + mins[axis]);
+    let mut maxs1 = maxs;
+    let mut mins2 = mins;
+    maxs1[axis] = dist;
+    mins2[axis] = dist;
+
+    let front = create_area_node(nodes, depth + 1, mins2, maxs);
+    let back = create_area_node(nodes, depth + 1, mins, maxs1);
+    let node = &mut nodes[index];
+    node.axis = Some(axis);
+    node.dist = dist;
+    node.children = [front, back];
+    index
+}
+
+fn boxes_overlap(mins1 : Vec3, maxs1 : Vec3, mins2 : Vec3, maxs2 : Vec3) -> bool {
+    (0..3).all(|i| mins1[i] <= maxs2[i] && maxs1[i] >= mins2[i])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TouchRecorder {
+        calls : Vec<(i32, i32, i32)>,
+    }
+
+    impl Progs for TouchRecorder {
+        fn execute(&mut self, sv : &mut Server, function : i32) {
+            self.calls.push((function, sv.globals.int(progdefs::G_SELF), sv.globals.int(progdefs::G_OTHER)));
+        }
+    }
+
+    fn spawn_box(sv : &mut Server, origin : Vec3, solid : f32) -> usize {
+        let ent = sv.alloc_edict().unwrap();
+        let v = &mut sv.edicts[ent].v;
+        v.set_vector(progdefs::ORIGIN, origin);
+        v.set_vector(progdefs::MINS, [-16.0; 3]);
+        v.set_vector(progdefs::MAXS, [16.0; 3]);
+        v.set_float(progdefs::SOLID, solid);
+        ent
+    }
+
+    #[test]
+    fn link_and_find_by_box() {
+        let mut sv = Server::new(1);
+        let mut progs = TouchRecorder { calls : Vec::new() };
+        assert_eq!(sv.areanodes.len(), 31);
+
+        let near = spawn_box(&mut sv, [100.0, 100.0, 0.0], server::SOLID_BBOX);
+        let far = spawn_box(&mut sv, [-2000.0, 1500.0, 0.0], server::SOLID_BBOX);
+        let center = spawn_box(&mut sv, [0.0, 0.0, 0.0], server::SOLID_BBOX);
+        let ghost = spawn_box(&mut sv, [100.0, 100.0, 0.0], server::SOLID_NOT);
+        for &ent in &[near, far, center, ghost] {
+            sv.link_edict(ent, false, &mut progs);
+        }
+        assert_eq!(sv.edicts[near].v.vector(progdefs::ABSMIN), [83.0, 83.0, -17.0]);
+        assert!(sv.edicts[ghost].area.is_none());
+
+        let mut found = sv.area_edicts([50.0, 50.0, -10.0], [90.0, 90.0, 10.0], AreaType::Solid);
+        found.sort();
+        assert_eq!(found, vec![near]);
+        let mut found = sv.area_edicts([-4096.0; 3], [4096.0; 3], AreaType::Solid);
+        found.sort();
+        assert_eq!(found, vec![near, far, center]);
+        assert!(sv.area_edicts([-4096.0; 3], [4096.0; 3], AreaType::Triggers).is_empty());
+
+        sv.free_edict(near);
+        assert!(sv.area_edicts([50.0, 50.0, -10.0], [90.0, 90.0, 10.0], AreaType::Solid).is_empty());
+    }
+
+    #[test]
+    fn trace_against_world_and_entities() {
+        use std::rc::Rc;
+        use model::BrushModel;
+        use trace::{ClipNode, Plane, CONTENTS_EMPTY, CONTENTS_SOLID};
+
+        // world with solid below z = 0, the player hull is expanded by its box
+        let floor = Hull {
+            clipnodes : Rc::new(vec![ClipNode { plane : 0, children : [CONTENTS_EMPTY, CONTENTS_SOLID] }]),
+            planes : Rc::new(vec![Plane { normal : [0.0, 0.0, 1.0], dist : 0.0, kind : 2 }]),
+            first_clipnode : 0,
+            clip_mins : [0.0; 3],
+            clip_maxs : [0.0; 3],
+        };
+        let mut player_floor = floor.clone();
+        player_floor.planes = Rc::new(vec![Plane { normal : [0.0, 0.0, 1.0], dist : 24.0, kind : 2 }]);
+        player_floor.clip_mins = [-16.0, -16.0, -24.0];
+        let mut sv = Server::new(1);
+        let mut progs = TouchRecorder { calls : Vec::new() };
+        sv.models = vec![None, Some(Rc::new(BrushModel {
+            mins : [-4096.0; 3],
+            maxs : [4096.0; 3],
+            hulls : [floor.clone(), player_floor, floor],
+        }))];
+        sv.edicts[0].v.set_float(progdefs::MODELINDEX, 1.0);
+        sv.edicts[0].v.set_float(progdefs::SOLID, server::SOLID_BSP);
+        sv.edicts[0].v.set_float(progdefs::MOVETYPE, server::MOVETYPE_PUSH);
+        assert_eq!(sv.point_contents([0.0, 0.0, -1.0]), CONTENTS_SOLID);
+
+        let trace = sv.trace_move([0.0, 0.0, 100.0], [-16.0, -16.0, -24.0], [16.0, 16.0, 32.0], [0.0, 0.0, 0.0],
+                                  MoveClip::Normal, None);
+        assert_eq!(trace.ent, Some(0));
+        assert!((trace.endpos[2] - 24.0).abs() < 0.1);
+        assert_eq!(trace.plane_normal, [0.0, 0.0, 1.0]);
+
+        let monster = spawn_box(&mut sv, [0.0, 0.0, 50.0], server::SOLID_SLIDEBOX);
+        sv.link_edict(monster, false, &mut progs);
+        let trace = sv.trace_move([-100.0, 0.0, 50.0], [0.0; 3], [0.0; 3], [100.0, 0.0, 50.0], MoveClip::Normal, None);
+        assert_eq!(trace.ent, Some(monster));
+        assert!((trace.endpos[0] + 16.0).abs() < 0.1);
+        assert_eq!(trace.plane_normal, [-1.0, 0.0, 0.0]);
+
+        let trace = sv.trace_move([-100.0, 0.0, 50.0], [0.0; 3], [0.0; 3], [100.0, 0.0, 50.0], MoveClip::NoMonsters, None);
+        assert_eq!((trace.fraction, trace.ent), (1.0, None));
+        let trace = sv.trace_move([-100.0, 0.0, 50.0], [0.0; 3], [0.0; 3], [100.0, 0.0, 50.0], MoveClip::Normal, Some(monster));
+        assert_eq!(trace.fraction, 1.0);
+
+        // missiles don't hit their owner
+        let missile = spawn_box(&mut sv, [-100.0, 0.0, 50.0], server::SOLID_BBOX);
+        sv.edicts[missile].v.set_int(progdefs::OWNER, monster as i32);
+        let trace = sv.trace_move([-100.0, 0.0, 50.0], [0.0; 3], [0.0; 3], [100.0, 0.0, 50.0], MoveClip::Missile, Some(missile));
+        assert_eq!(trace.fraction, 1.0);
+
+        assert_eq!(sv.test_entity_position(monster), None);
+        sv.edicts[monster].v.set_vector(progdefs::ORIGIN, [0.0, 0.0, 10.0]);
+        assert_eq!(sv.test_entity_position(monster), Some(0));
+    }
+
+    #[test]
+    fn touch_triggers() {
+        let mut sv = Server::new(1);
+        let mut progs = TouchRecorder { calls : Vec::new() };
+
+        let trigger = spawn_box(&mut sv, [0.0, 0.0, 0.0], server::SOLID_TRIGGER);
+        sv.edicts[trigger].v.set_int(progdefs::TOUCH, 7);
+        sv.link_edict(trigger, false, &mut progs);
+        let item = spawn_box(&mut sv, [60.0, 0.0, 0.0], server::SOLID_TRIGGER);
+        sv.edicts[item].v.set_int(progdefs::TOUCH, 9);
+        sv.edicts[item].v.set_float(progdefs::FLAGS, server::FL_ITEM as f32);
+        sv.link_edict(item, false, &mut progs);
+
+        let player = spawn_box(&mut sv, [200.0, 0.0, 0.0], server::SOLID_SLIDEBOX);
+        sv.link_edict(player, true, &mut progs);
+        assert!(progs.calls.is_empty());
+
+        sv.edicts[player].v.set_vector(progdefs::ORIGIN, [100.0, 0.0, 0.0]);
+        sv.link_edict(player, true, &mut progs);
+        assert_eq!(progs.calls, vec![(9, item as i32, player as i32)]);
+
+        progs.calls.clear();
+        sv.edicts[player].v.set_vector(progdefs::ORIGIN, [30.0, 0.0, 0.0]);
+        sv.link_edict(player, true, &mut progs);
+        progs.calls.sort();
+        assert_eq!(progs.calls, vec![(7, trigger as i32, player as i32), (9, item as i32, player as i32)]);
+        assert_eq!(sv.globals.int(progdefs::G_SELF), 0);
+    }
+}
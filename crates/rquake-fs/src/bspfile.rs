@@ -0,0 +1,760 @@
+#![warn(missing_docs)]
+
+//! Handling of .bsp map files and queries of their visibility data.
+//!
+//! Original source can be found in bspfile.h, model.c and sv_main.c
+
+extern crate byteorder;
+
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+use self::byteorder::{LittleEndian, ReadBytesExt};
+
+use error;
+
+const BSPVERSION : i32 = 29;
+
+const LUMP_ENTITIES : usize = 0;
+const LUMP_PLANES : usize = 1;
+const LUMP_TEXTURES : usize = 2;
+const LUMP_VERTEXES : usize = 3;
+const LUMP_VISIBILITY : usize = 4;
+const LUMP_NODES : usize = 5;
+const LUMP_TEXINFO : usize = 6;
+const LUMP_FACES : usize = 7;
+const LUMP_LIGHTING : usize = 8;
+const LUMP_CLIPNODES : usize = 9;
+const LUMP_LEAFS : usize = 10;
+const LUMP_MARKSURFACES : usize = 11;
+const LUMP_EDGES : usize = 12;
+const LUMP_SURFEDGES : usize = 13;
+const LUMP_MODELS : usize = 14;
+const HEADER_LUMPS : usize = 15;
+
+/// Contents of solid leafs.
+const CONTENTS_SOLID : i32 = -2;
+
+/// Maximum number of hulls of a model.
+pub const MAX_MAP_HULLS : usize = 4;
+
+/// Maximum number of light styles of a face.
+pub const MAXLIGHTMAPS : usize = 4;
+
+/// Texture info flag for sky and liquid textures that have no lightmaps.
+pub const TEX_SPECIAL : i32 = 1;
+
+/// Splitting plane of the BSP tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspPlane {
+    /// Normal of the plane.
+    pub normal : [f32; 3],
+    /// Distance from the origin.
+    pub dist : f32,
+    /// 0, 1 or 2 for planes along the x, y or z axis.
+    pub kind : i32,
+}
+
+/// Node of the drawing and visibility tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspNode {
+    /// Index of the splitting plane.
+    pub plane : i32,
+    /// Front and back child. Negative numbers n are leaf -(n + 1).
+    pub children : [i16; 2],
+    /// Bounding box for frustum culling.
+    pub mins : [i16; 3],
+    /// Bounding box for frustum culling.
+    pub maxs : [i16; 3],
+    /// First face of the node.
+    pub first_face : u16,
+    /// Number of faces of the node.
+    pub num_faces : u16,
+}
+
+/// Node of a clipping hull.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspClipNode {
+    /// Index of the splitting plane.
+    pub plane : i32,
+    /// Front and back child. Negative numbers are contents.
+    pub children : [i16; 2],
+}
+
+/// Leaf of the drawing and visibility tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspLeaf {
+    /// Contents of the leaf.
+    pub contents : i32,
+    /// Offset of the compressed visibility data, -1 for none.
+    pub visofs : i32,
+    /// Bounding box for frustum culling.
+    pub mins : [i16; 3],
+    /// Bounding box for frustum culling.
+    pub maxs : [i16; 3],
+    /// First entry of the mark surfaces of the leaf.
+    pub first_marksurface : u16,
+    /// Number of mark surfaces of the leaf.
+    pub num_marksurfaces : u16,
+    /// Volumes of the ambient sounds.
+    pub ambient_level : [u8; 4],
+}
+
+/// Texture of a map, only the full size mip level is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspTexture {
+    /// Texture name, sky textures start with sky and liquids with *.
+    pub name : String,
+    /// Width in pixels.
+    pub width : u32,
+    /// Height in pixels.
+    pub height : u32,
+    /// Palette indices.
+    pub pixels : Vec<u8>,
+}
+
+/// Mapping of a texture onto faces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspTexInfo {
+    /// The s and t axis with the offset as fourth value.
+    pub vecs : [[f32; 4]; 2],
+    /// Index of the texture.
+    pub miptex : i32,
+    /// `TEX_SPECIAL` for sky and liquids.
+    pub flags : i32,
+}
+
+/// Polygon of the map.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspFace {
+    /// Index of the plane.
+    pub plane : u16,
+    /// Non zero if the face is on the back side of the plane.
+    pub side : i16,
+    /// First entry of the surface edges.
+    pub first_edge : i32,
+    /// Number of edges.
+    pub num_edges : i16,
+    /// Index of the texture info.
+    pub texinfo : i16,
+    /// Light styles of the lightmaps, 255 for unused ones.
+    pub styles : [u8; MAXLIGHTMAPS],
+    /// Offset of the lightmaps in the lighting data, -1 for none.
+    pub lightofs : i32,
+}
+
+/// The world or a brush entity like a door.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BspModel {
+    /// Bounding box.
+    pub mins : [f32; 3],
+    /// Bounding box.
+    pub maxs : [f32; 3],
+    /// Origin of the model.
+    pub origin : [f32; 3],
+    /// First node of the drawing tree and the clipping hulls.
+    pub headnode : [i32; MAX_MAP_HULLS],
+    /// Number of leafs, not counting the solid leaf 0.
+    pub visleafs : i32,
+    /// First face of the model.
+    pub first_face : i32,
+    /// Number of faces of the model.
+    pub num_faces : i32,
+}
+
+/// Contents of a .bsp file.
+#[derive(Default)]
+pub struct BspFile {
+    /// Entity definitions.
+    pub entities : String,
+    /// Splitting planes.
+    pub planes : Vec<BspPlane>,
+    /// Textures, None for textures that are missing in the file.
+    pub textures : Vec<Option<BspTexture>>,
+    /// Vertices of the faces.
+    pub vertexes : Vec<[f32; 3]>,
+    /// Compressed visibility data.
+    pub visibility : Vec<u8>,
+    /// Nodes of the drawing tree.
+    pub nodes : Vec<BspNode>,
+    /// Texture mappings.
+    pub texinfo : Vec<BspTexInfo>,
+    /// Polygons.
+    pub faces : Vec<BspFace>,
+    /// Lightmaps with one byte per sample.
+    pub lighting : Vec<u8>,
+    /// Nodes of the clipping hulls.
+    pub clipnodes : Vec<BspClipNode>,
+    /// Leafs of the drawing tree.
+    pub leafs : Vec<BspLeaf>,
+    /// Face indices, each leaf owns a range starting at its first marksurface.
+    pub marksurfaces : Vec<u16>,
+    /// Vertex indices of the edges.
+    pub edges : Vec<[u16; 2]>,
+    /// Edges of the faces, negative numbers are edges used in reverse.
+    pub surfedges : Vec<i32>,
+    /// The world and the brush entities.
+    pub models : Vec<BspModel>,
+}
+
+impl BspFile {
+    /// Reads a .bsp file.
+    pub fn read(data : &[u8]) -> Result<BspFile, error::ReadError> {
+        let mut reader = Cursor::new(data);
+        let version = reader.read_i32::<LittleEndian>()?;
+        if version != BSPVERSION {
+            println!("Mod_LoadBrushModel: has wrong version number ({} should be {})", version, BSPVERSION);
+            return Err(error::ReadError::ParseError);
+        }
+
+        let mut lumps = [(0usize, 0usize); HEADER_LUMPS];
+        for lump in lumps.iter_mut() {
+            let offset = reader.read_i32::<LittleEndian>()?;
+            let length = reader.read_i32::<LittleEndian>()?;
+            if offset < 0 || length < 0 || offset as usize + length as usize > data.len() {
+                return Err(error::ReadError::ParseError);
+            }
+            *lump = (offset as usize, length as usize);
+        }
+        let lump = |index : usize| {
+            let (offset, length) = lumps[index];
+            &data[offset..offset + length]
+        };
+
+        let entities = lump(LUMP_ENTITIES);
+        let entities = entities.split(|&c| c == 0).next().unwrap_or(entities);
+
+        Ok(BspFile {
+            entities : String::from_utf8_lossy(entities).into_owned(),
+            planes : read_lump(lump(LUMP_PLANES), 20, read_plane)?,
+            textures : read_textures(lump(LUMP_TEXTURES))?,
+            vertexes : read_lump(lump(LUMP_VERTEXES), 12, read_vector)?,
+            visibility : lump(LUMP_VISIBILITY).to_vec(),
+            nodes : read_lump(lump(LUMP_NODES), 24, read_node)?,
+            texinfo : read_lump(lump(LUMP_TEXINFO), 40, read_texinfo)?,
+            faces : read_lump(lump(LUMP_FACES), 20, read_face)?,
+            lighting : lump(LUMP_LIGHTING).to_vec(),
+            clipnodes : read_lump(lump(LUMP_CLIPNODES), 8, read_clipnode)?,
+            leafs : read_lump(lump(LUMP_LEAFS), 28, read_leaf)?,
+            marksurfaces : read_lump(lump(LUMP_MARKSURFACES), 2, |reader| Ok(reader.read_u16::<LittleEndian>()?))?,
+            edges : read_lump(lump(LUMP_EDGES), 4, |reader| {
+                Ok([reader.read_u16::<LittleEndian>()?, reader.read_u16::<LittleEndian>()?])
+            })?,
+            surfedges : read_lump(lump(LUMP_SURFEDGES), 4, |reader| Ok(reader.read_i32::<LittleEndian>()?))?,
+            models : read_lump(lump(LUMP_MODELS), 64, read_model)?,
+        })
+    }
+
+    /// Returns the number of bytes of an uncompressed visibility row, one
+    /// bit for every leaf.
+    pub fn vis_row_size(&self) -> usize {
+        (self.leafs.len() + 7) >> 3
+    }
+
+    /// Returns the index of the leaf containing a point.
+    pub fn point_in_leaf(&self, p : &[f32; 3]) -> usize {
+        let mut num = 0i32;
+        while num >= 0 {
+            let node = match self.nodes.get(num as usize) {
+                Some(node) => node,
+                None => return 0,
+            };
+            let plane = match self.planes.get(node.plane as usize) {
+                Some(plane) => plane,
+                None => return 0,
+            };
+            let d = p[0] * plane.normal[0] + p[1] * plane.normal[1] + p[2] * plane.normal[2] - plane.dist;
+            num = if d > 0.0 { node.children[0] } else { node.children[1] } as i32;
+        }
+        (-1 - num) as usize
+    }
+
+    /// Returns the potentially visible set of a leaf with a bit for every
+    /// leaf, bit 0 is leaf 1. Everything is visible from the solid leaf 0
+    /// and from leafs without visibility data.
+    pub fn leaf_pvs(&self, leaf : usize) -> Vec<u8> {
+        let row = self.vis_row_size();
+        match self.leafs.get(leaf) {
+            Some(l) if leaf != 0 && l.visofs >= 0 && (l.visofs as usize) < self.visibility.len() => {
+                decompress_vis(&self.visibility[l.visofs as usize..], row)
+            }
+            _ => vec![0xff; row],
+        }
+    }
+
+    /// Returns true if a leaf is potentially visible from another leaf.
+    pub fn leafs_visible(&self, from : usize, to : usize) -> bool {
+        leaf_visible(&self.leaf_pvs(from), to)
+    }
+
+    /// Returns true if a point is potentially visible from another point.
+    pub fn point_visible(&self, from : &[f32; 3], to : &[f32; 3]) -> bool {
+        self.leafs_visible(self.point_in_leaf(from), self.point_in_leaf(to))
+    }
+
+    /// Returns the leafs touched by a box, at most max leafs. The solid
+    /// leaf 0 isn't returned.
+    pub fn box_leafs(&self, mins : &[f32; 3], maxs : &[f32; 3], max : usize) -> Vec<usize> {
+        let mut leafs = Vec::new();
+        if !self.nodes.is_empty() {
+            self.box_leafs_r(0, mins, maxs, max, &mut leafs);
+        }
+        leafs
+    }
+
+    fn box_leafs_r(&self, num : i32, mins : &[f32; 3], maxs : &[f32; 3], max : usize, leafs : &mut Vec<usize>) {
+        if num < 0 {
+            let leaf = (-1 - num) as usize;
+            let solid = self.leafs.get(leaf).map_or(true, |l| l.contents == CONTENTS_SOLID);
+            if !solid && leafs.len() < max {
+                leafs.push(leaf);
+            }
+            return;
+        }
+        let (node, plane) = match self.nodes.get(num as usize).and_then(|node| Some((node, self.planes.get(node.plane as usize)?))) {
+            Some(found) => found,
+            None => return,
+        };
+
+        // distances of the corners closest to and farthest from the plane
+        let mut far = 0.0;
+        let mut near = 0.0;
+        for i in 0..3 {
+            let (a, b) = if plane.normal[i] >= 0.0 { (maxs[i], mins[i]) } else { (mins[i], maxs[i]) };
+            far += plane.normal[i] * a;
+            near += plane.normal[i] * b;
+        }
+        if far >= plane.dist {
+            self.box_leafs_r(node.children[0] as i32, mins, maxs, max, leafs);
+        }
+        if near < plane.dist {
+            self.box_leafs_r(node.children[1] as i32, mins, maxs, max, leafs);
+        }
+    }
+
+    /// Returns the potentially visible set of all leafs within the radius
+    /// of a point, so nothing is missed when the point is close to a leaf
+    /// boundary.
+    pub fn fat_pvs(&self, org : &[f32; 3], radius : f32) -> Vec<u8> {
+        let mut pvs = vec![0; self.vis_row_size()];
+        if self.nodes.is_empty() {
+            return pvs;
+        }
+        self.fat_pvs_r(0, org, radius, &mut pvs);
+        pvs
+    }
+
+    fn fat_pvs_r(&self, mut num : i32, org : &[f32; 3], radius : f32, pvs : &mut Vec<u8>) {
+        while num >= 0 {
+            let (node, plane) = match self.nodes.get(num as usize).and_then(|node| Some((node, self.planes.get(node.plane as usize)?))) {
+                Some(found) => found,
+                None => return,
+            };
+            let d = org[0] * plane.normal[0] + org[1] * plane.normal[1] + org[2] * plane.normal[2] - plane.dist;
+            if d > radius {
+                num = node.children[0] as i32;
+            } else if d < -radius {
+                num = node.children[1] as i32;
+            } else {
+                // go down both
+                self.fat_pvs_r(node.children[0] as i32, org, radius, pvs);
+                num = node.children[1] as i32;
+            }
+        }
+
+        // if this is a leaf, accumulate the pvs bits
+        let leaf = (-1 - num) as usize;
+        if self.leafs.get(leaf).map_or(false, |l| l.contents != CONTENTS_SOLID) {
+            for (dest, src) in pvs.iter_mut().zip(self.leaf_pvs(leaf)) {
+                *dest |= src;
+            }
+        }
+    }
+
+    /// Returns the potentially hearable set of every leaf. A leaf hears
+    /// all leafs that are visible from any leaf in its potentially visible
+    /// set.
+    pub fn calc_phs(&self) -> Vec<Vec<u8>> {
+        let num = self.leafs.len();
+        let pvs : Vec<Vec<u8>> = (0..num).map(|leaf| self.leaf_pvs(leaf)).collect();
+        pvs.iter().map(|scan| {
+            let mut phs = scan.clone();
+            for index in (1..num).filter(|&index| leaf_visible(scan, index)) {
+                for (dest, src) in phs.iter_mut().zip(pvs[index].iter()) {
+                    *dest |= *src;
+                }
+            }
+            phs
+        }).collect()
+    }
+}
+
+/// Decompresses a visibility row. A zero byte is followed by the number
+/// of zero bytes it stands for, missing data is treated as not visible.
+pub fn decompress_vis(data : &[u8], row : usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(row);
+    let mut input = data.iter();
+    while out.len() < row {
+        match input.next() {
+            Some(&0) => {
+                let count = input.next().cloned().unwrap_or(0) as usize;
+                let count = count.min(row - out.len());
+                out.extend(std::iter::repeat(0).take(count));
+                if count == 0 {
+                    break;
+                }
+            }
+            Some(&bits) => out.push(bits),
+            None => break,
+        }
+    }
+    out.resize(row, 0);
+    out
+}
+
+/// Returns true if a leaf is set in an uncompressed visibility row, the
+/// solid leaf 0 is never visible.
+pub fn leaf_visible(vis : &[u8], leaf : usize) -> bool {
+    leaf != 0 && vis.get((leaf - 1) >> 3).map_or(false, |&bits| bits & (1 << ((leaf - 1) & 7)) != 0)
+}
+
+/// Reads all entries of a lump, the lump size has to be a multiple of the entry size.
+fn read_lump<T, F>(data : &[u8], size : usize, read_entry : F) -> Result<Vec<T>, error::ReadError>
+    where F : Fn(&mut Cursor<&[u8]>) -> Result<T, error::ReadError> {
+    if data.len() % size != 0 {
+        println!("MOD_LoadBmodel: funny lump size");
+        return Err(error::ReadError::ParseError);
+    }
+    let mut reader = Cursor::new(data);
+    let mut entries = Vec::with_capacity(data.len() / size);
+    for i in 0..data.len() / size {
+        reader.seek(SeekFrom::Start((i * size) as u64))?;
+        entries.push(read_entry(&mut reader)?);
+    }
+    Ok(entries)
+}
+
+fn read_vector(reader : &mut Cursor<&[u8]>) -> Result<[f32; 3], error::ReadError> {
+    Ok([reader.read_f32::<LittleEndian>()?, reader.read_f32::<LittleEndian>()?, reader.read_f32::<LittleEndian>()?])
+}
+
+fn read_short_vector(reader : &mut Cursor<&[u8]>) -> Result<[i16; 3], error::ReadError> {
+    Ok([reader.read_i16::<LittleEndian>()?, reader.read_i16::<LittleEndian>()?, reader.read_i16::<LittleEndian>()?])
+}
+
+fn read_plane(reader : &mut Cursor<&[u8]>) -> Result<BspPlane, error::ReadError> {
+    Ok(BspPlane {
+        normal : read_vector(reader)?,
+        dist : reader.read_f32::<LittleEndian>()?,
+        kind : reader.read_i32::<LittleEndian>()?,
+    })
+}
+
+fn read_node(reader : &mut Cursor<&[u8]>) -> Result<BspNode, error::ReadError> {
+    Ok(BspNode {
+        plane : reader.read_i32::<LittleEndian>()?,
+        children : [reader.read_i16::<LittleEndian>()?, reader.read_i16::<LittleEndian>()?],
+        mins : read_short_vector(reader)?,
+        maxs : read_short_vector(reader)?,
+        first_face : reader.read_u16::<LittleEndian>()?,
+        num_faces : reader.read_u16::<LittleEndian>()?,
+    })
+}
+
+/// Reads the texture lump, a list of offsets followed by the textures
+/// with their mip levels.
+fn read_textures(data : &[u8]) -> Result<Vec<Option<BspTexture>>, error::ReadError> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut reader = Cursor::new(data);
+    let count = reader.read_i32::<LittleEndian>()?;
+    let mut offsets = Vec::new();
+    for _ in 0..count.max(0) {
+        offsets.push(reader.read_i32::<LittleEndian>()?);
+    }
+
+    let mut textures = Vec::with_capacity(offsets.len());
+    for offset in offsets {
+        if offset < 0 {
+            textures.push(None);
+            continue;
+        }
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let mut name = [0u8; 16];
+        reader.read_exact(&mut name)?;
+        let name = name.split(|&c| c == 0).next().unwrap_or(&name);
+        let width = reader.read_u32::<LittleEndian>()?;
+        let height = reader.read_u32::<LittleEndian>()?;
+        let pixels_offset = reader.read_u32::<LittleEndian>()?;
+        if width & 15 != 0 || height & 15 != 0 {
+            println!("Texture {} is not 16 aligned", String::from_utf8_lossy(name));
+            return Err(error::ReadError::ParseError);
+        }
+
+        let start = offset as usize + pixels_offset as usize;
+        let size = width as usize * height as usize;
+        if start + size > data.len() {
+            return Err(error::ReadError::ParseError);
+        }
+        textures.push(Some(BspTexture {
+            name : String::from_utf8_lossy(name).into_owned(),
+            width : width,
+            height : height,
+            pixels : data[start..start + size].to_vec(),
+        }));
+    }
+    Ok(textures)
+}
+
+fn read_texinfo(reader : &mut Cursor<&[u8]>) -> Result<BspTexInfo, error::ReadError> {
+    let mut vecs = [[0.0; 4]; 2];
+    for v in vecs.iter_mut().flat_map(|v| v.iter_mut()) {
+        *v = reader.read_f32::<LittleEndian>()?;
+    }
+    Ok(BspTexInfo {
+        vecs : vecs,
+        miptex : reader.read_i32::<LittleEndian>()?,
+        flags : reader.read_i32::<LittleEndian>()?,
+    })
+}
+
+fn read_face(reader : &mut Cursor<&[u8]>) -> Result<BspFace, error::ReadError> {
+    let plane = reader.read_u16::<LittleEndian>()?;
+    let side = reader.read_i16::<LittleEndian>()?;
+    let first_edge = reader.read_i32::<LittleEndian>()?;
+    let num_edges = reader.read_i16::<LittleEndian>()?;
+    let texinfo = reader.read_i16::<LittleEndian>()?;
+    let mut styles = [0u8; MAXLIGHTMAPS];
+    reader.read_exact(&mut styles)?;
+    Ok(BspFace {
+        plane : plane,
+        side : side,
+        first_edge : first_edge,
+        num_edges : num_edges,
+        texinfo : texinfo,
+        styles : styles,
+        lightofs : reader.read_i32::<LittleEndian>()?,
+    })
+}
+
+fn read_clipnode(reader : &mut Cursor<&[u8]>) -> Result<BspClipNode, error::ReadError> {
+    Ok(BspClipNode {
+        plane : reader.read_i32::<LittleEndian>()?,
+        children : [reader.read_i16::<LittleEndian>()?, reader.read_i16::<LittleEndian>()?],
+    })
+}
+
+fn read_leaf(reader : &mut Cursor<&[u8]>) -> Result<BspLeaf, error::ReadError> {
+    let contents = reader.read_i32::<LittleEndian>()?;
+    let visofs = reader.read_i32::<LittleEndian>()?;
+    let mins = read_short_vector(reader)?;
+    let maxs = read_short_vector(reader)?;
+    let first_marksurface = reader.read_u16::<LittleEndian>()?;
+    let num_marksurfaces = reader.read_u16::<LittleEndian>()?;
+    let mut ambient_level = [0u8; 4];
+    reader.read_exact(&mut ambient_level)?;
+    Ok(BspLeaf {
+        contents : contents,
+        visofs : visofs,
+        mins : mins,
+        maxs : maxs,
+        first_marksurface : first_marksurface,
+        num_marksurfaces : num_marksurfaces,
+        ambient_level : ambient_level,
+    })
+}
+
+fn read_model(reader : &mut Cursor<&[u8]>) -> Result<BspModel, error::ReadError> {
+    // the bounds are spread by one unit like in the original
+    let mut mins = read_vector(reader)?;
+    let mut maxs = read_vector(reader)?;
+    for i in 0..3 {
+        mins[i] -= 1.0;
+        maxs[i] += 1.0;
+    }
+    let origin = read_vector(reader)?;
+    let mut headnode = [0; MAX_MAP_HULLS];
+    for node in headnode.iter_mut() {
+        *node = reader.read_i32::<LittleEndian>()?;
+    }
+    Ok(BspModel {
+        mins : mins,
+        maxs : maxs,
+        origin : origin,
+        headnode : headnode,
+        visleafs : reader.read_i32::<LittleEndian>()?,
+        first_face : reader.read_i32::<LittleEndian>()?,
+        num_faces : reader.read_i32::<LittleEndian>()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a .bsp file from raw lumps, missing lumps are empty.
+    fn build_bsp(lumps : &[(usize, Vec<u8>)]) -> Vec<u8> {
+        let mut contents = vec![Vec::new(); HEADER_LUMPS];
+        for &(index, ref data) in lumps {
+            contents[index] = data.clone();
+        }
+        let mut data = Vec::new();
+        data.extend_from_slice(&BSPVERSION.to_le_bytes());
+        let mut offset = 4 + HEADER_LUMPS * 8;
+        for lump in &contents {
+            data.extend_from_slice(&(offset as i32).to_le_bytes());
+            data.extend_from_slice(&(lump.len() as i32).to_le_bytes());
+            offset += lump.len();
+        }
+        for lump in &contents {
+            data.extend_from_slice(lump);
+        }
+        data
+    }
+
+    fn floats(values : &[f32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes().to_vec()).collect()
+    }
+
+    #[test]
+    fn read_lumps() {
+        let mut planes = floats(&[0.0, 0.0, 1.0, 64.0]);
+        planes.extend_from_slice(&2i32.to_le_bytes());
+        let mut clipnodes = 0i32.to_le_bytes().to_vec();
+        clipnodes.extend_from_slice(&(-1i16).to_le_bytes());
+        clipnodes.extend_from_slice(&(-2i16).to_le_bytes());
+        let mut model = floats(&[-64.0, -64.0, -64.0, 64.0, 64.0, 64.0, 0.0, 0.0, 0.0]);
+        for value in &[0i32, 0, 0, 0, 1, 0, 0] {
+            model.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let data = build_bsp(&[(LUMP_ENTITIES, b"{ \"classname\" \"worldspawn\" }\0".to_vec()),
+                               (LUMP_PLANES, planes), (LUMP_CLIPNODES, clipnodes), (LUMP_MODELS, model)]);
+        let bsp = BspFile::read(&data).unwrap();
+        assert_eq!(bsp.entities, "{ \"classname\" \"worldspawn\" }");
+        assert_eq!(bsp.planes, vec![BspPlane { normal : [0.0, 0.0, 1.0], dist : 64.0, kind : 2 }]);
+        assert_eq!(bsp.clipnodes, vec![BspClipNode { plane : 0, children : [-1, -2] }]);
+        assert_eq!(bsp.models.len(), 1);
+        assert_eq!(bsp.models[0].mins, [-65.0; 3]);
+        assert_eq!(bsp.models[0].visleafs, 1);
+        assert!(bsp.nodes.is_empty());
+
+        let mut broken = data.clone();
+        broken[0] = 30;
+        assert!(BspFile::read(&broken).is_err());
+        assert!(BspFile::read(&build_bsp(&[(LUMP_PLANES, vec![0; 19])])).is_err());
+    }
+
+    #[test]
+    fn read_faces() {
+        let mut textures = Vec::new();
+        for value in &[2i32, 12, -1] {
+            textures.extend_from_slice(&value.to_le_bytes());
+        }
+        textures.extend_from_slice(b"*water\0\0\0\0\0\0\0\0\0\0");
+        for value in &[16u32, 16, 40, 0, 0, 0] {
+            textures.extend_from_slice(&value.to_le_bytes());
+        }
+        textures.extend((0..256).map(|i| i as u8));
+        let mut texinfo = floats(&[1.0, 0.0, 0.0, 8.0, 0.0, 1.0, 0.0, 0.0]);
+        texinfo.extend_from_slice(&0i32.to_le_bytes());
+        texinfo.extend_from_slice(&TEX_SPECIAL.to_le_bytes());
+        let mut face = Vec::new();
+        face.extend_from_slice(&3u16.to_le_bytes());
+        face.extend_from_slice(&1i16.to_le_bytes());
+        face.extend_from_slice(&5i32.to_le_bytes());
+        face.extend_from_slice(&4i16.to_le_bytes());
+        face.extend_from_slice(&0i16.to_le_bytes());
+        face.extend_from_slice(&[0, 2, 255, 255]);
+        face.extend_from_slice(&(-1i32).to_le_bytes());
+        let edges = vec![1, 0, 2, 0];
+
+        let data = build_bsp(&[(LUMP_TEXTURES, textures), (LUMP_TEXINFO, texinfo), (LUMP_FACES, face),
+                               (LUMP_VERTEXES, floats(&[1.0, 2.0, 3.0])), (LUMP_EDGES, edges),
+                               (LUMP_SURFEDGES, (-7i32).to_le_bytes().to_vec()), (LUMP_LIGHTING, vec![9; 3]),
+                               (LUMP_MARKSURFACES, vec![0, 0, 3, 0])]);
+        let bsp = BspFile::read(&data).unwrap();
+        assert_eq!(bsp.textures.len(), 2);
+        assert!(bsp.textures[1].is_none());
+        let texture = bsp.textures[0].as_ref().unwrap();
+        assert_eq!(texture.name, "*water");
+        assert_eq!((texture.width, texture.height), (16, 16));
+        assert_eq!(texture.pixels[17], 17);
+        assert_eq!(bsp.texinfo[0].vecs[0], [1.0, 0.0, 0.0, 8.0]);
+        assert_eq!(bsp.texinfo[0].flags, TEX_SPECIAL);
+        assert_eq!(bsp.faces, vec![BspFace { plane : 3, side : 1, first_edge : 5, num_edges : 4, texinfo : 0,
+                                             styles : [0, 2, 255, 255], lightofs : -1 }]);
+        assert_eq!(bsp.vertexes, vec![[1.0, 2.0, 3.0]]);
+        assert_eq!(bsp.edges, vec![[1, 2]]);
+        assert_eq!(bsp.surfedges, vec![-7]);
+        assert_eq!(bsp.lighting, vec![9; 3]);
+        assert_eq!(bsp.marksurfaces, vec![0, 3]);
+    }
+
+    #[test]
+    fn decompress() {
+        assert_eq!(decompress_vis(&[0x05, 0x00, 0x02, 0x81], 4), vec![0x05, 0, 0, 0x81]);
+        assert_eq!(decompress_vis(&[0x00, 0x03], 3), vec![0; 3]);
+        // runs are cut at the end of the row and short data is padded
+        assert_eq!(decompress_vis(&[0x00, 0x09, 0xff], 2), vec![0; 2]);
+        assert_eq!(decompress_vis(&[0x07], 3), vec![0x07, 0, 0]);
+        assert!(leaf_visible(&[0x00, 0x01], 9));
+        assert!(!leaf_visible(&[0xff], 0));
+        assert!(!leaf_visible(&[0xff], 9));
+    }
+
+    /// Splits the map at x = 0 and x = 100 into leafs 1 to 3, each leaf
+    /// only sees its neighbours.
+    fn vis_map() -> BspFile {
+        let plane = |dist| BspPlane { normal : [1.0, 0.0, 0.0], dist : dist, kind : 0 };
+        let node = |plane, children| BspNode { plane : plane, children : children, mins : [0; 3], maxs : [0; 3], first_face : 0, num_faces : 0 };
+        let leaf = |visofs| BspLeaf {
+            contents : -1,
+            visofs : visofs,
+            mins : [0; 3],
+            maxs : [0; 3],
+            first_marksurface : 0,
+            num_marksurfaces : 0,
+            ambient_level : [0; 4],
+        };
+        BspFile {
+            planes : vec![plane(0.0), plane(100.0)],
+            nodes : vec![node(0, [1, -2]), node(1, [-4, -3])],
+            leafs : vec![BspLeaf { contents : -2, ..leaf(-1) }, leaf(0), leaf(1), leaf(2)],
+            visibility : vec![0x03, 0x07, 0x06],
+            ..BspFile::default()
+        }
+    }
+
+    #[test]
+    fn visibility() {
+        let bsp = vis_map();
+        assert_eq!(bsp.vis_row_size(), 1);
+        assert_eq!(bsp.point_in_leaf(&[-50.0, 0.0, 0.0]), 1);
+        assert_eq!(bsp.point_in_leaf(&[50.0, 0.0, 0.0]), 2);
+        assert_eq!(bsp.point_in_leaf(&[150.0, 0.0, 0.0]), 3);
+
+        assert_eq!(bsp.leaf_pvs(1), vec![0x03]);
+        assert_eq!(bsp.leaf_pvs(0), vec![0xff]);
+        assert!(bsp.leafs_visible(2, 3));
+        assert!(!bsp.leafs_visible(1, 3));
+        assert!(bsp.point_visible(&[-50.0, 0.0, 0.0], &[50.0, 0.0, 0.0]));
+        assert!(!bsp.point_visible(&[-50.0, 0.0, 0.0], &[150.0, 0.0, 0.0]));
+
+        // the far leafs hear each other through the middle one
+        let phs = bsp.calc_phs();
+        assert_eq!(phs[1], vec![0x07]);
+        assert_eq!(phs[3], vec![0x07]);
+        assert!(leaf_visible(&phs[1], 3));
+
+        // boxes and points close to a boundary see the leafs on both sides
+        assert_eq!(bsp.box_leafs(&[-10.0; 3], &[10.0; 3], 16), vec![2, 1]);
+        assert_eq!(bsp.box_leafs(&[-10.0; 3], &[110.0; 3], 2), vec![3, 2]);
+        assert_eq!(bsp.fat_pvs(&[-50.0, 0.0, 0.0], 8.0), vec![0x03]);
+        assert_eq!(bsp.fat_pvs(&[-4.0, 0.0, 0.0], 8.0), vec![0x07]);
+
+        // without visibility data everything is visible
+        let novis = BspFile { leafs : bsp.leafs.iter().map(|leaf| BspLeaf { visofs : -1, ..leaf.clone() }).collect(), ..vis_map() };
+        assert!(novis.leafs_visible(1, 3));
+    }
+}
@@ -0,0 +1,103 @@
+#![warn(missing_docs)]
+
+//! Parsing of entity definitions like the entity lump of a BSP file.
+//!
+//! Original source can be found in pr_edict.c (ED_ParseEdict) and common.c (COM_Parse)
+
+use error;
+
+/// Key / value pairs of a single entity, in the order they were defined.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EntityDef {
+    /// All fields of the entity.
+    pub fields : Vec<(String, String)>,
+}
+
+impl EntityDef {
+    /// Returns the value of a key.
+    pub fn get(&self, key : &str) -> Option<&str> {
+        self.fields.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v.as_str())
+    }
+}
+
+/// Returns the next token and the remaining text. Quoted strings are one token,
+/// braces are always a token of their own and // starts a comment.
+pub fn parse_token(mut data : &str) -> Option<(&str, &str)> {
+    loop {
+        data = data.trim_start();
+        if data.starts_with("//") {
+            data = match data.find('\n') {
+                Some(end) => &data[end..],
+                None => "",
+            };
+        } else {
+            break;
+        }
+    }
+
+    if data.is_empty() {
+        return None;
+    }
+    if data.starts_with('"') {
+        let rest = &data[1..];
+        return match rest.find('"') {
+            Some(end) => Some((&rest[..end], &rest[end + 1..])),
+            None => Some((rest, "")),
+        };
+    }
+    if data.starts_with('{') || data.starts_with('}') {
+        return Some((&data[..1], &data[1..]));
+    }
+    let end = data.find(|c : char| c.is_whitespace() || c == '{' || c == '}' || c == '"')
+        .unwrap_or(data.len());
+    Some((&data[..end], &data[end..]))
+}
+
+/// Parses a list of entities in the { "key" "value" ... } format.
+pub fn parse_entities(mut data : &str) -> Result<Vec<EntityDef>, error::ReadError> {
+    let mut entities = Vec::new();
+    while let Some((token, rest)) = parse_token(data) {
+        if token != "{" {
+            println!("ED_LoadFromFile: found {} when expecting {{", token);
+            return Err(error::ReadError::ParseError);
+        }
+        data = rest;
+
+        let mut entity = EntityDef::default();
+        loop {
+            let (key, rest) = parse_token(data).ok_or(error::ReadError::ParseError)?;
+            if key == "}" {
+                data = rest;
+                break;
+            }
+            let (value, rest) = parse_token(rest).ok_or(error::ReadError::ParseError)?;
+            if value == "}" {
+                println!("ED_ParseEntity: closing brace without data");
+                return Err(error::ReadError::ParseError);
+            }
+            entity.fields.push((key.to_string(), value.to_string()));
+            data = rest;
+        }
+        entities.push(entity);
+    }
+    Ok(entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_entity_lump() {
+        let text = "{\n\"classname\" \"worldspawn\"\n\"sounds\" \"4\"\n}\n// comment\n{ \"classname\" \"light\" \"origin\" \"0 0 64\" }\n";
+        let entities = parse_entities(text).unwrap();
+        assert_eq!(entities.len(), 2);
+        assert_eq!(entities[0].get("sounds"), Some("4"));
+        assert_eq!(entities[1].get("origin"), Some("0 0 64"));
+        assert_eq!(entities[1].get("sounds"), None);
+
+        assert!(parse_entities("{ \"classname\" }").is_err());
+        assert!(parse_entities("\"classname\"").is_err());
+        assert!(parse_entities("{ \"classname\" \"light\"").is_err());
+    }
+}
@@ -4,6 +4,7 @@ use std::error;
 use std::fmt;
 use std::io;
 
+use lewton;
 use riff_wave;
 
 /// Read errors when reading a file / resource.
@@ -13,6 +14,8 @@ pub enum ReadError {
     Io(io::Error),
     /// Error from riff_wave
     Wave(riff_wave::ReadError),
+    /// Error from lewton when decoding Ogg Vorbis files.
+    Vorbis(lewton::VorbisError),
     /// Custom error when the read input data is unexpected.
     ParseError,
     /// Custom error when trying to read a file / resource that doesn't exist.
@@ -24,6 +27,7 @@ impl fmt::Display for ReadError {
         match *self {
             ReadError::Io(ref err) => err.fmt(f),
             ReadError::Wave(ref err) => err.fmt(f),
+            ReadError::Vorbis(ref err) => err.fmt(f),
             ReadError::ParseError => write!(f, "Error parsing file"),
             ReadError::FileNotFound => write!(f, "File not found"),
         }
@@ -35,6 +39,7 @@ impl error::Error for ReadError {
         match *self {
             ReadError::Io(ref err) => err.description(),
             ReadError::Wave(ref err) => err.description(),
+            ReadError::Vorbis(_) => "ogg vorbis error",
             ReadError::ParseError => "parsing error",
             ReadError::FileNotFound => "file not found",
         }
@@ -44,6 +49,7 @@ impl error::Error for ReadError {
         match *self {
             ReadError::Io(ref err) => Some(err),
             ReadError::Wave(ref err) => Some(err),
+            ReadError::Vorbis(ref err) => Some(err),
             ReadError::ParseError => None,
             ReadError::FileNotFound => None,
         }
@@ -60,4 +66,10 @@ impl From<riff_wave::ReadError> for ReadError {
     fn from(err: riff_wave::ReadError) -> ReadError {
         ReadError::Wave(err)
     }
+}
+
+impl From<lewton::VorbisError> for ReadError {
+    fn from(err: lewton::VorbisError) -> ReadError {
+        ReadError::Vorbis(err)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,126 @@
+#![warn(missing_docs)]
+
+//! Writers for PCX and TGA image files.
+
+extern crate byteorder;
+
+use std::io::{Result, Write};
+use self::byteorder::{LittleEndian, WriteBytesExt};
+
+use lump::Palette;
+
+/// Splits a 0x00RRGGBB pixel into its red, green and blue parts.
+fn split_rgb(pixel : u32) -> (u8, u8, u8) {
+    ((pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8)
+}
+
+/// Writes palette indices as a run length encoded 8 bit PCX file like the
+/// screenshots of the original. The palette follows the image data.
+pub fn write_pcx<W : Write>(writer : &mut W, width : u32, height : u32, pixels : &[u8], palette : &Palette) -> Result<()> {
+    debug_assert_eq!(pixels.len(), (width * height) as usize);
+    // scanlines must have an even number of bytes
+    let bytes_per_line = (width + 1) & !1;
+
+    writer.write_u8(0x0a)?;     // manufacturer
+    writer.write_u8(5)?;        // version
+    writer.write_u8(1)?;        // run length encoding
+    writer.write_u8(8)?;        // bits per pixel
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>((width - 1) as u16)?;
+    writer.write_u16::<LittleEndian>((height - 1) as u16)?;
+    writer.write_u16::<LittleEndian>(width as u16)?;
+    writer.write_u16::<LittleEndian>(height as u16)?;
+    writer.write_all(&[0u8; 48])?;      // 16 color palette, unused
+    writer.write_u8(0)?;                // reserved
+    writer.write_u8(1)?;                // color planes
+    writer.write_u16::<LittleEndian>(bytes_per_line as u16)?;
+    writer.write_u16::<LittleEndian>(1)?;   // color palette
+    writer.write_all(&[0u8; 58])?;
+
+    let mut line = vec![0u8; bytes_per_line as usize];
+    for row in pixels.chunks(width as usize) {
+        line[..row.len()].copy_from_slice(row);
+        write_pcx_rle(writer, &line)?;
+    }
+
+    // the 256 color palette is marked with 0x0c
+    writer.write_u8(0x0c)?;
+    for index in 0..256 {
+        let (r, g, b) = split_rgb(palette.palette_lookup(index as u8));
+        writer.write_all(&[r, g, b])?;
+    }
+    Ok(())
+}
+
+/// Writes one run length encoded scanline.
+fn write_pcx_rle<W : Write>(writer : &mut W, data : &[u8]) -> Result<()> {
+    let mut i = 0;
+    while i < data.len() {
+        let value = data[i];
+        let mut count = 1;
+        while i + count < data.len() && count < 63 && data[i + count] == value {
+            count += 1;
+        }
+        if count > 1 || (value & 0xc0) == 0xc0 {
+            writer.write_u8(0xc0 | count as u8)?;
+        }
+        writer.write_u8(value)?;
+        i += count;
+    }
+    Ok(())
+}
+
+/// Writes 0x00RRGGBB pixels as an uncompressed 24 bit TGA file.
+pub fn write_tga<W : Write>(writer : &mut W, width : u32, height : u32, pixels : &[u32]) -> Result<()> {
+    debug_assert_eq!(pixels.len(), (width * height) as usize);
+
+    writer.write_u8(0)?;        // no image id
+    writer.write_u8(0)?;        // no color map
+    writer.write_u8(2)?;        // uncompressed true color
+    writer.write_all(&[0u8; 5])?;
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>(0)?;
+    writer.write_u16::<LittleEndian>(width as u16)?;
+    writer.write_u16::<LittleEndian>(height as u16)?;
+    writer.write_u8(24)?;
+    writer.write_u8(0x20)?;     // origin at the top left
+
+    let mut data = Vec::with_capacity(pixels.len() * 3);
+    for &pixel in pixels {
+        let (r, g, b) = split_rgb(pixel);
+        data.push(b);
+        data.push(g);
+        data.push(r);
+    }
+    writer.write_all(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_tga_file() {
+        let mut out = Vec::new();
+        write_tga(&mut out, 2, 1, &[0x00112233, 0x00ffffff]).unwrap();
+        assert_eq!(out.len(), 18 + 6);
+        assert_eq!(out[12], 2);
+        assert_eq!(&out[18..], &[0x33, 0x22, 0x11, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn write_pcx_file() {
+        let data : Vec<u8> = (0..256 * 3).map(|i| (i / 3) as u8).collect();
+        let palette = Palette::read(&mut &data[..]).unwrap();
+        let mut out = Vec::new();
+        write_pcx(&mut out, 3, 1, &[1, 1, 0xff], &palette).unwrap();
+        assert_eq!(out.len(), 128 + 5 + 1 + 768);
+        assert_eq!(out[0], 0x0a);
+        assert_eq!(out[65], 1);
+        // 0xff needs to be escaped, 4 bytes per line including padding
+        assert_eq!(&out[128..133], &[0xc2, 0x01, 0xc1, 0xff, 0x00]);
+        assert_eq!(out[133], 0x0c);
+        assert_eq!(&out[134 + 3 * 255..], &[0xff, 0xff, 0xff]);
+    }
+}
@@ -1,12 +1,18 @@
 #![crate_type= "lib"]
 
 extern crate riff_wave;
+extern crate lewton;
 
 pub use packfile::{PackFile};
+pub use wadfile::WadFile;
 pub use resources::GameResourcesImpl;
 pub use lump::{Picture,Palette};
 pub use error::ReadError;
 pub use wavefile::Sound;
+pub use imagefile::{write_pcx,write_tga};
+pub use entities::{EntityDef, parse_entities, parse_token};
+pub use bspfile::{BspFile, BspPlane, BspNode, BspClipNode, BspLeaf, BspModel, BspTexture, BspTexInfo, BspFace, MAX_MAP_HULLS, MAXLIGHTMAPS, TEX_SPECIAL};
+pub use bspfile::{decompress_vis, leaf_visible};
 
 mod packfile;
 mod resources;
@@ -14,4 +20,8 @@ mod lump;
 mod wadfile;
 mod wavefile;
 mod error;
-mod utils;
\ No newline at end of file
+mod utils;
+mod imagefile;
+mod oggfile;
+mod entities;
+mod bspfile;
\ No newline at end of file
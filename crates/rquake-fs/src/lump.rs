@@ -29,9 +29,27 @@ impl Palette {
         Ok(Palette { palette : pal32 })
     }
 
-    fn palette_lookup(&self, index : u8) -> u32 {
+    /// Returns the RGB color of a palette index.
+    pub fn palette_lookup(&self, index : u8) -> u32 {
         self.palette[index as usize]
     }
+
+    /// Returns the palette index with the color closest to an RGB color.
+    pub fn find_color(&self, color : u32) -> u8 {
+        let distance = |other : u32| -> i32 {
+            (0..3).map(|i| {
+                let d = ((color >> (i * 8)) & 0xff) as i32 - ((other >> (i * 8)) & 0xff) as i32;
+                d * d
+            }).sum()
+        };
+        let mut best = 0;
+        for (index, &other) in self.palette.iter().enumerate() {
+            if distance(other) < distance(self.palette[best]) {
+                best = index;
+            }
+        }
+        best as u8
+    }
 }
 
 /// Lump picture data.
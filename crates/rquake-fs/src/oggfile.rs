@@ -0,0 +1,36 @@
+#![warn(missing_docs)]
+
+//! Loading of Ogg Vorbis files, used for music tracks.
+
+use std::io::{Read, Seek};
+
+use lewton::inside_ogg::OggStreamReader;
+
+use error;
+use wavefile::Sound;
+
+impl Sound {
+    /// Decodes a complete Ogg Vorbis file.
+    pub fn read_ogg<T : Read + Seek>(reader : &mut T) -> Result<Sound, error::ReadError> {
+        let mut stream = OggStreamReader::new(reader)?;
+        let channels = stream.ident_hdr.audio_channels as u32;
+        let rate = stream.ident_hdr.audio_sample_rate;
+        if channels == 0 || rate == 0 {
+            return Err(error::ReadError::ParseError);
+        }
+
+        let mut samples = Vec::new();
+        while let Some(packet) = stream.read_dec_packet_itl()? {
+            samples.extend_from_slice(&packet);
+        }
+
+        Ok(Sound {
+            rate : rate,
+            width : 2,
+            channels : channels,
+            loop_start : None,
+            length : samples.len() / channels as usize,
+            samples : samples,
+        })
+    }
+}
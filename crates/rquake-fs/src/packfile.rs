@@ -131,6 +131,22 @@ impl PackFile {
         Sound::read(&mut reader)
     }
 
+    /// Returns true if the pak file contains the file.
+    pub fn contains(&self, name : &str) -> bool {
+        self.packfiles.iter().any(|f| f.name == name)
+    }
+
+    /// Reads the complete content of a file.
+    pub fn read_file(&mut self, name : &str) -> Result<Vec<u8>, error::ReadError> {
+        if !self.seek_to_file(name) {
+            return Err(error::ReadError::FileNotFound);
+        }
+        let pf = self.packfiles.iter().find(|&f| f.name == name).unwrap();
+        let mut data = vec![0u8; pf.filelen as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
     fn seek_to_file(&mut self, name : &str) -> bool {
         if let Some(pf) = self.packfiles.iter().find(|&f| f.name == name) {
             if let Err(err) = self.file.seek(SeekFrom::Start(pf.filepos as u64)) {
@@ -188,6 +204,14 @@ mod test {
         assert_eq!(pause_bitmap.height, 32);
     }
 
+    #[test]
+    fn read_raw_file() {
+        let mut packfile = PackFile::open("../../test-data/test.pak").unwrap();
+        assert!(packfile.contains("gfx/palette.lmp"));
+        assert_eq!(packfile.read_file("gfx/palette.lmp").unwrap().len(), 768);
+        assert!(packfile.read_file("gfx/missing.lmp").is_err());
+    }
+
     #[test]
     fn read_wav_file() {
         let mut packfile = PackFile::open("../../test-data/test.pak").unwrap();
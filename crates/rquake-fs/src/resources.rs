@@ -2,19 +2,32 @@
 
 extern crate rquake_common;
 
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
 use packfile::PackFile;
 use self::rquake_common::GameResources;
 
+/// Place where files are searched for.
+enum SearchPath {
+    /// Loose files in a directory.
+    Directory(String),
+    /// Content of a pak file.
+    Pack(PackFile),
+}
+
 /// Handles game resources.
 pub struct GameResourcesImpl {
-    packfiles : Vec<PackFile>,
+    /// Search paths, the last one is searched first.
+    searchpaths : Vec<SearchPath>,
 }
 
 impl GameResourcesImpl {
     /// Constructor
     pub fn new() -> GameResourcesImpl {
         GameResourcesImpl {
-            packfiles : Vec::new(),
+            searchpaths : Vec::new(),
         }
     }
 }
@@ -22,17 +35,58 @@ impl GameResourcesImpl {
 impl GameResources for GameResourcesImpl {
     /// Reads all PAK?.pak files in the given path.
     fn add_game_directory(&mut self, path: &str) {
+        self.searchpaths.push(SearchPath::Directory(path.to_string()));
         let mut i = 0;
         loop {
             let filepath = format!("{}/pak{}.pak", path, i);
             println!("Trying to read {}", &filepath);
             let new_packfile = PackFile::open(&filepath);
             match new_packfile {
-                Ok(new_packfile) => self.packfiles.push(new_packfile),
+                Ok(new_packfile) => self.searchpaths.push(SearchPath::Pack(new_packfile)),
                 Err(_) => break,
             }
             i += 1;
         }
-        println!("Read {} pak files", self.packfiles.len());
+        println!("Read {} pak files", i);
+    }
+
+    /// Reads a file from the pak files or the game directories.
+    fn load_file(&mut self, name: &str) -> Option<Vec<u8>> {
+        for searchpath in self.searchpaths.iter_mut().rev() {
+            match *searchpath {
+                SearchPath::Pack(ref mut packfile) => {
+                    if packfile.contains(name) {
+                        match packfile.read_file(name) {
+                            Ok(data) => return Some(data),
+                            Err(err) => println!("Failed to read {}: {}", name, err),
+                        }
+                    }
+                },
+                SearchPath::Directory(ref dir) => {
+                    let filepath = Path::new(dir).join(name);
+                    if let Ok(mut file) = File::open(&filepath) {
+                        let mut data = Vec::new();
+                        match file.read_to_end(&mut data) {
+                            Ok(_) => return Some(data),
+                            Err(err) => println!("Failed to read {}: {}", name, err),
+                        }
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn load_loose_file() {
+        let mut res = GameResourcesImpl::new();
+        res.add_game_directory("../../test-data");
+        assert_eq!(res.load_file("test.pak").unwrap().len(), 38100);
+        assert!(res.load_file("missing.pak").is_none());
     }
-}
\ No newline at end of file
+}
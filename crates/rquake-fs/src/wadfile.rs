@@ -107,4 +107,12 @@ impl WadFile {
         false
     }
 
+    /// Returns the position and length of a file inside a WAD file. Names
+    /// are compared without case like in the original W_GetLumpName.
+    pub fn file_range(&self, filename: &str) -> Option<(usize, usize)> {
+        self.wadfiles.iter()
+            .find(|f| f.name.eq_ignore_ascii_case(filename))
+            .map(|f| (f.filepos as usize, f.filelen as usize))
+    }
+
 }
\ No newline at end of file
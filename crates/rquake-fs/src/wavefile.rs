@@ -1,24 +1,216 @@
 #![warn(missing_docs)]
 
-use std::io::{Read,Seek};
-use riff_wave::WaveReader;
+//! Loading of RIFF-WAVE files.
+//!
+//! Original source can be found in snd_mem.c
+
+extern crate byteorder;
+
+use std::io::Read;
+use self::byteorder::{ByteOrder, LittleEndian};
 
 use error;
 
 /// RIFF-WAVE file data
+#[derive(Clone)]
 pub struct Sound {
-    /// unsigned 8 bit samples of a wave file.
-    pub samples : Vec<u8>,
+    /// Sample frames per second.
+    pub rate : u32,
+    /// Bytes per sample in the file, 1 or 2.
+    pub width : u32,
+    /// Number of channels.
+    pub channels : u32,
+    /// Sample frame where looping restarts, None if the sound isn't looped.
+    pub loop_start : Option<usize>,
+    /// Number of sample frames.
+    pub length : usize,
+    /// Signed 16 bit samples, channels are interleaved.
+    pub samples : Vec<i16>,
 }
 
 impl Sound {
-    /// Reads the samples of a 8 bit riff-wave file.
-    pub fn read<T : Read + Seek>(reader : &mut T) -> Result<Sound, error::ReadError> {
-        let mut samples : Vec<u8> = Vec::new();
-        let mut wav_reader = WaveReader::new(reader)?;
-        while let Ok(sample) = wav_reader.read_sample_u8() {
-            samples.push(sample);
+    /// Reads the samples of a 8 or 16 bit PCM riff-wave file, including the
+    /// loop points from the cue chunk.
+    pub fn read<T : Read>(reader : &mut T) -> Result<Sound, error::ReadError> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            println!("Missing RIFF/WAVE chunks");
+            return Err(error::ReadError::ParseError);
+        }
+        let chunks = read_chunks(&data[12..]);
+
+        let fmt = find_chunk(&chunks, b"fmt ").ok_or(error::ReadError::ParseError)?;
+        if fmt.len() < 16 {
+            return Err(error::ReadError::ParseError);
+        }
+        if LittleEndian::read_u16(&fmt[0..2]) != 1 {
+            println!("Microsoft PCM format only");
+            return Err(error::ReadError::ParseError);
+        }
+        let channels = LittleEndian::read_u16(&fmt[2..4]) as u32;
+        let rate = LittleEndian::read_u32(&fmt[4..8]);
+        let width = LittleEndian::read_u16(&fmt[14..16]) as u32 / 8;
+        if channels == 0 || rate == 0 || (width != 1 && width != 2) {
+            return Err(error::ReadError::ParseError);
+        }
+
+        // the first cue point is the loop start, a mark in the LIST chunk
+        // after it contains the length of the loop
+        let mut loop_start = None;
+        let mut loop_end = None;
+        if let Some(cue) = find_chunk(&chunks, b"cue ") {
+            if cue.len() >= 28 {
+                let start = LittleEndian::read_u32(&cue[24..28]) as usize;
+                loop_start = Some(start);
+                let marks = chunks.iter()
+                    .skip_while(|&&(id, _)| id != b"cue ")
+                    .find(|&&(id, ref content)| id == b"LIST" && content.len() >= 24 && &content[20..24] == b"mark");
+                if let Some(&(_, mark)) = marks {
+                    loop_end = Some(start + LittleEndian::read_u32(&mark[16..20]) as usize);
+                }
+            }
+        }
+
+        let pcm = find_chunk(&chunks, b"data").ok_or(error::ReadError::ParseError)?;
+        let frames = pcm.len() / (width * channels) as usize;
+        let length = match loop_end {
+            Some(end) if end > frames => {
+                println!("Sound has a bad loop length");
+                return Err(error::ReadError::ParseError);
+            },
+            Some(end) => end,
+            None => frames,
+        };
+
+        let count = length * channels as usize;
+        let samples = if width == 1 {
+            pcm[..count].iter().map(|&s| ((s as i16) - 128) << 8).collect()
+        } else {
+            pcm[..count * 2].chunks(2).map(|s| LittleEndian::read_i16(s)).collect()
+        };
+
+        Ok(Sound {
+            rate : rate,
+            width : width,
+            channels : channels,
+            loop_start : loop_start,
+            length : length,
+            samples : samples,
+        })
+    }
+
+    /// Returns the sound resampled to a new rate. Like the original this picks
+    /// the nearest sample and doesn't filter anything.
+    pub fn resample(&self, rate : u32) -> Sound {
+        let stepscale = self.rate as f64 / rate as f64;
+        let length = (self.length as f64 / stepscale) as usize;
+        let channels = self.channels as usize;
+
+        let mut samples = Vec::with_capacity(length * channels);
+        for i in 0..length {
+            let src = ((i as f64 * stepscale) as usize).min(self.length - 1);
+            samples.extend_from_slice(&self.samples[src * channels..(src + 1) * channels]);
+        }
+
+        Sound {
+            rate : rate,
+            width : self.width,
+            channels : self.channels,
+            loop_start : self.loop_start.map(|start| (start as f64 / stepscale) as usize),
+            length : length,
+            samples : samples,
         }
-        Ok(Sound { samples : samples })
     }
-}
\ No newline at end of file
+}
+
+/// Splits the content of the RIFF chunk into sub chunks.
+fn read_chunks(mut data : &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut chunks = Vec::new();
+    while data.len() >= 8 {
+        let id = &data[0..4];
+        let len = (LittleEndian::read_u32(&data[4..8]) as usize).min(data.len() - 8);
+        chunks.push((id, &data[8..8 + len]));
+        // chunks are padded to an even size
+        let next = (8 + len + 1) & !1;
+        data = &data[next.min(data.len())..];
+    }
+    chunks
+}
+
+fn find_chunk<'a>(chunks : &[(&[u8], &'a [u8])], id : &[u8]) -> Option<&'a [u8]> {
+    chunks.iter().find(|&&(chunk_id, _)| chunk_id == id).map(|&(_, content)| content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn chunk(id : &[u8], content : &[u8]) -> Vec<u8> {
+        let mut data = id.to_vec();
+        let mut len = [0u8; 4];
+        LittleEndian::write_u32(&mut len, content.len() as u32);
+        data.extend_from_slice(&len);
+        data.extend_from_slice(content);
+        if content.len() % 2 == 1 {
+            data.push(0);
+        }
+        data
+    }
+
+    fn wave_file(rate : u32, bits : u16, pcm : &[u8], extra : &[u8]) -> Vec<u8> {
+        let mut fmt = [0u8; 16];
+        LittleEndian::write_u16(&mut fmt[0..2], 1);
+        LittleEndian::write_u16(&mut fmt[2..4], 1);
+        LittleEndian::write_u32(&mut fmt[4..8], rate);
+        LittleEndian::write_u16(&mut fmt[14..16], bits);
+        let mut riff = b"WAVE".to_vec();
+        riff.extend(chunk(b"fmt ", &fmt));
+        riff.extend(chunk(b"data", pcm));
+        riff.extend_from_slice(extra);
+        chunk(b"RIFF", &riff)
+    }
+
+    #[test]
+    fn read_16bit_with_loop() {
+        let mut cue = [0u8; 28];
+        LittleEndian::write_u32(&mut cue[24..28], 1);
+        let mut list = [0u8; 28];
+        LittleEndian::write_u32(&mut list[16..20], 2);
+        list[20..24].copy_from_slice(b"mark");
+        let mut extra = chunk(b"cue ", &cue);
+        extra.extend(chunk(b"LIST", &list));
+
+        let pcm = [0x00, 0x80, 0xff, 0x7f, 0x01, 0x00, 0x02, 0x00];
+        let data = wave_file(22050, 16, &pcm, &extra);
+        let sound = Sound::read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(sound.rate, 22050);
+        assert_eq!(sound.width, 2);
+        assert_eq!(sound.loop_start, Some(1));
+        assert_eq!(sound.length, 3);
+        assert_eq!(sound.samples, vec![-32768, 32767, 1]);
+
+        let resampled = sound.resample(11025);
+        assert_eq!(resampled.length, 1);
+        assert_eq!(resampled.loop_start, Some(0));
+        assert_eq!(resampled.samples, vec![-32768]);
+    }
+
+    #[test]
+    fn read_8bit() {
+        let data = wave_file(11025, 8, &[0x80, 0xff, 0x00], &[]);
+        let sound = Sound::read(&mut Cursor::new(data)).unwrap();
+        assert_eq!(sound.width, 1);
+        assert_eq!(sound.loop_start, None);
+        assert_eq!(sound.samples, vec![0, 127 << 8, -128 << 8]);
+        assert_eq!(sound.resample(22050).samples, vec![0, 0, 127 << 8, 127 << 8, -128 << 8, -128 << 8]);
+    }
+
+    #[test]
+    fn reject_invalid_files() {
+        assert!(Sound::read(&mut Cursor::new(b"RIFF\0\0\0\0WAVX".to_vec())).is_err());
+        assert!(Sound::read(&mut Cursor::new(wave_file(11025, 24, &[0, 0, 0], &[]))).is_err());
+    }
+}
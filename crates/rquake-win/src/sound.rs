@@ -1,4 +1,4 @@
-use rquake_common::NativeSoundEngine;
+use rquake_common::{NativeSoundEngine, SoundFormat};
 use winapi::*;
 use user32::*;
 use std::mem;
@@ -11,9 +11,14 @@ extern "system" {
     ) -> HRESULT;
 }
 
+/// Size of the secondary buffer in bytes.
+const SECONDARY_BUFFER_SIZE : DWORD = 0x10000;
+
 pub struct DirectSoundEngine {
     ds_device : LPDIRECTSOUND,
     ds_buffer : LPDIRECTSOUNDBUFFER,
+    ds_secondary : LPDIRECTSOUNDBUFFER,
+    format : Option<SoundFormat>,
 }
 
 impl DirectSoundEngine {
@@ -21,6 +26,16 @@ impl DirectSoundEngine {
         DirectSoundEngine {
             ds_device : ptr::null_mut(),
             ds_buffer : ptr::null_mut(),
+            ds_secondary : ptr::null_mut(),
+            format : None,
+        }
+    }
+
+    /// Returns the size of one sample frame in bytes.
+    fn block_align(&self) -> usize {
+        match self.format {
+            Some(format) => (format.channels * format.bits / 8) as usize,
+            None => 1,
         }
     }
 }
@@ -34,8 +49,8 @@ impl NativeSoundEngine for DirectSoundEngine {
         format.nSamplesPerSec = 11025;
         format.nBlockAlign = format.nChannels * format.wBitsPerSample / 8;
         format.cbSize = 0;
-        format.nAvgBytesPerSec = format.nSamplesPerSec * format.nBlockAlign as u32; 
-        
+        format.nAvgBytesPerSec = format.nSamplesPerSec * format.nBlockAlign as u32;
+
         let hr = unsafe { DirectSoundCreate(ptr::null_mut(), &mut self.ds_device, ptr::null_mut()) };
         if hr != DS_OK {
             println!("DirectSoundCreate failed: {}", hr);
@@ -52,7 +67,7 @@ impl NativeSoundEngine for DirectSoundEngine {
         let mut dsbufdesc : DSBUFFERDESC = unsafe { mem::zeroed() };
         dsbufdesc.dwSize = mem::size_of::<DSBUFFERDESC>() as DWORD;
 	    dsbufdesc.dwFlags = DSBCAPS_PRIMARYBUFFER;
-	    
+
         let hr = unsafe { (*self.ds_device).CreateSoundBuffer(&dsbufdesc, &mut self.ds_buffer, ptr::null_mut()) };
         if hr != DS_OK {
             println!("CreateSoundBuffer failed: {}", hr);
@@ -76,20 +91,105 @@ impl NativeSoundEngine for DirectSoundEngine {
             return;
         }
 
+        // the secondary buffer is the ring buffer the mixer writes to
+        let mut dsbufdesc : DSBUFFERDESC = unsafe { mem::zeroed() };
+        dsbufdesc.dwSize = mem::size_of::<DSBUFFERDESC>() as DWORD;
+        dsbufdesc.dwFlags = DSBCAPS_LOCSOFTWARE | DSBCAPS_GETCURRENTPOSITION2;
+        dsbufdesc.dwBufferBytes = SECONDARY_BUFFER_SIZE;
+        dsbufdesc.lpwfxFormat = &mut format;
+
+        let hr = unsafe { (*self.ds_device).CreateSoundBuffer(&dsbufdesc, &mut self.ds_secondary, ptr::null_mut()) };
+        if hr != DS_OK {
+            println!("CreateSoundBuffer for secondary buffer failed: {}", hr);
+            self.shutdown();
+            return;
+        }
+
+        let hr = unsafe { (*self.ds_secondary).Play(0, 0, DSBPLAY_LOOPING) };
+        if hr != DS_OK {
+            println!("DirectSoundBuffer.Play failed: {}", hr);
+            self.shutdown();
+            return;
+        }
+
+        self.format = Some(SoundFormat {
+            rate : format.nSamplesPerSec,
+            channels : format.nChannels as u32,
+            bits : format.wBitsPerSample as u32,
+            frames : (SECONDARY_BUFFER_SIZE / format.nBlockAlign as DWORD) as usize,
+        });
+
         println!("DirectSoundEngine initialized.");
     }
 
     fn shutdown(&mut self) {
+        self.format = None;
+
+        if !self.ds_secondary.is_null() {
+            unsafe {
+                (*self.ds_secondary).Stop();
+                (*self.ds_secondary).Release();
+            }
+            self.ds_secondary = ptr::null_mut();
+        }
+
         if !self.ds_buffer.is_null() {
-            unsafe { 
+            unsafe {
                 (*self.ds_buffer).Stop();
-                (*self.ds_buffer).Release(); 
+                (*self.ds_buffer).Release();
             }
+            self.ds_buffer = ptr::null_mut();
         }
 
         if !self.ds_device.is_null() {
             unsafe { (*self.ds_device).Release(); }
+            self.ds_device = ptr::null_mut();
         }
         println!("DirectSoundEngine shut down.");
     }
-}
\ No newline at end of file
+
+    fn get_format(&self) -> Option<SoundFormat> {
+        self.format
+    }
+
+    fn get_dma_position(&mut self) -> usize {
+        if self.ds_secondary.is_null() {
+            return 0;
+        }
+        let mut play_cursor : DWORD = 0;
+        let mut write_cursor : DWORD = 0;
+        let hr = unsafe { (*self.ds_secondary).GetCurrentPosition(&mut play_cursor, &mut write_cursor) };
+        if hr != DS_OK {
+            println!("DirectSoundBuffer.GetCurrentPosition failed: {}", hr);
+            return 0;
+        }
+        play_cursor as usize / self.block_align()
+    }
+
+    fn write_samples(&mut self, offset : usize, samples : &[i16]) {
+        if self.ds_secondary.is_null() {
+            return;
+        }
+        let mut ptr1 : LPVOID = ptr::null_mut();
+        let mut ptr2 : LPVOID = ptr::null_mut();
+        let mut bytes1 : DWORD = 0;
+        let mut bytes2 : DWORD = 0;
+        let bytes = (samples.len() * 2) as DWORD;
+        let hr = unsafe {
+            (*self.ds_secondary).Lock((offset * self.block_align()) as DWORD, bytes,
+                &mut ptr1, &mut bytes1, &mut ptr2, &mut bytes2, 0)
+        };
+        if hr != DS_OK {
+            println!("DirectSoundBuffer.Lock failed: {}", hr);
+            return;
+        }
+
+        // the mixer never writes across the end of the buffer, so the
+        // second pointer is not used
+        let count = (bytes1 as usize / 2).min(samples.len());
+        unsafe {
+            ptr::copy_nonoverlapping(samples.as_ptr(), ptr1 as *mut i16, count);
+            (*self.ds_secondary).Unlock(ptr1, bytes1, ptr2, bytes2);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use rquake_common::{BackBuffer, Window, EventAction, ToggleFullscreen};
+use rquake_common::keys::*;
 use winapi::*;
 use user32::*;
 use kernel32::{GetModuleHandleW};
@@ -42,6 +43,7 @@ pub struct WinWindow {
     window_width : i32,
     window_height : i32,
     old_window_placement : WINDOWPLACEMENT,
+    last_mouse_pos : Option<(i32, i32)>,
 }
 
 impl WinWindow {
@@ -120,10 +122,48 @@ impl WinWindow {
             window_width : DEFAULT_WIDTH,
             window_height : DEFAULT_HEIGHT,
             old_window_placement : win_placement,
+            last_mouse_pos : None,
         })
     }
 }
 
+/// Translates a virtual key code into a key number.
+fn map_key(vk : WPARAM) -> Option<i32> {
+    let vk = vk as i32;
+    let key = match vk {
+        0x30 ... 0x39 => vk,
+        0x41 ... 0x5A => vk - 0x41 + 'a' as i32,
+        VK_F1 ... VK_F12 => vk - VK_F1 + K_F1,
+        VK_TAB => K_TAB,
+        VK_RETURN => K_ENTER,
+        VK_ESCAPE => K_ESCAPE,
+        VK_SPACE => K_SPACE,
+        VK_BACK => K_BACKSPACE,
+        VK_UP => K_UPARROW,
+        VK_DOWN => K_DOWNARROW,
+        VK_LEFT => K_LEFTARROW,
+        VK_RIGHT => K_RIGHTARROW,
+        VK_MENU => K_ALT,
+        VK_CONTROL => K_CTRL,
+        VK_SHIFT => K_SHIFT,
+        VK_INSERT => K_INS,
+        VK_DELETE => K_DEL,
+        VK_NEXT => K_PGDN,
+        VK_PRIOR => K_PGUP,
+        VK_HOME => K_HOME,
+        VK_END => K_END,
+        VK_PAUSE => K_PAUSE,
+        VK_OEM_1 => ';' as i32,
+        VK_OEM_2 => '/' as i32,
+        VK_OEM_COMMA => ',' as i32,
+        VK_OEM_PERIOD => '.' as i32,
+        VK_OEM_MINUS => '-' as i32,
+        VK_OEM_PLUS => '=' as i32,
+        _ => return None,
+    };
+    Some(key)
+}
+
 impl Window for WinWindow {
      fn show_window(&self) {
         unsafe { ShowWindow(self.hwnd, SW_SHOWDEFAULT); };
@@ -141,7 +181,36 @@ impl Window for WinWindow {
             
             match msg.message {
                 WM_QUIT => self.running = false,
-                WM_KEYDOWN => { actions.push(EventAction::ToggleFullscreen); self.toggle_fullscreen(); },
+                WM_SYSKEYDOWN if msg.wParam == VK_RETURN as WPARAM => {
+                    // alt + enter
+                    actions.push(EventAction::ToggleFullscreen);
+                    self.toggle_fullscreen();
+                },
+                WM_KEYDOWN | WM_SYSKEYDOWN => if let Some(key) = map_key(msg.wParam) {
+                    actions.push(EventAction::KeyDown(key));
+                },
+                WM_KEYUP | WM_SYSKEYUP => if let Some(key) = map_key(msg.wParam) {
+                    actions.push(EventAction::KeyUp(key));
+                },
+                WM_LBUTTONDOWN => actions.push(EventAction::KeyDown(K_MOUSE1)),
+                WM_LBUTTONUP => actions.push(EventAction::KeyUp(K_MOUSE1)),
+                WM_RBUTTONDOWN => actions.push(EventAction::KeyDown(K_MOUSE2)),
+                WM_RBUTTONUP => actions.push(EventAction::KeyUp(K_MOUSE2)),
+                WM_MBUTTONDOWN => actions.push(EventAction::KeyDown(K_MOUSE3)),
+                WM_MBUTTONUP => actions.push(EventAction::KeyUp(K_MOUSE3)),
+                WM_MOUSEWHEEL => {
+                    let key = if (msg.wParam >> 16) as i16 > 0 { K_MWHEELUP } else { K_MWHEELDOWN };
+                    actions.push(EventAction::KeyDown(key));
+                    actions.push(EventAction::KeyUp(key));
+                },
+                WM_MOUSEMOVE => {
+                    let x = (msg.lParam & 0xffff) as i16 as i32;
+                    let y = ((msg.lParam >> 16) & 0xffff) as i16 as i32;
+                    if let Some((last_x, last_y)) = self.last_mouse_pos {
+                        actions.push(EventAction::MouseMove(x - last_x, y - last_y));
+                    }
+                    self.last_mouse_pos = Some((x, y));
+                },
                 _ => unsafe { let _ = DispatchMessageW(&mut msg); },
             }
         }
@@ -213,7 +282,8 @@ impl BackBuffer for WinWindow {
     }
     
     fn get_height(&self) -> u32 {
-        self.bitmap_info.bmiHeader.biHeight as u32
+        // biHeight is negative for top-down bitmaps
+        -self.bitmap_info.bmiHeader.biHeight as u32
     }
 }
 
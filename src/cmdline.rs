@@ -1,12 +1,22 @@
 extern crate clap;
 use self::clap::{Arg, App};
+use std::env;
+
+/// Maximum number of players of a dedicated server.
+const MAX_SCOREBOARD : usize = 16;
 
 pub struct CmdConfig {
     pub nosound : bool,
     pub windowed : bool,
+    pub capture_dir : Option<String>,
+    pub frames : Option<u32>,
+    pub dedicated : Option<usize>,
 }
 
 pub fn parse_cmdline() -> CmdConfig {
+    // accept the single dash used by the original -dedicated parameter
+    let args = env::args().map(|arg| if arg == "-dedicated" { "--dedicated".to_string() } else { arg });
+
     let matches = App::new("Quake")
         .version("0.1")
         .author("Maurice Gilden <MauriceG@gmx.net>")
@@ -17,10 +27,40 @@ pub fn parse_cmdline() -> CmdConfig {
         .arg(Arg::with_name("windowed")
             .long("windowed")
             .help("start quake in windowed mode"))
-        .get_matches();
-        
+        .arg(Arg::with_name("capture-frames")
+            .long("capture-frames")
+            .value_name("DIR")
+            .takes_value(true)
+            .help("writes every frame to DIR using a fixed timestep"))
+        .arg(Arg::with_name("frames")
+            .long("frames")
+            .value_name("COUNT")
+            .takes_value(true)
+            .validator(|n| n.parse::<u32>().map(|_| ()).map_err(|_| format!("'{}' isn't a frame count", n)))
+            .help("quits after COUNT frames"))
+        .arg(Arg::with_name("dedicated")
+            .long("dedicated")
+            .value_name("MAXPLAYERS")
+            .takes_value(true)
+            .min_values(0)
+            .help("runs a server without window and sound for up to MAXPLAYERS (8) players"))
+        .get_matches_from(args);
+
+    let max_players = if matches.is_present("dedicated") {
+        let n = matches.value_of("dedicated").and_then(|n| n.parse().ok()).unwrap_or(0);
+        Some(match n {
+            0 => 8,
+            n => n.min(MAX_SCOREBOARD),
+        })
+    } else {
+        None
+    };
+
     CmdConfig {
         nosound : matches.is_present("nosound"),
         windowed : matches.is_present("windowed"),
+        capture_dir : matches.value_of("capture-frames").map(|dir| dir.to_string()),
+        frames : matches.value_of("frames").and_then(|n| n.parse().ok()),
+        dedicated : max_players,
     }
 }
@@ -5,18 +5,34 @@ extern crate rquake_engine;
 #[cfg(windows)]
 extern crate rquake_win;
 
-use rquake_common::{Timer,Window,NativeSoundEngine};
+use rquake_common::Timer;
 use rquake_engine::{Host,SoundEngine};
 use rquake_fs::{GameResourcesImpl};
 
+#[cfg(windows)]
+use rquake_common::{Window,NativeSoundEngine};
 #[cfg(windows)]
 use rquake_win::{WinWindow,DirectSoundEngine};
+#[cfg(not(windows))]
+use rquake_common::MemoryBackBuffer;
+#[cfg(not(windows))]
+use rquake_engine::WaveFileSoundEngine;
 
-use std::thread::sleep;
+#[cfg(not(windows))]
+use std::fs;
+use std::io::{self, BufRead};
+use std::sync::mpsc;
+use std::thread::{self, sleep};
 use std::time::Duration;
 
 mod cmdline;
 
+/// Size of the back buffer of headless frame captures.
+#[cfg(not(windows))]
+const HEADLESS_WIDTH : u32 = 320;
+#[cfg(not(windows))]
+const HEADLESS_HEIGHT : u32 = 240;
+
 #[cfg(windows)]
 fn create_window() -> Result<Box<Window>, &'static str> {
     let res = WinWindow::create_window();
@@ -31,9 +47,8 @@ fn create_sound_engine() -> Box<NativeSoundEngine> {
     Box::new(DirectSoundEngine::new())
 }
 
-fn main() {
-    let _ = cmdline::parse_cmdline();
-
+#[cfg(windows)]
+fn run_client(config : &cmdline::CmdConfig) {
     // Create main window
     let window = create_window();
     let mut window = match window {
@@ -50,21 +65,34 @@ fn main() {
     let mut host = Host::new(&mut game_res, &mut snd);
 
     host.init();
+    if let Some(ref dir) = config.capture_dir {
+        host.set_capture_dir(Some(dir.as_str()));
+    }
 
     // Create game timer
     let mut timer = Timer::new();
     timer.set_bounds(0.001, 0.1);
-    timer.set_target(1.0 / 72.0);
     
     // Game loop
     let mut pending_actions = Vec::new();
-    while window.is_running() {
+    let mut frames = 0;
+    while window.is_running() && host.is_running() && config.frames != Some(frames) {
         let mut new_actions = window.handle_message();
         pending_actions.append(&mut new_actions);
 
-        if let Some(time_step) = timer.next() {
-            host.frame(time_step, &pending_actions);
+        // timedemos run as fast as possible
+        timer.set_target(if host.is_timedemo() { 0.0 } else { 1.0 / 72.0 });
+
+        // frame captures always use the target frame time to get reproducible results
+        let time_step = match config.capture_dir {
+            Some(_) => Some(1.0 / 72.0),
+            None => timer.next(),
+        };
+        if let Some(time_step) = time_step {
+            host.frame(time_step, &pending_actions, Some(window.get_backbuffer()));
+            pending_actions.clear();
             window.render();
+            frames += 1;
         } else {
             sleep(Duration::from_millis(1));
         }
@@ -72,3 +100,95 @@ fn main() {
     
     host.shutdown();
 }
+
+/// Renders into memory without a window, only used to capture frames on
+/// headless machines. Console commands are read from stdin and the sound
+/// is written to sound.wav in the capture directory.
+#[cfg(not(windows))]
+fn run_client(config : &cmdline::CmdConfig) {
+    let dir = match config.capture_dir {
+        Some(ref dir) => dir,
+        None => {
+            println!("Only dedicated servers and frame captures are supported on this platform, use -dedicated or --capture-frames");
+            return;
+        },
+    };
+
+    if let Err(err) = fs::create_dir_all(dir) {
+        println!("Failed to create {}: {}", dir, err);
+        return;
+    }
+
+    let native_snd = WaveFileSoundEngine::new(&format!("{}/sound.wav", dir), 11025, 2);
+    let mut snd = SoundEngine::new(Box::new(native_snd));
+    let mut game_res = GameResourcesImpl::new();
+    let mut host = Host::new(&mut game_res, &mut snd);
+    host.init();
+    host.set_capture_dir(Some(dir.as_str()));
+
+    let console = read_console();
+    let mut backbuffer = MemoryBackBuffer::new(HEADLESS_WIDTH, HEADLESS_HEIGHT);
+    let mut frames = 0;
+    while host.is_running() && config.frames != Some(frames) {
+        while let Ok(line) = console.try_recv() {
+            host.add_command(&line);
+        }
+
+        // frame captures always use the target frame time to get reproducible results
+        host.frame(1.0 / 72.0, &[], Some(&mut backbuffer));
+        frames += 1;
+    }
+
+    host.shutdown();
+}
+
+/// Reads console input on its own thread so the game never blocks.
+fn read_console() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => if sender.send(line).is_err() {
+                    break;
+                },
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
+}
+
+fn run_dedicated(max_players : usize) {
+    let mut game_res = GameResourcesImpl::new();
+    let mut host = Host::new_dedicated(&mut game_res, max_players);
+    host.init();
+
+    let receiver = read_console();
+
+    let mut timer = Timer::new();
+    timer.set_bounds(0.001, 0.1);
+    while host.is_running() {
+        while let Ok(line) = receiver.try_recv() {
+            host.add_command(&line);
+        }
+
+        timer.set_target(host.ticrate());
+        if let Some(time_step) = timer.next() {
+            host.frame(time_step, &[], None);
+        } else {
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    host.shutdown();
+}
+
+fn main() {
+    let config = cmdline::parse_cmdline();
+
+    match config.dedicated {
+        Some(max_players) => run_dedicated(max_players),
+        None => run_client(&config),
+    }
+}